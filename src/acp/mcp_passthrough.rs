@@ -2,8 +2,8 @@
 //! pass through during `session/new` and `session/load`.
 //!
 //! Stdio MCP servers are always supported by ACP agents. HTTP/SSE servers
-//! are gated on `agent_capabilities.mcp_capabilities.{http,sse}`. Entries
-//! with empty fields, or HTTP servers with OAuth2 auth (whose tokens we
+//! are gated on `agent_capabilities.mcp_capabilities.{http,sse}`. Disabled
+//! servers, entries with empty fields, or HTTP servers with OAuth2 auth (whose tokens we
 //! cannot safely surface to the agent process), are silently dropped.
 //!
 //! See protocol docs: <https://agentclientprotocol.com/protocol/session-setup#mcp-servers>
@@ -11,7 +11,7 @@
 use std::path::PathBuf;
 
 use agent_client_protocol::schema::{
-    HttpHeader, McpCapabilities, McpServer, McpServerHttp, McpServerStdio,
+    HttpHeader, McpCapabilities, McpServer, McpServerHttp, McpServerSse, McpServerStdio,
 };
 
 use crate::config::{McpAuthConfig, McpConfig};
@@ -35,6 +35,9 @@ pub fn mcp_servers_from_configs(
 }
 
 fn convert_one(cfg: &McpConfig, caps: &McpCapabilities) -> Option<McpServer> {
+    if !cfg.enabled() {
+        return None;
+    }
     match cfg {
         McpConfig::Stdio(s) => {
             if s.command.trim().is_empty() {
@@ -67,13 +70,28 @@ fn convert_one(cfg: &McpConfig, caps: &McpCapabilities) -> Option<McpServer> {
             let http = McpServerHttp::new(h.name.clone(), h.endpoint.clone()).headers(headers);
             Some(McpServer::Http(http))
         }
+        McpConfig::Sse(s) => {
+            if !caps.sse || s.endpoint.trim().is_empty() {
+                return None;
+            }
+            let headers = if s.bearer_token.is_empty() {
+                Vec::new()
+            } else {
+                vec![HttpHeader::new(
+                    "Authorization",
+                    format!("Bearer {}", s.bearer_token),
+                )]
+            };
+            let sse = McpServerSse::new(s.name.clone(), s.endpoint.clone()).headers(headers);
+            Some(McpServer::Sse(sse))
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::{McpStdioConfig, McpStreamableHttpConfig};
+    use crate::config::{McpSseConfig, McpStdioConfig, McpStreamableHttpConfig};
 
     fn caps(http: bool, sse: bool) -> McpCapabilities {
         McpCapabilities::new().http(http).sse(sse)
@@ -85,6 +103,7 @@ mod tests {
             name: "fs".into(),
             command: "/usr/bin/mcp-fs".into(),
             args: vec!["--root".into(), "/tmp".into()],
+            ..McpStdioConfig::default()
         })];
         let out = mcp_servers_from_configs(&cfgs, &caps(false, false));
         assert_eq!(out.len(), 1);
@@ -97,6 +116,18 @@ mod tests {
             name: "x".into(),
             command: "   ".into(),
             args: vec![],
+            ..McpStdioConfig::default()
+        })];
+        assert!(mcp_servers_from_configs(&cfgs, &caps(true, true)).is_empty());
+    }
+
+    #[test]
+    fn disabled_server_dropped() {
+        let cfgs = vec![McpConfig::Stdio(McpStdioConfig {
+            name: "fs".into(),
+            command: "/usr/bin/mcp-fs".into(),
+            disabled: true,
+            ..McpStdioConfig::default()
         })];
         assert!(mcp_servers_from_configs(&cfgs, &caps(true, true)).is_empty());
     }
@@ -107,6 +138,7 @@ mod tests {
             name: "remote".into(),
             endpoint: "https://mcp.example.com".into(),
             auth: McpAuthConfig::None,
+            ..McpStreamableHttpConfig::default()
         })];
         assert!(mcp_servers_from_configs(&cfgs, &caps(false, false)).is_empty());
         let out = mcp_servers_from_configs(&cfgs, &caps(true, false));
@@ -114,6 +146,22 @@ mod tests {
         assert!(matches!(out[0], McpServer::Http(_)));
     }
 
+    #[test]
+    fn sse_gated_by_capability() {
+        let cfgs = vec![McpConfig::Sse(McpSseConfig {
+            name: "legacy".into(),
+            endpoint: "https://mcp.example.com/sse".into(),
+            bearer_token: "secret".into(),
+            ..McpSseConfig::default()
+        })];
+        assert!(mcp_servers_from_configs(&cfgs, &caps(true, false)).is_empty());
+        let out = mcp_servers_from_configs(&cfgs, &caps(false, true));
+        let McpServer::Sse(s) = &out[0] else {
+            panic!("expected sse variant")
+        };
+        assert_eq!(s.headers[0].value, "Bearer secret");
+    }
+
     #[test]
     fn http_bearer_token_becomes_authorization_header() {
         let cfgs = vec![McpConfig::StreamableHttp(McpStreamableHttpConfig {
@@ -122,6 +170,7 @@ mod tests {
             auth: McpAuthConfig::BearerToken {
                 token: "secret".into(),
             },
+            ..McpStreamableHttpConfig::default()
         })];
         let out = mcp_servers_from_configs(&cfgs, &caps(true, false));
         let McpServer::Http(h) = &out[0] else {
@@ -142,6 +191,7 @@ mod tests {
                 client_name: "Ergon".into(),
                 redirect_port: 8585,
             },
+            ..McpStreamableHttpConfig::default()
         })];
         assert!(mcp_servers_from_configs(&cfgs, &caps(true, true)).is_empty());
     }
@@ -37,7 +37,7 @@ pub fn spawn_stdio(cfg: &AcpAgentStdioConfig) -> Result<SpawnedAgent> {
             cmd.current_dir(p);
         }
     }
-    log::info!("ACP Agen command: {:?}", cmd);
+    tracing::info!("ACP Agen command: {:?}", cmd);
     cmd.stdin(std::process::Stdio::piped())
         .stdout(std::process::Stdio::piped())
         .stderr(std::process::Stdio::inherit())
@@ -3,64 +3,123 @@
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    config::{AnthropicConfig, Config},
-    models::{Choice, CompletionRequest, CompletionResponse, Message},
+    api::http,
+    config::{AnthropicConfig, CustomProviderConfig},
+    images,
+    models::{Choice, CompletionRequest, CompletionResponse, Message, TokenUsage, Tool},
 };
 
-use super::{ErgonClient, Model};
+use super::{history, retry, ErgonClient, Model};
 
 #[derive(Debug, Clone)]
 pub struct AnthropicClient {
     config: AnthropicConfig,
+    auth: Auth,
+}
+
+/// How requests are authenticated.
+#[derive(Clone)]
+enum Auth {
+    /// `x-api-key` with the configured key, which must be set.
+    ApiKey,
+    /// The header of a custom provider, if it sends one.
+    Header(Option<(String, String)>),
+}
+
+impl std::fmt::Debug for Auth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Auth::ApiKey => f.write_str("ApiKey"),
+            Auth::Header(header) => f
+                .debug_tuple("Header")
+                .field(&header.as_ref().map(|(name, _)| name))
+                .finish(),
+        }
+    }
 }
 
 impl AnthropicClient {
-    async fn request(&self, request: CompletionRequest) -> anyhow::Result<CompletionResponse> {
-        if self.config.api_key.is_empty() {
-            return Err(anyhow::anyhow!("API key is not set".to_string()));
+    pub fn new(config: AnthropicConfig) -> Self {
+        Self {
+            config,
+            auth: Auth::ApiKey,
+        }
+    }
+
+    /// A client for a custom provider speaking the Anthropic API, which
+    /// sends the provider's auth header instead of `x-api-key`.
+    pub fn custom(provider: &CustomProviderConfig) -> Self {
+        Self {
+            config: AnthropicConfig {
+                api_key: String::new(),
+                endpoint: provider.base_url.clone(),
+                retry: provider.retry,
+                timeouts: provider.timeouts,
+                proxy: provider.proxy.clone(),
+                ..AnthropicConfig::default()
+            },
+            auth: Auth::Header(provider.auth_header()),
+        }
+    }
+
+    fn auth_header(&self) -> anyhow::Result<Option<(String, String)>> {
+        match &self.auth {
+            Auth::ApiKey if self.config.api_key.is_empty() => {
+                Err(anyhow::anyhow!("API key is not set".to_string()))
+            }
+            Auth::ApiKey => Ok(Some(("x-api-key".to_string(), self.config.api_key.clone()))),
+            Auth::Header(header) => Ok(header.clone()),
         }
-        let client = reqwest::Client::new();
+    }
+
+    async fn request(&self, request: CompletionRequest) -> anyhow::Result<CompletionResponse> {
+        let auth_header = self.auth_header()?;
+        let client = http::client(&self.config.timeouts, self.config.proxy.as_ref());
         let url = format!("{}/messages", self.config.endpoint.trim_end_matches('/'));
         let data = self.serialize_request(request.into())?;
         println!("AnthropicClient: Sending request to URL: {}", url);
         println!("AnthropicClient: Request data: {}", data);
-        let response = client
-            .post(url)
-            .header("x-api-key", self.config.api_key.clone())
+        let mut request = client.post(url);
+        if let Some((name, value)) = auth_header {
+            request = request.header(name, value);
+        }
+        let request = request
             .header("anthropic-version", "2023-06-01")
             .header("Content-Type", "application/json")
-            .json(&data)
-            .send()
-            .await?;
+            .json(&data);
+        let response = retry::send(request, &self.config.retry).await?;
 
         if !response.status().is_success() {
+            let status = response.status();
             let error_text = response.text().await?;
-            log::error!("OpenAIClient: Request failed with error: {}", error_text);
-            return Err(anyhow::anyhow!("Error: {}", error_text));
+            tracing::error!("OpenAIClient: Request failed with error: {}", error_text);
+            return Err(retry::StatusError {
+                status,
+                message: error_text,
+            }
+            .into());
         }
-        log::info!(
+        tracing::info!(
             "AnthropicClient: Request successful with status: {}",
             response.status()
         );
         let text_data = response.text().await?;
-        log::info!("AnthropicClient: Response data: {}", text_data);
+        tracing::info!("AnthropicClient: Response data: {}", text_data);
         let completion_response: CompletionResponse = self.deserialize_response(text_data)?;
         Ok(completion_response)
     }
 
     async fn request_models(&self) -> anyhow::Result<Vec<Model>> {
-        log::info!("AnthropicClient: Requesting available models");
-        if self.config.api_key.is_empty() {
-            return Err(anyhow::anyhow!("API key is not set".to_string()));
-        }
-        let client = reqwest::Client::new();
+        tracing::info!("AnthropicClient: Requesting available models");
+        let auth_header = self.auth_header()?;
+        let client = http::client(&self.config.timeouts, self.config.proxy.as_ref());
         let url = format!("{}/models", self.config.endpoint.trim_end_matches('/'));
-        let response = client
-            .get(url)
-            .header("x-api-key", self.config.api_key.clone())
-            .header("anthropic-version", "2023-06-01")
-            .send()
-            .await;
+        let mut request = client.get(url);
+        if let Some((name, value)) = auth_header {
+            request = request.header(name, value);
+        }
+        let request = request.header("anthropic-version", "2023-06-01");
+        let response = retry::send(request, &self.config.retry).await;
         match response {
             Ok(resp) => {
                 if resp.status().is_success() {
@@ -82,23 +141,24 @@ impl AnthropicClient {
                                     Some(Model {
                                         name: name?,
                                         id: id?,
+                                        capabilities: None,
                                     })
                                 })
                                 .collect::<Vec<Model>>()
                         })
                         .unwrap_or_default();
-                    log::info!("AnthropicClient: Available models: {:?}", models);
+                    tracing::info!("AnthropicClient: Available models: {:?}", models);
                     Ok(models)
                 } else {
                     let status = resp.status();
                     let body = resp.text().await.map_err(anyhow::Error::from)?;
-                    log::error!("AnthropicClient: Request failed with status: {}", status);
-                    log::error!("AnthropicClient: Response body: {:?}", body);
+                    tracing::error!("AnthropicClient: Request failed with status: {}", status);
+                    tracing::error!("AnthropicClient: Response body: {:?}", body);
                     Err(anyhow::anyhow!("Error: {}", status))
                 }
             }
             Err(e) => {
-                log::error!("AnthropicClient: Request failed: {}", e);
+                tracing::error!("AnthropicClient: Request failed: {}", e);
                 Err(anyhow::anyhow!("Request failed: {}", e))
             }
         }
@@ -111,10 +171,26 @@ impl AnthropicClient {
         let request_json = serde_json::json!(request);
         match request_json {
             serde_json::Value::Object(mut map) => {
-                map.insert(
-                    "max_tokens".to_string(),
-                    serde_json::Value::Number(self.config.max_tokens.into()),
-                );
+                map.entry("max_tokens")
+                    .or_insert(serde_json::Value::Number(self.config.max_tokens.into()));
+                if let Some(budget) = self.config.thinking_budget {
+                    // Thinking counts towards max_tokens, and can't be
+                    // combined with changes to the sampling parameters.
+                    let max_tokens = map["max_tokens"].as_u64().unwrap_or_default();
+                    map.insert(
+                        "max_tokens".to_string(),
+                        (max_tokens + budget as u64).into(),
+                    );
+                    map.insert(
+                        "thinking".to_string(),
+                        serde_json::json!({ "type": "enabled", "budget_tokens": budget }),
+                    );
+                    map.remove("temperature");
+                    map.remove("top_p");
+                }
+                if self.config.prompt_caching {
+                    mark_cacheable(&mut map);
+                }
                 Ok(serde_json::Value::Object(map))
             }
             _ => Err(anyhow::anyhow!("Invalid request format")),
@@ -128,12 +204,34 @@ impl AnthropicClient {
     }
 }
 
+/// Add cache breakpoints after the last tool definition and the system
+/// prompt, so that prefix of the request is read from the cache on later
+/// turns.
+fn mark_cacheable(request: &mut serde_json::Map<String, serde_json::Value>) {
+    let cache_control = serde_json::json!({ "type": "ephemeral" });
+    if let Some(serde_json::Value::String(system)) = request.get("system") {
+        let block = serde_json::json!([{
+            "type": "text",
+            "text": system,
+            "cache_control": cache_control,
+        }]);
+        request.insert("system".to_string(), block);
+    }
+    if let Some(tool) = request
+        .get_mut("tools")
+        .and_then(|tools| tools.as_array_mut())
+        .and_then(|tools| tools.last_mut())
+    {
+        tool["cache_control"] = cache_control;
+    }
+}
+
 impl ErgonClient for AnthropicClient {
     async fn complete_message(
         &self,
         request: CompletionRequest,
     ) -> anyhow::Result<CompletionResponse> {
-        log::info!(
+        tracing::info!(
             "AnthropicClient: Completing message with {} messages using model {}",
             request.messages.len(),
             request.model
@@ -146,42 +244,86 @@ impl ErgonClient for AnthropicClient {
     }
 
     async fn list_models(&self) -> anyhow::Result<Vec<Model>> {
-        log::info!("AnthropicClient: Listing models");
+        tracing::info!("AnthropicClient: Listing models");
         self.request_models().await
     }
 }
 
-impl Default for AnthropicClient {
-    fn default() -> Self {
-        AnthropicClient {
-            config: Config::default().anthropic,
-        }
-    }
-}
-
 #[derive(Debug, Serialize)]
 pub struct AnthropicCompletionRequest {
     pub model: String,
+    /// The system messages' text, which the API takes apart from the
+    /// conversation.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
     pub messages: Vec<AnthropicMessage>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<AnthropicTool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub temperature: Option<f32>,
-    pub max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    /// Falls back to `AnthropicConfig::max_tokens` when unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub stop_sequences: Vec<String>,
 }
 
 impl From<CompletionRequest> for AnthropicCompletionRequest {
-    fn from(request: CompletionRequest) -> Self {
+    fn from(mut request: CompletionRequest) -> Self {
+        history::rename_tool_ids(&mut request.messages, history::sanitized_tool_id);
+        let (system, messages): (Vec<Message>, Vec<Message>) = request
+            .messages
+            .into_iter()
+            .partition(|message| message.role == "system");
+        let system: Vec<String> = system
+            .iter()
+            .flat_map(|message| message.text_content())
+            .cloned()
+            .collect();
         AnthropicCompletionRequest {
             model: request.model,
-            messages: request
-                .messages
+            system: (!system.is_empty()).then(|| system.join("\n\n")),
+            messages: merge_turns(messages.into_iter().map(AnthropicMessage::from)),
+            tools: request
+                .tools
                 .into_iter()
-                .map(AnthropicMessage::from)
+                .flatten()
+                .map(|Tool::Function(function)| AnthropicTool {
+                    name: function.name,
+                    description: function.description,
+                    input_schema: function.parameters,
+                })
                 .collect(),
             temperature: request.temperature,
-            max_tokens: 2048, // Default value; can be overridden in client
+            top_p: request.top_p,
+            max_tokens: request.max_tokens,
+            stop_sequences: request.stop,
         }
     }
 }
 
+/// Join consecutive messages from the same role into one turn, so the
+/// results of parallel tool calls reach the API together.
+fn merge_turns(messages: impl Iterator<Item = AnthropicMessage>) -> Vec<AnthropicMessage> {
+    let mut turns: Vec<AnthropicMessage> = Vec::new();
+    for message in messages {
+        match turns.last_mut() {
+            Some(last) if last.role == message.role => last.content.extend(message.content),
+            _ => turns.push(message),
+        }
+    }
+    turns
+}
+
+#[derive(Debug, Serialize)]
+pub struct AnthropicTool {
+    pub name: String,
+    pub description: String,
+    pub input_schema: serde_json::Value,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct AnthropicCompletionResponse {
     pub id: String,
@@ -198,24 +340,39 @@ pub struct AnthropicCompletionResponse {
 
 impl From<AnthropicCompletionResponse> for CompletionResponse {
     fn from(response: AnthropicCompletionResponse) -> Self {
-        // Convert Anthropic content blocks to unified Content format
+        // Convert Anthropic content blocks to unified Content format,
+        // setting thinking aside as the message's reasoning.
+        let mut thoughts = Vec::new();
+        let mut signature = None;
         let content: Vec<crate::models::Content> = response
             .content
             .into_iter()
-            .map(|c| match c {
-                AnthropicMessageContent::Text { text } => crate::models::Content::Text { text },
+            .filter_map(|c| match c {
+                AnthropicMessageContent::Text { text } => {
+                    Some(crate::models::Content::Text { text })
+                }
                 AnthropicMessageContent::ToolUse { id, name, input } => {
-                    crate::models::Content::ToolUse { id, name, input }
+                    Some(crate::models::Content::ToolUse { id, name, input })
                 }
                 AnthropicMessageContent::ToolResult {
                     tool_use_id,
                     content,
                     is_error,
-                } => crate::models::Content::ToolResult {
+                } => Some(crate::models::Content::ToolResult {
                     tool_use_id,
                     content,
                     is_error,
-                },
+                }),
+                AnthropicMessageContent::Thinking {
+                    thinking,
+                    signature: sig,
+                } => {
+                    thoughts.push(thinking);
+                    signature = Some(sig);
+                    None
+                }
+                AnthropicMessageContent::RedactedThinking { .. }
+                | AnthropicMessageContent::Image { .. } => None,
             })
             .collect();
 
@@ -224,9 +381,16 @@ impl From<AnthropicCompletionResponse> for CompletionResponse {
             role: response.role,
             content,
             tool_calls: None,
-            reasoning_content: None,
+            reasoning_content: (!thoughts.is_empty()).then(|| thoughts.join("\n\n")),
+            reasoning_signature: signature,
             tool_call_id: None,
         };
+        let finish_reason = match response.stop_reason.as_str() {
+            "end_turn" | "stop_sequence" => "stop",
+            "tool_use" => "tool_calls",
+            "max_tokens" => "length",
+            other => other,
+        };
 
         CompletionResponse {
             id: response.id,
@@ -236,8 +400,12 @@ impl From<AnthropicCompletionResponse> for CompletionResponse {
             choices: vec![Choice {
                 index: 0,
                 message: vec![message],
-                finish_reason: response.stop_reason,
+                finish_reason: finish_reason.to_string(),
             }],
+            usage: Some(TokenUsage {
+                prompt_tokens: response.usage.input_tokens,
+                completion_tokens: response.usage.output_tokens,
+            }),
         }
     }
 }
@@ -256,9 +424,11 @@ pub struct AnthropicMessage {
 
 impl From<Message> for AnthropicMessage {
     fn from(message: Message) -> Self {
-        let content = message
+        let mut content: Vec<AnthropicMessageContent> = message
             .content
             .into_iter()
+            // The API rejects empty text blocks.
+            .filter(|c| !matches!(c, crate::models::Content::Text { text } if text.is_empty()))
             .map(|c| match c {
                 crate::models::Content::Text { text } => AnthropicMessageContent::Text { text },
                 crate::models::Content::ToolUse { id, name, input } => {
@@ -273,15 +443,44 @@ impl From<Message> for AnthropicMessage {
                     content,
                     is_error,
                 },
-                crate::models::Content::ImageUrl { .. } => todo!("Handle ImageUrl content"),
+                crate::models::Content::ImageUrl { image_url } => AnthropicMessageContent::Image {
+                    // The API can't fetch images from this machine.
+                    source: AnthropicImageSource::from_url(images::inline_local_image(
+                        image_url.url,
+                    )),
+                },
                 crate::models::Content::File { .. } => todo!("Handle File content"),
                 crate::models::Content::Audio { .. } => todo!("Handle Audio content"),
             })
             .collect();
-        AnthropicMessage {
-            role: message.role,
-            content,
+        // Signed thinking goes back first, as the API requires during a
+        // tool loop.
+        if let (Some(thinking), Some(signature)) =
+            (message.reasoning_content, message.reasoning_signature)
+        {
+            content.insert(
+                0,
+                AnthropicMessageContent::Thinking {
+                    thinking,
+                    signature,
+                },
+            );
         }
+        // Tool calls recorded in the OpenAI shape become `tool_use` blocks.
+        content.extend(message.tool_calls.into_iter().flatten().map(|call| {
+            AnthropicMessageContent::ToolUse {
+                id: call.id,
+                name: call.function.name,
+                input: serde_json::from_str(&call.function.arguments)
+                    .unwrap_or_else(|_| serde_json::json!({})),
+            }
+        }));
+        // Anthropic has no `tool` role; tool results are sent by the user.
+        let role = match message.role.as_str() {
+            "tool" => "user".to_string(),
+            _ => message.role,
+        };
+        AnthropicMessage { role, content }
     }
 }
 
@@ -303,4 +502,368 @@ pub enum AnthropicMessageContent {
         #[serde(skip_serializing_if = "Option::is_none")]
         is_error: Option<bool>,
     },
+    #[serde(rename = "thinking")]
+    Thinking { thinking: String, signature: String },
+    /// Thinking flagged by safety systems, returned encrypted.
+    #[serde(rename = "redacted_thinking")]
+    RedactedThinking { data: String },
+    #[serde(rename = "image")]
+    Image { source: AnthropicImageSource },
+}
+
+/// Where the data of an image block comes from.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AnthropicImageSource {
+    Base64 { media_type: String, data: String },
+    Url { url: String },
+}
+
+impl AnthropicImageSource {
+    /// Inline data for a `data:` URL, the URL itself otherwise.
+    fn from_url(url: String) -> Self {
+        match url
+            .strip_prefix("data:")
+            .and_then(|rest| rest.split_once(";base64,"))
+        {
+            Some((media_type, data)) => AnthropicImageSource::Base64 {
+                media_type: media_type.to_string(),
+                data: data.to_string(),
+            },
+            None => AnthropicImageSource::Url { url },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{ToolCall, ToolFunction};
+
+    #[test]
+    fn test_tool_calls_and_results_map_to_anthropic_blocks() {
+        let mut assistant = Message::assistant("Let me check.");
+        assistant.tool_calls = Some(vec![ToolCall {
+            id: "toolu_1".to_string(),
+            _type: "function".to_string(),
+            function: ToolFunction {
+                name: "weather".to_string(),
+                arguments: "{\"city\":\"Oslo\"}".to_string(),
+            },
+        }]);
+        let converted = AnthropicMessage::from(assistant);
+        assert_eq!(converted.role, "assistant");
+        assert!(matches!(
+            &converted.content[1],
+            AnthropicMessageContent::ToolUse { id, name, input }
+                if id == "toolu_1" && name == "weather" && input["city"] == "Oslo"
+        ));
+
+        let result = AnthropicMessage::from(Message::tool_result("toolu_1", "sunny", None));
+        assert_eq!(result.role, "user");
+        assert!(matches!(
+            &result.content[0],
+            AnthropicMessageContent::ToolResult { tool_use_id, .. } if tool_use_id == "toolu_1"
+        ));
+    }
+
+    #[test]
+    fn test_images_map_to_image_blocks() {
+        let mut message = Message::user("What is this?", None);
+        message.content.push(crate::models::Content::image_url(
+            "data:image/png;base64,iVBORw0KGgo=",
+        ));
+        message.content.push(crate::models::Content::image_url(
+            "https://example.com/cat.jpg",
+        ));
+
+        let converted = serde_json::to_value(AnthropicMessage::from(message)).unwrap();
+        assert_eq!(
+            converted["content"][1],
+            serde_json::json!({"type": "image", "source": {
+                "type": "base64", "media_type": "image/png", "data": "iVBORw0KGgo="
+            }})
+        );
+        assert_eq!(
+            converted["content"][2]["source"],
+            serde_json::json!({"type": "url", "url": "https://example.com/cat.jpg"})
+        );
+
+        let path = std::env::temp_dir().join("ergon_test_anthropic_image.png");
+        image::RgbImage::new(1, 1).save(&path).unwrap();
+        let mut message = Message::user("And this?", None);
+        message
+            .content
+            .push(crate::models::Content::image_url(path.to_str().unwrap()));
+        let converted = serde_json::to_value(AnthropicMessage::from(message)).unwrap();
+        assert_eq!(converted["content"][1]["source"]["type"], "base64");
+        assert_eq!(converted["content"][1]["source"]["media_type"], "image/png");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_request_max_tokens_overrides_config() {
+        let client = AnthropicClient {
+            config: AnthropicConfig {
+                max_tokens: 1024,
+                ..AnthropicConfig::default()
+            },
+            auth: Auth::ApiKey,
+        };
+        let request = |max_tokens| CompletionRequest {
+            model: "claude".to_string(),
+            messages: vec![Message::user("Hi", None)],
+            temperature: None,
+            top_p: Some(0.9),
+            max_tokens,
+            tools: None,
+            reasoning_effort: None,
+            stop: vec!["END".to_string()],
+            seed: None,
+            presence_penalty: None,
+            frequency_penalty: None,
+        };
+
+        let json = client.serialize_request(request(None).into()).unwrap();
+        assert_eq!(json["max_tokens"], 1024);
+        assert_eq!(json["stop_sequences"], serde_json::json!(["END"]));
+        assert!(json.get("temperature").is_none());
+        assert!((json["top_p"].as_f64().unwrap() - 0.9).abs() < 0.01);
+
+        let json = client.serialize_request(request(Some(64)).into()).unwrap();
+        assert_eq!(json["max_tokens"], 64);
+    }
+
+    #[test]
+    fn test_system_messages_are_lifted_out_of_the_conversation() {
+        let request = AnthropicCompletionRequest::from(CompletionRequest {
+            model: "claude".to_string(),
+            messages: vec![
+                Message::system("Be brief."),
+                Message::user("Hi", None),
+                Message::assistant("Hello!"),
+                Message::system("Answer in French."),
+                Message::user("How are you?", None),
+            ],
+            temperature: None,
+            top_p: None,
+            max_tokens: None,
+            tools: None,
+            reasoning_effort: None,
+            stop: vec![],
+            seed: None,
+            presence_penalty: None,
+            frequency_penalty: None,
+        });
+        assert_eq!(
+            request.system.as_deref(),
+            Some("Be brief.\n\nAnswer in French.")
+        );
+        assert!(request.messages.iter().all(|m| m.role != "system"));
+        assert_eq!(request.messages.len(), 3);
+
+        let json = serde_json::to_value(&request).unwrap();
+        assert_eq!(json["messages"][0]["role"], "user");
+
+        let request = AnthropicCompletionRequest::from(CompletionRequest {
+            model: "claude".to_string(),
+            messages: vec![Message::user("Hi", None)],
+            temperature: None,
+            top_p: None,
+            max_tokens: None,
+            tools: None,
+            reasoning_effort: None,
+            stop: vec![],
+            seed: None,
+            presence_penalty: None,
+            frequency_penalty: None,
+        });
+        assert!(serde_json::to_value(&request)
+            .unwrap()
+            .get("system")
+            .is_none());
+    }
+
+    #[test]
+    fn test_system_prompt_and_tools_are_cacheable() {
+        let request = || CompletionRequest {
+            model: "claude".to_string(),
+            messages: vec![Message::system("Be brief."), Message::user("Hi", None)],
+            temperature: None,
+            top_p: None,
+            max_tokens: None,
+            tools: Some(vec![Tool::Function(crate::models::Function {
+                name: "weather".to_string(),
+                description: "Look up the weather".to_string(),
+                parameters: serde_json::json!({"type": "object"}),
+            })]),
+            reasoning_effort: None,
+            stop: vec![],
+            seed: None,
+            presence_penalty: None,
+            frequency_penalty: None,
+        };
+
+        let client = AnthropicClient::new(AnthropicConfig::default());
+        let json = client.serialize_request(request().into()).unwrap();
+        assert_eq!(json["system"], "Be brief.");
+        assert_eq!(json["messages"].as_array().unwrap().len(), 1);
+        assert_eq!(json["tools"][0]["input_schema"]["type"], "object");
+        assert!(json["tools"][0].get("cache_control").is_none());
+
+        let client = AnthropicClient::new(AnthropicConfig {
+            prompt_caching: true,
+            ..AnthropicConfig::default()
+        });
+        let json = client.serialize_request(request().into()).unwrap();
+        assert_eq!(json["system"][0]["text"], "Be brief.");
+        assert_eq!(json["system"][0]["cache_control"]["type"], "ephemeral");
+        assert_eq!(json["tools"][0]["cache_control"]["type"], "ephemeral");
+    }
+
+    #[test]
+    fn test_thinking_budget_adds_to_max_tokens() {
+        let client = AnthropicClient::new(AnthropicConfig {
+            max_tokens: 1024,
+            thinking_budget: Some(2048),
+            ..AnthropicConfig::default()
+        });
+        let request = CompletionRequest {
+            model: "claude".to_string(),
+            messages: vec![Message::user("Hi", None)],
+            temperature: Some(0.2),
+            top_p: None,
+            max_tokens: None,
+            tools: None,
+            reasoning_effort: None,
+            stop: vec![],
+            seed: None,
+            presence_penalty: None,
+            frequency_penalty: None,
+        };
+
+        let json = client.serialize_request(request.into()).unwrap();
+        assert_eq!(json["max_tokens"], 3072);
+        assert_eq!(json["thinking"]["type"], "enabled");
+        assert_eq!(json["thinking"]["budget_tokens"], 2048);
+        assert!(json.get("temperature").is_none());
+    }
+
+    #[test]
+    fn test_thinking_round_trips_with_its_signature() {
+        let response = AnthropicClient::new(AnthropicConfig::default())
+            .deserialize_response(
+                r#"{"id": "msg_1", "model": "claude", "role": "assistant", "type": "message",
+                    "content": [
+                        {"type": "thinking", "thinking": "Check the forecast.", "signature": "sig"},
+                        {"type": "redacted_thinking", "data": "opaque"},
+                        {"type": "text", "text": "Let me look."}
+                    ],
+                    "stop_reason": "end_turn",
+                    "usage": {"input_tokens": 12, "output_tokens": 3}}"#
+                    .to_string(),
+            )
+            .unwrap();
+        let message = response.choices[0].message[0].clone();
+        assert_eq!(
+            message.reasoning_content.as_deref(),
+            Some("Check the forecast.")
+        );
+        assert_eq!(message.text_content(), vec!["Let me look."]);
+
+        let converted = AnthropicMessage::from(message);
+        assert!(matches!(
+            &converted.content[0],
+            AnthropicMessageContent::Thinking { thinking, signature }
+                if thinking == "Check the forecast." && signature == "sig"
+        ));
+        assert_eq!(converted.content.len(), 2);
+    }
+
+    #[test]
+    fn test_tool_use_round_trip() {
+        let response = AnthropicClient::new(AnthropicConfig::default())
+            .deserialize_response(
+                r#"{"id": "msg_1", "model": "claude", "role": "assistant", "type": "message",
+                    "content": [
+                        {"type": "tool_use", "id": "toolu_1", "name": "weather",
+                         "input": {"city": "Oslo"}},
+                        {"type": "tool_use", "id": "toolu_2", "name": "weather",
+                         "input": {"city": "Bergen"}}
+                    ],
+                    "stop_reason": "tool_use",
+                    "usage": {"input_tokens": 12, "output_tokens": 3}}"#
+                    .to_string(),
+            )
+            .unwrap();
+        let deltas = response.into_deltas();
+        assert!(matches!(
+            &deltas[0],
+            crate::models::CompletionDelta::ToolCall { id: Some(id), arguments, .. }
+                if id == "toolu_1" && arguments.contains("Oslo")
+        ));
+        assert_eq!(
+            deltas.last(),
+            Some(&crate::models::CompletionDelta::Finished {
+                finish_reason: "tool_calls".to_string()
+            })
+        );
+
+        // The calls as the chat records them, followed by their results.
+        let mut assistant = Message::assistant("");
+        assistant.tool_calls = Some(
+            ["toolu_1", "toolu_2"]
+                .map(|id| ToolCall {
+                    id: id.to_string(),
+                    _type: "function".to_string(),
+                    function: ToolFunction {
+                        name: "weather".to_string(),
+                        arguments: "{}".to_string(),
+                    },
+                })
+                .to_vec(),
+        );
+        let request = AnthropicCompletionRequest::from(CompletionRequest {
+            model: "claude".to_string(),
+            messages: vec![
+                Message::user("Weather in Oslo and Bergen?", None),
+                assistant,
+                Message::tool_result("toolu_1", "-3C", None),
+                Message::tool_result("toolu_2", "2C", None),
+            ],
+            temperature: None,
+            top_p: None,
+            max_tokens: None,
+            tools: None,
+            reasoning_effort: None,
+            stop: vec![],
+            seed: None,
+            presence_penalty: None,
+            frequency_penalty: None,
+        });
+        let roles: Vec<&str> = request.messages.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, vec!["user", "assistant", "user"]);
+        // No empty text block next to the calls.
+        assert_eq!(request.messages[1].content.len(), 2);
+        assert_eq!(request.messages[2].content.len(), 2);
+    }
+
+    #[test]
+    fn test_response_usage_is_reported() {
+        let response = AnthropicClient::new(AnthropicConfig::default())
+            .deserialize_response(
+                r#"{"id": "msg_1", "model": "claude", "role": "assistant", "type": "message",
+                    "content": [{"type": "text", "text": "Hi!"}], "stop_reason": "end_turn",
+                    "usage": {"input_tokens": 12, "output_tokens": 3}}"#
+                    .to_string(),
+            )
+            .unwrap();
+        assert_eq!(
+            response.usage,
+            Some(TokenUsage {
+                prompt_tokens: 12,
+                completion_tokens: 3,
+            })
+        );
+    }
 }
@@ -0,0 +1,297 @@
+//! The audit log: one JSON line per request sent to a provider, appended to
+//! `~/.ergon/audit.log` while [`Config::audit_log`] is on. Only completions
+//! and embeddings are logged, since those carry the user's data; listing a
+//! provider's models is not. Requests made for a turn of a conversation
+//! carry its id, as its traced spans do.
+//!
+//! [`Config::audit_log`]: crate::config::Config::audit_log
+
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use iced::futures::{future::BoxFuture, stream, FutureExt, StreamExt};
+use serde_json::json;
+
+use super::{
+    CompletionDelta, CompletionRequest, CompletionResponse, CompletionStream, EmbeddingRequest,
+    Model, Provider,
+};
+use crate::{
+    config::ergon_dir, export::iso_timestamp, models::TokenUsage, storage::unix_now, trace,
+};
+
+/// Where the audit log is kept.
+pub fn path() -> PathBuf {
+    ergon_dir().join("audit.log")
+}
+
+/// A request being audited, written to the log when dropped so that
+/// cancelled streams are logged too.
+struct Entry {
+    path: PathBuf,
+    provider: String,
+    kind: &'static str,
+    model: String,
+    started: i64,
+    turn: Option<u64>,
+    usage: Option<TokenUsage>,
+    /// `None` until the request finishes; still `None` when dropped means
+    /// it was cancelled.
+    outcome: Option<Result<(), String>>,
+}
+
+impl Entry {
+    fn new(path: &Path, provider: &str, kind: &'static str, model: &str) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            provider: provider.to_string(),
+            kind,
+            model: model.to_string(),
+            started: unix_now(),
+            turn: trace::current_turn(),
+            usage: None,
+            outcome: None,
+        }
+    }
+
+    /// Record how the request finished.
+    fn finish(&mut self, outcome: Result<(), String>) {
+        self.outcome = Some(outcome);
+    }
+
+    fn line(&self) -> String {
+        let mut line = json!({
+            "timestamp": iso_timestamp(self.started),
+            "provider": self.provider,
+            "kind": self.kind,
+            "model": self.model,
+            "turn": self.turn,
+            "prompt_tokens": self.usage.map(|usage| usage.prompt_tokens),
+            "completion_tokens": self.usage.map(|usage| usage.completion_tokens),
+            "status": match &self.outcome {
+                Some(Ok(())) => "ok",
+                Some(Err(_)) => "error",
+                None => "cancelled",
+            },
+        });
+        if let Some(Err(err)) = &self.outcome {
+            line["error"] = json!(err);
+        }
+        line.to_string()
+    }
+}
+
+impl Drop for Entry {
+    fn drop(&mut self) {
+        let path = std::mem::take(&mut self.path);
+        let line = format!("{}\n", self.line());
+        // Entries are dropped on the async executor, which must not wait on
+        // the disk.
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn_blocking(move || append(&path, &line));
+            }
+            Err(_) => append(&path, &line),
+        }
+    }
+}
+
+/// Append `line` to the log at `path` in a single write, so the lines of
+/// concurrent requests never interleave.
+fn append(path: &Path, line: &str) {
+    let written = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(line.as_bytes()));
+    if let Err(e) = written {
+        tracing::warn!("Failed to write to {}: {}", path.display(), e);
+    }
+}
+
+/// A provider whose requests are written to the audit log.
+#[derive(Debug)]
+pub struct Audited {
+    provider: String,
+    inner: Arc<dyn Provider>,
+    path: PathBuf,
+}
+
+impl Audited {
+    pub fn new(provider: String, inner: Arc<dyn Provider>, path: PathBuf) -> Self {
+        Self {
+            provider,
+            inner,
+            path,
+        }
+    }
+}
+
+impl Provider for Audited {
+    fn complete_message(
+        &self,
+        request: CompletionRequest,
+    ) -> BoxFuture<'static, anyhow::Result<CompletionResponse>> {
+        let mut entry = Entry::new(&self.path, &self.provider, "completion", &request.model);
+        let response = self.inner.complete_message(request);
+        async move {
+            let response = response.await;
+            match &response {
+                Ok(response) => {
+                    entry.usage = response.usage;
+                    entry.finish(Ok(()));
+                }
+                Err(e) => entry.finish(Err(e.to_string())),
+            }
+            response
+        }
+        .boxed()
+    }
+
+    fn stream_message(
+        &self,
+        request: CompletionRequest,
+    ) -> BoxFuture<'static, anyhow::Result<CompletionStream>> {
+        let mut entry = Entry::new(&self.path, &self.provider, "completion", &request.model);
+        let deltas = self.inner.stream_message(request);
+        async move {
+            let deltas = match deltas.await {
+                Ok(deltas) => deltas,
+                Err(e) => {
+                    entry.finish(Err(e.to_string()));
+                    return Err(e);
+                }
+            };
+            // The entry travels with the stream, logged once it ends or is
+            // dropped.
+            let audited = stream::unfold((deltas, entry), |(mut deltas, mut entry)| async move {
+                let Some(delta) = deltas.next().await else {
+                    entry.outcome.get_or_insert(Ok(()));
+                    return None;
+                };
+                match &delta {
+                    Ok(CompletionDelta::Usage(usage)) => entry.usage = Some(*usage),
+                    Err(e) => entry.finish(Err(e.to_string())),
+                    Ok(_) => {}
+                }
+                Some((delta, (deltas, entry)))
+            });
+            Ok(audited.boxed())
+        }
+        .boxed()
+    }
+
+    fn list_models(&self) -> BoxFuture<'static, anyhow::Result<Vec<Model>>> {
+        self.inner.list_models()
+    }
+
+    fn embed(
+        &self,
+        request: EmbeddingRequest,
+    ) -> BoxFuture<'static, anyhow::Result<Vec<Vec<f32>>>> {
+        let mut entry = Entry::new(&self.path, &self.provider, "embeddings", &request.model);
+        let embeddings = self.inner.embed(request);
+        async move {
+            let embeddings = embeddings.await;
+            entry.finish(embeddings.as_ref().map(|_| ()).map_err(|e| e.to_string()));
+            embeddings
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Streams a short answer and its usage, and fails to embed.
+    #[derive(Debug)]
+    struct Scripted;
+
+    impl Provider for Scripted {
+        fn complete_message(
+            &self,
+            _request: CompletionRequest,
+        ) -> BoxFuture<'static, anyhow::Result<CompletionResponse>> {
+            unimplemented!()
+        }
+
+        fn stream_message(
+            &self,
+            _request: CompletionRequest,
+        ) -> BoxFuture<'static, anyhow::Result<CompletionStream>> {
+            let deltas = vec![
+                Ok(CompletionDelta::Text("Hi".to_string())),
+                Ok(CompletionDelta::Usage(TokenUsage {
+                    prompt_tokens: 12,
+                    completion_tokens: 3,
+                })),
+            ];
+            async move { Ok(stream::iter(deltas).boxed()) }.boxed()
+        }
+
+        fn list_models(&self) -> BoxFuture<'static, anyhow::Result<Vec<Model>>> {
+            unimplemented!()
+        }
+
+        fn embed(
+            &self,
+            _request: EmbeddingRequest,
+        ) -> BoxFuture<'static, anyhow::Result<Vec<Vec<f32>>>> {
+            async move { Err(anyhow::anyhow!("Rate limited")) }.boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_requests_are_appended_to_the_log() {
+        let path =
+            std::env::temp_dir().join(format!("ergon_test_audit_{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let audited = Audited::new("OpenAI".to_string(), Arc::new(Scripted), path.clone());
+
+        let request: CompletionRequest =
+            serde_json::from_value(json!({ "model": "gpt-4o", "messages": [] })).unwrap();
+        let deltas = trace::in_turn(7, tracing::Span::none(), audited.stream_message(request));
+        let deltas: Vec<_> = deltas.await.unwrap().collect().await;
+        assert_eq!(deltas.len(), 2);
+        let request = EmbeddingRequest {
+            model: "text-embedding-3-small".to_string(),
+            input: vec!["Hello".to_string()],
+        };
+        assert!(audited.embed(request).await.is_err());
+
+        // Lines are written in the background, in no particular order.
+        let mut lines: Vec<serde_json::Value> = vec![];
+        for _ in 0..100 {
+            let log = std::fs::read_to_string(&path).unwrap_or_default();
+            lines = log
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect();
+            if lines.len() == 2 {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(lines.len(), 2);
+        lines.sort_by_key(|line| line["kind"].to_string());
+        assert_eq!(lines[0]["provider"], "OpenAI");
+        assert_eq!(lines[0]["kind"], "completion");
+        assert_eq!(lines[0]["model"], "gpt-4o");
+        assert_eq!(lines[0]["turn"], 7);
+        assert_eq!(lines[0]["prompt_tokens"], 12);
+        assert_eq!(lines[0]["completion_tokens"], 3);
+        assert_eq!(lines[0]["status"], "ok");
+        assert!(lines[0]["timestamp"].as_str().unwrap().ends_with('Z'));
+        assert_eq!(lines[1]["kind"], "embeddings");
+        assert_eq!(lines[1]["status"], "error");
+        assert_eq!(lines[1]["error"], "Rate limited");
+        assert!(lines[1]["prompt_tokens"].is_null());
+        assert!(lines[1]["turn"].is_null());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,163 @@
+//! The Azure OpenAI client.
+//!
+//! Azure serves the OpenAI chat completions API per deployment, with the
+//! API version as a query parameter and the key in an `api-key` header.
+
+use crate::{
+    api::{clients::openai_compatible::OpenAICompatible, http},
+    config::{AzureConfig, RetryConfig},
+    models::{CompletionRequest, CompletionResponse, EmbeddingRequest},
+};
+
+use super::{CompletionStream, ErgonClient, Model};
+
+#[derive(Debug, Clone)]
+pub struct AzureClient {
+    config: AzureConfig,
+    deployment_url: String,
+}
+
+impl AzureClient {
+    pub fn new(config: AzureConfig) -> Self {
+        let deployment_url = config.deployment_url();
+        Self {
+            config,
+            deployment_url,
+        }
+    }
+
+    fn check_configured(&self) -> anyhow::Result<()> {
+        if self.config.is_configured() {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!(
+                "Azure OpenAI key, resource or deployment is not set".to_string()
+            ))
+        }
+    }
+}
+
+impl OpenAICompatible for AzureClient {
+    async fn request(&self, request: CompletionRequest) -> anyhow::Result<CompletionResponse> {
+        self.check_configured()?;
+        self.request_completion(request).await
+    }
+
+    fn endpoint(&self) -> &str {
+        &self.deployment_url
+    }
+
+    fn api_key(&self) -> Option<&str> {
+        Some(&self.config.api_key)
+    }
+
+    fn retry(&self) -> &RetryConfig {
+        &self.config.retry
+    }
+
+    fn http_client(&self) -> reqwest::Client {
+        http::client(&self.config.timeouts, self.config.proxy.as_ref())
+    }
+
+    fn completions_url(&self) -> String {
+        format!(
+            "{}/chat/completions?api-version={}",
+            self.deployment_url, self.config.api_version
+        )
+    }
+
+    /// Embedding models are deployments of their own, named by `model`.
+    fn embeddings_url(&self, model: &str) -> String {
+        format!(
+            "https://{}.openai.azure.com/openai/deployments/{}/embeddings?api-version={}",
+            self.config.resource_name, model, self.config.api_version
+        )
+    }
+
+    fn auth_header(&self) -> Option<(String, String)> {
+        Some(("api-key".to_string(), self.config.api_key.clone()))
+    }
+}
+
+impl ErgonClient for AzureClient {
+    async fn complete_message(
+        &self,
+        request: CompletionRequest,
+    ) -> anyhow::Result<CompletionResponse> {
+        if request.messages.is_empty() {
+            return Err(anyhow::anyhow!("No messages provided".to_string()));
+        }
+        self.request(request).await
+    }
+
+    async fn stream_message(&self, request: CompletionRequest) -> anyhow::Result<CompletionStream> {
+        if request.messages.is_empty() {
+            return Err(anyhow::anyhow!("No messages provided".to_string()));
+        }
+        self.check_configured()?;
+        self.request_completion_stream(request).await
+    }
+
+    async fn embed(&self, request: EmbeddingRequest) -> anyhow::Result<Vec<Vec<f32>>> {
+        if self.config.api_key.is_empty() || self.config.resource_name.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Azure OpenAI key or resource is not set".to_string()
+            ));
+        }
+        self.request_embeddings(request).await
+    }
+
+    /// Deployments can only be enumerated through the Azure management API,
+    /// so the configured deployment is the only model offered.
+    async fn list_models(&self) -> anyhow::Result<Vec<Model>> {
+        self.check_configured()?;
+        Ok(vec![Model {
+            name: self.config.deployment_name.clone(),
+            id: self.config.deployment_name.clone(),
+            capabilities: None,
+        }])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deployment_url_and_auth_header() {
+        let client = AzureClient::new(AzureConfig {
+            api_key: "secret".to_string(),
+            resource_name: "contoso".to_string(),
+            deployment_name: "gpt-4o".to_string(),
+            api_version: "2024-10-21".to_string(),
+            ..AzureConfig::default()
+        });
+        assert_eq!(
+            client.completions_url(),
+            "https://contoso.openai.azure.com/openai/deployments/gpt-4o/chat/completions?api-version=2024-10-21"
+        );
+        assert_eq!(
+            client.embeddings_url("text-embedding-3-small"),
+            "https://contoso.openai.azure.com/openai/deployments/text-embedding-3-small/embeddings?api-version=2024-10-21"
+        );
+        assert_eq!(
+            client.auth_header(),
+            Some(("api-key".to_string(), "secret".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_models_requires_configuration() {
+        let client = AzureClient::new(AzureConfig::default());
+        assert!(client.list_models().await.is_err());
+
+        let client = AzureClient::new(AzureConfig {
+            api_key: "secret".to_string(),
+            resource_name: "contoso".to_string(),
+            deployment_name: "gpt-4o".to_string(),
+            ..AzureConfig::default()
+        });
+        let models = client.list_models().await.unwrap();
+        assert_eq!(models[0].name, "gpt-4o");
+    }
+}
@@ -0,0 +1,510 @@
+//! The AWS Bedrock client, using the Converse API.
+//!
+//! Converse gives every Bedrock-hosted chat model (Claude, Titan, Llama, …)
+//! the same request shape. Requests are signed with SigV4 using the
+//! credentials from [`BedrockConfig`].
+
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    api::http,
+    config::BedrockConfig,
+    models::{
+        Choice, CompletionRequest, CompletionResponse, Content, Message, ModelCapabilities,
+        TokenUsage, Tool,
+    },
+    storage::unix_now,
+};
+
+use super::{
+    capabilities,
+    history::sanitized_tool_id,
+    retry,
+    sigv4::{uri_encode, Signer},
+    ErgonClient, Model,
+};
+
+#[derive(Debug, Clone)]
+pub struct BedrockClient {
+    config: BedrockConfig,
+}
+
+impl BedrockClient {
+    pub fn new(config: BedrockConfig) -> Self {
+        Self { config }
+    }
+
+    fn signer(&self) -> Signer<'_> {
+        Signer {
+            access_key_id: &self.config.access_key_id,
+            secret_access_key: &self.config.secret_access_key,
+            session_token: Some(self.config.session_token.as_str()).filter(|t| !t.is_empty()),
+            region: &self.config.region,
+            // The runtime endpoint signs for the same service name.
+            service: "bedrock",
+        }
+    }
+
+    /// Send a signed request to `https://<host><path>?<query>`.
+    async fn send(
+        &self,
+        method: reqwest::Method,
+        host: &str,
+        path: &str,
+        query: &str,
+        body: Vec<u8>,
+    ) -> anyhow::Result<serde_json::Value> {
+        if !self.config.is_configured() {
+            return Err(anyhow::anyhow!(
+                "AWS region or credentials are not set".to_string()
+            ));
+        }
+        let headers = self
+            .signer()
+            .headers(method.as_str(), host, path, query, &body, unix_now());
+
+        let mut url = format!("https://{host}{path}");
+        if !query.is_empty() {
+            url = format!("{url}?{query}");
+        }
+        tracing::info!("BedrockClient: Sending request to {}", url);
+        let mut request = http::client(&self.config.timeouts, self.config.proxy.as_ref())
+            .request(method, url)
+            .header("Content-Type", "application/json")
+            .body(body);
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        let response = retry::send(request, &self.config.retry).await?;
+
+        let status = response.status();
+        let json: serde_json::Value = response.json().await.unwrap_or_default();
+        if !status.is_success() {
+            let message = json["message"].as_str().unwrap_or_default();
+            tracing::error!("BedrockClient: Request failed with {}: {}", status, message);
+            return Err(retry::StatusError {
+                status,
+                message: message.to_string(),
+            }
+            .into());
+        }
+        Ok(json)
+    }
+
+    async fn request(&self, request: CompletionRequest) -> anyhow::Result<CompletionResponse> {
+        let host = format!("bedrock-runtime.{}.amazonaws.com", self.config.region);
+        let path = format!("/model/{}/converse", uri_encode(&request.model));
+        let body = serde_json::to_vec(&converse_payload(&request))?;
+        let json = self
+            .send(reqwest::Method::POST, &host, &path, "", body)
+            .await?;
+        let response: ConverseResponse = serde_json::from_value(json)?;
+        Ok(response.into_completion(request.model))
+    }
+
+    async fn request_models(&self) -> anyhow::Result<Vec<Model>> {
+        let host = format!("bedrock.{}.amazonaws.com", self.config.region);
+        let json = self
+            .send(
+                reqwest::Method::GET,
+                &host,
+                "/foundation-models",
+                "byOutputModality=TEXT",
+                Vec::new(),
+            )
+            .await?;
+        Ok(chat_models(&json))
+    }
+}
+
+impl ErgonClient for BedrockClient {
+    async fn complete_message(
+        &self,
+        request: CompletionRequest,
+    ) -> anyhow::Result<CompletionResponse> {
+        tracing::info!(
+            "BedrockClient: Completing message with {} messages using model {}",
+            request.messages.len(),
+            request.model
+        );
+        if request.messages.is_empty() {
+            return Err(anyhow::anyhow!("No messages provided".to_string()));
+        }
+        self.request(request).await
+    }
+
+    async fn list_models(&self) -> anyhow::Result<Vec<Model>> {
+        tracing::info!("BedrockClient: Listing models");
+        self.request_models().await
+    }
+}
+
+/// Text models that can be invoked on demand. Model ids are used as names
+/// since display names such as "Claude 3.5 Sonnet" clash with the
+/// Anthropic client's.
+fn chat_models(json: &serde_json::Value) -> Vec<Model> {
+    json["modelSummaries"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|model| {
+            model["inferenceTypesSupported"]
+                .as_array()
+                .is_some_and(|types| types.iter().any(|t| t == "ON_DEMAND"))
+        })
+        .filter_map(|model| {
+            let id = model["modelId"].as_str()?;
+            Some(Model {
+                name: id.to_string(),
+                id: id.to_string(),
+                capabilities: model["inputModalities"].as_array().map(|modalities| {
+                    ModelCapabilities {
+                        vision: modalities.iter().any(|m| m == "IMAGE"),
+                        ..capabilities::for_model(id)
+                    }
+                }),
+            })
+        })
+        .collect()
+}
+
+/// Build the Converse request body for `request`. System messages move to
+/// the top-level `system` list, tool results are sent by the user, and
+/// consecutive messages from the same role are merged since Converse
+/// requires the roles to alternate.
+fn converse_payload(request: &CompletionRequest) -> serde_json::Value {
+    let mut system = Vec::new();
+    let mut messages: Vec<(String, Vec<serde_json::Value>)> = Vec::new();
+    for message in &request.messages {
+        if message.role == "system" {
+            system.extend(
+                message
+                    .text_content()
+                    .into_iter()
+                    .map(|text| json!({ "text": text })),
+            );
+            continue;
+        }
+        let role = if message.role == "assistant" {
+            "assistant"
+        } else {
+            "user"
+        };
+        let blocks = content_blocks(message);
+        if blocks.is_empty() {
+            continue;
+        }
+        match messages.last_mut() {
+            Some((last_role, last_blocks)) if last_role == role => last_blocks.extend(blocks),
+            _ => messages.push((role.to_string(), blocks)),
+        }
+    }
+
+    let mut payload = json!({
+        "messages": messages
+            .into_iter()
+            .map(|(role, content)| json!({ "role": role, "content": content }))
+            .collect::<Vec<_>>(),
+    });
+    if !system.is_empty() {
+        payload["system"] = json!(system);
+    }
+    let mut inference = serde_json::Map::new();
+    if let Some(max_tokens) = request.max_tokens {
+        inference.insert("maxTokens".to_string(), json!(max_tokens));
+    }
+    if let Some(temperature) = request.temperature {
+        inference.insert("temperature".to_string(), json!(temperature));
+    }
+    if let Some(top_p) = request.top_p {
+        inference.insert("topP".to_string(), json!(top_p));
+    }
+    if !request.stop.is_empty() {
+        inference.insert("stopSequences".to_string(), json!(request.stop));
+    }
+    if !inference.is_empty() {
+        payload["inferenceConfig"] = serde_json::Value::Object(inference);
+    }
+    if let Some(tools) = request.tools.as_ref().filter(|tools| !tools.is_empty()) {
+        let specs: Vec<_> = tools
+            .iter()
+            .map(|Tool::Function(function)| {
+                json!({ "toolSpec": {
+                    "name": function.name,
+                    "description": function.description,
+                    "inputSchema": { "json": function.parameters },
+                }})
+            })
+            .collect();
+        payload["toolConfig"] = json!({ "tools": specs });
+    }
+    payload
+}
+
+/// The Converse content blocks for one message.
+fn content_blocks(message: &Message) -> Vec<serde_json::Value> {
+    let mut blocks = Vec::new();
+    for content in &message.content {
+        match content {
+            Content::Text { text } if !text.trim().is_empty() => {
+                blocks.push(json!({ "text": text }))
+            }
+            Content::Text { .. } => {}
+            Content::ImageUrl { image_url } => match image_block(&image_url.url) {
+                Some(block) => blocks.push(block),
+                None => tracing::warn!("BedrockClient: Skipping image that is not a data URL"),
+            },
+            Content::ToolUse { id, name, input } => blocks.push(json!({
+                "toolUse": { "toolUseId": sanitized_tool_id(id), "name": name, "input": input },
+            })),
+            Content::ToolResult {
+                tool_use_id,
+                content,
+                is_error,
+            } => {
+                let mut result = json!({
+                    "toolUseId": sanitized_tool_id(tool_use_id),
+                    "content": [{ "text": content }],
+                });
+                if *is_error == Some(true) {
+                    result["status"] = json!("error");
+                }
+                blocks.push(json!({ "toolResult": result }));
+            }
+            Content::File { .. } | Content::Audio { .. } => {
+                tracing::warn!("BedrockClient: Skipping file or audio content");
+            }
+        }
+    }
+    // Tool calls recorded in the OpenAI shape become `toolUse` blocks.
+    blocks.extend(message.tool_calls.iter().flatten().map(|call| {
+        let input: serde_json::Value =
+            serde_json::from_str(&call.function.arguments).unwrap_or_else(|_| json!({}));
+        json!({
+            "toolUse": {
+                "toolUseId": sanitized_tool_id(&call.id),
+                "name": call.function.name,
+                "input": input,
+            },
+        })
+    }));
+    blocks
+}
+
+/// Converse only takes inline image bytes, so only `data:` URLs map.
+fn image_block(url: &str) -> Option<serde_json::Value> {
+    let (header, data) = url.strip_prefix("data:image/")?.split_once(',')?;
+    let format = header.strip_suffix(";base64")?;
+    let format = if format == "jpg" { "jpeg" } else { format };
+    Some(json!({ "image": { "format": format, "source": { "bytes": data } } }))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ConverseResponse {
+    output: ConverseOutput,
+    stop_reason: String,
+    usage: Option<ConverseUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ConverseOutput {
+    message: ConverseMessage,
+}
+
+#[derive(Debug, Deserialize)]
+struct ConverseMessage {
+    #[serde(default)]
+    content: Vec<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ConverseUsage {
+    input_tokens: u32,
+    output_tokens: u32,
+}
+
+impl ConverseResponse {
+    fn into_completion(self, model: String) -> CompletionResponse {
+        let mut content = Vec::new();
+        let mut reasoning = Vec::new();
+        for block in self.output.message.content {
+            if let Some(text) = block["text"].as_str() {
+                content.push(Content::text(text));
+            } else if let Some(tool_use) = block.get("toolUse") {
+                content.push(Content::ToolUse {
+                    id: tool_use["toolUseId"]
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_string(),
+                    name: tool_use["name"].as_str().unwrap_or_default().to_string(),
+                    input: tool_use["input"].clone(),
+                });
+            } else if let Some(text) = block["reasoningContent"]["reasoningText"]["text"].as_str() {
+                reasoning.push(text.to_string());
+            }
+        }
+        let message = Message {
+            role: "assistant".to_string(),
+            content,
+            tool_calls: None,
+            reasoning_content: (!reasoning.is_empty()).then(|| reasoning.join("\n\n")),
+            reasoning_signature: None,
+            tool_call_id: None,
+        };
+        let finish_reason = match self.stop_reason.as_str() {
+            "end_turn" | "stop_sequence" => "stop",
+            "tool_use" => "tool_calls",
+            "max_tokens" => "length",
+            other => other,
+        };
+
+        CompletionResponse {
+            id: String::new(), // Converse responses carry no id
+            object: "bedrock.converse".to_string(),
+            created: 0,
+            model,
+            choices: vec![Choice {
+                index: 0,
+                message: vec![message],
+                finish_reason: finish_reason.to_string(),
+            }],
+            usage: self.usage.map(|usage| TokenUsage {
+                prompt_tokens: usage.input_tokens,
+                completion_tokens: usage.output_tokens,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Function, ToolCall, ToolFunction};
+
+    #[test]
+    fn test_converse_payload() {
+        let mut assistant = Message::assistant("Checking.");
+        assistant.tool_calls = Some(vec![ToolCall {
+            id: "tool_1".to_string(),
+            _type: "function".to_string(),
+            function: ToolFunction {
+                name: "weather".to_string(),
+                arguments: "{\"city\":\"Oslo\"}".to_string(),
+            },
+        }]);
+        let request = CompletionRequest {
+            model: "anthropic.claude-3-haiku-20240307-v1:0".to_string(),
+            messages: vec![
+                Message::system("Be brief."),
+                Message::user("Weather in Oslo?", None),
+                assistant,
+                Message::tool_result("tool_1", "-3C", Some(false)),
+                Message::user("And tomorrow?", None),
+            ],
+            temperature: None,
+            top_p: Some(0.9),
+            max_tokens: Some(256),
+            tools: Some(vec![Tool::Function(Function {
+                name: "weather".to_string(),
+                description: "Look up the weather".to_string(),
+                parameters: json!({"type": "object"}),
+            })]),
+            reasoning_effort: None,
+            stop: vec![],
+            seed: None,
+            presence_penalty: None,
+            frequency_penalty: None,
+        };
+
+        let payload = converse_payload(&request);
+        assert_eq!(payload["system"], json!([{ "text": "Be brief." }]));
+        assert_eq!(payload["inferenceConfig"]["maxTokens"], 256);
+        assert!(payload["inferenceConfig"].get("temperature").is_none());
+        assert_eq!(
+            payload["toolConfig"]["tools"][0]["toolSpec"]["inputSchema"]["json"]["type"],
+            "object"
+        );
+
+        let messages = payload["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[1]["role"], "assistant");
+        assert_eq!(
+            messages[1]["content"][1]["toolUse"]["input"]["city"],
+            "Oslo"
+        );
+        // The tool result and the follow-up question share one user turn.
+        assert_eq!(messages[2]["role"], "user");
+        assert_eq!(
+            messages[2]["content"][0]["toolResult"]["toolUseId"],
+            "tool_1"
+        );
+        assert_eq!(messages[2]["content"][1]["text"], "And tomorrow?");
+    }
+
+    #[test]
+    fn test_image_block_requires_data_url() {
+        assert_eq!(
+            image_block("data:image/jpg;base64,AAAA"),
+            Some(json!({ "image": { "format": "jpeg", "source": { "bytes": "AAAA" } } }))
+        );
+        assert_eq!(image_block("https://example.com/cat.png"), None);
+    }
+
+    #[test]
+    fn test_response_maps_content_and_usage() {
+        let response: ConverseResponse = serde_json::from_str(
+            r#"{"output": {"message": {"role": "assistant", "content": [
+                    {"reasoningContent": {"reasoningText": {"text": "Need the tool."}}},
+                    {"text": "Let me check."},
+                    {"toolUse": {"toolUseId": "tool_1", "name": "weather", "input": {"city": "Oslo"}}}]}},
+                "stopReason": "tool_use",
+                "usage": {"inputTokens": 30, "outputTokens": 12, "totalTokens": 42}}"#,
+        )
+        .unwrap();
+        let completion = response.into_completion("amazon.titan-text-express-v1".to_string());
+        let choice = &completion.choices[0];
+        assert_eq!(choice.finish_reason, "tool_calls");
+        let message = &choice.message[0];
+        assert_eq!(message.reasoning_content.as_deref(), Some("Need the tool."));
+        assert_eq!(message.text_content()[0], "Let me check.");
+        assert!(matches!(
+            &message.content[1],
+            Content::ToolUse { id, name, .. } if id == "tool_1" && name == "weather"
+        ));
+        assert_eq!(
+            completion.usage,
+            Some(TokenUsage {
+                prompt_tokens: 30,
+                completion_tokens: 12,
+            })
+        );
+    }
+
+    #[test]
+    fn test_chat_models_keeps_on_demand_models() {
+        let json = json!({ "modelSummaries": [
+            {"modelId": "anthropic.claude-3-haiku-20240307-v1:0",
+             "inputModalities": ["TEXT", "IMAGE"],
+             "inferenceTypesSupported": ["ON_DEMAND"]},
+            {"modelId": "anthropic.claude-3-opus-20240229-v1:0:200k",
+             "inferenceTypesSupported": ["PROVISIONED"]},
+            {"modelId": "amazon.titan-text-express-v1",
+             "inputModalities": ["TEXT"],
+             "inferenceTypesSupported": ["ON_DEMAND", "PROVISIONED"]}
+        ]});
+        let models = chat_models(&json);
+        let ids: Vec<&str> = models.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(
+            ids,
+            vec![
+                "anthropic.claude-3-haiku-20240307-v1:0",
+                "amazon.titan-text-express-v1"
+            ]
+        );
+        assert!(models[0].capabilities.unwrap().vision);
+        assert!(!models[1].capabilities.unwrap().vision);
+    }
+}
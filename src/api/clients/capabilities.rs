@@ -0,0 +1,66 @@
+//! Built-in capabilities of well-known models, for providers whose model
+//! lists don't describe them.
+
+use crate::models::ModelCapabilities;
+
+/// Model id prefixes and their capabilities. The first matching prefix
+/// wins, so more specific prefixes come first.
+const KNOWN_MODELS: &[(&str, bool, bool, u32, bool)] = &[
+    // (prefix, vision, tools, context length, reasoning)
+    ("gpt-5-chat", true, true, 128_000, false),
+    ("gpt-5", true, true, 400_000, true),
+    ("gpt-4.1", true, true, 1_047_576, false),
+    ("gpt-4o", true, true, 128_000, false),
+    ("gpt-4-turbo", true, true, 128_000, false),
+    ("gpt-4", false, true, 8_192, false),
+    ("gpt-3.5-turbo", false, true, 16_385, false),
+    ("o1-mini", false, false, 128_000, true),
+    ("o1", true, true, 200_000, true),
+    ("o3", true, true, 200_000, true),
+    ("o4-mini", true, true, 200_000, true),
+    ("claude-2", false, false, 100_000, false),
+    ("claude-instant", false, false, 100_000, false),
+    ("claude", true, true, 200_000, false),
+    ("command-a-vision", true, false, 128_000, false),
+    ("command-a", false, true, 256_000, false),
+    ("command-r", false, true, 128_000, false),
+    ("pixtral", true, true, 128_000, false),
+    ("llava", true, false, 4_096, false),
+];
+
+/// The capabilities of the model `id`, or the permissive default for
+/// unknown models. Provider prefixes such as `openai/` (OpenRouter) and
+/// `anthropic.` (Bedrock) are ignored.
+pub fn for_model(id: &str) -> ModelCapabilities {
+    let name = id.rsplit('/').next().unwrap_or(id).to_lowercase();
+    KNOWN_MODELS
+        .iter()
+        .find(|(prefix, ..)| name.starts_with(prefix) || name.contains(&format!(".{prefix}")))
+        .map(
+            |&(_, vision, tools, context_length, reasoning)| ModelCapabilities {
+                vision,
+                tools,
+                context_length: Some(context_length),
+                reasoning,
+            },
+        )
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_for_model() {
+        assert!(!for_model("gpt-3.5-turbo-0125").vision);
+        assert_eq!(for_model("gpt-4o-mini").context_length, Some(128_000));
+        assert!(!for_model("o1-mini").tools);
+        assert!(for_model("openai/o1").tools);
+        assert!(for_model("o3-mini").reasoning);
+        assert!(!for_model("gpt-5-chat-latest").reasoning);
+        assert!(!for_model("gpt-4o").reasoning);
+        assert!(for_model("us.anthropic.claude-3-5-sonnet-20240620-v1:0").vision);
+        assert_eq!(for_model("my-finetune"), ModelCapabilities::default());
+    }
+}
@@ -0,0 +1,474 @@
+//! The Cohere API client, using the v2 chat endpoint.
+//!
+//! Cohere takes OpenAI-style tool definitions and tool calls, but message
+//! content, sampling parameters and responses have their own shape.
+//! Citations attached to a response are appended to the reply as a list of
+//! sources, since Ergon has no separate place to show them.
+
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    api::http,
+    config::CohereConfig,
+    models::{
+        Choice, CompletionRequest, CompletionResponse, Content, Message, TokenUsage, ToolCall,
+    },
+};
+
+use super::{retry, ErgonClient, Model};
+
+#[derive(Debug, Clone)]
+pub struct CohereClient {
+    config: CohereConfig,
+}
+
+impl CohereClient {
+    pub fn new(config: CohereConfig) -> Self {
+        Self { config }
+    }
+
+    async fn request(&self, request: CompletionRequest) -> anyhow::Result<CompletionResponse> {
+        if self.config.api_key.is_empty() {
+            return Err(anyhow::anyhow!("API key is not set".to_string()));
+        }
+        let url = format!("{}/v2/chat", self.config.endpoint.trim_end_matches('/'));
+        tracing::info!("CohereClient: Sending request to {}", url);
+        let http_request = http::client(&self.config.timeouts, self.config.proxy.as_ref())
+            .post(url)
+            .bearer_auth(&self.config.api_key)
+            .json(&request_payload(&request));
+        let response = retry::send(http_request, &self.config.retry).await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().await?;
+            tracing::error!("CohereClient: Request failed with error: {}", error_text);
+            return Err(retry::StatusError {
+                status,
+                message: error_text,
+            }
+            .into());
+        }
+        let response: CohereChatResponse = response.json().await?;
+        Ok(response.into_completion(request.model))
+    }
+
+    async fn request_models(&self) -> anyhow::Result<Vec<Model>> {
+        if self.config.api_key.is_empty() {
+            return Err(anyhow::anyhow!("API key is not set".to_string()));
+        }
+        let url = format!(
+            "{}/v1/models?endpoint=chat",
+            self.config.endpoint.trim_end_matches('/')
+        );
+        let request = http::client(&self.config.timeouts, self.config.proxy.as_ref())
+            .get(url)
+            .bearer_auth(&self.config.api_key);
+        let response = retry::send(request, &self.config.retry).await?;
+        if !response.status().is_success() {
+            tracing::error!(
+                "CohereClient: List models failed with status: {}",
+                response.status()
+            );
+            return Err(anyhow::anyhow!("Error: {}", response.status()));
+        }
+        let json: serde_json::Value = response.json().await?;
+        Ok(json["models"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|model| model["name"].as_str())
+            .map(|name| Model {
+                name: name.to_string(),
+                id: name.to_string(),
+                capabilities: None,
+            })
+            .collect())
+    }
+}
+
+impl ErgonClient for CohereClient {
+    async fn complete_message(
+        &self,
+        request: CompletionRequest,
+    ) -> anyhow::Result<CompletionResponse> {
+        tracing::info!(
+            "CohereClient: Completing message with {} messages using model {}",
+            request.messages.len(),
+            request.model
+        );
+        if request.messages.is_empty() {
+            return Err(anyhow::anyhow!("No messages provided".to_string()));
+        }
+        self.request(request).await
+    }
+
+    async fn list_models(&self) -> anyhow::Result<Vec<Model>> {
+        tracing::info!("CohereClient: Listing models");
+        self.request_models().await
+    }
+}
+
+/// Build the `/v2/chat` request body for `request`.
+fn request_payload(request: &CompletionRequest) -> serde_json::Value {
+    let mut payload = json!({
+        "model": request.model,
+        "messages": request.messages.iter().map(cohere_message).collect::<Vec<_>>(),
+    });
+    if let Some(temperature) = request.temperature {
+        payload["temperature"] = json!(temperature);
+    }
+    if let Some(top_p) = request.top_p {
+        payload["p"] = json!(top_p);
+    }
+    if let Some(max_tokens) = request.max_tokens {
+        payload["max_tokens"] = json!(max_tokens);
+    }
+    if !request.stop.is_empty() {
+        payload["stop_sequences"] = json!(request.stop);
+    }
+    if let Some(seed) = request.seed {
+        payload["seed"] = json!(seed);
+    }
+    if let Some(penalty) = request.presence_penalty {
+        payload["presence_penalty"] = json!(penalty);
+    }
+    if let Some(penalty) = request.frequency_penalty {
+        payload["frequency_penalty"] = json!(penalty);
+    }
+    if let Some(tools) = request.tools.as_ref().filter(|tools| !tools.is_empty()) {
+        payload["tools"] = json!(tools);
+    }
+    payload
+}
+
+/// Convert one message. Tool results become `tool` messages, and tool calls
+/// recorded either OpenAI-style or as `ToolUse` blocks become `tool_calls`.
+fn cohere_message(message: &Message) -> serde_json::Value {
+    if message.role == "tool" {
+        let (tool_use_id, content) = message
+            .content
+            .iter()
+            .find_map(|c| match c {
+                Content::ToolResult {
+                    tool_use_id,
+                    content,
+                    ..
+                } => Some((Some(tool_use_id.clone()), content.clone())),
+                _ => None,
+            })
+            .unwrap_or_else(|| (None, message.text_content().into_iter().cloned().collect()));
+        return json!({
+            "role": "tool",
+            "tool_call_id": message.tool_call_id.clone().or(tool_use_id),
+            "content": content,
+        });
+    }
+
+    let mut content = Vec::new();
+    let mut tool_calls: Vec<ToolCall> = message.tool_calls.clone().unwrap_or_default();
+    for block in &message.content {
+        match block {
+            Content::Text { text } => content.push(json!({ "type": "text", "text": text })),
+            Content::ImageUrl { image_url } => content.push(json!({
+                "type": "image_url",
+                "image_url": { "url": image_url.url },
+            })),
+            Content::ToolUse { id, name, input } => tool_calls.push(ToolCall {
+                id: id.clone(),
+                _type: "function".to_string(),
+                function: crate::models::ToolFunction {
+                    name: name.clone(),
+                    arguments: input.to_string(),
+                },
+            }),
+            Content::ToolResult { content: text, .. } => {
+                content.push(json!({ "type": "text", "text": text }))
+            }
+            Content::File { .. } | Content::Audio { .. } => {
+                tracing::warn!(
+                    "CohereClient: Skipping file or audio content, which Cohere does not accept"
+                );
+            }
+        }
+    }
+
+    let mut converted = json!({ "role": message.role });
+    if !content.is_empty() {
+        converted["content"] = json!(content);
+    }
+    if !tool_calls.is_empty() {
+        converted["tool_calls"] = json!(tool_calls);
+    }
+    converted
+}
+
+#[derive(Debug, Deserialize)]
+struct CohereChatResponse {
+    id: String,
+    finish_reason: String,
+    message: CohereResponseMessage,
+    usage: Option<CohereUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CohereResponseMessage {
+    #[serde(default)]
+    content: Vec<CohereContent>,
+    #[serde(default)]
+    tool_plan: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<ToolCall>,
+    #[serde(default)]
+    citations: Vec<Citation>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum CohereContent {
+    Text {
+        text: String,
+    },
+    Thinking {
+        thinking: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+struct Citation {
+    #[serde(default)]
+    text: String,
+    #[serde(default)]
+    sources: Vec<CitationSource>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CitationSource {
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    document: Option<serde_json::Value>,
+}
+
+impl CitationSource {
+    /// A short label for the source: the document's title or URL when it
+    /// has one, otherwise its id.
+    fn label(&self) -> Option<String> {
+        let document = self.document.as_ref();
+        ["title", "url"]
+            .iter()
+            .find_map(|key| document?.get(key)?.as_str().map(str::to_string))
+            .or_else(|| self.id.clone())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct CohereUsage {
+    tokens: Option<CohereTokens>,
+    billed_units: Option<CohereTokens>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CohereTokens {
+    #[serde(default)]
+    input_tokens: f64,
+    #[serde(default)]
+    output_tokens: f64,
+}
+
+impl CohereChatResponse {
+    fn into_completion(self, model: String) -> CompletionResponse {
+        let mut text = String::new();
+        let mut reasoning = self.message.tool_plan;
+        for block in self.message.content {
+            match block {
+                CohereContent::Text { text: part } => text.push_str(&part),
+                CohereContent::Thinking { thinking } => reasoning
+                    .get_or_insert_with(String::new)
+                    .push_str(&thinking),
+                CohereContent::Other => {}
+            }
+        }
+        text.push_str(&sources_section(&self.message.citations));
+
+        let message = Message {
+            role: "assistant".to_string(),
+            content: if text.is_empty() {
+                vec![]
+            } else {
+                vec![Content::text(text)]
+            },
+            tool_calls: (!self.message.tool_calls.is_empty()).then_some(self.message.tool_calls),
+            reasoning_content: reasoning,
+            reasoning_signature: None,
+            tool_call_id: None,
+        };
+        let usage = self
+            .usage
+            .and_then(|usage| usage.tokens.or(usage.billed_units))
+            .map(|tokens| TokenUsage {
+                prompt_tokens: tokens.input_tokens as u32,
+                completion_tokens: tokens.output_tokens as u32,
+            });
+
+        CompletionResponse {
+            id: self.id,
+            object: "cohere.chat".to_string(),
+            created: 0, // Cohere responses carry no timestamp
+            model,
+            choices: vec![Choice {
+                index: 0,
+                message: vec![message],
+                finish_reason: finish_reason(&self.finish_reason).to_string(),
+            }],
+            usage,
+        }
+    }
+}
+
+/// Map Cohere's finish reasons onto the OpenAI names used elsewhere.
+fn finish_reason(reason: &str) -> &str {
+    match reason {
+        "COMPLETE" | "STOP_SEQUENCE" => "stop",
+        "MAX_TOKENS" => "length",
+        "TOOL_CALL" => "tool_calls",
+        other => other,
+    }
+}
+
+/// A markdown list of the cited passages and their sources, or an empty
+/// string when there are no citations.
+fn sources_section(citations: &[Citation]) -> String {
+    if citations.is_empty() {
+        return String::new();
+    }
+    let mut section = String::from("\n\n**Sources**\n");
+    for citation in citations {
+        let labels: Vec<String> = citation
+            .sources
+            .iter()
+            .filter_map(CitationSource::label)
+            .collect();
+        section.push_str(&format!("\n- \"{}\"", citation.text));
+        if !labels.is_empty() {
+            section.push_str(&format!(" — {}", labels.join(", ")));
+        }
+    }
+    section
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Function, Tool, ToolFunction};
+
+    #[test]
+    fn test_request_payload_maps_messages_and_sampling() {
+        let mut assistant = Message::assistant("Checking.");
+        assistant.tool_calls = Some(vec![ToolCall {
+            id: "call_1".to_string(),
+            _type: "function".to_string(),
+            function: ToolFunction {
+                name: "weather".to_string(),
+                arguments: "{\"city\":\"Oslo\"}".to_string(),
+            },
+        }]);
+        let request = CompletionRequest {
+            model: "command-r-plus".to_string(),
+            messages: vec![
+                Message::system("Be brief."),
+                Message::user("Weather in Oslo?", None),
+                assistant,
+                Message::tool_result("call_1", "-3C", None),
+            ],
+            temperature: Some(0.5),
+            top_p: Some(0.9),
+            max_tokens: None,
+            tools: Some(vec![Tool::Function(Function {
+                name: "weather".to_string(),
+                description: "Look up the weather".to_string(),
+                parameters: json!({"type": "object"}),
+            })]),
+            reasoning_effort: None,
+            stop: vec![],
+            seed: Some(7),
+            presence_penalty: None,
+            frequency_penalty: Some(0.25),
+        };
+
+        let payload = request_payload(&request);
+        assert_eq!(payload["model"], "command-r-plus");
+        assert_eq!(payload["seed"], 7);
+        assert_eq!(payload["frequency_penalty"], 0.25);
+        assert!(payload.get("presence_penalty").is_none());
+        assert!((payload["p"].as_f64().unwrap() - 0.9).abs() < 0.01);
+        assert!(payload.get("max_tokens").is_none());
+        assert_eq!(payload["tools"][0]["function"]["name"], "weather");
+
+        let messages = &payload["messages"];
+        assert_eq!(messages[0]["role"], "system");
+        assert_eq!(messages[1]["content"][0]["text"], "Weather in Oslo?");
+        assert_eq!(messages[2]["tool_calls"][0]["id"], "call_1");
+        assert_eq!(messages[2]["tool_calls"][0]["function"]["name"], "weather");
+        assert_eq!(messages[3]["role"], "tool");
+        assert_eq!(messages[3]["tool_call_id"], "call_1");
+        assert_eq!(messages[3]["content"], "-3C");
+    }
+
+    #[test]
+    fn test_response_maps_text_citations_and_usage() {
+        let response: CohereChatResponse = serde_json::from_str(
+            r#"{"id": "c1", "finish_reason": "COMPLETE",
+                "message": {"role": "assistant",
+                    "content": [{"type": "text", "text": "Oslo is cold."}],
+                    "citations": [{"start": 0, "end": 13, "text": "Oslo is cold.",
+                        "sources": [{"type": "document", "id": "doc:0",
+                                     "document": {"title": "Weather report"}},
+                                    {"type": "tool", "id": "weather:0", "tool_output": {}}]}]},
+                "usage": {"billed_units": {"input_tokens": 10, "output_tokens": 4},
+                          "tokens": {"input_tokens": 120, "output_tokens": 4}}}"#,
+        )
+        .unwrap();
+        let completion = response.into_completion("command-r-plus".to_string());
+        let choice = &completion.choices[0];
+        assert_eq!(choice.finish_reason, "stop");
+        assert_eq!(
+            choice.message[0].text_content()[0],
+            "Oslo is cold.\n\n**Sources**\n\n- \"Oslo is cold.\" — Weather report, weather:0"
+        );
+        assert_eq!(
+            completion.usage,
+            Some(TokenUsage {
+                prompt_tokens: 120,
+                completion_tokens: 4,
+            })
+        );
+    }
+
+    #[test]
+    fn test_response_tool_calls() {
+        let response: CohereChatResponse = serde_json::from_str(
+            r#"{"id": "c2", "finish_reason": "TOOL_CALL",
+                "message": {"role": "assistant", "tool_plan": "I will look it up.",
+                    "tool_calls": [{"id": "call_1", "type": "function",
+                        "function": {"name": "weather", "arguments": "{\"city\":\"Oslo\"}"}}]}}"#,
+        )
+        .unwrap();
+        let completion = response.into_completion("command-r-plus".to_string());
+        let message = &completion.choices[0].message[0];
+        assert_eq!(completion.choices[0].finish_reason, "tool_calls");
+        assert!(message.content.is_empty());
+        assert_eq!(
+            message.reasoning_content.as_deref(),
+            Some("I will look it up.")
+        );
+        assert_eq!(
+            message.tool_calls.as_ref().unwrap()[0].function.name,
+            "weather"
+        );
+        assert_eq!(completion.usage, None);
+    }
+}
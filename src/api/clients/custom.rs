@@ -0,0 +1,174 @@
+//! Client for providers declared in `settings.json`, speaking either the
+//! OpenAI or the Anthropic API.
+
+use crate::{
+    api::{
+        clients::{anthropic::AnthropicClient, openai_compatible::OpenAICompatible},
+        http,
+    },
+    config::{ApiFlavor, CustomProviderConfig, RetryConfig},
+    models::{CompletionRequest, CompletionResponse, EmbeddingRequest},
+};
+
+use super::{retry, CompletionStream, ErgonClient, Model};
+
+#[derive(Debug, Clone)]
+pub struct CustomClient {
+    config: CustomProviderConfig,
+}
+
+impl CustomClient {
+    pub fn new(config: CustomProviderConfig) -> Self {
+        Self { config }
+    }
+
+    fn anthropic(&self) -> AnthropicClient {
+        AnthropicClient::custom(&self.config)
+    }
+
+    async fn request_models(&self) -> anyhow::Result<Vec<Model>> {
+        let url = format!("{}/models", self.config.base_url.trim_end_matches('/'));
+        let mut request = http::client(&self.config.timeouts, self.config.proxy.as_ref()).get(url);
+        if let Some((name, value)) = self.auth_header() {
+            request = request.header(name, value);
+        }
+        let response = retry::send(request, &self.config.retry).await?;
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!("Error: {}", response.status()));
+        }
+        let json: serde_json::Value = response.json().await?;
+        Ok(json["data"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|model| model["id"].as_str())
+            .map(|id| Model {
+                name: id.to_string(),
+                id: id.to_string(),
+                capabilities: None,
+            })
+            .collect())
+    }
+}
+
+impl OpenAICompatible for CustomClient {
+    async fn request(&self, request: CompletionRequest) -> anyhow::Result<CompletionResponse> {
+        self.request_completion(request).await
+    }
+
+    fn endpoint(&self) -> &str {
+        &self.config.base_url
+    }
+
+    fn api_key(&self) -> Option<&str> {
+        Some(&self.config.api_key)
+    }
+
+    fn retry(&self) -> &RetryConfig {
+        &self.config.retry
+    }
+
+    fn http_client(&self) -> reqwest::Client {
+        http::client(&self.config.timeouts, self.config.proxy.as_ref())
+    }
+
+    fn auth_header(&self) -> Option<(String, String)> {
+        self.config.auth_header()
+    }
+}
+
+impl ErgonClient for CustomClient {
+    async fn complete_message(
+        &self,
+        request: CompletionRequest,
+    ) -> anyhow::Result<CompletionResponse> {
+        match self.config.flavor {
+            ApiFlavor::OpenAI => {
+                if request.messages.is_empty() {
+                    return Err(anyhow::anyhow!("No messages provided".to_string()));
+                }
+                self.request(request).await
+            }
+            ApiFlavor::Anthropic => self.anthropic().complete_message(request).await,
+        }
+    }
+
+    async fn stream_message(&self, request: CompletionRequest) -> anyhow::Result<CompletionStream> {
+        match self.config.flavor {
+            ApiFlavor::OpenAI => {
+                if request.messages.is_empty() {
+                    return Err(anyhow::anyhow!("No messages provided".to_string()));
+                }
+                self.request_completion_stream(request).await
+            }
+            ApiFlavor::Anthropic => self.anthropic().stream_message(request).await,
+        }
+    }
+
+    /// Only OpenAI-flavored providers have an embeddings API.
+    async fn embed(&self, request: EmbeddingRequest) -> anyhow::Result<Vec<Vec<f32>>> {
+        match self.config.flavor {
+            ApiFlavor::OpenAI => self.request_embeddings(request).await,
+            ApiFlavor::Anthropic => Err(anyhow::anyhow!(
+                "{} has no embeddings API",
+                self.config.name
+            )),
+        }
+    }
+
+    /// The configured models, or those listed by the provider if none are.
+    async fn list_models(&self) -> anyhow::Result<Vec<Model>> {
+        if !self.config.models.is_empty() {
+            return Ok(self
+                .config
+                .models
+                .iter()
+                .map(|id| Model {
+                    name: id.clone(),
+                    id: id.clone(),
+                    capabilities: None,
+                })
+                .collect());
+        }
+        match self.config.flavor {
+            ApiFlavor::OpenAI => self.request_models().await,
+            ApiFlavor::Anthropic => self.anthropic().list_models().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_configured_models_skip_listing() {
+        let client = CustomClient::new(CustomProviderConfig {
+            name: "Proxy".to_string(),
+            // Nothing listens here, so listing would fail.
+            base_url: "http://127.0.0.1:9/v1".to_string(),
+            models: vec!["llama-3.1-70b".to_string()],
+            ..CustomProviderConfig::default()
+        });
+        let models = client.list_models().await.unwrap();
+        assert_eq!(models[0].id, "llama-3.1-70b");
+    }
+
+    #[test]
+    fn test_openai_flavor_uses_auth_template() {
+        let client = CustomClient::new(CustomProviderConfig {
+            base_url: "https://llm.example.com/v1/".to_string(),
+            api_key: "k".to_string(),
+            auth_header: "X-Api-Key: {api_key}".to_string(),
+            ..CustomProviderConfig::default()
+        });
+        assert_eq!(
+            client.completions_url(),
+            "https://llm.example.com/v1/chat/completions"
+        );
+        assert_eq!(
+            client.auth_header(),
+            Some(("X-Api-Key".to_string(), "k".to_string()))
+        );
+    }
+}
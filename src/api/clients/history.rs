@@ -0,0 +1,257 @@
+//! Conversation history in one shape for every provider.
+//!
+//! Tool use is kept the way OpenAI-compatible APIs return it: the calls in
+//! an assistant message's `tool_calls` and each result in a `tool` message
+//! of its own. Anthropic responses and imported conversations carry
+//! `tool_use` and `tool_result` blocks in their content instead, and a
+//! conversation stopped during a tool loop may hold calls that were never
+//! answered. [`normalize`] rewrites such a history into that shape, which
+//! each client then converts into its own format, so a conversation can be
+//! continued on any provider.
+
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use crate::models::{Content, Message, ToolCall, ToolFunction};
+
+/// Sent in place of the result of a tool call that never got one.
+const MISSING_RESULT: &str = "The tool call was interrupted before it returned a result.";
+
+/// `messages` with tool calls in `tool_calls`, tool results in `tool`
+/// messages right after the calls they answer, every call answered and
+/// results without a call dropped.
+pub fn normalize(messages: Vec<Message>) -> Vec<Message> {
+    let mut normalized = Vec::new();
+    // Calls of the last assistant message still waiting for their result.
+    let mut pending: Vec<String> = Vec::new();
+    for message in messages.into_iter().flat_map(split_tool_blocks) {
+        if message.role == "tool" {
+            let Some(id) = result_id(&message) else {
+                continue;
+            };
+            match pending.iter().position(|pending| *pending == id) {
+                Some(index) => {
+                    pending.remove(index);
+                    normalized.push(message);
+                }
+                None => tracing::warn!("Dropping the result of unknown tool call {}", id),
+            }
+            continue;
+        }
+        answer_missing(&mut pending, &mut normalized);
+        pending = message
+            .tool_calls
+            .iter()
+            .flatten()
+            .map(|call| call.id.clone())
+            .collect();
+        normalized.push(message);
+    }
+    answer_missing(&mut pending, &mut normalized);
+    normalized
+}
+
+/// Move the `tool_use` blocks of `message` into its `tool_calls` and its
+/// `tool_result` blocks into `tool` messages ahead of it. The message
+/// itself is dropped if nothing else is left in it.
+fn split_tool_blocks(mut message: Message) -> Vec<Message> {
+    if message.role == "tool" {
+        return vec![message];
+    }
+    let mut split = Vec::new();
+    let mut content = Vec::new();
+    for block in message.content {
+        match block {
+            Content::ToolUse { id, name, input } => message
+                .tool_calls
+                .get_or_insert_with(Vec::new)
+                .push(ToolCall {
+                    id,
+                    _type: "function".to_string(),
+                    function: ToolFunction {
+                        name,
+                        arguments: input.to_string(),
+                    },
+                }),
+            Content::ToolResult {
+                tool_use_id,
+                content,
+                is_error,
+            } => split.push(Message::tool_result(tool_use_id, content, is_error)),
+            block => content.push(block),
+        }
+    }
+    message.content = content;
+    let empty = message
+        .content
+        .iter()
+        .all(|c| matches!(c, Content::Text { text } if text.trim().is_empty()));
+    if split.is_empty() || !empty || message.tool_calls.is_some() {
+        split.push(message);
+    }
+    split
+}
+
+/// The id of the call a `tool` message answers.
+fn result_id(message: &Message) -> Option<String> {
+    message.tool_call_id.clone().or_else(|| {
+        message.content.iter().find_map(|c| match c {
+            Content::ToolResult { tool_use_id, .. } => Some(tool_use_id.clone()),
+            _ => None,
+        })
+    })
+}
+
+fn answer_missing(pending: &mut Vec<String>, messages: &mut Vec<Message>) {
+    for id in pending.drain(..) {
+        tracing::warn!("Tool call {} has no result, sending a placeholder", id);
+        messages.push(Message::tool_result(id, MISSING_RESULT, Some(true)));
+    }
+}
+
+/// Replace every tool call id in `messages`, in the calls and in the
+/// results answering them alike, with `rename(id)`.
+pub fn rename_tool_ids(messages: &mut [Message], rename: impl Fn(&str) -> String) {
+    for message in messages {
+        for call in message.tool_calls.iter_mut().flatten() {
+            call.id = rename(&call.id);
+        }
+        if let Some(id) = &mut message.tool_call_id {
+            *id = rename(id);
+        }
+        for block in &mut message.content {
+            match block {
+                Content::ToolUse { id, .. } => *id = rename(id),
+                Content::ToolResult { tool_use_id, .. } => *tool_use_id = rename(tool_use_id),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// `id` with every character besides letters, digits, `_` and `-`
+/// replaced, as Anthropic and Bedrock require of tool call ids.
+pub fn sanitized_tool_id(id: &str) -> String {
+    let sanitized: String = id
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' => c,
+            _ => '_',
+        })
+        .collect();
+    if sanitized.is_empty() {
+        "tool".to_string()
+    } else {
+        sanitized
+    }
+}
+
+/// `id` as Mistral requires tool call ids: nine letters or digits. Other
+/// ids are replaced by one derived from them, so a call and its result
+/// still match.
+pub fn mistral_tool_id(id: &str) -> String {
+    const ALPHABET: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+    if id.len() == 9 && id.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return id.to_string();
+    }
+    let mut hasher = DefaultHasher::new();
+    id.hash(&mut hasher);
+    let mut hash = hasher.finish();
+    (0..9)
+        .map(|_| {
+            let c = ALPHABET[(hash % ALPHABET.len() as u64) as usize] as char;
+            hash /= ALPHABET.len() as u64;
+            c
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn call(id: &str) -> ToolCall {
+        ToolCall {
+            id: id.to_string(),
+            _type: "function".to_string(),
+            function: ToolFunction {
+                name: "weather".to_string(),
+                arguments: "{}".to_string(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_tool_blocks_become_tool_calls_and_messages() {
+        // As Anthropic returns it and the Claude import stores it.
+        let mut assistant = Message::assistant("Checking.");
+        assistant.content.push(Content::tool_use(
+            "toolu_1",
+            "weather",
+            json!({"city": "Oslo"}),
+        ));
+        let mut results = Message::user("", None);
+        results.content = vec![Content::tool_result("toolu_1", "-3C")];
+
+        let messages = normalize(vec![
+            Message::user("Weather in Oslo?", None),
+            assistant,
+            results,
+            Message::assistant("It's -3C."),
+        ]);
+        let roles: Vec<&str> = messages.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, ["user", "assistant", "tool", "assistant"]);
+
+        let calls = messages[1].tool_calls.as_ref().unwrap();
+        assert_eq!(calls[0].id, "toolu_1");
+        assert_eq!(calls[0].function.name, "weather");
+        assert_eq!(calls[0].function.arguments, r#"{"city":"Oslo"}"#);
+        assert_eq!(messages[1].content.len(), 1);
+        assert_eq!(messages[2].tool_call_id.as_deref(), Some("toolu_1"));
+    }
+
+    #[test]
+    fn test_unanswered_calls_get_a_result_and_stray_results_are_dropped() {
+        let mut assistant = Message::assistant("");
+        assistant.tool_calls = Some(vec![call("call_1"), call("call_2")]);
+
+        let messages = normalize(vec![
+            Message::user("Weather?", None),
+            assistant,
+            Message::tool_result("call_2", "-3C", None),
+            Message::tool_result("call_9", "stray", None),
+            Message::user("Never mind.", None),
+        ]);
+        let ids: Vec<Option<&str>> = messages.iter().map(|m| m.tool_call_id.as_deref()).collect();
+        assert_eq!(ids, [None, None, Some("call_2"), Some("call_1"), None]);
+        assert!(matches!(
+            &messages[3].content[0],
+            Content::ToolResult {
+                is_error: Some(true),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_renamed_ids_still_match() {
+        let mut assistant = Message::assistant("");
+        assistant.tool_calls = Some(vec![call("call_abc.123")]);
+        let mut messages = vec![assistant, Message::tool_result("call_abc.123", "ok", None)];
+
+        rename_tool_ids(&mut messages, mistral_tool_id);
+        let id = &messages[0].tool_calls.as_ref().unwrap()[0].id;
+        assert_eq!(id.len(), 9);
+        assert!(id.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert_eq!(messages[1].tool_call_id.as_ref(), Some(id));
+        assert!(matches!(
+            &messages[1].content[0],
+            Content::ToolResult { tool_use_id, .. } if tool_use_id == id
+        ));
+        assert_eq!(mistral_tool_id("Ab3dE6gh9"), "Ab3dE6gh9");
+
+        assert_eq!(sanitized_tool_id("call_abc.123"), "call_abc_123");
+        assert_eq!(sanitized_tool_id("toolu_01-X"), "toolu_01-X");
+    }
+}
@@ -0,0 +1,193 @@
+//! Client for OpenAI-compatible servers such as vLLM, LM Studio and
+//! llama.cpp, one per entry in `local_servers`.
+
+use crate::{
+    api::{clients::openai_compatible::OpenAICompatible, http},
+    config::{LocalServerConfig, RetryConfig},
+    models::{CompletionRequest, CompletionResponse, EmbeddingRequest, ModelCapabilities},
+};
+
+use super::{capabilities, retry, CompletionStream, ErgonClient, Model};
+
+#[derive(Debug, Clone)]
+pub struct LocalClient {
+    config: LocalServerConfig,
+}
+
+impl LocalClient {
+    pub fn new(config: LocalServerConfig) -> Self {
+        Self { config }
+    }
+
+    async fn request_models(&self) -> anyhow::Result<Vec<Model>> {
+        let url = format!("{}/models", self.config.endpoint.trim_end_matches('/'));
+        let mut request = http::client(&self.config.timeouts, self.config.proxy.as_ref()).get(url);
+        if let Some((name, value)) = self.auth_header() {
+            request = request.header(name, value);
+        }
+        let response = retry::send(request, &self.config.retry).await?;
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!("Error: {}", response.status()));
+        }
+        let json: serde_json::Value = response.json().await?;
+        Ok(self.offered_models(&json))
+    }
+
+    /// The models in a `/models` response that pass the server's filter.
+    /// vLLM reports each model's context length as `max_model_len`.
+    fn offered_models(&self, json: &serde_json::Value) -> Vec<Model> {
+        json["data"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|model| Some((model["id"].as_str()?, model)))
+            .filter(|(id, _)| self.config.offers(id))
+            .map(|(id, model)| Model {
+                name: id.to_string(),
+                id: id.to_string(),
+                capabilities: model["max_model_len"]
+                    .as_u64()
+                    .and_then(|n| u32::try_from(n).ok())
+                    .map(|len| ModelCapabilities {
+                        context_length: Some(len),
+                        ..capabilities::for_model(id)
+                    }),
+            })
+            .collect()
+    }
+}
+
+impl OpenAICompatible for LocalClient {
+    async fn request(&self, request: CompletionRequest) -> anyhow::Result<CompletionResponse> {
+        self.request_completion(request).await
+    }
+
+    fn endpoint(&self) -> &str {
+        &self.config.endpoint
+    }
+
+    fn api_key(&self) -> Option<&str> {
+        Some(self.config.api_key.as_str()).filter(|key| !key.is_empty())
+    }
+
+    fn retry(&self) -> &RetryConfig {
+        &self.config.retry
+    }
+
+    fn http_client(&self) -> reqwest::Client {
+        http::client(&self.config.timeouts, self.config.proxy.as_ref())
+    }
+}
+
+impl ErgonClient for LocalClient {
+    async fn complete_message(
+        &self,
+        request: CompletionRequest,
+    ) -> anyhow::Result<CompletionResponse> {
+        if request.messages.is_empty() {
+            return Err(anyhow::anyhow!("No messages provided".to_string()));
+        }
+        self.request(request).await
+    }
+
+    async fn stream_message(&self, request: CompletionRequest) -> anyhow::Result<CompletionStream> {
+        if request.messages.is_empty() {
+            return Err(anyhow::anyhow!("No messages provided".to_string()));
+        }
+        self.request_completion_stream(request).await
+    }
+
+    async fn embed(&self, request: EmbeddingRequest) -> anyhow::Result<Vec<Vec<f32>>> {
+        self.request_embeddings(request).await
+    }
+
+    /// The served models that pass the filter. If the server can't be asked,
+    /// the filter entries themselves are offered, so a server configured with
+    /// exact model ids stays usable.
+    async fn list_models(&self) -> anyhow::Result<Vec<Model>> {
+        match self.request_models().await {
+            Err(e) if !self.config.model_filter.is_empty() => {
+                tracing::warn!(
+                    "LocalClient: Listing models from {} failed, using the configured ones: {}",
+                    self.config.name,
+                    e
+                );
+                Ok(self
+                    .config
+                    .model_filter
+                    .iter()
+                    .map(|id| Model {
+                        name: id.clone(),
+                        id: id.clone(),
+                        capabilities: None,
+                    })
+                    .collect())
+            }
+            result => result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offered_models_applies_filter() {
+        let json = serde_json::json!({
+            "object": "list",
+            "data": [
+                {"id": "qwen2.5-7b-instruct", "max_model_len": 32768},
+                {"id": "nomic-embed-text"}
+            ]
+        });
+        let mut client = LocalClient {
+            config: LocalServerConfig::default(),
+        };
+        let models = client.offered_models(&json);
+        assert_eq!(models.len(), 2);
+        assert_eq!(models[0].capabilities.unwrap().context_length, Some(32768));
+        assert_eq!(models[1].capabilities, None);
+
+        client.config.model_filter = vec!["qwen".to_string()];
+        let names: Vec<String> = client
+            .offered_models(&json)
+            .into_iter()
+            .map(|m| m.name)
+            .collect();
+        assert_eq!(names, vec!["qwen2.5-7b-instruct"]);
+    }
+
+    #[tokio::test]
+    async fn test_list_models_falls_back_to_filter() {
+        let mut client = LocalClient {
+            config: LocalServerConfig {
+                // Nothing listens here, so listing fails.
+                endpoint: "http://127.0.0.1:9/v1/".to_string(),
+                retry: RetryConfig {
+                    max_retries: 0,
+                    ..RetryConfig::default()
+                },
+                ..LocalServerConfig::default()
+            },
+        };
+        assert!(client.list_models().await.is_err());
+
+        client.config.model_filter = vec!["google/gemma-3-270m".to_string()];
+        let models = client.list_models().await.unwrap();
+        assert_eq!(models[0].id, "google/gemma-3-270m");
+    }
+
+    #[test]
+    fn test_api_key_is_optional() {
+        let mut client = LocalClient {
+            config: LocalServerConfig::default(),
+        };
+        assert_eq!(client.auth_header(), None);
+        client.config.api_key = "token".to_string();
+        assert_eq!(
+            client.auth_header(),
+            Some(("Authorization".to_string(), "Bearer token".to_string()))
+        );
+    }
+}
@@ -0,0 +1,186 @@
+//! The Mistral API client.
+//!
+//! Mistral's chat completions API follows OpenAI's, but rejects unknown
+//! request fields and lists embedding and moderation models alongside the
+//! chat ones.
+
+use crate::{
+    api::{clients::openai_compatible::OpenAICompatible, http},
+    config::{MistralConfig, RetryConfig},
+    models::{CompletionRequest, CompletionResponse, EmbeddingRequest, ModelCapabilities},
+};
+
+use super::{history, retry, CompletionStream, ErgonClient, Model};
+
+#[derive(Debug, Clone)]
+pub struct MistralClient {
+    config: MistralConfig,
+}
+
+impl OpenAICompatible for MistralClient {
+    async fn request(&self, request: CompletionRequest) -> anyhow::Result<CompletionResponse> {
+        if self.config.api_key.is_empty() {
+            return Err(anyhow::anyhow!("API key is not set".to_string()));
+        }
+        self.request_completion(request).await
+    }
+
+    fn endpoint(&self) -> &str {
+        &self.config.endpoint
+    }
+
+    fn api_key(&self) -> Option<&str> {
+        Some(&self.config.api_key)
+    }
+
+    fn retry(&self) -> &RetryConfig {
+        &self.config.retry
+    }
+
+    fn http_client(&self) -> reqwest::Client {
+        http::client(&self.config.timeouts, self.config.proxy.as_ref())
+    }
+
+    fn supports_stream_options(&self) -> bool {
+        false
+    }
+
+    fn seed_parameter(&self) -> &'static str {
+        "random_seed"
+    }
+}
+
+impl ErgonClient for MistralClient {
+    async fn complete_message(
+        &self,
+        mut request: CompletionRequest,
+    ) -> anyhow::Result<CompletionResponse> {
+        tracing::info!(
+            "MistralClient: Completing message with {} messages using model {}",
+            request.messages.len(),
+            request.model
+        );
+        if request.messages.is_empty() {
+            return Err(anyhow::anyhow!("No messages provided".to_string()));
+        }
+        history::rename_tool_ids(&mut request.messages, history::mistral_tool_id);
+        self.request(request).await
+    }
+
+    async fn stream_message(
+        &self,
+        mut request: CompletionRequest,
+    ) -> anyhow::Result<CompletionStream> {
+        if request.messages.is_empty() {
+            return Err(anyhow::anyhow!("No messages provided".to_string()));
+        }
+        if self.config.api_key.is_empty() {
+            return Err(anyhow::anyhow!("API key is not set".to_string()));
+        }
+        history::rename_tool_ids(&mut request.messages, history::mistral_tool_id);
+        self.request_completion_stream(request).await
+    }
+
+    async fn embed(&self, request: EmbeddingRequest) -> anyhow::Result<Vec<Vec<f32>>> {
+        if self.config.api_key.is_empty() {
+            return Err(anyhow::anyhow!("API key is not set".to_string()));
+        }
+        self.request_embeddings(request).await
+    }
+
+    async fn list_models(&self) -> anyhow::Result<Vec<Model>> {
+        tracing::info!("MistralClient: Fetching available models");
+        if self.config.api_key.is_empty() {
+            return Err(anyhow::anyhow!("API key is not set".to_string()));
+        }
+
+        let url = format!("{}/models", self.config.endpoint.trim_end_matches('/'));
+        let request = http::client(&self.config.timeouts, self.config.proxy.as_ref())
+            .get(url)
+            .header("Authorization", format!("Bearer {}", self.config.api_key));
+        let response = retry::send(request, &self.config.retry).await?;
+        if !response.status().is_success() {
+            tracing::error!(
+                "MistralClient: List models failed with status: {}",
+                response.status()
+            );
+            return Err(anyhow::anyhow!("Error: {}", response.status()));
+        }
+        let json: serde_json::Value = response.json().await?;
+        Ok(chat_models(&json))
+    }
+}
+
+/// The chat-capable models in a `/models` response, with the capabilities
+/// the API reports. Entries without capability flags are kept.
+fn chat_models(json: &serde_json::Value) -> Vec<Model> {
+    let mut models: Vec<Model> = json["data"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|model| {
+            model["capabilities"]["completion_chat"]
+                .as_bool()
+                .unwrap_or(true)
+        })
+        .filter_map(|model| {
+            let id = model["id"].as_str()?;
+            let flags = &model["capabilities"];
+            Some(Model {
+                name: id.to_string(),
+                id: id.to_string(),
+                capabilities: flags.is_object().then(|| ModelCapabilities {
+                    vision: flags["vision"].as_bool().unwrap_or(false),
+                    tools: flags["function_calling"].as_bool().unwrap_or(false),
+                    context_length: model["max_context_length"]
+                        .as_u64()
+                        .and_then(|n| u32::try_from(n).ok()),
+                    reasoning: false,
+                }),
+            })
+        })
+        .collect();
+    // The API lists every alias of a model separately, in no fixed order.
+    models.sort_by(|a, b| a.name.cmp(&b.name));
+    models.dedup();
+    models
+}
+
+impl MistralClient {
+    pub fn new(config: MistralConfig) -> Self {
+        Self { config }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chat_models_skips_non_chat_models() {
+        let json = serde_json::json!({
+            "object": "list",
+            "data": [
+                {"id": "mistral-small-latest", "max_context_length": 32768,
+                 "capabilities": {"completion_chat": true, "function_calling": true}},
+                {"id": "mistral-embed", "capabilities": {"completion_chat": false}},
+                {"id": "codestral-latest"},
+                {"id": "mistral-small-latest", "max_context_length": 32768,
+                 "capabilities": {"completion_chat": true, "function_calling": true}}
+            ]
+        });
+        let models = chat_models(&json);
+        let names: Vec<&str> = models.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, vec!["codestral-latest", "mistral-small-latest"]);
+        assert_eq!(models[0].capabilities, None);
+        assert_eq!(
+            models[1].capabilities,
+            Some(ModelCapabilities {
+                vision: false,
+                tools: true,
+                context_length: Some(32768),
+                reasoning: false,
+            })
+        );
+    }
+}
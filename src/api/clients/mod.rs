@@ -1,11 +1,70 @@
 use std::sync::{Arc, RwLock};
+
+use iced::futures::{future::BoxFuture, stream::BoxStream, FutureExt, StreamExt};
+use tokio::sync::watch;
+use tracing::Instrument;
+mod audit;
+mod capabilities;
 mod openai_compatible;
+mod retry;
+mod sigv4;
+mod sse;
+
+use crate::config::{Config, EmbeddingConfig};
+pub use crate::models::{
+    Clients, CompletionDelta, CompletionRequest, CompletionResponse, EmbeddingRequest,
+    ModelCapabilities, ModelInfo,
+};
+
+/// A streamed completion, yielding deltas until the model finishes.
+pub type CompletionStream = BoxStream<'static, anyhow::Result<CompletionDelta>>;
+
+/// Why a completion failed, with the HTTP status the provider answered
+/// with when the request got that far.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompletionError {
+    pub text: String,
+    pub status: Option<reqwest::StatusCode>,
+}
+
+impl CompletionError {
+    /// Whether the provider answered with a status another attempt may get
+    /// past: a rate limit or a server error.
+    pub fn has_transient_status(&self) -> bool {
+        self.status.is_some_and(retry::is_transient)
+    }
+}
+
+impl From<anyhow::Error> for CompletionError {
+    fn from(err: anyhow::Error) -> Self {
+        Self {
+            status: err.downcast_ref::<retry::StatusError>().map(|e| e.status),
+            text: err.to_string(),
+        }
+    }
+}
+
+impl From<String> for CompletionError {
+    fn from(text: String) -> Self {
+        Self { text, status: None }
+    }
+}
 
-pub use crate::models::{Clients, CompletionRequest, CompletionResponse, ModelInfo};
+impl std::fmt::Display for CompletionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.text)
+    }
+}
 
 pub mod anthropic;
+pub mod azure;
+pub mod bedrock;
+pub mod cohere;
+pub mod custom;
+pub mod history;
+pub mod local;
+pub mod mistral;
 pub mod openai;
-pub mod vllm;
 
 pub trait ErgonClient {
     async fn complete_message(
@@ -14,103 +73,297 @@ pub trait ErgonClient {
     ) -> anyhow::Result<CompletionResponse>;
 
     async fn list_models(&self) -> anyhow::Result<Vec<Model>>;
+
+    /// Stream a completion as it is generated. Clients without native
+    /// streaming support fall back to a single [`Self::complete_message`]
+    /// call replayed as deltas.
+    async fn stream_message(&self, request: CompletionRequest) -> anyhow::Result<CompletionStream> {
+        let response = self.complete_message(request).await?;
+        Ok(iced::futures::stream::iter(response.into_deltas().into_iter().map(Ok)).boxed())
+    }
+
+    /// Embed the texts of `request`, one vector per input in input order.
+    /// Providers without an embeddings API return an error.
+    async fn embed(&self, request: EmbeddingRequest) -> anyhow::Result<Vec<Vec<f32>>> {
+        Err(anyhow::anyhow!(
+            "Embeddings are not supported for {}",
+            request.model
+        ))
+    }
 }
 
-impl Clients {
-    pub async fn complete_message(
+/// Object-safe form of [`ErgonClient`], so the configured providers can be
+/// kept side by side in the [`ModelManager`]'s registry and chat tasks can
+/// be handed any implementation, including mocks in tests.
+pub trait Provider: std::fmt::Debug + Send + Sync {
+    fn complete_message(
         &self,
         request: CompletionRequest,
-    ) -> anyhow::Result<CompletionResponse> {
-        match self {
-            Clients::OpenAI => {
-                openai::OpenAIClient::default()
-                    .complete_message(request)
-                    .await
+    ) -> BoxFuture<'static, anyhow::Result<CompletionResponse>>;
+
+    /// Like [`ErgonClient::stream_message`], replays
+    /// [`Self::complete_message`] unless overridden.
+    fn stream_message(
+        &self,
+        request: CompletionRequest,
+    ) -> BoxFuture<'static, anyhow::Result<CompletionStream>> {
+        let response = self.complete_message(request);
+        async move {
+            let response = response.await?;
+            Ok(iced::futures::stream::iter(response.into_deltas().into_iter().map(Ok)).boxed())
+        }
+        .boxed()
+    }
+
+    fn list_models(&self) -> BoxFuture<'static, anyhow::Result<Vec<Model>>>;
+
+    /// Like [`ErgonClient::embed`], unsupported unless overridden.
+    fn embed(
+        &self,
+        request: EmbeddingRequest,
+    ) -> BoxFuture<'static, anyhow::Result<Vec<Vec<f32>>>> {
+        async move {
+            Err(anyhow::anyhow!(
+                "Embeddings are not supported for {}",
+                request.model
+            ))
+        }
+        .boxed()
+    }
+}
+
+/// Implement [`Provider`] for clients by delegating to [`ErgonClient`]. A
+/// blanket impl can't promise the futures are `Send`, so each client is
+/// listed here.
+macro_rules! impl_provider {
+    ($($client:ty),* $(,)?) => {$(
+        impl Provider for $client {
+            fn complete_message(
+                &self,
+                request: CompletionRequest,
+            ) -> BoxFuture<'static, anyhow::Result<CompletionResponse>> {
+                let client = self.clone();
+                async move { ErgonClient::complete_message(&client, request).await }.boxed()
             }
-            Clients::Anthropic => {
-                anthropic::AnthropicClient::default()
-                    .complete_message(request)
-                    .await
+
+            fn stream_message(
+                &self,
+                request: CompletionRequest,
+            ) -> BoxFuture<'static, anyhow::Result<CompletionStream>> {
+                let client = self.clone();
+                async move { ErgonClient::stream_message(&client, request).await }.boxed()
+            }
+
+            fn list_models(&self) -> BoxFuture<'static, anyhow::Result<Vec<Model>>> {
+                let client = self.clone();
+                async move { ErgonClient::list_models(&client).await }.boxed()
+            }
+
+            fn embed(
+                &self,
+                request: EmbeddingRequest,
+            ) -> BoxFuture<'static, anyhow::Result<Vec<Vec<f32>>>> {
+                let client = self.clone();
+                async move { ErgonClient::embed(&client, request).await }.boxed()
             }
-            Clients::Vllm => vllm::VllmClient::default().complete_message(request).await,
         }
+    )*};
+}
+
+impl_provider!(
+    openai::OpenAIClient,
+    anthropic::AnthropicClient,
+    local::LocalClient,
+    azure::AzureClient,
+    mistral::MistralClient,
+    cohere::CohereClient,
+    bedrock::BedrockClient,
+    custom::CustomClient,
+);
+
+type Registry = Vec<(Clients, Arc<dyn Provider>)>;
+
+/// Build a provider for everything in `config`, keyed by the [`Clients`]
+/// value its models carry. New providers are registered here.
+fn providers(config: &Config) -> Registry {
+    let mut config = config.clone();
+    config.inherit_proxy();
+    let config = &config;
+    let mut registry: Registry = vec![
+        (
+            Clients::OpenAI,
+            Arc::new(openai::OpenAIClient::new(config.openai.clone())),
+        ),
+        (
+            Clients::Anthropic,
+            Arc::new(anthropic::AnthropicClient::new(config.anthropic.clone())),
+        ),
+    ];
+    for server in &config.local_servers {
+        registry.push((
+            Clients::Local(server.name.clone()),
+            Arc::new(local::LocalClient::new(server.clone())),
+        ));
+    }
+    registry.push((
+        Clients::Azure,
+        Arc::new(azure::AzureClient::new(config.azure.clone())),
+    ));
+    registry.push((
+        Clients::Mistral,
+        Arc::new(mistral::MistralClient::new(config.mistral.clone())),
+    ));
+    registry.push((
+        Clients::Cohere,
+        Arc::new(cohere::CohereClient::new(config.cohere.clone())),
+    ));
+    registry.push((
+        Clients::Bedrock,
+        Arc::new(bedrock::BedrockClient::new(config.bedrock.clone())),
+    ));
+    for provider in &config.custom_providers {
+        registry.push((
+            Clients::Custom(provider.name.clone()),
+            Arc::new(custom::CustomClient::new(provider.clone())),
+        ));
     }
+    if config.audit_log {
+        registry = registry
+            .into_iter()
+            .map(|(client, provider)| {
+                let audited = audit::Audited::new(client.to_string(), provider, audit::path());
+                (client, Arc::new(audited) as Arc<dyn Provider>)
+            })
+            .collect();
+    }
+    registry
+}
+
+impl Clients {
+    /// The registered provider serving this client's models.
+    pub fn provider(&self) -> anyhow::Result<Arc<dyn Provider>> {
+        get_model_manager()
+            .provider(self)
+            .ok_or_else(|| anyhow::anyhow!("{self} is not configured"))
+    }
+}
+
+/// Embed `texts` with the embedding model chosen in `config`, one vector
+/// per text in order.
+pub async fn embed(config: Config, texts: Vec<String>) -> anyhow::Result<Vec<Vec<f32>>> {
+    let provider = providers(&config)
+        .into_iter()
+        .find(|(client, _)| client.to_string() == config.embeddings.provider)
+        .map(|(_, provider)| provider)
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "Embedding provider {} is not configured",
+                config.embeddings.provider
+            )
+        })?;
+    embed_in_batches(provider.as_ref(), &config.embeddings, texts).await
+}
+
+/// Embed `texts` with `provider`, at most `batch_size` of them per request.
+async fn embed_in_batches(
+    provider: &dyn Provider,
+    config: &EmbeddingConfig,
+    texts: Vec<String>,
+) -> anyhow::Result<Vec<Vec<f32>>> {
+    let mut embeddings = Vec::with_capacity(texts.len());
+    for batch in texts.chunks(config.batch_size.max(1)) {
+        let request = EmbeddingRequest {
+            model: config.model.clone(),
+            input: batch.to_vec(),
+        };
+        embeddings.extend(provider.embed(request).await?);
+    }
+    Ok(embeddings)
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Model {
     pub name: String,
     pub id: String,
+    /// What the provider reports about the model. `None` falls back to
+    /// [`capabilities::for_model`].
+    pub capabilities: Option<ModelCapabilities>,
 }
 
 #[derive(Debug)]
 pub struct ModelManager {
     models: Arc<RwLock<Vec<ModelInfo>>>,
+    /// Publishes the model list every time it is refreshed so views can keep
+    /// their pickers in sync without polling.
+    updates: watch::Sender<Vec<ModelInfo>>,
+    /// The providers built from the config at the last refresh.
+    providers: RwLock<Registry>,
 }
 
 impl ModelManager {
     fn new() -> Self {
         Self {
             models: Arc::new(RwLock::new(Vec::new())),
+            updates: watch::Sender::new(Vec::new()),
+            providers: RwLock::new(Vec::new()),
         }
     }
 
-    pub async fn fetch_models(&self) -> Result<(), String> {
-        let mut all_models = Vec::new();
+    /// Subscribe to model list changes. The receiver starts out holding the
+    /// current list; subsequent refreshes mark it as changed only when the
+    /// list actually differs.
+    pub fn subscribe(&self) -> watch::Receiver<Vec<ModelInfo>> {
+        self.updates.subscribe()
+    }
 
-        let openai_client = openai::OpenAIClient::default();
-        match openai_client.list_models().await {
-            Ok(models) => {
-                for model in models {
-                    all_models.push(ModelInfo {
-                        name: model.name,
-                        id: model.id,
-                        client: crate::models::Clients::OpenAI,
-                    });
-                }
-            }
-            Err(e) => {
-                log::warn!("Failed to fetch OpenAI models: {}", e);
-            }
+    pub async fn fetch_models(&self) -> Result<(), String> {
+        let providers = providers(&Config::default());
+        {
+            let mut registry = self
+                .providers
+                .write()
+                .map_err(|_| "Failed to acquire write lock")?;
+            *registry = providers.clone();
         }
 
-        let anthropic_client = anthropic::AnthropicClient::default();
-        match anthropic_client.list_models().await {
-            Ok(models) => {
-                for model in models {
-                    all_models.push(ModelInfo {
-                        name: model.name,
-                        id: model.id,
-                        client: crate::models::Clients::Anthropic,
-                    });
+        let mut all_models = Vec::new();
+        for (client, provider) in providers {
+            let span = tracing::info_span!("list_models", provider = %client);
+            match provider.list_models().instrument(span).await {
+                Ok(models) => {
+                    for model in models {
+                        let capabilities = model
+                            .capabilities
+                            .unwrap_or_else(|| capabilities::for_model(&model.id));
+                        all_models.push(ModelInfo {
+                            name: model.name,
+                            id: model.id,
+                            client: client.clone(),
+                            capabilities,
+                        });
+                    }
                 }
-            }
-            Err(e) => {
-                log::warn!("Failed to fetch Anthropic models: {}", e);
-            }
-        }
-
-        let vllm_client = vllm::VllmClient::default();
-        match vllm_client.list_models().await {
-            Ok(models) => {
-                for model in models {
-                    all_models.push(ModelInfo {
-                        name: model.name,
-                        id: model.id,
-                        client: crate::models::Clients::Vllm, // Assuming vLLM uses OpenAI client type
-                    });
+                Err(e) => {
+                    tracing::warn!("Failed to fetch {} models: {}", client, e);
                 }
             }
-            Err(e) => {
-                log::warn!("Failed to fetch vLLM models: {}", e);
-            }
         }
 
-        let mut models = self
-            .models
-            .write()
-            .map_err(|_| "Failed to acquire write lock")?;
-        *models = all_models;
+        {
+            let mut models = self
+                .models
+                .write()
+                .map_err(|_| "Failed to acquire write lock")?;
+            *models = all_models.clone();
+        }
+        self.updates.send_if_modified(|current| {
+            if *current == all_models {
+                false
+            } else {
+                *current = all_models;
+                true
+            }
+        });
 
         Ok(())
     }
@@ -123,6 +376,15 @@ impl ModelManager {
         Ok(models.clone())
     }
 
+    /// The provider registered for `client` at the last refresh.
+    pub fn provider(&self, client: &Clients) -> Option<Arc<dyn Provider>> {
+        let providers = self.providers.read().ok()?;
+        providers
+            .iter()
+            .find(|(registered, _)| registered == client)
+            .map(|(_, provider)| provider.clone())
+    }
+
     pub fn find_model(&self, name: &str) -> Result<Option<ModelInfo>, String> {
         let models = self
             .models
@@ -137,3 +399,101 @@ static MODEL_MANAGER: std::sync::OnceLock<ModelManager> = std::sync::OnceLock::n
 pub fn get_model_manager() -> &'static ModelManager {
     MODEL_MANAGER.get_or_init(ModelManager::new)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_providers_follow_config() {
+        let config: Config = serde_json::from_str(
+            r#"{"theme":"Dark",
+                "local_servers":[{"name":"LM Studio"},{"name":"llama.cpp"}],
+                "custom_providers":[{"name":"OpenRouter","base_url":"https://openrouter.ai/api/v1"}]}"#,
+        )
+        .unwrap();
+        let clients: Vec<Clients> = providers(&config)
+            .into_iter()
+            .map(|(client, _)| client)
+            .collect();
+        assert_eq!(clients.len(), 9);
+        assert!(clients.contains(&Clients::Local("llama.cpp".to_string())));
+        assert_eq!(
+            clients.last(),
+            Some(&Clients::Custom("OpenRouter".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_completion_error_keeps_the_status() {
+        let rate_limited: CompletionError = anyhow::Error::from(retry::StatusError {
+            status: reqwest::StatusCode::TOO_MANY_REQUESTS,
+            message: "slow down".to_string(),
+        })
+        .into();
+        assert_eq!(rate_limited.text, "Error: 429 Too Many Requests slow down");
+        assert!(rate_limited.has_transient_status());
+
+        // Numbers in the message are not taken for a status.
+        let bad_request: CompletionError = anyhow::Error::from(retry::StatusError {
+            status: reqwest::StatusCode::BAD_REQUEST,
+            message: "context window of 512 tokens exceeded".to_string(),
+        })
+        .into();
+        assert!(!bad_request.has_transient_status());
+
+        let other: CompletionError = anyhow::anyhow!("Error: 503 from a proxy").into();
+        assert_eq!(other.status, None);
+    }
+
+    /// Embeds each text as its length, remembering the batches it was sent.
+    #[derive(Debug, Default)]
+    struct LengthEmbedder {
+        batches: std::sync::Mutex<Vec<usize>>,
+    }
+
+    impl Provider for LengthEmbedder {
+        fn complete_message(
+            &self,
+            _request: CompletionRequest,
+        ) -> BoxFuture<'static, anyhow::Result<CompletionResponse>> {
+            unimplemented!()
+        }
+
+        fn list_models(&self) -> BoxFuture<'static, anyhow::Result<Vec<Model>>> {
+            unimplemented!()
+        }
+
+        fn embed(
+            &self,
+            request: EmbeddingRequest,
+        ) -> BoxFuture<'static, anyhow::Result<Vec<Vec<f32>>>> {
+            self.batches.lock().unwrap().push(request.input.len());
+            let embeddings = request
+                .input
+                .iter()
+                .map(|text| vec![text.len() as f32])
+                .collect();
+            async move { Ok(embeddings) }.boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_embeddings_are_requested_in_batches() {
+        let provider = LengthEmbedder::default();
+        let config = EmbeddingConfig {
+            batch_size: 2,
+            ..EmbeddingConfig::default()
+        };
+        let texts = ["a", "bb", "ccc", "dddd", "eeeee"]
+            .map(String::from)
+            .to_vec();
+
+        let embeddings = embed_in_batches(&provider, &config, texts).await.unwrap();
+        assert_eq!(
+            embeddings,
+            vec![vec![1.0], vec![2.0], vec![3.0], vec![4.0], vec![5.0]]
+        );
+        assert_eq!(*provider.batches.lock().unwrap(), vec![2, 2, 1]);
+    }
+}
@@ -1,12 +1,12 @@
 //! The OpenAI API client.
 
 use crate::{
-    api::clients::openai_compatible::OpenAICompatible,
-    config::{Config, OpenAIConfig},
-    models::{CompletionRequest, CompletionResponse},
+    api::{clients::openai_compatible::OpenAICompatible, http},
+    config::{OpenAIConfig, RetryConfig},
+    models::{CompletionRequest, CompletionResponse, EmbeddingRequest},
 };
 
-use super::{ErgonClient, Model};
+use super::{retry, CompletionStream, ErgonClient, Model};
 
 #[derive(Debug, Clone)]
 pub struct OpenAIClient {
@@ -28,6 +28,25 @@ impl OpenAICompatible for OpenAIClient {
     fn api_key(&self) -> Option<&str> {
         Some(&self.config.api_key)
     }
+
+    fn retry(&self) -> &RetryConfig {
+        &self.config.retry
+    }
+
+    fn http_client(&self) -> reqwest::Client {
+        http::client(&self.config.timeouts, self.config.proxy.as_ref())
+    }
+
+    fn extra_headers(&self) -> Vec<(&'static str, String)> {
+        [
+            ("OpenAI-Organization", &self.config.organization),
+            ("OpenAI-Project", &self.config.project),
+        ]
+        .into_iter()
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(name, value)| (name, value.clone()))
+        .collect()
+    }
 }
 
 impl ErgonClient for OpenAIClient {
@@ -35,7 +54,7 @@ impl ErgonClient for OpenAIClient {
         &self,
         request: CompletionRequest,
     ) -> anyhow::Result<CompletionResponse> {
-        log::info!(
+        tracing::info!(
             "OpenAIClient: Completing message with {} messages using model {}",
             request.messages.len(),
             request.model
@@ -47,21 +66,46 @@ impl ErgonClient for OpenAIClient {
         }
     }
 
+    async fn stream_message(&self, request: CompletionRequest) -> anyhow::Result<CompletionStream> {
+        tracing::info!(
+            "OpenAIClient: Streaming message with {} messages using model {}",
+            request.messages.len(),
+            request.model
+        );
+        if request.messages.is_empty() {
+            Err(anyhow::anyhow!("No messages provided".to_string()))
+        } else {
+            if self.config.api_key.is_empty() {
+                return Err(anyhow::anyhow!("API key is not set".to_string()));
+            }
+            self.request_completion_stream(request).await
+        }
+    }
+
+    async fn embed(&self, request: EmbeddingRequest) -> anyhow::Result<Vec<Vec<f32>>> {
+        if self.config.api_key.is_empty() {
+            return Err(anyhow::anyhow!("API key is not set".to_string()));
+        }
+        self.request_embeddings(request).await
+    }
+
     async fn list_models(&self) -> anyhow::Result<Vec<Model>> {
-        log::info!("OpenAIClient: Fetching available models");
+        tracing::info!("OpenAIClient: Fetching available models");
         if self.config.api_key.is_empty() {
             return Err(anyhow::anyhow!("API key is not set".to_string()));
         }
 
-        let client = reqwest::Client::new();
+        let client = http::client(&self.config.timeouts, self.config.proxy.as_ref());
         let url = format!("{}/models", self.config.endpoint.trim_end_matches('/'));
 
-        let response = client
+        let mut request = client
             .get(url)
             .header("Authorization", format!("Bearer {}", self.config.api_key))
-            .header("Content-Type", "application/json")
-            .send()
-            .await;
+            .header("Content-Type", "application/json");
+        for (name, value) in self.extra_headers() {
+            request = request.header(name, value);
+        }
+        let response = retry::send(request, &self.config.retry).await;
 
         match response {
             Ok(resp) => {
@@ -76,11 +120,12 @@ impl ErgonClient for OpenAIClient {
                         .map(|s| Model {
                             name: s.to_string(),
                             id: s.to_string(),
+                            capabilities: None,
                         })
                         .collect();
                     Ok(models)
                 } else {
-                    log::error!(
+                    tracing::error!(
                         "OpenAIClient: List models failed with status: {}",
                         resp.status()
                     );
@@ -88,17 +133,39 @@ impl ErgonClient for OpenAIClient {
                 }
             }
             Err(e) => {
-                log::error!("OpenAIClient: List models request failed: {}", e);
+                tracing::error!("OpenAIClient: List models request failed: {}", e);
                 Err(anyhow::anyhow!("Request failed: {}", e))
             }
         }
     }
 }
 
-impl Default for OpenAIClient {
-    fn default() -> Self {
-        OpenAIClient {
-            config: Config::default().openai,
-        }
+impl OpenAIClient {
+    pub fn new(config: OpenAIConfig) -> Self {
+        Self { config }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_organization_and_project_headers() {
+        let client = OpenAIClient::new(OpenAIConfig::default());
+        assert!(client.extra_headers().is_empty());
+
+        let client = OpenAIClient::new(OpenAIConfig {
+            organization: "org-123".to_string(),
+            project: "proj_456".to_string(),
+            ..OpenAIConfig::default()
+        });
+        assert_eq!(
+            client.extra_headers(),
+            vec![
+                ("OpenAI-Organization", "org-123".to_string()),
+                ("OpenAI-Project", "proj_456".to_string()),
+            ]
+        );
     }
 }
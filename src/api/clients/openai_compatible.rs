@@ -1,6 +1,19 @@
+use std::collections::VecDeque;
+
+use iced::futures::{stream, StreamExt};
+use serde::Deserialize;
 use serde_json::json;
 
-use crate::models::{CompletionRequest, CompletionResponse, Content, Message};
+use crate::{
+    config::RetryConfig,
+    images,
+    models::{
+        CompletionDelta, CompletionRequest, CompletionResponse, Content, EmbeddingRequest, Message,
+        TokenUsage,
+    },
+};
+
+use super::{capabilities, retry, sse::SseDecoder, CompletionStream};
 
 pub trait OpenAICompatible {
     async fn request(&self, request: CompletionRequest) -> anyhow::Result<CompletionResponse>;
@@ -9,41 +22,356 @@ pub trait OpenAICompatible {
 
     fn api_key(&self) -> Option<&str>;
 
+    /// How failed requests are retried.
+    fn retry(&self) -> &RetryConfig;
+
+    /// The client requests are sent with, set up with the provider's
+    /// timeouts and proxy.
+    fn http_client(&self) -> reqwest::Client;
+
+    /// Full URL of the chat completions endpoint.
+    fn completions_url(&self) -> String {
+        format!("{}/chat/completions", self.endpoint().trim_end_matches('/'))
+    }
+
+    /// Full URL of the embeddings endpoint serving `model`.
+    fn embeddings_url(&self, _model: &str) -> String {
+        format!("{}/embeddings", self.endpoint().trim_end_matches('/'))
+    }
+
+    /// Header carrying the credentials, if any. Defaults to a bearer token
+    /// built from [`Self::api_key`].
+    fn auth_header(&self) -> Option<(String, String)> {
+        self.api_key()
+            .map(|api_key| ("Authorization".to_string(), format!("Bearer {}", api_key)))
+    }
+
+    /// Headers sent with every request besides the credentials.
+    fn extra_headers(&self) -> Vec<(&'static str, String)> {
+        Vec::new()
+    }
+
+    /// Whether the server accepts `stream_options` to report token usage at
+    /// the end of a stream. Servers that reject unknown fields opt out.
+    fn supports_stream_options(&self) -> bool {
+        true
+    }
+
+    /// The name the server expects the sampling seed under.
+    fn seed_parameter(&self) -> &'static str {
+        "seed"
+    }
+
     async fn request_completion(
         &self,
         request: CompletionRequest,
     ) -> anyhow::Result<CompletionResponse> {
-        let client = reqwest::Client::new();
-        let url = format!("{}/chat/completions", self.endpoint().trim_end_matches('/'));
-
-        let json_request = serde_json::json!({
-            "model": request.model,
-            "messages": request.messages.iter().map(OpenAIMessageAdapter::convert_message).collect::<Vec<_>>(),
-            "temperature": request.temperature,
-            "tools": request.tools,
-        });
-
-        log::info!("OpenAIClient: Sending request to {}", url);
-        log::info!("OpenAIClient: Request payload: {}", json_request);
+        let client = self.http_client();
+        let url = self.completions_url();
+
+        let json_request = completion_payload(&request, self.seed_parameter());
+
+        tracing::info!("OpenAIClient: Sending request to {}", url);
+        tracing::info!("OpenAIClient: Request payload: {}", json_request);
         let mut req = client.post(url);
-        if let Some(api_key) = self.api_key() {
-            req = req.header("Authorization", format!("Bearer {}", api_key));
+        if let Some((name, value)) = self.auth_header() {
+            req = req.header(name, value);
+        }
+        for (name, value) in self.extra_headers() {
+            req = req.header(name, value);
         }
         req = req.header("Content-Type", "application/json");
         req = req.json(&json_request);
-        let response = req.send().await?;
+        let response = retry::send(req, self.retry()).await?;
 
         if !response.status().is_success() {
+            let status = response.status();
             let error_text = response.text().await?;
-            log::error!("OpenAIClient: Request failed with error: {}", error_text);
-            return Err(anyhow::anyhow!("Error: {}", error_text));
+            tracing::error!("OpenAIClient: Request failed with error: {}", error_text);
+            return Err(retry::StatusError {
+                status,
+                message: error_text,
+            }
+            .into());
         }
         let text_data = response.text().await?;
         let completion_response: CompletionResponse = serde_json::from_str(&text_data)
-            .map_err(anyhow::Error::from)
-            .unwrap();
+            .map_err(|e| anyhow::anyhow!("Unexpected response ({}): {}", e, text_data))?;
         Ok(completion_response)
     }
+
+    /// Send `request` with `stream: true` and decode the server-sent events
+    /// into a stream of deltas.
+    async fn request_completion_stream(
+        &self,
+        request: CompletionRequest,
+    ) -> anyhow::Result<CompletionStream> {
+        let client = self.http_client();
+        let url = self.completions_url();
+
+        let mut json_request = completion_payload(&request, self.seed_parameter());
+        json_request["stream"] = serde_json::Value::Bool(true);
+        // Ask for a final chunk carrying the token counts.
+        if self.supports_stream_options() {
+            json_request["stream_options"] = json!({ "include_usage": true });
+        }
+
+        tracing::info!("OpenAIClient: Streaming request to {}", url);
+        let mut req = client.post(url);
+        if let Some((name, value)) = self.auth_header() {
+            req = req.header(name, value);
+        }
+        for (name, value) in self.extra_headers() {
+            req = req.header(name, value);
+        }
+        req = req.header("Content-Type", "application/json");
+        req = req.json(&json_request);
+        let response = retry::send(req, self.retry()).await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().await?;
+            tracing::error!(
+                "OpenAIClient: Stream request failed with error: {}",
+                error_text
+            );
+            return Err(retry::StatusError {
+                status,
+                message: error_text,
+            }
+            .into());
+        }
+        Ok(chat_completion_deltas(response))
+    }
+
+    /// Embed every input of `request`, returning the vectors in input order.
+    async fn request_embeddings(&self, request: EmbeddingRequest) -> anyhow::Result<Vec<Vec<f32>>> {
+        let client = self.http_client();
+        let url = self.embeddings_url(&request.model);
+
+        tracing::info!(
+            "OpenAIClient: Embedding {} texts with {} at {}",
+            request.input.len(),
+            request.model,
+            url
+        );
+        let mut req = client.post(url);
+        if let Some((name, value)) = self.auth_header() {
+            req = req.header(name, value);
+        }
+        for (name, value) in self.extra_headers() {
+            req = req.header(name, value);
+        }
+        req = req.header("Content-Type", "application/json");
+        req = req.json(&request);
+        let response = retry::send(req, self.retry()).await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().await?;
+            tracing::error!("OpenAIClient: Embedding failed with error: {}", error_text);
+            return Err(retry::StatusError {
+                status,
+                message: error_text,
+            }
+            .into());
+        }
+        parse_embeddings(&response.text().await?, request.input.len())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingData {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+/// The vectors of an `/embeddings` response to `count` inputs, ordered by
+/// the input they belong to.
+fn parse_embeddings(text: &str, count: usize) -> anyhow::Result<Vec<Vec<f32>>> {
+    let mut response: EmbeddingResponse = serde_json::from_str(text)
+        .map_err(|e| anyhow::anyhow!("Unexpected response ({}): {}", e, text))?;
+    if response.data.len() != count {
+        return Err(anyhow::anyhow!(
+            "Expected {} embeddings, got {}",
+            count,
+            response.data.len()
+        ));
+    }
+    response.data.sort_by_key(|data| data.index);
+    Ok(response
+        .data
+        .into_iter()
+        .map(|data| data.embedding)
+        .collect())
+}
+
+/// Build the `/chat/completions` request body for `request`, with its seed
+/// named `seed_parameter`. Reasoning models reject the sampling parameters
+/// and `max_tokens`, so they are sent the effort and
+/// `max_completion_tokens` instead.
+fn completion_payload(request: &CompletionRequest, seed_parameter: &str) -> serde_json::Value {
+    let mut payload = serde_json::json!({
+        "model": request.model,
+        "messages": request.messages.iter().map(OpenAIMessageAdapter::convert_message).collect::<Vec<_>>(),
+        "tools": request.tools,
+    });
+    if capabilities::for_model(&request.model).reasoning {
+        payload["max_completion_tokens"] = json!(request.max_tokens);
+        if let Some(effort) = request.reasoning_effort {
+            payload["reasoning_effort"] = json!(effort.as_str());
+        }
+    } else {
+        payload["temperature"] = json!(request.temperature);
+        payload["top_p"] = json!(request.top_p);
+        payload["max_tokens"] = json!(request.max_tokens);
+        if !request.stop.is_empty() {
+            payload["stop"] = json!(request.stop);
+        }
+        if let Some(penalty) = request.presence_penalty {
+            payload["presence_penalty"] = json!(penalty);
+        }
+        if let Some(penalty) = request.frequency_penalty {
+            payload["frequency_penalty"] = json!(penalty);
+        }
+    }
+    if let Some(seed) = request.seed {
+        payload[seed_parameter] = json!(seed);
+    }
+    payload
+}
+
+/// Turn a successful `stream: true` `/chat/completions` response into a
+/// stream of deltas. The stream ends after the `[DONE]` sentinel, when the
+/// connection closes, or after the first error.
+fn chat_completion_deltas(response: reqwest::Response) -> CompletionStream {
+    struct StreamState<S> {
+        bytes: S,
+        decoder: SseDecoder,
+        pending: VecDeque<anyhow::Result<CompletionDelta>>,
+        done: bool,
+    }
+
+    let state = StreamState {
+        bytes: response.bytes_stream(),
+        decoder: SseDecoder::new(),
+        pending: VecDeque::new(),
+        done: false,
+    };
+    stream::unfold(state, |mut state| async move {
+        loop {
+            if let Some(item) = state.pending.pop_front() {
+                return Some((item, state));
+            }
+            if state.done {
+                return None;
+            }
+            let events = match state.bytes.next().await {
+                Some(Ok(chunk)) => state.decoder.push(&chunk),
+                Some(Err(e)) => {
+                    state.pending.push_back(Err(e.into()));
+                    state.done = true;
+                    continue;
+                }
+                None => {
+                    state.done = true;
+                    state.decoder.finish().into_iter().collect()
+                }
+            };
+            for event in events {
+                if event.data == "[DONE]" {
+                    state.done = true;
+                    break;
+                }
+                match chunk_deltas(&event.data) {
+                    Ok(deltas) => state.pending.extend(deltas.into_iter().map(Ok)),
+                    Err(e) => {
+                        state.pending.push_back(Err(e));
+                        state.done = true;
+                        break;
+                    }
+                }
+            }
+        }
+    })
+    .boxed()
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatCompletionChunk {
+    #[serde(default)]
+    choices: Vec<ChunkChoice>,
+    usage: Option<TokenUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChunkChoice {
+    #[serde(default)]
+    delta: ChunkDelta,
+    finish_reason: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ChunkDelta {
+    content: Option<String>,
+    reasoning_content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<ChunkToolCall>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChunkToolCall {
+    index: usize,
+    id: Option<String>,
+    function: Option<ChunkFunction>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChunkFunction {
+    name: Option<String>,
+    arguments: Option<String>,
+}
+
+/// Parse the `data:` payload of one streamed chunk.
+fn chunk_deltas(data: &str) -> anyhow::Result<Vec<CompletionDelta>> {
+    let value: serde_json::Value = serde_json::from_str(data)?;
+    if let Some(error) = value.get("error") {
+        let message = error["message"].as_str().unwrap_or("unknown error");
+        return Err(anyhow::anyhow!("Error: {}", message));
+    }
+    let chunk: ChatCompletionChunk = serde_json::from_value(value)?;
+    let mut deltas = Vec::new();
+    // Only the first choice is rendered, matching the non-streamed path.
+    if let Some(choice) = chunk.choices.into_iter().next() {
+        if let Some(reasoning) = choice.delta.reasoning_content.filter(|r| !r.is_empty()) {
+            deltas.push(CompletionDelta::Reasoning(reasoning));
+        }
+        if let Some(text) = choice.delta.content.filter(|t| !t.is_empty()) {
+            deltas.push(CompletionDelta::Text(text));
+        }
+        for call in choice.delta.tool_calls {
+            let (name, arguments) = match call.function {
+                Some(f) => (f.name, f.arguments.unwrap_or_default()),
+                None => (None, String::new()),
+            };
+            deltas.push(CompletionDelta::ToolCall {
+                index: call.index,
+                id: call.id,
+                name,
+                arguments,
+            });
+        }
+        if let Some(finish_reason) = choice.finish_reason {
+            deltas.push(CompletionDelta::Finished { finish_reason });
+        }
+    }
+    deltas.extend(chunk.usage.map(CompletionDelta::Usage));
+    Ok(deltas)
 }
 
 struct OpenAIMessageAdapter;
@@ -67,7 +395,139 @@ impl OpenAIMessageAdapter {
                     "tool_call_id": msg.tool_call_id
                 })
             }
-            _ => serde_json::to_value(msg).unwrap(),
+            // OpenAI can't fetch images from this machine.
+            _ => serde_json::to_value(images::inline_local_images(msg)).unwrap(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::models::ReasoningEffort;
+
+    use super::*;
+
+    #[test]
+    fn test_completion_payload_for_reasoning_models() {
+        let request = |model: &str| CompletionRequest {
+            model: model.to_string(),
+            messages: vec![Message::user("Hi", None)],
+            temperature: Some(0.7),
+            top_p: None,
+            max_tokens: Some(512),
+            tools: None,
+            reasoning_effort: Some(ReasoningEffort::High),
+            stop: vec!["END".to_string()],
+            seed: Some(42),
+            presence_penalty: Some(0.5),
+            frequency_penalty: None,
+        };
+
+        let payload = completion_payload(&request("o3-mini"), "seed");
+        assert_eq!(payload["reasoning_effort"], "high");
+        assert_eq!(payload["max_completion_tokens"], 512);
+        assert!(payload.get("temperature").is_none());
+        assert!(payload.get("max_tokens").is_none());
+        assert!(payload.get("stop").is_none());
+        assert!(payload.get("presence_penalty").is_none());
+        assert_eq!(payload["seed"], 42);
+
+        let payload = completion_payload(&request("gpt-4o"), "random_seed");
+        assert!(payload.get("reasoning_effort").is_none());
+        assert_eq!(payload["stop"], json!(["END"]));
+        assert_eq!(payload["random_seed"], 42);
+        assert!(payload.get("seed").is_none());
+        assert_eq!(payload["presence_penalty"], 0.5);
+        assert!(payload.get("frequency_penalty").is_none());
+        assert_eq!(payload["max_tokens"], 512);
+        assert!((payload["temperature"].as_f64().unwrap() - 0.7).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_chunk_deltas_text_and_finish() {
+        let deltas = chunk_deltas(
+            r#"{"id":"c1","choices":[{"index":0,"delta":{"content":"Hel"},"finish_reason":null}]}"#,
+        )
+        .unwrap();
+        assert_eq!(deltas, vec![CompletionDelta::Text("Hel".to_string())]);
+
+        let deltas = chunk_deltas(
+            r#"{"id":"c1","choices":[{"index":0,"delta":{},"finish_reason":"stop"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            deltas,
+            vec![CompletionDelta::Finished {
+                finish_reason: "stop".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_chunk_deltas_usage() {
+        let deltas = chunk_deltas(
+            r#"{"id":"c1","choices":[],"usage":{"prompt_tokens":9,"completion_tokens":4,"total_tokens":13}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            deltas,
+            vec![CompletionDelta::Usage(TokenUsage {
+                prompt_tokens: 9,
+                completion_tokens: 4,
+            })]
+        );
+    }
+
+    #[test]
+    fn test_chunk_deltas_tool_call_fragments() {
+        let deltas = chunk_deltas(
+            r#"{"choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"id":"call_1","type":"function","function":{"name":"__srv__search","arguments":""}}]}}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            deltas,
+            vec![CompletionDelta::ToolCall {
+                index: 0,
+                id: Some("call_1".to_string()),
+                name: Some("__srv__search".to_string()),
+                arguments: String::new(),
+            }]
+        );
+
+        let deltas = chunk_deltas(
+            r#"{"choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"function":{"arguments":"{\"q\":"}}]}}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            deltas,
+            vec![CompletionDelta::ToolCall {
+                index: 0,
+                id: None,
+                name: None,
+                arguments: "{\"q\":".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_embeddings_are_ordered_by_input() {
+        let embeddings = parse_embeddings(
+            r#"{"object":"list","data":[
+                {"object":"embedding","index":1,"embedding":[0.5,0.25]},
+                {"object":"embedding","index":0,"embedding":[1.0,-1.0]}],
+              "model":"text-embedding-3-small"}"#,
+            2,
+        )
+        .unwrap();
+        assert_eq!(embeddings, vec![vec![1.0, -1.0], vec![0.5, 0.25]]);
+
+        let err = parse_embeddings(r#"{"data":[]}"#, 1).unwrap_err();
+        assert!(err.to_string().contains("Expected 1 embeddings, got 0"));
+    }
+
+    #[test]
+    fn test_chunk_deltas_error_payload() {
+        let err = chunk_deltas(r#"{"error":{"message":"rate limited"}}"#).unwrap_err();
+        assert!(err.to_string().contains("rate limited"));
+    }
+}
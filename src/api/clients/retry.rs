@@ -0,0 +1,160 @@
+//! Resending requests that failed for reasons likely to pass: rate limits,
+//! server errors and connections that could not be made or timed out.
+//!
+//! Attempts are spaced with exponential backoff and jitter, unless the
+//! server says how long to wait with `Retry-After` (in seconds).
+
+use std::{fmt, time::Duration};
+
+use reqwest::{header::RETRY_AFTER, RequestBuilder, Response, StatusCode};
+
+use crate::config::RetryConfig;
+
+/// Send `request`, retrying transient failures as `policy` allows. The
+/// outcome of the last attempt is returned as is, so a response that is
+/// still an error is reported by the caller as usual.
+pub async fn send(request: RequestBuilder, policy: &RetryConfig) -> reqwest::Result<Response> {
+    let mut attempt = 0;
+    loop {
+        // Requests with a streaming body can't be resent.
+        let Some(next) = request.try_clone() else {
+            return request.send().await;
+        };
+        let result = next.send().await;
+        if attempt >= policy.max_retries {
+            return result;
+        }
+        let delay = match &result {
+            Ok(response) if is_transient(response.status()) => retry_after(response)
+                .map(|delay| delay.min(Duration::from_millis(policy.max_backoff_ms)))
+                .unwrap_or_else(|| jitter(backoff(policy, attempt))),
+            Err(err) if err.is_connect() || err.is_timeout() => jitter(backoff(policy, attempt)),
+            _ => return result,
+        };
+        attempt += 1;
+        tracing::warn!(
+            "Request failed ({}), retrying in {:?} (attempt {} of {})",
+            match &result {
+                Ok(response) => response.status().to_string(),
+                Err(err) => err.to_string(),
+            },
+            delay,
+            attempt,
+            policy.max_retries
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// A request the server answered with an error status. Kept as its own
+/// error so callers can tell a rate limit from a bad request without
+/// reading the message.
+#[derive(Debug)]
+pub struct StatusError {
+    pub status: StatusCode,
+    /// What the server said went wrong, usually the response body.
+    pub message: String,
+}
+
+impl fmt::Display for StatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Error: {} {}", self.status, self.message)
+    }
+}
+
+impl std::error::Error for StatusError {}
+
+/// Rate limited, or a server error other than one that will not change.
+pub fn is_transient(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS
+        || (status.is_server_error() && status != StatusCode::NOT_IMPLEMENTED)
+}
+
+/// The delay asked for by a `Retry-After` header in seconds. The HTTP-date
+/// form is not supported and falls back to backoff.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let seconds: f64 = response
+        .headers()
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()?;
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+/// The longest delay before retry number `attempt + 1`.
+fn backoff(policy: &RetryConfig, attempt: u32) -> Duration {
+    let delay = policy
+        .initial_backoff_ms
+        .saturating_mul(1 << attempt.min(32))
+        .min(policy.max_backoff_ms);
+    Duration::from_millis(delay)
+}
+
+/// A random delay between half of `delay` and all of it, so clients that
+/// failed together don't all retry at the same moment.
+fn jitter(delay: Duration) -> Duration {
+    delay.mul_f64(rand::random_range(0.5..=1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_doubles_up_to_the_cap() {
+        let policy = RetryConfig {
+            max_retries: 10,
+            initial_backoff_ms: 500,
+            max_backoff_ms: 3_000,
+        };
+        let delays: Vec<u128> = (0..5)
+            .map(|attempt| backoff(&policy, attempt).as_millis())
+            .collect();
+        assert_eq!(delays, vec![500, 1_000, 2_000, 3_000, 3_000]);
+        assert_eq!(backoff(&policy, u32::MAX).as_millis(), 3_000);
+
+        let delay = jitter(Duration::from_millis(1_000));
+        assert!((500..=1_000).contains(&delay.as_millis()));
+    }
+
+    #[test]
+    fn test_transient_statuses() {
+        assert!(is_transient(StatusCode::TOO_MANY_REQUESTS));
+        assert!(is_transient(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(is_transient(StatusCode::from_u16(529).unwrap()));
+        assert!(!is_transient(StatusCode::NOT_IMPLEMENTED));
+        assert!(!is_transient(StatusCode::BAD_REQUEST));
+        assert!(!is_transient(StatusCode::UNAUTHORIZED));
+    }
+
+    #[tokio::test]
+    async fn test_send_honors_retry_after() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // Rate limits the first request, then succeeds.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            for reply in [
+                "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 0\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok",
+            ] {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buf = [0; 1024];
+                let _ = socket.read(&mut buf).await.unwrap();
+                socket.write_all(reply.as_bytes()).await.unwrap();
+            }
+        });
+
+        let policy = RetryConfig {
+            initial_backoff_ms: 60_000,
+            ..RetryConfig::default()
+        };
+        let request = reqwest::Client::new().get(url);
+        let response = send(request, &policy).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        server.await.unwrap();
+    }
+}
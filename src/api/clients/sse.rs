@@ -0,0 +1,147 @@
+//! Minimal server-sent events decoder for streamed completions.
+//!
+//! Only the parts of the SSE format that completion endpoints use are
+//! supported: `data:` lines (joined with `\n` when an event spans several),
+//! `event:` names, comments, and blank-line event terminators. Bytes may
+//! arrive split at arbitrary points, including in the middle of a UTF-8
+//! sequence.
+
+/// A single decoded server-sent event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SseEvent {
+    /// The `event:` field, if the server sent one.
+    pub event: Option<String>,
+    /// The `data:` payload.
+    pub data: String,
+}
+
+/// Incremental SSE decoder. Feed it chunks as they arrive with
+/// [`SseDecoder::push`]; complete events are returned as soon as their
+/// terminating blank line has been seen.
+#[derive(Debug, Default)]
+pub struct SseDecoder {
+    buffer: Vec<u8>,
+    event: Option<String>,
+    data: Vec<String>,
+}
+
+impl SseDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(pos) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+            if let Some(event) = self.process_line(line) {
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    /// Flush a trailing event that was not terminated by a blank line.
+    pub fn finish(&mut self) -> Option<SseEvent> {
+        if !self.buffer.is_empty() {
+            let rest = std::mem::take(&mut self.buffer);
+            let line = String::from_utf8_lossy(&rest).into_owned();
+            self.process_line(line.trim_end_matches('\r'));
+        }
+        self.dispatch()
+    }
+
+    fn process_line(&mut self, line: &str) -> Option<SseEvent> {
+        if line.is_empty() {
+            return self.dispatch();
+        }
+        if line.starts_with(':') {
+            return None;
+        }
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        match field {
+            "data" => self.data.push(value.to_string()),
+            "event" => self.event = Some(value.to_string()),
+            _ => {}
+        }
+        None
+    }
+
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        let event = self.event.take();
+        if self.data.is_empty() {
+            return None;
+        }
+        let data = std::mem::take(&mut self.data).join("\n");
+        Some(SseEvent { event, data })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decodes_events_split_across_chunks() {
+        let mut decoder = SseDecoder::new();
+        assert!(decoder.push(b"data: {\"a\":").is_empty());
+        let events = decoder.push(b"1}\n\ndata: [DONE]\n\n");
+        assert_eq!(
+            events,
+            vec![
+                SseEvent {
+                    event: None,
+                    data: "{\"a\":1}".to_string()
+                },
+                SseEvent {
+                    event: None,
+                    data: "[DONE]".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_handles_crlf_comments_and_event_names() {
+        let mut decoder = SseDecoder::new();
+        let events =
+            decoder.push(b": keep-alive\r\nevent: message_start\r\ndata: one\r\ndata: two\r\n\r\n");
+        assert_eq!(
+            events,
+            vec![SseEvent {
+                event: Some("message_start".to_string()),
+                data: "one\ntwo".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_multibyte_character_split_between_chunks() {
+        let mut decoder = SseDecoder::new();
+        let bytes = "data: héllo\n\n".as_bytes();
+        let split = bytes.iter().position(|b| *b == 0xc3).unwrap() + 1;
+        assert!(decoder.push(&bytes[..split]).is_empty());
+        let events = decoder.push(&bytes[split..]);
+        assert_eq!(events[0].data, "héllo");
+    }
+
+    #[test]
+    fn test_finish_flushes_unterminated_event() {
+        let mut decoder = SseDecoder::new();
+        assert!(decoder.push(b"data: tail").is_empty());
+        assert_eq!(
+            decoder.finish(),
+            Some(SseEvent {
+                event: None,
+                data: "tail".to_string()
+            })
+        );
+        assert_eq!(decoder.finish(), None);
+    }
+}
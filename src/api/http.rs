@@ -0,0 +1,112 @@
+//! The HTTP clients that providers and MCP servers are reached with.
+
+use std::time::Duration;
+
+use reqwest::{ClientBuilder, NoProxy, Proxy};
+
+use crate::config::{ProxyConfig, TimeoutConfig};
+
+/// A client that gives up on connections and on servers that stop sending
+/// after `timeouts`, so a hung endpoint fails the request instead of
+/// leaving it waiting forever. Requests go through `proxy` when one is set.
+pub fn client(timeouts: &TimeoutConfig, proxy: Option<&ProxyConfig>) -> reqwest::Client {
+    let builder = reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(timeouts.connect_secs))
+        .read_timeout(Duration::from_secs(timeouts.read_secs));
+    build(with_proxy(builder, proxy))
+}
+
+/// A client without timeouts, for long-lived connections such as MCP's,
+/// sending requests through `proxy` when one is set.
+pub fn proxied_client(proxy: &ProxyConfig) -> reqwest::Client {
+    build(with_proxy(reqwest::Client::builder(), Some(proxy)))
+}
+
+/// `builder` routing every request through `proxy`, except to the hosts it
+/// exempts. An invalid proxy URL is logged and ignored.
+pub fn with_proxy(builder: ClientBuilder, proxy: Option<&ProxyConfig>) -> ClientBuilder {
+    let Some(config) = proxy.filter(|proxy| proxy.is_set()) else {
+        return builder;
+    };
+    match Proxy::all(&config.url) {
+        Ok(proxy) => {
+            builder.proxy(proxy.no_proxy(NoProxy::from_string(&config.no_proxy.join(","))))
+        }
+        Err(err) => {
+            tracing::error!("Ignoring invalid proxy URL: {}", err);
+            builder
+        }
+    }
+}
+
+fn build(builder: ClientBuilder) -> reqwest::Client {
+    builder.build().unwrap_or_else(|err| {
+        tracing::error!("Failed to build HTTP client, using defaults: {}", err);
+        reqwest::Client::new()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    /// Answers one request with `body`.
+    async fn serve_once(listener: TcpListener, body: &'static str) {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut buf = [0; 1024];
+        let _ = socket.read(&mut buf).await.unwrap();
+        let reply = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        );
+        socket.write_all(reply.as_bytes()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_read_timeout_fails_silent_server() {
+        // Accepts the connection but never answers.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (_socket, _) = listener.accept().await.unwrap();
+            tokio::time::sleep(Duration::from_secs(10)).await;
+        });
+
+        let client = client(
+            &TimeoutConfig {
+                connect_secs: 1,
+                read_secs: 1,
+            },
+            None,
+        );
+        let err = client.get(url).send().await.unwrap_err();
+        assert!(err.is_timeout());
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_proxy_and_exemptions() {
+        let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let direct = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_url = format!("http://{}", proxy.local_addr().unwrap());
+        let direct_url = format!("http://{}/", direct.local_addr().unwrap());
+        tokio::spawn(serve_once(proxy, "proxied"));
+        tokio::spawn(serve_once(direct, "direct"));
+
+        let client = proxied_client(&ProxyConfig {
+            url: proxy_url,
+            no_proxy: vec!["127.0.0.1".to_string()],
+        });
+        let body = |url: String| {
+            let client = client.clone();
+            async move { client.get(url).send().await.unwrap().text().await.unwrap() }
+        };
+        assert_eq!(body("http://ergon.invalid/".to_string()).await, "proxied");
+        assert_eq!(body(direct_url).await, "direct");
+    }
+}
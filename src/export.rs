@@ -0,0 +1,624 @@
+//! Standalone HTML export of a conversation.
+//!
+//! The output is a single file with inline CSS and no scripts, so it can be
+//! shared or archived and opened in any browser. Markdown is rendered with
+//! `pulldown-cmark`; fenced code blocks go through a small built-in
+//! highlighter that colours comments, strings, numbers and the keywords of
+//! common languages.
+//!
+//! Token usage can also be exported as CSV for expense reports.
+
+use std::collections::HashMap;
+
+use pulldown_cmark::{html, CodeBlockKind, Event, Options, Parser, Tag, TagEnd};
+
+use crate::{
+    config::ModelPricing,
+    models::{Content, Message},
+    storage::{civil_from_days, UsageRecord},
+};
+
+const STYLE: &str = "
+body { font-family: -apple-system, 'Segoe UI', Helvetica, Arial, sans-serif;
+       max-width: 860px; margin: 2em auto; padding: 0 1em; color: #1f2328;
+       background: #ffffff; line-height: 1.5; }
+h1.title { border-bottom: 1px solid #d0d7de; padding-bottom: .3em; }
+.message { margin: 1.2em 0; padding: .6em 1em; border-radius: 8px;
+           border: 1px solid #d0d7de; }
+.message.user { background: #eef4ff; }
+.message.tool, .message.system { background: #f6f8fa; }
+.role { font-size: .8em; font-weight: 600; text-transform: uppercase;
+        color: #59636e; }
+pre { background: #f6f8fa; padding: .8em; border-radius: 6px; overflow-x: auto; }
+code { font-family: ui-monospace, SFMono-Regular, Menlo, Consolas, monospace;
+       font-size: .9em; }
+details { margin: .5em 0; }
+summary { cursor: pointer; font-weight: 600; }
+img { max-width: 100%; }
+.error { color: #d1242f; }
+.kw { color: #cf222e; }
+.str { color: #0a3069; }
+.com { color: #6e7781; font-style: italic; }
+.num { color: #0550ae; }
+";
+
+/// Render `messages` as a complete HTML document titled `title`.
+pub fn conversation_html(title: &str, messages: &[Message]) -> String {
+    let mut body = String::new();
+    for message in messages {
+        let role = escape(&message.role);
+        body.push_str(&format!(
+            "<section class=\"message {role}\">\n<div class=\"role\">{role}</div>\n"
+        ));
+        for content in &message.content {
+            body.push_str(&content_html(content));
+        }
+        for call in message.tool_calls.iter().flatten() {
+            body.push_str(&details(
+                &format!("Call {}", call.function.name),
+                &pretty_json(&call.function.arguments),
+                false,
+            ));
+        }
+        body.push_str("</section>\n");
+    }
+    let title = escape(title);
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>{title}</title>\n<style>{STYLE}</style>\n</head>\n<body>\n\
+         <h1 class=\"title\">{title}</h1>\n{body}</body>\n</html>\n"
+    )
+}
+
+fn content_html(content: &Content) -> String {
+    match content {
+        Content::Text { text } => markdown_html(text),
+        Content::ImageUrl { image_url } => {
+            format!(
+                "<p><img src=\"{}\" alt=\"image\"></p>\n",
+                escape(&image_url.url)
+            )
+        }
+        Content::ToolUse { name, input, .. } => details(
+            &format!("Call {name}"),
+            &serde_json::to_string_pretty(input).unwrap_or_default(),
+            false,
+        ),
+        Content::ToolResult {
+            content, is_error, ..
+        } => {
+            let is_error = is_error.unwrap_or(false);
+            let summary = if is_error { "Error" } else { "Result" };
+            details(summary, &pretty_json(content), is_error)
+        }
+        Content::File { file } => format!(
+            "<p>Attachment: {}</p>\n",
+            escape(file.filename.as_deref().unwrap_or("file"))
+        ),
+        Content::Audio { .. } => "<p>Audio attachment</p>\n".to_string(),
+    }
+}
+
+/// A collapsed block with a code body, used for tool calls and results.
+fn details(summary: &str, body: &str, is_error: bool) -> String {
+    let class = if is_error { " class=\"error\"" } else { "" };
+    format!(
+        "<details><summary{class}>{}</summary><pre><code>{}</code></pre></details>\n",
+        escape(summary),
+        escape(body)
+    )
+}
+
+/// Pretty-print `raw` if it is JSON, otherwise return it unchanged.
+pub fn pretty_json(raw: &str) -> String {
+    serde_json::from_str::<serde_json::Value>(raw)
+        .ok()
+        .and_then(|v| serde_json::to_string_pretty(&v).ok())
+        .unwrap_or_else(|| raw.to_string())
+}
+
+/// Render markdown to HTML. Raw HTML in the source is shown as text rather
+/// than passed through, links and images to anything but web, mail or
+/// relative URLs keep only their text, and fenced code blocks are
+/// highlighted.
+fn markdown_html(markdown: &str) -> String {
+    let mut events = Vec::new();
+    // Whether each open link or image was dropped, innermost last.
+    let mut dropped = Vec::new();
+    let mut code: Option<(String, String)> = None; // (language, source)
+    for event in Parser::new_ext(markdown, Options::all()) {
+        match event {
+            Event::Start(Tag::CodeBlock(kind)) => {
+                let language = match kind {
+                    CodeBlockKind::Fenced(info) => info
+                        .split_whitespace()
+                        .next()
+                        .unwrap_or_default()
+                        .to_string(),
+                    CodeBlockKind::Indented => String::new(),
+                };
+                code = Some((language, String::new()));
+            }
+            Event::Text(text) if code.is_some() => {
+                if let Some((_, source)) = code.as_mut() {
+                    source.push_str(&text);
+                }
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                if let Some((language, source)) = code.take() {
+                    events.push(Event::Html(
+                        format!(
+                            "<pre><code class=\"language-{}\">{}</code></pre>\n",
+                            escape(&language),
+                            highlight(&source, &language)
+                        )
+                        .into(),
+                    ));
+                }
+            }
+            Event::Start(Tag::Link { ref dest_url, .. } | Tag::Image { ref dest_url, .. }) => {
+                let safe = is_safe_url(dest_url);
+                dropped.push(!safe);
+                if safe {
+                    events.push(event);
+                }
+            }
+            Event::End(TagEnd::Link | TagEnd::Image) => {
+                if dropped.pop() != Some(true) {
+                    events.push(event);
+                }
+            }
+            Event::Html(raw) | Event::InlineHtml(raw) => events.push(Event::Text(raw)),
+            event => events.push(event),
+        }
+    }
+    let mut out = String::new();
+    html::push_html(&mut out, events.into_iter());
+    out
+}
+
+/// Whether `url` is safe to link to from an exported page: http, https,
+/// mailto or relative. Browsers ignore tabs and newlines inside a URL and
+/// leading control characters, so those are skipped before reading the
+/// scheme.
+fn is_safe_url(url: &str) -> bool {
+    let url: String = url
+        .chars()
+        .filter(|c| !matches!(c, '\t' | '\n' | '\r'))
+        .collect();
+    let url = url.trim_start_matches(|c: char| c <= ' ');
+    match url.find([':', '/', '?', '#']) {
+        Some(end) if url[end..].starts_with(':') => {
+            matches!(
+                url[..end].to_ascii_lowercase().as_str(),
+                "http" | "https" | "mailto"
+            )
+        }
+        _ => true,
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Lexical rules for one family of languages.
+struct Syntax {
+    line_comments: &'static [&'static str],
+    block_comment: Option<(&'static str, &'static str)>,
+    quotes: &'static [char],
+    keywords: &'static [&'static str],
+}
+
+fn syntax(language: &str) -> Syntax {
+    match language.to_ascii_lowercase().as_str() {
+        "rust" | "rs" => Syntax {
+            line_comments: &["//"],
+            block_comment: Some(("/*", "*/")),
+            // Single quotes also start lifetimes, so only double-quoted
+            // strings are recognised.
+            quotes: &['"'],
+            keywords: &[
+                "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else",
+                "enum", "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match",
+                "mod", "move", "mut", "pub", "ref", "return", "self", "Self", "static", "struct",
+                "super", "trait", "true", "type", "unsafe", "use", "where", "while",
+            ],
+        },
+        "python" | "py" => Syntax {
+            line_comments: &["#"],
+            block_comment: None,
+            quotes: &['"', '\''],
+            keywords: &[
+                "and", "as", "assert", "async", "await", "break", "class", "continue", "def",
+                "del", "elif", "else", "except", "False", "finally", "for", "from", "global", "if",
+                "import", "in", "is", "lambda", "None", "nonlocal", "not", "or", "pass", "raise",
+                "return", "True", "try", "while", "with", "yield",
+            ],
+        },
+        "javascript" | "js" | "jsx" | "typescript" | "ts" | "tsx" => Syntax {
+            line_comments: &["//"],
+            block_comment: Some(("/*", "*/")),
+            quotes: &['"', '\'', '`'],
+            keywords: &[
+                "async",
+                "await",
+                "break",
+                "case",
+                "catch",
+                "class",
+                "const",
+                "continue",
+                "default",
+                "delete",
+                "else",
+                "export",
+                "extends",
+                "false",
+                "finally",
+                "for",
+                "from",
+                "function",
+                "if",
+                "import",
+                "in",
+                "instanceof",
+                "interface",
+                "let",
+                "new",
+                "null",
+                "of",
+                "return",
+                "switch",
+                "this",
+                "throw",
+                "true",
+                "try",
+                "type",
+                "typeof",
+                "undefined",
+                "var",
+                "void",
+                "while",
+                "yield",
+            ],
+        },
+        "go" => Syntax {
+            line_comments: &["//"],
+            block_comment: Some(("/*", "*/")),
+            quotes: &['"', '\'', '`'],
+            keywords: &[
+                "break",
+                "case",
+                "chan",
+                "const",
+                "continue",
+                "default",
+                "defer",
+                "else",
+                "false",
+                "for",
+                "func",
+                "go",
+                "if",
+                "import",
+                "interface",
+                "map",
+                "nil",
+                "package",
+                "range",
+                "return",
+                "select",
+                "struct",
+                "switch",
+                "true",
+                "type",
+                "var",
+            ],
+        },
+        "c" | "h" | "cpp" | "c++" | "hpp" | "java" | "cs" | "csharp" | "kotlin" | "swift" => {
+            Syntax {
+                line_comments: &["//"],
+                block_comment: Some(("/*", "*/")),
+                quotes: &['"', '\''],
+                keywords: &[
+                    "auto",
+                    "bool",
+                    "break",
+                    "case",
+                    "catch",
+                    "char",
+                    "class",
+                    "const",
+                    "continue",
+                    "default",
+                    "do",
+                    "double",
+                    "else",
+                    "enum",
+                    "extends",
+                    "false",
+                    "final",
+                    "float",
+                    "for",
+                    "fun",
+                    "func",
+                    "if",
+                    "import",
+                    "int",
+                    "let",
+                    "long",
+                    "namespace",
+                    "new",
+                    "null",
+                    "nullptr",
+                    "private",
+                    "protected",
+                    "public",
+                    "return",
+                    "short",
+                    "static",
+                    "struct",
+                    "switch",
+                    "this",
+                    "throw",
+                    "true",
+                    "try",
+                    "typedef",
+                    "unsigned",
+                    "using",
+                    "val",
+                    "var",
+                    "void",
+                    "while",
+                ],
+            }
+        }
+        "sh" | "bash" | "shell" | "zsh" | "console" => Syntax {
+            line_comments: &["#"],
+            block_comment: None,
+            quotes: &['"', '\''],
+            keywords: &[
+                "case", "do", "done", "elif", "else", "esac", "export", "fi", "for", "function",
+                "if", "in", "local", "return", "then", "while",
+            ],
+        },
+        "toml" | "yaml" | "yml" | "ini" => Syntax {
+            line_comments: &["#"],
+            block_comment: None,
+            quotes: &['"', '\''],
+            keywords: &["true", "false", "null"],
+        },
+        "json" => Syntax {
+            line_comments: &[],
+            block_comment: None,
+            quotes: &['"'],
+            keywords: &["true", "false", "null"],
+        },
+        _ => Syntax {
+            line_comments: &[],
+            block_comment: None,
+            quotes: &['"'],
+            keywords: &[],
+        },
+    }
+}
+
+/// Escape `code` for HTML, wrapping comments, strings, numbers and keywords
+/// of `language` in spans.
+fn highlight(code: &str, language: &str) -> String {
+    let syntax = syntax(language);
+    let mut out = String::with_capacity(code.len() * 2);
+    let mut rest = code;
+    while let Some(c) = rest.chars().next() {
+        let (class, len) = if let Some(len) = comment_len(rest, &syntax) {
+            (Some("com"), len)
+        } else if syntax.quotes.contains(&c) {
+            (Some("str"), string_len(rest, c))
+        } else if c.is_ascii_digit() {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '.' || c == '_'))
+                .unwrap_or(rest.len());
+            (Some("num"), len)
+        } else if c.is_alphabetic() || c == '_' {
+            let len = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let keyword = syntax.keywords.contains(&&rest[..len]);
+            (keyword.then_some("kw"), len)
+        } else {
+            (None, c.len_utf8())
+        };
+        let token = escape(&rest[..len]);
+        match class {
+            Some(class) => out.push_str(&format!("<span class=\"{class}\">{token}</span>")),
+            None => out.push_str(&token),
+        }
+        rest = &rest[len..];
+    }
+    out
+}
+
+/// Length of the comment starting at the beginning of `text`, if any.
+fn comment_len(text: &str, syntax: &Syntax) -> Option<usize> {
+    if syntax.line_comments.iter().any(|p| text.starts_with(p)) {
+        return Some(text.find('\n').unwrap_or(text.len()));
+    }
+    let (open, close) = syntax.block_comment?;
+    if !text.starts_with(open) {
+        return None;
+    }
+    Some(
+        text[open.len()..]
+            .find(close)
+            .map(|end| open.len() + end + close.len())
+            .unwrap_or(text.len()),
+    )
+}
+
+/// Length of the string literal opened by `quote` at the start of `text`,
+/// including both quotes. Strings end at the first unescaped closing quote,
+/// or at the end of the line unless they are backtick strings.
+fn string_len(text: &str, quote: char) -> usize {
+    let mut escaped = false;
+    for (i, c) in text.char_indices().skip(1) {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == quote {
+            return i + c.len_utf8();
+        } else if c == '\n' && quote != '`' {
+            return i;
+        }
+    }
+    text.len()
+}
+
+/// Render usage `records` as CSV with one row per completion. `provider`
+/// names the provider serving a model, if known. The cost column is left
+/// empty for models without a `pricing` entry.
+pub fn usage_csv(
+    records: &[UsageRecord],
+    pricing: &HashMap<String, ModelPricing>,
+    provider: impl Fn(&str) -> Option<String>,
+) -> String {
+    let mut out = String::from(
+        "timestamp,conversation,model,provider,prompt_tokens,completion_tokens,cost_usd\n",
+    );
+    for record in records {
+        let model = record.model.as_deref().unwrap_or_default();
+        let cost = pricing
+            .get(model)
+            .map(|pricing| format!("{:.6}", pricing.cost(&record.usage)))
+            .unwrap_or_default();
+        out.push_str(&format!(
+            "{},{},{},{},{},{},{}\n",
+            iso_timestamp(record.created_at),
+            csv_field(&record.conversation_id),
+            csv_field(model),
+            csv_field(&provider(model).unwrap_or_default()),
+            record.usage.prompt_tokens,
+            record.usage.completion_tokens,
+            cost
+        ));
+    }
+    out
+}
+
+/// Quote a CSV field if it contains a separator, quote or line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Format Unix seconds as an RFC 3339 UTC timestamp.
+pub fn iso_timestamp(seconds: i64) -> String {
+    let (days, seconds) = (seconds.div_euclid(86_400), seconds.rem_euclid(86_400));
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_highlight_rust() {
+        let html = highlight("let s = \"a<b\"; // note\nlet n = 42;", "rust");
+        assert_eq!(
+            html,
+            "<span class=\"kw\">let</span> s = <span class=\"str\">&quot;a&lt;b&quot;</span>; \
+             <span class=\"com\">// note</span>\n<span class=\"kw\">let</span> n = \
+             <span class=\"num\">42</span>;"
+        );
+    }
+
+    #[test]
+    fn test_markdown_escapes_raw_html_and_highlights_code() {
+        let html = markdown_html("Hi <script>x</script>\n\n```python\nimport os\n```\n");
+        assert!(html.contains("&lt;script&gt;"));
+        assert!(!html.contains("<script>"));
+        assert!(html.contains(
+            "<pre><code class=\"language-python\"><span class=\"kw\">import</span> os\n</code></pre>"
+        ));
+    }
+
+    #[test]
+    fn test_markdown_drops_unsafe_links() {
+        let html = markdown_html(
+            "[a](https://example.com) [b](javascript:alert(1)) [c](docs/a.md) \
+             ![d](JavaScript:x) [e](<java\tscript:y>) [f](mailto:me@example.com)",
+        );
+        assert!(html.contains("<a href=\"https://example.com\">a</a>"));
+        assert!(html.contains("<a href=\"docs/a.md\">c</a>"));
+        assert!(html.contains("<a href=\"mailto:me@example.com\">f</a>"));
+        assert!(!html.to_lowercase().contains("script:"));
+        assert!(html.contains(" b ") && html.contains(" d ") && html.contains(" e "));
+    }
+
+    #[test]
+    fn test_conversation_html_includes_every_message() {
+        let messages = vec![
+            Message::user("What is **Rust**?", None),
+            Message::assistant("A systems language."),
+            Message::tool_result("call_1", "{\"ok\":true}", Some(true)),
+        ];
+        let html = conversation_html("Rust & me", &messages);
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<title>Rust &amp; me</title>"));
+        assert!(html.contains("<strong>Rust</strong>"));
+        assert!(html.contains("<section class=\"message assistant\">"));
+        assert!(html.contains("<summary class=\"error\">Error</summary>"));
+    }
+
+    #[test]
+    fn test_usage_csv() {
+        let record = |model: &str| UsageRecord {
+            conversation_id: "c1".to_string(),
+            created_at: 1_709_294_700,
+            model: Some(model.to_string()),
+            usage: crate::models::TokenUsage {
+                prompt_tokens: 1_000,
+                completion_tokens: 500,
+            },
+        };
+        let pricing = HashMap::from([(
+            "gpt-4o".to_string(),
+            ModelPricing {
+                prompt: 2.5,
+                completion: 10.0,
+            },
+        )]);
+        let csv = usage_csv(
+            &[record("gpt-4o"), record("local, quantized")],
+            &pricing,
+            |model| (model == "gpt-4o").then(|| "OpenAI".to_string()),
+        );
+        assert_eq!(
+            csv,
+            "timestamp,conversation,model,provider,prompt_tokens,completion_tokens,cost_usd\n\
+             2024-03-01T12:05:00Z,c1,gpt-4o,OpenAI,1000,500,0.007500\n\
+             2024-03-01T12:05:00Z,c1,\"local, quantized\",,1000,500,\n"
+        );
+    }
+}
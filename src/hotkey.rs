@@ -0,0 +1,101 @@
+//! The system-wide hotkey that opens the quick-chat window.
+//!
+//! Shortcuts are written like `Alt+Shift+Space`: modifiers (`Alt`, `Ctrl`,
+//! `Shift`, `Super`, `CmdOrCtrl`) and a key, joined by `+`. Wayland offers no
+//! global shortcuts, so on Linux the hotkey works under X11 only.
+
+use std::fmt;
+
+use anyhow::{anyhow, Result};
+use global_hotkey::{hotkey::HotKey, GlobalHotKeyEvent, GlobalHotKeyManager, HotKeyState};
+use iced::futures::{stream, Stream, StreamExt};
+
+/// The registered hotkey. Dropping it unregisters the hotkey.
+pub struct Hotkey {
+    manager: GlobalHotKeyManager,
+    registered: Option<(String, HotKey)>,
+}
+
+impl fmt::Debug for Hotkey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hotkey")
+            .field("registered", &self.registered)
+            .finish()
+    }
+}
+
+impl Hotkey {
+    /// Connect to the system's hotkey service. Must be called on the main
+    /// thread.
+    pub fn new() -> Result<Self> {
+        let manager = GlobalHotKeyManager::new()
+            .map_err(|e| anyhow!("Global hotkeys are unavailable: {}", e))?;
+        Ok(Self {
+            manager,
+            registered: None,
+        })
+    }
+
+    /// Register `binding` in place of the current hotkey. An empty binding
+    /// only unregisters it.
+    pub fn set(&mut self, binding: &str) -> Result<()> {
+        let binding = binding.trim();
+        if self
+            .registered
+            .as_ref()
+            .is_some_and(|(current, _)| current == binding)
+        {
+            return Ok(());
+        }
+        if let Some((_, hotkey)) = self.registered.take() {
+            self.manager
+                .unregister(hotkey)
+                .map_err(|e| anyhow!("Failed to unregister the hotkey: {}", e))?;
+        }
+        if binding.is_empty() {
+            return Ok(());
+        }
+        let hotkey = parse(binding)?;
+        self.manager
+            .register(hotkey)
+            .map_err(|e| anyhow!("Failed to register {}: {}", binding, e))?;
+        self.registered = Some((binding.to_string(), hotkey));
+        Ok(())
+    }
+
+    /// Id of the registered hotkey, as reported by [`presses`].
+    pub fn id(&self) -> Option<u32> {
+        self.registered.as_ref().map(|(_, hotkey)| hotkey.id())
+    }
+}
+
+/// The hotkey `binding` describes.
+pub fn parse(binding: &str) -> Result<HotKey> {
+    binding
+        .parse()
+        .map_err(|e| anyhow!("{} is not a valid hotkey: {}", binding, e))
+}
+
+/// Ids of the hotkeys pressed, as they are pressed.
+pub fn presses() -> impl Stream<Item = u32> {
+    stream::unfold((), |()| async {
+        let event = tokio::task::spawn_blocking(|| GlobalHotKeyEvent::receiver().recv())
+            .await
+            .ok()?
+            .ok()?;
+        Some((event, ()))
+    })
+    .filter_map(|event| async move { (event.state == HotKeyState::Pressed).then_some(event.id) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hotkeys() {
+        assert!(parse(crate::config::DEFAULT_QUICK_CHAT_HOTKEY).is_ok());
+        assert!(parse("Ctrl+Shift+KeyK").is_ok());
+        assert!(parse("Alt+Shift+").is_err());
+    }
+}
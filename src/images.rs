@@ -0,0 +1,186 @@
+//! Local images as `data:` URLs.
+//!
+//! Providers can't fetch a path on this machine, so images are sent inline
+//! instead, scaled down and recompressed when they are larger than
+//! providers accept.
+
+use std::borrow::Cow;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use base64::Engine as _;
+use image::{codecs::jpeg::JpegEncoder, imageops::FilterType, ImageFormat, ImageReader};
+
+use crate::models::{Content, Message};
+
+/// The longest side sent. OpenAI scales larger images down to it anyway.
+const MAX_SIDE: u32 = 2048;
+/// The largest image sent, Anthropic's limit and the lowest of the
+/// providers'.
+const MAX_BYTES: usize = 5 * 1024 * 1024;
+const JPEG_QUALITY: u8 = 85;
+
+/// The image at `path` as a `data:` URL.
+pub fn data_url(path: &Path) -> Result<String> {
+    let bytes =
+        std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let (mime_type, bytes) = encode(bytes)?;
+    Ok(format!(
+        "data:{};base64,{}",
+        mime_type,
+        base64::engine::general_purpose::STANDARD.encode(bytes)
+    ))
+}
+
+/// `message` with the images it links to on this machine inlined as
+/// `data:` URLs. An image that can't be read is logged and left as is.
+pub fn inline_local_images(message: &Message) -> Cow<'_, Message> {
+    let is_local = |content: &Content| match content {
+        Content::ImageUrl { image_url } => local_path(&image_url.url).is_some(),
+        _ => false,
+    };
+    if !message.content.iter().any(is_local) {
+        return Cow::Borrowed(message);
+    }
+    let mut message = message.clone();
+    for content in &mut message.content {
+        if let Content::ImageUrl { image_url } = content {
+            image_url.url = inline_local_image(std::mem::take(&mut image_url.url));
+        }
+    }
+    Cow::Owned(message)
+}
+
+/// `url` as a `data:` URL if it points to an image on this machine, as it
+/// is otherwise or if the image can't be read.
+pub fn inline_local_image(url: String) -> String {
+    let Some(path) = local_path(&url) else {
+        return url;
+    };
+    data_url(&path).unwrap_or_else(|err| {
+        tracing::error!("Failed to inline image {}: {}", path.display(), err);
+        url
+    })
+}
+
+/// The file an image URL points to on this machine: a `file://` URL or an
+/// absolute path.
+fn local_path(url: &str) -> Option<PathBuf> {
+    if url.starts_with("file:") {
+        return url::Url::parse(url).ok()?.to_file_path().ok();
+    }
+    let path = Path::new(url);
+    path.is_absolute().then(|| path.to_path_buf())
+}
+
+/// `bytes` as they are if providers take them, otherwise scaled to fit
+/// [`MAX_SIDE`] and re-encoded: as PNG when the image is transparent and
+/// that is small enough, as JPEG otherwise. Returns the MIME type with the
+/// bytes.
+fn encode(bytes: Vec<u8>) -> Result<(&'static str, Vec<u8>)> {
+    let reader = ImageReader::new(Cursor::new(&bytes)).with_guessed_format()?;
+    let format = reader.format().context("Unrecognized image format")?;
+    let (width, height) = reader.into_dimensions()?;
+    if let Some(mime_type) = mime_type(format) {
+        if width.max(height) <= MAX_SIDE && bytes.len() <= MAX_BYTES {
+            return Ok((mime_type, bytes));
+        }
+    }
+
+    let mut image = image::load_from_memory_with_format(&bytes, format)?;
+    if width.max(height) > MAX_SIDE {
+        image = image.resize(MAX_SIDE, MAX_SIDE, FilterType::Triangle);
+    }
+    let mut encoded = Vec::new();
+    if image.color().has_alpha() {
+        image.write_to(&mut Cursor::new(&mut encoded), ImageFormat::Png)?;
+        if encoded.len() <= MAX_BYTES {
+            return Ok(("image/png", encoded));
+        }
+        encoded.clear();
+    }
+    let encoder = JpegEncoder::new_with_quality(&mut encoded, JPEG_QUALITY);
+    image.to_rgb8().write_with_encoder(encoder)?;
+    Ok(("image/jpeg", encoded))
+}
+
+/// The MIME type of `format` if providers take it as is.
+fn mime_type(format: ImageFormat) -> Option<&'static str> {
+    match format {
+        ImageFormat::Png => Some("image/png"),
+        ImageFormat::Jpeg => Some("image/jpeg"),
+        ImageFormat::Gif => Some("image/gif"),
+        ImageFormat::WebP => Some("image/webp"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, GenericImageView, RgbImage, RgbaImage};
+
+    use super::*;
+
+    fn png(image: DynamicImage) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        image
+            .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
+            .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_small_images_are_sent_as_they_are() {
+        let bytes = png(RgbImage::new(64, 32).into());
+        let (mime_type, encoded) = encode(bytes.clone()).unwrap();
+        assert_eq!(mime_type, "image/png");
+        assert_eq!(encoded, bytes);
+    }
+
+    #[test]
+    fn test_large_images_are_scaled_down() {
+        let (mime_type, encoded) = encode(png(RgbImage::new(4096, 1024).into())).unwrap();
+        assert_eq!(mime_type, "image/jpeg");
+        let image = image::load_from_memory(&encoded).unwrap();
+        assert_eq!(image.dimensions(), (2048, 512));
+
+        // Transparency is kept.
+        let (mime_type, encoded) = encode(png(RgbaImage::new(3000, 3000).into())).unwrap();
+        assert_eq!(mime_type, "image/png");
+        let image = image::load_from_memory(&encoded).unwrap();
+        assert_eq!(image.dimensions(), (2048, 2048));
+    }
+
+    #[test]
+    fn test_local_images_are_inlined() {
+        let path = std::env::temp_dir().join("ergon_test_inline_image.png");
+        std::fs::write(&path, png(RgbImage::new(8, 8).into())).unwrap();
+        let url = url::Url::from_file_path(&path).unwrap().to_string();
+
+        let mut message = Message::user("Look", None);
+        message.content.push(Content::image_url(&url));
+        message
+            .content
+            .push(Content::image_url(path.to_str().unwrap()));
+        message
+            .content
+            .push(Content::image_url("https://example.com/cat.png"));
+        let inlined = inline_local_images(&message);
+        let urls: Vec<&str> = inlined
+            .content
+            .iter()
+            .filter_map(|content| match content {
+                Content::ImageUrl { image_url } => Some(image_url.url.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert!(urls[0].starts_with("data:image/png;base64,"));
+        assert_eq!(urls[0], urls[1]);
+        assert_eq!(urls[2], "https://example.com/cat.png");
+
+        let remote = Message::user("Hi", None);
+        assert!(matches!(inline_local_images(&remote), Cow::Borrowed(_)));
+        std::fs::remove_file(path).unwrap();
+    }
+}
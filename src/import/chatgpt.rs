@@ -0,0 +1,229 @@
+//! ChatGPT data exports (`conversations.json`).
+//!
+//! Each conversation stores its messages as a tree in `mapping`, since
+//! editing a prompt or regenerating an answer starts a new branch. The branch
+//! that was on screen ends at `current_node`; it is rebuilt by walking parent
+//! links from there back to the root.
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use serde::Deserialize;
+
+use super::ImportedConversation;
+use crate::models::Message;
+
+#[derive(Debug, Deserialize)]
+struct Conversation {
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    conversation_id: Option<String>,
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    create_time: Option<f64>,
+    #[serde(default)]
+    update_time: Option<f64>,
+    mapping: HashMap<String, Node>,
+    #[serde(default)]
+    current_node: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Node {
+    #[serde(default)]
+    message: Option<NodeMessage>,
+    #[serde(default)]
+    parent: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct NodeMessage {
+    author: Author,
+    content: NodeContent,
+    #[serde(default)]
+    metadata: serde_json::Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct Author {
+    role: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct NodeContent {
+    content_type: String,
+    #[serde(default)]
+    parts: Vec<serde_json::Value>,
+    /// Set for `code` content.
+    #[serde(default)]
+    text: Option<String>,
+    #[serde(default)]
+    language: Option<String>,
+}
+
+/// Whether `value` looks like a ChatGPT `conversations.json`.
+pub fn is_export(value: &serde_json::Value) -> bool {
+    value
+        .as_array()
+        .and_then(|items| items.first())
+        .is_some_and(|first| first.get("mapping").is_some())
+}
+
+pub fn parse(value: serde_json::Value) -> Result<Vec<ImportedConversation>> {
+    let conversations: Vec<Conversation> = serde_json::from_value(value)?;
+    Ok(conversations
+        .into_iter()
+        .enumerate()
+        .map(|(index, c)| convert(index, c))
+        .collect())
+}
+
+fn convert(index: usize, conversation: Conversation) -> ImportedConversation {
+    let source_id = conversation
+        .conversation_id
+        .or(conversation.id)
+        .unwrap_or_else(|| index.to_string());
+    let created_at = conversation.create_time.unwrap_or_default() as i64;
+    let messages: Vec<Message> = current_branch(&conversation.mapping, conversation.current_node)
+        .into_iter()
+        .filter_map(convert_message)
+        .collect();
+    ImportedConversation {
+        id: format!("chatgpt-{source_id}"),
+        title: conversation.title.unwrap_or_default(),
+        created_at,
+        updated_at: conversation
+            .update_time
+            .map(|t| t as i64)
+            .unwrap_or(created_at),
+        messages,
+    }
+}
+
+/// Messages on the path from the root to `current_node`, oldest first.
+/// Without a current node, any leaf of the tree is used.
+fn current_branch(
+    mapping: &HashMap<String, Node>,
+    current_node: Option<String>,
+) -> Vec<&NodeMessage> {
+    let mut node_id = current_node.or_else(|| {
+        let parents: Vec<&String> = mapping.values().filter_map(|n| n.parent.as_ref()).collect();
+        mapping.keys().find(|id| !parents.contains(id)).cloned()
+    });
+    let mut branch = Vec::new();
+    // Bounded by the mapping size in case of a malformed cycle.
+    for _ in 0..mapping.len() {
+        let Some(node) = node_id.as_ref().and_then(|id| mapping.get(id)) else {
+            break;
+        };
+        branch.extend(node.message.as_ref());
+        node_id = node.parent.clone();
+    }
+    branch.reverse();
+    branch
+}
+
+fn convert_message(message: &NodeMessage) -> Option<Message> {
+    let hidden = message
+        .metadata
+        .get("is_visually_hidden_from_conversation")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+    if hidden {
+        return None;
+    }
+    let text = match message.content.content_type.as_str() {
+        "text" | "multimodal_text" => message
+            .content
+            .parts
+            .iter()
+            .filter_map(|part| part.as_str())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        "code" => format!(
+            "```{}\n{}\n```",
+            message.content.language.as_deref().unwrap_or_default(),
+            message.content.text.as_deref().unwrap_or_default()
+        ),
+        _ => return None,
+    };
+    if text.trim().is_empty() {
+        return None;
+    }
+    match message.author.role.as_str() {
+        "user" => Some(Message::user(text, None)),
+        "assistant" => Some(Message::assistant(text)),
+        "system" => Some(Message::system(text)),
+        // Browsing and plugin output has no matching tool call to attach to.
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPORT: &str = r#"[{
+        "title": "Rust help",
+        "create_time": 1700000000.5,
+        "update_time": 1700000100.0,
+        "conversation_id": "abc",
+        "current_node": "n4",
+        "mapping": {
+            "n0": {"id": "n0", "message": null, "parent": null, "children": ["n1"]},
+            "n1": {"id": "n1", "parent": "n0", "children": ["n2", "n3"], "message": {
+                "author": {"role": "system"},
+                "content": {"content_type": "text", "parts": [""]},
+                "metadata": {"is_visually_hidden_from_conversation": true}}},
+            "n2": {"id": "n2", "parent": "n1", "children": [], "message": {
+                "author": {"role": "user"},
+                "content": {"content_type": "text", "parts": ["First draft"]}}},
+            "n3": {"id": "n3", "parent": "n1", "children": ["n4"], "message": {
+                "author": {"role": "user"},
+                "content": {"content_type": "text", "parts": ["What is a lifetime?"]}}},
+            "n4": {"id": "n4", "parent": "n3", "children": [], "message": {
+                "author": {"role": "assistant"},
+                "content": {"content_type": "text", "parts": ["A region of code."]}}}
+        }
+    }]"#;
+
+    #[test]
+    fn test_parse_follows_current_branch() {
+        let value: serde_json::Value = serde_json::from_str(EXPORT).unwrap();
+        assert!(is_export(&value));
+
+        let conversations = parse(value).unwrap();
+        assert_eq!(conversations.len(), 1);
+        let conversation = &conversations[0];
+        assert_eq!(conversation.id, "chatgpt-abc");
+        assert_eq!(conversation.title, "Rust help");
+        assert_eq!(conversation.created_at, 1700000000);
+        assert_eq!(conversation.updated_at, 1700000100);
+
+        let texts: Vec<(&str, &str)> = conversation
+            .messages
+            .iter()
+            .map(|m| (m.role.as_str(), m.text_content()[0].as_str()))
+            .collect();
+        assert_eq!(
+            texts,
+            vec![
+                ("user", "What is a lifetime?"),
+                ("assistant", "A region of code.")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_code_content_becomes_fenced_block() {
+        let message: NodeMessage = serde_json::from_str(
+            r#"{"author": {"role": "assistant"},
+                "content": {"content_type": "code", "language": "python", "text": "print(1)"}}"#,
+        )
+        .unwrap();
+        let message = convert_message(&message).unwrap();
+        assert_eq!(message.text_content()[0], "```python\nprint(1)\n```");
+    }
+}
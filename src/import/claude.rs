@@ -0,0 +1,289 @@
+//! Claude.ai data exports (`conversations.json`).
+//!
+//! Conversations hold a flat `chat_messages` list. Newer exports split each
+//! message into typed `content` blocks, which map onto [`Content`] variants;
+//! older ones only carry a plain `text` field, which is used as a fallback.
+
+use anyhow::Result;
+use serde::Deserialize;
+
+use super::ImportedConversation;
+use crate::models::{Content, Message};
+
+#[derive(Debug, Deserialize)]
+struct Conversation {
+    uuid: String,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    created_at: Option<String>,
+    #[serde(default)]
+    updated_at: Option<String>,
+    #[serde(default)]
+    chat_messages: Vec<ChatMessage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatMessage {
+    sender: String,
+    #[serde(default)]
+    text: String,
+    #[serde(default)]
+    content: Vec<Block>,
+    #[serde(default)]
+    attachments: Vec<Attachment>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Block {
+    Text {
+        text: String,
+    },
+    Thinking {
+        thinking: String,
+    },
+    ToolUse {
+        #[serde(default)]
+        id: Option<String>,
+        name: String,
+        #[serde(default)]
+        input: serde_json::Value,
+    },
+    ToolResult {
+        #[serde(default)]
+        tool_use_id: Option<String>,
+        #[serde(default)]
+        content: serde_json::Value,
+        #[serde(default)]
+        is_error: bool,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+struct Attachment {
+    #[serde(default)]
+    file_name: String,
+    #[serde(default)]
+    extracted_content: String,
+}
+
+/// Whether `value` looks like a Claude.ai `conversations.json`.
+pub fn is_export(value: &serde_json::Value) -> bool {
+    value
+        .as_array()
+        .and_then(|items| items.first())
+        .is_some_and(|first| first.get("chat_messages").is_some())
+}
+
+pub fn parse(value: serde_json::Value) -> Result<Vec<ImportedConversation>> {
+    let conversations: Vec<Conversation> = serde_json::from_value(value)?;
+    Ok(conversations.into_iter().map(convert).collect())
+}
+
+fn convert(conversation: Conversation) -> ImportedConversation {
+    let created_at = conversation
+        .created_at
+        .as_deref()
+        .and_then(parse_timestamp)
+        .unwrap_or_default();
+    ImportedConversation {
+        id: format!("claude-{}", conversation.uuid),
+        title: conversation.name.unwrap_or_default(),
+        created_at,
+        updated_at: conversation
+            .updated_at
+            .as_deref()
+            .and_then(parse_timestamp)
+            .unwrap_or(created_at),
+        messages: conversation
+            .chat_messages
+            .into_iter()
+            .filter_map(convert_message)
+            .collect(),
+    }
+}
+
+fn convert_message(message: ChatMessage) -> Option<Message> {
+    let role = match message.sender.as_str() {
+        "human" => "user",
+        "assistant" => "assistant",
+        _ => return None,
+    };
+    let mut content = Vec::new();
+    let mut reasoning = Vec::new();
+    for block in message.content {
+        match block {
+            Block::Text { text } if !text.is_empty() => content.push(Content::text(text)),
+            Block::Thinking { thinking } => reasoning.push(thinking),
+            Block::ToolUse { id, name, input } => {
+                content.push(Content::tool_use(id.unwrap_or_default(), name, input))
+            }
+            Block::ToolResult {
+                tool_use_id,
+                content: result,
+                is_error,
+            } => {
+                let tool_use_id = tool_use_id.unwrap_or_default();
+                let result = tool_result_text(result);
+                content.push(if is_error {
+                    Content::tool_result_error(tool_use_id, result)
+                } else {
+                    Content::tool_result(tool_use_id, result)
+                })
+            }
+            Block::Text { .. } | Block::Other => {}
+        }
+    }
+    if content.is_empty() && !message.text.is_empty() {
+        content.push(Content::text(message.text));
+    }
+    for attachment in message.attachments {
+        if !attachment.extracted_content.is_empty() {
+            content.push(Content::text(format!(
+                "Attachment `{}`:\n\n```\n{}\n```",
+                attachment.file_name, attachment.extracted_content
+            )));
+        }
+    }
+    if content.is_empty() {
+        return None;
+    }
+    Some(Message {
+        role: role.to_string(),
+        content,
+        tool_calls: None,
+        reasoning_content: (!reasoning.is_empty()).then(|| reasoning.join("\n\n")),
+        reasoning_signature: None,
+        tool_call_id: None,
+    })
+}
+
+/// Tool results are either a string or a list of content blocks; keep the
+/// text of the blocks.
+fn tool_result_text(value: serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(text) => text,
+        serde_json::Value::Array(blocks) => blocks
+            .iter()
+            .filter_map(|block| block.get("text").and_then(|t| t.as_str()))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        serde_json::Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+/// Parse an RFC 3339 timestamp such as `2024-03-01T12:34:56.789Z` into Unix
+/// seconds. Fractional seconds are dropped.
+fn parse_timestamp(text: &str) -> Option<i64> {
+    let (date, time) = text.split_once('T')?;
+    let mut date = date.splitn(3, '-').map(|p| p.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+
+    let offset_at = time.find(['Z', '+', '-']).unwrap_or(time.len());
+    let (clock, offset) = time.split_at(offset_at);
+    let mut clock = clock.splitn(3, ':');
+    let hour: i64 = clock.next()?.parse().ok()?;
+    let minute: i64 = clock.next()?.parse().ok()?;
+    let second: i64 = clock.next()?.split('.').next()?.parse().ok()?;
+    let offset_seconds = match offset.as_bytes().first() {
+        Some(sign @ (b'+' | b'-')) => {
+            let (h, m) = offset[1..].split_once(':')?;
+            let seconds = h.parse::<i64>().ok()? * 3600 + m.parse::<i64>().ok()? * 60;
+            if *sign == b'-' {
+                -seconds
+            } else {
+                seconds
+            }
+        }
+        _ => 0,
+    };
+
+    // Days since 1970-01-01 in the proleptic Gregorian calendar.
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    Some(days * 86_400 + hour * 3600 + minute * 60 + second - offset_seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPORT: &str = r#"[{
+        "uuid": "1234",
+        "name": "Weather lookup",
+        "created_at": "2024-03-01T12:00:00.123456Z",
+        "updated_at": "2024-03-01T12:05:00Z",
+        "chat_messages": [
+            {"sender": "human", "text": "Weather in Oslo?", "content": [
+                {"type": "text", "text": "Weather in Oslo?"}],
+             "attachments": [{"file_name": "notes.txt", "extracted_content": "Bring a coat"}]},
+            {"sender": "assistant", "text": "", "content": [
+                {"type": "thinking", "thinking": "Use the tool."},
+                {"type": "tool_use", "id": "tu_1", "name": "weather", "input": {"city": "Oslo"}},
+                {"type": "tool_result", "tool_use_id": "tu_1", "is_error": false,
+                 "content": [{"type": "text", "text": "-3C"}]},
+                {"type": "text", "text": "It is -3C."},
+                {"type": "image", "source": {}}]},
+            {"sender": "assistant", "text": "Legacy plain text"}
+        ]
+    }]"#;
+
+    #[test]
+    fn test_parse_maps_content_blocks() {
+        let value: serde_json::Value = serde_json::from_str(EXPORT).unwrap();
+        assert!(is_export(&value));
+
+        let conversations = parse(value).unwrap();
+        let conversation = &conversations[0];
+        assert_eq!(conversation.id, "claude-1234");
+        assert_eq!(conversation.title, "Weather lookup");
+        assert_eq!(conversation.created_at, 1_709_294_400);
+        assert_eq!(conversation.updated_at, 1_709_294_700);
+        assert_eq!(conversation.messages.len(), 3);
+
+        let user = &conversation.messages[0];
+        assert_eq!(user.role, "user");
+        assert_eq!(user.content.len(), 2);
+        assert!(user.text_content()[1].contains("Bring a coat"));
+
+        let assistant = &conversation.messages[1];
+        assert_eq!(
+            assistant.reasoning_content.as_deref(),
+            Some("Use the tool.")
+        );
+        assert!(matches!(
+            &assistant.content[0],
+            Content::ToolUse { id, name, .. } if id == "tu_1" && name == "weather"
+        ));
+        assert!(matches!(
+            &assistant.content[1],
+            Content::ToolResult { content, is_error: None, .. } if content == "-3C"
+        ));
+        assert_eq!(assistant.text_content()[1], "It is -3C.");
+
+        assert_eq!(
+            conversation.messages[2].text_content()[0],
+            "Legacy plain text"
+        );
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(parse_timestamp("2000-02-29T23:59:59Z"), Some(951_868_799));
+        assert_eq!(
+            parse_timestamp("2024-03-01T14:00:00+02:00"),
+            Some(1_709_294_400)
+        );
+        assert_eq!(parse_timestamp("yesterday"), None);
+    }
+}
@@ -0,0 +1,62 @@
+//! Importers for conversation exports from other chat apps.
+//!
+//! Each importer turns an export file into [`ImportedConversation`]s, which
+//! are then written to the local conversation store. Ids are derived from the
+//! source app's own conversation ids, so importing the same export twice
+//! updates the earlier copies instead of duplicating them.
+
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+
+use crate::{models::Message, storage::get_storage};
+
+mod chatgpt;
+mod claude;
+
+/// A conversation read from another app's export.
+#[derive(Debug, Clone)]
+pub struct ImportedConversation {
+    /// Local id, prefixed with the source app.
+    pub id: String,
+    pub title: String,
+    /// Unix timestamps, in seconds.
+    pub created_at: i64,
+    pub updated_at: i64,
+    pub messages: Vec<Message>,
+}
+
+/// Read the export at `path`, detecting its format, and store every
+/// conversation in it. Returns how many conversations were imported.
+pub fn import_file(path: &Path) -> Result<usize> {
+    let json = std::fs::read_to_string(path)?;
+    let conversations = parse_export(&json)?;
+    let storage = get_storage()?;
+    for conversation in &conversations {
+        storage.import_conversation(
+            &conversation.id,
+            &conversation.title,
+            conversation.created_at,
+            conversation.updated_at,
+            &conversation.messages,
+        )?;
+    }
+    Ok(conversations.len())
+}
+
+/// Parse an export file in any supported format. Conversations without any
+/// importable messages are dropped.
+fn parse_export(json: &str) -> Result<Vec<ImportedConversation>> {
+    let value: serde_json::Value = serde_json::from_str(json)?;
+    let conversations = if chatgpt::is_export(&value) {
+        chatgpt::parse(value)?
+    } else if claude::is_export(&value) {
+        claude::parse(value)?
+    } else {
+        return Err(anyhow!("Unrecognized conversation export format"));
+    };
+    Ok(conversations
+        .into_iter()
+        .filter(|c| !c.messages.is_empty())
+        .collect())
+}
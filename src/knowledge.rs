@@ -0,0 +1,381 @@
+//! The knowledge base: files and folders the user indexed, retrieved from
+//! to give the model context for each message.
+//!
+//! Files are split into passages, embedded with the model chosen in the
+//! settings and stored in the conversation database. Each message is
+//! embedded the same way and the closest passages are sent along with it,
+//! numbered so the reply can cite them.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::{
+    api::clients::embed,
+    config::Config,
+    pdf,
+    storage::{get_storage, KnowledgeChunk},
+};
+
+/// Longest passage, in characters.
+const CHUNK_CHARS: usize = 1500;
+/// Characters a passage shares with the one before it when a paragraph is
+/// too long for one passage.
+const CHUNK_OVERLAP: usize = 200;
+/// Passages sent with each message.
+const TOP_K: usize = 4;
+/// Most passages the knowledge base holds. Every one is scored against each
+/// message, so indexing stops adding files once it is full.
+const MAX_CHUNKS: usize = 5000;
+/// Larger files are skipped; they are rarely notes or documentation.
+const MAX_FILE_BYTES: u64 = 1024 * 1024;
+/// Folders never worth indexing.
+pub const SKIPPED_DIRS: [&str; 3] = ["node_modules", "target", "__pycache__"];
+
+/// A passage retrieved for a message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Passage {
+    pub source: String,
+    pub text: String,
+}
+
+/// What [`index`] stored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Indexed {
+    pub passages: usize,
+    /// Files left out, each with the reason.
+    pub skipped: Vec<String>,
+}
+
+/// Index every file at or under each of `paths` with the embedding model
+/// in `config`, replacing what was indexed from them before. Each path is
+/// stored in one go once its files are embedded. Files that fail to embed
+/// or don't fit in [`MAX_CHUNKS`] are skipped and reported; a path none of
+/// whose files could be indexed keeps what it held.
+pub async fn index(config: Config, paths: Vec<PathBuf>) -> Result<Indexed> {
+    let storage = get_storage()?;
+    let collections: Vec<String> = paths.iter().map(|p| p.display().to_string()).collect();
+    let mut held: usize = storage
+        .knowledge_collections()?
+        .iter()
+        .filter(|c| !collections.contains(&c.path))
+        .map(|c| c.chunks)
+        .sum();
+    let mut indexed = Indexed {
+        passages: 0,
+        skipped: vec![],
+    };
+    for (path, collection) in paths.into_iter().zip(collections) {
+        let files = tokio::task::spawn_blocking({
+            let path = path.clone();
+            move || read_files(&path)
+        })
+        .await?;
+        if files.is_empty() {
+            indexed.skipped.push(format!("{collection}: no text found"));
+            continue;
+        }
+        let mut chunks = Vec::new();
+        for (source, text) in files {
+            let texts = chunk_text(&text);
+            if held + chunks.len() + texts.len() > MAX_CHUNKS {
+                indexed
+                    .skipped
+                    .push(format!("{source}: knowledge base full"));
+                continue;
+            }
+            match embed(config.clone(), texts.clone()).await {
+                Ok(embeddings) => {
+                    chunks.extend(texts.into_iter().zip(embeddings).map(|(text, embedding)| {
+                        KnowledgeChunk {
+                            source: source.clone(),
+                            text,
+                            embedding,
+                        }
+                    }))
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to embed {}: {}", source, e);
+                    indexed.skipped.push(format!("{source}: {e}"));
+                }
+            }
+        }
+        if chunks.is_empty() {
+            continue;
+        }
+        storage.replace_knowledge(&collection, &config.embeddings.model, &chunks)?;
+        held += chunks.len();
+        indexed.passages += chunks.len();
+    }
+    Ok(indexed)
+}
+
+/// The passages closest to `query`. Empty, without asking the embedding
+/// model, while nothing is indexed with it.
+pub async fn retrieve(query: String) -> Result<Vec<Passage>> {
+    let config = Config::default();
+    let chunks = get_storage()?.knowledge_chunks(&config.embeddings.model, MAX_CHUNKS)?;
+    if chunks.is_empty() {
+        return Ok(vec![]);
+    }
+    let query = embed(config, vec![query])
+        .await?
+        .pop()
+        .context("No embedding was returned")?;
+    Ok(closest(&query, chunks, TOP_K))
+}
+
+/// The `k` chunks most similar to `query`, most similar first.
+fn closest(query: &[f32], chunks: Vec<KnowledgeChunk>, k: usize) -> Vec<Passage> {
+    let mut scored: Vec<(f32, KnowledgeChunk)> = chunks
+        .into_iter()
+        .map(|chunk| (cosine_similarity(query, &chunk.embedding), chunk))
+        .collect();
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+    scored
+        .into_iter()
+        .take(k)
+        .map(|(_, chunk)| Passage {
+            source: chunk.source,
+            text: chunk.text,
+        })
+        .collect()
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(a, b)| a * b).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 {
+        0.0
+    } else {
+        dot / norms
+    }
+}
+
+/// The text of every readable file at or under `path`, by file path.
+/// Unreadable, binary and large files are skipped.
+fn read_files(path: &Path) -> Vec<(String, String)> {
+    let mut files = Vec::new();
+    collect_files(path, &mut files);
+    files
+        .into_iter()
+        .filter_map(|file| Some((file.display().to_string(), read_text(&file)?)))
+        .collect()
+}
+
+/// `path` if it is a file, otherwise every file under it in name order,
+/// leaving out hidden entries and build folders.
+fn collect_files(path: &Path, files: &mut Vec<PathBuf>) {
+    if path.is_file() {
+        files.push(path.to_path_buf());
+        return;
+    }
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return;
+    };
+    let mut entries: Vec<PathBuf> = entries.flatten().map(|entry| entry.path()).collect();
+    entries.sort();
+    for entry in entries {
+        let name = entry.file_name().unwrap_or_default().to_string_lossy();
+        if name.starts_with('.') || SKIPPED_DIRS.contains(&name.as_ref()) {
+            continue;
+        }
+        collect_files(&entry, files);
+    }
+}
+
+/// The text of a PDF's pages or of a UTF-8 file.
+fn read_text(path: &Path) -> Option<String> {
+    if std::fs::metadata(path).ok()?.len() > MAX_FILE_BYTES {
+        return None;
+    }
+    let bytes = std::fs::read(path).ok()?;
+    let is_pdf = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("pdf"));
+    let text = if is_pdf {
+        pdf::page_texts(&bytes).ok()?.join("\n\n")
+    } else {
+        String::from_utf8(bytes).ok()?
+    };
+    (!text.trim().is_empty()).then_some(text)
+}
+
+/// Split `text` into passages of whole paragraphs, up to [`CHUNK_CHARS`]
+/// each. Longer paragraphs are cut into overlapping pieces.
+fn chunk_text(text: &str) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    for paragraph in text
+        .split("\n\n")
+        .map(str::trim)
+        .filter(|paragraph| !paragraph.is_empty())
+    {
+        if !current.is_empty()
+            && current.chars().count() + paragraph.chars().count() + 2 > CHUNK_CHARS
+        {
+            chunks.push(std::mem::take(&mut current));
+        }
+        let chars: Vec<char> = paragraph.chars().collect();
+        if chars.len() > CHUNK_CHARS {
+            let mut start = 0;
+            while start < chars.len() {
+                let end = (start + CHUNK_CHARS).min(chars.len());
+                chunks.push(chars[start..end].iter().collect());
+                if end == chars.len() {
+                    break;
+                }
+                start = end - CHUNK_OVERLAP;
+            }
+            continue;
+        }
+        if !current.is_empty() {
+            current.push_str("\n\n");
+        }
+        current.push_str(paragraph);
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+/// The system message giving the model `passages`, numbered from 1.
+pub fn context_prompt(passages: &[Passage]) -> String {
+    let mut prompt = String::from(
+        "Excerpts from the user's files that may help with their next message. \
+         Use them where they are relevant and cite each one you use by its \
+         number, like [1].",
+    );
+    for (index, passage) in passages.iter().enumerate() {
+        prompt.push_str(&format!(
+            "\n\n[{}] {}\n{}",
+            index + 1,
+            passage.source,
+            passage.text
+        ));
+    }
+    prompt
+}
+
+/// A markdown list of the passages `reply` cites, to append to the reply.
+/// Empty if it cites none.
+pub fn sources_section(reply: &str, passages: &[Passage]) -> String {
+    let cited: Vec<(usize, &Passage)> = passages
+        .iter()
+        .enumerate()
+        .map(|(index, p)| (index + 1, p))
+        .filter(|(number, _)| reply.contains(&format!("[{}]", number)))
+        .collect();
+    if cited.is_empty() {
+        return String::new();
+    }
+    let mut section = String::from("\n\n**Sources**\n");
+    for (number, passage) in cited {
+        section.push_str(&format!("\n- [{}] `{}`", number, passage.source));
+    }
+    section
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(source: &str, embedding: Vec<f32>) -> KnowledgeChunk {
+        KnowledgeChunk {
+            source: source.to_string(),
+            text: format!("From {}", source),
+            embedding,
+        }
+    }
+
+    #[test]
+    fn test_chunks_keep_paragraphs_together() {
+        let chunks = chunk_text("One.\n\nTwo.\n\n\n\nThree.");
+        assert_eq!(chunks, vec!["One.\n\nTwo.\n\nThree."]);
+
+        let paragraph = "a".repeat(CHUNK_CHARS - 4);
+        let chunks = chunk_text(&format!("{paragraph}\n\nShort."));
+        assert_eq!(chunks, vec![paragraph, "Short.".to_string()]);
+
+        // Paragraphs too long for one passage overlap where they are cut.
+        let long: String = (0..CHUNK_CHARS * 2 - CHUNK_OVERLAP)
+            .map(|i| char::from(b'a' + (i % 26) as u8))
+            .collect();
+        let chunks = chunk_text(&long);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].len(), CHUNK_CHARS);
+        assert!(chunks[1].starts_with(&long[CHUNK_CHARS - CHUNK_OVERLAP..CHUNK_CHARS]));
+        assert!(long.ends_with(&chunks[1]));
+    }
+
+    #[test]
+    fn test_closest_passages_come_first() {
+        let chunks = vec![
+            chunk("far.md", vec![-1.0, 0.0]),
+            chunk("near.md", vec![0.9, 0.1]),
+            chunk("mid.md", vec![0.5, 0.5]),
+            chunk("empty.md", vec![0.0, 0.0]),
+        ];
+        let sources: Vec<String> = closest(&[1.0, 0.0], chunks, 2)
+            .into_iter()
+            .map(|passage| passage.source)
+            .collect();
+        assert_eq!(sources, ["near.md", "mid.md"]);
+    }
+
+    #[test]
+    fn test_files_are_collected_without_hidden_and_binary_ones() {
+        let dir = std::env::temp_dir().join("ergon_test_knowledge");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("docs")).unwrap();
+        std::fs::create_dir_all(dir.join(".git")).unwrap();
+        std::fs::create_dir_all(dir.join("target")).unwrap();
+        std::fs::write(dir.join("README.md"), "# Readme").unwrap();
+        std::fs::write(dir.join("docs/guide.txt"), "Guide").unwrap();
+        std::fs::write(dir.join("docs/logo.png"), [0x89, b'P', b'N', b'G', 0xff]).unwrap();
+        std::fs::write(dir.join(".git/HEAD"), "ref").unwrap();
+        std::fs::write(dir.join("target/out.txt"), "built").unwrap();
+
+        let files: Vec<(String, String)> = read_files(&dir)
+            .into_iter()
+            .map(|(path, text)| (path.replace(&dir.display().to_string(), ""), text))
+            .collect();
+        let separator = std::path::MAIN_SEPARATOR;
+        assert_eq!(
+            files,
+            vec![
+                (format!("{separator}README.md"), "# Readme".to_string()),
+                (
+                    format!("{separator}docs{separator}guide.txt"),
+                    "Guide".to_string()
+                ),
+            ]
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_context_and_sources() {
+        let passages = vec![
+            Passage {
+                source: "/notes/a.md".to_string(),
+                text: "Alpha".to_string(),
+            },
+            Passage {
+                source: "/notes/b.md".to_string(),
+                text: "Beta".to_string(),
+            },
+        ];
+        let prompt = context_prompt(&passages);
+        assert!(prompt.ends_with("[1] /notes/a.md\nAlpha\n\n[2] /notes/b.md\nBeta"));
+
+        assert_eq!(
+            sources_section("It is beta [2].", &passages),
+            "\n\n**Sources**\n\n- [2] `/notes/b.md`"
+        );
+        assert_eq!(sources_section("No citations.", &passages), "");
+        assert_eq!(sources_section("Anything", &[]), "");
+    }
+}
@@ -1,8 +1,18 @@
 mod api;
 mod acp;
 mod config;
+mod export;
+mod hotkey;
+mod images;
+mod import;
+mod knowledge;
 mod mcp;
 mod models;
+mod pdf;
+mod storage;
+mod tokens;
+mod trace;
 mod ui;
+mod workspace;
 
-pub use ui::{init, subscription, update, view, Ergon};
+pub use ui::{init, subscription, title, update, view, Ergon};
@@ -5,13 +5,16 @@ pub fn main() -> iced::Result {
         .with_level(log::LevelFilter::Info)
         .init()
         .expect("Failed to initialize logger");
-    iced::application(ergon::init, ergon::update, ergon::view)
+    // A daemon rather than an application, for the quick-chat window next
+    // to the main one. Closing the main window quits.
+    iced::daemon(ergon::init, ergon::update, ergon::view)
+        .title(ergon::title)
         .subscription(ergon::subscription)
         .theme(theme)
         .font(iced_fonts::LUCIDE_FONT_BYTES)
         .run()
 }
 
-fn theme(state: &Ergon) -> iced::Theme {
+fn theme(state: &Ergon, _window: iced::window::Id) -> iced::Theme {
     state.settings.config.theme.clone()
 }
@@ -1,5 +1,5 @@
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use anyhow::Result;
 use async_trait::async_trait;
@@ -8,7 +8,10 @@ use rmcp::transport::auth::{
 };
 use tokio::sync::RwLock;
 
-use crate::config::{Config, McpAuthConfig, McpStreamableHttpConfig, StoredOAuthTokens};
+use crate::api::http;
+use crate::config::{
+    Config, McpAuthConfig, McpStreamableHttpConfig, ProxyConfig, StoredOAuthTokens,
+};
 use crate::mcp::oauth_callback;
 
 /// A credential store backed by the Ergon settings.json file.
@@ -231,6 +234,26 @@ impl CredentialStore for FileCredentialStore {
     }
 }
 
+/// Create an [`AuthorizationManager`] for `endpoint` whose discovery and
+/// token requests go through `proxy` when one is set.
+pub async fn authorization_manager(
+    endpoint: &str,
+    proxy: &ProxyConfig,
+) -> Result<AuthorizationManager> {
+    let mut auth_manager = AuthorizationManager::new(endpoint)
+        .await
+        .map_err(|e| anyhow::anyhow!("OAuth2 manager creation failed: {}", e))?;
+    if proxy.is_set() {
+        // Same timeout as rmcp's own client for these requests.
+        let builder = reqwest::Client::builder().timeout(Duration::from_secs(30));
+        let client = http::with_proxy(builder, Some(proxy)).build()?;
+        auth_manager
+            .with_client(client)
+            .map_err(|e| anyhow::anyhow!("OAuth2 manager creation failed: {}", e))?;
+    }
+    Ok(auth_manager)
+}
+
 /// Run the interactive OAuth2 authorization flow for the given server config.
 ///
 /// Performs metadata discovery, dynamic client registration, opens the user's
@@ -265,15 +288,13 @@ async fn run_oauth_authorization_inner(server_config: McpStreamableHttpConfig) -
     let server_name = server_config.name.clone();
     let endpoint = server_config.endpoint.clone();
 
-    log::info!(
+    tracing::info!(
         "MCP '{}': starting interactive OAuth2 authorization against {}",
         server_name,
         endpoint
     );
 
-    let mut auth_manager = AuthorizationManager::new(&endpoint)
-        .await
-        .map_err(|e| anyhow::anyhow!("OAuth2 manager creation failed: {}", e))?;
+    let mut auth_manager = authorization_manager(&endpoint, &Config::default().proxy).await?;
 
     auth_manager.set_credential_store(FileCredentialStore::new(&server_name));
 
@@ -284,6 +305,9 @@ async fn run_oauth_authorization_inner(server_config: McpStreamableHttpConfig) -
         .map_err(|e| anyhow::anyhow!("OAuth2 metadata discovery failed: {}", e))?;
     auth_manager.set_metadata(metadata);
 
+    // Listen for the redirect before registering a client and opening the
+    // browser, so a port already in use fails here.
+    let listener = oauth_callback::bind_oauth_callback(redirect_port).await?;
     let redirect_uri = format!("http://127.0.0.1:{}/callback", redirect_port);
 
     let scope_refs: Vec<&str> = scopes.iter().map(|s| s.as_str()).collect();
@@ -303,12 +327,7 @@ async fn run_oauth_authorization_inner(server_config: McpStreamableHttpConfig) -
 
     let auth_url = session.get_authorization_url().to_string();
 
-    // Start the callback listener first so the port is bound before the browser
-    // redirects back. tokio::spawn eagerly polls the future.
-    let port = redirect_port;
-    let callback_task = tokio::spawn(async move { oauth_callback::wait_for_oauth_callback(port).await });
-
-    log::info!(
+    tracing::info!(
         "MCP '{}': authorization URL: {}",
         server_name,
         auth_url
@@ -316,26 +335,24 @@ async fn run_oauth_authorization_inner(server_config: McpStreamableHttpConfig) -
 
     // Open the user's browser; log (but don't fail) if it can't be opened.
     if let Err(e) = open::that(&auth_url) {
-        log::error!(
+        tracing::error!(
             "MCP '{}': failed to open browser ({}). Please manually visit: {}",
             server_name,
             e,
             auth_url
         );
     } else {
-        log::info!("MCP '{}': opened browser for OAuth2 authorization", server_name);
+        tracing::info!("MCP '{}': opened browser for OAuth2 authorization", server_name);
     }
 
-    let callback_result = callback_task
-        .await
-        .map_err(|e| anyhow::anyhow!("OAuth callback task failed: {}", e))??;
+    let callback_result = oauth_callback::wait_for_oauth_callback(listener).await?;
 
     session
         .handle_callback(&callback_result.code, &callback_result.state)
         .await
         .map_err(|e| anyhow::anyhow!("OAuth2 token exchange failed: {}", e))?;
 
-    log::info!(
+    tracing::info!(
         "MCP '{}': OAuth2 authorization completed successfully",
         server_name
     );
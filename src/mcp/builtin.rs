@@ -0,0 +1,263 @@
+//! Tools built into Ergon and offered next to the MCP servers' tools,
+//! their names without a server prefix. The filesystem tools read only
+//! under the folders the user shared in the settings; the shell tool runs
+//! commands in the folder the user picked, each one approved first.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::{json, Value};
+
+use crate::models::{Function, Tool};
+
+pub const READ_FILE: &str = "read_file";
+pub const LIST_DIR: &str = "list_dir";
+/// Run by the chat view rather than [`call`], since its output is shown as
+/// it arrives.
+pub const RUN_COMMAND: &str = "run_command";
+/// Files longer than this are cut short when read.
+const MAX_FILE_BYTES: usize = 100_000;
+/// Entries listed per folder; larger folders are cut short.
+const MAX_ENTRIES: usize = 1000;
+
+/// Whether `name` is one of the built-in tools.
+pub fn is_builtin(name: &str) -> bool {
+    name == READ_FILE || name == LIST_DIR
+}
+
+/// The built-in tools, confined to `roots`. None without a root.
+pub fn tools(roots: &[PathBuf]) -> Vec<Tool> {
+    if roots.is_empty() {
+        return vec![];
+    }
+    let roots: Vec<String> = roots
+        .iter()
+        .map(|root| root.display().to_string())
+        .collect();
+    let scope = format!(
+        "Only paths under these folders are allowed: {}. Relative paths are \
+         resolved against the first.",
+        roots.join(", ")
+    );
+    let parameters = |description: &str| {
+        json!({
+            "type": "object",
+            "properties": {
+                "path": { "type": "string", "description": description }
+            },
+            "required": ["path"]
+        })
+    };
+    vec![
+        Tool::Function(Function {
+            name: READ_FILE.to_string(),
+            description: format!("Read a text file. {scope}"),
+            parameters: parameters("Path of the file to read"),
+        }),
+        Tool::Function(Function {
+            name: LIST_DIR.to_string(),
+            description: format!("List the entries of a folder, folders ending in `/`. {scope}"),
+            parameters: parameters("Path of the folder to list"),
+        }),
+    ]
+}
+
+/// The shell tool, running commands in `dir`.
+pub fn shell_tool(dir: &Path) -> Tool {
+    Tool::Function(Function {
+        name: RUN_COMMAND.to_string(),
+        description: format!(
+            "Run a shell command in {} and return what it writes to stdout and \
+             stderr. The user approves every command before it runs.",
+            dir.display()
+        ),
+        parameters: json!({
+            "type": "object",
+            "properties": {
+                "command": { "type": "string", "description": "The command line to run" }
+            },
+            "required": ["command"]
+        }),
+    })
+}
+
+/// The command line a call to [`RUN_COMMAND`] asks for.
+pub fn requested_command(arguments: &str) -> Option<String> {
+    let arguments: Value = serde_json::from_str(arguments).ok()?;
+    Some(arguments["command"].as_str()?.to_string())
+}
+
+/// Run the built-in tool `name` with its JSON `arguments`, confined to
+/// `roots`.
+pub fn call(roots: &[PathBuf], name: &str, arguments: &str) -> Result<String> {
+    let arguments: Value = serde_json::from_str(arguments).context("Failed to parse arguments")?;
+    let path = arguments["path"]
+        .as_str()
+        .ok_or_else(|| anyhow!("Missing the path"))?;
+    let path = resolve(roots, path)?;
+    match name {
+        READ_FILE => read_text(&path),
+        LIST_DIR => list_dir(&path),
+        _ => bail!("Unknown built-in tool {}", name),
+    }
+}
+
+/// `path`, relative to the first root unless absolute, with links
+/// followed. Paths outside every root are refused.
+fn resolve(roots: &[PathBuf], path: &str) -> Result<PathBuf> {
+    let first = roots
+        .first()
+        .ok_or_else(|| anyhow!("No folders are shared with the built-in tools"))?;
+    let resolved = first
+        .join(path)
+        .canonicalize()
+        .with_context(|| format!("{} does not exist", path))?;
+    let allowed = roots.iter().any(|root| {
+        root.canonicalize()
+            .is_ok_and(|root| resolved.starts_with(root))
+    });
+    if !allowed {
+        bail!("{} is outside the shared folders", path);
+    }
+    Ok(resolved)
+}
+
+/// The text of the UTF-8 file at `path`, cut short past
+/// [`MAX_FILE_BYTES`]. Only that much of the file is read.
+pub fn read_text(path: &Path) -> Result<String> {
+    if !path.is_file() {
+        bail!("{} is not a file", path.display());
+    }
+    let mut bytes = Vec::new();
+    File::open(path)?
+        .take(MAX_FILE_BYTES as u64 + 1)
+        .read_to_end(&mut bytes)?;
+    let truncated = bytes.len() > MAX_FILE_BYTES;
+    bytes.truncate(MAX_FILE_BYTES);
+    let text = match String::from_utf8(bytes) {
+        Ok(text) => text,
+        // A character cut in two at the limit is dropped.
+        Err(e) if truncated && e.utf8_error().error_len().is_none() => {
+            let end = e.utf8_error().valid_up_to();
+            let mut bytes = e.into_bytes();
+            bytes.truncate(end);
+            String::from_utf8(bytes)?
+        }
+        Err(_) => bail!("{} is not a text file", path.display()),
+    };
+    if !truncated {
+        return Ok(text);
+    }
+    Ok(format!(
+        "{}\n[Truncated to the first {} of {} bytes.]",
+        text,
+        text.len(),
+        std::fs::metadata(path)?.len()
+    ))
+}
+
+/// The names in the folder at `path`, one per line and sorted, folders
+/// ending in `/`.
+fn list_dir(path: &Path) -> Result<String> {
+    if !path.is_dir() {
+        bail!("{} is not a folder", path.display());
+    }
+    let mut names: Vec<String> = std::fs::read_dir(path)?
+        .flatten()
+        .map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if entry.path().is_dir() {
+                format!("{name}/")
+            } else {
+                name
+            }
+        })
+        .collect();
+    names.sort();
+    let total = names.len();
+    names.truncate(MAX_ENTRIES);
+    let mut listing = names.join("\n");
+    if total > MAX_ENTRIES {
+        listing.push_str(&format!(
+            "\n[Only the first {} of {} entries are listed.]",
+            MAX_ENTRIES, total
+        ));
+    }
+    Ok(listing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tools_stay_inside_their_roots() {
+        let dir = std::env::temp_dir().join("ergon_test_builtin_tools");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("shared/docs")).unwrap();
+        std::fs::write(dir.join("shared/notes.md"), "# Notes").unwrap();
+        std::fs::write(dir.join("secret.txt"), "hunter2").unwrap();
+        let roots = vec![dir.join("shared")];
+        assert!(tools(&[]).is_empty());
+        assert_eq!(tools(&roots).len(), 2);
+
+        let args = |path: &str| json!({ "path": path }).to_string();
+        assert_eq!(
+            call(&roots, LIST_DIR, &args(".")).unwrap(),
+            "docs/\nnotes.md"
+        );
+        assert_eq!(
+            call(&roots, READ_FILE, &args("notes.md")).unwrap(),
+            "# Notes"
+        );
+        let absolute = dir.join("shared/notes.md").display().to_string();
+        assert_eq!(
+            call(&roots, READ_FILE, &args(&absolute)).unwrap(),
+            "# Notes"
+        );
+
+        assert!(call(&roots, READ_FILE, &args("../secret.txt")).is_err());
+        let outside = dir.join("secret.txt").display().to_string();
+        assert!(call(&roots, READ_FILE, &args(&outside)).is_err());
+        assert!(call(&roots, READ_FILE, &args("docs")).is_err());
+        assert!(call(&roots, LIST_DIR, &args("notes.md")).is_err());
+        assert!(call(&[], READ_FILE, &args("notes.md")).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_long_files_are_cut_short() {
+        let dir = std::env::temp_dir().join("ergon_test_builtin_long_file");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        // The limit falls inside the two-byte "é".
+        let text = format!("{}é{}", "a".repeat(MAX_FILE_BYTES - 1), "b".repeat(10));
+        std::fs::write(dir.join("long.txt"), &text).unwrap();
+        let read = read_text(&dir.join("long.txt")).unwrap();
+        let (start, note) = read.split_once('\n').unwrap();
+        assert_eq!(start, "a".repeat(MAX_FILE_BYTES - 1));
+        assert_eq!(
+            note,
+            format!(
+                "[Truncated to the first {} of {} bytes.]",
+                MAX_FILE_BYTES - 1,
+                text.len()
+            )
+        );
+
+        std::fs::write(dir.join("binary.bin"), [0xff, 0xfe, 0x00]).unwrap();
+        assert!(read_text(&dir.join("binary.bin")).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_requested_command() {
+        assert_eq!(
+            requested_command(r#"{"command": "cargo test"}"#).as_deref(),
+            Some("cargo test")
+        );
+        assert_eq!(requested_command(r#"{"path": "."}"#), None);
+    }
+}
@@ -0,0 +1,598 @@
+//! What Ergon does when an MCP server asks something of it.
+//!
+//! Servers may ask for a completion from the user's model
+//! (`sampling/createMessage`) or for details only the user can give
+//! (`elicitation/create`). Each request is published through the
+//! [`ToolManager`](super::ToolManager) and waits for the user in the chat.
+//! An approved sampling request carries the model the user had selected, and
+//! the completion runs through that model's provider; the server never
+//! learns more of the conversation than the messages it sent. Its usage is
+//! recorded and counts towards the monthly budget, which the chat checks
+//! before approving. An elicitation is shown as a form built from the
+//! schema the server sent.
+//!
+//! Progress notifications of tool calls are passed on too. Tool calls use
+//! their call id as progress token, see [`super::call_tool`]. Messages a
+//! server logs join its log in the tool manager.
+
+use std::sync::{Arc, Mutex};
+
+use rmcp::{
+    model::{
+        ClientCapabilities, ClientInfo, CreateElicitationRequestParams, CreateElicitationResult,
+        CreateMessageRequestParams, CreateMessageResult, ElicitationAction, Implementation,
+        JsonObject, LoggingLevel, LoggingMessageNotificationParam, NumberOrString,
+        ProgressNotificationParam, Role, SamplingMessage, SamplingMessageContent,
+    },
+    service::{NotificationContext, RequestContext},
+    ClientHandler, ErrorData, RoleClient,
+};
+use serde_json::Value;
+use tokio::sync::oneshot;
+
+use super::{get_tool_manager, LogLevel, ServerLog};
+use crate::config::McpTimeoutConfig;
+use crate::models::{CompletionDelta, CompletionRequest, Message, ModelInfo, TokenUsage};
+use crate::storage::get_storage;
+
+/// Answers requests from the MCP server named `server`.
+#[derive(Debug, Clone)]
+pub struct Handler {
+    server: String,
+    timeouts: McpTimeoutConfig,
+}
+
+impl Handler {
+    pub fn new(server: impl Into<String>, timeouts: McpTimeoutConfig) -> Self {
+        Self {
+            server: server.into(),
+            timeouts,
+        }
+    }
+
+    /// The timeouts configured for the server.
+    pub fn timeouts(&self) -> McpTimeoutConfig {
+        self.timeouts
+    }
+}
+
+impl ClientHandler for Handler {
+    async fn create_message(
+        &self,
+        params: CreateMessageRequestParams,
+        _context: RequestContext<RoleClient>,
+    ) -> Result<CreateMessageResult, ErrorData> {
+        let (request, answer) = SamplingRequest::new(
+            self.server.clone(),
+            params.system_prompt.clone(),
+            sampling_messages(&params.messages),
+            params.max_tokens,
+        );
+        if !get_tool_manager().request_sampling(request) {
+            return Err(ErrorData::internal_error(
+                "No chat is open to approve the request",
+                None,
+            ));
+        }
+        let Ok(Some(model)) = answer.await else {
+            tracing::info!("MCP '{}': sampling request denied", self.server);
+            return Err(ErrorData::invalid_request(
+                "The user denied the sampling request",
+                None,
+            ));
+        };
+        tracing::info!(
+            "MCP '{}': sampling request approved for {}",
+            self.server,
+            model.name
+        );
+        let (text, usage) = complete(&model, &params)
+            .await
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        if let Some(usage) = usage {
+            record_usage(format!("MCP {}", self.server), model.name.clone(), usage).await;
+        }
+        Ok(CreateMessageResult::new(
+            SamplingMessage::new(Role::Assistant, SamplingMessageContent::text(text)),
+            model.id,
+        )
+        .with_stop_reason(CreateMessageResult::STOP_REASON_END_TURN))
+    }
+
+    async fn create_elicitation(
+        &self,
+        request: CreateElicitationRequestParams,
+        _context: RequestContext<RoleClient>,
+    ) -> Result<CreateElicitationResult, ErrorData> {
+        let CreateElicitationRequestParams::FormElicitationParams {
+            message,
+            requested_schema,
+            ..
+        } = request
+        else {
+            tracing::warn!(
+                "MCP '{}': declined an elicitation without a form",
+                self.server
+            );
+            return Ok(CreateElicitationResult::new(ElicitationAction::Decline));
+        };
+        let schema = serde_json::to_value(&requested_schema)
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let (request, answer) =
+            ElicitationRequest::new(self.server.clone(), message, form_fields(&schema));
+        if !get_tool_manager().request_elicitation(request) {
+            return Ok(CreateElicitationResult::new(ElicitationAction::Decline));
+        }
+        Ok(match answer.await {
+            Ok(ElicitationAnswer::Accept(content)) => {
+                CreateElicitationResult::new(ElicitationAction::Accept)
+                    .with_content(Value::Object(content))
+            }
+            Ok(ElicitationAnswer::Decline) => {
+                CreateElicitationResult::new(ElicitationAction::Decline)
+            }
+            // A chat that went away cancels the request.
+            Ok(ElicitationAnswer::Cancel) | Err(_) => {
+                CreateElicitationResult::new(ElicitationAction::Cancel)
+            }
+        })
+    }
+
+    async fn on_progress(
+        &self,
+        params: ProgressNotificationParam,
+        _context: NotificationContext<RoleClient>,
+    ) {
+        let call_id = match params.progress_token.0 {
+            NumberOrString::String(id) => id.to_string(),
+            NumberOrString::Number(id) => id.to_string(),
+        };
+        get_tool_manager().report_progress(ToolProgress {
+            call_id,
+            progress: params.progress,
+            total: params.total,
+            message: params.message,
+        });
+    }
+
+    async fn on_logging_message(
+        &self,
+        params: LoggingMessageNotificationParam,
+        _context: NotificationContext<RoleClient>,
+    ) {
+        let level = match params.level {
+            LoggingLevel::Debug => LogLevel::Debug,
+            LoggingLevel::Info | LoggingLevel::Notice => LogLevel::Info,
+            LoggingLevel::Warning => LogLevel::Warning,
+            _ => LogLevel::Error,
+        };
+        let message = match params.data {
+            Value::String(message) => message,
+            data => data.to_string(),
+        };
+        get_tool_manager().add_log(
+            &self.server,
+            ServerLog {
+                level,
+                logger: params.logger,
+                message,
+            },
+        );
+    }
+
+    fn get_info(&self) -> ClientInfo {
+        ClientInfo::new(
+            ClientCapabilities::builder()
+                .enable_sampling()
+                .enable_elicitation()
+                .build(),
+            Implementation::from_build_env(),
+        )
+    }
+}
+
+/// A `sampling/createMessage` request from an MCP server, waiting for the
+/// user to approve or deny it.
+#[derive(Debug, Clone)]
+pub struct SamplingRequest {
+    /// Name of the server asking.
+    pub server: String,
+    pub system_prompt: Option<String>,
+    /// The messages to complete, text only.
+    pub messages: Vec<Message>,
+    pub max_tokens: u32,
+    responder: Responder<Option<ModelInfo>>,
+}
+
+impl SamplingRequest {
+    /// A request and the receiver its answer arrives on: the model to
+    /// complete it with, or `None` if it was denied.
+    pub fn new(
+        server: String,
+        system_prompt: Option<String>,
+        messages: Vec<Message>,
+        max_tokens: u32,
+    ) -> (Self, oneshot::Receiver<Option<ModelInfo>>) {
+        let (responder, answer) = Responder::new();
+        let request = Self {
+            server,
+            system_prompt,
+            messages,
+            max_tokens,
+            responder,
+        };
+        (request, answer)
+    }
+
+    /// Let `model` complete the request.
+    pub fn approve(&self, model: ModelInfo) {
+        self.responder.send(Some(model));
+    }
+
+    pub fn deny(&self) {
+        self.responder.send(None);
+    }
+}
+
+/// How far a tool call has got, as last reported by its server.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolProgress {
+    /// Id of the tool call reported on.
+    pub call_id: String,
+    pub progress: f64,
+    /// What `progress` counts up to, if the server knows.
+    pub total: Option<f64>,
+    pub message: Option<String>,
+}
+
+impl ToolProgress {
+    /// The server's message, or how far the call has got.
+    pub fn status(&self) -> String {
+        match (&self.message, self.total) {
+            (Some(message), _) => message.clone(),
+            (None, Some(total)) => format!("{} of {}", self.progress, total),
+            (None, None) => format!("{} done", self.progress),
+        }
+    }
+}
+
+/// An `elicitation/create` request from an MCP server: a form for the user
+/// to fill in, or to decline.
+#[derive(Debug, Clone)]
+pub struct ElicitationRequest {
+    /// Name of the server asking.
+    pub server: String,
+    /// What the server needs the details for.
+    pub message: String,
+    pub fields: Vec<ElicitationField>,
+    responder: Responder<ElicitationAnswer>,
+}
+
+/// One value an elicitation asks for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ElicitationField {
+    /// Key of the value in the answer.
+    pub name: String,
+    /// What the form shows for it: its title if it has one, else its name.
+    pub label: String,
+    pub description: Option<String>,
+    pub required: bool,
+    pub kind: FieldKind,
+    /// The value the form starts with, as typed.
+    pub default: String,
+}
+
+/// What kind of value a field takes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldKind {
+    Text,
+    Number,
+    Integer,
+    /// Shown as a checkbox; its value is `"true"` or `"false"`.
+    Boolean,
+    /// One of these values.
+    Choice(Vec<String>),
+}
+
+/// How the user answered an elicitation.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ElicitationAnswer {
+    Accept(JsonObject),
+    Decline,
+    Cancel,
+}
+
+impl ElicitationRequest {
+    /// A request and the receiver the user's answer arrives on.
+    pub fn new(
+        server: String,
+        message: String,
+        fields: Vec<ElicitationField>,
+    ) -> (Self, oneshot::Receiver<ElicitationAnswer>) {
+        let (responder, answer) = Responder::new();
+        let request = Self {
+            server,
+            message,
+            fields,
+            responder,
+        };
+        (request, answer)
+    }
+
+    /// Send `values`, one per field as typed. Empty optional fields are
+    /// left out. Fails, without sending anything, if a required field is
+    /// empty or a number does not parse.
+    pub fn accept(&self, values: &[String]) -> Result<(), String> {
+        let mut content = JsonObject::new();
+        for (field, value) in self.fields.iter().zip(values) {
+            let value = value.trim();
+            if value.is_empty() {
+                if field.required {
+                    return Err(format!("{} is required", field.label));
+                }
+                continue;
+            }
+            let value = match field.kind {
+                FieldKind::Text | FieldKind::Choice(_) => Value::String(value.to_string()),
+                FieldKind::Boolean => Value::Bool(value == "true"),
+                FieldKind::Integer => value
+                    .parse::<i64>()
+                    .map(Value::from)
+                    .map_err(|_| format!("{} must be a whole number", field.label))?,
+                FieldKind::Number => value
+                    .parse::<f64>()
+                    .map(Value::from)
+                    .map_err(|_| format!("{} must be a number", field.label))?,
+            };
+            content.insert(field.name.clone(), value);
+        }
+        self.responder.send(ElicitationAnswer::Accept(content));
+        Ok(())
+    }
+
+    /// Refuse to give the details; the server carries on without them.
+    pub fn decline(&self) {
+        self.responder.send(ElicitationAnswer::Decline);
+    }
+
+    /// Stop whatever the server was doing that needed the details.
+    pub fn cancel(&self) {
+        self.responder.send(ElicitationAnswer::Cancel);
+    }
+}
+
+/// The fields of an elicitation form from its JSON schema, in the order of
+/// its properties.
+fn form_fields(schema: &Value) -> Vec<ElicitationField> {
+    let required: Vec<&str> = schema["required"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .collect();
+    let Some(properties) = schema["properties"].as_object() else {
+        return vec![];
+    };
+    properties
+        .iter()
+        .map(|(name, property)| {
+            let string = |key: &str| property[key].as_str().map(str::to_string);
+            let choices: Vec<String> =
+                match (property["enum"].as_array(), property["oneOf"].as_array()) {
+                    (Some(values), _) => values
+                        .iter()
+                        .filter_map(Value::as_str)
+                        .map(str::to_string)
+                        .collect(),
+                    (None, Some(options)) => options
+                        .iter()
+                        .filter_map(|option| option["const"].as_str())
+                        .map(str::to_string)
+                        .collect(),
+                    (None, None) => vec![],
+                };
+            let kind = match property["type"].as_str() {
+                _ if !choices.is_empty() => FieldKind::Choice(choices),
+                Some("boolean") => FieldKind::Boolean,
+                Some("integer") => FieldKind::Integer,
+                Some("number") => FieldKind::Number,
+                _ => FieldKind::Text,
+            };
+            let default = match &property["default"] {
+                Value::String(value) => value.clone(),
+                Value::Null if kind == FieldKind::Boolean => "false".to_string(),
+                Value::Null => String::new(),
+                value => value.to_string(),
+            };
+            ElicitationField {
+                name: name.clone(),
+                label: string("title").unwrap_or_else(|| name.clone()),
+                description: string("description"),
+                required: required.contains(&name.as_str()),
+                kind,
+                default,
+            }
+        })
+        .collect()
+}
+
+/// Sends the user's answer back to the handler waiting for it. Clones share
+/// the channel, so only the first answer is delivered.
+#[derive(Debug)]
+struct Responder<T>(Arc<Mutex<Option<oneshot::Sender<T>>>>);
+
+impl<T> Clone for Responder<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> Responder<T> {
+    fn new() -> (Self, oneshot::Receiver<T>) {
+        let (sender, receiver) = oneshot::channel();
+        (Self(Arc::new(Mutex::new(Some(sender)))), receiver)
+    }
+
+    fn send(&self, value: T) {
+        let sender = match self.0.lock() {
+            Ok(mut sender) => sender.take(),
+            Err(e) => {
+                tracing::error!("Failed to answer MCP request: {}", e);
+                return;
+            }
+        };
+        // The handler is gone if the server dropped the request.
+        if let Some(sender) = sender {
+            let _ = sender.send(value);
+        }
+    }
+}
+
+/// The text of `messages` as Ergon messages. Images, audio and tool content
+/// are left out.
+fn sampling_messages(messages: &[SamplingMessage]) -> Vec<Message> {
+    messages
+        .iter()
+        .map(|message| {
+            let text = message
+                .content
+                .clone()
+                .into_vec()
+                .into_iter()
+                .filter_map(|content| match content {
+                    SamplingMessageContent::Text(text) => Some(text.text),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            match message.role {
+                Role::User => Message::user(text, None),
+                Role::Assistant => Message::assistant(text),
+            }
+        })
+        .collect()
+}
+
+/// The text `model` replies to the request in `params`, and the tokens it
+/// used if the provider reported them.
+async fn complete(
+    model: &ModelInfo,
+    params: &CreateMessageRequestParams,
+) -> anyhow::Result<(String, Option<TokenUsage>)> {
+    let client = model.client.provider()?;
+    let messages = params
+        .system_prompt
+        .iter()
+        .map(Message::system)
+        .chain(sampling_messages(&params.messages))
+        .collect();
+    let request = CompletionRequest {
+        model: model.id.clone(),
+        messages,
+        temperature: params.temperature,
+        top_p: None,
+        max_tokens: Some(params.max_tokens),
+        tools: None,
+        reasoning_effort: None,
+        stop: params.stop_sequences.clone().unwrap_or_default(),
+        seed: None,
+        presence_penalty: None,
+        frequency_penalty: None,
+    };
+    let (mut text, mut usage) = (String::new(), None);
+    for delta in client.complete_message(request).await?.into_deltas() {
+        match delta {
+            CompletionDelta::Text(chunk) => text.push_str(&chunk),
+            CompletionDelta::Usage(reported) => usage = Some(reported),
+            _ => {}
+        }
+    }
+    Ok((text, usage))
+}
+
+/// Store the usage of a sampling request under `source` and tell the chats,
+/// so it counts towards this month's spend. Failures are logged.
+async fn record_usage(source: String, model: String, usage: TokenUsage) {
+    let result = tokio::task::spawn_blocking(move || {
+        get_storage().and_then(|storage| storage.record_usage(&source, Some(&model), &usage))
+    })
+    .await;
+    match result {
+        Ok(Ok(())) => get_tool_manager().report_usage(),
+        Ok(Err(e)) => tracing::error!("Failed to record sampling usage: {}", e),
+        Err(e) => tracing::error!("Recording sampling usage panicked: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_the_first_answer_is_delivered() {
+        let (request, mut answer) = SamplingRequest::new("notes".to_string(), None, vec![], 100);
+        let copy = request.clone();
+        copy.deny();
+        request.approve(ModelInfo {
+            name: "gpt-4o-mini".to_string(),
+            id: "gpt-4o-mini".to_string(),
+            client: crate::models::Clients::OpenAI,
+            capabilities: crate::models::ModelCapabilities::default(),
+        });
+        assert_eq!(answer.try_recv().unwrap(), None);
+    }
+
+    #[test]
+    fn test_elicitation_form_from_schema() {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "email": { "type": "string", "title": "Email", "format": "email" },
+                "plan": { "type": "string", "enum": ["free", "pro"], "default": "free" },
+                "seats": { "type": "integer", "description": "How many people" },
+                "updates": { "type": "boolean" }
+            },
+            "required": ["email", "seats"]
+        });
+        let fields = form_fields(&schema);
+        let kinds: Vec<_> = fields.iter().map(|f| f.kind.clone()).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                FieldKind::Text,
+                FieldKind::Choice(vec!["free".to_string(), "pro".to_string()]),
+                FieldKind::Integer,
+                FieldKind::Boolean,
+            ]
+        );
+        assert_eq!(fields[0].label, "Email");
+        assert_eq!(fields[1].default, "free");
+        assert_eq!(fields[3].default, "false");
+
+        let (request, mut answer) =
+            ElicitationRequest::new("billing".to_string(), "Upgrade".to_string(), fields);
+        let values = |email: &str, seats: &str| {
+            vec![
+                email.to_string(),
+                "pro".to_string(),
+                seats.to_string(),
+                "true".to_string(),
+            ]
+        };
+        assert!(request.accept(&values("", "3")).is_err());
+        assert!(request.accept(&values("me@example.com", "three")).is_err());
+        request.accept(&values("me@example.com", "3")).unwrap();
+        let ElicitationAnswer::Accept(content) = answer.try_recv().unwrap() else {
+            panic!("the form was accepted");
+        };
+        assert_eq!(
+            Value::Object(content),
+            serde_json::json!({
+                "email": "me@example.com",
+                "plan": "pro",
+                "seats": 3,
+                "updates": true
+            })
+        );
+    }
+}
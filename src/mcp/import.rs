@@ -0,0 +1,196 @@
+//! Importing MCP servers from Claude Desktop's `claude_desktop_config.json`.
+//!
+//! Servers live under `mcpServers`, keyed by name. Entries with a `command`
+//! are stdio servers; entries with a `url` are remote servers, which Ergon
+//! reaches over streamable HTTP, or over SSE when their `type` is `sse`. A
+//! bearer token in an `Authorization` header becomes
+//! [`McpAuthConfig::BearerToken`].
+
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+
+use crate::config::{
+    McpAuthConfig, McpConfig, McpSseConfig, McpStdioConfig, McpStreamableHttpConfig,
+};
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DesktopConfig {
+    mcp_servers: Option<BTreeMap<String, Server>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Server {
+    #[serde(default)]
+    command: Option<String>,
+    #[serde(default)]
+    args: Vec<String>,
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+    #[serde(default)]
+    cwd: Option<String>,
+    #[serde(default)]
+    url: Option<String>,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    /// `sse` for servers speaking the legacy SSE transport.
+    #[serde(default, rename = "type")]
+    transport: Option<String>,
+}
+
+/// Parse a Claude Desktop config into one [`McpConfig`] per server, in name
+/// order. Servers with neither a `command` nor a `url` are skipped.
+pub fn parse_claude_desktop_config(json: &str) -> Result<Vec<McpConfig>> {
+    let config: DesktopConfig = serde_json::from_str(json)?;
+    let servers = config
+        .mcp_servers
+        .ok_or_else(|| anyhow!("No \"mcpServers\" section found"))?;
+    Ok(servers
+        .into_iter()
+        .filter_map(|(name, server)| convert(name, server))
+        .collect())
+}
+
+fn convert(name: String, server: Server) -> Option<McpConfig> {
+    if let Some(command) = server.command {
+        return Some(McpConfig::Stdio(McpStdioConfig {
+            name,
+            command,
+            args: server.args,
+            env: server.env.into_iter().collect(),
+            cwd: server.cwd,
+            ..McpStdioConfig::default()
+        }));
+    }
+    let endpoint = server.url?;
+    let token = server
+        .headers
+        .iter()
+        .find(|(header, _)| header.eq_ignore_ascii_case("authorization"))
+        .and_then(|(_, value)| value.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string());
+    if server.transport.as_deref() == Some("sse") {
+        return Some(McpConfig::Sse(McpSseConfig {
+            name,
+            endpoint,
+            bearer_token: token.unwrap_or_default(),
+            ..McpSseConfig::default()
+        }));
+    }
+    let auth = token.map_or(McpAuthConfig::None, |token| McpAuthConfig::BearerToken {
+        token,
+    });
+    Some(McpConfig::StreamableHttp(McpStreamableHttpConfig {
+        name,
+        endpoint,
+        auth,
+        ..McpStreamableHttpConfig::default()
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_stdio_and_remote_servers() {
+        let json = r#"{
+            "globalShortcut": "Ctrl+Space",
+            "mcpServers": {
+                "filesystem": {
+                    "command": "npx",
+                    "args": ["-y", "@modelcontextprotocol/server-filesystem", "/tmp"]
+                },
+                "github": {
+                    "command": "docker",
+                    "args": ["run", "-i", "--rm", "ghcr.io/github/github-mcp-server"],
+                    "env": { "GITHUB_PERSONAL_ACCESS_TOKEN": "ghp-secret" }
+                },
+                "linear": {
+                    "url": "https://mcp.linear.app/mcp",
+                    "headers": { "Authorization": "Bearer lin-token" }
+                },
+                "docs": { "url": "https://docs.example.com/mcp" },
+                "broken": { "disabled": true }
+            }
+        }"#;
+
+        let configs = parse_claude_desktop_config(json).unwrap();
+
+        assert_eq!(
+            configs,
+            vec![
+                McpConfig::StreamableHttp(McpStreamableHttpConfig {
+                    name: "docs".to_string(),
+                    endpoint: "https://docs.example.com/mcp".to_string(),
+                    auth: McpAuthConfig::None,
+                    ..McpStreamableHttpConfig::default()
+                }),
+                McpConfig::Stdio(McpStdioConfig {
+                    name: "filesystem".to_string(),
+                    command: "npx".to_string(),
+                    args: vec![
+                        "-y".to_string(),
+                        "@modelcontextprotocol/server-filesystem".to_string(),
+                        "/tmp".to_string(),
+                    ],
+                    ..McpStdioConfig::default()
+                }),
+                McpConfig::Stdio(McpStdioConfig {
+                    name: "github".to_string(),
+                    command: "docker".to_string(),
+                    args: vec![
+                        "run".to_string(),
+                        "-i".to_string(),
+                        "--rm".to_string(),
+                        "ghcr.io/github/github-mcp-server".to_string(),
+                    ],
+                    env: vec![(
+                        "GITHUB_PERSONAL_ACCESS_TOKEN".to_string(),
+                        "ghp-secret".to_string()
+                    )],
+                    ..McpStdioConfig::default()
+                }),
+                McpConfig::StreamableHttp(McpStreamableHttpConfig {
+                    name: "linear".to_string(),
+                    endpoint: "https://mcp.linear.app/mcp".to_string(),
+                    auth: McpAuthConfig::BearerToken {
+                        token: "lin-token".to_string(),
+                    },
+                    ..McpStreamableHttpConfig::default()
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_sse_server() {
+        let json = r#"{
+            "mcpServers": {
+                "legacy": {
+                    "type": "sse",
+                    "url": "https://legacy.example.com/sse",
+                    "headers": { "authorization": "Bearer sse-token" }
+                }
+            }
+        }"#;
+
+        assert_eq!(
+            parse_claude_desktop_config(json).unwrap(),
+            vec![McpConfig::Sse(McpSseConfig {
+                name: "legacy".to_string(),
+                endpoint: "https://legacy.example.com/sse".to_string(),
+                bearer_token: "sse-token".to_string(),
+                ..McpSseConfig::default()
+            })]
+        );
+    }
+
+    #[test]
+    fn test_parse_rejects_config_without_servers() {
+        assert!(parse_claude_desktop_config(r#"{"globalShortcut": ""}"#).is_err());
+        assert!(parse_claude_desktop_config("not json").is_err());
+    }
+}
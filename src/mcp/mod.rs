@@ -1,92 +1,451 @@
 pub mod auth;
+pub mod builtin;
+pub mod handler;
+pub mod import;
 pub mod oauth_callback;
+pub mod sse;
 
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    fmt,
+    path::PathBuf,
+    process::Stdio,
     sync::{Arc, RwLock},
+    time::Duration,
 };
 
-use crate::config::{McpAuthConfig, McpConfig};
+use crate::{
+    api::http,
+    config::{McpAuthConfig, McpConfig, ProxyConfig},
+};
 use anyhow::Result;
 use iced::futures::future::join_all;
 use rmcp::{
-    service::{RunningService, ServiceExt},
+    model::{
+        CallToolRequest, CallToolRequestParams, CallToolResult, ClientRequest,
+        GetPromptRequestParams, JsonObject, Meta, NumberOrString, PingRequest, ProgressToken,
+        PromptMessageContent, PromptMessageRole, ReadResourceRequestParams, ResourceContents,
+        ServerResult,
+    },
+    service::{PeerRequestOptions, RunningService, ServiceExt},
     transport::{
-        auth::{AuthClient, AuthorizationManager},
-        streamable_http_client::StreamableHttpClientTransportConfig,
+        auth::AuthClient, streamable_http_client::StreamableHttpClientTransportConfig,
         ConfigureCommandExt, StreamableHttpClientTransport, TokioChildProcess,
     },
     RoleClient,
 };
-use tokio::process::Command;
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::{ChildStderr, Command},
+    sync::{broadcast, watch},
+};
+use tokio_util::sync::CancellationToken;
+
+use self::auth::{authorization_manager, FileCredentialStore};
+use self::handler::{ElicitationRequest, Handler, SamplingRequest, ToolProgress};
+use self::sse::SseClientTransport;
+
+pub type McpClient = RunningService<RoleClient, Handler>;
 
-use self::auth::FileCredentialStore;
+/// Requests and notifications from the servers kept for a chat that is
+/// slow to take them.
+const REQUEST_CHANNEL_CAP: usize = 16;
 
-pub type McpClient = RunningService<RoleClient, ()>;
+/// Messages kept of each server's log; older ones are dropped.
+const MAX_SERVER_LOGS: usize = 200;
+
+/// How often a connected server is checked for a dropped connection.
+const CONNECTION_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+/// Time a connected server has to answer a ping.
+const PING_TIMEOUT: Duration = Duration::from_secs(10);
+/// Wait before the first attempt to reconnect a dropped server, doubled
+/// after every failed attempt up to [`MAX_RECONNECT_DELAY`].
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+/// Attempts to reconnect a dropped server before giving up on it.
+const RECONNECT_ATTEMPTS: u32 = 10;
+
+/// Where the connection to an MCP server stands.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum ServerStatus {
+    #[default]
+    Disconnected,
+    Connecting,
+    Connected,
+    /// The last attempt to connect failed, for this reason.
+    Failed(String),
+}
+
+impl fmt::Display for ServerStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerStatus::Disconnected => write!(f, "Disconnected"),
+            ServerStatus::Connecting => write!(f, "Connecting…"),
+            ServerStatus::Connected => write!(f, "Connected"),
+            ServerStatus::Failed(error) => write!(f, "Failed: {}", error),
+        }
+    }
+}
+
+/// How serious a message a server logged is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogLevel::Debug => write!(f, "debug"),
+            LogLevel::Info => write!(f, "info"),
+            LogLevel::Warning => write!(f, "warning"),
+            LogLevel::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A message an MCP server logged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerLog {
+    pub level: LogLevel,
+    /// Which part of the server logged it, if it said.
+    pub logger: Option<String>,
+    pub message: String,
+}
+
+/// One configured MCP server as the management page shows it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerSummary {
+    pub name: String,
+    /// `false` while the server is turned off in the settings.
+    pub enabled: bool,
+    pub status: ServerStatus,
+    /// Number of tools the server exposes while connected.
+    pub tools: usize,
+}
+
+/// A resource an MCP server offers, which can be attached to a message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct McpResource {
+    /// Name of the server offering it.
+    pub server: String,
+    pub uri: String,
+    pub name: String,
+    pub description: Option<String>,
+}
+
+/// A prompt an MCP server offers, run from the chat input as a slash
+/// command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct McpPrompt {
+    /// Name of the server offering it.
+    pub server: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub arguments: Vec<McpPromptArgument>,
+}
+
+/// A value a prompt asks for before it is rendered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct McpPromptArgument {
+    pub name: String,
+    pub description: Option<String>,
+    pub required: bool,
+}
+
+/// A server just connected to, with what it offers.
+struct Connection {
+    client: McpClient,
+    /// Its tools, each prefixed with the server's name.
+    tools: Vec<crate::models::Tool>,
+    resources: Vec<McpResource>,
+    prompts: Vec<McpPrompt>,
+}
 
 #[derive(Debug)]
 pub struct ToolManager {
     /// Map of MCP client name to MCP client instance
     mcp_clients: Arc<RwLock<HashMap<String, Arc<McpClient>>>>,
+    /// Tools of each connected server, already prefixed with its name.
+    server_tools: Arc<RwLock<HashMap<String, Vec<crate::models::Tool>>>>,
+    /// Resources of each connected server.
+    server_resources: Arc<RwLock<HashMap<String, Vec<McpResource>>>>,
+    /// Prompts of each connected server.
+    server_prompts: Arc<RwLock<HashMap<String, Vec<McpPrompt>>>>,
     /// List of all available tools
     /// Each tool's name is prefixed with the MCP client name to ensure uniqueness
     tools: Arc<RwLock<Vec<crate::models::Tool>>>,
+    /// Folders the built-in filesystem tools may read.
+    fs_roots: Arc<RwLock<Vec<PathBuf>>>,
+    /// Where the built-in shell tool runs commands, if it is on.
+    shell_dir: Arc<RwLock<Option<PathBuf>>>,
+    /// Publishes the tool list after every reload.
+    updates: watch::Sender<Vec<crate::models::Tool>>,
+    /// Publishes the resources of the enabled servers along with the tools.
+    resources: watch::Sender<Vec<McpResource>>,
+    /// Publishes the prompts of the enabled servers along with the tools.
+    prompts: watch::Sender<Vec<McpPrompt>>,
+    /// Sampling requests from the servers, for the chat to approve.
+    sampling_requests: broadcast::Sender<SamplingRequest>,
+    /// Elicitation requests from the servers, for the chat to show.
+    elicitation_requests: broadcast::Sender<ElicitationRequest>,
+    /// Progress the servers report on tool calls.
+    progress: broadcast::Sender<ToolProgress>,
+    /// Signals that the usage of a sampling request was recorded, so the
+    /// chats count it towards this month's spend.
+    usage_recorded: broadcast::Sender<()>,
+    /// Status of each configured server by name, published on every change.
+    statuses: watch::Sender<BTreeMap<String, ServerStatus>>,
+    /// The last messages each server logged, oldest first, published on
+    /// every new one.
+    logs: watch::Sender<BTreeMap<String, VecDeque<ServerLog>>>,
+    /// Stops the task watching each server's connection and reconnecting
+    /// it when it drops.
+    supervisors: Arc<RwLock<HashMap<String, CancellationToken>>>,
+    /// Enabled servers not started yet because they start lazily, on the
+    /// first request that can use their tools.
+    deferred: Arc<RwLock<HashSet<String>>>,
 }
 
 impl ToolManager {
     fn new() -> Self {
         Self {
             mcp_clients: Arc::new(RwLock::new(HashMap::new())),
+            server_tools: Arc::new(RwLock::new(HashMap::new())),
+            server_resources: Arc::new(RwLock::new(HashMap::new())),
+            server_prompts: Arc::new(RwLock::new(HashMap::new())),
             tools: Arc::new(RwLock::new(Vec::new())),
+            fs_roots: Arc::new(RwLock::new(Vec::new())),
+            shell_dir: Arc::new(RwLock::new(None)),
+            updates: watch::Sender::new(Vec::new()),
+            resources: watch::Sender::new(Vec::new()),
+            prompts: watch::Sender::new(Vec::new()),
+            sampling_requests: broadcast::channel(REQUEST_CHANNEL_CAP).0,
+            elicitation_requests: broadcast::channel(REQUEST_CHANNEL_CAP).0,
+            progress: broadcast::channel(REQUEST_CHANNEL_CAP).0,
+            usage_recorded: broadcast::channel(REQUEST_CHANNEL_CAP).0,
+            statuses: watch::Sender::new(BTreeMap::new()),
+            logs: watch::Sender::new(BTreeMap::new()),
+            supervisors: Arc::new(RwLock::new(HashMap::new())),
+            deferred: Arc::new(RwLock::new(HashSet::new())),
+        }
+    }
+
+    /// Subscribe to tool list changes. Every completed [`Self::load_tools`],
+    /// and every server connected or disconnected, marks the receiver as
+    /// changed.
+    pub fn subscribe(&self) -> watch::Receiver<Vec<crate::models::Tool>> {
+        self.updates.subscribe()
+    }
+
+    /// Subscribe to the sampling requests of the servers.
+    pub fn subscribe_sampling(&self) -> broadcast::Receiver<SamplingRequest> {
+        self.sampling_requests.subscribe()
+    }
+
+    /// Ask the chat to approve `request`. False if no chat is listening.
+    pub fn request_sampling(&self, request: SamplingRequest) -> bool {
+        self.sampling_requests.send(request).is_ok()
+    }
+
+    /// Subscribe to the elicitation requests of the servers.
+    pub fn subscribe_elicitation(&self) -> broadcast::Receiver<ElicitationRequest> {
+        self.elicitation_requests.subscribe()
+    }
+
+    /// Ask the chat to show `request`. False if no chat is listening.
+    pub fn request_elicitation(&self, request: ElicitationRequest) -> bool {
+        self.elicitation_requests.send(request).is_ok()
+    }
+
+    /// Subscribe to the progress the servers report on tool calls.
+    pub fn subscribe_progress(&self) -> broadcast::Receiver<ToolProgress> {
+        self.progress.subscribe()
+    }
+
+    /// Pass `progress` on to the chat, if one is listening.
+    pub fn report_progress(&self, progress: ToolProgress) {
+        let _ = self.progress.send(progress);
+    }
+
+    /// Subscribe to the usage recorded for sampling requests.
+    pub fn subscribe_usage(&self) -> broadcast::Receiver<()> {
+        self.usage_recorded.subscribe()
+    }
+
+    /// Tell the chats that the usage of a sampling request was recorded.
+    pub fn report_usage(&self) {
+        let _ = self.usage_recorded.send(());
+    }
+
+    /// Subscribe to changes of the resources the servers offer.
+    pub fn subscribe_resources(&self) -> watch::Receiver<Vec<McpResource>> {
+        self.resources.subscribe()
+    }
+
+    /// Resources the connected servers offer, in settings order.
+    pub fn resources(&self) -> Vec<McpResource> {
+        self.resources.borrow().clone()
+    }
+
+    /// Subscribe to changes of the prompts the servers offer.
+    pub fn subscribe_prompts(&self) -> watch::Receiver<Vec<McpPrompt>> {
+        self.prompts.subscribe()
+    }
+
+    /// Prompts the connected servers offer, in settings order.
+    pub fn prompts(&self) -> Vec<McpPrompt> {
+        self.prompts.borrow().clone()
+    }
+
+    /// Subscribe to changes of the servers' statuses.
+    pub fn subscribe_status(&self) -> watch::Receiver<BTreeMap<String, ServerStatus>> {
+        self.statuses.subscribe()
+    }
+
+    /// Status of each configured server by name.
+    pub fn statuses(&self) -> BTreeMap<String, ServerStatus> {
+        self.statuses.borrow().clone()
+    }
+
+    fn set_status(&self, name: &str, status: ServerStatus) {
+        self.statuses.send_modify(|statuses| {
+            statuses.insert(name.to_string(), status);
+        });
+    }
+
+    /// Subscribe to new messages in the servers' logs.
+    pub fn subscribe_logs(&self) -> watch::Receiver<BTreeMap<String, VecDeque<ServerLog>>> {
+        self.logs.subscribe()
+    }
+
+    /// The last messages server `name` logged, oldest first.
+    pub fn server_logs(&self, name: &str) -> Vec<ServerLog> {
+        self.logs
+            .borrow()
+            .get(name)
+            .map(|logs| logs.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Add `log` to the log of server `name`, and to the app's log.
+    fn add_log(&self, name: &str, log: ServerLog) {
+        match log.level {
+            LogLevel::Debug => tracing::debug!("MCP '{}': {}", name, log.message),
+            LogLevel::Info => tracing::info!("MCP '{}': {}", name, log.message),
+            LogLevel::Warning => tracing::warn!("MCP '{}': {}", name, log.message),
+            LogLevel::Error => tracing::error!("MCP '{}': {}", name, log.message),
         }
+        self.logs.send_modify(|logs| {
+            let logs = logs.entry(name.to_string()).or_default();
+            if logs.len() == MAX_SERVER_LOGS {
+                logs.pop_front();
+            }
+            logs.push_back(log);
+        });
+    }
+
+    /// Stop the task watching server `name`, if there is one.
+    fn stop_supervisor(&self, name: &str) -> Result<()> {
+        let stop = self
+            .supervisors
+            .write()
+            .map_err(|e| anyhow::anyhow!(e.to_string()))?
+            .remove(name);
+        if let Some(stop) = stop {
+            stop.cancel();
+        }
+        Ok(())
+    }
+
+    /// Replace the task watching server `name`, returning the token that
+    /// stops the new one.
+    fn new_supervisor(&self, name: &str) -> Result<CancellationToken> {
+        let stop = CancellationToken::new();
+        let previous = self
+            .supervisors
+            .write()
+            .map_err(|e| anyhow::anyhow!(e.to_string()))?
+            .insert(name.to_string(), stop.clone());
+        if let Some(previous) = previous {
+            previous.cancel();
+        }
+        Ok(stop)
     }
 
     pub async fn load_tools(&self) -> Result<()> {
-        let clients: HashMap<String, Arc<McpClient>> = join_all(
-            crate::config::Config::default()
+        let settings = crate::config::Config::default();
+        let proxy = &settings.proxy;
+        {
+            let mut supervisors = self
+                .supervisors
+                .write()
+                .map_err(|e| anyhow::anyhow!(e.to_string()))?;
+            for (_, stop) in supervisors.drain() {
+                stop.cancel();
+            }
+        }
+        let lazy = settings.lazy_mcp_start;
+        *self
+            .deferred
+            .write()
+            .map_err(|e| anyhow::anyhow!(e.to_string()))? = settings
+            .mcp_configs
+            .iter()
+            .filter(|config| lazy && config.enabled())
+            .map(|config| config.name().to_string())
+            .collect();
+        // Servers no longer configured drop out of the statuses.
+        self.statuses.send_replace(
+            settings
                 .mcp_configs
                 .iter()
-                .map(async |config| (config.name().to_string(), init(config.clone()).await)),
-        )
-        .await
-        .into_iter()
-        .filter_map(|(name, result)| match result {
-            Ok(client) => Some((name, Arc::new(client))),
-            Err(e) => {
-                log::error!(
-                    "Failed to initialize MCP client '{}': {}. Skipping this server.",
-                    name,
-                    e
-                );
-                None
-            }
-        })
-        .collect::<HashMap<String, Arc<McpClient>>>();
-
-        let mut all_tools: Vec<crate::models::Tool> = Vec::new();
-        for (client_name, client) in clients.iter() {
-            match client.list_all_tools().await {
-                Ok(tools) => {
-                    let response: Vec<crate::models::Tool> = tools
-                        .into_iter()
-                        .map(|tool| {
-                            let mut tool = tool.into();
-                            match &mut tool {
-                                crate::models::Tool::Function(func) => {
-                                    func.name = format!("__{}__{}", client_name, func.name);
-                                }
-                            };
-                            tool
-                        })
-                        .collect();
-                    all_tools.extend(response);
+                .map(|config| {
+                    let status = if config.enabled() && !lazy {
+                        ServerStatus::Connecting
+                    } else {
+                        ServerStatus::Disconnected
+                    };
+                    (config.name().to_string(), status)
+                })
+                .collect(),
+        );
+        let inits = settings
+            .mcp_configs
+            .iter()
+            .filter(|config| config.enabled() && !lazy)
+            .map(async |config| {
+                (
+                    config.name().to_string(),
+                    start(config.clone(), proxy).await,
+                )
+            });
+        let mut clients = HashMap::new();
+        let mut server_tools = HashMap::new();
+        let mut server_resources = HashMap::new();
+        let mut server_prompts = HashMap::new();
+        for (name, result) in join_all(inits).await {
+            match result {
+                Ok(connection) => {
+                    self.set_status(&name, ServerStatus::Connected);
+                    let client = Arc::new(connection.client);
+                    let stop = self.new_supervisor(&name)?;
+                    tokio::spawn(supervise(name.clone(), client.clone(), stop));
+                    clients.insert(name.clone(), client);
+                    server_tools.insert(name.clone(), connection.tools);
+                    server_resources.insert(name.clone(), connection.resources);
+                    server_prompts.insert(name, connection.prompts);
                 }
                 Err(e) => {
-                    log::error!(
-                        "Failed to list tools for MCP client '{}': {}. Skipping.",
-                        client_name,
+                    tracing::error!(
+                        "Failed to initialize MCP client '{}': {}. Skipping this server.",
+                        name,
                         e
                     );
+                    self.set_status(&name, ServerStatus::Failed(e.to_string()));
                 }
             }
         }
@@ -99,16 +458,369 @@ impl ToolManager {
             *mcpclients = clients;
         }
 
+        {
+            let mut server_tools_lock = self
+                .server_tools
+                .write()
+                .map_err(|e| anyhow::anyhow!(e.to_string()))?;
+            *server_tools_lock = server_tools;
+        }
+
+        {
+            let mut server_resources_lock = self
+                .server_resources
+                .write()
+                .map_err(|e| anyhow::anyhow!(e.to_string()))?;
+            *server_resources_lock = server_resources;
+        }
+
+        {
+            let mut server_prompts_lock = self
+                .server_prompts
+                .write()
+                .map_err(|e| anyhow::anyhow!(e.to_string()))?;
+            *server_prompts_lock = server_prompts;
+        }
+
+        {
+            let mut roots_lock = self
+                .fs_roots
+                .write()
+                .map_err(|e| anyhow::anyhow!(e.to_string()))?;
+            *roots_lock = settings.fs_roots.iter().map(PathBuf::from).collect();
+        }
+
+        {
+            let mut shell_dir_lock = self
+                .shell_dir
+                .write()
+                .map_err(|e| anyhow::anyhow!(e.to_string()))?;
+            *shell_dir_lock = settings.shell_dir.as_ref().map(PathBuf::from);
+        }
+
+        self.publish(&settings.mcp_configs)
+    }
+
+    /// Connect the configured server `name`, replacing its client if it is
+    /// already connected. Should the connection drop later, it is retried.
+    pub async fn connect(&self, name: &str) -> Result<()> {
+        self.deferred
+            .write()
+            .map_err(|e| anyhow::anyhow!(e.to_string()))?
+            .remove(name);
+        let stop = self.new_supervisor(name)?;
+        if let Some(client) = self.connect_server(name, &stop).await? {
+            tokio::spawn(supervise(name.to_string(), client, stop));
+        }
+        Ok(())
+    }
+
+    /// Whether servers are waiting to be started lazily.
+    pub fn has_deferred(&self) -> bool {
+        self.deferred
+            .read()
+            .is_ok_and(|deferred| !deferred.is_empty())
+    }
+
+    /// Start the servers waiting to be started lazily, all at once. Those
+    /// that fail show it in their status and are not tried again until
+    /// reconnected.
+    pub async fn start_deferred(&self) -> Result<()> {
+        let names: Vec<String> = self
+            .deferred
+            .write()
+            .map_err(|e| anyhow::anyhow!(e.to_string()))?
+            .drain()
+            .collect();
+        let starts = names
+            .iter()
+            .map(async |name| (name, self.connect(name).await));
+        for (name, result) in join_all(starts).await {
+            if let Err(e) = result {
+                tracing::error!("Failed to start MCP server '{}': {}", name, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Connect server `name` and return its client, or `None` when `stop`
+    /// was cancelled while connecting, in which case the new connection is
+    /// closed again.
+    async fn connect_server(
+        &self,
+        name: &str,
+        stop: &CancellationToken,
+    ) -> Result<Option<Arc<McpClient>>> {
+        let settings = crate::config::Config::default();
+        let config = settings
+            .mcp_configs
+            .iter()
+            .find(|config| config.name() == name)
+            .ok_or_else(|| anyhow::anyhow!("No MCP server is named '{}'", name))?;
+        if !config.enabled() {
+            return Err(anyhow::anyhow!("MCP server '{}' is disabled", name));
+        }
+        self.set_status(name, ServerStatus::Connecting);
+        let connection = match start(config.clone(), &settings.proxy).await {
+            Ok(connection) => connection,
+            Err(e) => {
+                if !stop.is_cancelled() {
+                    self.set_status(name, ServerStatus::Failed(e.to_string()));
+                }
+                return Err(e);
+            }
+        };
+        if stop.is_cancelled() {
+            return Ok(None);
+        }
+        self.set_status(name, ServerStatus::Connected);
+        let client = Arc::new(connection.client);
+        self.mcp_clients
+            .write()
+            .map_err(|e| anyhow::anyhow!(e.to_string()))?
+            .insert(name.to_string(), client.clone());
+        self.server_tools
+            .write()
+            .map_err(|e| anyhow::anyhow!(e.to_string()))?
+            .insert(name.to_string(), connection.tools);
+        self.server_resources
+            .write()
+            .map_err(|e| anyhow::anyhow!(e.to_string()))?
+            .insert(name.to_string(), connection.resources);
+        self.server_prompts
+            .write()
+            .map_err(|e| anyhow::anyhow!(e.to_string()))?
+            .insert(name.to_string(), connection.prompts);
+        self.publish(&settings.mcp_configs)?;
+        Ok(Some(client))
+    }
+
+    /// Disconnect server `name` and drop its tools. The connection closes
+    /// once no tool call is using it any more.
+    pub fn disconnect(&self, name: &str) -> Result<()> {
+        self.stop_supervisor(name)?;
+        self.drop_server(name, ServerStatus::Disconnected)
+    }
+
+    /// Forget the client and tools of server `name`, leaving it at `status`.
+    fn drop_server(&self, name: &str, status: ServerStatus) -> Result<()> {
+        self.mcp_clients
+            .write()
+            .map_err(|e| anyhow::anyhow!(e.to_string()))?
+            .remove(name);
+        self.server_tools
+            .write()
+            .map_err(|e| anyhow::anyhow!(e.to_string()))?
+            .remove(name);
+        self.server_resources
+            .write()
+            .map_err(|e| anyhow::anyhow!(e.to_string()))?
+            .remove(name);
+        self.server_prompts
+            .write()
+            .map_err(|e| anyhow::anyhow!(e.to_string()))?
+            .remove(name);
+        self.set_status(name, status);
+        self.publish(&crate::config::Config::default().mcp_configs)
+    }
+
+    /// Disconnect server `name` and connect it again.
+    pub async fn restart(&self, name: &str) -> Result<()> {
+        self.disconnect(name)?;
+        self.connect(name).await
+    }
+
+    /// The configured servers, in settings order, with their status and
+    /// how many tools each exposes.
+    pub fn servers(&self) -> Result<Vec<ServerSummary>> {
+        let server_tools = self
+            .server_tools
+            .read()
+            .map_err(|e| anyhow::anyhow!(e.to_string()))?;
+        let statuses = self.statuses.borrow();
+        let servers = crate::config::Config::default()
+            .mcp_configs
+            .iter()
+            .map(|config| ServerSummary {
+                name: config.name().to_string(),
+                enabled: config.enabled(),
+                status: statuses.get(config.name()).cloned().unwrap_or_default(),
+                tools: server_tools.get(config.name()).map_or(0, Vec::len),
+            })
+            .collect();
+        Ok(servers)
+    }
+
+    /// Every tool server `name` offers while connected, including those
+    /// turned off in the settings, by the server's own names.
+    pub fn server_tool_names(&self, name: &str) -> Result<Vec<String>> {
+        let server_tools = self
+            .server_tools
+            .read()
+            .map_err(|e| anyhow::anyhow!(e.to_string()))?;
+        let prefix = tool_prefix(name);
+        Ok(server_tools
+            .get(name)
+            .into_iter()
+            .flatten()
+            .map(|tool| match tool {
+                crate::models::Tool::Function(func) => func
+                    .name
+                    .strip_prefix(&prefix)
+                    .unwrap_or(&func.name)
+                    .to_string(),
+            })
+            .collect())
+    }
+
+    /// Rebuild the tool list from the built-in tools and those of the
+    /// connected servers, in the order of `configs`, and announce it along
+    /// with the servers' resources and prompts. Disabled servers and tools
+    /// are left out.
+    fn publish(&self, configs: &[McpConfig]) -> Result<()> {
+        let fs_roots = self
+            .fs_roots
+            .read()
+            .map_err(|e| anyhow::anyhow!(e.to_string()))?
+            .clone();
+        let mut all_tools: Vec<crate::models::Tool> = builtin::tools(&fs_roots);
+        all_tools.extend(self.shell_dir().as_deref().map(builtin::shell_tool));
+        {
+            let server_tools = self
+                .server_tools
+                .read()
+                .map_err(|e| anyhow::anyhow!(e.to_string()))?;
+            for config in configs.iter().filter(|config| config.enabled()) {
+                let prefix = tool_prefix(config.name());
+                let Some(tools) = server_tools.get(config.name()) else {
+                    continue;
+                };
+                all_tools.extend(
+                    tools
+                        .iter()
+                        .filter(|tool| match tool {
+                            crate::models::Tool::Function(func) => config.tool_enabled(
+                                func.name.strip_prefix(&prefix).unwrap_or(&func.name),
+                            ),
+                        })
+                        .cloned(),
+                );
+            }
+        }
+
         {
             let mut tools_lock = self
                 .tools
                 .write()
                 .map_err(|e| anyhow::anyhow!(e.to_string()))?;
-            *tools_lock = all_tools.into_iter().collect();
+            *tools_lock = all_tools.clone();
         }
+        self.updates.send_replace(all_tools);
+
+        let all_resources: Vec<McpResource> = {
+            let server_resources = self
+                .server_resources
+                .read()
+                .map_err(|e| anyhow::anyhow!(e.to_string()))?;
+            configs
+                .iter()
+                .filter(|config| config.enabled())
+                .filter_map(|config| server_resources.get(config.name()))
+                .flatten()
+                .cloned()
+                .collect()
+        };
+        self.resources.send_replace(all_resources);
+
+        let all_prompts: Vec<McpPrompt> = {
+            let server_prompts = self
+                .server_prompts
+                .read()
+                .map_err(|e| anyhow::anyhow!(e.to_string()))?;
+            configs
+                .iter()
+                .filter(|config| config.enabled())
+                .filter_map(|config| server_prompts.get(config.name()))
+                .flatten()
+                .cloned()
+                .collect()
+        };
+        self.prompts.send_replace(all_prompts);
         Ok(())
     }
 
+    /// Render `prompt` with `arguments`, by name, into the messages it
+    /// stands for. Arguments left empty are not sent, and only the text of
+    /// the messages is kept.
+    pub async fn get_prompt(
+        &self,
+        prompt: &McpPrompt,
+        arguments: Vec<(String, String)>,
+    ) -> Result<Vec<crate::models::Message>> {
+        let client = self
+            .mcp_clients
+            .read()
+            .map_err(|e| anyhow::anyhow!(e.to_string()))?
+            .get(&prompt.server)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("MCP server '{}' is not connected", prompt.server))?;
+        let arguments: JsonObject = arguments
+            .into_iter()
+            .filter(|(_, value)| !value.is_empty())
+            .map(|(name, value)| (name, serde_json::Value::String(value)))
+            .collect();
+        let result = client
+            .get_prompt(GetPromptRequestParams::new(prompt.name.clone()).with_arguments(arguments))
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to get prompt '{}': {}", prompt.name, e))?;
+        Ok(result
+            .messages
+            .into_iter()
+            .filter_map(|message| {
+                let text = match message.content {
+                    PromptMessageContent::Text { text } => text,
+                    PromptMessageContent::Resource { resource } => match resource.raw.resource {
+                        ResourceContents::TextResourceContents { text, .. } => text,
+                        _ => return None,
+                    },
+                    _ => return None,
+                };
+                Some(match message.role {
+                    PromptMessageRole::User => crate::models::Message::user(text, None),
+                    PromptMessageRole::Assistant => crate::models::Message::assistant(text),
+                })
+            })
+            .collect())
+    }
+
+    /// Read resource `uri` of server `server` as text. Binary contents are
+    /// left out.
+    pub async fn read_resource(&self, server: &str, uri: &str) -> Result<String> {
+        let client = self
+            .mcp_clients
+            .read()
+            .map_err(|e| anyhow::anyhow!(e.to_string()))?
+            .get(server)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("MCP server '{}' is not connected", server))?;
+        let result = client
+            .read_resource(ReadResourceRequestParams::new(uri))
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to read '{}': {}", uri, e))?;
+        let texts: Vec<String> = result
+            .contents
+            .into_iter()
+            .filter_map(|contents| match contents {
+                ResourceContents::TextResourceContents { text, .. } => Some(text),
+                _ => None,
+            })
+            .collect();
+        if texts.is_empty() {
+            return Err(anyhow::anyhow!("'{}' has no text to attach", uri));
+        }
+        Ok(texts.join("\n\n"))
+    }
+
     pub fn get_tools(&self) -> Result<Vec<crate::models::Tool>> {
         let tools_lock = self
             .tools
@@ -117,6 +829,23 @@ impl ToolManager {
         Ok(tools_lock.clone())
     }
 
+    /// Run the built-in tool `name` with its JSON `arguments`, confined to
+    /// the folders shared when the tools were last loaded.
+    pub async fn call_builtin(&self, name: &str, arguments: &str) -> Result<String> {
+        let roots = self
+            .fs_roots
+            .read()
+            .map_err(|e| anyhow::anyhow!(e.to_string()))?
+            .clone();
+        let (name, arguments) = (name.to_string(), arguments.to_string());
+        tokio::task::spawn_blocking(move || builtin::call(&roots, &name, &arguments)).await?
+    }
+
+    /// Where the built-in shell tool runs commands, `None` while it is off.
+    pub fn shell_dir(&self) -> Option<PathBuf> {
+        self.shell_dir.read().ok()?.clone()
+    }
+
     pub fn get_client_by_tool_call(&self, tool_call_name: &str) -> Result<Option<Arc<McpClient>>> {
         let (client_name, tool_name) =
             match self.tool_client_and_name_by_tool_call(tool_call_name.to_string())? {
@@ -126,7 +855,7 @@ impl ToolManager {
                 }
             };
 
-        log::info!(
+        tracing::info!(
             "Looking for MCP client '{}' for tool call '{}'",
             client_name,
             tool_name
@@ -143,93 +872,356 @@ impl ToolManager {
         }
     }
 
+    /// Split a tool name as the model sees it into the connected server
+    /// that offers the tool and the tool's name on that server. `None` for
+    /// built-in tools and tools of servers that are not connected.
     pub fn tool_client_and_name_by_tool_call(
         &self,
         tool_call_name: String,
     ) -> Result<Option<(String, String)>> {
-        let parts: Vec<&str> = tool_call_name
-            .strip_prefix("__")
-            .unwrap_or(&tool_call_name)
-            .splitn(2, "__")
-            .collect();
-        if parts.len() != 2 {
-            return Ok(None);
+        let server_tools = self
+            .server_tools
+            .read()
+            .map_err(|e| anyhow::anyhow!(e.to_string()))?;
+        // Server names may themselves contain "__", so the longest
+        // matching prefix wins.
+        Ok(server_tools
+            .keys()
+            .filter_map(|server| {
+                let tool = tool_call_name.strip_prefix(&tool_prefix(server))?;
+                Some((server.clone(), tool.to_string()))
+            })
+            .max_by_key(|(server, _)| server.len()))
+    }
+}
+
+/// What the name of every tool of server `name` starts with, so that tools
+/// of the same name on two servers stay apart.
+fn tool_prefix(name: &str) -> String {
+    format!("__{}__", name)
+}
+
+/// Watch the connection to server `name` until `stop` is cancelled. When
+/// the child process exits, the transport drops or the server stops
+/// answering pings, the server's tools are withdrawn and it is reconnected.
+async fn supervise(name: String, mut client: Arc<McpClient>, stop: CancellationToken) {
+    let manager = get_tool_manager();
+    loop {
+        let failure = tokio::select! {
+            _ = stop.cancelled() => return,
+            failure = watch_connection(&client) => failure,
+        };
+        tracing::warn!("MCP '{}': {}, reconnecting", name, failure.to_lowercase());
+        let lost = ServerStatus::Failed(failure);
+        if let Err(e) = manager.drop_server(&name, lost) {
+            tracing::error!("MCP '{}': {}", name, e);
+        }
+        match reconnect(manager, &name, &stop).await {
+            Some(reconnected) => client = reconnected,
+            None => return,
+        }
+    }
+}
+
+/// Wait until the connection of `client` fails, and say how: the
+/// transport closed or a ping went unanswered.
+async fn watch_connection(client: &McpClient) -> String {
+    let ping_interval = match client.service().timeouts().ping_interval_secs {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    };
+    let mut last_ping = tokio::time::Instant::now();
+    loop {
+        if client.is_transport_closed() {
+            return "Connection lost".to_string();
+        }
+        if ping_interval.is_some_and(|interval| last_ping.elapsed() >= interval) {
+            if let Err(e) = ping(client).await {
+                return format!("Not responding: {}", e);
+            }
+            last_ping = tokio::time::Instant::now();
         }
-        let client_name = parts[0];
-        let tool_name = parts[1].to_string();
+        tokio::time::sleep(CONNECTION_CHECK_INTERVAL).await;
+    }
+}
 
-        log::info!(
-            "Looking for MCP client '{}' for tool call '{}'",
-            client_name,
-            tool_name
-        );
+/// Ping the server of `client`, failing if it takes longer than
+/// [`PING_TIMEOUT`] to answer.
+async fn ping(client: &McpClient) -> Result<()> {
+    let mut options = PeerRequestOptions::no_options();
+    options.timeout = Some(PING_TIMEOUT);
+    let request = ClientRequest::PingRequest(PingRequest::default());
+    client
+        .send_cancellable_request(request, options)
+        .await?
+        .await_response()
+        .await?;
+    Ok(())
+}
+
+/// Try to connect server `name` again, waiting longer after every failed
+/// attempt. `None` once stopped or out of attempts.
+async fn reconnect(
+    manager: &ToolManager,
+    name: &str,
+    stop: &CancellationToken,
+) -> Option<Arc<McpClient>> {
+    let mut delay = RECONNECT_DELAY;
+    for attempt in 1..=RECONNECT_ATTEMPTS {
+        tokio::select! {
+            _ = stop.cancelled() => return None,
+            _ = tokio::time::sleep(delay) => {}
+        }
+        match manager.connect_server(name, stop).await {
+            Ok(client) => return client,
+            Err(e) => tracing::warn!(
+                "MCP '{}': reconnect attempt {} of {} failed: {}",
+                name,
+                attempt,
+                RECONNECT_ATTEMPTS,
+                e
+            ),
+        }
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+    tracing::error!("MCP '{}': giving up reconnecting", name);
+    None
+}
+
+/// Connect to the server `config` describes, giving up once its init
+/// timeout passes.
+async fn start(config: McpConfig, proxy: &ProxyConfig) -> Result<Connection> {
+    let secs = config.timeouts().init_secs;
+    tokio::time::timeout(Duration::from_secs(secs), open(config, proxy))
+        .await
+        .map_err(|_| anyhow::anyhow!("No answer after {} seconds", secs))?
+}
+
+/// Connect to the server `config` describes and list its tools, each
+/// prefixed with the server's name, and its resources.
+async fn open(config: McpConfig, proxy: &ProxyConfig) -> Result<Connection> {
+    let name = config.name().to_string();
+    let client = init(config, proxy).await?;
+    let tools = client
+        .list_all_tools()
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to list tools: {}", e))?
+        .into_iter()
+        .map(|tool| {
+            let mut tool = tool.into();
+            match &mut tool {
+                crate::models::Tool::Function(func) => {
+                    func.name = format!("{}{}", tool_prefix(&name), func.name);
+                }
+            };
+            tool
+        })
+        .collect();
+    let resources = list_resources(&name, &client).await;
+    let prompts = list_prompts(&name, &client).await;
+    Ok(Connection {
+        client,
+        tools,
+        resources,
+        prompts,
+    })
+}
+
+/// The resources server `name` offers, none if it has no resources or
+/// listing them fails.
+async fn list_resources(name: &str, client: &McpClient) -> Vec<McpResource> {
+    let offers_resources = client
+        .peer_info()
+        .is_some_and(|info| info.capabilities.resources.is_some());
+    if !offers_resources {
+        return Vec::new();
+    }
+    match client.list_all_resources().await {
+        Ok(resources) => resources
+            .into_iter()
+            .map(|resource| McpResource {
+                server: name.to_string(),
+                uri: resource.raw.uri,
+                name: resource.raw.name,
+                description: resource.raw.description,
+            })
+            .collect(),
+        Err(e) => {
+            tracing::warn!("MCP '{}': failed to list resources: {}", name, e);
+            Vec::new()
+        }
+    }
+}
+
+/// The prompts server `name` offers, none if it has no prompts or listing
+/// them fails.
+async fn list_prompts(name: &str, client: &McpClient) -> Vec<McpPrompt> {
+    let offers_prompts = client
+        .peer_info()
+        .is_some_and(|info| info.capabilities.prompts.is_some());
+    if !offers_prompts {
+        return Vec::new();
+    }
+    match client.list_all_prompts().await {
+        Ok(prompts) => prompts
+            .into_iter()
+            .map(|prompt| McpPrompt {
+                server: name.to_string(),
+                name: prompt.name,
+                description: prompt.description,
+                arguments: prompt
+                    .arguments
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|argument| McpPromptArgument {
+                        name: argument.name,
+                        description: argument.description,
+                        required: argument.required.unwrap_or(false),
+                    })
+                    .collect(),
+            })
+            .collect(),
+        Err(e) => {
+            tracing::warn!("MCP '{}': failed to list prompts: {}", name, e);
+            Vec::new()
+        }
+    }
+}
 
-        Ok(Some((client_name.to_string(), tool_name)))
+/// Call a tool on `client`, asking the server to report progress under
+/// `call_id`, the id of the model's tool call. Fails once the server's
+/// tool call timeout passes.
+pub async fn call_tool(
+    client: &McpClient,
+    params: CallToolRequestParams,
+    call_id: &str,
+) -> Result<CallToolResult> {
+    let mut options = PeerRequestOptions::no_options();
+    options.timeout = Some(Duration::from_secs(
+        client.service().timeouts().tool_call_secs,
+    ));
+    options.meta = Some(Meta::with_progress_token(ProgressToken(
+        NumberOrString::String(call_id.into()),
+    )));
+    let request = ClientRequest::CallToolRequest(CallToolRequest::new(params));
+    match client
+        .send_cancellable_request(request, options)
+        .await?
+        .await_response()
+        .await?
+    {
+        ServerResult::CallToolResult(result) => Ok(result),
+        _ => Err(anyhow::anyhow!("Unexpected response to a tool call")),
     }
 }
 
-pub async fn init(config: McpConfig) -> Result<McpClient> {
-    log::info!("Initializing MCP client with config: {:?}", config);
+pub async fn init(config: McpConfig, proxy: &ProxyConfig) -> Result<McpClient> {
+    tracing::info!("Initializing MCP client with config: {:?}", config);
+    let handler = Handler::new(config.name(), config.timeouts());
     let client = match config {
         McpConfig::Stdio(cfg) => {
-            let transport = TokioChildProcess::new(Command::new(cfg.command).configure(|cmd| {
-                cmd.args(cfg.args);
-            }))?;
-            ().serve(transport).await?
+            let command = Command::new(&cfg.command).configure(|cmd| {
+                cmd.args(cfg.args).envs(cfg.env);
+                if let Some(cwd) = cfg.cwd {
+                    cmd.current_dir(cwd);
+                }
+            });
+            let (transport, stderr) = TokioChildProcess::builder(command)
+                .stderr(Stdio::piped())
+                .spawn()
+                .map_err(|e| anyhow::anyhow!("Failed to start '{}': {}", cfg.command, e))?;
+            if let Some(stderr) = stderr {
+                tokio::spawn(log_stderr(cfg.name, stderr));
+            }
+            handler.serve(transport).await?
         }
         McpConfig::StreamableHttp(server_config) => {
             init_streamable_http(
+                handler,
                 &server_config.name,
                 &server_config.endpoint,
                 &server_config.auth,
+                proxy,
             )
             .await?
         }
+        McpConfig::Sse(cfg) => {
+            tracing::info!(
+                "MCP '{}': connecting to {} over SSE",
+                cfg.name,
+                cfg.endpoint
+            );
+            let token = (!cfg.bearer_token.is_empty()).then_some(cfg.bearer_token);
+            let transport =
+                SseClientTransport::connect(http::proxied_client(proxy), &cfg.endpoint, token)
+                    .await?;
+            handler.serve(transport).await?
+        }
     };
     Ok(client)
 }
 
-/// Initialize a StreamableHTTP MCP client with the appropriate auth configuration.
+/// Add the lines a stdio server writes to stderr to its log, until it
+/// exits. Servers that fail to start usually say why there.
+async fn log_stderr(name: String, stderr: ChildStderr) {
+    let mut lines = BufReader::new(stderr).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        get_tool_manager().add_log(
+            &name,
+            ServerLog {
+                level: LogLevel::Info,
+                logger: Some("stderr".to_string()),
+                message: line,
+            },
+        );
+    }
+}
+
+/// Initialize a StreamableHTTP MCP client with the appropriate auth configuration,
+/// connecting through `proxy` when one is set.
 async fn init_streamable_http(
+    handler: Handler,
     server_name: &str,
     endpoint: &str,
     auth_config: &McpAuthConfig,
+    proxy: &ProxyConfig,
 ) -> Result<McpClient> {
     match auth_config {
         McpAuthConfig::None => {
-            log::info!(
+            tracing::info!(
                 "MCP '{}': connecting to {} with no authentication",
                 server_name,
                 endpoint
             );
-            let transport = StreamableHttpClientTransport::from_uri(endpoint);
-            let client = ().serve(transport).await?;
+            let config = StreamableHttpClientTransportConfig::with_uri(endpoint);
+            let transport =
+                StreamableHttpClientTransport::with_client(http::proxied_client(proxy), config);
+            let client = handler.serve(transport).await?;
             Ok(client)
         }
 
         McpAuthConfig::BearerToken { token } => {
-            log::info!(
+            tracing::info!(
                 "MCP '{}': connecting to {} with bearer token authentication",
                 server_name,
                 endpoint
             );
             let config =
                 StreamableHttpClientTransportConfig::with_uri(endpoint).auth_header(token.clone());
-            let transport = StreamableHttpClientTransport::from_config(config);
-            let client = ().serve(transport).await?;
+            let transport =
+                StreamableHttpClientTransport::with_client(http::proxied_client(proxy), config);
+            let client = handler.serve(transport).await?;
             Ok(client)
         }
 
         McpAuthConfig::OAuth2 { .. } => {
-            log::info!(
+            tracing::info!(
                 "MCP '{}': connecting to {} with OAuth2 authentication",
                 server_name,
                 endpoint
             );
 
-            let mut auth_manager = AuthorizationManager::new(endpoint)
-                .await
-                .map_err(|e| anyhow::anyhow!("OAuth2 manager creation failed: {}", e))?;
+            let mut auth_manager = authorization_manager(endpoint, proxy).await?;
 
             // Set file-backed credential store for persistence
             auth_manager.set_credential_store(FileCredentialStore::new(server_name));
@@ -248,14 +1240,14 @@ async fn init_streamable_http(
                 ));
             }
 
-            log::info!("MCP '{}': using stored OAuth2 credentials", server_name);
+            tracing::info!("MCP '{}': using stored OAuth2 credentials", server_name);
 
             // Create AuthClient that wraps reqwest::Client with automatic token injection
-            let auth_client = AuthClient::new(reqwest::Client::default(), auth_manager);
+            let auth_client = AuthClient::new(http::proxied_client(proxy), auth_manager);
 
             let config = StreamableHttpClientTransportConfig::with_uri(endpoint);
             let transport = StreamableHttpClientTransport::with_client(auth_client, config);
-            let client = ().serve(transport).await?;
+            let client = handler.serve(transport).await?;
             Ok(client)
         }
     }
@@ -276,3 +1268,74 @@ impl From<rmcp::model::Tool> for crate::models::Tool {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tool(name: &str) -> crate::models::Tool {
+        crate::models::Tool::Function(crate::models::Function {
+            name: name.to_string(),
+            description: String::new(),
+            parameters: serde_json::json!({"type": "object"}),
+        })
+    }
+
+    #[test]
+    fn test_tool_calls_resolve_to_their_server() {
+        let manager = ToolManager::new();
+        manager.server_tools.write().unwrap().extend([
+            ("github".to_string(), vec![tool("__github__search")]),
+            ("docs".to_string(), vec![tool("__docs__search")]),
+            ("docs__v2".to_string(), vec![tool("__docs__v2__search")]),
+        ]);
+        let resolve = |name: &str| {
+            manager
+                .tool_client_and_name_by_tool_call(name.to_string())
+                .unwrap()
+        };
+
+        assert_eq!(
+            resolve("__github__search"),
+            Some(("github".to_string(), "search".to_string()))
+        );
+        assert_eq!(
+            resolve("__docs__search"),
+            Some(("docs".to_string(), "search".to_string()))
+        );
+        assert_eq!(
+            resolve("__docs__v2__search"),
+            Some(("docs__v2".to_string(), "search".to_string()))
+        );
+        assert_eq!(resolve("read_file"), None);
+        assert_eq!(resolve("__slack__search"), None);
+        assert_eq!(
+            manager.server_tool_names("docs").unwrap(),
+            vec!["search".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_server_logs_keep_the_latest_messages() {
+        let manager = ToolManager::new();
+        for i in 0..MAX_SERVER_LOGS + 5 {
+            manager.add_log(
+                "docs",
+                ServerLog {
+                    level: LogLevel::Info,
+                    logger: None,
+                    message: format!("message {i}"),
+                },
+            );
+        }
+
+        let logs = manager.server_logs("docs");
+        assert_eq!(logs.len(), MAX_SERVER_LOGS);
+        assert_eq!(logs[0].message, "message 5");
+        assert_eq!(
+            logs.last().unwrap().message,
+            format!("message {}", MAX_SERVER_LOGS + 4)
+        );
+        assert!(manager.server_logs("github").is_empty());
+    }
+}
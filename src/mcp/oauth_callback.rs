@@ -36,23 +36,30 @@ pub struct OAuthCallbackResult {
     pub state: String,
 }
 
-/// Start a temporary localhost HTTP server to receive the OAuth2 authorization callback.
+/// Bind the temporary localhost HTTP server that receives the OAuth2
+/// authorization callback on `127.0.0.1:{port}/callback`.
 ///
-/// Listens on `127.0.0.1:{port}/callback`, waits for a single GET request with
-/// `code` and `state` query parameters, responds with a success page, and returns
-/// the extracted values.
-///
-/// Times out after 120 seconds if no callback is received.
-pub async fn wait_for_oauth_callback(port: u16) -> Result<OAuthCallbackResult> {
+/// Bind it before sending the user to the authorization server, so the
+/// redirect can't arrive before anything listens and a port already in use
+/// fails the flow before the user signs in.
+pub async fn bind_oauth_callback(port: u16) -> Result<TcpListener> {
     let listener = TcpListener::bind(format!("127.0.0.1:{}", port))
         .await
         .with_context(|| format!("Failed to bind OAuth callback server on port {}", port))?;
 
-    log::info!(
+    tracing::info!(
         "OAuth callback server listening on http://127.0.0.1:{}/callback",
         port
     );
 
+    Ok(listener)
+}
+
+/// Wait on `listener` for a single GET request with `code` and `state` query
+/// parameters, respond with a success page, and return the extracted values.
+///
+/// Times out after 120 seconds if no callback is received.
+pub async fn wait_for_oauth_callback(listener: TcpListener) -> Result<OAuthCallbackResult> {
     let result = tokio::time::timeout(
         Duration::from_secs(CALLBACK_TIMEOUT_SECS),
         handle_single_request(&listener),
@@ -70,7 +77,7 @@ async fn handle_single_request(listener: &TcpListener) -> Result<OAuthCallbackRe
             .await
             .context("Failed to accept connection")?;
 
-        log::debug!("OAuth callback: connection from {}", addr);
+        tracing::debug!("OAuth callback: connection from {}", addr);
 
         let mut buf = vec![0u8; 4096];
         let n = stream
@@ -154,7 +161,7 @@ async fn handle_single_request(listener: &TcpListener) -> Result<OAuthCallbackRe
         // Send success response
         send_response(&mut stream, 200, SUCCESS_HTML).await?;
 
-        log::info!("OAuth callback received successfully");
+        tracing::info!("OAuth callback received successfully");
 
         return Ok(OAuthCallbackResult { code, state });
     }
@@ -226,6 +233,14 @@ mod tests {
         assert_eq!(callback.state, "csrf_state_456");
     }
 
+    #[tokio::test]
+    async fn test_bind_fails_when_port_is_taken() {
+        let taken = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = taken.local_addr().unwrap().port();
+
+        assert!(bind_oauth_callback(port).await.is_err());
+    }
+
     #[tokio::test]
     async fn test_callback_rejects_missing_code() {
         let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
@@ -0,0 +1,270 @@
+//! The legacy HTTP+SSE MCP transport, which rmcp no longer ships.
+//!
+//! The client keeps an event stream open at the server's endpoint. The
+//! server's first `endpoint` event names the URL that messages are posted
+//! to, and its replies come back as `message` events on the stream.
+
+use std::{future::Future, pin::Pin};
+
+use anyhow::{anyhow, Result};
+use iced::futures::{Stream, StreamExt};
+use reqwest::header::ACCEPT;
+use rmcp::{
+    model::{ClientJsonRpcMessage, ServerJsonRpcMessage},
+    transport::Transport,
+    RoleClient,
+};
+use tokio::{sync::mpsc, task::JoinHandle};
+use url::Url;
+
+/// Messages from the server kept until the client takes them.
+const MESSAGE_CHANNEL_CAP: usize = 64;
+
+type Body = Pin<Box<dyn Stream<Item = reqwest::Result<Vec<u8>>> + Send>>;
+
+pub struct SseClientTransport {
+    client: reqwest::Client,
+    /// Where messages to the server are posted.
+    message_url: Url,
+    bearer_token: Option<String>,
+    messages: mpsc::Receiver<ServerJsonRpcMessage>,
+    /// Reads the event stream into `messages`.
+    reader: JoinHandle<()>,
+}
+
+impl SseClientTransport {
+    /// Open the event stream at `endpoint` and wait for the server to name
+    /// the URL messages go to.
+    pub async fn connect(
+        client: reqwest::Client,
+        endpoint: &str,
+        bearer_token: Option<String>,
+    ) -> Result<Self> {
+        let endpoint = Url::parse(endpoint)?;
+        let mut request = client
+            .get(endpoint.clone())
+            .header(ACCEPT, "text/event-stream");
+        if let Some(token) = &bearer_token {
+            request = request.bearer_auth(token);
+        }
+        let response = request.send().await?.error_for_status()?;
+        let mut body: Body = Box::pin(
+            response
+                .bytes_stream()
+                .map(|chunk| chunk.map(|bytes| bytes.to_vec())),
+        );
+        let mut parser = EventParser::default();
+        let (sender, messages) = mpsc::channel(MESSAGE_CHANNEL_CAP);
+        let message_url = loop {
+            let chunk = body.next().await.ok_or_else(|| {
+                anyhow!("The server closed the event stream without naming its message endpoint")
+            })??;
+            let mut events = parser
+                .push(&chunk)
+                .into_iter()
+                .skip_while(|event| event.name != "endpoint");
+            let Some(announced) = events.next() else {
+                continue;
+            };
+            for event in events {
+                forward(&sender, event).await;
+            }
+            break message_url(&endpoint, &announced.data)?;
+        };
+        tracing::info!("MCP SSE: posting messages to {}", message_url);
+        Ok(Self {
+            client,
+            message_url,
+            bearer_token,
+            messages,
+            reader: tokio::spawn(read_events(body, parser, sender)),
+        })
+    }
+}
+
+impl Transport<RoleClient> for SseClientTransport {
+    type Error = reqwest::Error;
+
+    fn send(
+        &mut self,
+        item: ClientJsonRpcMessage,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send + 'static {
+        let mut request = self.client.post(self.message_url.clone()).json(&item);
+        if let Some(token) = &self.bearer_token {
+            request = request.bearer_auth(token);
+        }
+        async move {
+            request.send().await?.error_for_status()?;
+            Ok(())
+        }
+    }
+
+    fn receive(&mut self) -> impl Future<Output = Option<ServerJsonRpcMessage>> + Send {
+        self.messages.recv()
+    }
+
+    async fn close(&mut self) -> Result<(), Self::Error> {
+        self.reader.abort();
+        Ok(())
+    }
+}
+
+impl Drop for SseClientTransport {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+/// Resolve the message endpoint the server announced against the event
+/// stream's URL `endpoint`. Messages carry the bearer token, so an endpoint
+/// with a different scheme, host or port is refused.
+fn message_url(endpoint: &Url, announced: &str) -> Result<Url> {
+    let url = endpoint.join(announced.trim())?;
+    if url.origin() != endpoint.origin() {
+        return Err(anyhow!(
+            "The server named a message endpoint on another origin: {url}"
+        ));
+    }
+    Ok(url)
+}
+
+/// Pass the messages on `body` to `sender` until either end closes.
+async fn read_events(
+    mut body: Body,
+    mut parser: EventParser,
+    sender: mpsc::Sender<ServerJsonRpcMessage>,
+) {
+    while let Some(Ok(chunk)) = body.next().await {
+        for event in parser.push(&chunk) {
+            if !forward(&sender, event).await {
+                return;
+            }
+        }
+    }
+    tracing::warn!("MCP SSE: the server closed the event stream");
+}
+
+/// Pass `event` to `sender` if it carries a message. False once nobody
+/// takes messages anymore.
+async fn forward(sender: &mpsc::Sender<ServerJsonRpcMessage>, event: Event) -> bool {
+    if event.name != "message" {
+        return true;
+    }
+    match serde_json::from_str(&event.data) {
+        Ok(message) => sender.send(message).await.is_ok(),
+        Err(e) => {
+            tracing::warn!("MCP SSE: ignoring a malformed message: {}", e);
+            true
+        }
+    }
+}
+
+/// An event of the stream: its name, `message` unless it says otherwise,
+/// and its data lines joined.
+#[derive(Debug, PartialEq, Eq)]
+struct Event {
+    name: String,
+    data: String,
+}
+
+/// Splits the bytes of an event stream, as they arrive, into events.
+#[derive(Debug, Default)]
+struct EventParser {
+    /// The line read so far.
+    line: Vec<u8>,
+    name: String,
+    data: Vec<String>,
+}
+
+impl EventParser {
+    /// Read `bytes`, returning the events they complete.
+    fn push(&mut self, bytes: &[u8]) -> Vec<Event> {
+        let mut events = Vec::new();
+        for &byte in bytes {
+            if byte != b'\n' {
+                self.line.push(byte);
+                continue;
+            }
+            let line = String::from_utf8_lossy(&self.line).into_owned();
+            self.line.clear();
+            events.extend(self.read_line(line.trim_end_matches('\r')));
+        }
+        events
+    }
+
+    /// Read one line, a blank one ending the event.
+    fn read_line(&mut self, line: &str) -> Option<Event> {
+        if line.is_empty() {
+            let name = std::mem::take(&mut self.name);
+            if self.data.is_empty() {
+                return None;
+            }
+            return Some(Event {
+                name: if name.is_empty() {
+                    "message".to_string()
+                } else {
+                    name
+                },
+                data: std::mem::take(&mut self.data).join("\n"),
+            });
+        }
+        let (field, value) = line.split_once(':').unwrap_or((line, ""));
+        let value = value.strip_prefix(' ').unwrap_or(value);
+        match field {
+            "event" => self.name = value.to_string(),
+            "data" => self.data.push(value.to_string()),
+            // Comments, `id` and `retry` don't matter here.
+            _ => {}
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(name: &str, data: &str) -> Event {
+        Event {
+            name: name.to_string(),
+            data: data.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_events_split_across_chunks() {
+        let mut parser = EventParser::default();
+
+        assert_eq!(parser.push(b"event: endpoint\r\nda"), vec![]);
+        assert_eq!(
+            parser.push(b"ta: /messages?session=1\r\n\r\n: keep-alive\n\ndata: {\"a\":"),
+            vec![event("endpoint", "/messages?session=1")]
+        );
+        assert_eq!(
+            parser.push(b"1}\ndata: {\"b\":2}\n\n"),
+            vec![event("message", "{\"a\":1}\n{\"b\":2}")]
+        );
+    }
+
+    #[test]
+    fn test_message_url_stays_on_the_stream_origin() {
+        let endpoint = Url::parse("https://mcp.example.com/sse").unwrap();
+        let url = message_url(&endpoint, " /messages?session=1\n").unwrap();
+        assert_eq!(url.as_str(), "https://mcp.example.com/messages?session=1");
+        assert!(message_url(&endpoint, "https://mcp.example.com:443/messages").is_ok());
+        assert!(message_url(&endpoint, "https://evil.example.com/messages").is_err());
+        assert!(message_url(&endpoint, "http://mcp.example.com/messages").is_err());
+        assert!(message_url(&endpoint, "https://mcp.example.com:8443/messages").is_err());
+        assert!(message_url(&endpoint, "//evil.example.com/messages").is_err());
+    }
+
+    #[test]
+    fn test_characters_split_across_chunks() {
+        let mut parser = EventParser::default();
+        let bytes = "data: café\n\n".as_bytes();
+
+        // "é" is two bytes; the first chunk ends between them.
+        assert_eq!(parser.push(&bytes[..10]), vec![]);
+        assert_eq!(parser.push(&bytes[10..]), vec![event("message", "café")]);
+    }
+}
@@ -1,3 +1,4 @@
+use base64::Engine as _;
 use serde::{Deserialize, Serialize};
 use strum_macros::EnumIter;
 
@@ -47,7 +48,28 @@ pub enum Clients {
     #[default]
     OpenAI,
     Anthropic,
-    Vllm,
+    /// An OpenAI-compatible server from `local_servers`, by name.
+    Local(String),
+    Azure,
+    Mistral,
+    Cohere,
+    Bedrock,
+    /// A provider from `custom_providers`, by name.
+    Custom(String),
+}
+
+impl std::fmt::Display for Clients {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Clients::OpenAI => write!(f, "OpenAI"),
+            Clients::Anthropic => write!(f, "Anthropic"),
+            Clients::Local(name) | Clients::Custom(name) => write!(f, "{name}"),
+            Clients::Azure => write!(f, "Azure OpenAI"),
+            Clients::Mistral => write!(f, "Mistral"),
+            Clients::Cohere => write!(f, "Cohere"),
+            Clients::Bedrock => write!(f, "Bedrock"),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
@@ -56,6 +78,35 @@ pub struct ModelInfo {
     pub id: String,
     #[serde(skip_serializing, skip_deserializing)]
     pub client: Clients,
+    #[serde(skip_serializing, skip_deserializing)]
+    pub capabilities: ModelCapabilities,
+}
+
+/// What a model can take and do. Models missing from the built-in table
+/// that their provider doesn't describe either are assumed capable, so
+/// nothing is held back from them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModelCapabilities {
+    /// Accepts images (and other attachments) in messages.
+    pub vision: bool,
+    /// Accepts tool definitions and returns tool calls.
+    pub tools: bool,
+    /// Maximum context length in tokens, if known.
+    pub context_length: Option<u32>,
+    /// Reasons before answering: takes a reasoning effort and rejects
+    /// sampling parameters such as temperature.
+    pub reasoning: bool,
+}
+
+impl Default for ModelCapabilities {
+    fn default() -> Self {
+        Self {
+            vision: true,
+            tools: true,
+            context_length: None,
+            reasoning: false,
+        }
+    }
 }
 
 // ImageUrl must be defined before Content since Content references it
@@ -216,7 +267,7 @@ impl Content {
                 content,
                 is_error,
             } => {
-                log::info!("Tool Result Content: {}", content);
+                tracing::info!("Tool Result Content: {}", content);
                 if let Some(true) = is_error {
                     Some(format!(
                         "Tool Result (Error) - Tool Use ID: {}, Content: \n```json\n{}\n```",
@@ -236,6 +287,18 @@ impl Content {
             _ => None,
         }
     }
+
+    /// The name of the file a text block inlines, if it is one.
+    pub fn attached_file_name(&self) -> Option<&str> {
+        let Content::Text { text } = self else {
+            return None;
+        };
+        if !text.ends_with("\n</file>") {
+            return None;
+        }
+        let (name, _) = text.strip_prefix("<file name=\"")?.split_once("\">\n")?;
+        Some(name)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -247,10 +310,51 @@ pub struct Message {
     pub tool_calls: Option<Vec<ToolCall>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub reasoning_content: Option<String>,
+    /// Signature Anthropic attaches to its thinking, which must be sent
+    /// back with it while a tool loop is in progress. Not kept once the
+    /// conversation is stored.
+    #[serde(skip)]
+    pub reasoning_signature: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_call_id: Option<String>,
 }
 
+/// The text of a file attached as a `data:text/…` or JSON URL. Such files
+/// are sent inline, since few providers take text files as files.
+fn attached_text(data_url: &str) -> Option<String> {
+    let (media_type, data) = data_url.strip_prefix("data:")?.split_once(";base64,")?;
+    if !media_type.starts_with("text/") && media_type != "application/json" {
+        return None;
+    }
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(data)
+        .ok()?;
+    String::from_utf8(bytes).ok()
+}
+
+/// Text files longer than this are cut short when inlined, so one file
+/// can't fill the context window.
+const MAX_ATTACHED_TEXT_BYTES: usize = 100_000;
+
+/// A text block inlining the file `name` holding `text`, as recognized by
+/// [`Content::attached_file_name`].
+fn inlined_file(name: &str, text: &str) -> String {
+    let mut end = text.len().min(MAX_ATTACHED_TEXT_BYTES);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    let mut block = format!("<file name=\"{name}\">\n{}", &text[..end]);
+    if end < text.len() {
+        block.push_str(&format!(
+            "\n[Truncated to the first {} of {} bytes.]",
+            end,
+            text.len()
+        ));
+    }
+    block.push_str("\n</file>");
+    block
+}
+
 impl Message {
     pub fn system(content: impl ToString) -> Self {
         Self {
@@ -258,6 +362,7 @@ impl Message {
             content: vec![Content::text(content)],
             tool_calls: None,
             reasoning_content: None,
+            reasoning_signature: None,
             tool_call_id: None,
         }
     }
@@ -285,6 +390,9 @@ impl Message {
                         .unwrap_or(&file_data)
                         .to_string();
                     content_vec.push(Content::audio_from_data(raw_data, format));
+                } else if let Some(text) = attached_text(&file_data) {
+                    let name = file.filename.as_deref().unwrap_or("attachment");
+                    content_vec.push(Content::text(inlined_file(name, &text)));
                 } else {
                     content_vec.push(Content::file_from_data(
                         file.filename,
@@ -299,6 +407,7 @@ impl Message {
             content: content_vec,
             tool_calls: None,
             reasoning_content: None,
+            reasoning_signature: None,
             tool_call_id: None,
         }
     }
@@ -309,6 +418,7 @@ impl Message {
             content: vec![Content::text(content)],
             tool_calls: None,
             reasoning_content: None,
+            reasoning_signature: None,
             tool_call_id: None,
         }
     }
@@ -327,6 +437,7 @@ impl Message {
             }],
             tool_calls: None,
             reasoning_content: None,
+            reasoning_signature: None,
             tool_call_id: Some(tool_use_id.to_string()),
         }
     }
@@ -350,7 +461,94 @@ pub struct CompletionRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub temperature: Option<f32>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub tools: Option<Vec<Tool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning_effort: Option<ReasoningEffort>,
+    /// Sequences that end the completion when generated.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
+    /// Seed for sampling, so that repeated requests give the same answer
+    /// where the provider supports it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+}
+
+/// Texts to turn into embedding vectors.
+#[derive(Debug, Clone, Serialize)]
+pub struct EmbeddingRequest {
+    pub model: String,
+    pub input: Vec<String>,
+}
+
+/// Sampling parameters chosen for a conversation. `None` leaves the
+/// provider's default in place.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SamplingParams {
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub max_tokens: Option<u32>,
+    /// Only sent to reasoning models.
+    #[serde(default)]
+    pub reasoning_effort: Option<ReasoningEffort>,
+    #[serde(default)]
+    pub stop: Vec<String>,
+    #[serde(default)]
+    pub seed: Option<u32>,
+    #[serde(default)]
+    pub presence_penalty: Option<f32>,
+    #[serde(default)]
+    pub frequency_penalty: Option<f32>,
+}
+
+/// How long a reasoning model thinks before it answers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReasoningEffort {
+    Low,
+    Medium,
+    High,
+}
+
+impl ReasoningEffort {
+    pub const ALL: [ReasoningEffort; 3] = [
+        ReasoningEffort::Low,
+        ReasoningEffort::Medium,
+        ReasoningEffort::High,
+    ];
+
+    /// The value the API takes, also used to store it.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReasoningEffort::Low => "low",
+            ReasoningEffort::Medium => "medium",
+            ReasoningEffort::High => "high",
+        }
+    }
+
+    /// The effort stored as `value` by [`Self::as_str`].
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|effort| effort.as_str() == value)
+    }
+}
+
+impl std::fmt::Display for ReasoningEffort {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReasoningEffort::Low => write!(f, "Low effort"),
+            ReasoningEffort::Medium => write!(f, "Medium effort"),
+            ReasoningEffort::High => write!(f, "High effort"),
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -373,6 +571,51 @@ pub struct CompletionResponse {
     pub created: u64,
     pub model: String,
     pub choices: Vec<Choice>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<TokenUsage>,
+}
+
+/// Tokens consumed by a completion, as reported by the provider.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+}
+
+impl std::ops::Add for TokenUsage {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            prompt_tokens: self.prompt_tokens + other.prompt_tokens,
+            completion_tokens: self.completion_tokens + other.completion_tokens,
+        }
+    }
+}
+
+/// How long a streamed completion took to arrive.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ResponseTiming {
+    /// Milliseconds from sending the request to the end of the response.
+    pub elapsed_ms: u64,
+    /// Milliseconds from the first streamed token to the end.
+    pub generation_ms: u64,
+    /// Tokens generated: the completion tokens reported, or those counted
+    /// locally when the provider reports none.
+    pub tokens: u32,
+}
+
+impl ResponseTiming {
+    /// Milliseconds until the first token arrived; `None` if none did.
+    pub fn first_token_ms(&self) -> Option<u64> {
+        (self.tokens > 0).then(|| self.elapsed_ms.saturating_sub(self.generation_ms))
+    }
+
+    /// Tokens generated per second, once generation took measurable time.
+    pub fn tokens_per_second(&self) -> Option<f64> {
+        (self.generation_ms > 0 && self.tokens > 0)
+            .then(|| f64::from(self.tokens) * 1000.0 / self.generation_ms as f64)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -383,13 +626,99 @@ pub struct Choice {
     pub finish_reason: String,
 }
 
+/// An incremental piece of a streamed completion.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompletionDelta {
+    /// More assistant text.
+    Text(String),
+    /// More reasoning text, for models that expose their thinking.
+    Reasoning(String),
+    /// The signature of the reasoning, see [`Message::reasoning_signature`].
+    ReasoningSignature(String),
+    /// A fragment of the tool call at `index`. `id` and `name` are usually
+    /// only present on the first fragment; `arguments` must be concatenated
+    /// across fragments.
+    ToolCall {
+        index: usize,
+        id: Option<String>,
+        name: Option<String>,
+        arguments: String,
+    },
+    /// Token counts for the whole completion, usually sent just before or
+    /// after it finishes.
+    Usage(TokenUsage),
+    /// The model finished generating.
+    Finished { finish_reason: String },
+}
+
+impl CompletionResponse {
+    /// Replay a complete response as deltas, for clients that cannot stream.
+    pub fn into_deltas(self) -> Vec<CompletionDelta> {
+        let Some(choice) = self.choices.into_iter().next() else {
+            return vec![];
+        };
+        let mut deltas = Vec::new();
+        let mut tool_calls = Vec::new();
+        for message in choice.message {
+            if let Some(reasoning) = message.reasoning_content {
+                deltas.push(CompletionDelta::Reasoning(reasoning));
+            }
+            if let Some(signature) = message.reasoning_signature {
+                deltas.push(CompletionDelta::ReasoningSignature(signature));
+            }
+            let text: String = message
+                .content
+                .iter()
+                .filter_map(|c| match c {
+                    Content::Text { text } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect();
+            if !text.is_empty() {
+                deltas.push(CompletionDelta::Text(text));
+            }
+            tool_calls.extend(message.tool_calls.unwrap_or_default());
+            // Providers with native tool-use blocks report calls as content.
+            tool_calls.extend(message.content.into_iter().filter_map(|c| match c {
+                Content::ToolUse { id, name, input } => Some(ToolCall {
+                    id,
+                    _type: "function".to_string(),
+                    function: ToolFunction {
+                        name,
+                        arguments: input.to_string(),
+                    },
+                }),
+                _ => None,
+            }));
+        }
+        deltas.extend(tool_calls.into_iter().enumerate().map(|(index, call)| {
+            CompletionDelta::ToolCall {
+                index,
+                id: Some(call.id),
+                name: Some(call.function.name),
+                arguments: call.function.arguments,
+            }
+        }));
+        deltas.extend(self.usage.map(CompletionDelta::Usage));
+        deltas.push(CompletionDelta::Finished {
+            finish_reason: choice.finish_reason,
+        });
+        deltas
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ToolCall {
     pub id: String,
-    #[serde(rename = "type")]
+    /// Always `function`; some OpenAI-compatible servers leave it out.
+    #[serde(rename = "type", default = "function_type")]
     pub _type: String,
     pub function: ToolFunction,
 }
+fn function_type() -> String {
+    "function".to_string()
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ToolFunction {
     pub name: String,
@@ -410,6 +739,7 @@ impl From<ToolCallResult> for Message {
             content: tool_call_result.contents,
             tool_calls: None,
             reasoning_content: None,
+            reasoning_signature: None,
             tool_call_id: Some(tool_call_result.id),
         }
     }
@@ -482,6 +812,40 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_text_files_are_sent_inline() {
+        let files = vec![
+            FileData {
+                filename: Some("notes.md".to_string()),
+                file_data: Some("data:text/markdown;base64,IyBOb3Rlcw==".to_string()),
+                file_id: None,
+            },
+            FileData {
+                filename: Some("binary.txt".to_string()),
+                file_data: Some("data:text/plain;base64,/w==".to_string()),
+                file_id: None,
+            },
+        ];
+        let message = Message::user("Summarize", Some(files));
+        assert_eq!(message.content.len(), 3);
+        match &message.content[1] {
+            Content::Text { text } => {
+                assert_eq!(text, "<file name=\"notes.md\">\n# Notes\n</file>")
+            }
+            _ => panic!("Expected Text variant"),
+        }
+        assert_eq!(message.content[1].attached_file_name(), Some("notes.md"));
+        assert_eq!(message.content[0].attached_file_name(), None);
+        // Not UTF-8, so it stays a file.
+        assert!(matches!(message.content[2], Content::File { .. }));
+
+        // Cut before the character the limit falls in.
+        let long = format!("{}é", "a".repeat(MAX_ATTACHED_TEXT_BYTES - 1));
+        let block = inlined_file("long.txt", &long);
+        assert!(block.ends_with("aaa\n[Truncated to the first 99999 of 100001 bytes.]\n</file>"));
+        assert_eq!(Content::text(block).attached_file_name(), Some("long.txt"));
+    }
+
     #[test]
     fn test_text_content_serialization() {
         let content = Content::text("Hello, GPT!");
@@ -552,6 +916,7 @@ mod tests {
             ],
             tool_calls: None,
             reasoning_content: None,
+            reasoning_signature: None,
             tool_call_id: None,
         };
 
@@ -614,6 +979,7 @@ mod tests {
             ],
             tool_calls: None,
             reasoning_content: None,
+            reasoning_signature: None,
             tool_call_id: None,
         };
 
@@ -672,6 +1038,7 @@ mod tests {
             ],
             tool_calls: None,
             reasoning_content: None,
+            reasoning_signature: None,
             tool_call_id: None,
         };
 
@@ -723,7 +1090,14 @@ mod tests {
             model: "gpt-4".to_string(),
             messages: vec![Message::user("Hello!", None)],
             temperature: Some(0.7),
+            top_p: None,
+            max_tokens: Some(256),
             tools: None,
+            reasoning_effort: None,
+            stop: vec![],
+            seed: None,
+            presence_penalty: None,
+            frequency_penalty: None,
         };
 
         let json = serde_json::to_value(&request).unwrap();
@@ -735,6 +1109,8 @@ mod tests {
         assert_eq!(json["messages"][0]["content"][0]["text"], "Hello!");
         // Check temperature exists and is close to 0.7 (floating point precision)
         assert!((json["temperature"].as_f64().unwrap() - 0.7).abs() < 0.01);
+        assert_eq!(json["max_tokens"], 256);
+        assert!(json.get("top_p").is_none());
     }
 
     #[test]
@@ -748,6 +1124,7 @@ mod tests {
             ],
             tool_calls: None,
             reasoning_content: None,
+            reasoning_signature: None,
             tool_call_id: None,
         };
 
@@ -859,4 +1236,133 @@ mod tests {
             _ => panic!("Expected Audio variant"),
         }
     }
+
+    #[test]
+    fn test_completion_response_into_deltas() {
+        let json = r#"{
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 0,
+            "model": "gpt-4o-mini",
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": "Checking the weather.",
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": {"name": "__srv__weather", "arguments": "{\"city\":\"Oslo\"}"}
+                    }]
+                },
+                "finish_reason": "tool_calls"
+            }],
+            "usage": {"prompt_tokens": 42, "completion_tokens": 7, "total_tokens": 49}
+        }"#;
+        let response: CompletionResponse = serde_json::from_str(json).unwrap();
+
+        assert_eq!(
+            response.into_deltas(),
+            vec![
+                CompletionDelta::Text("Checking the weather.".to_string()),
+                CompletionDelta::ToolCall {
+                    index: 0,
+                    id: Some("call_1".to_string()),
+                    name: Some("__srv__weather".to_string()),
+                    arguments: "{\"city\":\"Oslo\"}".to_string(),
+                },
+                CompletionDelta::Usage(TokenUsage {
+                    prompt_tokens: 42,
+                    completion_tokens: 7,
+                }),
+                CompletionDelta::Finished {
+                    finish_reason: "tool_calls".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tool_only_response_into_deltas() {
+        // No text, and a tool call without `type`, as some servers send.
+        let json = r#"{
+            "id": "chatcmpl-2",
+            "object": "chat.completion",
+            "created": 0,
+            "model": "llama-3.1-8b",
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "function": {"name": "weather", "arguments": "{}"}
+                    }]
+                },
+                "finish_reason": "tool_calls"
+            }]
+        }"#;
+        let response: CompletionResponse = serde_json::from_str(json).unwrap();
+        let message = &response.choices[0].message[0];
+        assert!(message.content.is_empty());
+        assert_eq!(message.tool_calls.as_ref().unwrap()[0]._type, "function");
+
+        assert_eq!(
+            response.into_deltas(),
+            vec![
+                CompletionDelta::ToolCall {
+                    index: 0,
+                    id: Some("call_1".to_string()),
+                    name: Some("weather".to_string()),
+                    arguments: "{}".to_string(),
+                },
+                CompletionDelta::Finished {
+                    finish_reason: "tool_calls".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tool_use_content_becomes_tool_call_delta() {
+        let response = CompletionResponse {
+            id: "msg_1".to_string(),
+            object: "anthropic.completion".to_string(),
+            created: 0,
+            model: "claude".to_string(),
+            choices: vec![Choice {
+                index: 0,
+                message: vec![Message {
+                    role: "assistant".to_string(),
+                    content: vec![Content::tool_use(
+                        "toolu_1",
+                        "__srv__weather",
+                        serde_json::json!({"city": "Oslo"}),
+                    )],
+                    tool_calls: None,
+                    reasoning_content: None,
+                    reasoning_signature: None,
+                    tool_call_id: None,
+                }],
+                finish_reason: "tool_use".to_string(),
+            }],
+            usage: None,
+        };
+
+        assert_eq!(
+            response.into_deltas(),
+            vec![
+                CompletionDelta::ToolCall {
+                    index: 0,
+                    id: Some("toolu_1".to_string()),
+                    name: Some("__srv__weather".to_string()),
+                    arguments: "{\"city\":\"Oslo\"}".to_string(),
+                },
+                CompletionDelta::Finished {
+                    finish_reason: "tool_use".to_string()
+                },
+            ]
+        );
+    }
 }
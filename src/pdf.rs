@@ -0,0 +1,99 @@
+//! Text extracted from attached PDFs. Few providers take PDF files, so the
+//! text of the pages the user picks is sent inline instead.
+
+use anyhow::{anyhow, Result};
+
+/// Pages of a longer document sent unless the user picks others.
+pub const DEFAULT_PAGES: usize = 20;
+
+/// The text of each page of the PDF in `bytes`.
+pub fn page_texts(bytes: &[u8]) -> Result<Vec<String>> {
+    // pdf-extract panics on some malformed documents.
+    std::panic::catch_unwind(|| pdf_extract::extract_text_from_mem_by_pages(bytes))
+        .map_err(|_| anyhow!("The PDF could not be read"))?
+        .map_err(|err| anyhow!("The PDF could not be read: {}", err))
+}
+
+/// Every page of a short document, the first [`DEFAULT_PAGES`] of a
+/// longer one, in the form [`parse_page_range`] takes.
+pub fn default_range(page_count: usize) -> String {
+    match page_count.min(DEFAULT_PAGES) {
+        0 | 1 => "1".to_string(),
+        last => format!("1-{}", last),
+    }
+}
+
+/// The pages, counted from 1, picked by `range`: pages and spans of pages
+/// separated by commas, such as `1-5, 8`. `None` if `range` is malformed,
+/// picks nothing or names a page past `page_count`.
+pub fn parse_page_range(range: &str, page_count: usize) -> Option<Vec<usize>> {
+    let mut pages = Vec::new();
+    for part in range
+        .split(',')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+    {
+        let (start, end): (usize, usize) = match part.split_once('-') {
+            Some((start, end)) => (start.trim().parse().ok()?, end.trim().parse().ok()?),
+            None => {
+                let page = part.parse().ok()?;
+                (page, page)
+            }
+        };
+        if start == 0 || start > end || end > page_count {
+            return None;
+        }
+        pages.extend(start..=end);
+    }
+    (!pages.is_empty()).then_some(pages)
+}
+
+/// The text of `pages` out of `texts`, each under a line naming the page.
+pub fn pages_text(texts: &[String], pages: &[usize]) -> String {
+    pages
+        .iter()
+        .filter_map(|&page| {
+            let text = texts.get(page.checked_sub(1)?)?;
+            Some(format!("--- Page {} ---\n{}", page, text.trim()))
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_ranges() {
+        assert_eq!(parse_page_range("1-3, 5", 10), Some(vec![1, 2, 3, 5]));
+        assert_eq!(parse_page_range(" 7 ", 10), Some(vec![7]));
+        assert_eq!(parse_page_range("0-2", 10), None);
+        assert_eq!(parse_page_range("3-1", 10), None);
+        assert_eq!(parse_page_range("9-11", 10), None);
+        assert_eq!(parse_page_range("1-", 10), None);
+        assert_eq!(parse_page_range(" , ", 10), None);
+
+        assert_eq!(default_range(1), "1");
+        assert_eq!(default_range(5), "1-5");
+        assert_eq!(default_range(300), "1-20");
+        assert_eq!(
+            parse_page_range(&default_range(300), 300).unwrap().len(),
+            20
+        );
+    }
+
+    #[test]
+    fn test_pages_text_names_each_page() {
+        let texts = vec!["One\n".to_string(), "Two".to_string(), "Three".to_string()];
+        assert_eq!(
+            pages_text(&texts, &[1, 3, 4]),
+            "--- Page 1 ---\nOne\n\n--- Page 3 ---\nThree"
+        );
+    }
+
+    #[test]
+    fn test_unreadable_pdf_is_an_error() {
+        assert!(page_texts(b"%PDF-1.4 not really").is_err());
+    }
+}
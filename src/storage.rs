@@ -0,0 +1,1226 @@
+//! SQLite-backed conversation history.
+//!
+//! Every conversation is stored in `~/.ergon/ergon.db` together with its
+//! messages, so chats survive restarts. Messages are kept as the JSON form of
+//! [`Message`] alongside their role, the time they were sent, the model
+//! that wrote them and the tokens it used. Their plain text is also kept in an FTS5 index for
+//! searching. The same database holds the knowledge base: passages of the
+//! files the user indexed with their embeddings.
+
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::{
+    config::ergon_dir,
+    models::{Message, ReasoningEffort, ResponseTiming, SamplingParams, TokenUsage},
+};
+
+const DATABASE_FILE: &str = "ergon.db";
+
+const SCHEMA: &str = "
+    PRAGMA foreign_keys = ON;
+    CREATE TABLE IF NOT EXISTS conversations (
+        id TEXT PRIMARY KEY,
+        title TEXT NOT NULL DEFAULT '',
+        model TEXT,
+        created_at INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS messages (
+        conversation_id TEXT NOT NULL REFERENCES conversations(id) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        role TEXT NOT NULL,
+        body TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        PRIMARY KEY (conversation_id, position)
+    );
+    CREATE VIRTUAL TABLE IF NOT EXISTS messages_fts USING fts5(
+        text,
+        conversation_id UNINDEXED,
+        position UNINDEXED
+    );
+";
+
+/// Schema changes applied after `SCHEMA`, in order. `PRAGMA user_version`
+/// records how many of them a database has already had.
+const MIGRATIONS: &[&str] = &[
+    "ALTER TABLE conversations ADD COLUMN system_prompt TEXT NOT NULL DEFAULT ''",
+    "ALTER TABLE conversations ADD COLUMN temperature REAL;
+     ALTER TABLE conversations ADD COLUMN top_p REAL;
+     ALTER TABLE conversations ADD COLUMN max_tokens INTEGER;",
+    "ALTER TABLE messages ADD COLUMN model TEXT",
+    "ALTER TABLE messages ADD COLUMN prompt_tokens INTEGER;
+     ALTER TABLE messages ADD COLUMN completion_tokens INTEGER;",
+    "ALTER TABLE conversations ADD COLUMN reasoning_effort TEXT",
+    "ALTER TABLE conversations ADD COLUMN stop_sequences TEXT",
+    "ALTER TABLE conversations ADD COLUMN seed INTEGER;
+     ALTER TABLE conversations ADD COLUMN presence_penalty REAL;
+     ALTER TABLE conversations ADD COLUMN frequency_penalty REAL;",
+    "CREATE TABLE knowledge_chunks (
+         collection TEXT NOT NULL,
+         source TEXT NOT NULL,
+         position INTEGER NOT NULL,
+         text TEXT NOT NULL,
+         model TEXT NOT NULL,
+         embedding BLOB NOT NULL
+     );
+     CREATE INDEX knowledge_chunks_model ON knowledge_chunks(model);",
+    "ALTER TABLE messages ADD COLUMN elapsed_ms INTEGER;
+     ALTER TABLE messages ADD COLUMN generation_ms INTEGER;
+     ALTER TABLE messages ADD COLUMN generated_tokens INTEGER;",
+    "ALTER TABLE conversations ADD COLUMN revision INTEGER NOT NULL DEFAULT 0",
+    "CREATE TABLE other_usage (
+         source TEXT NOT NULL,
+         created_at INTEGER NOT NULL,
+         model TEXT,
+         prompt_tokens INTEGER NOT NULL,
+         completion_tokens INTEGER NOT NULL
+     );",
+    "ALTER TABLE conversations ADD COLUMN summary TEXT;
+     ALTER TABLE conversations ADD COLUMN summary_covered INTEGER;",
+];
+
+/// Token usage of one completion, for spend tracking and reports.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UsageRecord {
+    /// The conversation the completion belongs to, or what asked for it
+    /// (such as the quick chat) if it belongs to none.
+    pub conversation_id: String,
+    /// Unix timestamp, in seconds, of the message the completion produced.
+    pub created_at: i64,
+    pub model: Option<String>,
+    pub usage: TokenUsage,
+}
+
+/// How long one streamed completion took, for response-time reports.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimingRecord {
+    /// Unix timestamp, in seconds, of the message the completion produced.
+    pub created_at: i64,
+    pub model: Option<String>,
+    pub timing: ResponseTiming,
+}
+
+/// Most search hits returned by [`Storage::search`].
+const MAX_SEARCH_HITS: usize = 50;
+
+/// A conversation loaded from the database.
+#[derive(Debug, Clone)]
+pub struct StoredConversation {
+    pub id: String,
+    pub title: String,
+    pub model: Option<String>,
+    /// Sent as a system message ahead of the transcript; empty for none.
+    pub system_prompt: String,
+    pub sampling: SamplingParams,
+    pub messages: Vec<StoredMessage>,
+    /// Revision of the last save, see [`Storage::save_conversation`].
+    pub revision: u64,
+    /// Summary of the oldest messages, sent in their place, and how many
+    /// messages it stands in for.
+    pub summary: Option<(String, usize)>,
+}
+
+/// A message together with when it was sent and who wrote it.
+#[derive(Debug, Clone)]
+pub struct StoredMessage {
+    pub message: Message,
+    /// Unix timestamp, in seconds, of when the message was sent or received.
+    pub created_at: i64,
+    /// The model or agent that wrote the message; `None` for the user's own
+    /// messages and for history saved before models were recorded.
+    pub model: Option<String>,
+    /// Tokens reported for the completion that produced the message.
+    pub usage: Option<TokenUsage>,
+    /// How long the message took to stream in.
+    pub timing: Option<ResponseTiming>,
+}
+
+impl From<Message> for StoredMessage {
+    /// A message sent just now by nobody in particular.
+    fn from(message: Message) -> Self {
+        Self {
+            message,
+            created_at: unix_now(),
+            model: None,
+            usage: None,
+            timing: None,
+        }
+    }
+}
+
+/// A row of the conversation list, without its messages.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConversationSummary {
+    pub id: String,
+    pub title: String,
+    /// Unix timestamp, in seconds, of the last save.
+    pub updated_at: i64,
+}
+
+/// A message matching a full-text search.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchHit {
+    pub conversation_id: String,
+    pub title: String,
+    /// Index of the matching message within its conversation.
+    pub position: usize,
+    /// The matching part of the message text.
+    pub snippet: String,
+}
+
+/// A passage of an indexed file with its embedding.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KnowledgeChunk {
+    /// Path of the file the passage was taken from.
+    pub source: String,
+    pub text: String,
+    pub embedding: Vec<f32>,
+}
+
+/// A file or folder the user added to the knowledge base.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KnowledgeCollection {
+    pub path: String,
+    pub files: usize,
+    pub chunks: usize,
+}
+
+/// Longest title derived from the first user message.
+const MAX_TITLE_CHARS: usize = 60;
+
+pub struct Storage {
+    connection: Mutex<Connection>,
+}
+
+impl Storage {
+    pub fn open(path: &Path) -> Result<Self> {
+        Self::with_connection(Connection::open(path)?)
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<Self> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(connection: Connection) -> Result<Self> {
+        connection.execute_batch(SCHEMA)?;
+        Self::migrate(&connection)?;
+        let storage = Self {
+            connection: Mutex::new(connection),
+        };
+        storage.build_search_index()?;
+        Ok(storage)
+    }
+
+    /// Apply the migrations the database has not had yet.
+    fn migrate(connection: &Connection) -> Result<()> {
+        let version: usize = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            connection.execute_batch(migration)?;
+            connection.pragma_update(None, "user_version", index + 1)?;
+        }
+        Ok(())
+    }
+
+    /// Index messages saved before the search index existed.
+    fn build_search_index(&self) -> Result<()> {
+        let mut connection = self.connection()?;
+        let indexed: i64 =
+            connection.query_row("SELECT count(*) FROM messages_fts", [], |row| row.get(0))?;
+        if indexed > 0 {
+            return Ok(());
+        }
+        let tx = connection.transaction()?;
+        {
+            let mut statement =
+                tx.prepare("SELECT conversation_id, position, body FROM messages")?;
+            let rows = statement.query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, String>(2)?,
+                ))
+            })?;
+            for row in rows {
+                let (id, position, body) = row?;
+                let message: Message = serde_json::from_str(&body)?;
+                Self::index_message(&tx, &id, position, &message)?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn index_message(
+        connection: &Connection,
+        id: &str,
+        position: i64,
+        message: &Message,
+    ) -> Result<()> {
+        let text: Vec<&str> = message
+            .text_content()
+            .into_iter()
+            .map(String::as_str)
+            .collect();
+        if text.is_empty() {
+            return Ok(());
+        }
+        connection.execute(
+            "INSERT INTO messages_fts (text, conversation_id, position) VALUES (?1, ?2, ?3)",
+            params![text.join("\n"), id, position],
+        )?;
+        Ok(())
+    }
+
+    fn connection(&self) -> Result<std::sync::MutexGuard<'_, Connection>> {
+        self.connection.lock().map_err(|e| anyhow!(e.to_string()))
+    }
+
+    /// Create or update conversation `id` so that it holds exactly
+    /// `messages`, `system_prompt` and `sampling`. Untitled conversations are
+    /// named after their first user message.
+    ///
+    /// Saves may finish out of order, so each carries a `revision` that
+    /// grows with every save of the conversation. A save no newer than the
+    /// stored one is dropped, and `false` returned.
+    pub fn save_conversation(
+        &self,
+        id: &str,
+        revision: u64,
+        model: Option<&str>,
+        system_prompt: &str,
+        sampling: &SamplingParams,
+        messages: &[StoredMessage],
+    ) -> Result<bool> {
+        let now = unix_now();
+        let mut connection = self.connection()?;
+        let tx = connection.transaction()?;
+        let stored: Option<i64> = tx
+            .query_row(
+                "SELECT revision FROM conversations WHERE id = ?1",
+                params![id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if stored.is_some_and(|stored| stored as u64 >= revision) {
+            return Ok(false);
+        }
+        tx.execute(
+            "INSERT INTO conversations (id, title, model, system_prompt, temperature, top_p,
+                                        max_tokens, reasoning_effort, stop_sequences, seed,
+                                        presence_penalty, frequency_penalty,
+                                        created_at, updated_at, revision)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?13, ?14)
+             ON CONFLICT(id) DO UPDATE SET
+                 title = CASE WHEN title = '' THEN excluded.title ELSE title END,
+                 model = COALESCE(excluded.model, model),
+                 system_prompt = excluded.system_prompt,
+                 temperature = excluded.temperature,
+                 top_p = excluded.top_p,
+                 max_tokens = excluded.max_tokens,
+                 reasoning_effort = excluded.reasoning_effort,
+                 stop_sequences = excluded.stop_sequences,
+                 seed = excluded.seed,
+                 presence_penalty = excluded.presence_penalty,
+                 frequency_penalty = excluded.frequency_penalty,
+                 updated_at = excluded.updated_at,
+                 revision = excluded.revision",
+            params![
+                id,
+                default_title(messages.iter().map(|m| &m.message)),
+                model,
+                system_prompt,
+                sampling.temperature,
+                sampling.top_p,
+                sampling.max_tokens,
+                sampling.reasoning_effort.map(|effort| effort.as_str()),
+                (!sampling.stop.is_empty()).then(|| serde_json::json!(sampling.stop).to_string()),
+                sampling.seed,
+                sampling.presence_penalty,
+                sampling.frequency_penalty,
+                now,
+                revision as i64
+            ],
+        )?;
+        Self::write_messages(&tx, id, messages)?;
+        tx.commit()?;
+        Ok(true)
+    }
+
+    /// Store a conversation brought in from another app with its own title
+    /// and timestamps, replacing any conversation already stored as `id`.
+    pub fn import_conversation(
+        &self,
+        id: &str,
+        title: &str,
+        created_at: i64,
+        updated_at: i64,
+        messages: &[Message],
+    ) -> Result<()> {
+        let mut connection = self.connection()?;
+        let tx = connection.transaction()?;
+        tx.execute(
+            "INSERT INTO conversations (id, title, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(id) DO UPDATE SET
+                 title = excluded.title,
+                 created_at = excluded.created_at,
+                 updated_at = excluded.updated_at",
+            params![id, title, created_at, updated_at],
+        )?;
+        let messages: Vec<StoredMessage> = messages
+            .iter()
+            .map(|message| StoredMessage {
+                message: message.clone(),
+                created_at,
+                model: None,
+                usage: None,
+                timing: None,
+            })
+            .collect();
+        Self::write_messages(&tx, id, &messages)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Make conversation `id` hold exactly `messages` and re-index their
+    /// text.
+    fn write_messages(connection: &Connection, id: &str, messages: &[StoredMessage]) -> Result<()> {
+        for (position, stored) in messages.iter().enumerate() {
+            connection.execute(
+                "INSERT INTO messages (conversation_id, position, role, body, created_at, model,
+                                       prompt_tokens, completion_tokens, elapsed_ms,
+                                       generation_ms, generated_tokens)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+                 ON CONFLICT(conversation_id, position) DO UPDATE SET
+                     role = excluded.role,
+                     body = excluded.body,
+                     created_at = excluded.created_at,
+                     model = excluded.model,
+                     prompt_tokens = excluded.prompt_tokens,
+                     completion_tokens = excluded.completion_tokens,
+                     elapsed_ms = excluded.elapsed_ms,
+                     generation_ms = excluded.generation_ms,
+                     generated_tokens = excluded.generated_tokens",
+                params![
+                    id,
+                    position as i64,
+                    stored.message.role,
+                    serde_json::to_string(&stored.message)?,
+                    stored.created_at,
+                    stored.model,
+                    stored.usage.map(|u| u.prompt_tokens),
+                    stored.usage.map(|u| u.completion_tokens),
+                    stored.timing.map(|t| t.elapsed_ms as i64),
+                    stored.timing.map(|t| t.generation_ms as i64),
+                    stored.timing.map(|t| t.tokens)
+                ],
+            )?;
+        }
+        connection.execute(
+            "DELETE FROM messages WHERE conversation_id = ?1 AND position >= ?2",
+            params![id, messages.len() as i64],
+        )?;
+        connection.execute(
+            "DELETE FROM messages_fts WHERE conversation_id = ?1",
+            params![id],
+        )?;
+        for (position, stored) in messages.iter().enumerate() {
+            Self::index_message(connection, id, position as i64, &stored.message)?;
+        }
+        Ok(())
+    }
+
+    /// Replace the summary of conversation `id` with `summary` and the
+    /// number of messages it covers, or remove it.
+    pub fn set_summary(&self, id: &str, summary: Option<(&str, usize)>) -> Result<()> {
+        let (text, covered) = summary.unzip();
+        self.connection()?.execute(
+            "UPDATE conversations SET summary = ?2, summary_covered = ?3 WHERE id = ?1",
+            params![id, text, covered.map(|covered| covered as i64)],
+        )?;
+        Ok(())
+    }
+
+    /// Replace the title of conversation `id`.
+    pub fn set_title(&self, id: &str, title: &str) -> Result<()> {
+        self.connection()?.execute(
+            "UPDATE conversations SET title = ?2 WHERE id = ?1",
+            params![id, title],
+        )?;
+        Ok(())
+    }
+
+    /// Messages whose text matches every word of `query`, best matches
+    /// first. Each word also matches as a prefix, so results update sensibly
+    /// while the user is still typing.
+    pub fn search(&self, query: &str) -> Result<Vec<SearchHit>> {
+        let Some(query) = fts_query(query) else {
+            return Ok(vec![]);
+        };
+        let connection = self.connection()?;
+        let mut statement = connection.prepare(
+            "SELECT f.conversation_id, c.title, f.position,
+                    snippet(messages_fts, 0, '', '', '…', 12)
+             FROM messages_fts f
+             JOIN conversations c ON c.id = f.conversation_id
+             WHERE messages_fts MATCH ?1
+             ORDER BY rank
+             LIMIT ?2",
+        )?;
+        let rows = statement.query_map(params![query, MAX_SEARCH_HITS as i64], |row| {
+            Ok(SearchHit {
+                conversation_id: row.get(0)?,
+                title: row.get(1)?,
+                position: row.get::<_, i64>(2)? as usize,
+                snippet: row.get(3)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Record the usage of a completion that is not part of any stored
+    /// conversation, so it still counts towards spend. `source` says what
+    /// asked for it and stands in for the conversation id in reports.
+    pub fn record_usage(
+        &self,
+        source: &str,
+        model: Option<&str>,
+        usage: &TokenUsage,
+    ) -> Result<()> {
+        self.connection()?.execute(
+            "INSERT INTO other_usage (source, created_at, model, prompt_tokens, completion_tokens)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                source,
+                unix_now(),
+                model,
+                usage.prompt_tokens,
+                usage.completion_tokens
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Usage of every stored completion produced at or after `since`,
+    /// oldest first, including that recorded with
+    /// [`Storage::record_usage`].
+    pub fn usage_since(&self, since: i64) -> Result<Vec<UsageRecord>> {
+        let connection = self.connection()?;
+        let mut statement = connection.prepare(
+            "SELECT conversation_id, created_at, model, prompt_tokens, completion_tokens, position
+             FROM messages
+             WHERE created_at >= ?1 AND prompt_tokens IS NOT NULL
+             UNION ALL
+             SELECT source, created_at, model, prompt_tokens, completion_tokens, 0
+             FROM other_usage
+             WHERE created_at >= ?1
+             ORDER BY 2, 1, 6",
+        )?;
+        let rows = statement.query_map(params![since], |row| {
+            Ok(UsageRecord {
+                conversation_id: row.get(0)?,
+                created_at: row.get(1)?,
+                model: row.get(2)?,
+                usage: TokenUsage {
+                    prompt_tokens: row.get(3)?,
+                    completion_tokens: row.get::<_, Option<u32>>(4)?.unwrap_or_default(),
+                },
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Timing of every streamed completion saved since the Unix timestamp
+    /// `since`, oldest first.
+    pub fn timings_since(&self, since: i64) -> Result<Vec<TimingRecord>> {
+        let connection = self.connection()?;
+        let mut statement = connection.prepare(
+            "SELECT created_at, model, elapsed_ms, generation_ms, generated_tokens
+             FROM messages
+             WHERE created_at >= ?1 AND elapsed_ms IS NOT NULL
+             ORDER BY created_at, conversation_id, position",
+        )?;
+        let rows = statement.query_map(params![since], |row| {
+            Ok(TimingRecord {
+                created_at: row.get(0)?,
+                model: row.get(1)?,
+                timing: ResponseTiming {
+                    elapsed_ms: row.get::<_, i64>(2)? as u64,
+                    generation_ms: row.get::<_, Option<i64>>(3)?.unwrap_or_default() as u64,
+                    tokens: row.get::<_, Option<u32>>(4)?.unwrap_or_default(),
+                },
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Make `collection` hold exactly `chunks`, embedded with `model`.
+    pub fn replace_knowledge(
+        &self,
+        collection: &str,
+        model: &str,
+        chunks: &[KnowledgeChunk],
+    ) -> Result<()> {
+        let mut connection = self.connection()?;
+        let tx = connection.transaction()?;
+        tx.execute(
+            "DELETE FROM knowledge_chunks WHERE collection = ?1",
+            params![collection],
+        )?;
+        for (position, chunk) in chunks.iter().enumerate() {
+            let embedding: Vec<u8> = chunk
+                .embedding
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect();
+            tx.execute(
+                "INSERT INTO knowledge_chunks (collection, source, position, text, model, embedding)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    collection,
+                    chunk.source,
+                    position as i64,
+                    chunk.text,
+                    model,
+                    embedding
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// The first `limit` passages embedded with `model`. Vectors of other
+    /// models can't be compared with its.
+    pub fn knowledge_chunks(&self, model: &str, limit: usize) -> Result<Vec<KnowledgeChunk>> {
+        let connection = self.connection()?;
+        let mut statement = connection.prepare(
+            "SELECT source, text, embedding FROM knowledge_chunks
+             WHERE model = ?1 ORDER BY collection, position LIMIT ?2",
+        )?;
+        let rows = statement.query_map(params![model, limit as i64], |row| {
+            let embedding: Vec<u8> = row.get(2)?;
+            Ok(KnowledgeChunk {
+                source: row.get(0)?,
+                text: row.get(1)?,
+                embedding: embedding
+                    .chunks_exact(4)
+                    .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                    .collect(),
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Everything in the knowledge base, by the path the user added.
+    pub fn knowledge_collections(&self) -> Result<Vec<KnowledgeCollection>> {
+        let connection = self.connection()?;
+        let mut statement = connection.prepare(
+            "SELECT collection, count(DISTINCT source), count(*) FROM knowledge_chunks
+             GROUP BY collection ORDER BY collection",
+        )?;
+        let rows = statement.query_map([], |row| {
+            Ok(KnowledgeCollection {
+                path: row.get(0)?,
+                files: row.get::<_, i64>(1)? as usize,
+                chunks: row.get::<_, i64>(2)? as usize,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Drop `collection` from the knowledge base.
+    pub fn remove_knowledge(&self, collection: &str) -> Result<()> {
+        self.connection()?.execute(
+            "DELETE FROM knowledge_chunks WHERE collection = ?1",
+            params![collection],
+        )?;
+        Ok(())
+    }
+
+    /// Every stored conversation, most recently updated first.
+    pub fn list_conversations(&self) -> Result<Vec<ConversationSummary>> {
+        let connection = self.connection()?;
+        let mut statement = connection.prepare(
+            "SELECT id, title, updated_at FROM conversations
+             ORDER BY updated_at DESC, rowid DESC",
+        )?;
+        let rows = statement.query_map([], |row| {
+            Ok(ConversationSummary {
+                id: row.get(0)?,
+                title: row.get(1)?,
+                updated_at: row.get(2)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// The most recently updated conversation, if any.
+    pub fn latest_conversation(&self) -> Result<Option<StoredConversation>> {
+        let id = self
+            .connection()?
+            .query_row(
+                "SELECT id FROM conversations ORDER BY updated_at DESC, rowid DESC LIMIT 1",
+                [],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()?;
+        match id {
+            Some(id) => self.load_conversation(&id),
+            None => Ok(None),
+        }
+    }
+
+    /// Conversation `id` with all of its messages, if it exists.
+    pub fn load_conversation(&self, id: &str) -> Result<Option<StoredConversation>> {
+        let connection = self.connection()?;
+        let row = connection
+            .query_row(
+                "SELECT title, model, system_prompt, temperature, top_p, max_tokens,
+                        reasoning_effort, stop_sequences, seed, presence_penalty,
+                        frequency_penalty, revision, summary, summary_covered
+                 FROM conversations WHERE id = ?1",
+                params![id],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, Option<String>>(1)?,
+                        row.get::<_, String>(2)?,
+                        SamplingParams {
+                            temperature: row.get(3)?,
+                            top_p: row.get(4)?,
+                            max_tokens: row.get(5)?,
+                            reasoning_effort: row
+                                .get::<_, Option<String>>(6)?
+                                .as_deref()
+                                .and_then(ReasoningEffort::parse),
+                            stop: row
+                                .get::<_, Option<String>>(7)?
+                                .and_then(|stop| serde_json::from_str(&stop).ok())
+                                .unwrap_or_default(),
+                            seed: row.get(8)?,
+                            presence_penalty: row.get(9)?,
+                            frequency_penalty: row.get(10)?,
+                        },
+                        row.get::<_, i64>(11)? as u64,
+                        match (
+                            row.get::<_, Option<String>>(12)?,
+                            row.get::<_, Option<i64>>(13)?,
+                        ) {
+                            (Some(text), Some(covered)) => Some((text, covered as usize)),
+                            _ => None,
+                        },
+                    ))
+                },
+            )
+            .optional()?;
+        let Some((title, model, system_prompt, sampling, revision, summary)) = row else {
+            return Ok(None);
+        };
+        let messages = Self::load_messages(&connection, id)?;
+        Ok(Some(StoredConversation {
+            id: id.to_string(),
+            title,
+            model,
+            system_prompt,
+            sampling,
+            messages,
+            revision,
+            summary,
+        }))
+    }
+
+    fn load_messages(connection: &Connection, id: &str) -> Result<Vec<StoredMessage>> {
+        let mut statement = connection.prepare(
+            "SELECT body, created_at, model, prompt_tokens, completion_tokens, elapsed_ms,
+                    generation_ms, generated_tokens FROM messages
+             WHERE conversation_id = ?1 ORDER BY position",
+        )?;
+        let rows = statement.query_map(params![id], |row| {
+            let usage = match (row.get(3)?, row.get(4)?) {
+                (Some(prompt_tokens), Some(completion_tokens)) => Some(TokenUsage {
+                    prompt_tokens,
+                    completion_tokens,
+                }),
+                _ => None,
+            };
+            let timing = match (row.get::<_, Option<i64>>(5)?, row.get::<_, Option<i64>>(6)?) {
+                (Some(elapsed_ms), Some(generation_ms)) => Some(ResponseTiming {
+                    elapsed_ms: elapsed_ms as u64,
+                    generation_ms: generation_ms as u64,
+                    tokens: row.get::<_, Option<u32>>(7)?.unwrap_or(0),
+                }),
+                _ => None,
+            };
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                usage,
+                timing,
+            ))
+        })?;
+        rows.map(|row| {
+            let (body, created_at, model, usage, timing) = row?;
+            Ok(StoredMessage {
+                message: serde_json::from_str(&body)?,
+                created_at,
+                model,
+                usage,
+                timing,
+            })
+        })
+        .collect()
+    }
+}
+
+/// Turn free text typed by the user into an FTS5 query: every word quoted,
+/// so punctuation is taken literally, and matched as a prefix.
+fn fts_query(text: &str) -> Option<String> {
+    let terms: Vec<String> = text
+        .split_whitespace()
+        .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+        .collect();
+    (!terms.is_empty()).then(|| terms.join(" "))
+}
+
+/// The first line of the first user message, shortened to
+/// `MAX_TITLE_CHARS`.
+fn default_title<'a>(messages: impl Iterator<Item = &'a Message>) -> String {
+    let Some(text) = messages
+        .filter(|m| m.role == "user")
+        .find_map(|m| m.content.iter().find_map(|c| c.as_text()))
+    else {
+        return String::new();
+    };
+    let line = text.lines().next().unwrap_or_default().trim();
+    if line.chars().count() <= MAX_TITLE_CHARS {
+        line.to_string()
+    } else {
+        let short: String = line.chars().take(MAX_TITLE_CHARS - 1).collect();
+        format!("{}…", short.trim_end())
+    }
+}
+
+/// A fresh random conversation id.
+pub fn new_conversation_id() -> String {
+    format!("{:016x}", rand::random::<u64>())
+}
+
+/// Current time as Unix seconds.
+pub fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+/// The `(year, month, day)` that lies `days` days after 1970-01-01, in the
+/// proleptic Gregorian calendar.
+pub fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Unix seconds at the start of the calendar month, in UTC, that contains
+/// the Unix time `seconds`.
+pub fn month_start(seconds: i64) -> i64 {
+    let days = seconds.div_euclid(86_400);
+    let (_, _, day) = civil_from_days(days);
+    (days - (day - 1)) * 86_400
+}
+
+static STORAGE: OnceLock<Result<Storage, String>> = OnceLock::new();
+
+/// The shared conversation store, opened on first use.
+pub fn get_storage() -> Result<&'static Storage> {
+    STORAGE
+        .get_or_init(|| Storage::open(&ergon_dir().join(DATABASE_FILE)).map_err(|e| e.to_string()))
+        .as_ref()
+        .map_err(|e| anyhow!("Failed to open conversation database: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Save with no model, system prompt or sampling parameters, newer
+    /// than every earlier save.
+    fn save(storage: &Storage, id: &str, messages: &[Message]) -> Result<()> {
+        static REVISION: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(1);
+        let revision = REVISION.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let messages: Vec<StoredMessage> = messages.iter().cloned().map(Into::into).collect();
+        storage.save_conversation(
+            id,
+            revision,
+            None,
+            "",
+            &SamplingParams::default(),
+            &messages,
+        )?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_and_load_latest_conversation() -> Result<()> {
+        let storage = Storage::open_in_memory()?;
+        assert!(storage.latest_conversation()?.is_none());
+
+        let messages = vec![
+            StoredMessage {
+                message: Message::user("Hi", None),
+                created_at: 1_000,
+                model: None,
+                usage: None,
+                timing: None,
+            },
+            StoredMessage {
+                message: Message::assistant("Hello!"),
+                created_at: 1_005,
+                model: Some("gpt-4o-mini".to_string()),
+                usage: Some(TokenUsage {
+                    prompt_tokens: 8,
+                    completion_tokens: 2,
+                }),
+                timing: Some(ResponseTiming {
+                    elapsed_ms: 1_500,
+                    generation_ms: 1_000,
+                    tokens: 2,
+                }),
+            },
+        ];
+        let sampling = SamplingParams {
+            temperature: Some(0.5),
+            top_p: None,
+            max_tokens: Some(512),
+            reasoning_effort: Some(ReasoningEffort::Medium),
+            stop: vec!["\n\nUser:".to_string()],
+            seed: Some(1234),
+            presence_penalty: None,
+            frequency_penalty: Some(0.5),
+        };
+        storage.save_conversation(
+            "a",
+            1,
+            Some("gpt-4o-mini"),
+            "Be brief.",
+            &sampling,
+            &messages,
+        )?;
+
+        let loaded = storage.latest_conversation()?.unwrap();
+        assert_eq!(loaded.id, "a");
+        assert_eq!(loaded.model.as_deref(), Some("gpt-4o-mini"));
+        assert_eq!(loaded.system_prompt, "Be brief.");
+        assert_eq!(loaded.revision, 1);
+        assert_eq!(loaded.sampling, sampling);
+        assert_eq!(loaded.messages.len(), 2);
+        let reply = &loaded.messages[1];
+        assert_eq!(reply.message.role, "assistant");
+        assert_eq!(reply.created_at, 1_005);
+        assert_eq!(reply.model.as_deref(), Some("gpt-4o-mini"));
+        assert_eq!(reply.usage.map(|u| u.completion_tokens), Some(2));
+        assert_eq!(reply.timing, messages[1].timing);
+        assert_eq!(loaded.messages[0].model, None);
+        assert_eq!(loaded.messages[0].usage, None);
+        assert_eq!(loaded.messages[0].timing, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_replaces_message_list() -> Result<()> {
+        let storage = Storage::open_in_memory()?;
+        let messages = vec![
+            Message::user("one", None),
+            Message::assistant("two"),
+            Message::user("three", None),
+        ];
+        save(&storage, "a", &messages)?;
+        save(&storage, "a", &messages[..1])?;
+        save(&storage, "b", &messages[..2])?;
+
+        let latest = storage.latest_conversation()?.unwrap();
+        assert_eq!(latest.id, "b");
+        let first = storage.load_conversation("a")?.unwrap();
+        assert_eq!(first.messages.len(), 1);
+        assert!(storage.load_conversation("missing")?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_stale_save_is_dropped() -> Result<()> {
+        let storage = Storage::open_in_memory()?;
+        let sampling = SamplingParams::default();
+        let newer: Vec<StoredMessage> = vec![
+            Message::user("Hi", None).into(),
+            Message::assistant("Hello!").into(),
+        ];
+        assert!(storage.save_conversation("a", 2, None, "", &sampling, &newer)?);
+        // An older snapshot finishing last doesn't overwrite the newer one.
+        assert!(!storage.save_conversation("a", 1, None, "", &sampling, &newer[..1])?);
+
+        let loaded = storage.load_conversation("a")?.unwrap();
+        assert_eq!(loaded.messages.len(), 2);
+        assert_eq!(loaded.revision, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_summary_round_trip() -> Result<()> {
+        let storage = Storage::open_in_memory()?;
+        save(&storage, "a", &[Message::user("Hi", None)])?;
+        assert_eq!(storage.load_conversation("a")?.unwrap().summary, None);
+
+        storage.set_summary("a", Some(("They said hi.", 1)))?;
+        let loaded = storage.load_conversation("a")?.unwrap();
+        assert_eq!(loaded.summary, Some(("They said hi.".to_string(), 1)));
+
+        storage.set_summary("a", None)?;
+        assert_eq!(storage.load_conversation("a")?.unwrap().summary, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_usage_since() -> Result<()> {
+        let storage = Storage::open_in_memory()?;
+        let usage = TokenUsage {
+            prompt_tokens: 10,
+            completion_tokens: 4,
+        };
+        let reply = |created_at| StoredMessage {
+            message: Message::assistant("Hello!"),
+            created_at,
+            model: Some("gpt-4o".to_string()),
+            usage: Some(usage),
+            timing: None,
+        };
+        let messages = vec![
+            reply(100),
+            StoredMessage {
+                created_at: 150,
+                ..Message::user("Thanks", None).into()
+            },
+            reply(200),
+        ];
+        storage.save_conversation("a", 1, None, "", &SamplingParams::default(), &messages)?;
+
+        let records = storage.usage_since(150)?;
+        assert_eq!(
+            records,
+            vec![UsageRecord {
+                conversation_id: "a".to_string(),
+                created_at: 200,
+                model: Some("gpt-4o".to_string()),
+                usage,
+            }]
+        );
+
+        storage.record_usage("quick chat", Some("gpt-4o-mini"), &usage)?;
+        let records = storage.usage_since(150)?;
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].conversation_id, "quick chat");
+        assert_eq!(records[1].model.as_deref(), Some("gpt-4o-mini"));
+        assert_eq!(records[1].usage, usage);
+        Ok(())
+    }
+
+    #[test]
+    fn test_timings_since() -> Result<()> {
+        let storage = Storage::open_in_memory()?;
+        let timing = ResponseTiming {
+            elapsed_ms: 2_000,
+            generation_ms: 1_500,
+            tokens: 30,
+        };
+        let messages = vec![
+            StoredMessage {
+                created_at: 100,
+                ..Message::user("Hi", None).into()
+            },
+            StoredMessage {
+                created_at: 105,
+                model: Some("gpt-4o".to_string()),
+                timing: Some(timing),
+                ..Message::assistant("Hello!").into()
+            },
+        ];
+        storage.save_conversation("a", 1, None, "", &SamplingParams::default(), &messages)?;
+
+        assert_eq!(
+            storage.timings_since(0)?,
+            vec![TimingRecord {
+                created_at: 105,
+                model: Some("gpt-4o".to_string()),
+                timing,
+            }]
+        );
+        assert!(storage.timings_since(106)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_month_start() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        // 2024-03-15 10:00 UTC falls in the month starting 2024-03-01.
+        assert_eq!(month_start(1_710_496_800), 1_709_251_200);
+        assert_eq!(month_start(1_709_251_200), 1_709_251_200);
+    }
+
+    #[test]
+    fn test_migrations_run_once() -> Result<()> {
+        let connection = Connection::open_in_memory()?;
+        connection.execute_batch(SCHEMA)?;
+        Storage::migrate(&connection)?;
+        // A second run finds nothing left to apply.
+        Storage::migrate(&connection)?;
+        let version: usize = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        assert_eq!(version, MIGRATIONS.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_conversations_titles_from_first_user_message() -> Result<()> {
+        let storage = Storage::open_in_memory()?;
+        save(
+            &storage,
+            "a",
+            &[Message::user("Plan a trip\nto Rome", None)],
+        )?;
+        save(&storage, "b", &[Message::assistant("Hello!")])?;
+        save(
+            &storage,
+            "b",
+            &[Message::assistant("Hello!"), Message::user("Why?", None)],
+        )?;
+
+        let list = storage.list_conversations()?;
+        let titles: Vec<_> = list
+            .iter()
+            .map(|c| (c.id.as_str(), c.title.as_str()))
+            .collect();
+        assert_eq!(titles, vec![("b", "Why?"), ("a", "Plan a trip")]);
+
+        // Once set, a title is not replaced by later saves.
+        save(&storage, "a", &[Message::user("Something else", None)])?;
+        let first = storage.list_conversations()?;
+        assert_eq!(
+            first.iter().find(|c| c.id == "a").unwrap().title,
+            "Plan a trip"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_knowledge_collections() -> Result<()> {
+        let storage = Storage::open_in_memory()?;
+        let chunk = |source: &str, text: &str, embedding: Vec<f32>| KnowledgeChunk {
+            source: source.to_string(),
+            text: text.to_string(),
+            embedding,
+        };
+        storage.replace_knowledge(
+            "/notes",
+            "text-embedding-3-small",
+            &[
+                chunk("/notes/a.md", "Alpha", vec![0.5, -1.25]),
+                chunk("/notes/a.md", "More alpha", vec![1.0, 0.0]),
+                chunk("/notes/b.md", "Beta", vec![0.0, 1.0]),
+            ],
+        )?;
+        storage.replace_knowledge(
+            "/todo.txt",
+            "nomic-embed-text",
+            &[chunk("/todo.txt", "Milk", vec![1.0, 2.0, 3.0])],
+        )?;
+
+        let chunks = storage.knowledge_chunks("text-embedding-3-small", 10)?;
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[0], chunk("/notes/a.md", "Alpha", vec![0.5, -1.25]));
+        assert_eq!(
+            storage.knowledge_chunks("text-embedding-3-small", 2)?.len(),
+            2
+        );
+        assert_eq!(
+            storage.knowledge_collections()?,
+            vec![
+                KnowledgeCollection {
+                    path: "/notes".to_string(),
+                    files: 2,
+                    chunks: 3,
+                },
+                KnowledgeCollection {
+                    path: "/todo.txt".to_string(),
+                    files: 1,
+                    chunks: 1,
+                },
+            ]
+        );
+
+        // Indexing a collection again replaces what it held.
+        storage.replace_knowledge(
+            "/notes",
+            "text-embedding-3-small",
+            &[chunk("/notes/b.md", "Beta", vec![0.0, 1.0])],
+        )?;
+        assert_eq!(
+            storage
+                .knowledge_chunks("text-embedding-3-small", 10)?
+                .len(),
+            1
+        );
+        storage.remove_knowledge("/notes")?;
+        assert!(storage
+            .knowledge_chunks("text-embedding-3-small", 10)?
+            .is_empty());
+        assert_eq!(storage.knowledge_collections()?.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_finds_messages_by_prefix() -> Result<()> {
+        let storage = Storage::open_in_memory()?;
+        save(
+            &storage,
+            "a",
+            &[
+                Message::user("How do lifetimes work?", None),
+                Message::assistant("A lifetime names a region of code (\"scope\")."),
+            ],
+        )?;
+        save(&storage, "b", &[Message::user("Best pasta recipes", None)])?;
+
+        let hits = storage.search("lifetime")?;
+        assert_eq!(hits.len(), 2);
+        assert!(hits.iter().all(|h| h.conversation_id == "a"));
+        assert!(hits.iter().any(|h| h.position == 1));
+
+        let hits = storage.search("pas")?;
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].title, "Best pasta recipes");
+
+        // Quotes and operators are searched for literally.
+        assert_eq!(storage.search("\"scope\" AND")?.len(), 0);
+        assert!(storage.search("   ")?.is_empty());
+
+        // Re-saving replaces the indexed text.
+        save(&storage, "b", &[Message::user("Risotto", None)])?;
+        assert!(storage.search("pasta")?.is_empty());
+        Ok(())
+    }
+}
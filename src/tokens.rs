@@ -0,0 +1,153 @@
+//! Prompt tokens counted locally, to show how much of the model's context
+//! window a message would use before it is sent.
+//!
+//! Counts use OpenAI's encodings: exact for OpenAI models, close enough
+//! for others to warn before a prompt overflows the context window.
+
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use tiktoken_rs::{
+    tokenizer::{get_tokenizer, Tokenizer},
+    CoreBPE,
+};
+
+use crate::models::{Content, Message};
+
+/// Tokens framing each message, besides its content.
+const TOKENS_PER_MESSAGE: usize = 4;
+/// Tokens priming the reply.
+const TOKENS_PER_REPLY: usize = 3;
+/// Tokens of an attached image at high detail, the most providers charge
+/// for one of ordinary size.
+const TOKENS_PER_IMAGE: usize = 765;
+
+/// The encoding of `model_id`: o200k for OpenAI's newer models, cl100k for
+/// the rest.
+fn encoding(model_id: &str) -> &'static CoreBPE {
+    // Routers name models like `openai/gpt-4o`.
+    let name = model_id.rsplit('/').next().unwrap_or(model_id);
+    match get_tokenizer(name) {
+        Some(Tokenizer::O200kBase) => tiktoken_rs::o200k_base_singleton(),
+        _ => tiktoken_rs::cl100k_base_singleton(),
+    }
+}
+
+/// Tokens of `text` for `model_id`.
+pub fn count_text(model_id: &str, text: &str) -> usize {
+    encoding(model_id).encode_ordinary(text).len()
+}
+
+/// The text of `message` that is encoded, and the number of images it
+/// carries.
+fn countable(message: &Message) -> (String, usize) {
+    let mut text = message.role.clone();
+    let mut images = 0;
+    for content in &message.content {
+        match content {
+            Content::Text { text: part } => text.push_str(part),
+            Content::ToolResult { content, .. } => text.push_str(content),
+            Content::ToolUse { name, input, .. } => {
+                text.push_str(name);
+                text.push_str(&input.to_string());
+            }
+            Content::ImageUrl { .. } => images += 1,
+            _ => {}
+        }
+    }
+    for call in message.tool_calls.iter().flatten() {
+        text.push_str(&call.function.name);
+        text.push_str(&call.function.arguments);
+    }
+    (text, images)
+}
+
+/// Counts the tokens of a transcript, remembering each message's count so
+/// that only new or edited messages are encoded again.
+#[derive(Debug, Clone, Default)]
+pub struct TokenCounter {
+    /// Tokens of the messages last counted, by a hash of the model and the
+    /// message's text.
+    counted: HashMap<u64, usize>,
+}
+
+impl TokenCounter {
+    /// Tokens `messages` take up when sent to `model_id`.
+    pub fn count<'a>(
+        &mut self,
+        model_id: &str,
+        messages: impl IntoIterator<Item = &'a Message>,
+    ) -> usize {
+        let mut counted = HashMap::new();
+        let mut total = TOKENS_PER_REPLY;
+        for message in messages {
+            let (text, images) = countable(message);
+            let mut hasher = DefaultHasher::new();
+            (model_id, &text).hash(&mut hasher);
+            let key = hasher.finish();
+            let tokens = match self.counted.get(&key) {
+                Some(&tokens) => tokens,
+                None => count_text(model_id, &text),
+            };
+            counted.insert(key, tokens);
+            total += tokens + TOKENS_PER_MESSAGE + images * TOKENS_PER_IMAGE;
+        }
+        self.counted = counted;
+        total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encoding_follows_the_model() {
+        let text = "Hello, world! Ergon counts tokens locally.";
+        assert_eq!(count_text("gpt-4", "Hello world"), 2);
+        assert_eq!(
+            count_text("gpt-4o", text),
+            count_text("openai/gpt-4o", text)
+        );
+        assert_eq!(
+            count_text("claude-sonnet-4", text),
+            count_text("gpt-4", text)
+        );
+        assert_eq!(count_text("gpt-4o", ""), 0);
+    }
+
+    #[test]
+    fn test_messages_are_counted_with_their_framing() {
+        let mut counter = TokenCounter::default();
+        assert_eq!(counter.count("gpt-4", &[]), TOKENS_PER_REPLY);
+
+        let system = Message::system("Be brief.");
+        let user = Message::user("Hello world", None);
+        let expected = TOKENS_PER_REPLY
+            + count_text("gpt-4", "systemBe brief.")
+            + count_text("gpt-4", "userHello world")
+            + 2 * TOKENS_PER_MESSAGE;
+        assert_eq!(counter.count("gpt-4", [&system, &user]), expected);
+        assert_eq!(counter.counted.len(), 2);
+        // Counting again gives the same total from the cache.
+        assert_eq!(counter.count("gpt-4", [&system, &user]), expected);
+        // Messages no longer in the transcript are forgotten.
+        let user_only =
+            TOKENS_PER_REPLY + count_text("gpt-4", "userHello world") + TOKENS_PER_MESSAGE;
+        assert_eq!(counter.count("gpt-4", [&user]), user_only);
+        assert_eq!(counter.counted.len(), 1);
+
+        let mut with_image = Message::user("Look", None);
+        with_image
+            .content
+            .push(Content::image_url("data:image/png;base64,AAAA"));
+        assert_eq!(
+            counter.count("gpt-4", [&with_image]),
+            TOKENS_PER_REPLY
+                + count_text("gpt-4", "userLook")
+                + TOKENS_PER_MESSAGE
+                + TOKENS_PER_IMAGE
+        );
+        assert_eq!(counter.counted.len(), 1);
+    }
+}
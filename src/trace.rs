@@ -0,0 +1,74 @@
+//! Turns of a conversation, traced. The completion requests and tool calls
+//! made for one turn run inside spans carrying its id, as do the lines the
+//! audit log writes for them, so one turn's events can be told apart from
+//! another's.
+//!
+//! No `tracing` subscriber is installed: spans and events are forwarded to
+//! the logger set up in `main`.
+
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use iced::futures::{stream, Stream, StreamExt};
+use tracing::{Instrument, Span};
+
+static NEXT_TURN: AtomicU64 = AtomicU64::new(1);
+
+tokio::task_local! {
+    /// The turn the task is working for.
+    static TURN: u64;
+}
+
+/// Id for a new turn, unique while the app runs.
+pub fn new_turn() -> u64 {
+    NEXT_TURN.fetch_add(1, Ordering::Relaxed)
+}
+
+/// The turn the running task is working for, if any.
+pub fn current_turn() -> Option<u64> {
+    TURN.try_with(|turn| *turn).ok()
+}
+
+/// Run `future` for `turn`, inside `span`.
+pub async fn in_turn<F: Future>(turn: u64, span: Span, future: F) -> F::Output {
+    TURN.scope(turn, future).instrument(span).await
+}
+
+/// Poll `stream` for `turn`, inside `span`.
+pub fn stream_in_turn<S: Stream>(
+    turn: u64,
+    span: Span,
+    stream: S,
+) -> impl Stream<Item = S::Item> {
+    stream::unfold(Box::pin(stream), move |mut stream| {
+        let span = span.clone();
+        async move {
+            let item = in_turn(turn, span, stream.next()).await?;
+            Some((item, stream))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_work_knows_its_turn() {
+        let turn = new_turn();
+        assert_ne!(new_turn(), turn);
+        assert_eq!(current_turn(), None);
+
+        let seen = in_turn(turn, Span::none(), async { current_turn() }).await;
+        assert_eq!(seen, Some(turn));
+        let seen: Vec<_> = stream_in_turn(
+            turn,
+            Span::none(),
+            stream::iter([1, 2]).map(|_| current_turn()),
+        )
+        .collect()
+        .await;
+        assert_eq!(seen, vec![Some(turn), Some(turn)]);
+        assert_eq!(current_turn(), None);
+    }
+}
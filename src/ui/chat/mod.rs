@@ -1,6 +1,10 @@
 mod models;
 mod state;
 mod tasks;
-pub use models::{ChatAction, ChatTarget};
+mod viewer;
+pub use models::{ChatAction, ChatMessage, ChatTarget, StreamingMessage};
 pub use state::State;
-pub use tasks::{call_tool, complete_message, load_models, load_tools, prompt_agent, start_agent};
+pub use tasks::{
+    call_tool, load_models, load_tools, prompt_agent, record_usage, refresh_models, refresh_tools,
+    start_agent, stream_message,
+};
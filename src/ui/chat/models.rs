@@ -1,15 +1,41 @@
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 
-use iced::widget::markdown;
+use base64::Engine as _;
+use iced::widget::{image, markdown, scrollable, text_editor};
 
 use crate::acp::AgentEvent;
-use crate::models::{CompletionResponse, Message, ModelInfo, Tool, ToolCall, ToolCallResult};
-use crate::ui::chat::tasks::{AgentPromptOutcome, AgentStartOutcome};
+use crate::api::clients::CompletionError;
+use crate::knowledge::Passage;
+use crate::mcp::{
+    handler::{ElicitationRequest, SamplingRequest, ToolProgress},
+    McpPrompt, McpResource, ServerStatus,
+};
+use crate::models::{
+    CompletionDelta, Content, Message, ModelInfo, ReasoningEffort, ResponseTiming, TokenUsage,
+    Tool, ToolCall, ToolCallResult,
+};
+use crate::storage::{unix_now, StoredConversation, StoredMessage};
+use crate::ui::chat::tasks::{AgentPromptOutcome, AgentStartOutcome, CommandEvent};
+use crate::workspace::Workspace;
+
+/// Role of the notes the chat adds to the transcript for the user alone.
+const NOTE_ROLE: &str = "note";
 
 #[derive(Debug, Clone)]
 pub struct ChatMessage {
     pub message: Message,
     pub markdown_items: Vec<markdown::Item>,
+    /// Unix timestamp, in seconds, of when the message was sent or received.
+    pub created_at: i64,
+    /// The model or agent that wrote the message, if any.
+    pub model: Option<String>,
+    /// Tokens reported for the completion that produced the message.
+    pub usage: Option<TokenUsage>,
+    /// How long the message took to stream in.
+    pub timing: Option<ResponseTiming>,
+    /// Thumbnails of the images attached to the message, decoded once.
+    pub images: Vec<image::Handle>,
 }
 
 impl ChatMessage {
@@ -22,14 +48,37 @@ impl ChatMessage {
             content: vec![crate::models::Content::text(text.clone())],
             tool_calls: None,
             reasoning_content: None,
+            reasoning_signature: None,
             tool_call_id: None,
         };
         Self {
             markdown_items: markdown::parse(&text).collect(),
             message,
+            created_at: unix_now(),
+            model: None,
+            usage: None,
+            timing: None,
+            images: vec![],
         }
     }
 
+    /// A note in the transcript, such as a switch to a fallback model, that
+    /// is only shown and never sent to the model.
+    pub fn note(text: impl Into<String>) -> Self {
+        Self::from_role_and_text(NOTE_ROLE, text)
+    }
+
+    /// Whether the message is a [`ChatMessage::note`].
+    pub fn is_note(&self) -> bool {
+        self.message.role == NOTE_ROLE
+    }
+
+    /// Attribute the message to `model`.
+    pub fn with_model(mut self, model: Option<String>) -> Self {
+        self.model = model;
+        self
+    }
+
     /// Append more text to the underlying message and re-parse markdown.
     /// Used for streaming agent message chunks.
     pub fn append_text(&mut self, more: &str) {
@@ -71,6 +120,8 @@ impl From<Message> for ChatMessage {
             .content
             .clone()
             .iter()
+            // Inlined files are shown as chips instead.
+            .filter(|c| c.attached_file_name().is_none())
             .flat_map(|c| {
                 match c.as_text() {
                     Some(text) => markdown::parse(&text).collect::<Vec<_>>(),
@@ -79,10 +130,115 @@ impl From<Message> for ChatMessage {
                 .into_iter()
             })
             .collect();
-        log::info!("Parsed markdown items: {:?}", markdown_items);
+        tracing::info!("Parsed markdown items: {:?}", markdown_items);
         Self {
             markdown_items,
+            images: image_handles(&message),
             message,
+            created_at: unix_now(),
+            model: None,
+            usage: None,
+            timing: None,
+        }
+    }
+}
+
+/// Handles for the images in `message` that are sent inline as `data:`
+/// URLs, the way attachments are.
+pub fn image_handles(message: &Message) -> Vec<image::Handle> {
+    message
+        .content
+        .iter()
+        .filter_map(|content| match content {
+            Content::ImageUrl { image_url } => {
+                let (_, data) = image_url
+                    .url
+                    .strip_prefix("data:image/")?
+                    .split_once(";base64,")?;
+                let bytes = base64::engine::general_purpose::STANDARD
+                    .decode(data)
+                    .ok()?;
+                Some(image::Handle::from_bytes(bytes))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+impl From<StoredMessage> for ChatMessage {
+    fn from(stored: StoredMessage) -> Self {
+        Self {
+            created_at: stored.created_at,
+            timing: stored.timing,
+            model: stored.model,
+            usage: stored.usage,
+            ..Self::from(stored.message)
+        }
+    }
+}
+
+impl From<&ChatMessage> for StoredMessage {
+    fn from(chat_message: &ChatMessage) -> Self {
+        Self {
+            message: chat_message.message.clone(),
+            timing: chat_message.timing,
+            created_at: chat_message.created_at,
+            model: chat_message.model.clone(),
+            usage: chat_message.usage,
+        }
+    }
+}
+
+/// The assistant message of an in-flight streamed LLM response.
+///
+/// Markdown is parsed incrementally as text arrives, so each delta only
+/// re-parses the unfinished tail of the message rather than the whole
+/// transcript. The message joins `State::messages` once the stream ends.
+#[derive(Debug, Default)]
+pub struct StreamingMessage {
+    text: String,
+    content: markdown::Content,
+}
+
+impl StreamingMessage {
+    pub fn push_str(&mut self, text: &str) {
+        self.text.push_str(text);
+        self.content.push_str(text);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn markdown_items(&self) -> &[markdown::Item] {
+        self.content.items()
+    }
+
+    /// Turn the streamed text into a regular assistant message, keeping the
+    /// already-parsed markdown.
+    pub fn into_chat_message(self) -> ChatMessage {
+        ChatMessage {
+            markdown_items: self.content.items().to_vec(),
+            message: Message::assistant(self.text),
+            created_at: unix_now(),
+            model: None,
+            usage: None,
+            timing: None,
+            images: vec![],
+        }
+    }
+}
+
+impl Clone for StreamingMessage {
+    fn clone(&self) -> Self {
+        // `markdown::Content` is not `Clone`; parse the text from scratch.
+        Self {
+            text: self.text.clone(),
+            content: markdown::Content::parse(&self.text),
         }
     }
 }
@@ -106,19 +262,249 @@ impl std::fmt::Display for ChatTarget {
     }
 }
 
+/// The part of the transcript in view, in logical pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TranscriptViewport {
+    /// Distance from the top of the transcript to the top of the view.
+    pub offset: f32,
+    /// Distance from the bottom of the view to the end of the transcript.
+    pub remaining: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Default for TranscriptViewport {
+    /// A window-sized view at the end of the transcript, assumed until the
+    /// transcript is first laid out.
+    fn default() -> Self {
+        Self {
+            offset: 0.0,
+            remaining: 0.0,
+            width: 800.0,
+            height: 600.0,
+        }
+    }
+}
+
+impl From<scrollable::Viewport> for TranscriptViewport {
+    fn from(viewport: scrollable::Viewport) -> Self {
+        let bounds = viewport.bounds();
+        Self {
+            offset: viewport.absolute_offset().y,
+            remaining: viewport.absolute_offset_reversed().y,
+            width: bounds.width,
+            height: bounds.height,
+        }
+    }
+}
+
+/// A sampling parameter input next to the model picker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SamplingField {
+    Temperature,
+    TopP,
+    MaxTokens,
+    /// Comma-separated stop sequences.
+    Stop,
+    Seed,
+    PresencePenalty,
+    FrequencyPenalty,
+}
+
 #[derive(Debug, Clone)]
 pub enum ChatAction {
     InputChanged(String),
     SendMessage,
-    ResponseReceived(CompletionResponse),
+    /// A delta (or error) from the in-flight streamed LLM response.
+    StreamDelta(Result<CompletionDelta, CompletionError>),
+    /// The in-flight streamed LLM response ended.
+    StreamFinished,
+    /// User clicked "Stop": abort the in-flight response, keeping whatever
+    /// has arrived so far.
+    StopGeneration,
     ModelSelected(String),
     ModelsLoaded(Vec<ModelInfo>),
     ToolsLoaaded(Vec<Tool>),
+    /// The model manager published a new model list.
+    ModelsChanged(Vec<ModelInfo>),
+    /// The tool manager published a new tool list.
+    ToolsChanged(Vec<Tool>),
+    /// The MCP servers started lazily for the pending request are up,
+    /// offering these tools.
+    DeferredServersStarted(Vec<Tool>),
+    /// The tool manager published new MCP server statuses.
+    McpStatusChanged(BTreeMap<String, ServerStatus>),
+    /// Periodic tick asking for the model list to be re-fetched.
+    RefreshModels,
+    /// The last conversation was read from the database at startup.
+    ConversationLoaded(Option<StoredConversation>),
+    /// Replace the transcript with the stored conversation with this id,
+    /// optionally scrolling to the message at the given position.
+    OpenConversation(String, Option<usize>),
+    /// A conversation requested by `OpenConversation` was read.
+    ConversationOpened(Option<StoredConversation>, Option<usize>),
+    /// Scroll to and highlight the message at this position.
+    ShowMessage(usize),
+    /// The transcript was scrolled or resized.
+    TranscriptScrolled(TranscriptViewport),
+    /// User clicked "Jump to latest" while scrolled back.
+    JumpToLatest,
+    /// Send the request that failed again.
+    RetryRequest,
+    /// Hide the error banner without retrying.
+    DismissError,
+    /// Clear the transcript and start a fresh conversation.
+    NewConversation,
+    /// The transcript was written to the database.
+    ConversationSaved,
+    /// Expand or collapse the system prompt editor.
+    ToggleSystemPrompt,
+    /// User edited the system prompt.
+    SystemPromptEdited(text_editor::Action),
+    /// User edited a sampling parameter. Empty text means the provider
+    /// default.
+    SamplingChanged(SamplingField, String),
+    /// User picked how hard a reasoning model should think.
+    ReasoningEffortSelected(ReasoningEffort),
+    /// User picked a model to answer the next turn only.
+    NextTurnModelSelected(String),
+    /// User added a model to answer each prompt alongside the selected one.
+    CompareModelAdded(String),
+    /// User stopped comparing the selected model with this one.
+    CompareModelRemoved(String),
+    /// One of the models the prompt of comparison `u64` was sent to
+    /// replied; `None` if it was stopped before anything arrived.
+    ComparisonReplied(u64, Result<Option<ChatMessage>, String>),
+    /// Let the conversation's model answer the next turn again.
+    ClearNextTurnModel,
+    /// Start editing the user message at this position.
+    EditMessage(usize),
+    /// User changed the text of the message being edited.
+    EditedMessageChanged(text_editor::Action),
+    /// Resend the edited message, dropping every message after it.
+    ResendEditedMessage,
+    /// Discard the edit, leaving the message as it was.
+    CancelEdit,
+    /// Put the raw markdown of the message at this position on the
+    /// clipboard.
+    CopyMessage(usize),
+    /// Put the contents of a code block on the clipboard.
+    CopyCode(String),
+    /// Save a code block to a file picked by the user. Carries the fence
+    /// language, if any, and the code.
+    SaveCode(Option<String>, String),
+    /// The code block was saved. `Ok(None)` means the dialog was cancelled.
+    CodeSaved(Result<Option<PathBuf>, String>),
+    /// Copy the conversation up to the message at this position into a new
+    /// stored conversation.
+    BranchFrom(usize),
+    /// The branch was stored under this id; `None` if saving failed. The
+    /// app shell opens it in a new tab.
+    ConversationBranched(Option<String>),
+    /// This month's estimated spend across all conversations was computed.
+    MonthSpendLoaded(f64),
+    /// A title was generated and stored for the conversation with this id.
+    TitleGenerated(String, Option<String>),
+    /// The messages of the conversation with this id up to the position
+    /// given were summarized, or the summary failed.
+    SummaryGenerated(String, usize, Option<String>),
     UrlClicked(String),
     CallTool(ToolCall),
+    /// User approved the pending tool call with this id.
+    ApproveToolCall(String),
+    /// User denied the pending tool call with this id; the model receives an
+    /// error result instead.
+    DenyToolCall(String),
+    /// An MCP server asked for a completion from the selected model.
+    SamplingRequested(SamplingRequest),
+    /// User let the server of the first pending sampling request use the
+    /// selected model.
+    ApproveSampling,
+    /// User denied the first pending sampling request.
+    DenySampling,
+    /// The usage of an approved sampling request was recorded.
+    SamplingUsageRecorded,
+    /// An MCP server asked the user to fill in a form.
+    ElicitationRequested(ElicitationRequest),
+    /// User edited the field at this position of the form in front.
+    ElicitationFieldChanged(usize, String),
+    /// Send the form in front to its server.
+    SubmitElicitation,
+    /// Refuse to fill in the form in front; the server carries on.
+    DeclineElicitation,
+    /// Close the form in front and stop what the server asked it for.
+    CancelElicitation,
+    /// An MCP server reported how far a tool call has got.
+    ToolProgressed(ToolProgress),
+    /// Expand or collapse the transcript block for the tool call with this id.
+    ToggleToolBlock(String),
+    /// Expand or collapse the thinking of the message at this position.
+    ToggleThinking(usize),
     ToolResponseReceived(Result<ToolCallResult, (String, String)>),
+    /// The shell command run for the tool call with this id wrote a line or
+    /// exited.
+    CommandEvent(String, CommandEvent),
     OpenFileDialog,
+    /// User clicked "Export": save the transcript as a standalone HTML file.
+    ExportHtml,
+    /// The export finished. `Ok(None)` means the save dialog was cancelled.
+    HtmlExported(Result<Option<PathBuf>, String>),
     FileSelected(Option<Vec<PathBuf>>),
+    /// Drop the pending attachment at this position.
+    RemoveAttachment(usize),
+    /// A PDF picked as an attachment was read: its file name and the text
+    /// of each page.
+    PdfRead(Result<(String, Vec<String>), String>),
+    /// User edited the pages to send of the attached PDF at this position.
+    PdfPagesChanged(usize, String),
+    /// Drop the attached PDF at this position.
+    RemovePdf(usize),
+    /// The tool manager published the resources the MCP servers offer.
+    McpResourcesChanged(Vec<McpResource>),
+    /// User picked a resource suggested for the `@` mention being typed.
+    ResourceMentioned(McpResource),
+    /// A mentioned resource was read: the resource and its text.
+    ResourceRead(Result<(McpResource, String), String>),
+    /// Drop the attached resource at this position.
+    RemoveResource(usize),
+    /// The tool manager published the prompts the MCP servers offer.
+    McpPromptsChanged(Vec<McpPrompt>),
+    /// User picked a prompt suggested for the `/` command being typed.
+    PromptSelected(McpPrompt),
+    /// User edited the value of an argument of the pending prompt.
+    PromptArgumentChanged(String, String),
+    /// Render the pending prompt with the arguments entered.
+    RunPrompt,
+    /// Discard the pending prompt.
+    CancelPrompt,
+    /// The server rendered the prompt into these messages.
+    PromptFetched(Result<Vec<Message>, String>),
+    /// User clicked the camera button: capture part of the screen.
+    CaptureScreenshot,
+    /// The capture finished. `Ok(None)` means it was cancelled.
+    ScreenshotCaptured(Result<Option<PathBuf>, String>),
+    /// Attach the previewed screenshot to the next message.
+    AttachScreenshot,
+    /// Throw the previewed screenshot away.
+    DiscardScreenshot,
+    /// User toggled retrieval from the knowledge base.
+    ToggleKnowledge,
+    /// Passages were retrieved for the message just sent.
+    KnowledgeRetrieved(Result<Vec<Passage>, String>),
+    /// User clicked the folder button: pick a folder as the workspace.
+    PickWorkspace,
+    /// The folder was listed. `Ok(None)` means the dialog was cancelled.
+    WorkspacePicked(Result<Option<Workspace>, String>),
+    /// Detach the workspace from the conversation.
+    CloseWorkspace,
+    /// User picked a conversation template by name.
+    TemplateSelected(String),
+    /// User edited the value of a template variable.
+    TemplateVariableChanged(String, String),
+    /// Start a new conversation from the pending template.
+    StartTemplate,
+    /// Discard the pending template without touching the transcript.
+    CancelTemplate,
 
     // ── ACP agent path ────────────────────────────────────────────────
     /// User picked a chat target (LLM or Agent(name)).
@@ -146,7 +532,9 @@ pub enum ChatAction {
     SlashCommandSelected(String),
     /// User clicked the "Resume last session" button. Triggers `resume_agent`
     /// for the named agent using the stored session id from `Config`.
-    ResumeAgent { agent: String },
+    ResumeAgent {
+        agent: String,
+    },
     /// `resume_agent` finished.
     AgentResumed {
         agent: String,
@@ -1,47 +1,199 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::time::Instant;
 
 use base64::Engine as _;
 
 use iced::{
     futures::{stream, StreamExt},
     widget::{
-        button, column, container, markdown, pick_list, row, scrollable, text, text_input, Row,
+        button, center, checkbox, column, container, image, markdown, opaque,
+        operation::{self, AbsoluteOffset},
+        pick_list, progress_bar, row, scrollable, space, stack, text, text_editor, text_input,
+        tooltip, Row,
     },
     Alignment, Element,
     Length::{self, Fill, Shrink},
     Subscription, Task, Theme,
 };
 use iced_aw::Spinner;
-use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::wrappers::{BroadcastStream, WatchStream};
+use tokio_util::sync::CancellationToken;
 
 use crate::{
     acp::{get_agent_manager, AgentEvent, AgentUpdate, AuthMethodInfo, AvailableCommand, StopReason},
-    api::clients::get_model_manager,
-    config::Config,
+    api::clients::{get_model_manager, CompletionError},
+    images,
+    knowledge::{self, retrieve, Passage},
+    mcp::{
+        builtin, get_tool_manager,
+        handler::{ElicitationRequest, FieldKind, SamplingRequest, ToolProgress},
+        McpPrompt, McpResource, ServerStatus,
+    },
+    config::{Budget, Config, ConversationTemplate, ModelPricing, ToolPolicy},
+    export::{conversation_html, pretty_json},
     models::{
-        Clients, CompletionResponse, FileData, Message, ModelInfo, Tool, ToolCall, ToolCallResult,
+        Clients, CompletionDelta, Content, FileData, Message, ModelCapabilities, ModelInfo,
+        ReasoningEffort, ResponseTiming, SamplingParams, TokenUsage, Tool, ToolCall,
+        ToolCallResult, ToolFunction,
     },
+    pdf,
+    storage::{civil_from_days, new_conversation_id, unix_now, StoredConversation, StoredMessage},
+    tokens::{self, TokenCounter},
+    trace,
     ui::chat::{
-        call_tool, complete_message, load_models, load_tools, models::ChatMessage, prompt_agent,
-        start_agent,
+        call_tool, load_models, load_tools,
+        models::{image_handles, ChatMessage, SamplingField, StreamingMessage, TranscriptViewport},
+        prompt_agent, refresh_models, start_agent, stream_message,
         tasks::{
-            authenticate_agent, current_session_info, persist_agent_session, resume_agent,
-            AgentPromptOutcome, AgentResumeOutcome, AgentStartOutcome,
+            authenticate_agent, branch_conversation, cancel_agent, capture_screenshot,
+            compare_reply, current_session_info, export_html, generate_title, get_prompt,
+            load_conversation, load_latest_conversation, load_month_spend, persist_agent_session,
+            pick_workspace, read_pdf, read_resource, read_workspace_file, resume_agent,
+            run_command, save_code, save_conversation, start_deferred_servers, summarize,
+            AgentPromptOutcome, AgentResumeOutcome, AgentStartOutcome, CommandEvent,
         },
+        viewer::MessageViewer,
         ChatAction, ChatTarget,
     },
+    ui::servers::status_style,
+    workspace::{self, Workspace},
 };
 
 #[derive(Debug, Default, Clone)]
 pub struct State {
     messages: Vec<ChatMessage>,
     input_value: String,
+    /// Tokens the next request would send, the draft included. `None`
+    /// without a model or before there is anything to send.
+    prompt_tokens: Option<usize>,
+    token_counter: TokenCounter,
     awaiting_response: bool,
     selected_model: Option<ModelInfo>,
+    /// Model picked in the transcript to answer the next turn; the
+    /// conversation keeps `selected_model`.
+    next_turn_model: Option<ModelInfo>,
+    /// Model answering the current turn in place of `selected_model`.
+    turn_model: Option<ModelInfo>,
+    /// Models that also answer each prompt, for comparing their replies
+    /// with the selected model's.
+    compare_models: Vec<String>,
+    /// Replies still expected from a prompt sent to several models.
+    comparison: Option<PendingComparison>,
+    /// Comparisons started so far, which number them so that replies to
+    /// a stopped one are told apart from the next one's.
+    comparisons: u64,
     available_models: Vec<ModelInfo>,
     available_tools: Vec<Tool>,
+    /// Connection status of each MCP server by name.
+    mcp_status: BTreeMap<String, ServerStatus>,
+    /// Resources the MCP servers offer, suggested after an `@` in the input.
+    mcp_resources: Vec<McpResource>,
+    /// Prompts the MCP servers offer, suggested after a `/` in the input.
+    mcp_prompts: Vec<McpPrompt>,
+    /// Prompt picked by the user that is still waiting for its arguments.
+    pending_prompt: Option<PendingPrompt>,
     pending_tool_calls: HashSet<String>,
+    /// Last progress reported on the pending tool calls, by call id.
+    tool_progress: HashMap<String, ToolProgress>,
+    /// Tool calls requested by the model that are waiting for the user to
+    /// approve or deny them, in the order they were requested.
+    pending_approvals: VecDeque<ToolCall>,
+    /// Sampling requests from MCP servers waiting for the user, oldest
+    /// first.
+    pending_sampling: VecDeque<SamplingRequest>,
+    /// Forms MCP servers asked the user to fill in, oldest first.
+    pending_elicitation: VecDeque<PendingElicitation>,
+    /// Shell commands the model asked for that are still running.
+    running_commands: Vec<RunningCommand>,
+    /// Tool call ids whose transcript blocks are expanded.
+    expanded_tool_blocks: HashSet<String>,
+    /// Positions of the messages whose thinking is expanded.
+    expanded_thinking: HashSet<usize>,
+    /// Whether the user scrolled back from the end of the transcript. New
+    /// output is followed only while it is at the end.
+    scrolled_back: bool,
+    /// The part of the transcript last in view.
+    viewport: TranscriptViewport,
+    /// Per-tool execution policies mirrored from `Config::tool_policies`.
+    tool_policies: HashMap<String, ToolPolicy>,
+    /// Mirrored from `Config::max_tool_iterations`.
+    max_tool_iterations: u32,
+    /// Per-model prices mirrored from `Config::pricing`.
+    pricing: HashMap<String, ModelPricing>,
+    /// Mirrored from `Config::budget`.
+    budget: Budget,
+    /// Mirrored from `Config::fallback_models`.
+    fallback_models: Vec<String>,
+    /// Mirrored from `Config::summarize_after`.
+    summarize_after: u32,
+    /// Stands in for the oldest messages in requests once the conversation
+    /// grows past `summarize_after`.
+    summary: Option<Summary>,
+    /// End of the messages being summarized, while a summary is in flight.
+    summarizing: Option<usize>,
+    /// The last request's failure, shown in a banner until it is retried
+    /// or dismissed.
+    error: Option<RequestError>,
+    /// A note on the current turn, like a model that failed and the one
+    /// that answered instead, shown until the next turn. Kept out of the
+    /// transcript, which is sent.
+    notice: Option<String>,
+    /// Estimated spend this month across all conversations, reloaded after
+    /// every save.
+    month_spend: f64,
+    /// Tool-calling rounds so far in the current turn.
+    tool_iterations: u32,
+    /// Id of the current turn, traced with its requests and tool calls.
+    turn: u64,
+    /// The LLM response currently being streamed, if any.
+    pending_response: Option<PendingResponse>,
     files: Option<Vec<FileData>>,
+    /// PDFs attached to the next message, sent as the text of the pages
+    /// picked.
+    pdfs: Vec<PdfAttachment>,
+    /// MCP resources mentioned in the next message, sent as text files.
+    resources: Vec<ResourceAttachment>,
+    /// A screenshot waiting to be attached or discarded, saved to a
+    /// temporary file, with its preview.
+    screenshot: Option<(PathBuf, image::Handle)>,
+    /// Whether each message is sent with passages from the knowledge base.
+    use_knowledge: bool,
+    /// Passages retrieved for the last user message, sent with every
+    /// request of its turn.
+    knowledge: Vec<Passage>,
+    /// Folder attached to the conversation, whose files the model can read.
+    workspace: Option<Workspace>,
+    /// Database id of the conversation on screen. Empty until it is first
+    /// saved.
+    conversation_id: String,
+    /// Revision of the last save of the conversation, bumped on every save
+    /// so the database can drop ones that finish out of order.
+    revision: u64,
+    /// Title generated for the conversation after its first exchange.
+    title: Option<String>,
+    /// Whether a title was already requested (or loaded) for this
+    /// conversation.
+    title_requested: bool,
+    /// Message highlighted after jumping to a search hit.
+    highlighted_message: Option<usize>,
+    /// Sent as a system message ahead of the transcript on every LLM
+    /// request, and saved with the conversation.
+    system_prompt: text_editor::Content,
+    /// Whether the system prompt editor is expanded.
+    system_prompt_open: bool,
+    /// Mirrored from `Config::default_system_prompt`; new conversations
+    /// start with it.
+    default_system_prompt: String,
+    /// Sampling parameters for this conversation, as typed.
+    sampling: SamplingInputs,
+    /// Position of the user message being edited, with its draft text.
+    editing: Option<(usize, text_editor::Content)>,
+    /// Conversation templates mirrored from `Config::templates`.
+    available_templates: Vec<ConversationTemplate>,
+    /// Template picked by the user that is still waiting for its variables.
+    pending_template: Option<PendingTemplate>,
 
     // ── ACP agent path ────────────────────────────────────────────────
     /// Where the next prompt is routed. Defaults to LLM.
@@ -65,9 +217,339 @@ pub struct State {
     plan_message_index: Option<usize>,
 }
 
+/// Id of the transcript's scrollable, for jumping to a message.
+const MESSAGE_LIST: &str = "messages";
+/// Distance from the end of the transcript, in logical pixels, within which
+/// it still counts as scrolled to the end.
+const SCROLL_END_SLACK: f32 = 20.0;
+/// Most MCP resources suggested for an `@` mention at once.
+const MAX_RESOURCE_SUGGESTIONS: usize = 8;
+/// Transcripts longer than this render only the messages near the view.
+const VIRTUALIZE_AFTER: usize = 50;
+/// Rough size of transcript text, for estimating the height of messages
+/// that are not rendered.
+const CHAR_WIDTH: f32 = 8.0;
+const LINE_HEIGHT: f32 = 21.0;
+/// Spacing between the rows of the transcript.
+const ROW_SPACING: f32 = 10.0;
+
+/// Longest tab label taken from the first user message.
+const TAB_TITLE_CHARS: usize = 24;
+/// Output kept from a shell command; the rest is dropped.
+const MAX_COMMAND_OUTPUT: usize = 100_000;
+/// Error shown when the user leaves a conversation whose turn is running.
+const SWITCH_WHILE_BUSY: &str = "Stop the current response before switching conversations.";
+/// Error shown when the budget blocks a request.
+const BUDGET_REACHED: &str =
+    "Monthly budget reached. Raise the limit in Settings to send more requests.";
+
+/// Accumulates a streamed LLM response until its stream ends.
+#[derive(Debug, Default, Clone)]
+struct PendingResponse {
+    /// Text streamed so far, rendered below the transcript until the
+    /// stream ends.
+    message: StreamingMessage,
+    /// Tool calls assembled from their streamed fragments, by index.
+    tool_calls: Vec<ToolCall>,
+    /// Reasoning text streamed alongside the answer.
+    reasoning: String,
+    /// Signature of the reasoning, for providers that sign it.
+    reasoning_signature: Option<String>,
+    /// Token counts reported for the response.
+    usage: Option<TokenUsage>,
+    /// When the request was sent and when its first token arrived.
+    started_at: Option<Instant>,
+    first_token_at: Option<Instant>,
+    /// Tokens streamed so far, counted locally.
+    streamed_tokens: usize,
+    /// Length of the transcript when the request was sent.
+    transcript_len: usize,
+    /// Set when the stream failed; the error has already been shown.
+    failed: bool,
+    /// Models that already failed this request, so a fallback chain never
+    /// comes back to them.
+    failed_models: Vec<String>,
+    /// Fallback to resend the request to once this stream finishes.
+    retry_with: Option<ModelInfo>,
+    /// Cancelled by the Stop button to abort the request.
+    cancel: CancellationToken,
+}
+
+impl PendingResponse {
+    /// Timing of the response up to `now`. Throughput is measured from the
+    /// first token, so it leaves out the wait for the model to start.
+    fn timing(&self, now: Instant) -> Option<ResponseTiming> {
+        let since = |instant: Instant| now.duration_since(instant).as_millis() as u64;
+        let tokens = match self.usage {
+            Some(usage) if usage.completion_tokens > 0 => usage.completion_tokens,
+            _ => self.streamed_tokens as u32,
+        };
+        Some(ResponseTiming {
+            elapsed_ms: since(self.started_at?),
+            generation_ms: self.first_token_at.map_or(0, since),
+            tokens,
+        })
+    }
+}
+
+/// A prompt sent to several models at once, waiting for their replies.
+#[derive(Debug, Clone)]
+struct PendingComparison {
+    id: u64,
+    /// Where the replies go in the transcript. The answering model's reply
+    /// goes first, as the one later requests send.
+    start: usize,
+    /// Name of the answering model.
+    model: Option<String>,
+    remaining: usize,
+    /// Failures so far, shown in the error banner once every model is done.
+    errors: Vec<String>,
+    /// Cancelled by the Stop button to abort every request.
+    cancel: CancellationToken,
+}
+
+/// A shell command run for a tool call, with what it wrote so far.
+#[derive(Debug, Clone)]
+struct RunningCommand {
+    call_id: String,
+    command: String,
+    output: String,
+    /// Whether output was dropped past [`MAX_COMMAND_OUTPUT`].
+    truncated: bool,
+    /// Cancelled by the Stop button to kill the command.
+    cancel: CancellationToken,
+}
+
+/// A completion request, or anything else the chat did for the user,
+/// that failed.
+#[derive(Debug, Clone, PartialEq)]
+struct RequestError {
+    /// The error as the provider or client reported it.
+    text: String,
+    /// Length of the transcript when the request was sent. Retrying drops
+    /// whatever the failed request left after it. `None` for failures that
+    /// weren't a request and can't be retried.
+    retry_from: Option<usize>,
+}
+
+/// The oldest messages of a long conversation, summarized.
+#[derive(Debug, Clone, PartialEq)]
+struct Summary {
+    text: String,
+    /// How many messages from the start of the transcript it stands in for.
+    covered: usize,
+}
+
+/// A PDF attached to the next message.
+#[derive(Debug, Clone)]
+struct PdfAttachment {
+    filename: String,
+    /// The text of each page.
+    pages: Vec<String>,
+    /// The pages to send, as typed.
+    range: String,
+}
+
+impl PdfAttachment {
+    /// The picked pages as a text file. A range that doesn't parse sends the
+    /// default pages.
+    fn as_file(&self) -> FileData {
+        let count = self.pages.len();
+        let pages = pdf::parse_page_range(&self.range, count)
+            .or_else(|| pdf::parse_page_range(&pdf::default_range(count), count))
+            .unwrap_or_default();
+        let text = pdf::pages_text(&self.pages, &pages);
+        FileData {
+            filename: Some(self.filename.clone()),
+            file_data: Some(format!(
+                "data:text/plain;base64,{}",
+                base64::engine::general_purpose::STANDARD.encode(text)
+            )),
+            file_id: None,
+        }
+    }
+}
+
+/// An MCP resource mentioned in the next message, with its text.
+#[derive(Debug, Clone)]
+struct ResourceAttachment {
+    resource: McpResource,
+    text: String,
+}
+
+impl ResourceAttachment {
+    /// The resource's text as a text file named after the resource.
+    fn as_file(&self) -> FileData {
+        FileData {
+            filename: Some(self.resource.name.clone()),
+            file_data: Some(format!(
+                "data:text/plain;base64,{}",
+                base64::engine::general_purpose::STANDARD.encode(&self.text)
+            )),
+            file_id: None,
+        }
+    }
+}
+
+/// Text of the sampling parameter inputs. Kept as typed, so that partial
+/// numbers such as `0.0` survive editing, and parsed when a request is sent.
+#[derive(Debug, Default, Clone)]
+struct SamplingInputs {
+    temperature: String,
+    top_p: String,
+    max_tokens: String,
+    reasoning_effort: Option<ReasoningEffort>,
+    stop: String,
+    seed: String,
+    presence_penalty: String,
+    frequency_penalty: String,
+}
+
+impl SamplingInputs {
+    fn from_params(params: &SamplingParams) -> Self {
+        let text = |value: Option<String>| value.unwrap_or_default();
+        Self {
+            temperature: text(params.temperature.map(|t| t.to_string())),
+            top_p: text(params.top_p.map(|p| p.to_string())),
+            max_tokens: text(params.max_tokens.map(|m| m.to_string())),
+            reasoning_effort: params.reasoning_effort,
+            stop: params
+                .stop
+                .iter()
+                .map(|stop| escape_stop(stop))
+                .collect::<Vec<_>>()
+                .join(","),
+            seed: text(params.seed.map(|s| s.to_string())),
+            presence_penalty: text(params.presence_penalty.map(|p| p.to_string())),
+            frequency_penalty: text(params.frequency_penalty.map(|p| p.to_string())),
+        }
+    }
+
+    fn params(&self) -> SamplingParams {
+        SamplingParams {
+            temperature: self.temperature.parse().ok(),
+            top_p: self.top_p.parse().ok(),
+            max_tokens: self.max_tokens.parse().ok(),
+            reasoning_effort: self.reasoning_effort,
+            stop: unescape_stops(&self.stop),
+            seed: self.seed.parse().ok(),
+            presence_penalty: self.presence_penalty.parse().ok(),
+            frequency_penalty: self.frequency_penalty.parse().ok(),
+        }
+    }
+
+    /// Set `field` to `value` if it is empty, a number in the field's range
+    /// or, for stop sequences, any text; other edits are ignored. A lone `-`
+    /// is kept so that negative penalties can be typed.
+    fn set(&mut self, field: SamplingField, value: String) {
+        let penalty = |value: &str| {
+            value == "-"
+                || value
+                    .parse::<f32>()
+                    .is_ok_and(|p| (-2.0..=2.0).contains(&p))
+        };
+        let valid = value.is_empty()
+            || match field {
+                SamplingField::Temperature => {
+                    value.parse::<f32>().is_ok_and(|t| (0.0..=2.0).contains(&t))
+                }
+                SamplingField::TopP => value.parse::<f32>().is_ok_and(|p| (0.0..=1.0).contains(&p)),
+                SamplingField::MaxTokens => value.parse::<u32>().is_ok_and(|m| m > 0),
+                SamplingField::Stop => true,
+                SamplingField::Seed => value.parse::<u32>().is_ok(),
+                SamplingField::PresencePenalty | SamplingField::FrequencyPenalty => penalty(&value),
+            };
+        if !valid {
+            return;
+        }
+        match field {
+            SamplingField::Temperature => self.temperature = value,
+            SamplingField::TopP => self.top_p = value,
+            SamplingField::MaxTokens => self.max_tokens = value,
+            SamplingField::Stop => self.stop = value,
+            SamplingField::Seed => self.seed = value,
+            SamplingField::PresencePenalty => self.presence_penalty = value,
+            SamplingField::FrequencyPenalty => self.frequency_penalty = value,
+        }
+    }
+}
+
+/// A stop sequence as typed in its input, with line breaks and tabs
+/// written as `\n` and `\t`, and commas, which separate sequences, as `\,`.
+fn escape_stop(stop: &str) -> String {
+    stop.replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t")
+        .replace(',', "\\,")
+}
+
+/// The stop sequences typed as `text`, see [`escape_stop`]. They are split
+/// at unescaped commas and otherwise kept as typed, spaces included.
+fn unescape_stops(text: &str) -> Vec<String> {
+    let mut stops = Vec::new();
+    let mut stop = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            (',', _) => {
+                stops.push(std::mem::take(&mut stop));
+                continue;
+            }
+            ('\\', Some('n')) => stop.push('\n'),
+            ('\\', Some('t')) => stop.push('\t'),
+            ('\\', Some(escaped @ ('\\' | ','))) => stop.push(escaped),
+            _ => {
+                stop.push(c);
+                continue;
+            }
+        }
+        chars.next();
+    }
+    stops.push(stop);
+    stops.retain(|stop| !stop.is_empty());
+    stops
+}
+
+/// A template selected from the picker together with the values entered so
+/// far for each of its variables (in the order they appear in the template).
+#[derive(Debug, Clone)]
+struct PendingTemplate {
+    template: ConversationTemplate,
+    values: Vec<(String, String)>,
+}
+
+/// An MCP prompt picked from the suggestions together with the values
+/// entered so far for each of its arguments.
+#[derive(Debug, Clone)]
+struct PendingPrompt {
+    prompt: McpPrompt,
+    values: Vec<(String, String)>,
+}
+
+/// A form from an MCP server with the values typed so far, one per field,
+/// and why it could not be sent last time.
+#[derive(Debug, Clone)]
+struct PendingElicitation {
+    request: ElicitationRequest,
+    values: Vec<String>,
+    error: Option<String>,
+}
+
+impl PendingPrompt {
+    /// Whether every required argument has a value.
+    fn ready(&self) -> bool {
+        self.prompt
+            .arguments
+            .iter()
+            .zip(&self.values)
+            .all(|(argument, (_, value))| !argument.required || !value.trim().is_empty())
+    }
+}
+
 impl State {
     pub fn new() -> (Self, Task<ChatAction>) {
-        let available_agents: Vec<String> = Config::default()
+        let config = Config::default();
+        let available_agents: Vec<String> = config
             .acp_agents
             .iter()
             .map(|a| a.name().to_string())
@@ -75,28 +557,539 @@ impl State {
         let state = State {
             awaiting_response: true,
             available_agents,
+            available_templates: config.templates,
+            tool_policies: config.tool_policies,
+            max_tool_iterations: config.max_tool_iterations,
+            pricing: config.pricing,
+            budget: config.budget,
+            fallback_models: config.fallback_models,
+            summarize_after: config.summarize_after,
+            mcp_resources: get_tool_manager().resources(),
+            mcp_prompts: get_tool_manager().prompts(),
+            system_prompt: text_editor::Content::with_text(&config.default_system_prompt),
+            default_system_prompt: config.default_system_prompt,
             ..Default::default()
         };
         let task = Task::batch([
             Task::perform(load_models(), ChatAction::ModelsLoaded),
             Task::perform(load_tools(), ChatAction::ToolsLoaaded),
+            Task::perform(load_latest_conversation(), ChatAction::ConversationLoaded),
+            state.reload_month_spend(),
         ]);
         (state, task)
     }
 
+    /// A blank chat that shares this one's model and tool lists and
+    /// settings, for opening in a new tab.
+    pub fn new_tab(&self) -> Self {
+        State {
+            selected_model: self.selected_model.clone(),
+            available_models: self.available_models.clone(),
+            available_tools: self.available_tools.clone(),
+            mcp_status: self.mcp_status.clone(),
+            mcp_resources: self.mcp_resources.clone(),
+            mcp_prompts: self.mcp_prompts.clone(),
+            tool_policies: self.tool_policies.clone(),
+            max_tool_iterations: self.max_tool_iterations,
+            pricing: self.pricing.clone(),
+            budget: self.budget,
+            fallback_models: self.fallback_models.clone(),
+            summarize_after: self.summarize_after,
+            month_spend: self.month_spend,
+            available_templates: self.available_templates.clone(),
+            available_agents: self.available_agents.clone(),
+            system_prompt: text_editor::Content::with_text(&self.default_system_prompt),
+            default_system_prompt: self.default_system_prompt.clone(),
+            use_knowledge: self.use_knowledge,
+            ..Default::default()
+        }
+    }
+
+    /// Short label for the tab showing this chat: its generated title, or
+    /// else the start of the first user message.
+    pub fn title(&self) -> String {
+        let first = self.title.as_ref().or_else(|| {
+            self.messages
+                .iter()
+                .filter(|m| m.message.role == "user")
+                .find_map(|m| m.message.text_content().into_iter().next())
+        });
+        let Some(line) = first.and_then(|t| t.lines().next()) else {
+            return "New chat".to_string();
+        };
+        if line.chars().count() <= TAB_TITLE_CHARS {
+            line.to_string()
+        } else {
+            let short: String = line.chars().take(TAB_TITLE_CHARS - 1).collect();
+            format!("{}…", short.trim_end())
+        }
+    }
+
     pub fn update(&mut self, action: ChatAction) -> Task<ChatAction> {
+        let extent = self.transcript_extent();
+        let task = self.dispatch(action);
+        self.count_prompt_tokens();
+        if !self.scrolled_back && self.transcript_extent() != extent {
+            return Task::batch([task, operation::snap_to_end(MESSAGE_LIST)]);
+        }
+        task
+    }
+
+    /// How much the transcript shows, to tell when it grew: the number of
+    /// messages, and the length of the last one and of whatever is still
+    /// streaming in.
+    fn transcript_extent(&self) -> (usize, usize, usize) {
+        let last = self.messages.last().map_or(0, |m| {
+            m.message.text_content().iter().map(|t| t.len()).sum()
+        });
+        let pending = self
+            .pending_response
+            .as_ref()
+            .map_or(0, |p| p.message.text().len() + p.reasoning.len());
+        let commands: usize = self.running_commands.iter().map(|c| c.output.len()).sum();
+        (self.messages.len(), last, pending + commands)
+    }
+
+    fn dispatch(&mut self, action: ChatAction) -> Task<ChatAction> {
         match action {
             ChatAction::InputChanged(value) => self.on_input_changed(value),
-            ChatAction::SendMessage => self.on_send_message(),
-            ChatAction::ResponseReceived(response) => self.on_response_received(response),
+            ChatAction::SendMessage => {
+                // A new user turn gets a fresh tool-loop budget.
+                self.tool_iterations = 0;
+                self.turn = trace::new_turn();
+                self.scrolled_back = false;
+                self.on_send_message()
+            }
+            ChatAction::StreamDelta(delta) => self.on_stream_delta(delta),
+            ChatAction::StreamFinished => self.on_stream_finished(),
+            ChatAction::StopGeneration => self.on_stop_generation(),
             ChatAction::ModelSelected(model_name) => self.on_model_selected(model_name),
             ChatAction::ModelsLoaded(models) => self.on_models_loaded(models),
             ChatAction::UrlClicked(url) => self.on_url_clicked(url),
             ChatAction::ToolsLoaaded(tools) => self.on_tools_loaded(tools),
+            ChatAction::ModelsChanged(models) => {
+                self.apply_models(models);
+                Task::none()
+            }
+            ChatAction::ToolsChanged(tools) => self.on_tools_loaded(tools),
+            ChatAction::DeferredServersStarted(tools) => {
+                self.available_tools = tools;
+                if !self.awaiting_response {
+                    return Task::none();
+                }
+                self.request_completion()
+            }
+            ChatAction::McpStatusChanged(statuses) => {
+                self.mcp_status = statuses;
+                Task::none()
+            }
+            ChatAction::RefreshModels => Task::future(refresh_models()).discard(),
+            ChatAction::ConversationLoaded(conversation) => {
+                self.on_conversation_loaded(conversation)
+            }
+            ChatAction::OpenConversation(id, message) => {
+                if self.is_busy() {
+                    self.show_error(SWITCH_WHILE_BUSY.to_string());
+                    return Task::none();
+                }
+                Task::perform(load_conversation(id), move |conversation| {
+                    ChatAction::ConversationOpened(conversation, message)
+                })
+            }
+            ChatAction::ConversationOpened(conversation, message) => {
+                // A turn started while the conversation was read keeps its
+                // transcript.
+                let Some(conversation) = conversation.filter(|_| !self.is_busy()) else {
+                    return Task::none();
+                };
+                self.show_conversation(conversation);
+                match message {
+                    Some(position) => self.on_show_message(position),
+                    None => Task::none(),
+                }
+            }
+            ChatAction::ShowMessage(position) => self.on_show_message(position),
+            ChatAction::TranscriptScrolled(viewport) => {
+                self.scrolled_back = viewport.remaining > SCROLL_END_SLACK;
+                self.viewport = viewport;
+                Task::none()
+            }
+            ChatAction::JumpToLatest => {
+                self.scrolled_back = false;
+                operation::snap_to_end(MESSAGE_LIST)
+            }
+            ChatAction::RetryRequest => self.on_retry_request(),
+            ChatAction::DismissError => {
+                self.error = None;
+                Task::none()
+            }
+            ChatAction::NewConversation => {
+                if self.is_busy() {
+                    self.show_error(SWITCH_WHILE_BUSY.to_string());
+                } else {
+                    self.reset_conversation();
+                }
+                Task::none()
+            }
+            ChatAction::ConversationSaved => self.reload_month_spend(),
+            ChatAction::MonthSpendLoaded(spend) => {
+                self.month_spend = spend;
+                Task::none()
+            }
+            ChatAction::ToggleSystemPrompt => {
+                self.system_prompt_open = !self.system_prompt_open;
+                // Keep edits to a stored conversation once the editor closes.
+                if self.system_prompt_open || self.conversation_id.is_empty() {
+                    Task::none()
+                } else {
+                    self.save_conversation()
+                }
+            }
+            ChatAction::SystemPromptEdited(action) => {
+                self.system_prompt.perform(action);
+                Task::none()
+            }
+            ChatAction::SamplingChanged(field, value) => {
+                self.sampling.set(field, value);
+                Task::none()
+            }
+            ChatAction::ReasoningEffortSelected(effort) => {
+                self.sampling.reasoning_effort = Some(effort);
+                Task::none()
+            }
+            ChatAction::NextTurnModelSelected(model_name) => {
+                self.next_turn_model = self
+                    .available_models
+                    .iter()
+                    .find(|m| m.name == model_name)
+                    .cloned();
+                Task::none()
+            }
+            ChatAction::ClearNextTurnModel => {
+                self.next_turn_model = None;
+                Task::none()
+            }
+            ChatAction::CompareModelAdded(model_name) => {
+                if !self.compare_models.contains(&model_name) {
+                    self.compare_models.push(model_name);
+                }
+                Task::none()
+            }
+            ChatAction::CompareModelRemoved(model_name) => {
+                self.compare_models.retain(|name| *name != model_name);
+                Task::none()
+            }
+            ChatAction::ComparisonReplied(id, reply) => self.on_comparison_replied(id, reply),
+            ChatAction::EditMessage(index) => {
+                self.on_edit_message(index);
+                Task::none()
+            }
+            ChatAction::EditedMessageChanged(action) => {
+                if let Some((_, draft)) = &mut self.editing {
+                    draft.perform(action);
+                }
+                Task::none()
+            }
+            ChatAction::ResendEditedMessage => self.on_resend_edited_message(),
+            ChatAction::CancelEdit => {
+                self.editing = None;
+                Task::none()
+            }
+            ChatAction::CopyMessage(index) => match self.messages.get(index) {
+                Some(message) => {
+                    let text: Vec<String> = message
+                        .message
+                        .content
+                        .iter()
+                        .filter_map(|content| match content {
+                            Content::Text { text } => Some(text.clone()),
+                            _ => None,
+                        })
+                        .collect();
+                    iced::clipboard::write(text.join("\n\n"))
+                }
+                None => Task::none(),
+            },
+            ChatAction::CopyCode(code) => iced::clipboard::write(code),
+            ChatAction::SaveCode(language, code) => {
+                Task::perform(save_code(language, code), ChatAction::CodeSaved)
+            }
+            ChatAction::CodeSaved(result) => {
+                match result {
+                    Ok(Some(path)) => tracing::info!("Saved code block to {}", path.display()),
+                    Ok(None) => {}
+                    Err(err) => {
+                        tracing::error!("Failed to save code block: {}", err);
+                        self.show_error(format!("Saving code failed: {err}"));
+                    }
+                }
+                Task::none()
+            }
+            ChatAction::BranchFrom(index) => self.on_branch_from(index),
+            // Opened in a new tab by `ui::update`.
+            ChatAction::ConversationBranched(_) => Task::none(),
+            ChatAction::TitleGenerated(id, title) => {
+                if id == self.conversation_id && title.is_some() {
+                    self.title = title;
+                }
+                Task::none()
+            }
+            ChatAction::SummaryGenerated(id, end, summary) => {
+                self.on_summary_generated(id, end, summary)
+            }
             ChatAction::CallTool(tool_call) => self.on_tool_called(tool_call),
+            ChatAction::ApproveToolCall(id) => self.on_approve_tool_call(id),
+            ChatAction::DenyToolCall(id) => self.on_deny_tool_call(id),
+            ChatAction::ToolProgressed(progress) => {
+                // Every chat hears of every call; keep only this one's.
+                if self.pending_tool_calls.contains(&progress.call_id) {
+                    self.tool_progress
+                        .insert(progress.call_id.clone(), progress);
+                }
+                Task::none()
+            }
+            ChatAction::SamplingRequested(request) => {
+                self.pending_sampling.push_back(request);
+                Task::none()
+            }
+            ChatAction::ApproveSampling => {
+                if let Some(model) = self.selected_model.clone() {
+                    if let Some(request) = self.pending_sampling.pop_front() {
+                        if self.over_budget() {
+                            request.deny();
+                            self.show_error(BUDGET_REACHED.to_string());
+                        } else {
+                            request.approve(model);
+                        }
+                    }
+                }
+                Task::none()
+            }
+            ChatAction::SamplingUsageRecorded => self.reload_month_spend(),
+            ChatAction::DenySampling => {
+                if let Some(request) = self.pending_sampling.pop_front() {
+                    request.deny();
+                }
+                Task::none()
+            }
+            ChatAction::ElicitationRequested(request) => {
+                let values = request.fields.iter().map(|f| f.default.clone()).collect();
+                self.pending_elicitation.push_back(PendingElicitation {
+                    request,
+                    values,
+                    error: None,
+                });
+                Task::none()
+            }
+            ChatAction::ElicitationFieldChanged(index, value) => {
+                if let Some(pending) = self.pending_elicitation.front_mut() {
+                    if let Some(entry) = pending.values.get_mut(index) {
+                        *entry = value;
+                    }
+                }
+                Task::none()
+            }
+            ChatAction::SubmitElicitation => {
+                if let Some(pending) = self.pending_elicitation.front_mut() {
+                    match pending.request.accept(&pending.values) {
+                        Ok(()) => {
+                            self.pending_elicitation.pop_front();
+                        }
+                        Err(err) => pending.error = Some(err),
+                    }
+                }
+                Task::none()
+            }
+            ChatAction::DeclineElicitation => {
+                if let Some(pending) = self.pending_elicitation.pop_front() {
+                    pending.request.decline();
+                }
+                Task::none()
+            }
+            ChatAction::CancelElicitation => {
+                if let Some(pending) = self.pending_elicitation.pop_front() {
+                    pending.request.cancel();
+                }
+                Task::none()
+            }
+            ChatAction::ToggleToolBlock(id) => {
+                if !self.expanded_tool_blocks.remove(&id) {
+                    self.expanded_tool_blocks.insert(id);
+                }
+                Task::none()
+            }
+            ChatAction::ToggleThinking(index) => {
+                if !self.expanded_thinking.remove(&index) {
+                    self.expanded_thinking.insert(index);
+                }
+                Task::none()
+            }
             ChatAction::ToolResponseReceived(response) => self.on_tool_response_received(response),
+            ChatAction::CommandEvent(call_id, event) => self.on_command_event(call_id, event),
             ChatAction::OpenFileDialog => self.on_open_file_dialog(),
+            ChatAction::ExportHtml => {
+                let title = self.title();
+                let messages: Vec<Message> =
+                    self.messages.iter().map(|m| m.message.clone()).collect();
+                let html = conversation_html(&title, &messages);
+                Task::perform(export_html(title, html), ChatAction::HtmlExported)
+            }
+            ChatAction::HtmlExported(result) => {
+                match result {
+                    Ok(Some(path)) => tracing::info!("Exported conversation to {}", path.display()),
+                    Ok(None) => {}
+                    Err(err) => {
+                        tracing::error!("Failed to export conversation: {}", err);
+                        self.show_error(format!("Export failed: {err}"));
+                    }
+                }
+                Task::none()
+            }
             ChatAction::FileSelected(path_buffer) => self.on_file_selected(path_buffer),
+            ChatAction::RemoveAttachment(index) => {
+                if let Some(files) = self.files.as_mut().filter(|files| index < files.len()) {
+                    files.remove(index);
+                }
+                Task::none()
+            }
+            ChatAction::PdfRead(result) => {
+                match result {
+                    Ok((filename, pages)) => self.pdfs.push(PdfAttachment {
+                        range: pdf::default_range(pages.len()),
+                        filename,
+                        pages,
+                    }),
+                    Err(err) => {
+                        tracing::error!("Failed to read PDF: {}", err);
+                        self.show_error(format!("Couldn't attach the PDF: {err}"));
+                    }
+                }
+                Task::none()
+            }
+            ChatAction::PdfPagesChanged(index, range) => {
+                let typed = range
+                    .chars()
+                    .all(|c| c.is_ascii_digit() || matches!(c, '-' | ',' | ' '));
+                if let Some(pdf) = self.pdfs.get_mut(index).filter(|_| typed) {
+                    pdf.range = range;
+                }
+                Task::none()
+            }
+            ChatAction::RemovePdf(index) => {
+                if index < self.pdfs.len() {
+                    self.pdfs.remove(index);
+                }
+                Task::none()
+            }
+            ChatAction::McpResourcesChanged(resources) => {
+                self.mcp_resources = resources;
+                Task::none()
+            }
+            ChatAction::ResourceMentioned(resource) => self.on_resource_mentioned(resource),
+            ChatAction::ResourceRead(result) => {
+                match result {
+                    Ok((resource, text)) => {
+                        self.resources.push(ResourceAttachment { resource, text })
+                    }
+                    Err(err) => {
+                        tracing::error!("Failed to read MCP resource: {}", err);
+                        self.show_error(format!("Couldn't attach the resource: {err}"));
+                    }
+                }
+                Task::none()
+            }
+            ChatAction::RemoveResource(index) => {
+                if index < self.resources.len() {
+                    self.resources.remove(index);
+                }
+                Task::none()
+            }
+            ChatAction::McpPromptsChanged(prompts) => {
+                if let Some(pending) = &self.pending_prompt {
+                    if !prompts.contains(&pending.prompt) {
+                        self.pending_prompt = None;
+                    }
+                }
+                self.mcp_prompts = prompts;
+                Task::none()
+            }
+            ChatAction::PromptSelected(prompt) => self.on_prompt_selected(prompt),
+            ChatAction::PromptArgumentChanged(name, value) => {
+                if let Some(pending) = &mut self.pending_prompt {
+                    if let Some(entry) = pending.values.iter_mut().find(|(n, _)| *n == name) {
+                        entry.1 = value;
+                    }
+                }
+                Task::none()
+            }
+            ChatAction::RunPrompt => self.on_run_prompt(),
+            ChatAction::CancelPrompt => {
+                self.pending_prompt = None;
+                Task::none()
+            }
+            ChatAction::PromptFetched(result) => self.on_prompt_fetched(result),
+            ChatAction::CaptureScreenshot => {
+                Task::perform(capture_screenshot(), ChatAction::ScreenshotCaptured)
+            }
+            ChatAction::ScreenshotCaptured(result) => {
+                match result {
+                    Ok(Some(path)) => {
+                        self.discard_screenshot();
+                        let preview = image::Handle::from_path(&path);
+                        self.screenshot = Some((path, preview));
+                    }
+                    Ok(None) => tracing::info!("Screenshot cancelled"),
+                    Err(err) => {
+                        tracing::error!("Failed to capture screenshot: {}", err);
+                        self.show_error(format!("Screenshot failed: {err}"));
+                    }
+                }
+                Task::none()
+            }
+            ChatAction::AttachScreenshot => {
+                let Some((path, _)) = self.screenshot.clone() else {
+                    return Task::none();
+                };
+                let task = self.on_file_selected(Some(vec![path]));
+                self.discard_screenshot();
+                task
+            }
+            ChatAction::DiscardScreenshot => {
+                self.discard_screenshot();
+                Task::none()
+            }
+            ChatAction::ToggleKnowledge => {
+                self.use_knowledge = !self.use_knowledge;
+                Task::none()
+            }
+            ChatAction::KnowledgeRetrieved(result) => self.on_knowledge_retrieved(result),
+            ChatAction::PickWorkspace => {
+                Task::perform(pick_workspace(), ChatAction::WorkspacePicked)
+            }
+            ChatAction::WorkspacePicked(result) => {
+                match result {
+                    Ok(Some(workspace)) => self.workspace = Some(workspace),
+                    Ok(None) => tracing::info!("Workspace selection cancelled"),
+                    Err(err) => {
+                        tracing::error!("Failed to open workspace: {}", err);
+                        self.show_error(format!("Opening the workspace failed: {err}"));
+                    }
+                }
+                Task::none()
+            }
+            ChatAction::CloseWorkspace => {
+                self.workspace = None;
+                Task::none()
+            }
+            ChatAction::TemplateSelected(name) => self.on_template_selected(name),
+            ChatAction::TemplateVariableChanged(name, value) => {
+                self.on_template_variable_changed(name, value)
+            }
+            ChatAction::StartTemplate => self.on_start_template(),
+            ChatAction::CancelTemplate => {
+                self.pending_template = None;
+                Task::none()
+            }
             ChatAction::TargetSelected(target) => self.on_target_selected(target),
             ChatAction::AgentStarted(result) => self.on_agent_started(result),
             ChatAction::AgentEvent(event) => self.on_agent_event(event),
@@ -121,44 +1114,462 @@ impl State {
         Task::none()
     }
 
-    fn on_send_message(&mut self) -> Task<ChatAction> {
-        // Route based on chat target.
-        match self.chat_target.clone() {
-            ChatTarget::Llm => self.on_send_message_llm(),
-            ChatTarget::Agent(name) => self.on_send_message_agent(name),
-        }
+    /// The `@` mention being typed at the end of the input, without the `@`.
+    fn mention_query(&self) -> Option<&str> {
+        self.input_value
+            .rsplit(char::is_whitespace)
+            .next()?
+            .strip_prefix('@')
     }
 
-    fn on_send_message_llm(&mut self) -> Task<ChatAction> {
-        self.awaiting_response = true;
-        if !self.input_value.is_empty() {
+    /// The resources whose name or URI matches the `@` mention being typed.
+    fn resource_suggestions(&self) -> Vec<&McpResource> {
+        let Some(query) = self.mention_query().map(str::to_lowercase) else {
+            return vec![];
+        };
+        self.mcp_resources
+            .iter()
+            .filter(|resource| {
+                resource.name.to_lowercase().contains(&query)
+                    || resource.uri.to_lowercase().contains(&query)
+            })
+            .take(MAX_RESOURCE_SUGGESTIONS)
+            .collect()
+    }
+
+    /// Complete the `@` mention being typed with `resource`'s name and read
+    /// the resource to attach it.
+    fn on_resource_mentioned(&mut self, resource: McpResource) -> Task<ChatAction> {
+        if let Some(typed) = self.mention_query().map(str::len) {
+            let mention_start = self.input_value.len() - typed - 1;
+            self.input_value.truncate(mention_start);
+        }
+        self.input_value.push_str(&format!("@{} ", resource.name));
+        // Mentioned twice, it is still attached once.
+        if self.resources.iter().any(|r| r.resource == resource) {
+            return Task::none();
+        }
+        Task::perform(read_resource(resource), ChatAction::ResourceRead)
+    }
+
+    /// The prompts whose name starts with the `/` command being typed. The
+    /// command must be all the input holds.
+    fn prompt_suggestions(&self) -> Vec<&McpPrompt> {
+        let Some(query) = self.input_value.strip_prefix('/') else {
+            return vec![];
+        };
+        if query.contains(char::is_whitespace) {
+            return vec![];
+        }
+        let query = query.to_lowercase();
+        self.mcp_prompts
+            .iter()
+            .filter(|prompt| prompt.name.to_lowercase().starts_with(&query))
+            .collect()
+    }
+
+    fn on_prompt_selected(&mut self, prompt: McpPrompt) -> Task<ChatAction> {
+        self.input_value.clear();
+        let values = prompt
+            .arguments
+            .iter()
+            .map(|argument| (argument.name.clone(), String::new()))
+            .collect::<Vec<_>>();
+        let has_arguments = !values.is_empty();
+        self.pending_prompt = Some(PendingPrompt { prompt, values });
+        if has_arguments {
+            Task::none()
+        } else {
+            // Nothing to ask for: run straight away.
+            self.on_run_prompt()
+        }
+    }
+
+    fn on_run_prompt(&mut self) -> Task<ChatAction> {
+        if self.awaiting_response {
+            return Task::none();
+        }
+        let Some(pending) = self.pending_prompt.take_if(|pending| pending.ready()) else {
+            return Task::none();
+        };
+        self.awaiting_response = true;
+        Task::perform(
+            get_prompt(pending.prompt, pending.values),
+            ChatAction::PromptFetched,
+        )
+    }
+
+    /// Add the messages the prompt rendered to the transcript and, when they
+    /// end with the user's turn, ask the model to reply.
+    fn on_prompt_fetched(&mut self, result: Result<Vec<Message>, String>) -> Task<ChatAction> {
+        self.awaiting_response = false;
+        let messages = match result {
+            Ok(messages) => messages,
+            Err(err) => {
+                tracing::error!("Failed to get MCP prompt: {}", err);
+                self.show_error(format!("Couldn't run the prompt: {err}"));
+                return Task::none();
+            }
+        };
+        let ends_with_user = messages.last().is_some_and(|m| m.role == "user");
+        self.messages
+            .extend(messages.into_iter().map(ChatMessage::from));
+        if !ends_with_user {
+            return Task::none();
+        }
+        self.tool_iterations = 0;
+        self.turn = trace::new_turn();
+        self.scrolled_back = false;
+        self.awaiting_response = true;
+        self.turn_model = self.next_turn_model.take();
+        self.notice = None;
+        self.request_replies()
+    }
+
+    fn on_send_message(&mut self) -> Task<ChatAction> {
+        // Route based on chat target.
+        match self.chat_target.clone() {
+            ChatTarget::Llm => self.on_send_message_llm(),
+            ChatTarget::Agent(name) => self.on_send_message_agent(name),
+        }
+    }
+
+    fn on_send_message_llm(&mut self) -> Task<ChatAction> {
+        self.awaiting_response = true;
+        self.turn_model = self.next_turn_model.take();
+        self.notice = None;
+        if !self.input_value.is_empty() {
             let user_message = self.build_pending_message();
             self.messages.push(user_message);
+            // Attachments go with this message only.
+            self.files = None;
+            self.pdfs.clear();
+            self.resources.clear();
+            self.knowledge.clear();
+            if self.use_knowledge {
+                return Task::perform(retrieve(self.input_value.clone()), |result| {
+                    ChatAction::KnowledgeRetrieved(result.map_err(|e| e.to_string()))
+                });
+            }
+        }
+        self.request_replies()
+    }
+
+    /// Send the request held back for retrieval, with the passages found.
+    fn on_knowledge_retrieved(&mut self, result: Result<Vec<Passage>, String>) -> Task<ChatAction> {
+        match result {
+            Ok(passages) => self.knowledge = passages,
+            Err(err) => {
+                tracing::error!("Failed to search the knowledge base: {}", err);
+                self.notice = Some(format!(
+                    "Knowledge base search failed ({err}); sent without passages."
+                ));
+            }
+        }
+        self.request_replies()
+    }
+
+    /// Ask the answering model, and any it is compared with, for a reply.
+    fn request_replies(&mut self) -> Task<ChatAction> {
+        if !self.compare_models.is_empty() {
+            return self.request_comparison();
+        }
+        self.request_completion()
+    }
+
+    /// Send the transcript to the answering model and every model it is
+    /// compared with at once, without tools. Each reply joins the
+    /// transcript, with its model's badge, as it arrives; only the
+    /// answering model's is sent with later requests.
+    fn request_comparison(&mut self) -> Task<ChatAction> {
+        if self.budget_reached() {
+            return Task::none();
+        }
+        let mut models: Vec<ModelInfo> = Vec::new();
+        let compared = self
+            .compare_models
+            .iter()
+            .filter_map(|name| self.available_models.iter().find(|m| &m.name == name));
+        for model in self.answering_model().into_iter().chain(compared) {
+            if !models.contains(model) {
+                models.push(model.clone());
+            }
+        }
+        if models.is_empty() {
+            tracing::error!("No model selected, cannot send message");
+            self.awaiting_response = false;
+            return Task::none();
+        }
+        let cancel = CancellationToken::new();
+        self.comparisons += 1;
+        let id = self.comparisons;
+        self.comparison = Some(PendingComparison {
+            id,
+            start: self.messages.len(),
+            model: self.answering_model().map(|m| m.name.clone()),
+            remaining: models.len(),
+            errors: vec![],
+            cancel: cancel.clone(),
+        });
+        let messages = self.request_messages();
+        Task::batch(models.into_iter().map(|model| {
+            let sampling = self.request_sampling(&model);
+            Task::perform(
+                compare_reply(messages.clone(), model, sampling, self.turn, cancel.clone()),
+                move |reply| ChatAction::ComparisonReplied(id, reply),
+            )
+        }))
+    }
+
+    /// Add a compared model's reply, ending the turn once every model has
+    /// answered. Replies to a comparison other than the pending one are
+    /// ignored.
+    fn on_comparison_replied(
+        &mut self,
+        id: u64,
+        reply: Result<Option<ChatMessage>, String>,
+    ) -> Task<ChatAction> {
+        let Some(comparison) = self.comparison.as_mut().filter(|c| c.id == id) else {
+            return Task::none();
+        };
+        match reply {
+            Ok(Some(reply)) if reply.model == comparison.model => {
+                self.messages.insert(comparison.start, reply);
+            }
+            Ok(reply) => self.messages.extend(reply),
+            Err(err) => {
+                tracing::error!("Compared model failed: {}", err);
+                comparison.errors.push(err);
+            }
+        }
+        comparison.remaining -= 1;
+        if comparison.remaining > 0 {
+            return Task::none();
+        }
+        let errors = std::mem::take(&mut comparison.errors);
+        self.comparison = None;
+        self.input_value.clear();
+        let task = self.finish_turn(vec![]);
+        if !errors.is_empty() {
+            self.show_error(errors.join("\n"));
+        }
+        task
+    }
+
+    /// Whether the monthly budget stops requests, in which case the turn
+    /// ends with the error banner saying so. Retrying it sends the request
+    /// once the limit is raised.
+    fn budget_reached(&mut self) -> bool {
+        if !self.over_budget() {
+            return false;
         }
+        tracing::warn!("Monthly budget reached, not sending the request");
+        self.error = Some(RequestError {
+            text: BUDGET_REACHED.to_string(),
+            retry_from: Some(self.messages.len()),
+        });
+        self.awaiting_response = false;
+        true
+    }
 
-        if self.selected_model.is_none() {
-            log::error!("No model selected, cannot send message");
+    /// Stream the model's reply to the transcript as it stands.
+    fn request_completion(&mut self) -> Task<ChatAction> {
+        if self.answering_model().is_none() {
+            tracing::error!("No model selected, cannot send message");
             self.awaiting_response = false;
             return Task::none();
         }
+        if self.budget_reached() {
+            return Task::none();
+        }
 
         let model = get_model_manager()
-            .find_model(&self.selected_model.as_ref().unwrap().name)
+            .find_model(&self.answering_model().unwrap().name)
             .unwrap_or(None)
             .unwrap_or(ModelInfo {
                 name: "gpt-4o-mini".to_string(),
                 id: "gpt-4o-mini".to_string(),
                 client: Clients::OpenAI,
+                capabilities: ModelCapabilities::default(),
             });
-        Task::perform(
-            complete_message(
-                self.messages.clone(),
-                model.client.clone(),
+        // Servers that start lazily come up before the first request that
+        // can use their tools.
+        if model.capabilities.tools && get_tool_manager().has_deferred() {
+            return Task::perform(start_deferred_servers(), ChatAction::DeferredServersStarted);
+        }
+        self.error = None;
+        let pending = PendingResponse {
+            started_at: Some(Instant::now()),
+            transcript_len: self.messages.len(),
+            ..PendingResponse::default()
+        };
+        let cancel = pending.cancel.clone();
+        self.pending_response = Some(pending);
+        let client = match model.client.provider() {
+            Ok(client) => client,
+            Err(e) => {
+                return Task::done(ChatAction::StreamDelta(Err(e.into())))
+                    .chain(Task::done(ChatAction::StreamFinished));
+            }
+        };
+        Task::run(
+            stream_message(
+                self.request_messages(),
+                client,
                 model.id.clone(),
-                self.available_tools.clone(),
+                self.request_tools(&model),
+                self.request_sampling(&model),
+                self.turn,
+                cancel,
             ),
-            ChatAction::ResponseReceived,
+            ChatAction::StreamDelta,
         )
+        .chain(Task::done(ChatAction::StreamFinished))
+    }
+
+    /// The tools offered to `model`, with the workspace's read-file tool
+    /// while one is attached: none if it can't call them.
+    fn request_tools(&self, model: &ModelInfo) -> Vec<Tool> {
+        if !model.capabilities.tools {
+            return vec![];
+        }
+        let mut tools = self.available_tools.clone();
+        if self.workspace.is_some() {
+            tools.push(workspace::read_file_tool());
+        }
+        tools
+    }
+
+    /// The conversation's sampling parameters that `model` accepts:
+    /// reasoning models take an effort instead of temperature and top_p.
+    fn request_sampling(&self, model: &ModelInfo) -> SamplingParams {
+        let params = self.sampling.params();
+        if model.capabilities.reasoning {
+            SamplingParams {
+                temperature: None,
+                top_p: None,
+                ..params
+            }
+        } else {
+            SamplingParams {
+                reasoning_effort: None,
+                ..params
+            }
+        }
+    }
+
+    /// What the selected model can do. Agents manage their own model, so
+    /// nothing is restricted for them.
+    fn selected_capabilities(&self) -> ModelCapabilities {
+        match (&self.chat_target, &self.selected_model) {
+            (ChatTarget::Llm, Some(model)) => model.capabilities,
+            _ => ModelCapabilities::default(),
+        }
+    }
+
+    /// Note under the transcript naming what the selected model can't do.
+    fn capability_note(&self) -> Option<String> {
+        let capabilities = self.selected_capabilities();
+        let mut missing = Vec::new();
+        if !capabilities.vision {
+            missing.push("read attachments");
+        }
+        if !capabilities.tools && (!self.available_tools.is_empty() || self.workspace.is_some()) {
+            missing.push("use tools");
+        }
+        if missing.is_empty() {
+            return None;
+        }
+        Some(format!(
+            "The selected model can't {}.",
+            missing.join(" or ")
+        ))
+    }
+
+    /// The transcript as sent to the model, led by the system prompt if one
+    /// is set, the workspace's files and the passages retrieved for the
+    /// turn.
+    fn request_messages(&self) -> Vec<ChatMessage> {
+        let mut messages: Vec<ChatMessage> = self
+            .context_messages()
+            .into_iter()
+            .map(ChatMessage::from)
+            .collect();
+        messages.extend(sent_messages(self.unsummarized_messages()).cloned());
+        messages
+    }
+
+    /// The transcript after the messages the summary stands in for.
+    fn unsummarized_messages(&self) -> &[ChatMessage] {
+        let covered = self.summary.as_ref().map_or(0, |s| s.covered);
+        &self.messages[covered.min(self.messages.len())..]
+    }
+
+    /// The system messages sent ahead of the transcript, the summary of its
+    /// oldest messages last.
+    fn context_messages(&self) -> Vec<Message> {
+        let system_prompt = self.system_prompt.text();
+        let mut messages = Vec::new();
+        if !system_prompt.trim().is_empty() {
+            messages.push(Message::system(system_prompt));
+        }
+        if let Some(workspace) = &self.workspace {
+            messages.push(Message::system(workspace.prompt()));
+        }
+        if !self.knowledge.is_empty() {
+            messages.push(Message::system(knowledge::context_prompt(&self.knowledge)));
+        }
+        if let Some(summary) = &self.summary {
+            messages.push(Message::system(format!(
+                "Summary of the earlier part of this conversation, whose messages \
+                 are not included:\n\n{}",
+                summary.text
+            )));
+        }
+        messages
+    }
+
+    /// Recount [`State::prompt_tokens`]: the context messages, the
+    /// transcript and the message being written. Messages already counted
+    /// are not encoded again.
+    fn count_prompt_tokens(&mut self) {
+        let model = match (&self.chat_target, &self.selected_model) {
+            (ChatTarget::Llm, Some(model)) => model.id.clone(),
+            _ => {
+                self.prompt_tokens = None;
+                return;
+            }
+        };
+        let has_draft = !self.input_value.is_empty()
+            || self.files.is_some()
+            || !self.pdfs.is_empty()
+            || !self.resources.is_empty();
+        if self.messages.is_empty() && !has_draft {
+            self.prompt_tokens = None;
+            return;
+        }
+        let context = self.context_messages();
+        let covered = self.messages.len() - self.unsummarized_messages().len();
+        let draft = has_draft.then(|| self.pending_message());
+        let messages = context
+            .iter()
+            .chain(sent_messages(&self.messages[covered..]).map(|m| &m.message))
+            .chain(draft.iter());
+        self.prompt_tokens = Some(self.token_counter.count(&model, messages));
+    }
+
+    /// The prompt count shown by the input, and whether it overflows the
+    /// selected model's context window.
+    fn prompt_token_note(&self) -> Option<(String, bool)> {
+        let tokens = self.prompt_tokens?;
+        let note = format!("~{} prompt tokens", tokens);
+        match self.selected_capabilities().context_length {
+            Some(limit) if tokens > limit as usize => Some((
+                format!("{note}, more than the {limit} the selected model takes."),
+                true,
+            )),
+            _ => Some((note, false)),
+        }
     }
 
     fn on_send_message_agent(&mut self, agent_name: String) -> Task<ChatAction> {
@@ -217,7 +1628,7 @@ impl State {
     ) -> Task<ChatAction> {
         match result {
             Ok(AgentStartOutcome::Ready) => {
-                log::info!("ACP agent ready");
+                tracing::info!("ACP agent ready");
                 // Capture and persist the freshly-allocated session id so a
                 // future "Resume last session" works across restarts.
                 if let ChatTarget::Agent(name) = &self.chat_target {
@@ -232,11 +1643,8 @@ impl State {
                 self.push_auth_required_bubble(methods);
             }
             Err(err) => {
-                log::error!("Failed to start ACP agent: {}", err);
-                self.messages.push(ChatMessage::from_role_and_text(
-                    "assistant",
-                    format!("**Agent failed to start:** {err}"),
-                ));
+                tracing::error!("Failed to start ACP agent: {}", err);
+                self.show_error(format!("Agent failed to start: {err}"));
                 self.awaiting_response = false;
                 // Fall back to LLM mode so the input doesn't lock up.
                 self.chat_target = ChatTarget::Llm;
@@ -263,7 +1671,7 @@ impl State {
         let stored = match cfg.acp_session_state.get(&agent) {
             Some(s) => s.clone(),
             None => {
-                log::warn!("ResumeAgent: no stored session for '{}'", agent);
+                tracing::warn!("ResumeAgent: no stored session for '{}'", agent);
                 return Task::none();
             }
         };
@@ -313,11 +1721,8 @@ impl State {
                 Task::none()
             }
             Err(err) => {
-                log::error!("resume_agent({agent}) failed: {err}");
-                self.messages.push(ChatMessage::from_role_and_text(
-                    "assistant",
-                    format!("**Failed to resume session:** {err}"),
-                ));
+                tracing::error!("resume_agent({agent}) failed: {err}");
+                self.show_error(format!("Failed to resume session: {err}"));
                 Task::none()
             }
         }
@@ -380,7 +1785,7 @@ impl State {
         self.awaiting_response = false;
         match result {
             Ok(()) => {
-                log::info!("Authenticated agent '{}' with method '{}'", agent, method_id);
+                tracing::info!("Authenticated agent '{}' with method '{}'", agent, method_id);
                 self.pending_auth_methods.clear();
                 self.messages.push(ChatMessage::from_role_and_text(
                     "assistant",
@@ -390,11 +1795,8 @@ impl State {
                 Task::perform(start_agent(agent), ChatAction::AgentStarted)
             }
             Err(err) => {
-                log::error!("authenticate({method_id}) failed: {err}");
-                self.messages.push(ChatMessage::from_role_and_text(
-                    "assistant",
-                    format!("**Authentication failed (`{method_id}`):** {err}"),
-                ));
+                tracing::error!("authenticate({method_id}) failed: {err}");
+                self.show_error(format!("Authentication failed ({method_id}): {err}"));
                 Task::none()
             }
         }
@@ -404,10 +1806,7 @@ impl State {
         match event {
             AgentEvent::Update(update) => self.apply_agent_update(update),
             AgentEvent::Fatal(msg) => {
-                self.messages.push(ChatMessage::from_role_and_text(
-                    "assistant",
-                    format!("**Agent error:** {msg}"),
-                ));
+                self.show_error(format!("Agent error: {msg}"));
                 self.awaiting_response = false;
                 self.streaming_agent_message = None;
             }
@@ -481,7 +1880,7 @@ impl State {
                 self.streaming_agent_message = None;
             }
             AgentUpdate::AvailableCommands(cmds) => {
-                log::info!(
+                tracing::info!(
                     "Agent advertised {} command(s): {:?}",
                     cmds.len(),
                     cmds.iter().map(|c| &c.name).collect::<Vec<_>>()
@@ -489,10 +1888,10 @@ impl State {
                 self.available_commands = cmds;
             }
             AgentUpdate::ModeChanged(m) => {
-                log::info!("Agent mode changed: {}", m);
+                tracing::info!("Agent mode changed: {}", m);
             }
             AgentUpdate::Other(text) => {
-                log::debug!("Agent other update: {}", text);
+                tracing::debug!("Agent other update: {}", text);
             }
         }
     }
@@ -508,8 +1907,8 @@ impl State {
             }
         }
         // Otherwise start a new bubble.
-        self.messages
-            .push(ChatMessage::from_role_and_text(role, chunk));
+        let message = ChatMessage::from_role_and_text(role, chunk).with_model(self.stored_model());
+        self.messages.push(message);
         self.streaming_agent_message = Some(self.messages.len() - 1);
     }
 
@@ -524,130 +1923,1060 @@ impl State {
         match result {
             Ok(AgentPromptOutcome::Completed(outcome)) => {
                 if !matches!(outcome.stop_reason, StopReason::EndTurn) {
-                    log::info!("Agent stopped: {:?}", outcome.stop_reason);
+                    tracing::info!("Agent stopped: {:?}", outcome.stop_reason);
                 }
             }
             Ok(AgentPromptOutcome::AuthRequired(methods)) => {
                 self.push_auth_required_bubble(methods);
             }
             Err(err) => {
-                log::error!("Agent prompt failed: {}", err);
-                self.messages.push(ChatMessage::from_role_and_text(
-                    "assistant",
-                    format!("**Agent prompt failed:** {err}"),
-                ));
+                tracing::error!("Agent prompt failed: {}", err);
+                self.show_error(format!("Agent prompt failed: {err}"));
             }
         }
-        Task::none()
+        self.save_conversation()
+    }
+
+    /// The message being written, with its attachments.
+    fn pending_message(&self) -> Message {
+        let mut files = self.files.clone();
+        if !self.pdfs.is_empty() {
+            let pdfs = self.pdfs.iter().map(PdfAttachment::as_file);
+            files.get_or_insert_with(Vec::new).extend(pdfs);
+        }
+        if !self.resources.is_empty() {
+            let resources = self.resources.iter().map(ResourceAttachment::as_file);
+            files.get_or_insert_with(Vec::new).extend(resources);
+        }
+        Message::user(self.input_value.clone(), files)
     }
 
     fn build_pending_message(&self) -> ChatMessage {
+        let message = self.pending_message();
         ChatMessage {
-            message: Message::user(self.input_value.clone(), self.files.clone()),
             markdown_items: markdown::parse(&self.input_value).collect(),
+            images: image_handles(&message),
+            message,
+            created_at: unix_now(),
+            model: None,
+            usage: None,
+            timing: None,
         }
     }
 
-    fn on_response_received(&mut self, response: CompletionResponse) -> Task<ChatAction> {
-        let choices = &response.choices;
-        self.input_value.clear();
-        if choices.is_empty() {
-            self.messages
-                .push(Message::assistant("Error: No response from model.".to_string()).into());
-            self.input_value.clear();
-            self.awaiting_response = false;
+    fn on_stream_delta(&mut self, delta: Result<CompletionDelta, String>) -> Task<ChatAction> {
+        let Some(pending) = self.pending_response.as_mut() else {
+            return Task::none();
+        };
+        // `answering_model()`, spelled out while `pending` borrows `self`.
+        let answering = self.turn_model.as_ref().or(self.selected_model.as_ref());
+        let streamed = match &delta {
+            Ok(CompletionDelta::Text(text) | CompletionDelta::Reasoning(text)) => Some(text),
+            Ok(CompletionDelta::ToolCall { arguments, .. }) => Some(arguments),
+            _ => None,
+        };
+        if let (Some(text), Some(model)) = (streamed, answering) {
+            pending.first_token_at.get_or_insert_with(Instant::now);
+            // Counted piece by piece; close enough to show throughput.
+            pending.streamed_tokens += tokens::count_text(&model.id, text);
+        }
+        match delta {
+            Ok(CompletionDelta::Text(text)) => pending.message.push_str(&text),
+            Ok(CompletionDelta::Reasoning(text)) => pending.reasoning.push_str(&text),
+            Ok(CompletionDelta::ReasoningSignature(signature)) => {
+                pending.reasoning_signature = Some(signature)
+            }
+            Ok(CompletionDelta::ToolCall {
+                index,
+                id,
+                name,
+                arguments,
+            }) => {
+                if pending.tool_calls.len() <= index {
+                    pending.tool_calls.resize_with(index + 1, || ToolCall {
+                        id: String::new(),
+                        _type: "function".to_string(),
+                        function: ToolFunction {
+                            name: String::new(),
+                            arguments: String::new(),
+                        },
+                    });
+                }
+                let call = &mut pending.tool_calls[index];
+                if let Some(id) = id {
+                    call.id = id;
+                }
+                if let Some(name) = name {
+                    call.function.name.push_str(&name);
+                }
+                call.function.arguments.push_str(&arguments);
+            }
+            Ok(CompletionDelta::Usage(usage)) => pending.usage = Some(usage),
+            Ok(CompletionDelta::Finished { finish_reason }) => {
+                tracing::info!("Completion finished: {}", finish_reason);
+            }
+            Err(err) => {
+                tracing::error!("Completion stream failed: {}", err);
+                pending.failed = true;
+                let nothing_streamed = pending.message.is_empty() && pending.tool_calls.is_empty();
+                if let Some(failed) = answering.cloned() {
+                    pending.failed_models.push(failed.name.clone());
+                    if nothing_streamed && is_retryable(&err) {
+                        let tried = &pending.failed_models;
+                        if let Some(fallback) = self
+                            .fallback_models
+                            .iter()
+                            .filter(|name| !tried.contains(name))
+                            .find_map(|name| self.available_models.iter().find(|m| &m.name == name))
+                        {
+                            tracing::warn!("Retrying with fallback model {}", fallback.name);
+                            self.messages.push(ChatMessage::note(format!(
+                                "{} failed ({err}); retried with {}.",
+                                failed.name, fallback.name
+                            )));
+                            pending.retry_with = Some(fallback.clone());
+                            return Task::none();
+                        }
+                    }
+                }
+                // Keep whatever arrived before the failure.
+                let partial = std::mem::take(&mut pending.message);
+                self.error = Some(RequestError {
+                    text: err.text,
+                    retry_from: Some(pending.transcript_len),
+                });
+                if !partial.is_empty() {
+                    let model = self.reply_model();
+                    self.messages
+                        .push(partial.into_chat_message().with_model(model));
+                }
+            }
+        }
+        Task::none()
+    }
+
+    /// Finalize the streamed assistant message and either dispatch its tool
+    /// calls or end the turn.
+    fn on_stream_finished(&mut self) -> Task<ChatAction> {
+        let Some(mut pending) = self.pending_response.take() else {
             return Task::none();
+        };
+        let timing = pending.timing(Instant::now());
+        if let Some(fallback) = pending.retry_with {
+            if !pending.cancel.is_cancelled() {
+                // For this turn only; the conversation keeps its model.
+                self.turn_model = Some(fallback);
+                let task = self.request_completion();
+                if let Some(retry) = self.pending_response.as_mut() {
+                    retry.failed_models = pending.failed_models;
+                }
+                return task;
+            }
         }
-        self.messages.append(
-            choices[0]
+        self.input_value.clear();
+        let stopped = pending.cancel.is_cancelled();
+        // Tool calls cut off by Stop may be incomplete; never dispatch them.
+        let tool_calls: Vec<ToolCall> = pending
+            .tool_calls
+            .into_iter()
+            .filter(|call| !call.id.is_empty() && !stopped)
+            .collect();
+        let reasoning = (!pending.reasoning.is_empty()).then_some(pending.reasoning);
+        if tool_calls.is_empty() && !pending.message.is_empty() {
+            let sources = knowledge::sources_section(pending.message.text(), &self.knowledge);
+            pending.message.push_str(&sources);
+        }
+
+        if !pending.message.is_empty() {
+            let mut msg = pending
                 .message
-                .iter()
-                .map(|m| m.clone().into())
-                .collect::<Vec<_>>()
-                .as_mut(),
-        );
-        let tool_calls = self.get_response_tool_calls(choices);
-        if !tool_calls.is_empty() {
-            tool_calls.iter().for_each(|tool_call| {
-                self.pending_tool_calls.insert(tool_call.id.clone());
+                .into_chat_message()
+                .with_model(self.reply_model());
+            msg.usage = pending.usage;
+            msg.timing = timing;
+            msg.message.reasoning_content = reasoning;
+            msg.message.reasoning_signature = pending.reasoning_signature;
+            if !tool_calls.is_empty() {
+                msg.message.tool_calls = Some(tool_calls.clone());
+            }
+            self.messages.push(msg);
+        } else if !tool_calls.is_empty() {
+            // Tool-only turn: the assistant message carrying the calls
+            // must still precede the tool results in the transcript.
+            let mut message = Message::assistant(String::new());
+            message.content.clear();
+            message.reasoning_content = reasoning;
+            message.reasoning_signature = pending.reasoning_signature;
+            message.tool_calls = Some(tool_calls.clone());
+            let mut msg = ChatMessage::from(message).with_model(self.reply_model());
+            msg.usage = pending.usage;
+            msg.timing = timing;
+            self.messages.push(msg);
+        } else if !pending.failed && !stopped {
+            self.error = Some(RequestError {
+                text: "No response from model.".to_string(),
+                retry_from: Some(pending.transcript_len),
             });
-            Task::batch(
-                tool_calls
-                    .into_iter()
-                    .map(|tool_call| Task::perform(async move { tool_call }, ChatAction::CallTool)),
-            )
-        } else {
+        }
+        self.finish_turn(tool_calls)
+    }
+
+    /// Abort whatever the current turn is waiting on. A streamed response is
+    /// cancelled and finalized with the text received so far through the
+    /// usual `StreamFinished`; outstanding tool calls are abandoned, and an
+    /// agent turn is asked to cancel via `session/cancel`.
+    fn on_stop_generation(&mut self) -> Task<ChatAction> {
+        if let Some(pending) = self.pending_response.as_ref() {
+            pending.cancel.cancel();
+            return Task::none();
+        }
+        if let Some(comparison) = self.comparison.as_ref() {
+            comparison.cancel.cancel();
+            return Task::none();
+        }
+        if !self.pending_tool_calls.is_empty() {
+            self.pending_tool_calls.clear();
             self.awaiting_response = false;
-            Task::none()
+            // Every tool call needs a result before the next request.
+            for call in std::mem::take(&mut self.pending_approvals) {
+                self.messages.push(
+                    Message::tool_result(call.id, "Cancelled by the user.", Some(true)).into(),
+                );
+            }
+            for command in std::mem::take(&mut self.running_commands) {
+                command.cancel.cancel();
+                let output = format!("{}[Stopped by the user.]", command.output);
+                self.messages
+                    .push(Message::tool_result(command.call_id, output, Some(true)).into());
+            }
+            return self.save_conversation();
+        }
+        match &self.chat_target {
+            ChatTarget::Agent(agent) if self.awaiting_response => {
+                let agent = agent.clone();
+                Task::future(async move {
+                    if let Err(err) = cancel_agent(agent).await {
+                        tracing::error!("Failed to cancel agent turn: {}", err);
+                    }
+                })
+                .discard()
+            }
+            _ => Task::none(),
         }
     }
 
-    fn get_response_tool_calls(&self, choices: &[crate::models::Choice]) -> Vec<ToolCall> {
-        if !choices.is_empty() {
-            choices[0]
-                .message
-                .iter()
-                .flat_map(|m| m.tool_calls.clone().unwrap_or(vec![]))
-                .collect::<Vec<_>>()
-        } else {
-            vec![]
+    /// Whether the Stop button should be offered.
+    fn can_stop(&self) -> bool {
+        self.pending_response.is_some()
+            || self.comparison.is_some()
+            || !self.pending_tool_calls.is_empty()
+            || (matches!(self.chat_target, ChatTarget::Agent(_)) && self.awaiting_response)
+    }
+
+    /// Persist the transcript in the background, allocating a conversation
+    /// id on first save.
+    fn save_conversation(&mut self) -> Task<ChatAction> {
+        if self.messages.is_empty() {
+            return Task::none();
+        }
+        if self.conversation_id.is_empty() {
+            self.conversation_id = new_conversation_id();
         }
+        self.revision += 1;
+        let messages = self.messages.iter().map(StoredMessage::from).collect();
+        Task::perform(
+            save_conversation(
+                self.conversation_id.clone(),
+                self.revision,
+                self.stored_model(),
+                self.system_prompt.text(),
+                self.sampling.params(),
+                messages,
+                self.summary.as_ref().map(|s| (s.text.clone(), s.covered)),
+            ),
+            |()| ChatAction::ConversationSaved,
+        )
     }
 
-    fn on_model_selected(&mut self, model_name: String) -> Task<ChatAction> {
-        self.selected_model = self
-            .available_models
+    /// Tokens used by every completion in the transcript, if any were
+    /// reported.
+    fn conversation_usage(&self) -> Option<TokenUsage> {
+        self.messages
             .iter()
-            .find(|m| m.name == model_name)
-            .cloned();
-        Task::none()
+            .filter_map(|m| m.usage)
+            .reduce(|total, usage| total + usage)
     }
 
-    fn on_models_loaded(&mut self, models: Vec<ModelInfo>) -> Task<ChatAction> {
-        self.available_models = models;
-        if (self.selected_model.is_none() && !self.available_models.is_empty()) ||
-        !self.available_models.contains(self.selected_model.as_ref().unwrap()) {
-            self.selected_model = Some(self.available_models[0].clone());
+    /// Estimated cost of `message`, if its model has a price and its usage
+    /// was reported.
+    fn message_cost(&self, message: &ChatMessage) -> Option<f64> {
+        let pricing = self.pricing.get(message.model.as_deref()?)?;
+        Some(pricing.cost(message.usage.as_ref()?))
+    }
+
+    /// Estimated cost of every priced message in the transcript.
+    fn conversation_cost(&self) -> Option<f64> {
+        self.messages
+            .iter()
+            .filter_map(|m| self.message_cost(m))
+            .reduce(|total, cost| total + cost)
+    }
+
+    /// The model or agent name stored with the conversation.
+    fn stored_model(&self) -> Option<String> {
+        match self.chat_target {
+            ChatTarget::Llm => self.selected_model.as_ref().map(|m| m.name.clone()),
+            ChatTarget::Agent(ref name) => Some(name.clone()),
         }
-        self.awaiting_response = false;
-        Task::none()
     }
 
-    fn on_tools_loaded(&mut self, tools: Vec<crate::models::Tool>) -> Task<ChatAction> {
-        self.available_tools = tools;
-        Task::none()
+    /// The model answering the current turn.
+    fn answering_model(&self) -> Option<&ModelInfo> {
+        self.turn_model.as_ref().or(self.selected_model.as_ref())
     }
 
-    fn on_tool_called(&mut self, tool_call: ToolCall) -> Task<ChatAction> {
-        Task::perform(call_tool(tool_call), ChatAction::ToolResponseReceived)
+    /// The model or agent name replies of the current turn are attributed
+    /// to.
+    fn reply_model(&self) -> Option<String> {
+        match self.chat_target {
+            ChatTarget::Llm => self.answering_model().map(|m| m.name.clone()),
+            ChatTarget::Agent(ref name) => Some(name.clone()),
+        }
     }
 
-    fn on_tool_response_received(
-        &mut self,
-        response: Result<ToolCallResult, (String, String)>,
+    /// Messages up to and including the one at `index`, plus the tool
+    /// results answering its tool calls, so the branch stays valid to send.
+    fn branch_messages(&self, index: usize) -> Vec<StoredMessage> {
+        let end = self
+            .messages
+            .iter()
+            .skip(index + 1)
+            .take_while(|m| m.message.role == "tool")
+            .count()
+            + index
+            + 1;
+        self.messages[..end.min(self.messages.len())]
+            .iter()
+            .map(StoredMessage::from)
+            .collect()
+    }
+
+    /// Save the conversation up to the message at `index` as a new
+    /// conversation. The app shell opens it once it is stored.
+    fn on_branch_from(&mut self, index: usize) -> Task<ChatAction> {
+        if index >= self.messages.len() || self.is_busy() {
+            return Task::none();
+        }
+        let title = self.title.as_ref().map(|title| format!("{title} (branch)"));
+        Task::perform(
+            branch_conversation(
+                title,
+                self.stored_model(),
+                self.system_prompt.text(),
+                self.sampling.params(),
+                self.branch_messages(index),
+            ),
+            ChatAction::ConversationBranched,
+        )
+    }
+
+    /// Database id of the conversation on screen; empty if it was never
+    /// saved.
+    pub fn conversation_id(&self) -> &str {
+        &self.conversation_id
+    }
+
+    /// Whether a response or tool call is still in flight. Switching
+    /// conversations is disabled meanwhile.
+    pub fn is_busy(&self) -> bool {
+        self.can_stop() || !self.pending_approvals.is_empty()
+    }
+
+    /// Hand the requests of MCP servers this chat is still asking the user
+    /// about over to `other`.
+    pub fn move_server_requests(&mut self, other: &mut State) {
+        other.pending_sampling.append(&mut self.pending_sampling);
+        other
+            .pending_elicitation
+            .append(&mut self.pending_elicitation);
+    }
+
+    /// The model the conversation is answered by, if one is selected.
+    pub fn selected_model(&self) -> Option<&ModelInfo> {
+        self.selected_model.as_ref()
+    }
+
+    /// Fill this blank chat with `messages` from elsewhere, such as an
+    /// exchange in the quick-chat window, and save it as a conversation.
+    pub fn adopt_messages(&mut self, messages: Vec<ChatMessage>) -> Task<ChatAction> {
+        self.messages = messages;
+        self.save_conversation().chain(self.request_title())
+    }
+
+    /// Generate a title in the background once the first exchange of a
+    /// conversation with an LLM is complete.
+    fn request_title(&mut self) -> Task<ChatAction> {
+        if self.title_requested || self.conversation_id.is_empty() {
+            return Task::none();
+        }
+        let Some(model) = self.selected_model.clone() else {
+            return Task::none();
+        };
+        self.title_requested = true;
+        let id = self.conversation_id.clone();
+        let messages = self.messages.iter().map(|m| m.message.clone()).collect();
+        Task::perform(generate_title(id.clone(), model, messages), move |title| {
+            ChatAction::TitleGenerated(id.clone(), title)
+        })
+    }
+
+    /// Where the next summary should end: at the start of the latest turn
+    /// that leaves at least half of `summarize_after` messages in full.
+    /// `None` while summaries are off or the conversation is short enough.
+    fn summary_end(&self) -> Option<usize> {
+        let limit = self.summarize_after as usize;
+        let covered = self.summary.as_ref().map_or(0, |s| s.covered);
+        if limit == 0 || self.messages.len() <= covered + limit {
+            return None;
+        }
+        // Cutting before a user message keeps tool calls with their results.
+        let last = self.messages.len() - (limit / 2).max(1);
+        (covered + 1..=last)
+            .rev()
+            .find(|&index| self.messages[index].message.role == "user")
+    }
+
+    /// Summarize the oldest messages in the background once the
+    /// conversation outgrows `summarize_after`, folding in the previous
+    /// summary.
+    fn request_summary(&mut self) -> Task<ChatAction> {
+        if self.summarizing.is_some() || self.conversation_id.is_empty() {
+            return Task::none();
+        }
+        let (Some(end), Some(model)) = (self.summary_end(), self.selected_model.clone()) else {
+            return Task::none();
+        };
+        self.summarizing = Some(end);
+        let covered = self.summary.as_ref().map_or(0, |s| s.covered);
+        let previous = self.summary.as_ref().map(|s| s.text.clone());
+        let messages = self.messages[covered..end]
+            .iter()
+            .map(|m| m.message.clone())
+            .collect();
+        let id = self.conversation_id.clone();
+        Task::perform(summarize(model, previous, messages), move |summary| {
+            ChatAction::SummaryGenerated(id.clone(), end, summary)
+        })
+    }
+
+    /// Keep and save the summary requested by [`State::request_summary`],
+    /// unless the conversation changed or the summarized messages were
+    /// edited since.
+    fn on_summary_generated(
+        &mut self,
+        id: String,
+        end: usize,
+        summary: Option<String>,
+    ) -> Task<ChatAction> {
+        if id != self.conversation_id || self.summarizing != Some(end) {
+            return Task::none();
+        }
+        self.summarizing = None;
+        let Some(text) = summary else {
+            return Task::none();
+        };
+        self.summary = Some(Summary { text, covered: end });
+        self.save_conversation()
+    }
+
+    /// Show the conversation restored from the database, unless the user
+    /// already started a new one.
+    fn on_conversation_loaded(
+        &mut self,
+        conversation: Option<StoredConversation>,
+    ) -> Task<ChatAction> {
+        let Some(conversation) = conversation else {
+            return Task::none();
+        };
+        if !self.messages.is_empty() || !self.conversation_id.is_empty() {
+            return Task::none();
+        }
+        self.show_conversation(conversation);
+        Task::none()
+    }
+
+    /// Replace the transcript with `conversation`, selecting the model it
+    /// was last used with if that model is still available.
+    fn show_conversation(&mut self, conversation: StoredConversation) {
+        self.reset_conversation();
+        self.conversation_id = conversation.id;
+        self.revision = conversation.revision;
+        self.title = (!conversation.title.is_empty()).then_some(conversation.title);
+        // Stored conversations keep whatever title they already have.
+        self.title_requested = true;
+        self.system_prompt = text_editor::Content::with_text(&conversation.system_prompt);
+        self.sampling = SamplingInputs::from_params(&conversation.sampling);
+        self.messages = conversation
+            .messages
+            .into_iter()
+            .map(ChatMessage::from)
+            .collect();
+        self.summary = conversation
+            .summary
+            .filter(|(_, covered)| *covered <= self.messages.len())
+            .map(|(text, covered)| Summary { text, covered });
+        if let Some(model) = conversation
+            .model
+            .and_then(|name| self.available_models.iter().find(|m| m.name == name))
+        {
+            self.selected_model = Some(model.clone());
+        }
+    }
+
+    /// Send the failed request again, without what it left in the
+    /// transcript.
+    fn on_retry_request(&mut self) -> Task<ChatAction> {
+        if self.is_busy() {
+            return Task::none();
+        }
+        let Some(retry_from) = self.error.as_ref().and_then(|e| e.retry_from) else {
+            return Task::none();
+        };
+        self.error = None;
+        self.messages.truncate(retry_from);
+        self.awaiting_response = true;
+        self.request_completion()
+    }
+
+    /// Show `text` in the error banner. Nothing is retried from it, and it
+    /// stays out of the transcript sent to the model.
+    fn show_error(&mut self, text: String) {
+        self.error = Some(RequestError {
+            text,
+            retry_from: None,
+        });
+    }
+
+    /// Clear the transcript and everything tied to it. The next save
+    /// allocates a new conversation id.
+    fn reset_conversation(&mut self) {
+        self.messages.clear();
+        self.error = None;
+        self.input_value.clear();
+        self.files = None;
+        self.pdfs.clear();
+        self.resources.clear();
+        self.discard_screenshot();
+        self.knowledge.clear();
+        self.pending_tool_calls.clear();
+        self.pending_approvals.clear();
+        for command in std::mem::take(&mut self.running_commands) {
+            command.cancel.cancel();
+        }
+        self.expanded_tool_blocks.clear();
+        self.expanded_thinking.clear();
+        self.scrolled_back = false;
+        self.tool_iterations = 0;
+        self.next_turn_model = None;
+        self.turn_model = None;
+        self.notice = None;
+        if let Some(comparison) = self.comparison.take() {
+            comparison.cancel.cancel();
+        }
+        self.streaming_agent_message = None;
+        self.plan_message_index = None;
+        self.conversation_id = String::new();
+        self.revision = 0;
+        self.title = None;
+        self.title_requested = false;
+        self.summary = None;
+        self.summarizing = None;
+        self.highlighted_message = None;
+        self.editing = None;
+        self.system_prompt = text_editor::Content::with_text(&self.default_system_prompt);
+        self.sampling = SamplingInputs::default();
+    }
+
+    /// Highlight the message at `position` and scroll it into view. The
+    /// offset is estimated from the message's place in the transcript, since
+    /// message heights vary.
+    fn on_show_message(&mut self, position: usize) -> Task<ChatAction> {
+        if position >= self.messages.len() {
+            return Task::none();
+        }
+        self.highlighted_message = Some(position);
+        // Stay on the message rather than following new output.
+        self.scrolled_back = true;
+        let y = (0..position)
+            .map(|index| self.estimated_height(index, self.viewport.width))
+            .sum();
+        operation::scroll_to(MESSAGE_LIST, AbsoluteOffset { x: 0.0, y })
+    }
+
+    /// Estimated height of the rows of the message at `index` in a
+    /// transcript `width` wide, spacing included.
+    fn estimated_height(&self, index: usize, width: f32) -> f32 {
+        let message = &self.messages[index];
+        // The role label and actions take about 240 pixels beside the text.
+        let columns = ((width - 240.0) / CHAR_WIDTH).max(20.0) as usize;
+        let lines = |text: &str| -> f32 {
+            text.split('\n')
+                .map(|line| line.len().div_ceil(columns).max(1))
+                .sum::<usize>() as f32
+        };
+        let block = |expanded: bool, body: &str| {
+            let body = if expanded {
+                5.0 + 20.0 + lines(&pretty_json(body)) * LINE_HEIGHT
+            } else {
+                0.0
+            };
+            LINE_HEIGHT + body + ROW_SPACING
+        };
+        let msg = &message.message;
+        let mut height = 0.0;
+        for content in &msg.content {
+            if let Content::ToolResult {
+                tool_use_id,
+                content,
+                ..
+            } = content
+            {
+                let key = format!("{tool_use_id}:result");
+                height += block(self.expanded_tool_blocks.contains(&key), content);
+            }
+        }
+        if let Some(reasoning) = &msg.reasoning_content {
+            height += block(self.expanded_thinking.contains(&index), reasoning);
+        }
+        let text: Vec<&str> = msg
+            .content
+            .iter()
+            .filter_map(|c| match c {
+                Content::Text { text } if c.attached_file_name().is_none() => Some(text.as_str()),
+                _ => None,
+            })
+            .collect();
+        if msg.role != "tool" && (!text.is_empty() || msg.tool_calls.is_none()) {
+            height += match &self.editing {
+                Some((editing, draft)) if *editing == index => {
+                    (lines(&draft.text()) + 1.0) * LINE_HEIGHT + 40.0
+                }
+                _ => lines(&text.join("\n")) * LINE_HEIGHT,
+            } + ROW_SPACING;
+        }
+        if !message.images.is_empty() {
+            height += 120.0 + ROW_SPACING;
+        }
+        if msg.content.iter().any(|c| c.attached_file_name().is_some()) {
+            height += LINE_HEIGHT + ROW_SPACING;
+        }
+        for call in msg.tool_calls.iter().flatten() {
+            height += block(
+                self.expanded_tool_blocks.contains(&call.id),
+                &call.function.arguments,
+            );
+        }
+        height
+    }
+
+    /// The messages to render, with the estimated heights of those left out
+    /// above and below them. Long transcripts are rendered only near the
+    /// view, a screen's worth either side, so that thousands of messages
+    /// are not laid out on every update.
+    fn rendered_messages(&self) -> (Range<usize>, f32, f32) {
+        let count = self.messages.len();
+        if count <= VIRTUALIZE_AFTER {
+            return (0..count, 0.0, 0.0);
+        }
+        let viewport = self.viewport;
+        let heights: Vec<f32> = (0..count)
+            .map(|index| self.estimated_height(index, viewport.width))
+            .collect();
+        let total: f32 = heights.iter().sum();
+        // While following new output the view is at the end, wherever it
+        // was last reported.
+        let top = if self.scrolled_back {
+            viewport.offset
+        } else {
+            total - viewport.height
+        };
+        let (from, to) = (top - viewport.height, top + 2.0 * viewport.height);
+        let (mut start, mut above) = (0, 0.0);
+        while start < count && above + heights[start] < from {
+            above += heights[start];
+            start += 1;
+        }
+        let (mut end, mut bottom) = (start, above);
+        while end < count && bottom < to {
+            bottom += heights[end];
+            end += 1;
+        }
+        (start..end, above, total - bottom)
+    }
+
+    /// Whether the user message at `index` may be edited and resent. Agents
+    /// keep their own history, so only LLM conversations can be rewound.
+    fn can_edit_message(&self, index: usize) -> bool {
+        matches!(self.chat_target, ChatTarget::Llm)
+            && !self.is_busy()
+            && self
+                .messages
+                .get(index)
+                .is_some_and(|m| m.message.role == "user")
+    }
+
+    fn on_edit_message(&mut self, index: usize) {
+        if !self.can_edit_message(index) {
+            return;
+        }
+        let text: Vec<&str> = self.messages[index]
+            .message
+            .content
+            .iter()
+            .filter(|content| content.attached_file_name().is_none())
+            .filter_map(|content| match content {
+                Content::Text { text } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect();
+        self.editing = Some((index, text_editor::Content::with_text(&text.join("\n"))));
+    }
+
+    /// Replace the edited message's text, drop everything after it and ask
+    /// the model again. Attachments on the message are kept.
+    fn on_resend_edited_message(&mut self) -> Task<ChatAction> {
+        let Some((index, draft)) = self.editing.take() else {
+            return Task::none();
+        };
+        let text = draft.text();
+        if text.trim().is_empty() || !self.can_edit_message(index) {
+            return Task::none();
+        }
+        let mut message = self.messages[index].message.clone();
+        message.content.retain(|content| {
+            !matches!(content, Content::Text { .. }) || content.attached_file_name().is_some()
+        });
+        message.content.insert(0, Content::text(text));
+        if self.summary.as_ref().is_some_and(|s| index < s.covered) {
+            self.summary = None;
+        }
+        if self.summarizing.is_some_and(|end| index < end) {
+            self.summarizing = None;
+        }
+        self.messages.truncate(index);
+        self.messages.push(ChatMessage::from(message));
+        self.highlighted_message = None;
+        self.tool_iterations = 0;
+        self.turn = trace::new_turn();
+        self.awaiting_response = true;
+        self.turn_model = self.next_turn_model.take();
+        self.notice = None;
+        self.request_completion()
+    }
+
+    /// Dispatch any tool calls requested by the model, or end the turn.
+    fn finish_turn(&mut self, tool_calls: Vec<ToolCall>) -> Task<ChatAction> {
+        if tool_calls.is_empty() {
+            self.awaiting_response = false;
+            // The title task updates the row the save creates, so it runs
+            // after. Summaries need the conversation id the save allocates.
+            let save = self.save_conversation();
+            return save.chain(Task::batch([self.request_title(), self.request_summary()]));
+        }
+        if self.tool_iterations >= self.max_tool_iterations {
+            tracing::warn!(
+                "Tool loop limit of {} reached; not running further tools",
+                self.max_tool_iterations
+            );
+            // Answer every call so the transcript stays valid for the next turn.
+            for tool_call in tool_calls {
+                self.messages.push(
+                    Message::tool_result(tool_call.id, "Tool loop limit reached.", Some(true))
+                        .into(),
+                );
+            }
+            self.show_error(format!(
+                "Loop limit reached: stopped after {} consecutive tool rounds.",
+                self.max_tool_iterations
+            ));
+            self.awaiting_response = false;
+            return self.save_conversation();
+        }
+        self.tool_iterations += 1;
+        tool_calls.iter().for_each(|tool_call| {
+            self.pending_tool_calls.insert(tool_call.id.clone());
+        });
+        let mut tasks = Vec::new();
+        for tool_call in tool_calls {
+            let policy = self
+                .tool_policies
+                .get(&tool_call.function.name)
+                .copied()
+                .unwrap_or_default();
+            // Shell commands always wait for the user's approval.
+            let policy = match policy {
+                ToolPolicy::Auto if tool_call.function.name == builtin::RUN_COMMAND => {
+                    ToolPolicy::Ask
+                }
+                policy => policy,
+            };
+            match policy {
+                ToolPolicy::Auto => tasks.push(self.on_tool_called(tool_call)),
+                ToolPolicy::Ask => self.pending_approvals.push_back(tool_call),
+                ToolPolicy::Never => {
+                    tracing::info!("Tool call blocked by policy: {}", tool_call.function.name);
+                    tasks.push(self.on_tool_response_received(Err((
+                        tool_call.id,
+                        "This tool is disabled by the user's tool policy.".to_string(),
+                    ))));
+                }
+            }
+        }
+        Task::batch(tasks)
+    }
+
+    fn on_approve_tool_call(&mut self, id: String) -> Task<ChatAction> {
+        let Some(pos) = self.pending_approvals.iter().position(|c| c.id == id) else {
+            return Task::none();
+        };
+        let tool_call = self.pending_approvals.remove(pos).unwrap();
+        self.on_tool_called(tool_call)
+    }
+
+    fn on_deny_tool_call(&mut self, id: String) -> Task<ChatAction> {
+        let Some(pos) = self.pending_approvals.iter().position(|c| c.id == id) else {
+            return Task::none();
+        };
+        let tool_call = self.pending_approvals.remove(pos).unwrap();
+        tracing::info!("User denied tool call: {}", tool_call.function.name);
+        self.on_tool_response_received(Err((
+            tool_call.id,
+            "The user denied this tool call.".to_string(),
+        )))
+    }
+
+    fn on_model_selected(&mut self, model_name: String) -> Task<ChatAction> {
+        self.selected_model = self
+            .available_models
+            .iter()
+            .find(|m| m.name == model_name)
+            .cloned();
+        Task::none()
+    }
+
+    fn on_models_loaded(&mut self, models: Vec<ModelInfo>) -> Task<ChatAction> {
+        self.apply_models(models);
+        self.awaiting_response = false;
+        Task::none()
+    }
+
+    /// Replace the model list, keeping the current selection if it is still
+    /// offered and otherwise falling back to the first available model.
+    fn apply_models(&mut self, models: Vec<ModelInfo>) {
+        self.available_models = models;
+        match &self.selected_model {
+            Some(selected) if self.available_models.contains(selected) => {}
+            _ => self.selected_model = self.available_models.first().cloned(),
+        }
+        self.next_turn_model = self
+            .next_turn_model
+            .take()
+            .filter(|model| self.available_models.contains(model));
+    }
+
+    fn on_tools_loaded(&mut self, tools: Vec<crate::models::Tool>) -> Task<ChatAction> {
+        self.available_tools = tools;
+        Task::none()
+    }
+
+    fn on_tool_called(&mut self, tool_call: ToolCall) -> Task<ChatAction> {
+        if tool_call.function.name == builtin::RUN_COMMAND {
+            return self.on_run_command(tool_call);
+        }
+        let span = tracing::info_span!(
+            "tool_call",
+            turn = self.turn,
+            tool = %tool_call.function.name
+        );
+        if tool_call.function.name != workspace::READ_FILE_TOOL {
+            return Task::perform(
+                trace::in_turn(self.turn, span, call_tool(tool_call)),
+                ChatAction::ToolResponseReceived,
+            );
+        }
+        match self.workspace.clone() {
+            Some(workspace) => Task::perform(
+                trace::in_turn(self.turn, span, read_workspace_file(workspace, tool_call)),
+                ChatAction::ToolResponseReceived,
+            ),
+            None => self.on_tool_response_received(Err((
+                tool_call.id,
+                "No workspace is attached.".to_string(),
+            ))),
+        }
+    }
+
+    /// Start the shell command an approved tool call asks for, showing its
+    /// output as it arrives.
+    fn on_run_command(&mut self, tool_call: ToolCall) -> Task<ChatAction> {
+        let Some(dir) = get_tool_manager().shell_dir() else {
+            return self.on_tool_response_received(Err((
+                tool_call.id,
+                "The shell tool is turned off.".to_string(),
+            )));
+        };
+        let Some(command) = builtin::requested_command(&tool_call.function.arguments) else {
+            return self.on_tool_response_received(Err((
+                tool_call.id,
+                "Missing the command to run.".to_string(),
+            )));
+        };
+        let cancel = CancellationToken::new();
+        self.expanded_tool_blocks
+            .insert(format!("{}:result", tool_call.id));
+        self.running_commands.push(RunningCommand {
+            call_id: tool_call.id.clone(),
+            command: command.clone(),
+            output: String::new(),
+            truncated: false,
+            cancel: cancel.clone(),
+        });
+        let call_id = tool_call.id;
+        Task::run(run_command(dir, command, cancel), move |event| {
+            ChatAction::CommandEvent(call_id.clone(), event)
+        })
+    }
+
+    /// Add a line of a running command's output, or answer its tool call
+    /// once it exits. Events of commands stopped by the user are dropped.
+    fn on_command_event(&mut self, call_id: String, event: CommandEvent) -> Task<ChatAction> {
+        let Some(index) = self
+            .running_commands
+            .iter()
+            .position(|command| command.call_id == call_id)
+        else {
+            return Task::none();
+        };
+        let status = match event {
+            CommandEvent::Output(line) => {
+                let command = &mut self.running_commands[index];
+                if command.output.len() + line.len() < MAX_COMMAND_OUTPUT {
+                    command.output.push_str(&line);
+                    command.output.push('\n');
+                } else {
+                    command.truncated = true;
+                }
+                return Task::none();
+            }
+            CommandEvent::Exited(status) => status,
+        };
+        let command = self.running_commands.remove(index);
+        let mut output = command.output;
+        if command.truncated {
+            output.push_str("[Output truncated.]\n");
+        }
+        let (success, output) = match status {
+            Ok(Some(0)) => (true, output),
+            Ok(Some(code)) => (false, format!("{output}[Exited with code {code}.]")),
+            Ok(None) => (false, format!("{output}[Killed by a signal.]")),
+            Err(err) => (false, format!("Failed to run the command: {err}")),
+        };
+        let content = if success {
+            Content::tool_result(&call_id, output)
+        } else {
+            Content::tool_result_error(&call_id, output)
+        };
+        self.on_tool_response_received(Ok(ToolCallResult {
+            id: call_id,
+            success,
+            contents: vec![content],
+        }))
+    }
+
+    fn on_tool_response_received(
+        &mut self,
+        response: Result<ToolCallResult, (String, String)>,
     ) -> Task<ChatAction> {
-        match response {
+        let was_pending = match response {
             Ok(result) => {
-                self.pending_tool_calls.remove(&result.id);
+                let was_pending = self.pending_tool_calls.remove(&result.id);
                 let message: Message = result.into();
-                self.messages.push(message.into())
+                self.messages.push(message.into());
+                was_pending
             }
             Err((call_id, error_message)) => {
-                log::error!("Tool call failed: {}", error_message);
-                self.pending_tool_calls.remove(&call_id);
+                tracing::error!("Tool call failed: {}", error_message);
+                let was_pending = self.pending_tool_calls.remove(&call_id);
                 self.messages
-                    .push(Message::tool_result(call_id, error_message, Some(true)).into())
+                    .push(Message::tool_result(call_id, error_message, Some(true)).into());
+                was_pending
             }
-        }
-        if self.pending_tool_calls.is_empty() {
+        };
+        let pending = &self.pending_tool_calls;
+        self.tool_progress.retain(|id, _| pending.contains(id));
+        // Results that arrive after Stop are recorded but don't resume the turn.
+        if was_pending && self.pending_tool_calls.is_empty() {
             self.on_send_message()
         } else {
             Task::none()
         }
     }
 
+    fn on_template_selected(&mut self, name: String) -> Task<ChatAction> {
+        let Some(template) = self
+            .available_templates
+            .iter()
+            .find(|t| t.name == name)
+            .cloned()
+        else {
+            tracing::warn!("TemplateSelected: unknown template '{}'", name);
+            return Task::none();
+        };
+        let values = template
+            .variables()
+            .into_iter()
+            .map(|v| (v, String::new()))
+            .collect::<Vec<_>>();
+        let has_variables = !values.is_empty();
+        self.pending_template = Some(PendingTemplate { template, values });
+        if has_variables {
+            Task::none()
+        } else {
+            // Nothing to ask for: start straight away.
+            self.on_start_template()
+        }
+    }
+
+    fn on_template_variable_changed(&mut self, name: String, value: String) -> Task<ChatAction> {
+        if let Some(pending) = &mut self.pending_template {
+            if let Some(entry) = pending.values.iter_mut().find(|(n, _)| *n == name) {
+                entry.1 = value;
+            }
+        }
+        Task::none()
+    }
+
+    /// Replace the transcript with the rendered messages of the pending
+    /// template. The conversation is not sent; the user continues it by
+    /// typing the next message as usual.
+    fn on_start_template(&mut self) -> Task<ChatAction> {
+        let Some(pending) = self.pending_template.take() else {
+            return Task::none();
+        };
+        let values: HashMap<String, String> = pending.values.into_iter().collect();
+        // A template always starts a new conversation.
+        self.reset_conversation();
+        self.messages = pending
+            .template
+            .render(&values)
+            .into_iter()
+            .map(|m| ChatMessage::from_role_and_text(m.role, m.content))
+            .collect();
+        Task::none()
+    }
+
     fn on_url_clicked(&mut self, url: String) -> Task<ChatAction> {
-        log::info!("URL clicked: {}", url);
+        tracing::info!("URL clicked: {}", url);
         Task::none()
     }
 
@@ -675,10 +3004,113 @@ impl State {
         }
     }
 
+    /// Refresh the per-tool policies and loop limit from `Config`. Called
+    /// when settings save.
+    pub fn refresh_tool_settings(&mut self) {
+        let config = Config::default();
+        self.tool_policies = config.tool_policies;
+        self.max_tool_iterations = config.max_tool_iterations;
+    }
+
+    /// Refresh the fallback model chain from `Config`. Called when settings
+    /// save.
+    pub fn refresh_fallback_models(&mut self) {
+        self.fallback_models = Config::default().fallback_models;
+    }
+
+    /// Refresh `summarize_after` from `Config`. Turning summaries off sends
+    /// the whole transcript again. Called when settings save.
+    pub fn refresh_summarize_after(&mut self) {
+        self.summarize_after = Config::default().summarize_after;
+        if self.summarize_after == 0 {
+            self.summary = None;
+        }
+    }
+
+    /// Refresh the pricing table and budget from `Config`, and recompute
+    /// this month's spend with the new prices. Called when settings save.
+    pub fn refresh_cost_settings(&mut self) -> Task<ChatAction> {
+        let config = Config::default();
+        self.pricing = config.pricing;
+        self.budget = config.budget;
+        self.reload_month_spend()
+    }
+
+    fn reload_month_spend(&self) -> Task<ChatAction> {
+        Task::perform(
+            load_month_spend(self.pricing.clone()),
+            ChatAction::MonthSpendLoaded,
+        )
+    }
+
+    /// Estimated spend, in US dollars, on completions this month.
+    pub fn month_spend(&self) -> f64 {
+        self.month_spend
+    }
+
+    /// Whether the budget forbids sending more LLM requests this month.
+    fn over_budget(&self) -> bool {
+        self.budget.block_when_exceeded && self.budget.is_exceeded(self.month_spend)
+    }
+
+    /// Warning shown once this month's spend nears or passes the budget.
+    fn budget_warning(&self) -> Option<String> {
+        let spend = format_cost(self.month_spend);
+        let limit = self.budget.monthly_limit;
+        if self.budget.is_exceeded(self.month_spend) {
+            let mut warning = format!("Monthly budget of ${limit:.2} reached ({spend} spent).");
+            if self.budget.block_when_exceeded {
+                warning.push_str(" New requests are blocked; raise the limit in Settings.");
+            }
+            Some(warning)
+        } else if self.budget.is_near(self.month_spend) {
+            let percent = self.month_spend / limit * 100.0;
+            Some(format!(
+                "{spend} of the ${limit:.2} monthly budget spent ({percent:.0}%)."
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// Pick up a changed `Config::default_system_prompt`. A blank chat
+    /// still showing the old default switches to the new one.
+    pub fn refresh_default_system_prompt(&mut self) {
+        let default_system_prompt = Config::default().default_system_prompt;
+        let untouched = self.system_prompt.text() == self.default_system_prompt;
+        if untouched && self.messages.is_empty() && self.conversation_id.is_empty() {
+            self.system_prompt = text_editor::Content::with_text(&default_system_prompt);
+        }
+        self.default_system_prompt = default_system_prompt;
+    }
+
+    /// Refresh the list of conversation templates from `Config`. Called when
+    /// settings save.
+    pub fn refresh_available_templates(&mut self) {
+        self.available_templates = Config::default().templates;
+        if let Some(pending) = &self.pending_template {
+            if !self.available_templates.contains(&pending.template) {
+                self.pending_template = None;
+            }
+        }
+    }
+
+    /// Drop the previewed screenshot and delete its temporary file. An
+    /// attached screenshot was already read into the message.
+    fn discard_screenshot(&mut self) {
+        if let Some((path, _)) = self.screenshot.take() {
+            if let Err(err) = std::fs::remove_file(&path) {
+                tracing::warn!("Failed to delete screenshot {}: {}", path.display(), err);
+            }
+        }
+    }
+
     fn on_open_file_dialog(&mut self) -> Task<ChatAction> {
         Task::perform(
             async {
                 rfd::AsyncFileDialog::new()
+                    .add_filter("Images", &["png", "jpg", "jpeg", "gif", "webp"])
+                    .add_filter("PDF", &["pdf"])
                     .add_filter("All files", &["*"])
                     .pick_files()
                     .await
@@ -706,21 +3138,33 @@ impl State {
             if self.files.is_none() {
                 self.files = Some(vec![]);
             }
+            // PDFs are sent as the text of their pages, read in the background.
+            let (pdfs, paths): (Vec<_>, Vec<_>) = paths.into_iter().partition(|path| {
+                mime_guess::from_path(path).first() == Some(mime_guess::mime::APPLICATION_PDF)
+            });
             let file_infos: Vec<FileData> = paths
                 .iter()
                 .filter_map(|path| {
-                    log::info!("Selected file: {}", path.display());
+                    tracing::info!("Selected file: {}", path.display());
                     let mime_type = mime_guess::from_path(path)
                         .first_or_octet_stream()
                         .essence_str()
                         .to_string();
-                    let file_data = match std::fs::read(path) {
-                        Ok(data) => {
-                            let base64_content = BASE64_ENGINE.encode(&data);
-                            Some(format!("data:{};base64,{}", mime_type, base64_content))
-                        }
+                    let file_data = if mime_type.starts_with("image/") {
+                        images::data_url(path)
+                    } else {
+                        std::fs::read(path)
+                            .map_err(anyhow::Error::from)
+                            .map(|data| {
+                                let mime_type = attachment_mime_type(&mime_type, &data);
+                                let base64_content = BASE64_ENGINE.encode(&data);
+                                format!("data:{};base64,{}", mime_type, base64_content)
+                            })
+                    };
+                    let file_data = match file_data {
+                        Ok(data) => Some(data),
                         Err(err) => {
-                            log::error!("Failed to read file {}: {}", path.display(), err);
+                            tracing::error!("Failed to read file {}: {}", path.display(), err);
                             None
                         }
                     };
@@ -739,14 +3183,18 @@ impl State {
             if let Some(files) = &mut self.files {
                 files.extend(file_infos);
             }
+            return Task::batch(
+                pdfs.into_iter()
+                    .map(|path| Task::perform(read_pdf(path), ChatAction::PdfRead)),
+            );
         } else {
-            log::info!("File selection cancelled");
+            tracing::info!("File selection cancelled");
         }
         Task::none()
     }
 
-    /// Subscription that streams [`AgentEvent`]s from the active ACP session,
-    /// if any. Each event is mapped to [`ChatAction::AgentEvent`].
+    /// Events for this chat alone: the [`AgentEvent`]s of the active ACP
+    /// session, if any.
     pub fn subscription(&self) -> Subscription<ChatAction> {
         match &self.chat_target {
             ChatTarget::Agent(name) => {
@@ -756,36 +3204,768 @@ impl State {
         }
     }
 
+    /// Events every open chat needs: model and tool list changes, plus the
+    /// periodic model refresh. Subscribed to once, not per chat.
+    pub fn shared_subscription() -> Subscription<ChatAction> {
+        Subscription::batch([
+            Subscription::run(model_updates),
+            Subscription::run(tool_updates),
+            Subscription::run(mcp_status_updates),
+            Subscription::run(mcp_resource_updates),
+            Subscription::run(mcp_prompt_updates),
+            Subscription::run(sampling_requests),
+            Subscription::run(elicitation_requests),
+            Subscription::run(tool_progress_updates),
+            Subscription::run(sampling_usage_updates),
+            iced::time::every(MODEL_REFRESH_INTERVAL).map(|_| ChatAction::RefreshModels),
+        ])
+    }
+
     pub fn view<'a>(&'a self, theme: &'a Theme) -> Element<'a, ChatAction> {
-        let chat_window = column![self.build_message_list(theme), self.build_input_area(),]
-            .spacing(10)
-            .padding(10);
+        let mut chat_window = column![].spacing(10).padding(10);
+        if matches!(self.chat_target, ChatTarget::Llm) {
+            chat_window = chat_window.push(self.build_system_prompt());
+        }
+        let mut chat_window = chat_window.push(self.build_message_list(theme));
+        if let Some(usage) = self.conversation_usage() {
+            let mut summary = format!("Conversation: {}", format_usage(&usage));
+            if let Some(cost) = self.conversation_cost() {
+                summary.push_str(&format!(" · {}", format_cost(cost)));
+            }
+            chat_window = chat_window.push(
+                container(text(summary).size(11).style(text::secondary))
+                    .width(Fill)
+                    .align_x(Alignment::End),
+            );
+        }
+        if let Some(note) = self.capability_note() {
+            chat_window = chat_window.push(text(note).size(11).style(text::secondary));
+        }
+        if let Some(note) = &self.notice {
+            chat_window = chat_window.push(text(note).size(11).style(text::warning));
+        }
+        if let Some(summary) = &self.summary {
+            let note = format!(
+                "The first {} messages are sent to the model as a summary.",
+                summary.covered
+            );
+            chat_window = chat_window.push(text(note).size(11).style(text::secondary));
+        }
+        if let Some((note, overflows)) = self.prompt_token_note() {
+            let style = if overflows {
+                text::danger
+            } else {
+                text::secondary
+            };
+            chat_window = chat_window.push(text(note).size(11).style(style));
+        }
+        if let Some(warning) = self.budget_warning() {
+            let style = if self.budget.is_exceeded(self.month_spend) {
+                text::danger
+            } else {
+                text::warning
+            };
+            chat_window = chat_window.push(text(warning).style(style));
+        }
+        if let Some(error) = &self.error {
+            chat_window = chat_window.push(Self::build_error_banner(error));
+        }
+        let chat_window = chat_window.push(self.build_input_area());
 
-        container(chat_window)
+        let page = container(chat_window)
             .width(Length::Fill)
-            .height(Length::Fill)
+            .height(Length::Fill);
+
+        let dialog = if let Some(tool_call) = self.pending_approvals.front() {
+            Self::build_tool_approval(tool_call)
+        } else if let Some(request) = self.pending_sampling.front() {
+            self.build_sampling_approval(request)
+        } else if let Some(pending) = self.pending_elicitation.front() {
+            Self::build_elicitation_form(pending)
+        } else {
+            return page.into();
+        };
+        stack![page, opaque(center(dialog).style(modal_backdrop))].into()
+    }
+
+    /// Banner showing why the last request failed, with buttons to retry
+    /// it, when it can be, or dismiss the banner.
+    fn build_error_banner(error: &RequestError) -> Element<'_, ChatAction> {
+        let mut banner = row![text(&error.text).width(Fill)]
+            .spacing(10)
+            .align_y(Alignment::Center);
+        if error.retry_from.is_some() {
+            banner = banner.push(
+                button(
+                    row![iced_fonts::lucide::refresh_cw(), text("Retry")]
+                        .spacing(5)
+                        .align_y(Alignment::Center),
+                )
+                .style(button::secondary)
+                .on_press(ChatAction::RetryRequest),
+            );
+        }
+        let banner = banner.push(
+            button(iced_fonts::lucide::x())
+                .style(button::text)
+                .on_press(ChatAction::DismissError),
+        );
+        container(banner)
+            .padding(10)
+            .width(Fill)
+            .style(container::danger)
+            .into()
+    }
+
+    /// Modal asking the user to approve or deny `tool_call`.
+    fn build_tool_approval(tool_call: &ToolCall) -> Element<'_, ChatAction> {
+        let command = (tool_call.function.name == builtin::RUN_COMMAND)
+            .then(|| builtin::requested_command(&tool_call.function.arguments))
+            .flatten();
+        let (title, name, origin, details) = match command {
+            Some(command) => {
+                let dir = get_tool_manager().shell_dir().unwrap_or_default();
+                (
+                    "The model wants to run a command",
+                    format!("in {}", dir.display()),
+                    "Built-in shell".to_string(),
+                    command,
+                )
+            }
+            None => {
+                let (name, origin) = match get_tool_manager()
+                    .tool_client_and_name_by_tool_call(tool_call.function.name.clone())
+                {
+                    Ok(Some((server, tool))) => (tool, format!("From MCP server \"{server}\"")),
+                    _ => (tool_call.function.name.clone(), "Built-in".to_string()),
+                };
+                (
+                    "The model wants to run a tool",
+                    name,
+                    origin,
+                    pretty_json(&tool_call.function.arguments),
+                )
+            }
+        };
+        let dialog = column![
+            text(title).size(20),
+            text(name).font(iced::Font::MONOSPACE),
+            text(origin).size(12).style(text::secondary),
+            scrollable(text(details).font(iced::Font::MONOSPACE)).height(Length::Shrink),
+            row![
+                button(text("Deny"))
+                    .style(button::secondary)
+                    .on_press(ChatAction::DenyToolCall(tool_call.id.clone())),
+                button(text("Approve")).on_press(ChatAction::ApproveToolCall(tool_call.id.clone())),
+            ]
+            .spacing(10),
+        ]
+        .spacing(12)
+        .max_width(560);
+
+        container(dialog)
+            .padding(20)
+            .style(container::rounded_box)
+            .into()
+    }
+
+    /// Modal asking the user to let `request`'s server use the selected
+    /// model. It can only be approved while a model is selected.
+    fn build_sampling_approval<'a>(
+        &'a self,
+        request: &'a SamplingRequest,
+    ) -> Element<'a, ChatAction> {
+        let model = match &self.selected_model {
+            Some(model) => format!(
+                "Answered by {}, at most {} tokens",
+                model.name, request.max_tokens
+            ),
+            None => "Select a model to answer it".to_string(),
+        };
+        let transcript = request
+            .system_prompt
+            .iter()
+            .map(|prompt| format!("system: {prompt}"))
+            .chain(request.messages.iter().map(|message| {
+                let text = message
+                    .text_content()
+                    .into_iter()
+                    .cloned()
+                    .collect::<Vec<_>>();
+                format!("{}: {}", message.role, text.join("\n"))
+            }))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        let dialog = column![
+            text("An MCP server wants to use your model").size(20),
+            text(format!("From MCP server \"{}\"", request.server))
+                .size(12)
+                .style(text::secondary),
+            text(model).size(12).style(text::secondary),
+            scrollable(text(transcript).font(iced::Font::MONOSPACE)).height(Length::Shrink),
+            row![
+                button(text("Deny"))
+                    .style(button::secondary)
+                    .on_press(ChatAction::DenySampling),
+                button(text("Approve")).on_press_maybe(
+                    self.selected_model
+                        .is_some()
+                        .then_some(ChatAction::ApproveSampling)
+                ),
+            ]
+            .spacing(10),
+        ]
+        .spacing(12)
+        .max_width(560);
+
+        container(dialog)
+            .padding(20)
+            .style(container::rounded_box)
+            .into()
+    }
+
+    /// Modal with the form an MCP server asked the user to fill in, one
+    /// input per field. Required fields are marked with an asterisk.
+    fn build_elicitation_form(pending: &PendingElicitation) -> Element<'_, ChatAction> {
+        let request = &pending.request;
+        let mut form = column![
+            text("An MCP server needs some details").size(20),
+            text(format!("From MCP server \"{}\"", request.server))
+                .size(12)
+                .style(text::secondary),
+            text(&request.message),
+        ]
+        .spacing(12)
+        .max_width(560);
+        for (index, (field, value)) in request.fields.iter().zip(&pending.values).enumerate() {
+            let label = if field.required {
+                format!("{}*", field.label)
+            } else {
+                field.label.clone()
+            };
+            let on_change = move |value| ChatAction::ElicitationFieldChanged(index, value);
+            let input: Element<'_, ChatAction> = match &field.kind {
+                FieldKind::Boolean => checkbox(value == "true")
+                    .label(label)
+                    .on_toggle(move |checked| on_change(checked.to_string()))
+                    .into(),
+                FieldKind::Choice(options) => column![
+                    text(label),
+                    pick_list(
+                        options.as_slice(),
+                        (!value.is_empty()).then(|| value.clone()),
+                        on_change,
+                    ),
+                ]
+                .spacing(4)
+                .into(),
+                FieldKind::Text | FieldKind::Number | FieldKind::Integer => column![
+                    text(label),
+                    text_input(&field.label, value).on_input(on_change),
+                ]
+                .spacing(4)
+                .into(),
+            };
+            let mut entry = column![input].spacing(2);
+            if let Some(description) = &field.description {
+                entry = entry.push(text(description).size(11).style(text::secondary));
+            }
+            form = form.push(entry);
+        }
+        if let Some(error) = &pending.error {
+            form = form.push(text(error).size(12).style(text::danger));
+        }
+        form = form.push(
+            row![
+                button(text("Cancel"))
+                    .style(button::secondary)
+                    .on_press(ChatAction::CancelElicitation),
+                button(text("Decline"))
+                    .style(button::secondary)
+                    .on_press(ChatAction::DeclineElicitation),
+                button(text("Submit")).on_press(ChatAction::SubmitElicitation),
+            ]
+            .spacing(10),
+        );
+
+        container(scrollable(form))
+            .padding(20)
+            .style(container::rounded_box)
             .into()
     }
 
     fn build_message_list<'a>(&'a self, theme: &'a Theme) -> Element<'a, ChatAction> {
-        let rows: Vec<Element<ChatAction>> = self
+        // Tool names by call id, so result blocks can say which tool ran.
+        let mut tool_names: HashMap<&str, &str> = HashMap::new();
+        let mut rows: Vec<Element<ChatAction>> = Vec::new();
+        let (rendered, above, below) = self.rendered_messages();
+        for msg in &self.messages[..rendered.start] {
+            for call in msg.message.tool_calls.iter().flatten() {
+                tool_names.insert(&call.id, &call.function.name);
+            }
+        }
+        if above > 0.0 {
+            rows.push(space().height(above - ROW_SPACING).into());
+        }
+        for (index, msg) in self
             .messages
             .iter()
-            .map(|msg| Self::build_message_row(&msg.message.role, msg, theme))
-            .collect();
+            .enumerate()
+            .take(rendered.end)
+            .skip(rendered.start)
+        {
+            let message = &msg.message;
+            if msg.is_note() {
+                let note = message.content.iter().filter_map(Content::as_text);
+                rows.push(
+                    text(note.collect::<String>())
+                        .size(11)
+                        .style(text::warning)
+                        .into(),
+                );
+                continue;
+            }
+            let first_row = rows.len();
+            if message.role == "tool" {
+                rows.push(self.build_tool_result_row(message, &tool_names, theme));
+            }
+            if let Some(reasoning) = &message.reasoning_content {
+                rows.push(self.build_thinking_block(index, reasoning, theme));
+            }
+            let has_text = message.content.iter().any(|c| c.as_text().is_some());
+            if message.role != "tool" && (has_text || message.tool_calls.is_none()) {
+                rows.push(match &self.editing {
+                    Some((editing, draft)) if *editing == index => Self::build_edit_row(draft),
+                    _ => row![
+                        Self::build_message_row(&message.role, &msg.markdown_items, theme),
+                        self.build_message_actions(index),
+                    ]
+                    .spacing(10)
+                    .into(),
+                });
+            }
+            if !msg.images.is_empty() {
+                rows.push(Self::build_thumbnails(&message.role, &msg.images));
+            }
+            let files: Vec<&str> = message
+                .content
+                .iter()
+                .filter_map(Content::attached_file_name)
+                .collect();
+            if !files.is_empty() {
+                rows.push(Self::build_file_chips(&message.role, files));
+            }
+            for call in message.tool_calls.iter().flatten() {
+                tool_names.insert(&call.id, &call.function.name);
+                let mut block = column![self.build_tool_block(
+                    call.id.clone(),
+                    tool_call_title(call),
+                    &call.function.arguments,
+                )]
+                .spacing(5);
+                if let Some(progress) = self.tool_progress.get(&call.id) {
+                    block = block.push(Self::build_tool_progress(progress));
+                }
+                rows.push(Self::build_tool_row("tool call", block.into(), theme));
+            }
+            if self.highlighted_message == Some(index) {
+                let highlighted = column(rows.drain(first_row..)).spacing(10);
+                rows.push(
+                    container(highlighted)
+                        .padding(5)
+                        .style(container::rounded_box)
+                        .into(),
+                );
+            }
+        }
+        if below > 0.0 {
+            rows.push(space().height(below - ROW_SPACING).into());
+        }
+        for command in &self.running_commands {
+            rows.push(Self::build_tool_row(
+                "tool",
+                self.build_tool_block(
+                    format!("{}:result", command.call_id),
+                    format!("Running {}", command.command),
+                    &command.output,
+                ),
+                theme,
+            ));
+        }
+        if let Some(pending) = self.pending_response.as_ref() {
+            if !pending.reasoning.is_empty() {
+                // Keyed by the position the finished message will take.
+                rows.push(self.build_thinking_block(
+                    self.messages.len(),
+                    &pending.reasoning,
+                    theme,
+                ));
+            }
+            if !pending.message.is_empty() {
+                rows.push(Self::build_message_row(
+                    "assistant",
+                    pending.message.markdown_items(),
+                    theme,
+                ));
+            }
+            let timing = pending.timing(Instant::now());
+            if let (Some(_), Some(timing)) = (pending.first_token_at, timing) {
+                rows.push(
+                    text(format_timing(&timing))
+                        .size(11)
+                        .style(text::secondary)
+                        .into(),
+                );
+            }
+        }
+        if matches!(self.chat_target, ChatTarget::Llm) && !self.messages.is_empty() {
+            rows.push(self.build_next_turn_picker());
+        }
 
-        scrollable(
+        let list = scrollable(
             container(column(rows).spacing(10).padding(10))
                 .width(Length::Fill)
                 .padding(10),
         )
+        .id(MESSAGE_LIST)
         .height(Length::Fill)
+        .on_scroll(|viewport| ChatAction::TranscriptScrolled(viewport.into()));
+        if !self.scrolled_back {
+            return list.into();
+        }
+        let jump = button(
+            row![
+                iced_fonts::lucide::arrow_down(),
+                text("Jump to latest").size(14)
+            ]
+            .spacing(6)
+            .align_y(Alignment::Center),
+        )
+        .on_press(ChatAction::JumpToLatest)
+        .style(button::secondary);
+        stack![
+            list,
+            container(jump)
+                .padding(15)
+                .width(Fill)
+                .height(Fill)
+                .align_x(Alignment::Center)
+                .align_y(Alignment::End),
+        ]
+        .into()
+    }
+
+    /// Thumbnails of a message's images, on the same side as its text.
+    fn build_thumbnails<'a>(role: &str, images: &'a [image::Handle]) -> Element<'a, ChatAction> {
+        let align = match role {
+            "user" => Alignment::End,
+            _ => Alignment::Start,
+        };
+        let thumbnails = images
+            .iter()
+            .map(|handle| image(handle.clone()).height(Length::Fixed(120.0)).into());
+        container(Row::with_children(thumbnails).spacing(10))
+            .width(Fill)
+            .align_x(align)
+            .into()
+    }
+
+    /// A chip naming each text file inlined in a message, in place of its
+    /// contents.
+    fn build_file_chips<'a>(role: &str, files: Vec<&'a str>) -> Element<'a, ChatAction> {
+        let align = match role {
+            "user" => Alignment::End,
+            _ => Alignment::Start,
+        };
+        let chips = files.into_iter().map(|name| {
+            container(
+                row![
+                    iced_fonts::lucide::paperclip().size(11),
+                    text(name).size(11)
+                ]
+                .spacing(3)
+                .align_y(Alignment::Center),
+            )
+            .padding([1, 6])
+            .style(container::rounded_box)
+            .into()
+        });
+        container(Row::with_children(chips).spacing(5))
+            .width(Fill)
+            .align_x(align)
+            .into()
+    }
+
+    /// Picker below the transcript for a model to answer the next turn
+    /// only, leaving the conversation's model selected.
+    fn build_next_turn_picker(&self) -> Element<'_, ChatAction> {
+        let names: Vec<String> = self
+            .available_models
+            .iter()
+            .map(|m| m.name.clone())
+            .collect();
+        let placeholder = self
+            .selected_model
+            .as_ref()
+            .map_or(String::new(), |m| m.name.clone());
+        let mut picker = row![
+            text("Next reply from").size(11).style(text::secondary),
+            pick_list(
+                names,
+                self.next_turn_model.as_ref().map(|m| m.name.clone()),
+                ChatAction::NextTurnModelSelected,
+            )
+            .placeholder(placeholder)
+            .text_size(11),
+        ]
+        .spacing(5)
+        .align_y(Alignment::Center);
+        if self.next_turn_model.is_some() {
+            picker = picker.push(
+                button(iced_fonts::lucide::x())
+                    .style(button::text)
+                    .padding(0)
+                    .on_press(ChatAction::ClearNextTurnModel),
+            );
+        }
+        picker.into()
+    }
+
+    /// A badge naming the model that wrote the message at `index` and when
+    /// it was written, followed by buttons acting on it.
+    fn build_message_actions(&self, index: usize) -> Element<'_, ChatAction> {
+        let message = &self.messages[index];
+        let mut actions = row![].spacing(5).align_y(Alignment::Center);
+        if let Some(model) = &message.model {
+            actions = actions.push(
+                container(text(model).size(11))
+                    .padding([1, 6])
+                    .style(container::rounded_box),
+            );
+        }
+        let metadata = text(message_metadata(message, self.message_cost(message)))
+            .size(11)
+            .style(text::secondary);
+        let metadata: Element<'_, ChatAction> = match &message.timing {
+            // The timing in full when hovered.
+            Some(timing) => tooltip(
+                metadata,
+                container(text(timing_details(timing)).size(11))
+                    .padding(5)
+                    .style(container::rounded_box),
+                tooltip::Position::Top,
+            )
+            .into(),
+            None => metadata.into(),
+        };
+        actions = actions.push(metadata).push(
+            button(iced_fonts::lucide::copy())
+                .style(button::text)
+                .padding(0)
+                .on_press(ChatAction::CopyMessage(index)),
+        );
+        if self.can_edit_message(index) {
+            actions = actions.push(
+                button(iced_fonts::lucide::pencil())
+                    .style(button::text)
+                    .padding(0)
+                    .on_press(ChatAction::EditMessage(index)),
+            );
+        }
+        if !self.is_busy() {
+            actions = actions.push(
+                button(iced_fonts::lucide::git_branch())
+                    .style(button::text)
+                    .padding(0)
+                    .on_press(ChatAction::BranchFrom(index)),
+            );
+        }
+        actions.into()
+    }
+
+    /// Inline editor replacing a user message while it is being edited.
+    fn build_edit_row(draft: &text_editor::Content) -> Element<'_, ChatAction> {
+        column![
+            text_editor(draft).on_action(ChatAction::EditedMessageChanged),
+            row![
+                button("Cancel")
+                    .style(button::secondary)
+                    .on_press(ChatAction::CancelEdit),
+                button("Send").on_press(ChatAction::ResendEditedMessage),
+            ]
+            .spacing(10),
+        ]
+        .spacing(5)
+        .align_x(Alignment::End)
+        .into()
+    }
+
+    fn build_tool_result_row<'a>(
+        &'a self,
+        message: &'a Message,
+        tool_names: &HashMap<&str, &str>,
+        theme: &'a Theme,
+    ) -> Element<'a, ChatAction> {
+        let blocks = message.content.iter().filter_map(|content| match content {
+            crate::models::Content::ToolResult {
+                tool_use_id,
+                content,
+                is_error,
+            } => {
+                let name = tool_names
+                    .get(tool_use_id.as_str())
+                    .copied()
+                    .unwrap_or("tool");
+                let title = match is_error {
+                    Some(true) => format!("Error from {name}"),
+                    _ => format!("Result from {name}"),
+                };
+                Some(self.build_tool_block(format!("{tool_use_id}:result"), title, content))
+            }
+            _ => None,
+        });
+        Self::build_tool_row("tool", column(blocks).spacing(5).into(), theme)
+    }
+
+    /// A role label followed by tool blocks, laid out like an assistant row.
+    fn build_tool_row<'a>(
+        role: &'a str,
+        content: Element<'a, ChatAction>,
+        theme: &'a Theme,
+    ) -> Element<'a, ChatAction> {
+        row![
+            container(text(role).color(theme.palette().background)).width(Shrink),
+            container(content).width(Fill),
+        ]
+        .spacing(20)
+        .width(Fill)
         .into()
     }
 
+    /// Collapsible editor for the conversation's system prompt. While
+    /// collapsed, the header shows the start of the prompt.
+    fn build_system_prompt(&self) -> Element<'_, ChatAction> {
+        let chevron = if self.system_prompt_open {
+            iced_fonts::lucide::chevron_down()
+        } else {
+            iced_fonts::lucide::chevron_right()
+        };
+        let prompt = self.system_prompt.text();
+        let summary = match prompt.lines().find(|l| !l.trim().is_empty()) {
+            Some(line) if !self.system_prompt_open => format!("System prompt: {}", line.trim()),
+            _ => "System prompt".to_string(),
+        };
+        let header = button(
+            row![chevron, text(summary)]
+                .spacing(5)
+                .align_y(Alignment::Center),
+        )
+        .style(button::text)
+        .padding(0)
+        .on_press(ChatAction::ToggleSystemPrompt);
+
+        let mut block = column![header].spacing(5);
+        if self.system_prompt_open {
+            block = block.push(
+                text_editor(&self.system_prompt)
+                    .placeholder("Instructions sent to the model before every message")
+                    .on_action(ChatAction::SystemPromptEdited)
+                    .height(120),
+            );
+        }
+        block.into()
+    }
+
+    /// A collapsible block for a tool call or result, toggled by its header
+    /// and remembered under `key`. When expanded, `body` is shown
+    /// pretty-printed if it is JSON.
+    fn build_tool_block<'a>(
+        &self,
+        key: String,
+        title: String,
+        body: &'a str,
+    ) -> Element<'a, ChatAction> {
+        let expanded = self.expanded_tool_blocks.contains(&key);
+        let chevron = if expanded {
+            iced_fonts::lucide::chevron_down()
+        } else {
+            iced_fonts::lucide::chevron_right()
+        };
+        let header = button(
+            row![chevron, text(title)]
+                .spacing(5)
+                .align_y(Alignment::Center),
+        )
+        .style(button::text)
+        .padding(0)
+        .on_press(ChatAction::ToggleToolBlock(key));
+
+        let mut block = column![header].spacing(5);
+        if expanded {
+            block = block.push(
+                container(text(pretty_json(body)).font(iced::Font::MONOSPACE))
+                    .padding(10)
+                    .width(Fill)
+                    .style(container::rounded_box),
+            );
+        }
+        block.into()
+    }
+
+    /// Status line of a running tool call: a bar when its server knows how
+    /// much there is to do, and its last message.
+    fn build_tool_progress(progress: &ToolProgress) -> Element<'_, ChatAction> {
+        let mut status = row![].spacing(10).align_y(Alignment::Center);
+        if let Some(total) = progress.total.filter(|total| *total > 0.0) {
+            status = status.push(
+                progress_bar(0.0..=total as f32, progress.progress as f32)
+                    .girth(6)
+                    .length(Length::Fixed(160.0)),
+            );
+        }
+        status
+            .push(text(progress.status()).size(11).style(text::secondary))
+            .into()
+    }
+
+    /// A collapsed "Thinking…" header above the answer of the message at
+    /// `index`, showing the model's `reasoning` when expanded.
+    fn build_thinking_block<'a>(
+        &self,
+        index: usize,
+        reasoning: &'a str,
+        theme: &'a Theme,
+    ) -> Element<'a, ChatAction> {
+        let expanded = self.expanded_thinking.contains(&index);
+        let chevron = if expanded {
+            iced_fonts::lucide::chevron_down()
+        } else {
+            iced_fonts::lucide::chevron_right()
+        };
+        let header = button(
+            row![chevron, text("Thinking…").style(text::secondary)]
+                .spacing(5)
+                .align_y(Alignment::Center),
+        )
+        .style(button::text)
+        .padding(0)
+        .on_press(ChatAction::ToggleThinking(index));
+
+        let mut block = column![header].spacing(5);
+        if expanded {
+            block = block.push(
+                container(text(reasoning).style(text::secondary))
+                    .padding(10)
+                    .width(Fill)
+                    .style(container::rounded_box),
+            );
+        }
+        Self::build_tool_row("assistant", block.into(), theme)
+    }
+
     fn build_message_row<'a>(
         role: &'a str,
-        message: &'a ChatMessage,
+        markdown_items: &'a [markdown::Item],
         theme: &'a Theme,
     ) -> Element<'a, ChatAction> {
         let align = match role {
@@ -801,22 +3981,21 @@ impl State {
             container(text(role).color(color))
                 .width(Shrink)
                 .align_x(align);
-        let content_widget: container::Container<'_, ChatAction, _, _> = container(
-            markdown(
-                &message.markdown_items,
+        let content_widget: container::Container<'_, ChatAction, _, _> =
+            container(markdown::view_with(
+                markdown_items,
                 markdown::Settings::with_style(markdown::Style::from_palette(theme.palette())),
-            )
-            .map(|url| ChatAction::UrlClicked(url.to_string())),
-        )
-        .width(Fill)
-        .align_x(align);
+                &MessageViewer,
+            ))
+            .width(Fill)
+            .align_x(align);
         let mut elements = vec![];
         match role {
             "user" => {
                 elements.push(content_widget.into());
                 elements.push(role_widget.into());
             }
-            "assistant" | "tool" => {
+            "assistant" | "tool" | "system" => {
                 elements.push(role_widget.into());
                 elements.push(content_widget.into());
             }
@@ -862,7 +4041,7 @@ impl State {
                 .into()
         };
 
-        let main_row = row![
+        let mut main_row = row![
             text_input("Type a message...", &self.input_value)
                 .on_input_maybe(if self.awaiting_response {
                     None
@@ -872,22 +4051,67 @@ impl State {
                 .on_submit(ChatAction::SendMessage)
                 .width(Length::FillPortion(10)),
             button("📁")
-                .on_press(ChatAction::OpenFileDialog)
+                .on_press_maybe(
+                    self.selected_capabilities()
+                        .vision
+                        .then_some(ChatAction::OpenFileDialog)
+                )
+                .width(Length::FillPortion(1)),
+            button(iced_fonts::lucide::camera())
+                .on_press_maybe(
+                    self.selected_capabilities()
+                        .vision
+                        .then_some(ChatAction::CaptureScreenshot)
+                )
+                .width(Length::FillPortion(1)),
+            button(iced_fonts::lucide::book_open())
+                .style(if self.use_knowledge {
+                    button::primary
+                } else {
+                    button::secondary
+                })
+                .on_press(ChatAction::ToggleKnowledge)
+                .width(Length::FillPortion(1)),
+            button(iced_fonts::lucide::folder_open())
+                .style(if self.workspace.is_some() {
+                    button::primary
+                } else {
+                    button::secondary
+                })
+                .on_press(ChatAction::PickWorkspace)
+                .width(Length::FillPortion(1)),
+            button(iced_fonts::lucide::download())
+                .on_press_maybe((!self.messages.is_empty()).then_some(ChatAction::ExportHtml))
                 .width(Length::FillPortion(1)),
             self.build_send_button(),
+            self.build_stop_button(),
             target_picker,
             model_picker,
         ]
         .spacing(10)
         .align_y(Alignment::Center);
+        if matches!(self.chat_target, ChatTarget::Llm) {
+            main_row = main_row.extend(self.build_sampling_inputs());
+        }
 
         // Auth row: only present when there are advertised auth methods for
         // the active agent and no auth attempt is currently in flight.
         let auth_row = self.build_auth_row();
         let cmd_row = self.build_slash_command_row();
         let resume_row = self.build_resume_row();
+        let template_rows = self.build_template_rows();
+        let compare_row = self.build_compare_row();
+        let workspace_row = self.build_workspace_row();
+        let mcp_status_row = self.build_mcp_status_row();
+        let resource_row = self.build_resource_suggestion_row();
+        let prompt_rows = self.build_prompt_rows();
+        let attachment_row = self.build_attachment_row();
+        let screenshot_preview = self.build_screenshot_preview();
 
         let mut col = column![].spacing(8);
+        if let Some(tr) = template_rows {
+            col = col.push(tr);
+        }
         if let Some(rr) = resume_row {
             col = col.push(rr);
         }
@@ -897,65 +4121,490 @@ impl State {
         if let Some(cr) = cmd_row {
             col = col.push(cr);
         }
+        if let Some(cr) = compare_row {
+            col = col.push(cr);
+        }
+        if let Some(sp) = screenshot_preview {
+            col = col.push(sp);
+        }
+        if let Some(wr) = workspace_row {
+            col = col.push(wr);
+        }
+        if let Some(mr) = mcp_status_row {
+            col = col.push(mr);
+        }
+        if let Some(ar) = attachment_row {
+            col = col.push(ar);
+        }
+        if let Some(rr) = resource_row {
+            col = col.push(rr);
+        }
+        if let Some(pr) = prompt_rows {
+            col = col.push(pr);
+        }
         col.push(main_row).into()
     }
 
-    /// Build a "Resume last session" row when the active agent has a stored
-    /// session id and is not currently in an auth-required state. Returns
-    /// `None` otherwise.
-    fn build_resume_row(&self) -> Option<Element<'_, ChatAction>> {
-        let agent = match &self.chat_target {
-            ChatTarget::Agent(name) => name.clone(),
-            ChatTarget::Llm => return None,
-        };
-        if !self.pending_auth_methods.is_empty() {
+    /// The attached workspace, removable. `None` when there is none.
+    fn build_workspace_row(&self) -> Option<Element<'_, ChatAction>> {
+        let workspace = self.workspace.as_ref()?;
+        let chip = container(
+            row![
+                iced_fonts::lucide::folder_open().size(11),
+                text(workspace.name()).size(11),
+                button(iced_fonts::lucide::x())
+                    .style(button::text)
+                    .padding(0)
+                    .on_press(ChatAction::CloseWorkspace),
+            ]
+            .spacing(3)
+            .align_y(Alignment::Center),
+        )
+        .padding([1, 6])
+        .style(container::rounded_box);
+        Some(
+            row![text("Workspace").size(11).style(text::secondary), chip]
+                .spacing(5)
+                .align_y(Alignment::Center)
+                .into(),
+        )
+    }
+
+    /// A chip per MCP server, coloured by whether it is up, with its full
+    /// status when hovered. `None` in agent mode or without servers.
+    fn build_mcp_status_row(&self) -> Option<Element<'_, ChatAction>> {
+        if !matches!(self.chat_target, ChatTarget::Llm) || self.mcp_status.is_empty() {
             return None;
         }
-        // Check stored session presence (cheap: Config::default reads the
-        // settings file but this view is only re-rendered on state changes).
-        let cfg = Config::default();
-        let stored = cfg.acp_session_state.get(&agent)?;
-        let label = format!(
-            "Resume last session ({}…)",
-            stored.session_id.chars().take(8).collect::<String>()
-        );
-        let mut btn = button(text(label));
-        if !self.awaiting_response {
-            btn = btn.on_press(ChatAction::ResumeAgent { agent });
+        let mut status_row = row![text("MCP").size(11).style(text::secondary)]
+            .spacing(5)
+            .align_y(Alignment::Center);
+        for (name, status) in &self.mcp_status {
+            let chip = container(
+                row![
+                    text("●").size(11).style(status_style(status)),
+                    text(name).size(11),
+                ]
+                .spacing(3)
+                .align_y(Alignment::Center),
+            )
+            .padding([1, 6])
+            .style(container::rounded_box);
+            status_row = status_row.push(tooltip(
+                chip,
+                container(text(status.to_string()).size(11))
+                    .padding(5)
+                    .style(container::rounded_box),
+                tooltip::Position::Top,
+            ));
         }
-        let row_widgets: Row<'_, ChatAction> = Row::new()
-            .spacing(10)
-            .align_y(Alignment::Center)
-            .push(btn);
-        Some(row_widgets.into())
+        Some(status_row.wrap().into())
     }
 
-    /// Build a horizontal chip row with one button per advertised slash
-    /// command. Returns `None` outside of agent mode or when no commands are
-    /// advertised.
-    fn build_slash_command_row(&self) -> Option<Element<'_, ChatAction>> {
-        if self.available_commands.is_empty()
-            || matches!(self.chat_target, ChatTarget::Llm)
-        {
+    /// A button per MCP resource matching the `@` mention being typed, with
+    /// its server and description when hovered. `None` in agent mode or
+    /// when nothing matches.
+    fn build_resource_suggestion_row(&self) -> Option<Element<'_, ChatAction>> {
+        if !matches!(self.chat_target, ChatTarget::Llm) {
             return None;
         }
-        let mut row_widgets: Row<'_, ChatAction> = Row::new().spacing(6).align_y(Alignment::Center);
-        row_widgets = row_widgets.push(text("Commands:"));
-        for cmd in &self.available_commands {
-            let name = cmd.name.clone();
-            let label = match &cmd.input_hint {
-                Some(h) if !h.is_empty() => format!("/{} ⟨{}⟩", cmd.name, h),
-                _ => format!("/{}", cmd.name),
-            };
-            let mut btn = button(text(label));
-            if !self.awaiting_response {
-                btn = btn.on_press(ChatAction::SlashCommandSelected(name));
-            }
-            row_widgets = row_widgets.push(btn);
+        let suggestions = self.resource_suggestions();
+        if suggestions.is_empty() {
+            return None;
         }
-        Some(scrollable(row_widgets).direction(scrollable::Direction::Horizontal(
-            scrollable::Scrollbar::default(),
-        )).into())
+        let mut suggestion_row = row![text("Resources").size(11).style(text::secondary)]
+            .spacing(5)
+            .align_y(Alignment::Center);
+        for resource in suggestions {
+            let details = resource.description.as_deref().unwrap_or(&resource.uri);
+            suggestion_row = suggestion_row.push(tooltip(
+                button(text(format!("@{}", resource.name)).size(11))
+                    .style(button::secondary)
+                    .padding([1, 6])
+                    .on_press(ChatAction::ResourceMentioned(resource.clone())),
+                container(text(format!("{} · {}", resource.server, details)).size(11))
+                    .padding(5)
+                    .style(container::rounded_box),
+                tooltip::Position::Top,
+            ));
+        }
+        Some(suggestion_row.wrap().into())
+    }
+
+    /// The form for the arguments of the pending MCP prompt or, without
+    /// one, a button per prompt matching the `/` command being typed. `None`
+    /// in agent mode or when there is neither.
+    fn build_prompt_rows(&self) -> Option<Element<'_, ChatAction>> {
+        if !matches!(self.chat_target, ChatTarget::Llm) {
+            return None;
+        }
+        let Some(pending) = &self.pending_prompt else {
+            let suggestions = self.prompt_suggestions();
+            if suggestions.is_empty() {
+                return None;
+            }
+            let mut suggestion_row = row![text("Prompts").size(11).style(text::secondary)]
+                .spacing(5)
+                .align_y(Alignment::Center);
+            for prompt in suggestions {
+                let details = prompt.description.as_deref().unwrap_or(&prompt.name);
+                suggestion_row = suggestion_row.push(tooltip(
+                    button(text(format!("/{}", prompt.name)).size(11))
+                        .style(button::secondary)
+                        .padding([1, 6])
+                        .on_press(ChatAction::PromptSelected(prompt.clone())),
+                    container(text(format!("{} · {}", prompt.server, details)).size(11))
+                        .padding(5)
+                        .style(container::rounded_box),
+                    tooltip::Position::Top,
+                ));
+            }
+            return Some(suggestion_row.wrap().into());
+        };
+
+        let mut form = column![row![
+            text(format!("/{}", pending.prompt.name)),
+            text(&pending.prompt.server).size(12).style(text::secondary),
+        ]
+        .spacing(10)
+        .align_y(Alignment::Center)]
+        .spacing(6);
+        for (argument, (name, value)) in pending.prompt.arguments.iter().zip(&pending.values) {
+            let label = if argument.required {
+                format!("{name}*:")
+            } else {
+                format!("{name}:")
+            };
+            let placeholder = argument.description.as_deref().unwrap_or(name);
+            let arg = name.clone();
+            form = form.push(
+                row![
+                    text(label),
+                    text_input(placeholder, value)
+                        .on_input(move |v| ChatAction::PromptArgumentChanged(arg.clone(), v)),
+                ]
+                .spacing(10)
+                .align_y(Alignment::Center),
+            );
+        }
+        let mut run_btn = button(text("Run"));
+        if !self.awaiting_response && pending.ready() {
+            run_btn = run_btn.on_press(ChatAction::RunPrompt);
+        }
+        form = form.push(
+            row![
+                run_btn,
+                button(text("Cancel")).on_press(ChatAction::CancelPrompt),
+            ]
+            .spacing(10),
+        );
+        Some(form.into())
+    }
+
+    /// The captured screenshot with buttons to attach or discard it. `None`
+    /// when there is none.
+    fn build_screenshot_preview(&self) -> Option<Element<'_, ChatAction>> {
+        let (_, preview) = self.screenshot.as_ref()?;
+        let buttons = column![
+            button(text("Attach")).on_press(ChatAction::AttachScreenshot),
+            button(text("Discard"))
+                .style(button::secondary)
+                .on_press(ChatAction::DiscardScreenshot),
+        ]
+        .spacing(5);
+        Some(
+            container(
+                row![image(preview.clone()).height(Length::Fixed(160.0)), buttons]
+                    .spacing(10)
+                    .align_y(Alignment::Center),
+            )
+            .padding(5)
+            .style(container::rounded_box)
+            .into(),
+        )
+    }
+
+    /// The files attached to the next message, each removable. `None` when
+    /// nothing is attached.
+    fn build_attachment_row(&self) -> Option<Element<'_, ChatAction>> {
+        let files = self.files.as_deref().unwrap_or_default();
+        if files.is_empty() && self.pdfs.is_empty() && self.resources.is_empty() {
+            return None;
+        }
+        let mut attachment_row = row![text("Attached").size(11).style(text::secondary)]
+            .spacing(5)
+            .align_y(Alignment::Center);
+        for (index, file) in files.iter().enumerate() {
+            attachment_row = attachment_row.push(
+                container(
+                    row![
+                        text(file.filename.clone().unwrap_or_default()).size(11),
+                        button(iced_fonts::lucide::x())
+                            .style(button::text)
+                            .padding(0)
+                            .on_press(ChatAction::RemoveAttachment(index)),
+                    ]
+                    .spacing(3)
+                    .align_y(Alignment::Center),
+                )
+                .padding([1, 6])
+                .style(container::rounded_box),
+            );
+        }
+        for (index, attachment) in self.pdfs.iter().enumerate() {
+            let count = attachment.pages.len();
+            attachment_row = attachment_row.push(
+                container(
+                    row![
+                        text(&attachment.filename).size(11),
+                        text("pages").size(11).style(text::secondary),
+                        text_input(&pdf::default_range(count), &attachment.range)
+                            .on_input(move |range| ChatAction::PdfPagesChanged(index, range))
+                            .size(11)
+                            .padding(2)
+                            .width(Length::Fixed(70.0)),
+                        text(format!("of {}", count))
+                            .size(11)
+                            .style(text::secondary),
+                        button(iced_fonts::lucide::x())
+                            .style(button::text)
+                            .padding(0)
+                            .on_press(ChatAction::RemovePdf(index)),
+                    ]
+                    .spacing(3)
+                    .align_y(Alignment::Center),
+                )
+                .padding([1, 6])
+                .style(container::rounded_box),
+            );
+        }
+        for (index, attachment) in self.resources.iter().enumerate() {
+            attachment_row = attachment_row.push(
+                container(
+                    row![
+                        text(format!("@{}", attachment.resource.name)).size(11),
+                        button(iced_fonts::lucide::x())
+                            .style(button::text)
+                            .padding(0)
+                            .on_press(ChatAction::RemoveResource(index)),
+                    ]
+                    .spacing(3)
+                    .align_y(Alignment::Center),
+                )
+                .padding([1, 6])
+                .style(container::rounded_box),
+            );
+        }
+        Some(attachment_row.into())
+    }
+
+    /// The models each prompt is also sent to, each removable, and a picker
+    /// adding another. Only offered in LLM mode with more than one model.
+    fn build_compare_row(&self) -> Option<Element<'_, ChatAction>> {
+        if !matches!(self.chat_target, ChatTarget::Llm) || self.available_models.len() < 2 {
+            return None;
+        }
+        let mut compare_row = row![text("Compare with").size(11).style(text::secondary)]
+            .spacing(5)
+            .align_y(Alignment::Center);
+        for name in &self.compare_models {
+            compare_row = compare_row.push(
+                container(
+                    row![
+                        text(name).size(11),
+                        button(iced_fonts::lucide::x())
+                            .style(button::text)
+                            .padding(0)
+                            .on_press(ChatAction::CompareModelRemoved(name.clone())),
+                    ]
+                    .spacing(3)
+                    .align_y(Alignment::Center),
+                )
+                .padding([1, 6])
+                .style(container::rounded_box),
+            );
+        }
+        let addable: Vec<String> = self
+            .available_models
+            .iter()
+            .map(|m| m.name.clone())
+            .filter(|name| {
+                !self.compare_models.contains(name)
+                    && self.selected_model.as_ref().map(|m| &m.name) != Some(name)
+            })
+            .collect();
+        if !addable.is_empty() {
+            compare_row = compare_row.push(
+                pick_list(addable, None::<String>, ChatAction::CompareModelAdded)
+                    .placeholder("Add model…")
+                    .text_size(11),
+            );
+        }
+        Some(compare_row.into())
+    }
+
+    /// Inputs for the conversation's sampling parameters. Empty inputs
+    /// show the parameter name and leave the provider default in place.
+    /// Reasoning models get an effort picker in place of temp and top_p.
+    fn build_sampling_inputs(&self) -> Vec<Element<'_, ChatAction>> {
+        let inputs = &self.sampling;
+        let reasoning = self.selected_capabilities().reasoning;
+        let mut fields = if reasoning {
+            vec![(SamplingField::MaxTokens, "max tokens", &inputs.max_tokens)]
+        } else {
+            vec![
+                (SamplingField::Temperature, "temp", &inputs.temperature),
+                (SamplingField::TopP, "top_p", &inputs.top_p),
+                (SamplingField::MaxTokens, "max tokens", &inputs.max_tokens),
+                (SamplingField::Stop, "stop", &inputs.stop),
+                (
+                    SamplingField::PresencePenalty,
+                    "presence",
+                    &inputs.presence_penalty,
+                ),
+                (
+                    SamplingField::FrequencyPenalty,
+                    "frequency",
+                    &inputs.frequency_penalty,
+                ),
+            ]
+        };
+        fields.push((SamplingField::Seed, "seed", &inputs.seed));
+        let mut elements: Vec<Element<'_, ChatAction>> = Vec::new();
+        if reasoning {
+            elements.push(
+                pick_list(
+                    ReasoningEffort::ALL,
+                    inputs.reasoning_effort,
+                    ChatAction::ReasoningEffortSelected,
+                )
+                .placeholder("effort")
+                .width(Length::FillPortion(3))
+                .into(),
+            );
+        }
+        elements.extend(fields.into_iter().map(|(field, placeholder, value)| {
+            text_input(placeholder, value)
+                .on_input(move |value| ChatAction::SamplingChanged(field, value))
+                .width(Length::FillPortion(2))
+                .into()
+        }));
+        elements
+    }
+
+    /// Build the template picker and, when a template with variables has been
+    /// picked, one input per variable plus Start / Cancel buttons. Returns
+    /// `None` outside of LLM mode or when no templates are configured.
+    fn build_template_rows(&self) -> Option<Element<'_, ChatAction>> {
+        if self.available_templates.is_empty() || !matches!(self.chat_target, ChatTarget::Llm) {
+            return None;
+        }
+        let names: Vec<String> = self
+            .available_templates
+            .iter()
+            .map(|t| t.name.clone())
+            .collect();
+        let picker = pick_list(
+            names,
+            self.pending_template
+                .as_ref()
+                .map(|p| p.template.name.clone()),
+            ChatAction::TemplateSelected,
+        )
+        .placeholder("Start from template…");
+        let picker_row: Row<'_, ChatAction> = Row::new()
+            .spacing(10)
+            .align_y(Alignment::Center)
+            .push(text("Template:"))
+            .push(picker);
+
+        let Some(pending) = &self.pending_template else {
+            return Some(picker_row.into());
+        };
+
+        let mut form = column![picker_row].spacing(6);
+        for (name, value) in &pending.values {
+            let var = name.clone();
+            form = form.push(
+                row![
+                    text(format!("{name}:")),
+                    text_input(name, value)
+                        .on_input(move |v| ChatAction::TemplateVariableChanged(var.clone(), v)),
+                ]
+                .spacing(10)
+                .align_y(Alignment::Center),
+            );
+        }
+        let mut start_btn = button(text("Start"));
+        if !self.awaiting_response {
+            start_btn = start_btn.on_press(ChatAction::StartTemplate);
+        }
+        form = form.push(
+            row![
+                start_btn,
+                button(text("Cancel")).on_press(ChatAction::CancelTemplate),
+            ]
+            .spacing(10),
+        );
+        Some(form.into())
+    }
+
+    /// Build a "Resume last session" row when the active agent has a stored
+    /// session id and is not currently in an auth-required state. Returns
+    /// `None` otherwise.
+    fn build_resume_row(&self) -> Option<Element<'_, ChatAction>> {
+        let agent = match &self.chat_target {
+            ChatTarget::Agent(name) => name.clone(),
+            ChatTarget::Llm => return None,
+        };
+        if !self.pending_auth_methods.is_empty() {
+            return None;
+        }
+        // Check stored session presence (cheap: Config::default reads the
+        // settings file but this view is only re-rendered on state changes).
+        let cfg = Config::default();
+        let stored = cfg.acp_session_state.get(&agent)?;
+        let label = format!(
+            "Resume last session ({}…)",
+            stored.session_id.chars().take(8).collect::<String>()
+        );
+        let mut btn = button(text(label));
+        if !self.awaiting_response {
+            btn = btn.on_press(ChatAction::ResumeAgent { agent });
+        }
+        let row_widgets: Row<'_, ChatAction> = Row::new()
+            .spacing(10)
+            .align_y(Alignment::Center)
+            .push(btn);
+        Some(row_widgets.into())
+    }
+
+    /// Build a horizontal chip row with one button per advertised slash
+    /// command. Returns `None` outside of agent mode or when no commands are
+    /// advertised.
+    fn build_slash_command_row(&self) -> Option<Element<'_, ChatAction>> {
+        if self.available_commands.is_empty()
+            || matches!(self.chat_target, ChatTarget::Llm)
+        {
+            return None;
+        }
+        let mut row_widgets: Row<'_, ChatAction> = Row::new().spacing(6).align_y(Alignment::Center);
+        row_widgets = row_widgets.push(text("Commands:"));
+        for cmd in &self.available_commands {
+            let name = cmd.name.clone();
+            let label = match &cmd.input_hint {
+                Some(h) if !h.is_empty() => format!("/{} ⟨{}⟩", cmd.name, h),
+                _ => format!("/{}", cmd.name),
+            };
+            let mut btn = button(text(label));
+            if !self.awaiting_response {
+                btn = btn.on_press(ChatAction::SlashCommandSelected(name));
+            }
+            row_widgets = row_widgets.push(btn);
+        }
+        Some(scrollable(row_widgets).direction(scrollable::Direction::Horizontal(
+            scrollable::Scrollbar::default(),
+        )).into())
     }
 
     /// Build the "Sign in with X" button row when the active agent has
@@ -988,6 +4637,14 @@ impl State {
         Some(row_widgets.into())
     }
 
+    fn build_stop_button(&self) -> Element<'_, ChatAction> {
+        button(text("Stop").width(Length::Fill).center())
+            .on_press_maybe(self.can_stop().then_some(ChatAction::StopGeneration))
+            .style(button::danger)
+            .width(Length::FillPortion(2))
+            .into()
+    }
+
     fn build_send_button(&self) -> Element<'_, ChatAction> {
         let button_content = if self.awaiting_response {
             container(Spinner::new())
@@ -1006,6 +4663,212 @@ impl State {
     }
 }
 
+/// `messages` as sent with requests: without notes or the replies of
+/// compared models, which are only shown. The replies to a comparison follow
+/// one another, the answering model's first, so a reply from a different
+/// model right after another model's answer is a compared one.
+fn sent_messages(messages: &[ChatMessage]) -> impl Iterator<Item = &ChatMessage> {
+    let is_compared_reply = |previous: &ChatMessage, message: &ChatMessage| {
+        previous.message.role == "assistant"
+            && message.message.role == "assistant"
+            && previous.message.tool_calls.is_none()
+            && previous.model.is_some()
+            && message.model.is_some()
+            && previous.model != message.model
+    };
+    messages
+        .iter()
+        .enumerate()
+        .filter(move |(i, message)| *i == 0 || !is_compared_reply(&messages[i - 1], message))
+        .map(|(_, message)| message)
+        .filter(|message| !message.is_note())
+}
+
+/// Whether a failed request is worth sending to a fallback model: the
+/// provider was rate limited, overloaded or answered with a server error.
+/// Errors reported inside a stream carry no status and are told by their
+/// text.
+fn is_retryable(err: &CompletionError) -> bool {
+    if err.status.is_some() {
+        return err.has_transient_status();
+    }
+    let text = err.text.to_lowercase();
+    text.contains("rate limit") || text.contains("rate_limit") || text.contains("overloaded")
+}
+
+/// Time of the message in UTC, followed by the tokens it used and their
+/// estimated `cost`. The model that wrote it has a badge of its own.
+fn message_metadata(message: &ChatMessage, cost: Option<f64>) -> String {
+    let mut parts = vec![format_timestamp(message.created_at)];
+    parts.extend(message.usage.as_ref().map(format_usage));
+    parts.extend(message.timing.as_ref().map(format_timing));
+    parts.extend(cost.map(format_cost));
+    parts.join(" · ")
+}
+
+/// Throughput and total time of a response, like `42.0 tok/s · 3.1 s`.
+fn format_timing(timing: &ResponseTiming) -> String {
+    let elapsed = format!("{:.1} s", timing.elapsed_ms as f64 / 1000.0);
+    match timing.tokens_per_second() {
+        Some(rate) => format!("{rate:.1} tok/s · {elapsed}"),
+        None => elapsed,
+    }
+}
+
+fn format_cost(cost: f64) -> String {
+    format!("~${cost:.4}")
+}
+
+fn format_usage(usage: &TokenUsage) -> String {
+    format!(
+        "{} prompt / {} completion tokens",
+        usage.prompt_tokens, usage.completion_tokens
+    )
+}
+
+/// The timing of a response line by line: time to first token, total
+/// time and throughput.
+fn timing_details(timing: &ResponseTiming) -> String {
+    let seconds = |ms: u64| ms as f64 / 1000.0;
+    let mut lines = vec![];
+    if let Some(first_token_ms) = timing.first_token_ms() {
+        lines.push(format!(
+            "First token after {:.2} s",
+            seconds(first_token_ms)
+        ));
+    }
+    lines.push(format!("Done after {:.2} s", seconds(timing.elapsed_ms)));
+    if let Some(rate) = timing.tokens_per_second() {
+        lines.push(format!("{} tokens at {rate:.1} tokens/s", timing.tokens));
+    }
+    lines.join("\n")
+}
+
+/// Format Unix seconds as `YYYY-MM-DD HH:MM UTC`.
+fn format_timestamp(seconds: i64) -> String {
+    let (days, seconds) = (seconds.div_euclid(86_400), seconds.rem_euclid(86_400));
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02} UTC",
+        seconds / 3600,
+        seconds % 3600 / 60
+    )
+}
+
+/// `mime_type` for an attached file holding `data`, with UTF-8 files not
+/// typed as text, as most source files aren't, sent as `text/plain`.
+fn attachment_mime_type(mime_type: &str, data: &[u8]) -> String {
+    let is_text = mime_type.starts_with("text/") || mime_type == "application/json";
+    if !is_text && !mime_type.starts_with("audio/") && std::str::from_utf8(data).is_ok() {
+        "text/plain".to_string()
+    } else {
+        mime_type.to_string()
+    }
+}
+
+/// Header of a tool call's transcript block, naming the file for reads
+/// from the workspace and the command line for shell commands.
+fn tool_call_title(call: &ToolCall) -> String {
+    let arguments = &call.function.arguments;
+    let title = match call.function.name.as_str() {
+        workspace::READ_FILE_TOOL => {
+            workspace::requested_path(arguments).map(|path| format!("Read {path}"))
+        }
+        builtin::RUN_COMMAND => {
+            builtin::requested_command(arguments).map(|command| format!("Run {command}"))
+        }
+        _ => None,
+    };
+    title.unwrap_or_else(|| format!("Call {}", call.function.name))
+}
+
+/// Dimmed backdrop behind modal dialogs.
+fn modal_backdrop(_theme: &Theme) -> container::Style {
+    container::Style {
+        background: Some(
+            iced::Color {
+                a: 0.6,
+                ..iced::Color::BLACK
+            }
+            .into(),
+        ),
+        ..container::Style::default()
+    }
+}
+
+/// How often the model list is re-fetched in the background.
+const MODEL_REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15 * 60);
+
+/// Stream of [`ChatAction::ModelsChanged`] driven by the model manager's
+/// watch channel. Only changes after subscription are forwarded; the initial
+/// list arrives through [`ChatAction::ModelsLoaded`].
+fn model_updates() -> impl iced::futures::Stream<Item = ChatAction> {
+    WatchStream::from_changes(get_model_manager().subscribe()).map(ChatAction::ModelsChanged)
+}
+
+/// Stream of [`ChatAction::ToolsChanged`] driven by the tool manager's
+/// watch channel.
+fn tool_updates() -> impl iced::futures::Stream<Item = ChatAction> {
+    WatchStream::from_changes(get_tool_manager().subscribe()).map(ChatAction::ToolsChanged)
+}
+
+/// Stream of [`ChatAction::McpStatusChanged`] driven by the tool manager's
+/// status channel.
+fn mcp_status_updates() -> impl iced::futures::Stream<Item = ChatAction> {
+    WatchStream::from_changes(get_tool_manager().subscribe_status())
+        .map(ChatAction::McpStatusChanged)
+}
+
+/// Stream of [`ChatAction::McpResourcesChanged`] driven by the tool
+/// manager's resource channel.
+fn mcp_resource_updates() -> impl iced::futures::Stream<Item = ChatAction> {
+    WatchStream::from_changes(get_tool_manager().subscribe_resources())
+        .map(ChatAction::McpResourcesChanged)
+}
+
+/// Stream of [`ChatAction::McpPromptsChanged`] driven by the tool manager's
+/// prompt channel.
+fn mcp_prompt_updates() -> impl iced::futures::Stream<Item = ChatAction> {
+    WatchStream::from_changes(get_tool_manager().subscribe_prompts())
+        .map(ChatAction::McpPromptsChanged)
+}
+
+/// Stream of [`ChatAction::SamplingRequested`] for each sampling request
+/// of an MCP server. Requests missed while the chat was lagging are dropped,
+/// which denies them.
+fn sampling_requests() -> impl iced::futures::Stream<Item = ChatAction> {
+    BroadcastStream::new(get_tool_manager().subscribe_sampling())
+        .filter_map(|request| std::future::ready(request.ok()))
+        .map(ChatAction::SamplingRequested)
+}
+
+/// Stream of [`ChatAction::ElicitationRequested`] for each form an MCP
+/// server asks the user to fill in. Requests missed while the chat was
+/// lagging are dropped, which cancels them.
+fn elicitation_requests() -> impl iced::futures::Stream<Item = ChatAction> {
+    BroadcastStream::new(get_tool_manager().subscribe_elicitation())
+        .filter_map(|request| std::future::ready(request.ok()))
+        .map(ChatAction::ElicitationRequested)
+}
+
+/// Stream of [`ChatAction::ToolProgressed`] for each progress notification
+/// of a tool call. Notifications missed while the chat was lagging are
+/// dropped; the next one catches up.
+fn tool_progress_updates() -> impl iced::futures::Stream<Item = ChatAction> {
+    BroadcastStream::new(get_tool_manager().subscribe_progress())
+        .filter_map(|progress| std::future::ready(progress.ok()))
+        .map(ChatAction::ToolProgressed)
+}
+
+/// Stream of [`ChatAction::SamplingUsageRecorded`] for each sampling
+/// request whose usage was recorded. Signals missed while the chat was
+/// lagging are dropped; the next spend reload catches up.
+fn sampling_usage_updates() -> impl iced::futures::Stream<Item = ChatAction> {
+    BroadcastStream::new(get_tool_manager().subscribe_usage())
+        .filter_map(|signal| std::future::ready(signal.ok()))
+        .map(|()| ChatAction::SamplingUsageRecorded)
+}
+
 /// Build a stream of [`ChatAction::AgentEvent`]s for the named agent.
 ///
 /// Used as the `builder` argument to [`Subscription::run_with`]. We poll the
@@ -1086,11 +4949,11 @@ enum AgentSubState {
 #[cfg(test)]
 mod tests {
 
-    use crate::models::CompletionResponse;
-
     use super::*;
+    use crate::config::DEFAULT_MAX_TOOL_ITERATIONS;
     use anyhow::Result;
     use iced::futures::executor::block_on;
+    use reqwest::StatusCode;
 
     #[test]
     fn test_input_changed() {
@@ -1115,11 +4978,13 @@ mod tests {
                 name: "gpt-4o-mini".to_string(),
                 id: "gpt-4o-mini".to_string(),
                 client: Clients::OpenAI,
+                capabilities: ModelCapabilities::default(),
             }),
             available_models: vec![ModelInfo {
                 name: "gpt-4o-mini".to_string(),
                 id: "gpt-4o-mini".to_string(),
                 client: Clients::OpenAI,
+                capabilities: ModelCapabilities::default(),
             }],
             available_tools: vec![],
             awaiting_response: false,
@@ -1131,6 +4996,7 @@ mod tests {
             pending_auth_methods: Vec::new(),
             available_commands: Vec::new(),
             plan_message_index: None,
+            ..State::default()
         };
 
         let message = ChatAction::SendMessage;
@@ -1162,11 +5028,13 @@ mod tests {
                 name: "gpt-4o-mini".to_string(),
                 id: "gpt-4o-mini".to_string(),
                 client: Clients::OpenAI,
+                capabilities: ModelCapabilities::default(),
             }),
             available_models: vec![ModelInfo {
                 name: "gpt-4o-mini".to_string(),
                 id: "gpt-4o-mini".to_string(),
                 client: Clients::OpenAI,
+                capabilities: ModelCapabilities::default(),
             }],
             available_tools: vec![],
             awaiting_response: false,
@@ -1178,6 +5046,7 @@ mod tests {
             pending_auth_methods: Vec::new(),
             available_commands: Vec::new(),
             plan_message_index: None,
+            ..State::default()
         };
 
         let message = ChatAction::SendMessage;
@@ -1213,16 +5082,23 @@ mod tests {
             messages: vec![ChatMessage {
                 message: Message::user("Hello".to_string(), None),
                 markdown_items: markdown::parse("Hello").collect(),
+                created_at: 0,
+                model: None,
+                usage: None,
+                timing: None,
+                images: vec![],
             }],
             selected_model: Some(ModelInfo {
                 name: "gpt-4o-mini".to_string(),
                 id: "gpt-4o-mini".to_string(),
                 client: Clients::OpenAI,
+                capabilities: ModelCapabilities::default(),
             }),
             available_models: vec![ModelInfo {
                 name: "gpt-4o-mini".to_string(),
                 id: "gpt-4o-mini".to_string(),
                 client: Clients::OpenAI,
+                capabilities: ModelCapabilities::default(),
             }],
             available_tools: vec![],
             awaiting_response: true,
@@ -1234,20 +5110,20 @@ mod tests {
             pending_auth_methods: Vec::new(),
             available_commands: Vec::new(),
             plan_message_index: None,
+            pending_response: Some(PendingResponse::default()),
+            ..State::default()
         };
 
-        let response = ChatAction::ResponseReceived(CompletionResponse {
-            id: "resp1".to_string(),
-            object: "chat.completion".to_string(),
-            created: 0,
-            model: "gpt-4o-mini".to_string(),
-            choices: vec![crate::models::Choice {
-                index: 0,
-                message: vec![crate::models::Message::assistant("Hi there!".to_string())],
-                finish_reason: "stop".to_string(),
-            }],
-        });
-        let _ = state.update(response);
+        let _ = state.update(ChatAction::StreamDelta(Ok(CompletionDelta::Text(
+            "Hi ".to_string(),
+        ))));
+        let _ = state.update(ChatAction::StreamDelta(Ok(CompletionDelta::Text(
+            "there!".to_string(),
+        ))));
+        let _ = state.update(ChatAction::StreamDelta(Ok(CompletionDelta::Finished {
+            finish_reason: "stop".to_string(),
+        })));
+        let _ = state.update(ChatAction::StreamFinished);
 
         assert_eq!(state.messages.len(), 2);
         assert_eq!(state.messages[1].message.role, "assistant");
@@ -1259,6 +5135,168 @@ mod tests {
         assert!(!state.awaiting_response);
     }
 
+    #[test]
+    fn test_reasoning_is_kept_with_the_answer() {
+        let mut state = State {
+            pending_response: Some(PendingResponse::default()),
+            ..State::default()
+        };
+        for delta in [
+            CompletionDelta::Reasoning("Think it over.".to_string()),
+            CompletionDelta::ReasoningSignature("sig".to_string()),
+            CompletionDelta::Text("Done.".to_string()),
+        ] {
+            let _ = state.update(ChatAction::StreamDelta(Ok(delta)));
+        }
+        let _ = state.update(ChatAction::StreamFinished);
+
+        let message = &state.messages[0].message;
+        assert_eq!(message.reasoning_content.as_deref(), Some("Think it over."));
+        assert_eq!(message.reasoning_signature.as_deref(), Some("sig"));
+
+        let _ = state.update(ChatAction::ToggleThinking(0));
+        assert!(state.expanded_thinking.contains(&0));
+        let _ = state.update(ChatAction::ToggleThinking(0));
+        assert!(state.expanded_thinking.is_empty());
+    }
+
+    #[test]
+    fn test_workspace_is_offered_while_attached() {
+        let model = ModelInfo {
+            name: "gpt-4o-mini".to_string(),
+            id: "gpt-4o-mini".to_string(),
+            client: Clients::OpenAI,
+            capabilities: ModelCapabilities::default(),
+        };
+        let mut state = State {
+            messages: vec![ChatMessage::from_role_and_text("user", "Hi".to_string())],
+            ..State::default()
+        };
+        assert!(state.request_tools(&model).is_empty());
+
+        let _ = state.update(ChatAction::WorkspacePicked(Ok(Some(Workspace {
+            root: PathBuf::from("/projects/ergon"),
+            tree: "src/\n  main.rs".to_string(),
+        }))));
+        let names: Vec<String> = state
+            .request_tools(&model)
+            .into_iter()
+            .map(|Tool::Function(function)| function.name)
+            .collect();
+        assert_eq!(names, [workspace::READ_FILE_TOOL]);
+        let messages = state.request_messages();
+        assert!(messages[0].message.text_content()[0].ends_with("src/\n  main.rs"));
+
+        let call = ToolCall {
+            id: "call_1".to_string(),
+            _type: "function".to_string(),
+            function: ToolFunction {
+                name: workspace::READ_FILE_TOOL.to_string(),
+                arguments: r#"{"path": "src/main.rs"}"#.to_string(),
+            },
+        };
+        assert_eq!(tool_call_title(&call), "Read src/main.rs");
+
+        // A read asked for after the workspace was closed gets an error.
+        let _ = state.update(ChatAction::CloseWorkspace);
+        assert!(state.request_tools(&model).is_empty());
+        let _ = state.update(ChatAction::CallTool(call));
+        assert_eq!(
+            state.messages[1].message.tool_call_id.as_deref(),
+            Some("call_1")
+        );
+    }
+
+    #[test]
+    fn test_shell_commands_need_approval_and_stream_their_output() {
+        let mut state = State {
+            tool_policies: HashMap::from([(builtin::RUN_COMMAND.to_string(), ToolPolicy::Auto)]),
+            max_tool_iterations: 10,
+            ..State::default()
+        };
+        let call = ToolCall {
+            id: "call_1".to_string(),
+            _type: "function".to_string(),
+            function: ToolFunction {
+                name: builtin::RUN_COMMAND.to_string(),
+                arguments: r#"{"command": "cargo test"}"#.to_string(),
+            },
+        };
+        assert_eq!(tool_call_title(&call), "Run cargo test");
+        let _ = state.finish_turn(vec![call]);
+        assert_eq!(state.pending_approvals.len(), 1);
+        assert!(state.running_commands.is_empty());
+
+        // As if the approved command had started.
+        state.pending_approvals.clear();
+        state.running_commands.push(RunningCommand {
+            call_id: "call_1".to_string(),
+            command: "cargo test".to_string(),
+            output: String::new(),
+            truncated: false,
+            cancel: CancellationToken::new(),
+        });
+        for line in ["running 2 tests", "1 failed"] {
+            let _ = state.update(ChatAction::CommandEvent(
+                "call_1".to_string(),
+                CommandEvent::Output(line.to_string()),
+            ));
+        }
+        assert_eq!(
+            state.running_commands[0].output,
+            "running 2 tests\n1 failed\n"
+        );
+        let _ = state.update(ChatAction::CommandEvent(
+            "call_1".to_string(),
+            CommandEvent::Exited(Ok(Some(101))),
+        ));
+        assert!(state.running_commands.is_empty());
+        assert!(matches!(
+            &state.messages[0].message.content[0],
+            Content::ToolResult { content, is_error: Some(true), .. }
+                if content == "running 2 tests\n1 failed\n[Exited with code 101.]"
+        ));
+
+        // Output of a command that is no longer running is dropped.
+        let _ = state.update(ChatAction::CommandEvent(
+            "call_1".to_string(),
+            CommandEvent::Output("late".to_string()),
+        ));
+        assert_eq!(state.messages.len(), 1);
+    }
+
+    #[test]
+    fn test_retrieved_passages_are_sent_and_cited() {
+        let mut state = State {
+            messages: vec![ChatMessage::from_role_and_text("user", "Why?".to_string())],
+            knowledge: vec![
+                Passage {
+                    source: "/notes/a.md".to_string(),
+                    text: "Alpha".to_string(),
+                },
+                Passage {
+                    source: "/notes/b.md".to_string(),
+                    text: "Beta".to_string(),
+                },
+            ],
+            pending_response: Some(PendingResponse::default()),
+            ..State::default()
+        };
+        let messages = state.request_messages();
+        assert_eq!(messages[0].message.role, "system");
+        assert!(messages[0].message.text_content()[0].contains("[2] /notes/b.md\nBeta"));
+        assert_eq!(messages[1].message.role, "user");
+
+        let _ = state.update(ChatAction::StreamDelta(Ok(CompletionDelta::Text(
+            "Because of beta [2].".to_string(),
+        ))));
+        let _ = state.update(ChatAction::StreamFinished);
+        assert_eq!(
+            state.messages[1].message.text_content()[0],
+            "Because of beta [2].\n\n**Sources**\n\n- [2] `/notes/b.md`"
+        );
+    }
+
     #[test]
     fn test_response_received_error() {
         let mut state = State {
@@ -1266,16 +5304,23 @@ mod tests {
             messages: vec![ChatMessage {
                 message: Message::user("Hello".to_string(), None),
                 markdown_items: markdown::parse("Hello").collect(),
+                created_at: 0,
+                model: None,
+                usage: None,
+                timing: None,
+                images: vec![],
             }],
             selected_model: Some(ModelInfo {
                 name: "gpt-4o-mini".to_string(),
                 id: "gpt-4o-mini".to_string(),
                 client: Clients::OpenAI,
+                capabilities: ModelCapabilities::default(),
             }),
             available_models: vec![ModelInfo {
                 name: "gpt-4o-mini".to_string(),
                 id: "gpt-4o-mini".to_string(),
                 client: Clients::OpenAI,
+                capabilities: ModelCapabilities::default(),
             }],
             available_tools: vec![],
             awaiting_response: true,
@@ -1287,56 +5332,1283 @@ mod tests {
             pending_auth_methods: Vec::new(),
             available_commands: Vec::new(),
             plan_message_index: None,
+            pending_response: Some(PendingResponse::default()),
+            ..State::default()
         };
 
-        let response = ChatAction::ResponseReceived(CompletionResponse {
-            id: "error".to_string(),
-            object: "chat.completion".to_string(),
-            created: 0,
-            model: "gpt-4o-mini".to_string(),
-            choices: vec![],
-        });
-        let _ = state.update(response);
+        let _ = state.update(ChatAction::StreamFinished);
 
-        assert_eq!(state.messages.len(), 2);
-        assert_eq!(state.messages[1].message.role, "assistant");
+        assert_eq!(state.messages.len(), 1);
         assert_eq!(
-            state.messages[1].message.text_content().first(),
-            Some(&&"Error: No response from model.".to_string())
+            state.error.as_ref().map(|e| e.text.as_str()),
+            Some("No response from model.")
         );
         assert!(state.input_value.is_empty());
         assert!(!state.awaiting_response);
     }
 
     #[test]
-    fn test_model_selection() {
+    fn test_stream_error_is_shown_once() {
         let mut state = State {
-            available_models: vec![
-                ModelInfo {
-                    name: "gpt-4o-mini".to_string(),
-                    id: "gpt-4o-mini".to_string(),
-                    client: Clients::OpenAI,
-                },
-                ModelInfo {
-                    name: "gpt-3.5-turbo".to_string(),
-                    id: "gpt-3.5-turbo".to_string(),
-                    client: Clients::OpenAI,
-                },
-            ],
+            awaiting_response: true,
+            pending_response: Some(PendingResponse::default()),
             ..State::default()
         };
-        let model_name = "gpt-4o-mini".to_string();
 
-        let action = ChatAction::ModelSelected(model_name.clone());
-        let _ = state.update(action);
+        let error = CompletionError::from("rate limited".to_string());
+        let _ = state.update(ChatAction::StreamDelta(Err(error)));
+        let _ = state.update(ChatAction::StreamFinished);
+
+        // The error goes to the banner, not the transcript.
+        assert!(state.messages.is_empty());
+        assert_eq!(
+            state.error,
+            Some(RequestError {
+                text: "rate limited".to_string(),
+                retry_from: Some(0),
+            })
+        );
+        assert!(!state.awaiting_response);
+
+        let _ = state.update(ChatAction::DismissError);
+        assert!(state.error.is_none());
+    }
+
+    #[test]
+    fn test_other_failures_are_shown_without_retry() {
+        let mut state = State {
+            messages: vec![ChatMessage::from_role_and_text("user", "Hello")],
+            ..State::default()
+        };
+
+        let _ = state.update(ChatAction::HtmlExported(Err("disk full".to_string())));
+
+        assert_eq!(state.messages.len(), 1);
+        assert_eq!(
+            state.error,
+            Some(RequestError {
+                text: "Export failed: disk full".to_string(),
+                retry_from: None,
+            })
+        );
+        let _ = state.update(ChatAction::RetryRequest);
+        assert!(state.error.is_some());
+        assert!(!state.awaiting_response);
+    }
+
+    #[test]
+    fn test_retry_drops_what_the_failed_request_left() {
+        let mut state = State {
+            messages: vec![ChatMessage::from_role_and_text("user", "Hello")],
+            selected_model: Some(ModelInfo {
+                name: "gpt-4o-mini".to_string(),
+                id: "gpt-4o-mini".to_string(),
+                client: Clients::OpenAI,
+                capabilities: ModelCapabilities::default(),
+            }),
+            awaiting_response: true,
+            pending_response: Some(PendingResponse {
+                transcript_len: 1,
+                ..PendingResponse::default()
+            }),
+            ..State::default()
+        };
+        let _ = state.update(ChatAction::StreamDelta(Ok(CompletionDelta::Text(
+            "Hel".to_string(),
+        ))));
+        let error = CompletionError::from("connection reset".to_string());
+        let _ = state.update(ChatAction::StreamDelta(Err(error)));
+        let _ = state.update(ChatAction::StreamFinished);
+        assert_eq!(state.messages.len(), 2);
+        assert!(state.error.is_some());
+
+        let _ = state.update(ChatAction::RetryRequest);
+        assert!(state.error.is_none());
+        assert_eq!(state.messages.len(), 1);
+        assert!(state.awaiting_response);
+        assert_eq!(
+            state.pending_response.as_ref().map(|p| p.transcript_len),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn test_stream_error_retries_with_fallback_model() {
+        let model = |name: &str, client| ModelInfo {
+            name: name.to_string(),
+            id: name.to_string(),
+            client,
+            capabilities: ModelCapabilities::default(),
+        };
+        let mut state = State {
+            messages: vec![ChatMessage::from_role_and_text("user", "Hello")],
+            awaiting_response: true,
+            selected_model: Some(model("gpt-4o", Clients::OpenAI)),
+            available_models: vec![
+                model("gpt-4o", Clients::OpenAI),
+                model("claude-haiku-4-5", Clients::Anthropic),
+            ],
+            fallback_models: vec!["gpt-4o".to_string(), "claude-haiku-4-5".to_string()],
+            pending_response: Some(PendingResponse {
+                transcript_len: 1,
+                ..PendingResponse::default()
+            }),
+            ..State::default()
+        };
+
+        let _ = state.update(ChatAction::StreamDelta(Err(CompletionError {
+            text: "Error: 429 Too Many Requests".to_string(),
+            status: Some(StatusCode::TOO_MANY_REQUESTS),
+        })));
+        let _ = state.update(ChatAction::StreamFinished);
+
+        // The same request goes to the fallback, for this turn only, and the
+        // switch is noted in the transcript but never sent.
+        assert_eq!(state.request_messages().len(), 1);
+        assert_eq!(state.request_messages()[0].message.role, "user");
+        assert!(state.messages[1].is_note());
+        assert_eq!(
+            state.messages[1].message.content[0].as_text().as_deref(),
+            Some("gpt-4o failed (Error: 429 Too Many Requests); retried with claude-haiku-4-5.")
+        );
+        assert_eq!(
+            state.turn_model.as_ref().map(|m| m.name.as_str()),
+            Some("claude-haiku-4-5")
+        );
+        assert_eq!(
+            state.selected_model.as_ref().map(|m| m.name.as_str()),
+            Some("gpt-4o")
+        );
+        let retry = state.pending_response.as_ref().unwrap();
+        assert_eq!(retry.failed_models, vec!["gpt-4o"]);
+
+        // The chain is exhausted once the fallback fails too.
+        let _ = state.update(ChatAction::StreamDelta(Err(CompletionError {
+            text: "Error: 503 Service Unavailable".to_string(),
+            status: Some(StatusCode::SERVICE_UNAVAILABLE),
+        })));
+        let _ = state.update(ChatAction::StreamFinished);
+        assert_eq!(state.messages.len(), 2);
+        assert_eq!(
+            state.error.as_ref().map(|e| e.text.as_str()),
+            Some("Error: 503 Service Unavailable")
+        );
+        assert!(state.pending_response.is_none());
+        assert!(!state.awaiting_response);
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        let error = |text: &str, status| CompletionError {
+            text: text.to_string(),
+            status,
+        };
+        assert!(is_retryable(&error(
+            "Error: 429 Too Many Requests",
+            Some(StatusCode::TOO_MANY_REQUESTS)
+        )));
+        assert!(is_retryable(&error(
+            "Error: 502 Bad Gateway <html>",
+            Some(StatusCode::BAD_GATEWAY)
+        )));
+        assert!(is_retryable(&error(r#"{"type":"overloaded_error"}"#, None)));
+        assert!(!is_retryable(&error(
+            "Error: 401 Unauthorized",
+            Some(StatusCode::UNAUTHORIZED)
+        )));
+        // Only the status counts, not numbers in the message.
+        assert!(!is_retryable(&error(
+            "Error: 400 Bad Request context window of 512 tokens exceeded",
+            Some(StatusCode::BAD_REQUEST)
+        )));
+        assert!(!is_retryable(&error("OpenAI is not configured", None)));
+    }
+
+    #[test]
+    fn test_streamed_text_is_parsed_incrementally() {
+        let mut state = State {
+            awaiting_response: true,
+            pending_response: Some(PendingResponse::default()),
+            ..State::default()
+        };
+
+        let _ = state.update(ChatAction::StreamDelta(Ok(CompletionDelta::Text(
+            "# Title\n\nSome ".to_string(),
+        ))));
+        assert!(state.messages.is_empty());
+        let streaming = &state.pending_response.as_ref().unwrap().message;
+        assert!(!streaming.markdown_items().is_empty());
+
+        let _ = state.update(ChatAction::StreamDelta(Ok(CompletionDelta::Text(
+            "text".to_string(),
+        ))));
+        let error = CompletionError::from("connection reset".to_string());
+        let _ = state.update(ChatAction::StreamDelta(Err(error)));
+        let _ = state.update(ChatAction::StreamFinished);
+
+        assert_eq!(state.messages.len(), 1);
+        assert_eq!(
+            state.messages[0].message.text_content().first(),
+            Some(&&"# Title\n\nSome text".to_string())
+        );
+        assert!(!state.messages[0].markdown_items.is_empty());
+        assert_eq!(
+            state.error.as_ref().map(|e| e.text.as_str()),
+            Some("connection reset")
+        );
+    }
+
+    #[test]
+    fn test_usage_is_attached_to_the_response() {
+        let usage = TokenUsage {
+            prompt_tokens: 30,
+            completion_tokens: 10,
+        };
+        let mut state = State {
+            messages: vec![ChatMessage {
+                usage: Some(usage),
+                ..ChatMessage::from(Message::assistant("Earlier answer"))
+            }],
+            awaiting_response: true,
+            pending_response: Some(PendingResponse::default()),
+            ..State::default()
+        };
+
+        let _ = state.update(ChatAction::StreamDelta(Ok(CompletionDelta::Text(
+            "Hi".to_string(),
+        ))));
+        let _ = state.update(ChatAction::StreamDelta(Ok(CompletionDelta::Usage(usage))));
+        let _ = state.update(ChatAction::StreamFinished);
+
+        assert_eq!(state.messages[1].usage, Some(usage));
+        assert_eq!(
+            state.conversation_usage(),
+            Some(TokenUsage {
+                prompt_tokens: 60,
+                completion_tokens: 20,
+            })
+        );
+    }
+
+    #[test]
+    fn test_stop_keeps_partial_response() {
+        let mut state = State {
+            awaiting_response: true,
+            pending_response: Some(PendingResponse::default()),
+            ..State::default()
+        };
+
+        let _ = state.update(ChatAction::StreamDelta(Ok(CompletionDelta::Text(
+            "Partial".to_string(),
+        ))));
+        let _ = state.update(ChatAction::StreamDelta(Ok(CompletionDelta::ToolCall {
+            index: 0,
+            id: Some("call_1".to_string()),
+            name: Some("search".to_string()),
+            arguments: "{\"q\":".to_string(),
+        })));
+        let _ = state.update(ChatAction::StopGeneration);
+        assert!(state
+            .pending_response
+            .as_ref()
+            .is_some_and(|p| p.cancel.is_cancelled()));
+        let _ = state.update(ChatAction::StreamFinished);
+
+        assert_eq!(state.messages.len(), 1);
+        assert_eq!(
+            state.messages[0].message.text_content().first(),
+            Some(&&"Partial".to_string())
+        );
+        assert!(state.messages[0].message.tool_calls.is_none());
+        assert!(state.pending_tool_calls.is_empty());
+        assert!(!state.awaiting_response);
+    }
+
+    #[test]
+    fn test_tool_result_after_stop_does_not_resume() {
+        let mut state = State {
+            awaiting_response: true,
+            pending_tool_calls: HashSet::from(["call_1".to_string()]),
+            ..State::default()
+        };
+
+        let _ = state.update(ChatAction::StopGeneration);
+        assert!(!state.awaiting_response);
+
+        let _ = state.update(ChatAction::ToolResponseReceived(Err((
+            "call_1".to_string(),
+            "interrupted".to_string(),
+        ))));
+        assert_eq!(state.messages.len(), 1);
+        assert!(!state.awaiting_response);
+        assert!(state.pending_response.is_none());
+    }
+
+    #[test]
+    fn test_tool_results_re_invoke_the_model() {
+        let mut state = State {
+            awaiting_response: true,
+            selected_model: Some(ModelInfo {
+                name: "gpt-4o-mini".to_string(),
+                id: "gpt-4o-mini".to_string(),
+                client: Clients::OpenAI,
+                capabilities: ModelCapabilities::default(),
+            }),
+            pending_response: Some(PendingResponse::default()),
+            max_tool_iterations: DEFAULT_MAX_TOOL_ITERATIONS,
+            ..State::default()
+        };
+
+        let _ = state.update(ChatAction::StreamDelta(Ok(CompletionDelta::ToolCall {
+            index: 0,
+            id: Some("call_1".to_string()),
+            name: Some("__srv__weather".to_string()),
+            arguments: "{}".to_string(),
+        })));
+        let _ = state.update(ChatAction::StreamFinished);
+        assert!(state.pending_tool_calls.contains("call_1"));
+        assert!(state.pending_response.is_none());
+        assert_eq!(state.pending_approvals.len(), 1);
+
+        let _ = state.update(ChatAction::ApproveToolCall("call_1".to_string()));
+        assert!(state.pending_approvals.is_empty());
+
+        let _ = state.update(ChatAction::ToolResponseReceived(Ok(ToolCallResult {
+            success: true,
+            id: "call_1".to_string(),
+            contents: vec![crate::models::Content::tool_result("call_1", "sunny")],
+        })));
+
+        assert_eq!(state.messages.len(), 2);
+        assert_eq!(state.messages[1].message.role, "tool");
+        // The follow-up request carrying the tool result is in flight.
+        assert!(state.pending_response.is_some());
+        assert!(state.awaiting_response);
+    }
+
+    #[test]
+    fn test_denied_tool_call_returns_error_result() {
+        let mut state = State {
+            awaiting_response: true,
+            pending_response: Some(PendingResponse::default()),
+            max_tool_iterations: DEFAULT_MAX_TOOL_ITERATIONS,
+            ..State::default()
+        };
+
+        let _ = state.update(ChatAction::StreamDelta(Ok(CompletionDelta::ToolCall {
+            index: 0,
+            id: Some("call_1".to_string()),
+            name: Some("__srv__delete_file".to_string()),
+            arguments: "{}".to_string(),
+        })));
+        let _ = state.update(ChatAction::StreamFinished);
+        let _ = state.update(ChatAction::DenyToolCall("call_1".to_string()));
+
+        assert!(state.pending_approvals.is_empty());
+        assert!(state.pending_tool_calls.is_empty());
+        let result = &state.messages[1].message;
+        assert_eq!(result.role, "tool");
+        assert!(matches!(
+            &result.content[0],
+            crate::models::Content::ToolResult { tool_use_id, is_error: Some(true), .. }
+                if tool_use_id == "call_1"
+        ));
+    }
+
+    #[test]
+    fn test_tool_policies_skip_or_block_approval() {
+        let mut state = State {
+            awaiting_response: true,
+            pending_response: Some(PendingResponse::default()),
+            tool_policies: HashMap::from([
+                ("__fs__read".to_string(), ToolPolicy::Auto),
+                ("__fs__delete".to_string(), ToolPolicy::Never),
+            ]),
+            max_tool_iterations: DEFAULT_MAX_TOOL_ITERATIONS,
+            ..State::default()
+        };
+
+        for (index, name) in ["__fs__read", "__fs__delete", "__fs__write"]
+            .iter()
+            .enumerate()
+        {
+            let _ = state.update(ChatAction::StreamDelta(Ok(CompletionDelta::ToolCall {
+                index,
+                id: Some(format!("call_{index}")),
+                name: Some(name.to_string()),
+                arguments: "{}".to_string(),
+            })));
+        }
+        let _ = state.update(ChatAction::StreamFinished);
+
+        // Only the tool without a policy waits for approval.
+        assert_eq!(state.pending_approvals.len(), 1);
+        assert_eq!(state.pending_approvals[0].function.name, "__fs__write");
+        // The auto tool is running; the blocked one already has its result.
+        assert!(state.pending_tool_calls.contains("call_0"));
+        assert!(!state.pending_tool_calls.contains("call_1"));
+        assert!(matches!(
+            &state.messages.last().unwrap().message.content[0],
+            crate::models::Content::ToolResult { tool_use_id, is_error: Some(true), .. }
+                if tool_use_id == "call_1"
+        ));
+    }
+
+    #[test]
+    fn test_toggle_tool_block() {
+        let mut state = State::default();
+        let _ = state.update(ChatAction::ToggleToolBlock("call_1".to_string()));
+        assert!(state.expanded_tool_blocks.contains("call_1"));
+        let _ = state.update(ChatAction::ToggleToolBlock("call_1".to_string()));
+        assert!(state.expanded_tool_blocks.is_empty());
+    }
+
+    #[test]
+    fn test_tool_loop_stops_at_limit() {
+        let mut state = State {
+            awaiting_response: true,
+            max_tool_iterations: 1,
+            tool_iterations: 1,
+            pending_response: Some(PendingResponse::default()),
+            ..State::default()
+        };
+
+        let _ = state.update(ChatAction::StreamDelta(Ok(CompletionDelta::ToolCall {
+            index: 0,
+            id: Some("call_2".to_string()),
+            name: Some("__srv__search".to_string()),
+            arguments: "{}".to_string(),
+        })));
+        let _ = state.update(ChatAction::StreamFinished);
+
+        assert!(state.pending_approvals.is_empty());
+        assert!(state.pending_tool_calls.is_empty());
+        assert!(!state.awaiting_response);
+        assert_eq!(state.messages.len(), 2);
+        assert_eq!(state.messages[1].message.role, "tool");
+        let error = state.error.as_ref().unwrap();
+        assert!(error.text.contains("Loop limit reached"));
+        assert!(error.retry_from.is_none());
+    }
+
+    #[test]
+    fn test_conversation_loaded_only_into_empty_chat() {
+        let stored = StoredConversation {
+            id: "abc".to_string(),
+            title: String::new(),
+            model: None,
+            system_prompt: String::new(),
+            sampling: SamplingParams::default(),
+            messages: vec![
+                Message::user("Hi", None).into(),
+                StoredMessage {
+                    message: Message::assistant("Hello!"),
+                    created_at: 1_700_000_000,
+                    model: Some("gpt-4o-mini".to_string()),
+                    usage: None,
+                    timing: None,
+                },
+            ],
+            revision: 1,
+            summary: None,
+        };
+
+        let mut state = State::default();
+        let _ = state.update(ChatAction::ConversationLoaded(Some(stored.clone())));
+        assert_eq!(state.revision, 1);
+        assert_eq!(state.conversation_id, "abc");
+        assert_eq!(state.messages.len(), 2);
+        assert_eq!(state.messages[1].created_at, 1_700_000_000);
+        assert_eq!(state.messages[1].model.as_deref(), Some("gpt-4o-mini"));
+
+        let mut busy = State {
+            messages: vec![ChatMessage::from_role_and_text("user", "New chat")],
+            ..State::default()
+        };
+        let _ = busy.update(ChatAction::ConversationLoaded(Some(stored)));
+        assert!(busy.conversation_id.is_empty());
+        assert_eq!(busy.messages.len(), 1);
+    }
+
+    #[test]
+    fn test_title_requested_once_after_first_exchange() {
+        let mut state = State {
+            messages: vec![ChatMessage::from_role_and_text("user", "Hello")],
+            selected_model: Some(ModelInfo {
+                name: "gpt-4o-mini".to_string(),
+                id: "gpt-4o-mini".to_string(),
+                client: Clients::OpenAI,
+                capabilities: ModelCapabilities::default(),
+            }),
+            awaiting_response: true,
+            pending_response: Some(PendingResponse::default()),
+            ..State::default()
+        };
+        let _ = state.update(ChatAction::StreamDelta(Ok(CompletionDelta::Text(
+            "Hi there!".to_string(),
+        ))));
+        let _ = state.update(ChatAction::StreamFinished);
+        assert!(state.title_requested);
+        assert!(!state.conversation_id.is_empty());
+
+        // A title for another conversation is ignored.
+        let id = state.conversation_id.clone();
+        let _ = state.update(ChatAction::TitleGenerated(
+            "other".to_string(),
+            Some("Elsewhere".to_string()),
+        ));
+        assert_eq!(state.title(), "Hello");
+        let _ = state.update(ChatAction::TitleGenerated(
+            id,
+            Some("Greetings".to_string()),
+        ));
+        assert_eq!(state.title(), "Greetings");
+    }
+
+    #[test]
+    fn test_older_turns_are_replaced_by_their_summary() {
+        let turn = |n: usize| {
+            [
+                ChatMessage::from_role_and_text("user", format!("Question {n}")),
+                ChatMessage::from_role_and_text("assistant", format!("Answer {n}")),
+            ]
+        };
+        let mut messages: Vec<ChatMessage> = (1..=2).flat_map(turn).collect();
+        messages.push(ChatMessage::from_role_and_text("user", "Question 3"));
+        let mut state = State {
+            messages,
+            selected_model: Some(ModelInfo {
+                name: "gpt-4o-mini".to_string(),
+                id: "gpt-4o-mini".to_string(),
+                client: Clients::OpenAI,
+                capabilities: ModelCapabilities::default(),
+            }),
+            summarize_after: 4,
+            awaiting_response: true,
+            pending_response: Some(PendingResponse::default()),
+            ..State::default()
+        };
+        let _ = state.update(ChatAction::StreamDelta(Ok(CompletionDelta::Text(
+            "Answer 3".to_string(),
+        ))));
+        let _ = state.update(ChatAction::StreamFinished);
+        // The last turn is kept in full; the two before it are summarized.
+        assert_eq!(state.summarizing, Some(4));
+        assert_eq!(state.request_messages().len(), 6);
+
+        let id = state.conversation_id.clone();
+        let summary = Some("They asked two questions.".to_string());
+        let _ = state.update(ChatAction::SummaryGenerated(
+            "other".to_string(),
+            4,
+            summary.clone(),
+        ));
+        assert_eq!(state.summary, None);
+        let revision = state.revision;
+        let _ = state.update(ChatAction::SummaryGenerated(id.clone(), 4, summary.clone()));
+        assert_eq!(state.summarizing, None);
+        // The summary is saved with the conversation.
+        assert_eq!(state.revision, revision + 1);
+        let messages = state.request_messages();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0].message.role, "system");
+        assert!(messages[0].message.text_content()[0].ends_with("They asked two questions."));
+        assert_eq!(messages[1].message.text_content(), ["Question 3"]);
+        // Nothing more to summarize until the conversation grows again.
+        assert_eq!(state.summary_end(), None);
+        state.messages.extend((4..=5).flat_map(turn));
+        assert_eq!(state.summary_end(), Some(8));
+
+        // A summary no longer awaited is ignored.
+        let _ = state.update(ChatAction::SummaryGenerated(id, 4, summary));
+        assert_eq!(state.summary.as_ref().map(|s| s.covered), Some(4));
+        state.reset_conversation();
+        assert_eq!(state.summary, None);
+    }
+
+    #[test]
+    fn test_next_turn_model_answers_one_turn_only() {
+        let model = |name: &str, client| ModelInfo {
+            name: name.to_string(),
+            id: name.to_string(),
+            client,
+            capabilities: ModelCapabilities::default(),
+        };
+        let mut state = State {
+            selected_model: Some(model("gpt-4o", Clients::OpenAI)),
+            available_models: vec![
+                model("gpt-4o", Clients::OpenAI),
+                model("claude-haiku-4-5", Clients::Anthropic),
+            ],
+            ..State::default()
+        };
+        let _ = state.update(ChatAction::NextTurnModelSelected(
+            "claude-haiku-4-5".to_string(),
+        ));
+        state.input_value = "Hello".to_string();
+        let _ = state.update(ChatAction::SendMessage);
+        assert!(state.next_turn_model.is_none());
+        let _ = state.update(ChatAction::StreamDelta(Ok(CompletionDelta::Text(
+            "Hi!".to_string(),
+        ))));
+        let _ = state.update(ChatAction::StreamFinished);
+
+        assert_eq!(state.messages[1].model.as_deref(), Some("claude-haiku-4-5"));
+        assert_eq!(state.stored_model().as_deref(), Some("gpt-4o"));
+
+        // The following turn goes back to the conversation's model.
+        state.input_value = "Again".to_string();
+        let _ = state.update(ChatAction::SendMessage);
+        assert_eq!(
+            state.answering_model().map(|m| m.name.as_str()),
+            Some("gpt-4o")
+        );
+
+        let _ = state.update(ChatAction::NextTurnModelSelected("gpt-4o".to_string()));
+        let _ = state.update(ChatAction::ClearNextTurnModel);
+        assert!(state.next_turn_model.is_none());
+    }
+
+    #[test]
+    fn test_comparison_waits_for_every_model() {
+        let model = |name: &str| ModelInfo {
+            name: name.to_string(),
+            id: name.to_string(),
+            client: Clients::OpenAI,
+            capabilities: ModelCapabilities::default(),
+        };
+        let mut state = State {
+            input_value: "Hello".to_string(),
+            selected_model: Some(model("gpt-4o")),
+            available_models: vec![model("gpt-4o"), model("gpt-4o-mini")],
+            ..State::default()
+        };
+        let _ = state.update(ChatAction::CompareModelAdded("gpt-4o-mini".to_string()));
+        let _ = state.update(ChatAction::CompareModelAdded("gpt-4o-mini".to_string()));
+        assert_eq!(state.compare_models, vec!["gpt-4o-mini".to_string()]);
+
+        let _ = state.update(ChatAction::SendMessage);
+        assert_eq!(state.comparison.as_ref().map(|c| c.remaining), Some(2));
+        assert!(state.can_stop());
+        let id = state.comparison.as_ref().unwrap().id;
+
+        let reply = |name: &str| {
+            ChatMessage::from(Message::assistant("Hi!")).with_model(Some(name.to_string()))
+        };
+        let _ = state.update(ChatAction::ComparisonReplied(
+            id,
+            Ok(Some(reply("gpt-4o-mini"))),
+        ));
+        assert!(state.awaiting_response);
+        // Stopped before anything arrived.
+        let _ = state.update(ChatAction::StopGeneration);
+        let _ = state.update(ChatAction::ComparisonReplied(id, Ok(None)));
+        assert!(!state.awaiting_response);
+        assert!(state.comparison.is_none());
+        assert_eq!(state.messages.len(), 2);
+        assert_eq!(state.messages[1].model.as_deref(), Some("gpt-4o-mini"));
+
+        // Replies arriving after the turn ended are ignored.
+        let _ = state.update(ChatAction::ComparisonReplied(id, Ok(Some(reply("gpt-4o")))));
+        assert_eq!(state.messages.len(), 2);
+
+        // The answering model's reply goes first and is the only one sent
+        // on; failures go to the error banner.
+        state.input_value = "Again".to_string();
+        let _ = state.update(ChatAction::SendMessage);
+        let next = state.comparison.as_ref().unwrap().id;
+        let _ = state.update(ChatAction::ComparisonReplied(id, Ok(None)));
+        assert_eq!(state.comparison.as_ref().map(|c| c.remaining), Some(2));
+        let _ = state.update(ChatAction::ComparisonReplied(
+            next,
+            Ok(Some(reply("gpt-4o-mini"))),
+        ));
+        let _ = state.update(ChatAction::ComparisonReplied(
+            next,
+            Ok(Some(reply("gpt-4o"))),
+        ));
+        assert_eq!(state.messages.len(), 5);
+        assert_eq!(state.messages[3].model.as_deref(), Some("gpt-4o"));
+        assert_eq!(state.messages[4].model.as_deref(), Some("gpt-4o-mini"));
+        let sent = state.request_messages();
+        assert_eq!(sent.len(), 4);
+        assert_eq!(sent[3].model.as_deref(), Some("gpt-4o"));
+
+        state.input_value = "Once more".to_string();
+        let _ = state.update(ChatAction::SendMessage);
+        let last = state.comparison.as_ref().unwrap().id;
+        let _ = state.update(ChatAction::ComparisonReplied(
+            last,
+            Err("gpt-4o-mini: rate limited".to_string()),
+        ));
+        let _ = state.update(ChatAction::ComparisonReplied(
+            last,
+            Ok(Some(reply("gpt-4o"))),
+        ));
+        assert_eq!(state.messages.len(), 7);
+        assert_eq!(
+            state.error.as_ref().map(|e| e.text.as_str()),
+            Some("gpt-4o-mini: rate limited")
+        );
+
+        let _ = state.update(ChatAction::CompareModelRemoved("gpt-4o-mini".to_string()));
+        assert!(state.compare_models.is_empty());
+    }
+
+    #[test]
+    fn test_mcp_status_row_follows_the_tool_manager() {
+        let mut state = State::default();
+        assert!(state.build_mcp_status_row().is_none());
+
+        let statuses = BTreeMap::from([
+            ("github".to_string(), ServerStatus::Connected),
+            ("files".to_string(), ServerStatus::Failed("not found".to_string())),
+        ]);
+        let _ = state.update(ChatAction::McpStatusChanged(statuses.clone()));
+        assert_eq!(state.mcp_status, statuses);
+        assert!(state.build_mcp_status_row().is_some());
+        assert_eq!(state.new_tab().mcp_status, statuses);
+
+        // Agents bring their own tools.
+        state.chat_target = ChatTarget::Agent("claude".to_string());
+        assert!(state.build_mcp_status_row().is_none());
+    }
+
+    #[test]
+    fn test_new_tab_keeps_models_but_not_transcript() {
+        let model = ModelInfo {
+            name: "gpt-4o-mini".to_string(),
+            id: "gpt-4o-mini".to_string(),
+            client: Clients::OpenAI,
+            capabilities: ModelCapabilities::default(),
+        };
+        let state = State {
+            messages: vec![ChatMessage::from_role_and_text(
+                "user",
+                "How do I write a tokio runtime by hand?\nDetails follow.",
+            )],
+            conversation_id: "abc".to_string(),
+            selected_model: Some(model.clone()),
+            available_models: vec![model.clone()],
+            ..State::default()
+        };
+        assert_eq!(state.title(), "How do I write a tokio…");
+
+        let tab = state.new_tab();
+        assert_eq!(tab.title(), "New chat");
+        assert!(tab.conversation_id().is_empty());
+        assert_eq!(tab.selected_model, Some(model));
+        assert_eq!(tab.available_models.len(), 1);
+    }
+
+    #[test]
+    fn test_open_and_new_conversation() {
+        let mut state = State {
+            messages: vec![ChatMessage::from_role_and_text("user", "Current chat")],
+            conversation_id: "current".to_string(),
+            input_value: "draft".to_string(),
+            ..State::default()
+        };
+        let stored = StoredConversation {
+            id: "older".to_string(),
+            title: "Greetings".to_string(),
+            model: None,
+            system_prompt: "Answer in French.".to_string(),
+            sampling: SamplingParams {
+                temperature: Some(0.2),
+                ..SamplingParams::default()
+            },
+            messages: vec![
+                Message::user("Hi", None).into(),
+                Message::assistant("Hello!").into(),
+            ],
+            revision: 1,
+            summary: Some(("They greeted each other.".to_string(), 1)),
+        };
+        let _ = state.update(ChatAction::ConversationOpened(Some(stored), Some(1)));
+        assert_eq!(state.conversation_id(), "older");
+        assert_eq!(state.title(), "Greetings");
+        assert_eq!(state.messages.len(), 2);
+        assert_eq!(state.unsummarized_messages().len(), 1);
+        assert_eq!(state.highlighted_message, Some(1));
+        assert!(state.input_value.is_empty());
+        assert_eq!(state.system_prompt.text(), "Answer in French.");
+        assert_eq!(state.sampling.temperature, "0.2");
+
+        // Positions past the end of the transcript are ignored.
+        let _ = state.update(ChatAction::ShowMessage(5));
+        assert_eq!(state.highlighted_message, Some(1));
+
+        let _ = state.update(ChatAction::NewConversation);
+        assert!(state.conversation_id().is_empty());
+        assert!(state.messages.is_empty());
+        assert!(state.highlighted_message.is_none());
+        assert!(state.system_prompt.text().is_empty());
+        assert_eq!(state.sampling.params(), SamplingParams::default());
+    }
+
+    #[test]
+    fn test_transcript_follows_new_output_only_at_its_end() {
+        let mut state = State {
+            messages: vec![
+                ChatMessage::from_role_and_text("user", "Hi"),
+                ChatMessage::from_role_and_text("assistant", "Hello"),
+            ],
+            ..State::default()
+        };
+        let scrolled = |remaining| {
+            ChatAction::TranscriptScrolled(TranscriptViewport {
+                remaining,
+                ..TranscriptViewport::default()
+            })
+        };
+        let extent = state.transcript_extent();
+        state.messages[1] = ChatMessage::from_role_and_text("assistant", "Hello there");
+        assert_ne!(state.transcript_extent(), extent);
+
+        let _ = state.update(scrolled(300.0));
+        assert!(state.scrolled_back);
+        let _ = state.update(scrolled(5.0));
+        assert!(!state.scrolled_back);
+
+        // Showing a message keeps it in view until the user jumps back.
+        let _ = state.update(ChatAction::ShowMessage(0));
+        assert!(state.scrolled_back);
+        let _ = state.update(ChatAction::JumpToLatest);
+        assert!(!state.scrolled_back);
+
+        let _ = state.update(scrolled(300.0));
+        let _ = state.update(ChatAction::NewConversation);
+        assert!(!state.scrolled_back);
+    }
+
+    #[test]
+    fn test_conversations_stay_while_a_turn_is_running() {
+        let mut state = State {
+            messages: vec![ChatMessage::from_role_and_text("user", "Current chat")],
+            conversation_id: "current".to_string(),
+            awaiting_response: true,
+            pending_response: Some(PendingResponse::default()),
+            ..State::default()
+        };
+        let _ = state.update(ChatAction::NewConversation);
+        assert_eq!(state.conversation_id(), "current");
+        assert_eq!(state.messages.len(), 1);
+        assert!(state.error.take().is_some());
+
+        let _ = state.update(ChatAction::OpenConversation("older".to_string(), None));
+        assert!(state.error.take().is_some());
+        let stored = StoredConversation {
+            id: "older".to_string(),
+            title: "Greetings".to_string(),
+            model: None,
+            system_prompt: String::new(),
+            sampling: SamplingParams::default(),
+            messages: vec![Message::user("Hi", None).into()],
+            revision: 1,
+            summary: None,
+        };
+        let _ = state.update(ChatAction::ConversationOpened(Some(stored), None));
+        assert_eq!(state.conversation_id(), "current");
+        assert!(state.pending_response.is_some());
+    }
+
+    #[test]
+    fn test_long_transcripts_render_only_near_the_view() {
+        let mut state = State {
+            messages: (0..200)
+                .map(|i| ChatMessage::from_role_and_text("user", format!("Message {i}")))
+                .collect(),
+            ..State::default()
+        };
+        let height = state.estimated_height(0, 800.0);
+        let total = 200.0 * height;
+
+        // Following the end, the last messages are rendered.
+        let (rendered, above, below) = state.rendered_messages();
+        assert_eq!(rendered.end, 200);
+        assert!(rendered.start > 0);
+        assert_eq!(above, rendered.start as f32 * height);
+        assert_eq!(below, 0.0);
+
+        // Scrolled back, the messages around the view are.
+        let _ = state.update(ChatAction::TranscriptScrolled(TranscriptViewport {
+            offset: 100.0 * height,
+            remaining: total - 100.0 * height - 600.0,
+            width: 800.0,
+            height: 600.0,
+        }));
+        let (rendered, above, below) = state.rendered_messages();
+        assert!(rendered.contains(&100) && rendered.start > 0 && rendered.end < 200);
+        assert_eq!(above + below + rendered.len() as f32 * height, total);
+
+        // Short transcripts are rendered whole.
+        state.messages.truncate(VIRTUALIZE_AFTER);
+        assert_eq!(state.rendered_messages(), (0..VIRTUALIZE_AFTER, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_system_prompt_leads_every_request() {
+        let mut state = State {
+            messages: vec![ChatMessage::from_role_and_text("user", "Hi")],
+            ..State::default()
+        };
+        assert_eq!(state.request_messages().len(), 1);
+
+        let _ = state.update(ChatAction::SystemPromptEdited(text_editor::Action::Edit(
+            text_editor::Edit::Paste(std::sync::Arc::new("Be terse.".to_string())),
+        )));
+        let messages = state.request_messages();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].message.role, "system");
+        assert_eq!(messages[0].message.text_content()[0], "Be terse.");
+        // The prompt is not part of the visible transcript.
+        assert_eq!(state.messages.len(), 1);
+    }
+
+    #[test]
+    fn test_sampling_inputs_accept_only_valid_values() {
+        let mut state = State::default();
+        for (field, value) in [
+            (SamplingField::Temperature, "0."),
+            (SamplingField::Temperature, "0.7"),
+            (SamplingField::Temperature, "3"),
+            (SamplingField::TopP, "0.9"),
+            (SamplingField::TopP, "1.5"),
+            (SamplingField::MaxTokens, "abc"),
+            (SamplingField::MaxTokens, "256"),
+            (SamplingField::Stop, "END,\\nUser:,"),
+            (SamplingField::Seed, "-1"),
+            (SamplingField::Seed, "42"),
+            (SamplingField::PresencePenalty, "-"),
+            (SamplingField::PresencePenalty, "-0.5"),
+            (SamplingField::FrequencyPenalty, "2.5"),
+        ] {
+            let _ = state.update(ChatAction::SamplingChanged(field, value.to_string()));
+        }
+        assert_eq!(
+            state.sampling.params(),
+            SamplingParams {
+                temperature: Some(0.7),
+                top_p: Some(0.9),
+                max_tokens: Some(256),
+                reasoning_effort: None,
+                stop: vec!["END".to_string(), "\nUser:".to_string()],
+                seed: Some(42),
+                presence_penalty: Some(-0.5),
+                frequency_penalty: None,
+            }
+        );
+        // Stored sequences are shown as they were typed.
+        let inputs = SamplingInputs::from_params(&state.sampling.params());
+        assert_eq!(inputs.stop, "END,\\nUser:");
+
+        let _ = state.update(ChatAction::SamplingChanged(
+            SamplingField::Temperature,
+            String::new(),
+        ));
+        assert_eq!(state.sampling.params().temperature, None);
+    }
+
+    #[test]
+    fn test_stop_sequences_round_trip() {
+        let stops = vec![
+            ", ".to_string(),
+            "},".to_string(),
+            " User:".to_string(),
+            "a\\b\n".to_string(),
+        ];
+        let typed = SamplingInputs::from_params(&SamplingParams {
+            stop: stops.clone(),
+            ..SamplingParams::default()
+        })
+        .stop;
+        assert_eq!(typed, "\\, ,}\\,, User:,a\\\\b\\n");
+        assert_eq!(unescape_stops(&typed), stops);
+    }
+
+    #[test]
+    fn test_request_sampling_depends_on_reasoning() {
+        let mut state = State::default();
+        let _ = state.update(ChatAction::SamplingChanged(
+            SamplingField::Temperature,
+            "0.7".to_string(),
+        ));
+        let _ = state.update(ChatAction::ReasoningEffortSelected(ReasoningEffort::Low));
+        let mut model = ModelInfo {
+            name: "gpt-4o".to_string(),
+            id: "gpt-4o".to_string(),
+            client: Clients::OpenAI,
+            capabilities: ModelCapabilities::default(),
+        };
+
+        let sampling = state.request_sampling(&model);
+        assert_eq!(sampling.temperature, Some(0.7));
+        assert_eq!(sampling.reasoning_effort, None);
+
+        model.capabilities.reasoning = true;
+        let sampling = state.request_sampling(&model);
+        assert_eq!(sampling.temperature, None);
+        assert_eq!(sampling.reasoning_effort, Some(ReasoningEffort::Low));
+        // Both are kept for the conversation whichever model is picked.
+        assert_eq!(state.sampling.params().temperature, Some(0.7));
+    }
+
+    #[test]
+    fn test_edit_and_resend_truncates_later_messages() {
+        let mut state = State {
+            messages: vec![
+                ChatMessage::from(Message::user("Capital of France?", None)),
+                ChatMessage::from(Message::assistant("Paris.")),
+                ChatMessage::from(Message::user("And Spain?", None)),
+                ChatMessage::from(Message::assistant("Madrid.")),
+            ],
+            selected_model: Some(ModelInfo {
+                name: "gpt-4o-mini".to_string(),
+                id: "gpt-4o-mini".to_string(),
+                client: Clients::OpenAI,
+                capabilities: ModelCapabilities::default(),
+            }),
+            ..State::default()
+        };
+
+        // Only user messages can be edited.
+        let _ = state.update(ChatAction::EditMessage(1));
+        assert!(state.editing.is_none());
+
+        let _ = state.update(ChatAction::EditMessage(0));
+        let (_, draft) = state.editing.as_ref().unwrap();
+        assert_eq!(draft.text(), "Capital of France?");
+        let _ = state.update(ChatAction::EditedMessageChanged(
+            text_editor::Action::SelectAll,
+        ));
+        let _ = state.update(ChatAction::EditedMessageChanged(text_editor::Action::Edit(
+            text_editor::Edit::Paste(std::sync::Arc::new("Capital of Italy?".to_string())),
+        )));
+        let _ = state.update(ChatAction::ResendEditedMessage);
+
+        assert!(state.editing.is_none());
+        assert!(state.awaiting_response);
+        assert!(state.pending_response.is_some());
+        assert_eq!(state.messages.len(), 1);
+        assert_eq!(
+            state.messages[0].message.text_content()[0],
+            "Capital of Italy?"
+        );
+
+        // No further edits while the reply is streaming.
+        let _ = state.update(ChatAction::EditMessage(0));
+        assert!(state.editing.is_none());
+    }
+
+    #[test]
+    fn test_branch_keeps_tool_results_of_the_last_message() {
+        let mut call = Message::assistant("");
+        call.tool_calls = Some(vec![ToolCall {
+            id: "call_1".to_string(),
+            _type: "function".to_string(),
+            function: ToolFunction {
+                name: "weather".to_string(),
+                arguments: "{}".to_string(),
+            },
+        }]);
+        let state = State {
+            messages: vec![
+                ChatMessage::from(Message::user("Weather?", None)),
+                ChatMessage::from(call),
+                ChatMessage::from(Message::tool_result("call_1", "Sunny", None)),
+                ChatMessage::from(Message::assistant("It is sunny.")),
+            ],
+            ..State::default()
+        };
+
+        assert_eq!(state.branch_messages(0).len(), 1);
+        let branch = state.branch_messages(1);
+        assert_eq!(branch.len(), 3);
+        assert_eq!(branch[2].message.role, "tool");
+        assert_eq!(state.branch_messages(3).len(), 4);
+    }
+
+    #[test]
+    fn test_new_conversations_start_with_default_system_prompt() {
+        let mut state = State {
+            default_system_prompt: "Be terse.".to_string(),
+            messages: vec![ChatMessage::from_role_and_text("user", "Hi")],
+            ..State::default()
+        };
+        assert!(state.system_prompt.text().is_empty());
+
+        let _ = state.update(ChatAction::NewConversation);
+        assert_eq!(state.system_prompt.text(), "Be terse.");
+        assert_eq!(state.new_tab().system_prompt.text(), "Be terse.");
+    }
+
+    #[test]
+    fn test_streamed_tool_call_fragments_are_assembled() {
+        let mut state = State {
+            awaiting_response: true,
+            pending_response: Some(PendingResponse::default()),
+            max_tool_iterations: DEFAULT_MAX_TOOL_ITERATIONS,
+            ..State::default()
+        };
+
+        let _ = state.update(ChatAction::StreamDelta(Ok(CompletionDelta::ToolCall {
+            index: 0,
+            id: Some("call_1".to_string()),
+            name: Some("__srv__search".to_string()),
+            arguments: "{\"q\":".to_string(),
+        })));
+        let _ = state.update(ChatAction::StreamDelta(Ok(CompletionDelta::ToolCall {
+            index: 0,
+            id: None,
+            name: None,
+            arguments: "\"rust\"}".to_string(),
+        })));
+        let _ = state.update(ChatAction::StreamFinished);
+
+        assert_eq!(state.messages.len(), 1);
+        let calls = state.messages[0].message.tool_calls.clone().unwrap();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].id, "call_1");
+        assert_eq!(calls[0].function.name, "__srv__search");
+        assert_eq!(calls[0].function.arguments, "{\"q\":\"rust\"}");
+        assert!(state.pending_tool_calls.contains("call_1"));
+        assert!(state.awaiting_response);
+    }
+
+    #[test]
+    fn test_model_selection() {
+        let mut state = State {
+            available_models: vec![
+                ModelInfo {
+                    name: "gpt-4o-mini".to_string(),
+                    id: "gpt-4o-mini".to_string(),
+                    client: Clients::OpenAI,
+                    capabilities: ModelCapabilities::default(),
+                },
+                ModelInfo {
+                    name: "gpt-3.5-turbo".to_string(),
+                    id: "gpt-3.5-turbo".to_string(),
+                    client: Clients::OpenAI,
+                    capabilities: ModelCapabilities::default(),
+                },
+            ],
+            ..State::default()
+        };
+        let model_name = "gpt-4o-mini".to_string();
+
+        let action = ChatAction::ModelSelected(model_name.clone());
+        let _ = state.update(action);
 
         assert_eq!(state.selected_model, Some(ModelInfo {
             name: model_name.clone(),
             id: model_name,
             client: Clients::OpenAI,
+            capabilities: ModelCapabilities::default(),
         }));
     }
 
+    #[test]
+    fn test_template_with_variables_prompts_then_populates() {
+        let mut state = State {
+            available_templates: vec![ConversationTemplate {
+                name: "translate".to_string(),
+                messages: vec![
+                    crate::config::TemplateMessage {
+                        role: "system".to_string(),
+                        content: "Translate into {{target}}.".to_string(),
+                    },
+                    crate::config::TemplateMessage {
+                        role: "user".to_string(),
+                        content: "{{text}}".to_string(),
+                    },
+                ],
+            }],
+            ..State::default()
+        };
+
+        let _ = state.update(ChatAction::TemplateSelected("translate".to_string()));
+        assert!(state.messages.is_empty());
+        assert!(state.pending_template.is_some());
+
+        let _ = state.update(ChatAction::TemplateVariableChanged(
+            "target".to_string(),
+            "French".to_string(),
+        ));
+        let _ = state.update(ChatAction::TemplateVariableChanged(
+            "text".to_string(),
+            "Good morning".to_string(),
+        ));
+        let _ = state.update(ChatAction::StartTemplate);
+
+        assert!(state.pending_template.is_none());
+        assert_eq!(state.messages.len(), 2);
+        assert_eq!(state.messages[0].message.role, "system");
+        assert_eq!(
+            state.messages[0].message.text_content().first(),
+            Some(&&"Translate into French.".to_string())
+        );
+        assert_eq!(state.messages[1].message.role, "user");
+        assert_eq!(
+            state.messages[1].message.text_content().first(),
+            Some(&&"Good morning".to_string())
+        );
+    }
+
+    #[test]
+    fn test_template_without_variables_starts_immediately() {
+        let mut state = State {
+            messages: vec![ChatMessage::from_role_and_text("user", "old")],
+            available_templates: vec![ConversationTemplate {
+                name: "plain".to_string(),
+                messages: vec![crate::config::TemplateMessage {
+                    role: "system".to_string(),
+                    content: "Be brief.".to_string(),
+                }],
+            }],
+            ..State::default()
+        };
+
+        let _ = state.update(ChatAction::TemplateSelected("plain".to_string()));
+
+        assert!(state.pending_template.is_none());
+        assert_eq!(state.messages.len(), 1);
+        assert_eq!(state.messages[0].message.role, "system");
+    }
+
+    #[test]
+    fn test_models_changed_keeps_selection_and_awaiting_flag() {
+        let gpt = ModelInfo {
+            name: "gpt-4o-mini".to_string(),
+            id: "gpt-4o-mini".to_string(),
+            client: Clients::OpenAI,
+            capabilities: ModelCapabilities::default(),
+        };
+        let claude = ModelInfo {
+            name: "claude".to_string(),
+            id: "claude".to_string(),
+            client: Clients::Anthropic,
+            capabilities: ModelCapabilities::default(),
+        };
+        let mut state = State {
+            selected_model: Some(claude.clone()),
+            available_models: vec![claude.clone()],
+            awaiting_response: true,
+            ..State::default()
+        };
+
+        let _ = state.update(ChatAction::ModelsChanged(vec![gpt.clone(), claude.clone()]));
+        assert_eq!(state.available_models.len(), 2);
+        assert_eq!(state.selected_model, Some(claude));
+        assert!(state.awaiting_response);
+
+        let _ = state.update(ChatAction::ModelsChanged(vec![gpt.clone()]));
+        assert_eq!(state.selected_model, Some(gpt));
+
+        let _ = state.update(ChatAction::ModelsChanged(vec![]));
+        assert_eq!(state.selected_model, None);
+    }
+
     #[test]
     fn test_file_selection() {
         let mut state = State::default();
@@ -1348,4 +6620,552 @@ mod tests {
         // Not reading actual files. The file reader defaults to None if it can't read the file.
         assert_eq!(state.files, Some(vec![]));
     }
+
+    #[test]
+    fn test_attachments_go_with_one_message() {
+        let image = |name: &str| FileData {
+            filename: Some(name.to_string()),
+            file_data: Some("data:image/png;base64,iVBORw0KGgo=".to_string()),
+            file_id: None,
+        };
+        let mut state = State {
+            input_value: "What are these?".to_string(),
+            files: Some(vec![image("a.png"), image("b.png"), image("c.png")]),
+            ..State::default()
+        };
+        let _ = state.update(ChatAction::RemoveAttachment(1));
+        let _ = state.update(ChatAction::RemoveAttachment(5));
+        assert_eq!(state.files.as_ref().map(Vec::len), Some(2));
+
+        let _ = state.update(ChatAction::SendMessage);
+        assert!(state.files.is_none());
+        assert_eq!(state.messages[0].images.len(), 2);
+        assert!(matches!(
+            state.messages[0].message.content[1],
+            Content::ImageUrl { .. }
+        ));
+    }
+
+    #[test]
+    fn test_screenshot_is_previewed_then_attached() {
+        let capture = |name: &str| {
+            let path = std::env::temp_dir().join(name);
+            ::image::RgbImage::new(4, 4).save(&path).unwrap();
+            path
+        };
+        let mut state = State::default();
+        let _ = state.update(ChatAction::ScreenshotCaptured(Ok(None)));
+        assert!(state.screenshot.is_none());
+
+        let discarded = capture("ergon_test_screenshot_discarded.png");
+        let _ = state.update(ChatAction::ScreenshotCaptured(Ok(Some(discarded.clone()))));
+        let _ = state.update(ChatAction::DiscardScreenshot);
+        assert!(state.screenshot.is_none());
+        assert!(!discarded.exists());
+
+        let attached = capture("ergon_test_screenshot_attached.png");
+        let _ = state.update(ChatAction::ScreenshotCaptured(Ok(Some(attached.clone()))));
+        assert!(state.files.is_none());
+        let _ = state.update(ChatAction::AttachScreenshot);
+        assert!(state.screenshot.is_none());
+        assert!(!attached.exists());
+        let files = state.files.as_ref().unwrap();
+        assert!(files[0]
+            .file_data
+            .as_ref()
+            .is_some_and(|data| data.starts_with("data:image/png;base64,")));
+    }
+
+    #[test]
+    fn test_pdf_pages_are_sent_as_text() {
+        let mut state = State {
+            input_value: "Summarize".to_string(),
+            ..State::default()
+        };
+        let pages = (1..=30).map(|page| format!("Text {page}")).collect();
+        let _ = state.update(ChatAction::PdfRead(Ok(("report.pdf".to_string(), pages))));
+        assert_eq!(state.pdfs[0].range, "1-20");
+
+        let _ = state.update(ChatAction::PdfPagesChanged(0, "2, 30".to_string()));
+        let _ = state.update(ChatAction::PdfPagesChanged(0, "all".to_string()));
+        assert_eq!(state.pdfs[0].range, "2, 30");
+
+        let _ = state.update(ChatAction::SendMessage);
+        assert!(state.pdfs.is_empty());
+        let content = &state.messages[0].message.content;
+        assert_eq!(content[1].attached_file_name(), Some("report.pdf"));
+        assert_eq!(
+            content[1].as_text().unwrap(),
+            "<file name=\"report.pdf\">\n--- Page 2 ---\nText 2\n\n--- Page 30 ---\nText 30\n</file>"
+        );
+    }
+
+    #[test]
+    fn test_mentioned_resources_are_suggested_and_sent_as_text() {
+        let resource = |name: &str, uri: &str| McpResource {
+            server: "docs".to_string(),
+            uri: uri.to_string(),
+            name: name.to_string(),
+            description: None,
+        };
+        let readme = resource("README", "file:///repo/README.md");
+        let mut state = State {
+            mcp_resources: vec![readme.clone(), resource("schema", "db://main/schema")],
+            ..State::default()
+        };
+        let _ = state.update(ChatAction::InputChanged("Summarize @read".to_string()));
+        assert_eq!(state.resource_suggestions(), vec![&readme]);
+        let _ = state.update(ChatAction::InputChanged("Summarize @read ".to_string()));
+        assert!(state.resource_suggestions().is_empty());
+
+        let _ = state.update(ChatAction::InputChanged("Summarize @read".to_string()));
+        let _ = state.update(ChatAction::ResourceMentioned(readme.clone()));
+        assert_eq!(state.input_value, "Summarize @README ");
+        let _ = state.update(ChatAction::ResourceRead(Ok((
+            readme,
+            "# Ergon".to_string(),
+        ))));
+
+        let _ = state.update(ChatAction::SendMessage);
+        assert!(state.resources.is_empty());
+        let content = &state.messages[0].message.content;
+        assert_eq!(content[1].attached_file_name(), Some("README"));
+        assert_eq!(
+            content[1].as_text().unwrap(),
+            "<file name=\"README\">\n# Ergon\n</file>"
+        );
+    }
+
+    #[test]
+    fn test_prompt_asks_for_required_arguments_then_joins_the_transcript() {
+        let review = McpPrompt {
+            server: "git".to_string(),
+            name: "review".to_string(),
+            description: Some("Review a branch".to_string()),
+            arguments: vec![
+                crate::mcp::McpPromptArgument {
+                    name: "branch".to_string(),
+                    description: None,
+                    required: true,
+                },
+                crate::mcp::McpPromptArgument {
+                    name: "focus".to_string(),
+                    description: None,
+                    required: false,
+                },
+            ],
+        };
+        let mut state = State {
+            mcp_prompts: vec![review.clone()],
+            ..State::default()
+        };
+        let _ = state.update(ChatAction::InputChanged("/rev".to_string()));
+        assert_eq!(state.prompt_suggestions(), vec![&review]);
+        let _ = state.update(ChatAction::InputChanged("/rev main".to_string()));
+        assert!(state.prompt_suggestions().is_empty());
+
+        let _ = state.update(ChatAction::PromptSelected(review));
+        assert!(state.input_value.is_empty());
+        // The branch is required.
+        let _ = state.update(ChatAction::RunPrompt);
+        assert!(state.pending_prompt.is_some());
+        let _ = state.update(ChatAction::PromptArgumentChanged(
+            "branch".to_string(),
+            "main".to_string(),
+        ));
+        let _ = state.update(ChatAction::RunPrompt);
+        assert!(state.pending_prompt.is_none());
+        assert!(state.awaiting_response);
+
+        let _ = state.update(ChatAction::PromptFetched(Ok(vec![Message::user(
+            "Review the changes on main.",
+            None,
+        )])));
+        assert_eq!(state.messages.len(), 1);
+        assert_eq!(state.messages[0].message.role, "user");
+    }
+
+    #[test]
+    fn test_sampling_request_needs_a_selected_model_to_be_approved() {
+        let model = ModelInfo {
+            name: "gpt-4o-mini".to_string(),
+            id: "gpt-4o-mini".to_string(),
+            client: Clients::OpenAI,
+            capabilities: ModelCapabilities::default(),
+        };
+        let (request, mut answer) = SamplingRequest::new(
+            "notes".to_string(),
+            None,
+            vec![Message::user("Summarize my notes.", None)],
+            200,
+        );
+        let mut state = State::default();
+        let _ = state.update(ChatAction::SamplingRequested(request));
+
+        let _ = state.update(ChatAction::ApproveSampling);
+        assert_eq!(state.pending_sampling.len(), 1);
+        assert!(answer.try_recv().is_err());
+
+        state.selected_model = Some(model.clone());
+        let _ = state.update(ChatAction::ApproveSampling);
+        assert!(state.pending_sampling.is_empty());
+        assert_eq!(answer.try_recv().unwrap(), Some(model));
+    }
+
+    #[test]
+    fn test_sampling_request_is_denied_over_the_budget() {
+        let (request, mut answer) = SamplingRequest::new("notes".to_string(), None, vec![], 200);
+        let mut state = State {
+            selected_model: Some(ModelInfo {
+                name: "gpt-4o-mini".to_string(),
+                id: "gpt-4o-mini".to_string(),
+                client: Clients::OpenAI,
+                capabilities: ModelCapabilities::default(),
+            }),
+            budget: Budget {
+                monthly_limit: 5.0,
+                block_when_exceeded: true,
+            },
+            month_spend: 6.0,
+            ..State::default()
+        };
+        let _ = state.update(ChatAction::SamplingRequested(request));
+        let _ = state.update(ChatAction::ApproveSampling);
+        assert!(state.pending_sampling.is_empty());
+        assert_eq!(answer.try_recv().unwrap(), None);
+        assert!(state.error.unwrap().text.contains("budget"));
+    }
+
+    #[test]
+    fn test_elicitation_form_stays_open_until_it_is_valid() {
+        let field = crate::mcp::handler::ElicitationField {
+            name: "seats".to_string(),
+            label: "Seats".to_string(),
+            description: None,
+            required: true,
+            kind: FieldKind::Integer,
+            default: String::new(),
+        };
+        let (request, mut answer) =
+            ElicitationRequest::new("billing".to_string(), "Upgrade".to_string(), vec![field]);
+        let mut state = State::default();
+        let _ = state.update(ChatAction::ElicitationRequested(request));
+
+        let _ = state.update(ChatAction::SubmitElicitation);
+        assert_eq!(
+            state.pending_elicitation[0].error.as_deref(),
+            Some("Seats is required")
+        );
+
+        let _ = state.update(ChatAction::ElicitationFieldChanged(0, "3".to_string()));
+        let _ = state.update(ChatAction::SubmitElicitation);
+        assert!(state.pending_elicitation.is_empty());
+        assert!(matches!(
+            answer.try_recv(),
+            Ok(crate::mcp::handler::ElicitationAnswer::Accept(_))
+        ));
+    }
+
+    #[test]
+    fn test_tool_progress_is_kept_while_the_call_runs() {
+        let progress = |call_id: &str| ToolProgress {
+            call_id: call_id.to_string(),
+            progress: 2.0,
+            total: Some(5.0),
+            message: None,
+        };
+        let mut state = State {
+            pending_tool_calls: HashSet::from(["call_1".to_string(), "call_2".to_string()]),
+            ..State::default()
+        };
+        let _ = state.update(ChatAction::ToolProgressed(progress("call_1")));
+        let _ = state.update(ChatAction::ToolProgressed(progress("elsewhere")));
+        assert_eq!(state.tool_progress.len(), 1);
+        assert_eq!(state.tool_progress["call_1"].status(), "2 of 5");
+
+        let _ = state.update(ChatAction::ToolResponseReceived(Ok(ToolCallResult {
+            success: true,
+            id: "call_1".to_string(),
+            contents: vec![crate::models::Content::tool_result("call_1", "done")],
+        })));
+        assert!(state.tool_progress.is_empty());
+    }
+
+    #[test]
+    fn test_text_attachments_are_kept_out_of_the_transcript_text() {
+        let notes = FileData {
+            filename: Some("notes.md".to_string()),
+            file_data: Some("data:text/markdown;base64,IyBOb3Rlcw==".to_string()),
+            file_id: None,
+        };
+        let mut state = State {
+            messages: vec![ChatMessage::from(Message::user(
+                "Summarize",
+                Some(vec![notes]),
+            ))],
+            ..State::default()
+        };
+        assert_eq!(state.messages[0].markdown_items.len(), 1);
+
+        let _ = state.update(ChatAction::EditMessage(0));
+        let (_, draft) = state.editing.as_ref().unwrap();
+        assert_eq!(draft.text(), "Summarize");
+        let _ = state.update(ChatAction::ResendEditedMessage);
+        let content = &state.messages[0].message.content;
+        assert_eq!(content.len(), 2);
+        assert_eq!(content[1].attached_file_name(), Some("notes.md"));
+
+        assert_eq!(
+            attachment_mime_type("application/rls-services+xml", b"fn main() {}"),
+            "text/plain"
+        );
+        assert_eq!(
+            attachment_mime_type("application/pdf", &[0xff]),
+            "application/pdf"
+        );
+        assert_eq!(attachment_mime_type("audio/wav", b"RIFF"), "audio/wav");
+    }
+
+    #[test]
+    fn test_message_metadata() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00 UTC");
+        assert_eq!(format_timestamp(951_868_799), "2000-02-29 23:59 UTC");
+
+        let mut message = ChatMessage::from(Message::assistant("Hi"));
+        message.created_at = 1_709_294_700;
+        assert_eq!(message_metadata(&message, None), "2024-03-01 12:05 UTC");
+        let mut message = message.with_model(Some("gpt-4o-mini".to_string()));
+        assert_eq!(message_metadata(&message, None), "2024-03-01 12:05 UTC");
+        message.usage = Some(TokenUsage {
+            prompt_tokens: 20,
+            completion_tokens: 5,
+        });
+        assert_eq!(
+            message_metadata(&message, Some(0.0075)),
+            "2024-03-01 12:05 UTC · 20 prompt / 5 completion tokens · ~$0.0075"
+        );
+        message.usage = None;
+        message.timing = Some(ResponseTiming {
+            elapsed_ms: 3_140,
+            generation_ms: 2_500,
+            tokens: 105,
+        });
+        assert_eq!(
+            message_metadata(&message, None),
+            "2024-03-01 12:05 UTC · 42.0 tok/s · 3.1 s"
+        );
+        assert_eq!(
+            timing_details(&message.timing.unwrap()),
+            "First token after 0.64 s\nDone after 3.14 s\n105 tokens at 42.0 tokens/s"
+        );
+    }
+
+    #[test]
+    fn test_streamed_response_is_timed() {
+        let model = ModelInfo {
+            name: "gpt-4o-mini".to_string(),
+            id: "gpt-4o-mini".to_string(),
+            client: Clients::OpenAI,
+            capabilities: ModelCapabilities::default(),
+        };
+        let started_at = Instant::now() - std::time::Duration::from_secs(2);
+        let mut state = State {
+            selected_model: Some(model),
+            awaiting_response: true,
+            pending_response: Some(PendingResponse {
+                started_at: Some(started_at),
+                ..PendingResponse::default()
+            }),
+            ..State::default()
+        };
+        let _ = state.update(ChatAction::StreamDelta(Ok(CompletionDelta::Text(
+            "Hello world".to_string(),
+        ))));
+        let pending = state.pending_response.as_ref().unwrap();
+        assert!(pending.first_token_at.is_some());
+        assert_eq!(pending.streamed_tokens, 2);
+
+        let _ = state.update(ChatAction::StreamFinished);
+        let timing = state.messages[0].timing.unwrap();
+        assert!(timing.elapsed_ms >= 2_000);
+        assert!(timing.generation_ms < timing.elapsed_ms);
+        assert_eq!(timing.tokens, 2);
+    }
+
+    #[test]
+    fn test_conversation_cost_sums_priced_messages() {
+        let usage = Some(TokenUsage {
+            prompt_tokens: 1_000,
+            completion_tokens: 500,
+        });
+        let reply = |model: &str| ChatMessage {
+            usage,
+            ..ChatMessage::from(Message::assistant("Hi")).with_model(Some(model.to_string()))
+        };
+        let mut state = State {
+            messages: vec![reply("gpt-4o"), reply("unpriced")],
+            pricing: HashMap::from([(
+                "gpt-4o".to_string(),
+                ModelPricing {
+                    prompt: 2.5,
+                    completion: 10.0,
+                },
+            )]),
+            ..State::default()
+        };
+        assert_eq!(state.message_cost(&state.messages[1]), None);
+        assert!((state.conversation_cost().unwrap() - 0.0075).abs() < 1e-12);
+
+        state.messages.push(reply("gpt-4o"));
+        assert!((state.conversation_cost().unwrap() - 0.015).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_budget_blocks_requests_when_exceeded() {
+        let model = ModelInfo {
+            name: "gpt-4o-mini".to_string(),
+            id: "gpt-4o-mini".to_string(),
+            client: Clients::OpenAI,
+            capabilities: ModelCapabilities::default(),
+        };
+        let mut state = State {
+            input_value: "Hello".to_string(),
+            selected_model: Some(model.clone()),
+            available_models: vec![model],
+            budget: Budget {
+                monthly_limit: 10.0,
+                block_when_exceeded: true,
+            },
+            month_spend: 12.0,
+            ..State::default()
+        };
+        let _ = state.update(ChatAction::SendMessage);
+        assert!(!state.awaiting_response);
+        // Nothing was sent, so nothing joins the transcript.
+        assert_eq!(state.messages.len(), 1);
+        assert!(state
+            .error
+            .as_ref()
+            .is_some_and(|e| e.text.contains("Monthly budget reached")));
+
+        state.budget.block_when_exceeded = false;
+        state.input_value = "Again".to_string();
+        let _ = state.update(ChatAction::SendMessage);
+        assert!(state.awaiting_response);
+    }
+
+    #[test]
+    fn test_budget_warning() {
+        let mut state = State {
+            budget: Budget {
+                monthly_limit: 10.0,
+                block_when_exceeded: false,
+            },
+            ..State::default()
+        };
+        let _ = state.update(ChatAction::MonthSpendLoaded(2.0));
+        assert_eq!(state.budget_warning(), None);
+
+        let _ = state.update(ChatAction::MonthSpendLoaded(8.5));
+        assert_eq!(
+            state.budget_warning().as_deref(),
+            Some("~$8.5000 of the $10.00 monthly budget spent (85%).")
+        );
+
+        let _ = state.update(ChatAction::MonthSpendLoaded(10.5));
+        assert_eq!(
+            state.budget_warning().as_deref(),
+            Some("Monthly budget of $10.00 reached (~$10.5000 spent).")
+        );
+        state.budget.block_when_exceeded = true;
+        let warning = state.budget_warning().unwrap();
+        assert!(warning.contains("New requests are blocked"));
+    }
+
+    #[test]
+    fn test_capabilities_gate_attachments_and_tools() {
+        let model = ModelInfo {
+            name: "gpt-3.5-turbo".to_string(),
+            id: "gpt-3.5-turbo".to_string(),
+            client: Clients::OpenAI,
+            capabilities: ModelCapabilities {
+                vision: false,
+                tools: false,
+                context_length: Some(16_385),
+                reasoning: false,
+            },
+        };
+        let mut state = State {
+            selected_model: Some(model.clone()),
+            available_tools: vec![Tool::Function(crate::models::Function {
+                name: "search".to_string(),
+                description: String::new(),
+                parameters: serde_json::json!({"type": "object"}),
+            })],
+            ..State::default()
+        };
+        assert!(state.request_tools(&model).is_empty());
+        assert_eq!(
+            state.capability_note().as_deref(),
+            Some("The selected model can't read attachments or use tools.")
+        );
+
+        state.available_tools.clear();
+        assert_eq!(
+            state.capability_note().as_deref(),
+            Some("The selected model can't read attachments.")
+        );
+
+        state.chat_target = ChatTarget::Agent("agent".to_string());
+        assert_eq!(state.capability_note(), None);
+    }
+
+    #[test]
+    fn test_prompt_tokens_are_counted_against_the_context_window() {
+        let model = ModelInfo {
+            name: "gpt-4".to_string(),
+            id: "gpt-4".to_string(),
+            client: Clients::OpenAI,
+            capabilities: ModelCapabilities {
+                context_length: Some(50),
+                ..ModelCapabilities::default()
+            },
+        };
+        let mut state = State {
+            selected_model: Some(model),
+            ..State::default()
+        };
+        let _ = state.update(ChatAction::InputChanged(String::new()));
+        assert_eq!(state.prompt_token_note(), None);
+
+        let _ = state.update(ChatAction::InputChanged("Hello world".to_string()));
+        let draft = state.prompt_tokens.unwrap();
+        assert_eq!(
+            state.prompt_token_note(),
+            Some((format!("~{} prompt tokens", draft), false))
+        );
+
+        // The system prompt and transcript count too.
+        state.messages.push(ChatMessage::from_role_and_text(
+            "assistant",
+            "word ".repeat(60),
+        ));
+        let _ = state.update(ChatAction::InputChanged("Hello world!".to_string()));
+        let tokens = state.prompt_tokens.unwrap();
+        assert!(tokens > draft + 60);
+        assert_eq!(
+            state.prompt_token_note(),
+            Some((
+                format!(
+                    "~{} prompt tokens, more than the 50 the selected model takes.",
+                    tokens
+                ),
+                true
+            ))
+        );
+
+        state.chat_target = ChatTarget::Agent("agent".to_string());
+        let _ = state.update(ChatAction::InputChanged(String::new()));
+        assert_eq!(state.prompt_token_note(), None);
+    }
 }
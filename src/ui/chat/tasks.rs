@@ -1,30 +1,54 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use iced::futures::{future, stream, Stream, StreamExt};
 use rmcp::model::JsonObject;
 use serde_json::Value;
+use tokio_util::sync::CancellationToken;
 
 use crate::{
     acp::{get_agent_manager, AuthMethodInfo, PromptOutcome},
-    api::clients::get_model_manager,
+    api::clients::{get_model_manager, history, CompletionError, Provider},
+    config::ModelPricing,
+    mcp::{McpPrompt, McpResource},
     models::{
-        Clients, CompletionRequest, CompletionResponse, Content, ModelInfo, Tool, ToolCall,
-        ToolCallResult,
+        Clients, CompletionDelta, CompletionRequest, Content, Message, ModelCapabilities,
+        ModelInfo, SamplingParams, TokenUsage, Tool, ToolCall, ToolCallResult,
+    },
+    pdf,
+    storage::{
+        get_storage, month_start, new_conversation_id, unix_now, StoredConversation, StoredMessage,
     },
+    trace,
     ui::chat::models::ChatMessage,
+    workspace::{self, Workspace},
 };
 
-pub async fn complete_message(
+/// Stream a completion for `messages`, traced as part of `turn`. Failures
+/// to start the request and errors mid-stream are both surfaced as an `Err`
+/// item, after which the stream ends. Cancelling `cancel` ends the stream
+/// early and drops the underlying request.
+pub fn stream_message(
     messages: Vec<ChatMessage>,
-    client: Clients,
+    client: Arc<dyn Provider>,
     model: String,
     tools: Vec<Tool>,
-) -> CompletionResponse {
-    log::info!(
+    sampling: SamplingParams,
+    turn: u64,
+    cancel: CancellationToken,
+) -> impl Stream<Item = Result<CompletionDelta, CompletionError>> {
+    let span = tracing::info_span!("completion", turn, model = %model);
+    tracing::info!(
+        turn,
         "message roles: {:?}",
         messages
             .iter()
             .map(|m| m.message.role.clone())
             .collect::<Vec<String>>()
     );
-    log::info!(
+    tracing::info!(
+        turn,
         "message contents: {:?}",
         messages
             .iter()
@@ -32,22 +56,660 @@ pub async fn complete_message(
             .collect::<Vec<Vec<Content>>>()
     );
     let request = CompletionRequest {
-        messages: messages.iter().map(|cm| cm.clone().into()).collect(),
+        messages: history::normalize(messages.iter().map(|cm| cm.clone().into()).collect()),
         model,
-        temperature: None,
+        temperature: sampling.temperature,
+        top_p: sampling.top_p,
+        max_tokens: sampling.max_tokens,
         tools: Some(tools),
+        reasoning_effort: sampling.reasoning_effort,
+        stop: sampling.stop,
+        seed: sampling.seed,
+        presence_penalty: sampling.presence_penalty,
+        frequency_penalty: sampling.frequency_penalty,
+    };
+    let deltas = stream::once(async move { client.stream_message(request).await })
+        .flat_map(|result| match result {
+            Ok(deltas) => deltas.map(|delta| delta.map_err(CompletionError::from)).boxed(),
+            Err(err) => stream::once(future::ready(Err(err.into()))).boxed(),
+        });
+    trace::stream_in_turn(turn, span, deltas).take_until(cancel.cancelled_owned())
+}
+
+/// Ask `model` for a reply to `messages` without tools, gathering the
+/// streamed deltas into one message attributed to the model, so several
+/// models' answers to the same prompt can be compared. `None` if `cancel`
+/// stopped the request before anything arrived; failures name the model.
+pub async fn compare_reply(
+    messages: Vec<ChatMessage>,
+    model: ModelInfo,
+    sampling: SamplingParams,
+    turn: u64,
+    cancel: CancellationToken,
+) -> Result<Option<ChatMessage>, String> {
+    let error = |err: String| Err(format!("{}: {err}", model.name));
+    let client = match model.client.provider() {
+        Ok(client) => client,
+        Err(e) => return error(e.to_string()),
     };
-    let result = client.complete_message(request).await;
+    let deltas = stream_message(
+        messages,
+        client,
+        model.id.clone(),
+        vec![],
+        sampling,
+        turn,
+        cancel.clone(),
+    );
+    let mut deltas = std::pin::pin!(deltas);
+    let (mut text, mut reasoning, mut usage) = (String::new(), String::new(), None);
+    while let Some(delta) = deltas.next().await {
+        match delta {
+            Ok(CompletionDelta::Text(chunk)) => text.push_str(&chunk),
+            Ok(CompletionDelta::Reasoning(chunk)) => reasoning.push_str(&chunk),
+            Ok(CompletionDelta::Usage(reported)) => usage = Some(reported),
+            Ok(_) => {}
+            Err(err) => return error(err.text),
+        }
+    }
+    if text.is_empty() && cancel.is_cancelled() {
+        return Ok(None);
+    }
+    if text.is_empty() {
+        return error("No response from model.".to_string());
+    }
+    let mut reply = ChatMessage::from(Message::assistant(text)).with_model(Some(model.name));
+    reply.message.reasoning_content = (!reasoning.is_empty()).then_some(reasoning);
+    reply.usage = usage;
+    Ok(Some(reply))
+}
+
+/// Load the most recently updated conversation from the database.
+pub async fn load_latest_conversation() -> Option<StoredConversation> {
+    match get_storage().and_then(|storage| storage.latest_conversation()) {
+        Ok(conversation) => conversation,
+        Err(e) => {
+            tracing::error!("Failed to load the last conversation: {}", e);
+            None
+        }
+    }
+}
+
+/// Load conversation `id` from the database.
+pub async fn load_conversation(id: String) -> Option<StoredConversation> {
+    match get_storage().and_then(|storage| storage.load_conversation(&id)) {
+        Ok(conversation) => conversation,
+        Err(e) => {
+            tracing::error!("Failed to load conversation {}: {}", id, e);
+            None
+        }
+    }
+}
+
+/// Persist `messages` and `summary`, with the number of messages it covers,
+/// as conversation `id` at `revision`, unless a newer save got there first.
+/// Failures are logged; the chat keeps working without history.
+pub async fn save_conversation(
+    id: String,
+    revision: u64,
+    model: Option<String>,
+    system_prompt: String,
+    sampling: SamplingParams,
+    messages: Vec<StoredMessage>,
+    summary: Option<(String, usize)>,
+) {
+    let result = tokio::task::spawn_blocking(move || {
+        let saved = get_storage().and_then(|storage| {
+            let saved = storage.save_conversation(
+                &id,
+                revision,
+                model.as_deref(),
+                &system_prompt,
+                &sampling,
+                &messages,
+            )?;
+            if saved {
+                let summary = summary
+                    .as_ref()
+                    .map(|(text, covered)| (text.as_str(), *covered));
+                storage.set_summary(&id, summary)?;
+            }
+            Ok(saved)
+        });
+        match saved {
+            Ok(false) => tracing::debug!("Dropped stale save {} of conversation {}", revision, id),
+            Ok(true) => {}
+            Err(e) => tracing::error!("Failed to save conversation {}: {}", id, e),
+        }
+    })
+    .await;
+    if let Err(e) = result {
+        tracing::error!("Saving a conversation panicked: {}", e);
+    }
+}
+
+/// Estimated spend, in US dollars, on completions stored since the start of
+/// the current month (UTC). Models without a price count as free. Failures
+/// are logged and yield zero.
+pub async fn load_month_spend(pricing: HashMap<String, ModelPricing>) -> f64 {
+    let since = month_start(unix_now());
+    match get_storage().and_then(|storage| storage.usage_since(since)) {
+        Ok(records) => records
+            .iter()
+            .filter_map(|record| Some(pricing.get(record.model.as_deref()?)?.cost(&record.usage)))
+            .sum(),
+        Err(e) => {
+            tracing::error!("Failed to load this month's usage: {}", e);
+            0.0
+        }
+    }
+}
+
+/// Record the usage of a completion that belongs to no conversation, so it
+/// counts towards this month's spend. Failures are logged.
+pub async fn record_usage(source: &'static str, model: Option<String>, usage: TokenUsage) {
+    let result =
+        get_storage().and_then(|storage| storage.record_usage(source, model.as_deref(), &usage));
+    if let Err(e) = result {
+        tracing::error!("Failed to record {} usage: {}", source, e);
+    }
+}
+
+/// Store `messages` as a new conversation branched off another one and
+/// return its id. Failures are logged and yield `None`.
+pub async fn branch_conversation(
+    title: Option<String>,
+    model: Option<String>,
+    system_prompt: String,
+    sampling: SamplingParams,
+    messages: Vec<StoredMessage>,
+) -> Option<String> {
+    let id = new_conversation_id();
+    let result = tokio::task::spawn_blocking({
+        let id = id.clone();
+        move || {
+            let storage = get_storage()?;
+            storage.save_conversation(
+                &id,
+                1,
+                model.as_deref(),
+                &system_prompt,
+                &sampling,
+                &messages,
+            )?;
+            match title {
+                Some(title) => storage.set_title(&id, &title),
+                None => Ok(()),
+            }
+        }
+    })
+    .await
+    .map_err(anyhow::Error::from)
+    .and_then(|result| result);
     match result {
-        Ok(response) => response,
-        Err(err) => CompletionResponse {
-            id: "error".to_string(),
-            object: err.to_string(),
-            created: 0,
-            model: "".to_string(),
-            choices: vec![],
-        },
+        Ok(()) => Some(id),
+        Err(e) => {
+            tracing::error!("Failed to branch conversation: {}", e);
+            None
+        }
+    }
+}
+
+/// Ask where to save `html` and write it there, suggesting `title` as the
+/// file name. Returns the chosen path, or `None` if the user cancelled.
+pub async fn export_html(title: String, html: String) -> Result<Option<PathBuf>, String> {
+    let file_name: String = title
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, ' ' | '-' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let Some(file) = rfd::AsyncFileDialog::new()
+        .add_filter("HTML", &["html"])
+        .set_file_name(format!("{}.html", file_name.trim()))
+        .save_file()
+        .await
+    else {
+        return Ok(None);
+    };
+    file.write(html.as_bytes())
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(Some(file.path().to_path_buf()))
+}
+
+/// Read the PDF at `path` into its file name and the text of each page.
+/// Extraction is slow on long documents, so it runs on a blocking thread.
+pub async fn read_pdf(path: PathBuf) -> Result<(String, Vec<String>), String> {
+    let filename = path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    let pages = tokio::task::spawn_blocking(move || {
+        let bytes = std::fs::read(&path)?;
+        pdf::page_texts(&bytes)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())?;
+    Ok((filename, pages))
+}
+
+/// Read the text of an MCP resource mentioned in the input.
+pub async fn read_resource(resource: McpResource) -> Result<(McpResource, String), String> {
+    let text = crate::mcp::get_tool_manager()
+        .read_resource(&resource.server, &resource.uri)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok((resource, text))
+}
+
+/// Render `prompt` with `arguments` on its server.
+pub async fn get_prompt(
+    prompt: McpPrompt,
+    arguments: Vec<(String, String)>,
+) -> Result<Vec<Message>, String> {
+    crate::mcp::get_tool_manager()
+        .get_prompt(&prompt, arguments)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Ask for a folder to attach as the workspace and list its files. Returns
+/// `Ok(None)` if the dialog was cancelled.
+pub async fn pick_workspace() -> Result<Option<Workspace>, String> {
+    let Some(folder) = rfd::AsyncFileDialog::new().pick_folder().await else {
+        return Ok(None);
+    };
+    let root = folder.path().to_path_buf();
+    tokio::task::spawn_blocking(move || Workspace::open(root))
+        .await
+        .map_err(|e| e.to_string())?
+        .map(Some)
+        .map_err(|e| e.to_string())
+}
+
+/// Answer a call to the workspace's read-file tool with the file's text.
+pub async fn read_workspace_file(
+    workspace: Workspace,
+    tool_call: ToolCall,
+) -> Result<ToolCallResult, (String, String)> {
+    let call_id = tool_call.id.clone();
+    let path = workspace::requested_path(&tool_call.function.arguments)
+        .ok_or_else(|| (call_id.clone(), "Missing the path to read".to_string()))?;
+    let text = tokio::task::spawn_blocking(move || workspace.read_file(&path))
+        .await
+        .map_err(|e| (call_id.clone(), e.to_string()))?
+        .map_err(|e| (call_id.clone(), e.to_string()))?;
+    Ok(ToolCallResult {
+        success: true,
+        id: call_id.clone(),
+        contents: vec![Content::tool_result(call_id, text)],
+    })
+}
+
+/// What a running shell command reports.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandEvent {
+    /// A line the command wrote to stdout or stderr.
+    Output(String),
+    /// The command ended with this exit code, `None` if it was killed by a
+    /// signal, or it could not be started.
+    Exited(Result<Option<i32>, String>),
+}
+
+/// Run `command` with the shell in `dir`, streaming what it writes line by
+/// line and ending with how it exited. Cancelling `cancel` kills it, along
+/// with anything it started, which shares its process group on Unix.
+pub fn run_command(
+    dir: PathBuf,
+    command: String,
+    cancel: CancellationToken,
+) -> impl Stream<Item = CommandEvent> {
+    #[cfg(windows)]
+    let mut shell = tokio::process::Command::new("cmd");
+    #[cfg(windows)]
+    shell.arg("/C");
+    #[cfg(not(windows))]
+    let mut shell = tokio::process::Command::new("sh");
+    #[cfg(not(windows))]
+    shell.arg("-c");
+    #[cfg(unix)]
+    shell.process_group(0);
+    let spawned = shell
+        .arg(&command)
+        .current_dir(dir)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn();
+    let mut child = match spawned {
+        Ok(child) => child,
+        Err(err) => {
+            let exited = CommandEvent::Exited(Err(err.to_string()));
+            return stream::once(future::ready(exited)).boxed();
+        }
+    };
+    #[cfg(unix)]
+    let mut group = ProcessGroup(child.id());
+    let stdout = child.stdout.take().map(output_lines);
+    let stderr = child.stderr.take().map(output_lines);
+    let exited = stream::once(async move {
+        let status = child.wait().await;
+        #[cfg(unix)]
+        group.disarm();
+        CommandEvent::Exited(
+            status
+                .map(|status| status.code())
+                .map_err(|e| e.to_string()),
+        )
+    });
+    stream::select(
+        stream::iter(stdout).flatten(),
+        stream::iter(stderr).flatten(),
+    )
+    .chain(exited)
+    .take_until(cancel.cancelled_owned())
+    .boxed()
+}
+
+/// The process group of a running shell command, killed when dropped
+/// before the shell exited so the commands it started end with it.
+#[cfg(unix)]
+struct ProcessGroup(Option<u32>);
+
+#[cfg(unix)]
+impl ProcessGroup {
+    /// Leave the group alone once the shell has exited and been reaped.
+    fn disarm(&mut self) {
+        self.0 = None;
+    }
+}
+
+#[cfg(unix)]
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        if let Some(id) = self.0 {
+            // SAFETY: kill only sends a signal; the group is the shell's own
+            // and outlives it until the shell is reaped.
+            unsafe {
+                libc::kill(-(id as libc::pid_t), libc::SIGKILL);
+            }
+        }
+    }
+}
+
+/// The lines read from one of a command's output pipes.
+fn output_lines(
+    pipe: impl tokio::io::AsyncRead + Unpin + Send + 'static,
+) -> stream::BoxStream<'static, CommandEvent> {
+    use tokio::io::AsyncBufReadExt;
+    let lines = tokio::io::BufReader::new(pipe).lines();
+    stream::unfold(lines, |mut lines| async move {
+        let line = lines.next_line().await.ok()??;
+        Some((CommandEvent::Output(line), lines))
+    })
+    .boxed()
+}
+
+/// Screenshot tools that let the user pick a region or window and save it
+/// to the path passed last, tried in order until one is installed.
+#[cfg(target_os = "macos")]
+const SCREENSHOT_TOOLS: &[(&str, &[&str])] = &[("screencapture", &["-i"])];
+#[cfg(not(target_os = "macos"))]
+const SCREENSHOT_TOOLS: &[(&str, &[&str])] = &[
+    ("gnome-screenshot", &["-a", "-f"]),
+    ("spectacle", &["-b", "-n", "-r", "-o"]),
+    ("scrot", &["-s"]),
+    ("maim", &["-s"]),
+];
+
+/// Capture part of the screen with the platform's screenshot tool. Returns
+/// the PNG it saved, or `None` if the user cancelled the capture.
+pub async fn capture_screenshot() -> Result<Option<PathBuf>, String> {
+    let path = std::env::temp_dir().join(format!("ergon-screenshot-{}.png", unix_now()));
+    for (program, args) in SCREENSHOT_TOOLS {
+        let status = tokio::process::Command::new(program)
+            .args(*args)
+            .arg(&path)
+            .status()
+            .await;
+        match status {
+            Ok(_) => return Ok(path.exists().then_some(path)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(format!("{} failed: {}", program, err)),
+        }
+    }
+    let names: Vec<&str> = SCREENSHOT_TOOLS
+        .iter()
+        .map(|(program, _)| *program)
+        .collect();
+    Err(format!(
+        "No screenshot tool found, install one of: {}",
+        names.join(", ")
+    ))
+}
+
+/// Ask where to save a code block and write it there. The suggested file
+/// name takes its extension from the fence language. Returns `Ok(None)` if
+/// the dialog was cancelled.
+pub async fn save_code(language: Option<String>, code: String) -> Result<Option<PathBuf>, String> {
+    let extension = code_extension(language.as_deref());
+    let Some(file) = rfd::AsyncFileDialog::new()
+        .set_file_name(format!("snippet.{extension}"))
+        .save_file()
+        .await
+    else {
+        return Ok(None);
+    };
+    file.write(code.as_bytes())
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(Some(file.path().to_path_buf()))
+}
+
+/// File extension for a code fence language such as `rust` or `py`.
+/// Unknown languages that already look like an extension are used as is;
+/// anything else falls back to `txt`.
+fn code_extension(language: Option<&str>) -> String {
+    let Some(language) = language.map(|l| l.trim().to_lowercase()) else {
+        return "txt".to_string();
+    };
+    let extension = match language.as_str() {
+        "rust" => "rs",
+        "python" | "python3" => "py",
+        "javascript" | "node" => "js",
+        "typescript" => "ts",
+        "shell" | "bash" | "zsh" | "console" => "sh",
+        "ruby" => "rb",
+        "golang" => "go",
+        "kotlin" => "kt",
+        "csharp" | "c#" => "cs",
+        "c++" => "cpp",
+        "markdown" => "md",
+        "yaml" => "yml",
+        "haskell" => "hs",
+        "elixir" => "ex",
+        "perl" => "pl",
+        "powershell" => "ps1",
+        "text" | "plaintext" | "" => "txt",
+        other if other.chars().all(|c| c.is_ascii_alphanumeric()) => other,
+        _ => "txt",
+    };
+    extension.to_string()
+}
+
+/// Longest title kept from the model's answer.
+const MAX_TITLE_CHARS: usize = 60;
+
+/// Ask a model for a short title for the conversation in `messages` and
+/// store it as the title of conversation `id`. Uses `Config::title_model`
+/// when it names an available model, otherwise `model`. Returns the title
+/// that was stored.
+pub async fn generate_title(
+    id: String,
+    model: ModelInfo,
+    messages: Vec<Message>,
+) -> Option<String> {
+    let model = crate::config::Config::default()
+        .title_model
+        .and_then(|name| get_model_manager().find_model(&name).ok().flatten())
+        .unwrap_or(model);
+    let transcript = transcript_text(&messages);
+    let prompt = format!(
+        "Write a title of at most six words for this conversation. \
+         Reply with the title only.\n\n{transcript}"
+    );
+    let title = match model.client.provider() {
+        Ok(client) => request_title(client, prompt_request(model.id, prompt)).await,
+        Err(e) => Err(e),
+    };
+    let title = match title {
+        Ok(title) => title?,
+        Err(e) => {
+            tracing::error!("Failed to generate a title for conversation {}: {}", id, e);
+            return None;
+        }
+    };
+    if let Err(e) = get_storage().and_then(|storage| storage.set_title(&id, &title)) {
+        tracing::error!("Failed to store the title of conversation {}: {}", id, e);
+    }
+    Some(title)
+}
+
+/// Ask `model` to fold `messages` into `previous`, the summary of the
+/// conversation before them, if there is one. Uses `Config::summary_model`
+/// when it names an available model, otherwise `model`.
+pub async fn summarize(
+    model: ModelInfo,
+    previous: Option<String>,
+    messages: Vec<Message>,
+) -> Option<String> {
+    let model = crate::config::Config::default()
+        .summary_model
+        .and_then(|name| get_model_manager().find_model(&name).ok().flatten())
+        .unwrap_or(model);
+    let mut prompt = String::from(
+        "Summarize this conversation for an assistant that will continue it \
+         without seeing these messages. Keep the facts, decisions, names, \
+         code and open questions it needs, and leave out pleasantries. Reply \
+         with the summary only.",
+    );
+    if let Some(previous) = previous {
+        prompt.push_str(&format!(
+            "\n\nSummary of the conversation before these messages:\n{previous}"
+        ));
+    }
+    prompt.push_str(&format!("\n\n{}", transcript_text(&messages)));
+    let summary = match model.client.provider() {
+        Ok(client) => complete_text(client, prompt_request(model.id, prompt)).await,
+        Err(e) => Err(e),
+    };
+    match summary {
+        Ok(summary) if !summary.trim().is_empty() => Some(summary.trim().to_string()),
+        Ok(_) => None,
+        Err(e) => {
+            tracing::error!("Failed to summarize the conversation: {}", e);
+            None
+        }
+    }
+}
+
+/// Longest tool result kept in a transcript sent for a title or summary.
+const MAX_TRANSCRIPT_RESULT_CHARS: usize = 500;
+
+/// The user, assistant and tool messages in `messages` as plain text, each
+/// led by its role. Tool results are cut to
+/// [`MAX_TRANSCRIPT_RESULT_CHARS`].
+fn transcript_text(messages: &[Message]) -> String {
+    messages
+        .iter()
+        .filter(|m| matches!(m.role.as_str(), "user" | "assistant" | "tool"))
+        .map(|m| {
+            let text: Vec<String> = m
+                .content
+                .iter()
+                .filter_map(|content| match content {
+                    Content::Text { text } => Some(text.clone()),
+                    Content::ToolResult { content, .. } => {
+                        let mut result: String =
+                            content.chars().take(MAX_TRANSCRIPT_RESULT_CHARS).collect();
+                        if result.len() < content.len() {
+                            result.push('…');
+                        }
+                        Some(result)
+                    }
+                    _ => None,
+                })
+                .collect();
+            format!("{}: {}", m.role, text.join("\n"))
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// A request sending `prompt` to `model` as a lone user message.
+fn prompt_request(model: String, prompt: String) -> CompletionRequest {
+    CompletionRequest {
+        messages: vec![Message::user(prompt, None)],
+        model,
+        temperature: None,
+        top_p: None,
+        max_tokens: None,
+        tools: None,
+        reasoning_effort: None,
+        stop: vec![],
+        seed: None,
+        presence_penalty: None,
+        frequency_penalty: None,
+    }
+}
+
+/// Ask `client` for a title, cleaned up by [`clean_title`].
+async fn request_title(
+    client: Arc<dyn Provider>,
+    request: CompletionRequest,
+) -> anyhow::Result<Option<String>> {
+    Ok(clean_title(&complete_text(client, request).await?))
+}
+
+/// The text of `client`'s reply to `request`.
+async fn complete_text(
+    client: Arc<dyn Provider>,
+    request: CompletionRequest,
+) -> anyhow::Result<String> {
+    Ok(client
+        .complete_message(request)
+        .await?
+        .into_deltas()
+        .into_iter()
+        .filter_map(|delta| match delta {
+            CompletionDelta::Text(text) => Some(text),
+            _ => None,
+        })
+        .collect())
+}
+
+/// First non-empty line of a model-written title, without surrounding quotes
+/// or markdown emphasis, shortened to `MAX_TITLE_CHARS`.
+fn clean_title(text: &str) -> Option<String> {
+    let decoration = |c: char| matches!(c, '"' | '\'' | '*' | '#') || c.is_whitespace();
+    let line = text.lines().map(str::trim).find(|l| !l.is_empty())?;
+    let line = line.trim_matches(decoration);
+    let line = line
+        .strip_prefix("Title:")
+        .unwrap_or(line)
+        .trim_matches(decoration);
+    if line.is_empty() {
+        return None;
     }
+    Some(line.chars().take(MAX_TITLE_CHARS).collect())
 }
 
 pub async fn load_models() -> Vec<ModelInfo> {
@@ -63,11 +725,13 @@ pub async fn load_models() -> Vec<ModelInfo> {
                             name: "gpt-4o-mini".to_string(),
                             id: "gpt-4o-mini".to_string(),
                             client: Clients::OpenAI,
+                            capabilities: ModelCapabilities::default(),
                         },
                         ModelInfo {
                             name: "Claude 3.5 Sonnet".to_string(),
                             id: "claude-3-5-sonnet-20241022".to_string(),
                             client: Clients::Anthropic,
+                            capabilities: ModelCapabilities::default(),
                         },
                     ]
                 }
@@ -80,11 +744,13 @@ pub async fn load_models() -> Vec<ModelInfo> {
                     name: "gpt-4o-mini".to_string(),
                     id: "gpt-4o-mini".to_string(),
                     client: Clients::OpenAI,
+                    capabilities: ModelCapabilities::default(),
                 },
                 ModelInfo {
                     name: "Claude 3.5 Sonnet".to_string(),
                     id: "claude-3-5-sonnet-20241022".to_string(),
                     client: Clients::Anthropic,
+                    capabilities: ModelCapabilities::default(),
                 },
             ]
         }
@@ -99,10 +765,48 @@ pub async fn load_tools() -> Vec<crate::models::Tool> {
     }
 }
 
+/// Start the MCP servers waiting to be started lazily and list the tools
+/// then offered.
+pub async fn start_deferred_servers() -> Vec<crate::models::Tool> {
+    let manager = crate::mcp::get_tool_manager();
+    if let Err(e) = manager.start_deferred().await {
+        tracing::error!("Failed to start MCP servers: {}", e);
+    }
+    manager.get_tools().unwrap_or_default()
+}
+
+/// Re-fetch the model list in the background. Subscribers of the
+/// [`ModelManager`](crate::api::clients::ModelManager) are notified if it
+/// changed, so the result is not returned here.
+pub async fn refresh_models() {
+    if let Err(e) = get_model_manager().fetch_models().await {
+        tracing::error!("Failed to refresh models: {}", e);
+    }
+}
+
+/// Reload MCP tools in the background. The chat view picks up the new list
+/// through the tool manager's change notifications.
+pub async fn refresh_tools() {
+    if let Err(e) = crate::mcp::get_tool_manager().load_tools().await {
+        tracing::error!("Failed to reload MCP tools: {}", e);
+    }
+}
+
 pub async fn call_tool(tool_call: ToolCall) -> Result<ToolCallResult, (String, String)> {
-    log::info!("Received tool call: {:?}", tool_call);
+    tracing::info!("Received tool call: {:?}", tool_call);
     let manager = crate::mcp::get_tool_manager();
     let call_id = tool_call.id.clone();
+    if crate::mcp::builtin::is_builtin(&tool_call.function.name) {
+        let text = manager
+            .call_builtin(&tool_call.function.name, &tool_call.function.arguments)
+            .await
+            .map_err(|e| (call_id.clone(), e.to_string()))?;
+        return Ok(ToolCallResult {
+            success: true,
+            id: call_id.clone(),
+            contents: vec![Content::tool_result(call_id, text)],
+        });
+    }
     let client = manager
         .get_client_by_tool_call(&tool_call.function.name)
         .map_err(|e| (call_id.clone(), e.to_string()))?
@@ -114,7 +818,7 @@ pub async fn call_tool(tool_call: ToolCall) -> Result<ToolCallResult, (String, S
         })?;
     let args_json: JsonObject<Value> = serde_json::from_str(&tool_call.function.arguments)
         .map_err(|e| (call_id.clone(), format!("Failed to parse arguments: {}", e)))?;
-    log::info!("Tool call arguments as JSON: {:?}", args_json);
+    tracing::info!("Tool call arguments as JSON: {:?}", args_json);
     let function_name = tool_call.function.name.clone();
     let (_, client_function_name) = manager
         .tool_client_and_name_by_tool_call(function_name)
@@ -132,13 +836,12 @@ pub async fn call_tool(tool_call: ToolCall) -> Result<ToolCallResult, (String, S
         })?;
     let request_params = rmcp::model::CallToolRequestParams::new(client_function_name.clone())
         .with_arguments(args_json.clone());
-    log::info!(
+    tracing::info!(
         "Calling tool: {} with args: {:?}",
         client_function_name,
         request_params.arguments
     );
-    let tool_result = client
-        .call_tool(request_params)
+    let tool_result = crate::mcp::call_tool(&client, request_params, &call_id)
         .await
         .map_err(|e| (call_id.clone(), e.to_string()))?;
     let json_string = serde_json::to_string(&tool_result).map_err(|e| {
@@ -206,6 +909,17 @@ pub async fn prompt_agent(
     }
 }
 
+/// Ask the named agent to cancel its in-flight prompt turn. The turn itself
+/// still completes through `prompt_agent`, with a `Cancelled` stop reason.
+pub async fn cancel_agent(agent_name: String) -> Result<(), String> {
+    let manager = get_agent_manager();
+    let handle = manager
+        .get(&agent_name)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("agent '{agent_name}' is not running"))?;
+    handle.cancel().await.map_err(|e| e.to_string())
+}
+
 /// Run an `authenticate` request against the named agent.
 pub async fn authenticate_agent(agent_name: String, method_id: String) -> Result<(), String> {
     let manager = get_agent_manager();
@@ -295,3 +1009,193 @@ pub async fn resume_agent(
         Err(SessionError::Other(e)) => Err(e.to_string()),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use iced::futures::{future::BoxFuture, FutureExt};
+
+    use crate::{
+        api::clients::{CompletionStream, Model},
+        models::CompletionResponse,
+    };
+
+    use super::*;
+
+    /// Replies with `reply`, streamed one word at a time.
+    #[derive(Debug)]
+    struct MockProvider {
+        reply: &'static str,
+    }
+
+    impl Provider for MockProvider {
+        fn complete_message(
+            &self,
+            request: CompletionRequest,
+        ) -> BoxFuture<'static, anyhow::Result<CompletionResponse>> {
+            let response = serde_json::json!({
+                "id": "mock",
+                "object": "chat.completion",
+                "created": 0,
+                "model": request.model,
+                "choices": [{
+                    "index": 0,
+                    "message": {"role": "assistant", "content": self.reply},
+                    "finish_reason": "stop"
+                }]
+            });
+            async move { Ok(serde_json::from_value(response)?) }.boxed()
+        }
+
+        fn stream_message(
+            &self,
+            _request: CompletionRequest,
+        ) -> BoxFuture<'static, anyhow::Result<CompletionStream>> {
+            let deltas: Vec<_> = self
+                .reply
+                .split_inclusive(' ')
+                .map(|word| Ok(CompletionDelta::Text(word.to_string())))
+                .collect();
+            async move { Ok(stream::iter(deltas).boxed()) }.boxed()
+        }
+
+        fn list_models(&self) -> BoxFuture<'static, anyhow::Result<Vec<Model>>> {
+            async { Ok(vec![]) }.boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream_message_yields_provider_deltas() {
+        let client = Arc::new(MockProvider {
+            reply: "Hello there",
+        });
+        let deltas: Vec<_> = stream_message(
+            vec![ChatMessage::from_role_and_text("user", "Hi")],
+            client,
+            "mock".to_string(),
+            vec![],
+            SamplingParams::default(),
+            trace::new_turn(),
+            CancellationToken::new(),
+        )
+        .collect()
+        .await;
+        assert_eq!(
+            deltas,
+            vec![
+                Ok(CompletionDelta::Text("Hello ".to_string())),
+                Ok(CompletionDelta::Text("there".to_string())),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_request_title() {
+        let client = Arc::new(MockProvider {
+            reply: "\"Rome Trip Plan\"",
+        });
+        let request = CompletionRequest {
+            messages: vec![Message::user("Title?", None)],
+            model: "mock".to_string(),
+            temperature: None,
+            top_p: None,
+            max_tokens: None,
+            tools: None,
+            reasoning_effort: None,
+            stop: vec![],
+            seed: None,
+            presence_penalty: None,
+            frequency_penalty: None,
+        };
+        assert_eq!(
+            request_title(client, request).await.unwrap().as_deref(),
+            Some("Rome Trip Plan")
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run_command_streams_output_then_exit_code() {
+        let events: Vec<CommandEvent> = run_command(
+            std::env::temp_dir(),
+            "echo out; echo err >&2; exit 3".to_string(),
+            CancellationToken::new(),
+        )
+        .collect()
+        .await;
+        assert_eq!(events.len(), 3);
+        assert!(events.contains(&CommandEvent::Output("out".to_string())));
+        assert!(events.contains(&CommandEvent::Output("err".to_string())));
+        assert_eq!(events[2], CommandEvent::Exited(Ok(Some(3))));
+
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let events: Vec<CommandEvent> =
+            run_command(std::env::temp_dir(), "sleep 10".to_string(), cancel)
+                .collect()
+                .await;
+        assert!(events.is_empty());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_cancelling_a_command_kills_what_it_started() {
+        let cancel = CancellationToken::new();
+        let mut events = run_command(
+            std::env::temp_dir(),
+            "sleep 30 & echo $!; wait".to_string(),
+            cancel.clone(),
+        );
+        let Some(CommandEvent::Output(pid)) = events.next().await else {
+            panic!("expected the background pid");
+        };
+        cancel.cancel();
+        assert_eq!(events.next().await, None);
+        drop(events);
+        // Gone, or a zombie waiting to be reaped, once the signal lands.
+        let killed = (0..50).any(|_| {
+            let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).unwrap_or_default();
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            stat.is_empty() || stat.contains(") Z ")
+        });
+        assert!(killed);
+    }
+
+    #[test]
+    fn test_transcript_text_cuts_tool_results() {
+        let long = "x".repeat(MAX_TRANSCRIPT_RESULT_CHARS + 10);
+        let messages = vec![
+            Message::user("List the files", None),
+            Message::tool_result("call_1", &long, None),
+            Message::assistant("There are many."),
+        ];
+        let transcript = transcript_text(&messages);
+        let expected = format!("tool: {}…", "x".repeat(MAX_TRANSCRIPT_RESULT_CHARS));
+        assert_eq!(
+            transcript,
+            format!("user: List the files\n\n{expected}\n\nassistant: There are many.")
+        );
+    }
+
+    #[test]
+    fn test_clean_title() {
+        assert_eq!(
+            clean_title("\"Rome Trip Plan\"\n").as_deref(),
+            Some("Rome Trip Plan")
+        );
+        assert_eq!(
+            clean_title("\n**Title: Rust lifetimes**").as_deref(),
+            Some("Rust lifetimes")
+        );
+        assert_eq!(clean_title("  \n\"\""), None);
+    }
+
+    #[test]
+    fn test_code_extension() {
+        assert_eq!(code_extension(Some("rust")), "rs");
+        assert_eq!(code_extension(Some("Python")), "py");
+        assert_eq!(code_extension(Some("json")), "json");
+        assert_eq!(code_extension(Some("")), "txt");
+        assert_eq!(code_extension(Some("objective-c")), "txt");
+        assert_eq!(code_extension(None), "txt");
+    }
+}
@@ -0,0 +1,58 @@
+//! Markdown rendering for chat messages.
+
+use iced::{
+    widget::{button, column, markdown, row, text},
+    Alignment, Element,
+};
+
+use crate::ui::chat::ChatAction;
+
+/// Renders message markdown like the stock viewer, adding copy and save
+/// buttons above every code block.
+pub struct MessageViewer;
+
+impl<'a> markdown::Viewer<'a, ChatAction> for MessageViewer {
+    fn on_link_click(url: markdown::Uri) -> ChatAction {
+        ChatAction::UrlClicked(url)
+    }
+
+    fn code_block(
+        &self,
+        settings: markdown::Settings,
+        language: Option<&'a str>,
+        code: &'a str,
+        lines: &'a [markdown::Text],
+    ) -> Element<'a, ChatAction> {
+        let copy = button(
+            row![iced_fonts::lucide::copy().size(12), text("Copy").size(12)]
+                .spacing(4)
+                .align_y(Alignment::Center),
+        )
+        .style(button::text)
+        .padding(2)
+        .on_press(ChatAction::CopyCode(code.to_string()));
+        let save = button(
+            row![
+                iced_fonts::lucide::save().size(12),
+                text("Save as…").size(12)
+            ]
+            .spacing(4)
+            .align_y(Alignment::Center),
+        )
+        .style(button::text)
+        .padding(2)
+        .on_press(ChatAction::SaveCode(
+            language.map(str::to_string),
+            code.to_string(),
+        ));
+
+        column![
+            row![text(language.unwrap_or_default()).size(12), copy, save]
+                .spacing(10)
+                .align_y(Alignment::Center),
+            markdown::code_block(settings, lines, Self::on_link_click),
+        ]
+        .align_x(Alignment::End)
+        .into()
+    }
+}
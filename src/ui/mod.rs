@@ -1,47 +1,241 @@
+use std::path::PathBuf;
+
 use iced::{
-    widget::{button, column, row},
-    Element, Subscription, Task,
+    widget::{button, column, row, text, Row},
+    window, Alignment, Element, Event, Size, Subscription, Task,
 };
 
+use crate::hotkey::{self, Hotkey};
+
 mod chat;
+mod quick_chat;
+mod servers;
 mod settings;
+mod sidebar;
 
 pub fn init() -> (Ergon, Task<NavigationAction>) {
     Ergon::new()
 }
 
-#[derive(Debug, Default)]
+/// Stable identifier of an open chat tab. Unlike its position, it survives
+/// other tabs being closed, so in-flight tasks still reach the right tab.
+pub type TabId = usize;
+
+#[derive(Debug)]
+struct ChatTab {
+    id: TabId,
+    chat: chat::State,
+}
+
+#[derive(Debug)]
 pub struct Ergon {
     current_page: PageId,
-    chat: chat::State,
+    /// Open conversations, each with its own transcript, model and pending
+    /// request. Never empty.
+    tabs: Vec<ChatTab>,
+    active_tab: TabId,
+    next_tab_id: TabId,
+    sidebar: sidebar::State,
     pub settings: settings::State,
+    servers: servers::State,
+    main_window: window::Id,
+    /// The quick-chat window, while it is open.
+    quick_chat: Option<(window::Id, quick_chat::State)>,
+    /// The hotkey opening the quick-chat window. `None` where global
+    /// hotkeys are unavailable.
+    hotkey: Option<Hotkey>,
 }
 
 impl Ergon {
     pub fn new() -> (Self, Task<NavigationAction>) {
         let (chat_state, chat_task) = chat::State::new();
+        let (sidebar, sidebar_task) = sidebar::State::new();
         let settings = settings::State::new();
+        let (main_window, open_main_window) = window::open(window::Settings::default());
+        let hotkey = match Hotkey::new() {
+            Ok(mut hotkey) => {
+                if let Err(e) = hotkey.set(&settings.config.quick_chat_hotkey) {
+                    tracing::error!("{}", e);
+                }
+                Some(hotkey)
+            }
+            Err(e) => {
+                tracing::error!("{}", e);
+                None
+            }
+        };
         let state = Self {
             current_page: PageId::default(),
-            chat: chat_state,
+            tabs: vec![ChatTab {
+                id: 0,
+                chat: chat_state,
+            }],
+            active_tab: 0,
+            next_tab_id: 1,
+            sidebar,
             settings,
+            servers: servers::State::default(),
+            main_window,
+            quick_chat: None,
+            hotkey,
         };
-        let task = chat_task.map(NavigationAction::Chat);
+        let task = Task::batch([
+            open_main_window.discard(),
+            chat_task.map(|action| NavigationAction::Chat(0, action)),
+            sidebar_task.map(NavigationAction::Sidebar),
+        ]);
         (state, task)
     }
+
+    fn tab_mut(&mut self, id: TabId) -> Option<&mut chat::State> {
+        self.tabs
+            .iter_mut()
+            .find(|tab| tab.id == id)
+            .map(|tab| &mut tab.chat)
+    }
+
+    fn active_chat(&self) -> &chat::State {
+        self.tabs
+            .iter()
+            .find(|tab| tab.id == self.active_tab)
+            .map(|tab| &tab.chat)
+            .expect("the active tab is always open")
+    }
+
+    /// Open a blank chat in a new tab and switch to it.
+    fn open_tab(&mut self) -> TabId {
+        let id = self.next_tab_id;
+        self.next_tab_id += 1;
+        let chat = self.active_chat().new_tab();
+        self.tabs.push(ChatTab { id, chat });
+        self.active_tab = id;
+        id
+    }
+
+    /// Close tab `id`, stopping whatever it was waiting on. Closing the last
+    /// tab leaves a blank one in its place.
+    fn close_tab(&mut self, id: TabId) -> Task<NavigationAction> {
+        let Some(index) = self.tabs.iter().position(|tab| tab.id == id) else {
+            return Task::none();
+        };
+        if self.tabs.len() == 1 {
+            self.open_tab();
+        }
+        let mut tab = self.tabs.remove(index);
+        if self.active_tab == id {
+            let next = index.min(self.tabs.len() - 1);
+            self.active_tab = self.tabs[next].id;
+        }
+        // Requests of MCP servers it was asking about move to the tab in front.
+        if let Some(chat) = self.tab_mut(self.active_tab) {
+            tab.chat.move_server_requests(chat);
+        }
+        // The stop task only saves the transcript; its result is routed to a
+        // tab that no longer exists and dropped.
+        tab.chat
+            .update(chat::ChatAction::StopGeneration)
+            .map(move |action| NavigationAction::Chat(id, action))
+    }
+
+    /// Show stored conversation `id`: switch to the tab that already has it
+    /// open, or load it into the active tab. A tab that is still waiting on a
+    /// response is left alone and the conversation opens in a new tab. With
+    /// `message`, the transcript then jumps to that message.
+    fn open_conversation(&mut self, id: String, message: Option<usize>) -> Task<NavigationAction> {
+        let action = if let Some(tab) = self
+            .tabs
+            .iter()
+            .find(|tab| tab.chat.conversation_id() == id)
+        {
+            self.active_tab = tab.id;
+            match message {
+                Some(position) => chat::ChatAction::ShowMessage(position),
+                None => return Task::none(),
+            }
+        } else {
+            if self.active_chat().is_busy() {
+                self.open_tab();
+            }
+            chat::ChatAction::OpenConversation(id, message)
+        };
+        let tab = self.active_tab;
+        self.tab_mut(tab)
+            .map(|chat| chat.update(action))
+            .unwrap_or_else(Task::none)
+            .map(move |action| NavigationAction::Chat(tab, action))
+    }
+
+    /// Open the quick-chat window, answered by the active chat's model, or
+    /// bring it to the front if it is already open.
+    fn open_quick_chat(&mut self) -> Task<NavigationAction> {
+        if let Some((id, _)) = &self.quick_chat {
+            return window::gain_focus(*id);
+        }
+        let (id, open) = window::open(window::Settings {
+            size: Size::new(560.0, 380.0),
+            position: window::Position::Centered,
+            level: window::Level::AlwaysOnTop,
+            ..window::Settings::default()
+        });
+        let chat = self.active_chat();
+        let model = chat.selected_model().cloned();
+        let (quick_chat, focus) = quick_chat::State::new(model, chat.month_spend());
+        self.quick_chat = Some((id, quick_chat));
+        open.discard().chain(focus.map(NavigationAction::QuickChat))
+    }
+
+    /// Continue the quick chat's exchange in a new tab of the main window,
+    /// closing the quick-chat window.
+    fn expand_quick_chat(&mut self) -> Task<NavigationAction> {
+        let Some((quick_window, chat)) = &self.quick_chat else {
+            return Task::none();
+        };
+        let Some(messages) = chat.exchange() else {
+            return Task::none();
+        };
+        let quick_window = *quick_window;
+        self.quick_chat = None;
+        let tab = self.open_tab();
+        self.current_page = PageId::Chat;
+        let adopt = self
+            .tab_mut(tab)
+            .map(|chat| chat.adopt_messages(messages))
+            .unwrap_or_else(Task::none)
+            .map(move |action| NavigationAction::Chat(tab, action));
+        Task::batch([
+            adopt,
+            window::close(quick_window),
+            window::gain_focus(self.main_window),
+        ])
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum NavigationAction {
     Navigate(PageId),
-    Chat(chat::ChatAction),
+    /// An action for the chat in tab `TabId`.
+    Chat(TabId, chat::ChatAction),
+    /// An action delivered to every open chat.
+    AllChats(chat::ChatAction),
+    SelectTab(TabId),
+    CloseTab(TabId),
+    Sidebar(sidebar::SidebarAction),
     Settings(settings::SettingsAction),
+    Servers(servers::ServersAction),
+    /// A file was dropped onto a window; if it is the main one, the file is
+    /// attached to the next message of the active chat.
+    FileDropped(window::Id, PathBuf),
+    QuickChat(quick_chat::QuickChatAction),
+    /// The global hotkey with this id was pressed.
+    HotkeyPressed(u32),
+    WindowClosed(window::Id),
 }
 
 #[derive(PartialEq, Eq, Clone, Debug, Default)]
 pub enum PageId {
     #[default]
     Chat,
+    Servers,
     Settings,
 }
 
@@ -49,16 +243,121 @@ pub fn update(state: &mut Ergon, action: NavigationAction) -> Task<NavigationAct
     match action {
         NavigationAction::Navigate(page_id) => {
             state.current_page = page_id;
+            // The knowledge base and response times may have changed since
+            // the page was last shown.
+            if state.current_page == PageId::Settings {
+                return Task::batch(
+                    [
+                        settings::SettingsAction::LoadKnowledge,
+                        settings::SettingsAction::LoadResponseTimes,
+                    ]
+                    .map(|action| Task::done(NavigationAction::Settings(action))),
+                );
+            }
+            if state.current_page == PageId::Servers {
+                return Task::done(NavigationAction::Servers(servers::ServersAction::Load));
+            }
+            Task::none()
+        }
+        NavigationAction::Chat(tab, chat_action) => {
+            // Every save may add, rename or reorder conversations in the
+            // sidebar.
+            let refresh_task = if matches!(
+                chat_action,
+                chat::ChatAction::ConversationSaved | chat::ChatAction::TitleGenerated(..)
+            ) {
+                sidebar::State::refresh().map(NavigationAction::Sidebar)
+            } else {
+                Task::none()
+            };
+            // A new branch opens next to the conversation it came from.
+            let branch_task = match &chat_action {
+                chat::ChatAction::ConversationBranched(Some(id)) => {
+                    let branch_tab = state.open_tab();
+                    let open = chat::ChatAction::OpenConversation(id.clone(), None);
+                    Task::batch([
+                        Task::done(NavigationAction::Chat(branch_tab, open)),
+                        sidebar::State::refresh().map(NavigationAction::Sidebar),
+                    ])
+                }
+                _ => Task::none(),
+            };
+            // Results for a closed tab are dropped.
+            let task = state
+                .tab_mut(tab)
+                .map(|chat| chat.update(chat_action))
+                .unwrap_or_else(Task::none)
+                .map(move |action| NavigationAction::Chat(tab, action));
+            Task::batch([task, refresh_task, branch_task])
+        }
+        NavigationAction::AllChats(chat::ChatAction::RefreshModels) => {
+            // One fetch serves every tab through the model manager's updates.
+            Task::future(chat::refresh_models()).discard()
+        }
+        NavigationAction::AllChats(
+            chat_action @ (chat::ChatAction::SamplingRequested(_)
+            | chat::ChatAction::ElicitationRequested(_)),
+        ) => {
+            // Asked once, in the tab in front.
+            Task::done(NavigationAction::Chat(state.active_tab, chat_action))
+        }
+        NavigationAction::AllChats(chat_action) => {
+            let tasks = state.tabs.iter_mut().map(|tab| {
+                let id = tab.id;
+                tab.chat
+                    .update(chat_action.clone())
+                    .map(move |action| NavigationAction::Chat(id, action))
+            });
+            Task::batch(tasks.collect::<Vec<_>>())
+        }
+        NavigationAction::SelectTab(tab) => {
+            state.active_tab = tab;
+            state.current_page = PageId::Chat;
             Task::none()
         }
-        NavigationAction::Chat(chat_action) => {
-            let task = state.chat.update(chat_action);
-            task.map(NavigationAction::Chat)
+        NavigationAction::CloseTab(tab) => state.close_tab(tab),
+        NavigationAction::FileDropped(window, path) => {
+            if window != state.main_window || state.current_page != PageId::Chat {
+                return Task::none();
+            }
+            let tab = state.active_tab;
+            let action = chat::ChatAction::FileSelected(Some(vec![path]));
+            Task::done(NavigationAction::Chat(tab, action))
+        }
+        NavigationAction::Sidebar(sidebar_action) => {
+            let chat_task = match &sidebar_action {
+                sidebar::SidebarAction::Open(id) => {
+                    state.current_page = PageId::Chat;
+                    state.open_conversation(id.clone(), None)
+                }
+                sidebar::SidebarAction::OpenMessage(id, position) => {
+                    state.current_page = PageId::Chat;
+                    state.open_conversation(id.clone(), Some(*position))
+                }
+                sidebar::SidebarAction::NewChat => {
+                    state.current_page = PageId::Chat;
+                    state.open_tab();
+                    Task::none()
+                }
+                sidebar::SidebarAction::ConversationsLoaded(_)
+                | sidebar::SidebarAction::Import
+                | sidebar::SidebarAction::Imported(_)
+                | sidebar::SidebarAction::SearchChanged(_)
+                | sidebar::SidebarAction::SearchResults(..) => Task::none(),
+            };
+            let sidebar_task = state
+                .sidebar
+                .update(sidebar_action)
+                .map(NavigationAction::Sidebar);
+            Task::batch([sidebar_task, chat_task])
         }
         NavigationAction::Settings(settings_action) => {
             // Intercept SaveCompleted before forwarding: dispatch reload tasks
             // for models/tools when the corresponding configs changed, and
             // refresh the chat-mode agent picker from the freshly-saved config.
+            // The reloaded lists reach the chat view through the managers'
+            // change subscriptions, so those tasks produce no action; only the
+            // per-tab month spend reload reports back to its tab.
             let reload_task = if let settings::SettingsAction::SaveCompleted {
                 llm_changed,
                 mcp_changed,
@@ -66,20 +365,34 @@ pub fn update(state: &mut Ergon, action: NavigationAction) -> Task<NavigationAct
             {
                 let mut tasks: Vec<Task<NavigationAction>> = Vec::new();
                 if *llm_changed {
-                    tasks.push(
-                        Task::perform(chat::load_models(), chat::ChatAction::ModelsLoaded)
-                            .map(NavigationAction::Chat),
-                    );
+                    tasks.push(Task::future(chat::refresh_models()).discard());
                 }
                 if *mcp_changed {
+                    tasks.push(Task::future(chat::refresh_tools()).discard());
+                }
+                // Re-registered only if the shortcut changed.
+                if let Some(hotkey) = &mut state.hotkey {
+                    if let Err(e) = hotkey.set(&state.settings.config.quick_chat_hotkey) {
+                        tracing::error!("{}", e);
+                    }
+                }
+                // ACP agents, templates, tool policies, fallback models,
+                // summaries, pricing and budget may have changed even when
+                // llm/mcp didn't. Cheap to refresh unconditionally.
+                for tab in &mut state.tabs {
+                    tab.chat.refresh_available_agents();
+                    tab.chat.refresh_available_templates();
+                    tab.chat.refresh_default_system_prompt();
+                    tab.chat.refresh_tool_settings();
+                    tab.chat.refresh_fallback_models();
+                    tab.chat.refresh_summarize_after();
+                    let id = tab.id;
                     tasks.push(
-                        Task::perform(chat::load_tools(), chat::ChatAction::ToolsLoaaded)
-                            .map(NavigationAction::Chat),
+                        tab.chat
+                            .refresh_cost_settings()
+                            .map(move |action| NavigationAction::Chat(id, action)),
                     );
                 }
-                // ACP agent list may have changed even when llm/mcp didn't.
-                // Cheap to refresh unconditionally on save.
-                state.chat.refresh_available_agents();
                 Task::batch(tasks)
             } else {
                 Task::none()
@@ -92,30 +405,136 @@ pub fn update(state: &mut Ergon, action: NavigationAction) -> Task<NavigationAct
 
             Task::batch([settings_task, reload_task])
         }
+        NavigationAction::Servers(action) => {
+            state.servers.update(action).map(NavigationAction::Servers)
+        }
+        NavigationAction::QuickChat(quick_chat::QuickChatAction::Expand) => {
+            state.expand_quick_chat()
+        }
+        NavigationAction::QuickChat(action) => match &mut state.quick_chat {
+            Some((_, chat)) => chat.update(action).map(NavigationAction::QuickChat),
+            None => Task::none(),
+        },
+        NavigationAction::HotkeyPressed(id) => {
+            if state.hotkey.as_ref().and_then(Hotkey::id) != Some(id) {
+                return Task::none();
+            }
+            state.open_quick_chat()
+        }
+        NavigationAction::WindowClosed(id) => {
+            if id == state.main_window {
+                return iced::exit();
+            }
+            if let Some((_, mut chat)) = state.quick_chat.take_if(|(window, _)| *window == id) {
+                chat.stop();
+            }
+            Task::none()
+        }
     }
 }
 
 pub fn subscription(state: &Ergon) -> Subscription<NavigationAction> {
-    state.chat.subscription().map(NavigationAction::Chat)
+    let tabs = state.tabs.iter().map(|tab| {
+        tab.chat
+            .subscription()
+            .with(tab.id)
+            .map(|(id, action)| NavigationAction::Chat(id, action))
+    });
+    Subscription::batch(tabs.chain([
+        chat::State::shared_subscription().map(NavigationAction::AllChats),
+        servers::State::subscription().map(NavigationAction::Servers),
+        settings::State::subscription().map(NavigationAction::Settings),
+        iced::event::listen_with(file_dropped),
+        window::close_events().map(NavigationAction::WindowClosed),
+        Subscription::run(hotkey::presses).map(NavigationAction::HotkeyPressed),
+    ]))
 }
 
-pub fn view(state: &Ergon) -> Element<'_, NavigationAction> {
+/// Files dropped onto a window, for [`NavigationAction::FileDropped`].
+fn file_dropped(
+    event: Event,
+    _status: iced::event::Status,
+    window: window::Id,
+) -> Option<NavigationAction> {
+    match event {
+        Event::Window(window::Event::FileDropped(path)) => {
+            Some(NavigationAction::FileDropped(window, path))
+        }
+        _ => None,
+    }
+}
+
+pub fn title(state: &Ergon, window: window::Id) -> String {
+    match &state.quick_chat {
+        Some((id, _)) if *id == window => "Quick chat".to_string(),
+        _ => "Ergon".to_string(),
+    }
+}
+
+pub fn view(state: &Ergon, window: window::Id) -> Element<'_, NavigationAction> {
+    if let Some((id, quick_chat)) = &state.quick_chat {
+        if *id == window {
+            return quick_chat
+                .view(&state.settings.config.theme)
+                .map(NavigationAction::QuickChat);
+        }
+    }
     let navigation = build_navigation_bar(&state.current_page);
+    let active = state.active_chat();
 
     let page_content = match &state.current_page {
-        PageId::Chat => state
-            .chat
-            .view(&state.settings.config.theme)
-            .map(NavigationAction::Chat),
+        PageId::Chat => {
+            let tab = state.active_tab;
+            column![
+                build_tab_bar(state),
+                active
+                    .view(&state.settings.config.theme)
+                    .map(move |action| NavigationAction::Chat(tab, action)),
+            ]
+            .spacing(10)
+            .into()
+        }
+        PageId::Servers => state.servers.view().map(NavigationAction::Servers),
         PageId::Settings => state.settings.view().map(NavigationAction::Settings),
     };
 
-    column![navigation, page_content]
+    let sidebar = state
+        .sidebar
+        .view(active.conversation_id())
+        .map(NavigationAction::Sidebar);
+
+    column![navigation, row![sidebar, page_content].spacing(10)]
         .spacing(10)
         .padding(10)
         .into()
 }
 
+/// One button per open chat, plus a close button for each.
+fn build_tab_bar(state: &Ergon) -> Element<'_, NavigationAction> {
+    let tabs = state.tabs.iter().map(|tab| {
+        let label = if tab.chat.is_busy() {
+            format!("{} …", tab.chat.title())
+        } else {
+            tab.chat.title()
+        };
+        row![
+            button(text(label).size(14))
+                .on_press(NavigationAction::SelectTab(tab.id))
+                .style(if tab.id == state.active_tab {
+                    button::primary
+                } else {
+                    button::secondary
+                }),
+            button(iced_fonts::lucide::x())
+                .on_press(NavigationAction::CloseTab(tab.id))
+                .style(button::text),
+        ]
+        .align_y(Alignment::Center)
+        .into()
+    });
+    Row::with_children(tabs).spacing(6).wrap().into()
+}
+
 fn build_navigation_bar(current_page: &PageId) -> Element<'static, NavigationAction> {
     row![
         button("Chat").on_press_maybe(if current_page != &PageId::Chat {
@@ -123,6 +542,11 @@ fn build_navigation_bar(current_page: &PageId) -> Element<'static, NavigationAct
         } else {
             None
         }),
+        button("MCP Servers").on_press_maybe(if current_page != &PageId::Servers {
+            Some(NavigationAction::Navigate(PageId::Servers))
+        } else {
+            None
+        }),
         button("Settings").on_press_maybe(if current_page != &PageId::Settings {
             Some(NavigationAction::Navigate(PageId::Settings))
         } else {
@@ -0,0 +1,283 @@
+//! The small always-on-top window opened by the global hotkey: one prompt
+//! and its answer, which can be copied or continued in the main window.
+
+use std::collections::HashMap;
+
+use iced::{
+    widget::{button, column, container, markdown, operation, row, scrollable, text, text_input},
+    Alignment, Element, Length, Task, Theme,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    api::clients::CompletionError,
+    config::{Budget, Config, ModelPricing},
+    models::{CompletionDelta, Message, ModelInfo, SamplingParams},
+    trace,
+    ui::chat::{record_usage, stream_message, ChatMessage, StreamingMessage},
+};
+
+/// Id of the prompt input, focused when the window opens.
+const PROMPT_INPUT: &str = "quick_chat_prompt";
+
+/// Stands in for the conversation id of quick-chat usage in reports.
+const USAGE_SOURCE: &str = "quick chat";
+
+#[derive(Debug, Default)]
+pub struct State {
+    /// The model of the main window's active chat when the window opened.
+    model: Option<ModelInfo>,
+    system_prompt: String,
+    input: String,
+    /// The prompt last sent and the answer to it so far.
+    prompt: String,
+    answer: StreamingMessage,
+    error: Option<String>,
+    pricing: HashMap<String, ModelPricing>,
+    budget: Budget,
+    /// Estimated spend this month, counting the answers given here.
+    month_spend: f64,
+    /// Set while the answer streams in; cancelled to stop it.
+    cancel: Option<CancellationToken>,
+}
+
+#[derive(Debug, Clone)]
+pub enum QuickChatAction {
+    InputChanged(String),
+    Send,
+    StreamDelta(Result<CompletionDelta, CompletionError>),
+    StreamFinished,
+    /// Copy the answer to the clipboard.
+    Copy,
+    /// Continue the exchange in a new tab of the main window. Handled by
+    /// the application, which owns the tabs.
+    Expand,
+    UrlClicked(markdown::Uri),
+}
+
+impl State {
+    /// A blank quick chat answered by `model`, with `month_spend` spent so
+    /// far this month, and the task focusing its prompt.
+    pub fn new(model: Option<ModelInfo>, month_spend: f64) -> (Self, Task<QuickChatAction>) {
+        let config = Config::default();
+        let state = Self {
+            model,
+            system_prompt: config.default_system_prompt,
+            pricing: config.pricing,
+            budget: config.budget,
+            month_spend,
+            ..Self::default()
+        };
+        (state, operation::focus(PROMPT_INPUT))
+    }
+
+    pub fn update(&mut self, action: QuickChatAction) -> Task<QuickChatAction> {
+        match action {
+            QuickChatAction::InputChanged(value) => self.input = value,
+            QuickChatAction::Send => return self.send(),
+            QuickChatAction::StreamDelta(Ok(CompletionDelta::Text(text))) => {
+                self.answer.push_str(&text);
+            }
+            QuickChatAction::StreamDelta(Ok(CompletionDelta::Usage(usage))) => {
+                let model = self.model.as_ref().map(|m| m.name.clone());
+                if let Some(pricing) = model.as_ref().and_then(|name| self.pricing.get(name)) {
+                    self.month_spend += pricing.cost(&usage);
+                }
+                return Task::future(record_usage(USAGE_SOURCE, model, usage)).discard();
+            }
+            QuickChatAction::StreamDelta(Ok(_)) => {}
+            QuickChatAction::StreamDelta(Err(err)) => self.error = Some(err.text),
+            QuickChatAction::StreamFinished => self.cancel = None,
+            QuickChatAction::Copy => return iced::clipboard::write(self.answer.text().to_string()),
+            QuickChatAction::Expand => {}
+            QuickChatAction::UrlClicked(url) => tracing::info!("URL clicked: {}", url),
+        }
+        Task::none()
+    }
+
+    /// Ask the model about the input, replacing the previous exchange.
+    fn send(&mut self) -> Task<QuickChatAction> {
+        if self.input.trim().is_empty() || self.cancel.is_some() {
+            return Task::none();
+        }
+        let Some(model) = self.model.clone() else {
+            self.error = Some("No model is selected in the main window.".to_string());
+            return Task::none();
+        };
+        if self.budget.block_when_exceeded && self.budget.is_exceeded(self.month_spend) {
+            self.error = Some(
+                "Monthly budget reached. Raise the limit in Settings to send more requests."
+                    .to_string(),
+            );
+            return Task::none();
+        }
+        self.prompt = std::mem::take(&mut self.input);
+        self.answer = StreamingMessage::default();
+        self.error = None;
+        let client = match model.client.provider() {
+            Ok(client) => client,
+            Err(err) => {
+                self.error = Some(err.to_string());
+                return Task::none();
+            }
+        };
+        let mut messages = Vec::new();
+        if !self.system_prompt.trim().is_empty() {
+            messages.push(ChatMessage::from(Message::system(&self.system_prompt)));
+        }
+        messages.push(ChatMessage::from_role_and_text("user", &self.prompt));
+        let cancel = CancellationToken::new();
+        self.cancel = Some(cancel.clone());
+        Task::run(
+            stream_message(
+                messages,
+                client,
+                model.id,
+                vec![],
+                SamplingParams::default(),
+                trace::new_turn(),
+                cancel,
+            ),
+            QuickChatAction::StreamDelta,
+        )
+        .chain(Task::done(QuickChatAction::StreamFinished))
+    }
+
+    /// Stop the answer streaming in, if any.
+    pub fn stop(&mut self) {
+        if let Some(cancel) = self.cancel.take() {
+            cancel.cancel();
+        }
+    }
+
+    /// The prompt and its answer, for continuing in the main window. `None`
+    /// until an answer is complete.
+    pub fn exchange(&self) -> Option<Vec<ChatMessage>> {
+        if self.cancel.is_some() || self.answer.is_empty() {
+            return None;
+        }
+        let mut answer = ChatMessage::from_role_and_text("assistant", self.answer.text());
+        answer.model = self.model.as_ref().map(|m| m.name.clone());
+        Some(vec![
+            ChatMessage::from_role_and_text("user", &self.prompt),
+            answer,
+        ])
+    }
+
+    pub fn view<'a>(&'a self, theme: &'a Theme) -> Element<'a, QuickChatAction> {
+        let model = self
+            .model
+            .as_ref()
+            .map_or("No model selected".to_string(), |m| m.name.clone());
+        let input = text_input("Ask anything…", &self.input)
+            .id(PROMPT_INPUT)
+            .on_input(QuickChatAction::InputChanged)
+            .on_submit(QuickChatAction::Send)
+            .padding(10);
+        let mut content = column![input, text(model).size(11).style(text::secondary)].spacing(8);
+        if let Some(error) = &self.error {
+            content = content.push(text(error).style(text::danger));
+        }
+        if !self.answer.is_empty() {
+            let settings =
+                markdown::Settings::with_style(markdown::Style::from_palette(theme.palette()));
+            let answer = markdown::view(self.answer.markdown_items(), settings)
+                .map(QuickChatAction::UrlClicked);
+            content = content.push(
+                scrollable(container(answer).padding(5))
+                    .height(Length::Fill)
+                    .width(Length::Fill),
+            );
+            let done = self.cancel.is_none();
+            content = content.push(
+                row![
+                    button(text("Copy").size(14))
+                        .on_press_maybe(done.then_some(QuickChatAction::Copy))
+                        .style(button::secondary),
+                    button(text("Open in Ergon").size(14))
+                        .on_press_maybe(done.then_some(QuickChatAction::Expand)),
+                ]
+                .spacing(10)
+                .align_y(Alignment::Center),
+            );
+        }
+        container(content).padding(12).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Clients, ModelCapabilities, TokenUsage};
+
+    #[test]
+    fn test_answer_streams_in_before_it_can_be_expanded() {
+        let mut state = State {
+            prompt: "Capital of France?".to_string(),
+            cancel: Some(CancellationToken::new()),
+            ..State::default()
+        };
+        let _ = state.update(QuickChatAction::StreamDelta(Ok(CompletionDelta::Text(
+            "Paris".to_string(),
+        ))));
+        assert!(state.exchange().is_none());
+
+        let _ = state.update(QuickChatAction::StreamFinished);
+        let exchange = state.exchange().unwrap();
+        assert_eq!(exchange[0].message.text_content(), ["Capital of France?"]);
+        assert_eq!(exchange[1].message.role, "assistant");
+        assert_eq!(exchange[1].message.text_content(), ["Paris"]);
+    }
+
+    #[test]
+    fn test_usage_counts_towards_the_budget() {
+        let model = ModelInfo {
+            name: "gpt-4o".to_string(),
+            id: "gpt-4o".to_string(),
+            client: Clients::OpenAI,
+            capabilities: ModelCapabilities::default(),
+        };
+        let mut state = State {
+            model: Some(model),
+            pricing: HashMap::from([(
+                "gpt-4o".to_string(),
+                ModelPricing {
+                    prompt: 2.0,
+                    completion: 8.0,
+                },
+            )]),
+            budget: Budget {
+                monthly_limit: 10.0,
+                block_when_exceeded: true,
+            },
+            month_spend: 9.0,
+            cancel: Some(CancellationToken::new()),
+            ..State::default()
+        };
+        let _ = state.update(QuickChatAction::StreamDelta(Ok(CompletionDelta::Usage(
+            TokenUsage {
+                prompt_tokens: 100_000,
+                completion_tokens: 100_000,
+            },
+        ))));
+        assert!((state.month_spend - 10.0).abs() < 1e-9);
+
+        let _ = state.update(QuickChatAction::StreamFinished);
+        state.input = "Another question".to_string();
+        let _ = state.update(QuickChatAction::Send);
+        assert!(state.error.unwrap().contains("budget"));
+        assert_eq!(state.input, "Another question");
+        assert!(state.cancel.is_none());
+    }
+
+    #[test]
+    fn test_send_needs_a_model() {
+        let mut state = State {
+            input: "Hello".to_string(),
+            ..State::default()
+        };
+        let _ = state.update(QuickChatAction::Send);
+        assert!(state.error.is_some());
+        assert_eq!(state.input, "Hello");
+    }
+}
@@ -0,0 +1,235 @@
+//! The MCP servers page: every configured server with its status and how
+//! many tools it exposes, buttons to connect, disconnect or restart it, and
+//! the messages it logged. Servers are added and edited in the settings.
+
+use std::collections::HashSet;
+
+use iced::{
+    futures::StreamExt,
+    widget::{button, column, container, row, scrollable, text},
+    Alignment, Element, Length, Subscription, Task, Theme,
+};
+use tokio_stream::wrappers::WatchStream;
+
+use crate::mcp::{get_tool_manager, LogLevel, ServerLog, ServerStatus, ServerSummary};
+
+#[derive(Debug, Default)]
+pub struct State {
+    servers: Vec<ServerSummary>,
+    /// Servers with a connect, disconnect or restart still running.
+    busy: HashSet<String>,
+    /// Server whose log is open, with its messages.
+    open_log: Option<(String, Vec<ServerLog>)>,
+}
+
+#[derive(Debug, Clone)]
+pub enum ServersAction {
+    /// Re-read the server list from the tool manager.
+    Load,
+    Connect(String),
+    Disconnect(String),
+    Restart(String),
+    /// A connect, disconnect or restart of the named server finished.
+    Finished(String, Result<(), String>),
+    /// Show or hide the log of the named server.
+    ToggleLog(String),
+}
+
+/// What the user asked to do with a server.
+#[derive(Debug, Clone, Copy)]
+enum Operation {
+    Connect,
+    Disconnect,
+    Restart,
+}
+
+impl State {
+    pub fn update(&mut self, action: ServersAction) -> Task<ServersAction> {
+        match action {
+            ServersAction::Load => {
+                match get_tool_manager().servers() {
+                    Ok(servers) => self.servers = servers,
+                    Err(e) => tracing::error!("Failed to list MCP servers: {}", e),
+                }
+                if let Some((name, logs)) = &mut self.open_log {
+                    *logs = get_tool_manager().server_logs(name);
+                }
+            }
+            ServersAction::Connect(name) => return self.start(name, Operation::Connect),
+            ServersAction::Disconnect(name) => return self.start(name, Operation::Disconnect),
+            ServersAction::Restart(name) => return self.start(name, Operation::Restart),
+            ServersAction::Finished(name, result) => {
+                // Failures show in the server's status.
+                if let Err(e) = result {
+                    tracing::error!("MCP server '{}': {}", name, e);
+                }
+                self.busy.remove(&name);
+                return Task::done(ServersAction::Load);
+            }
+            ServersAction::ToggleLog(name) => {
+                self.open_log = match self.open_log.take() {
+                    Some((open, _)) if open == name => None,
+                    _ => {
+                        let logs = get_tool_manager().server_logs(&name);
+                        Some((name, logs))
+                    }
+                };
+            }
+        }
+        Task::none()
+    }
+
+    fn start(&mut self, name: String, operation: Operation) -> Task<ServersAction> {
+        if !self.busy.insert(name.clone()) {
+            return Task::none();
+        }
+        Task::perform(run(name.clone(), operation), move |result| {
+            ServersAction::Finished(name.clone(), result)
+        })
+    }
+
+    /// Reload the list whenever the tool manager's tools, the servers'
+    /// statuses or their logs change, which includes settings being saved.
+    pub fn subscription() -> Subscription<ServersAction> {
+        Subscription::batch([
+            Subscription::run(server_updates),
+            Subscription::run(status_updates),
+            Subscription::run(log_updates),
+        ])
+    }
+
+    pub fn view(&self) -> Element<'_, ServersAction> {
+        let mut list = column![text("MCP Servers").size(18)].spacing(10);
+        if self.servers.is_empty() {
+            list = list.push(
+                text("No MCP servers are configured. Add them in the settings.")
+                    .style(text::secondary),
+            );
+        }
+        for server in &self.servers {
+            list = list.push(self.server_row(server));
+            match &self.open_log {
+                Some((name, logs)) if *name == server.name => list = list.push(log_view(logs)),
+                _ => {}
+            }
+        }
+        container(scrollable(list.padding(20)))
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .into()
+    }
+
+    fn server_row<'a>(&'a self, server: &'a ServerSummary) -> Element<'a, ServersAction> {
+        let busy = self.busy.contains(&server.name);
+        let state = match (&server.status, server.tools) {
+            _ if !server.enabled => "Disabled in the settings".to_string(),
+            (ServerStatus::Connected, 1) => "Connected · 1 tool".to_string(),
+            (ServerStatus::Connected, tools) => format!("Connected · {tools} tools"),
+            (status, _) => status.to_string(),
+        };
+        let connected = server.status == ServerStatus::Connected;
+        let name = server.name.clone();
+        let log_open = matches!(&self.open_log, Some((open, _)) if *open == server.name);
+        let log_button = button(if log_open { "Hide log" } else { "Log" })
+            .style(button::text)
+            .on_press(ServersAction::ToggleLog(name.clone()));
+        let actions = if !server.enabled {
+            row![]
+        } else if connected {
+            row![
+                button("Disconnect")
+                    .on_press_maybe((!busy).then(|| ServersAction::Disconnect(name.clone())))
+                    .style(button::secondary),
+                button("Restart")
+                    .on_press_maybe((!busy).then(|| ServersAction::Restart(name.clone()))),
+            ]
+        } else {
+            row![button("Connect").on_press_maybe((!busy).then(|| ServersAction::Connect(name)))]
+        };
+        let details = column![
+            text(&server.name).size(16),
+            text(state).size(12).style(status_style(&server.status)),
+        ]
+        .spacing(2);
+        row![
+            container(details).width(Length::Fill),
+            log_button,
+            actions.spacing(10)
+        ]
+        .spacing(10)
+        .align_y(Alignment::Center)
+        .into()
+    }
+}
+
+/// The messages a server logged, oldest first, each led by its level.
+fn log_view(logs: &[ServerLog]) -> Element<'_, ServersAction> {
+    if logs.is_empty() {
+        return text("Nothing logged yet.")
+            .size(12)
+            .style(text::secondary)
+            .into();
+    }
+    let lines = logs.iter().map(|log| {
+        let source = match &log.logger {
+            Some(logger) => format!("{} {}", log.level, logger),
+            None => log.level.to_string(),
+        };
+        row![
+            text(source).size(12).style(log_style(log.level)),
+            text(&log.message).size(12).font(iced::Font::MONOSPACE),
+        ]
+        .spacing(10)
+        .into()
+    });
+    container(scrollable(column(lines).spacing(2)).height(Length::Fixed(200.0)))
+        .padding(10)
+        .width(Length::Fill)
+        .style(container::rounded_box)
+        .into()
+}
+
+/// Text style for the level of a logged message.
+fn log_style(level: LogLevel) -> fn(&Theme) -> text::Style {
+    match level {
+        LogLevel::Error => text::danger,
+        LogLevel::Warning => text::warning,
+        LogLevel::Debug | LogLevel::Info => text::secondary,
+    }
+}
+
+/// Text style for a server's status: green when connected, red when it
+/// failed.
+pub fn status_style(status: &ServerStatus) -> fn(&Theme) -> text::Style {
+    match status {
+        ServerStatus::Connected => text::success,
+        ServerStatus::Failed(_) => text::danger,
+        ServerStatus::Connecting | ServerStatus::Disconnected => text::secondary,
+    }
+}
+
+async fn run(name: String, operation: Operation) -> Result<(), String> {
+    let manager = get_tool_manager();
+    let result = match operation {
+        Operation::Connect => manager.connect(&name).await,
+        Operation::Disconnect => manager.disconnect(&name),
+        Operation::Restart => manager.restart(&name).await,
+    };
+    result.map_err(|e| e.to_string())
+}
+
+/// Stream of [`ServersAction::Load`], one for every change of the tool list.
+fn server_updates() -> impl iced::futures::Stream<Item = ServersAction> {
+    WatchStream::from_changes(get_tool_manager().subscribe()).map(|_| ServersAction::Load)
+}
+
+/// Stream of [`ServersAction::Load`], one for every message a server logs.
+fn log_updates() -> impl iced::futures::Stream<Item = ServersAction> {
+    WatchStream::from_changes(get_tool_manager().subscribe_logs()).map(|_| ServersAction::Load)
+}
+
+/// Stream of [`ServersAction::Load`], one for every change of a server's
+/// status.
+fn status_updates() -> impl iced::futures::Stream<Item = ServersAction> {
+    WatchStream::from_changes(get_tool_manager().subscribe_status()).map(|_| ServersAction::Load)
+}
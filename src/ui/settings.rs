@@ -1,18 +1,80 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::PathBuf;
 
-use iced::widget::{button, column, container, pick_list, row, text, text_input};
-use iced::{Alignment, Element, Length, Task, Theme};
+use iced::futures::StreamExt;
+use iced::widget::{
+    button, checkbox, column, container, pick_list, row, text, text_editor, text_input,
+};
+use iced::{Alignment, Element, Length, Subscription, Task, Theme};
 use iced_aw::number_input;
+use tokio_stream::wrappers::WatchStream;
 
 use crate::config::{
-    AcpAgentConfig, Config, McpAuthConfig, McpConfig, McpStdioConfig,
-    McpStreamableHttpConfig,
+    AcpAgentConfig, ApiFlavor, Budget, Config, ConversationTemplate, LocalServerConfig,
+    McpAuthConfig, McpConfig, McpSseConfig, McpStdioConfig, McpStreamableHttpConfig, ModelPricing,
+    TemplateMessage, ToolPolicy, DEFAULT_THINKING_BUDGET,
 };
+use crate::hotkey;
+use crate::knowledge::Indexed;
+use crate::mcp::{get_tool_manager, ServerStatus};
+use crate::models::ResponseTiming;
+use crate::storage::{get_storage, KnowledgeCollection, TimingRecord};
+
+use super::servers::status_style;
+
+/// Roles a seeded template message may take.
+const TEMPLATE_ROLES: [&str; 3] = ["system", "user", "assistant"];
+
+/// One model's streamed responses, timed on average.
+#[derive(Debug, Clone, PartialEq)]
+struct ModelTimings {
+    model: String,
+    responses: usize,
+    /// Average milliseconds until the first token, over the responses
+    /// that streamed any.
+    first_token_ms: Option<u64>,
+    /// Average milliseconds until the response ended.
+    elapsed_ms: u64,
+    /// Tokens per second over all of the model's generation time.
+    tokens_per_second: Option<f64>,
+}
+
+/// `records` grouped by model, in model order.
+fn timings_by_model(records: &[TimingRecord]) -> Vec<ModelTimings> {
+    let mut by_model: BTreeMap<&str, Vec<&TimingRecord>> = BTreeMap::new();
+    for record in records {
+        let model = record.model.as_deref().unwrap_or("Unknown model");
+        by_model.entry(model).or_default().push(record);
+    }
+    by_model
+        .into_iter()
+        .map(|(model, records)| {
+            let first_tokens: Vec<u64> = records
+                .iter()
+                .filter_map(|r| r.timing.first_token_ms())
+                .collect();
+            let total = ResponseTiming {
+                elapsed_ms: records.iter().map(|r| r.timing.elapsed_ms).sum(),
+                generation_ms: records.iter().map(|r| r.timing.generation_ms).sum(),
+                tokens: records.iter().map(|r| r.timing.tokens).sum(),
+            };
+            ModelTimings {
+                model: model.to_string(),
+                responses: records.len(),
+                first_token_ms: (!first_tokens.is_empty())
+                    .then(|| first_tokens.iter().sum::<u64>() / first_tokens.len() as u64),
+                elapsed_ms: total.elapsed_ms / records.len() as u64,
+                tokens_per_second: total.tokens_per_second(),
+            }
+        })
+        .collect()
+}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum McpConfigType {
     Stdio,
     StreamableHttp,
+    Sse,
 }
 
 impl std::fmt::Display for McpConfigType {
@@ -20,12 +82,17 @@ impl std::fmt::Display for McpConfigType {
         match self {
             McpConfigType::Stdio => write!(f, "Stdio"),
             McpConfigType::StreamableHttp => write!(f, "Streamable HTTP"),
+            McpConfigType::Sse => write!(f, "SSE (legacy)"),
         }
     }
 }
 
 impl McpConfigType {
-    const ALL: [McpConfigType; 2] = [McpConfigType::Stdio, McpConfigType::StreamableHttp];
+    const ALL: [McpConfigType; 3] = [
+        McpConfigType::Stdio,
+        McpConfigType::StreamableHttp,
+        McpConfigType::Sse,
+    ];
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -63,6 +130,25 @@ impl From<&McpAuthConfig> for McpAuthType {
     }
 }
 
+/// Settings fields holding a secret. These render as masked inputs with a
+/// reveal toggle and a paste-from-clipboard button.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SecretField {
+    OpenAIKey,
+    AnthropicKey,
+    AzureKey,
+    MistralKey,
+    CohereKey,
+    BedrockSecretKey,
+    BedrockSessionToken,
+    ProxyUrl,
+    LocalServerKey(usize),
+    McpBearerToken(usize),
+    /// The environment variables of a stdio MCP server, which usually
+    /// carry its API keys.
+    McpEnv(usize),
+}
+
 #[derive(Debug, Clone, Default)]
 pub enum AuthStatus {
     #[default]
@@ -82,6 +168,28 @@ pub struct State {
     saved_config: Config,
     /// OAuth auth status keyed by server name (stable across add/remove/reorder).
     auth_status: HashMap<String, AuthStatus>,
+    /// Connection status of each MCP server by name, as the tool manager
+    /// reports it.
+    mcp_status: BTreeMap<String, ServerStatus>,
+    /// Outcome of the last MCP config import, shown under the server list.
+    mcp_import_status: Option<String>,
+    /// Secret fields the user has toggled to plain-text display.
+    revealed_secrets: HashSet<SecretField>,
+    /// Editor contents for `config.default_system_prompt`.
+    default_system_prompt: text_editor::Content,
+    /// Rows of the pricing table, in display order. `config.pricing` is
+    /// rebuilt from them on every edit, skipping rows without a model name.
+    pricing_rows: Vec<(String, ModelPricing)>,
+    /// Outcome of the last usage CSV export, shown under the export button.
+    usage_export_status: Option<String>,
+    /// Outcome of the last embeddings test, shown under the test button.
+    embeddings_status: Option<String>,
+    /// What the knowledge base holds, loaded when the page is opened.
+    knowledge: Vec<KnowledgeCollection>,
+    /// Progress or outcome of the last indexing.
+    knowledge_status: Option<String>,
+    /// Response times per model, loaded when the page is opened.
+    response_times: Vec<ModelTimings>,
 }
 
 #[derive(Debug, Clone)]
@@ -89,23 +197,56 @@ pub enum SettingsAction {
     ChangeTheme(Theme),
     ChangeOpenAIKey(String),
     ChangeOpenAIUrl(String),
+    ChangeOpenAIOrganization(String),
+    ChangeOpenAIProject(String),
     ChangeAnthropicKey(String),
     ChangeAnthropicUrl(String),
     ChangeAnthropicMaxTokens(u32),
-    ChangeVllmUrl(String),
-    ChangeVllmModel(String),
+    ToggleAnthropicThinking(bool),
+    ChangeAnthropicThinkingBudget(u32),
+    ToggleAnthropicPromptCaching(bool),
+    AddLocalServer,
+    RemoveLocalServer(usize),
+    ChangeLocalServerName(usize, String),
+    ChangeLocalServerUrl(usize, String),
+    ChangeLocalServerKey(usize, String),
+    ChangeLocalServerFilter(usize, String),
+    ChangeAzureKey(String),
+    ChangeAzureResource(String),
+    ChangeAzureDeployment(String),
+    ChangeAzureApiVersion(String),
+    ChangeMistralKey(String),
+    ChangeMistralUrl(String),
+    ChangeCohereKey(String),
+    ChangeCohereUrl(String),
+    ChangeBedrockRegion(String),
+    ChangeBedrockAccessKey(String),
+    ChangeBedrockSecretKey(String),
+    ChangeBedrockSessionToken(String),
+    ChangeProxyUrl(String),
+    ChangeNoProxy(String), // comma-separated hosts
     AddMcpConfig,
     ChangeMcpConfigName(usize, String),
-    ChangeMcpConfigType(usize, bool), // index, true for Stdio, false for StreamableHttp
+    ChangeMcpConfigType(usize, McpConfigType),
     ChangeMcpStdioCommand(usize, String),
     ChangeMcpStdioArgs(usize, String), // comma-separated args string
+    ChangeMcpStdioEnv(usize, String),  // "KEY=value, KEY2=value2"
+    ChangeMcpStdioCwd(usize, String),  // empty means Ergon's own directory
     ChangeMcpHttpEndpoint(usize, String),
     ChangeMcpHttpAuthType(usize, McpAuthType),
     ChangeMcpHttpBearerToken(usize, String),
     ChangeMcpHttpOAuthScopes(usize, String),
     ChangeMcpHttpOAuthClientName(usize, String),
     ChangeMcpHttpOAuthRedirectPort(usize, u16),
+    ToggleMcpServer(usize, bool),
+    ToggleMcpTool(usize, String, bool), // tool name as the server gives it
+    ToggleLazyMcpStart(bool),
     RemoveMcpConfig(usize),
+    /// User clicked "Import MCP config": pick a `claude_desktop_config.json`.
+    ImportMcpConfig,
+    McpConfigImported(Result<Option<Vec<McpConfig>>, String>),
+    /// The tool manager published new MCP server statuses.
+    McpStatusChanged(BTreeMap<String, ServerStatus>),
     SaveSettings,
     /// Emitted after `SaveSettings` completes. Consumed by the app shell to
     /// trigger reloading of models and/or tools if the relevant configs changed.
@@ -117,6 +258,9 @@ pub enum SettingsAction {
     OAuthAuthFinished(String, Result<(), String>),
     ClearOAuthTokens(usize),
     OAuthTokensCleared(String, Result<(), String>),
+    ToggleSecretVisibility(SecretField),
+    PasteSecret(SecretField),
+    SecretPasted(SecretField, Option<String>),
 
     // ── ACP agents ─────────────────────────────────────────────────────
     AddAcpAgent,
@@ -126,6 +270,62 @@ pub enum SettingsAction {
     ChangeAcpAgentArgs(usize, String),
     ChangeAcpAgentWorkspaceRoot(usize, String),
     ChangeAcpAgentEnv(usize, String),
+
+    // ── Conversation templates ─────────────────────────────────────────
+    AddTemplate,
+    RemoveTemplate(usize),
+    ChangeTemplateName(usize, String),
+    AddTemplateMessage(usize),
+    RemoveTemplateMessage(usize, usize), // template index, message index
+    ChangeTemplateMessageRole(usize, usize, &'static str),
+    ChangeTemplateMessageContent(usize, usize, String),
+
+    // ── Tool policies ──────────────────────────────────────────────────
+    ChangeToolPolicy(String, ToolPolicy), // tool name as the model sees it
+    ChangeMaxToolIterations(u32),
+
+    // ── Conversations ──────────────────────────────────────────────────
+    ChangeTitleModel(String),   // empty means the conversation's own model
+    ChangeSummarizeAfter(u32),  // 0 turns summaries off
+    ChangeSummaryModel(String), // empty means the conversation's own model
+    ChangeQuickChatHotkey(String), // empty means no hotkey
+    EditDefaultSystemPrompt(text_editor::Action),
+    ChangeFallbackModels(String), // comma-separated model names
+
+    // ── Pricing ────────────────────────────────────────────────────────
+    AddPricing,
+    RemovePricing(usize),
+    ChangePricingModel(usize, String),
+    ChangePricingPrompt(usize, f64), // US dollars per million tokens
+    ChangePricingCompletion(usize, f64),
+    ChangeMonthlyBudget(f64), // US dollars; zero for no cap
+    ToggleBlockOverBudget(bool),
+    ExportUsage,
+    UsageExported(Result<Option<PathBuf>, String>),
+    LoadResponseTimes,
+    ResponseTimesLoaded(Result<Vec<TimingRecord>, String>),
+    ToggleAuditLog(bool),
+
+    // ── Retrieval ──────────────────────────────────────────────────────
+    ChangeEmbeddingProvider(String),
+    ChangeEmbeddingModel(String),
+    ChangeEmbeddingBatchSize(usize),
+    TestEmbeddings,
+    EmbeddingsTested(Result<usize, String>), // dimensions of the vectors
+    LoadKnowledge,
+    KnowledgeLoaded(Result<Vec<KnowledgeCollection>, String>),
+    AddKnowledge {
+        folders: bool,
+    },
+    KnowledgePicked(Option<Vec<PathBuf>>),
+    KnowledgeIndexed(Result<Indexed, String>),
+    RemoveKnowledge(String),
+    AddFsRoot,
+    FsRootsPicked(Option<Vec<PathBuf>>),
+    RemoveFsRoot(usize),
+    ChooseShellDir,
+    ShellDirPicked(Option<Vec<PathBuf>>),
+    ClearShellDir,
 }
 
 impl State {
@@ -133,21 +333,52 @@ impl State {
     /// the `saved_config` baseline from the on-disk settings file.
     pub fn new() -> Self {
         let config = Config::default();
+        let mut pricing_rows: Vec<(String, ModelPricing)> = config
+            .pricing
+            .iter()
+            .map(|(model, pricing)| (model.clone(), *pricing))
+            .collect();
+        pricing_rows.sort_by(|a, b| a.0.cmp(&b.0));
         Self {
             saved_config: config.clone(),
+            default_system_prompt: text_editor::Content::with_text(&config.default_system_prompt),
+            pricing_rows,
             config,
             auth_status: HashMap::new(),
+            mcp_status: get_tool_manager().statuses(),
+            mcp_import_status: None,
+            revealed_secrets: HashSet::new(),
+            usage_export_status: None,
+            embeddings_status: None,
+            knowledge: vec![],
+            knowledge_status: None,
+            response_times: vec![],
         }
     }
 
     /// Returns true if any LLM provider config changed between `old` and `new`.
     fn llm_configs_changed(old: &Config, new: &Config) -> bool {
-        old.openai != new.openai || old.anthropic != new.anthropic || old.vllm != new.vllm
+        old.openai != new.openai
+            || old.anthropic != new.anthropic
+            || old.local_servers != new.local_servers
+            || old.azure != new.azure
+            || old.mistral != new.mistral
+            || old.cohere != new.cohere
+            || old.bedrock != new.bedrock
+            || old.custom_providers != new.custom_providers
+            || old.proxy != new.proxy
+            || old.audit_log != new.audit_log
     }
 
-    /// Returns true if the MCP server list changed.
+    /// Returns true if the MCP server list, when the servers start, the
+    /// proxy they are reached through or the folders the built-in tools
+    /// work in changed.
     fn mcp_configs_changed(old: &Config, new: &Config) -> bool {
         old.mcp_configs != new.mcp_configs
+            || old.lazy_mcp_start != new.lazy_mcp_start
+            || old.proxy != new.proxy
+            || old.fs_roots != new.fs_roots
+            || old.shell_dir != new.shell_dir
     }
 
     /// Look up the saved (on-disk) version of the MCP config at the given index
@@ -184,6 +415,12 @@ impl State {
             SettingsAction::ChangeOpenAIUrl(endpoint) => {
                 self.config.openai.endpoint = endpoint;
             }
+            SettingsAction::ChangeOpenAIOrganization(organization) => {
+                self.config.openai.organization = organization;
+            }
+            SettingsAction::ChangeOpenAIProject(project) => {
+                self.config.openai.project = project;
+            }
             SettingsAction::ChangeAnthropicKey(api_key) => {
                 self.config.anthropic.api_key = api_key;
             }
@@ -193,11 +430,92 @@ impl State {
             SettingsAction::ChangeAnthropicMaxTokens(max_tokens) => {
                 self.config.anthropic.max_tokens = max_tokens;
             }
-            SettingsAction::ChangeVllmUrl(endpoint) => {
-                self.config.vllm.endpoint = endpoint;
+            SettingsAction::ToggleAnthropicThinking(enabled) => {
+                self.config.anthropic.thinking_budget = enabled.then_some(DEFAULT_THINKING_BUDGET);
+            }
+            SettingsAction::ChangeAnthropicThinkingBudget(budget) => {
+                self.config.anthropic.thinking_budget = Some(budget);
+            }
+            SettingsAction::ToggleAnthropicPromptCaching(enabled) => {
+                self.config.anthropic.prompt_caching = enabled;
+            }
+            SettingsAction::AddLocalServer => {
+                self.config.local_servers.push(LocalServerConfig::default());
+            }
+            SettingsAction::RemoveLocalServer(index) => {
+                if index < self.config.local_servers.len() {
+                    self.config.local_servers.remove(index);
+                }
+            }
+            SettingsAction::ChangeLocalServerName(index, name) => {
+                if let Some(server) = self.config.local_servers.get_mut(index) {
+                    server.name = name;
+                }
+            }
+            SettingsAction::ChangeLocalServerUrl(index, endpoint) => {
+                if let Some(server) = self.config.local_servers.get_mut(index) {
+                    server.endpoint = endpoint;
+                }
+            }
+            SettingsAction::ChangeLocalServerKey(index, api_key) => {
+                if let Some(server) = self.config.local_servers.get_mut(index) {
+                    server.api_key = api_key;
+                }
+            }
+            SettingsAction::ChangeLocalServerFilter(index, filter) => {
+                if let Some(server) = self.config.local_servers.get_mut(index) {
+                    server.model_filter = filter
+                        .split(',')
+                        .map(|s| s.trim().to_string())
+                        .filter(|s| !s.is_empty())
+                        .collect();
+                }
+            }
+            SettingsAction::ChangeAzureKey(key) => {
+                self.config.azure.api_key = key;
+            }
+            SettingsAction::ChangeAzureResource(resource) => {
+                self.config.azure.resource_name = resource;
+            }
+            SettingsAction::ChangeAzureDeployment(deployment) => {
+                self.config.azure.deployment_name = deployment;
+            }
+            SettingsAction::ChangeAzureApiVersion(version) => {
+                self.config.azure.api_version = version;
+            }
+            SettingsAction::ChangeMistralKey(key) => {
+                self.config.mistral.api_key = key;
+            }
+            SettingsAction::ChangeMistralUrl(endpoint) => {
+                self.config.mistral.endpoint = endpoint;
+            }
+            SettingsAction::ChangeCohereKey(key) => {
+                self.config.cohere.api_key = key;
+            }
+            SettingsAction::ChangeCohereUrl(endpoint) => {
+                self.config.cohere.endpoint = endpoint;
             }
-            SettingsAction::ChangeVllmModel(model) => {
-                self.config.vllm.model = model;
+            SettingsAction::ChangeBedrockRegion(region) => {
+                self.config.bedrock.region = region;
+            }
+            SettingsAction::ChangeBedrockAccessKey(key) => {
+                self.config.bedrock.access_key_id = key;
+            }
+            SettingsAction::ChangeBedrockSecretKey(key) => {
+                self.config.bedrock.secret_access_key = key;
+            }
+            SettingsAction::ChangeBedrockSessionToken(token) => {
+                self.config.bedrock.session_token = token;
+            }
+            SettingsAction::ChangeProxyUrl(url) => {
+                self.config.proxy.url = url;
+            }
+            SettingsAction::ChangeNoProxy(hosts) => {
+                self.config.proxy.no_proxy = hosts
+                    .split(',')
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty())
+                    .collect();
             }
             SettingsAction::AddMcpConfig => {
                 self.config.mcp_configs.push(McpConfig::default());
@@ -207,12 +525,14 @@ impl State {
                     config.set_name(name);
                 }
             }
-            SettingsAction::ChangeMcpConfigType(index, is_stdio) => {
+            SettingsAction::ChangeMcpConfigType(index, config_type) => {
                 if let Some(config) = self.config.mcp_configs.get_mut(index) {
-                    *config = if is_stdio {
-                        McpConfig::Stdio(McpStdioConfig::default())
-                    } else {
-                        McpConfig::StreamableHttp(McpStreamableHttpConfig::default())
+                    *config = match config_type {
+                        McpConfigType::Stdio => McpConfig::Stdio(McpStdioConfig::default()),
+                        McpConfigType::StreamableHttp => {
+                            McpConfig::StreamableHttp(McpStreamableHttpConfig::default())
+                        }
+                        McpConfigType::Sse => McpConfig::Sse(McpSseConfig::default()),
                     };
                 }
             }
@@ -232,11 +552,23 @@ impl State {
                         .collect();
                 }
             }
-            SettingsAction::ChangeMcpHttpEndpoint(index, endpoint) => {
-                if let Some(McpConfig::StreamableHttp(http_config)) =
-                    self.config.mcp_configs.get_mut(index)
+            SettingsAction::ChangeMcpStdioEnv(index, env_str) => {
+                if let Some(McpConfig::Stdio(stdio_config)) = self.config.mcp_configs.get_mut(index)
+                {
+                    stdio_config.env = parse_env(&env_str);
+                }
+            }
+            SettingsAction::ChangeMcpStdioCwd(index, cwd) => {
+                if let Some(McpConfig::Stdio(stdio_config)) = self.config.mcp_configs.get_mut(index)
                 {
-                    http_config.endpoint = endpoint;
+                    stdio_config.cwd = (!cwd.trim().is_empty()).then_some(cwd);
+                }
+            }
+            SettingsAction::ChangeMcpHttpEndpoint(index, endpoint) => {
+                match self.config.mcp_configs.get_mut(index) {
+                    Some(McpConfig::StreamableHttp(http_config)) => http_config.endpoint = endpoint,
+                    Some(McpConfig::Sse(sse_config)) => sse_config.endpoint = endpoint,
+                    _ => {}
                 }
             }
             SettingsAction::ChangeMcpHttpAuthType(index, auth_type) => {
@@ -257,12 +589,14 @@ impl State {
                 }
             }
             SettingsAction::ChangeMcpHttpBearerToken(index, token) => {
-                if let Some(McpConfig::StreamableHttp(http_config)) =
-                    self.config.mcp_configs.get_mut(index)
-                {
-                    if let McpAuthConfig::BearerToken { token: ref mut t } = http_config.auth {
-                        *t = token;
+                match self.config.mcp_configs.get_mut(index) {
+                    Some(McpConfig::StreamableHttp(http_config)) => {
+                        if let McpAuthConfig::BearerToken { token: ref mut t } = http_config.auth {
+                            *t = token;
+                        }
                     }
+                    Some(McpConfig::Sse(sse_config)) => sse_config.bearer_token = token,
+                    _ => {}
                 }
             }
             SettingsAction::ChangeMcpHttpOAuthScopes(index, scopes_str) => {
@@ -304,11 +638,40 @@ impl State {
                     }
                 }
             }
+            SettingsAction::ToggleMcpServer(index, enabled) => {
+                if let Some(mcp_config) = self.config.mcp_configs.get_mut(index) {
+                    mcp_config.set_enabled(enabled);
+                }
+            }
+            SettingsAction::ToggleLazyMcpStart(lazy) => {
+                self.config.lazy_mcp_start = lazy;
+            }
+            SettingsAction::ToggleMcpTool(index, tool, enabled) => {
+                if let Some(mcp_config) = self.config.mcp_configs.get_mut(index) {
+                    mcp_config.set_tool_enabled(&tool, enabled);
+                }
+            }
             SettingsAction::RemoveMcpConfig(index) => {
                 if index < self.config.mcp_configs.len() {
                     self.config.mcp_configs.remove(index);
                 }
             }
+            SettingsAction::ImportMcpConfig => {
+                return Task::perform(import_mcp_config(), SettingsAction::McpConfigImported);
+            }
+            SettingsAction::McpConfigImported(result) => {
+                self.mcp_import_status = match result {
+                    Ok(Some(configs)) => Some(self.add_imported_mcp_configs(configs)),
+                    Ok(None) => None,
+                    Err(e) => {
+                        tracing::error!("Failed to import MCP config: {}", e);
+                        Some(format!("Import failed: {}", e))
+                    }
+                };
+            }
+            SettingsAction::McpStatusChanged(statuses) => {
+                self.mcp_status = statuses;
+            }
             SettingsAction::SaveSettings => {
                 let llm_changed = Self::llm_configs_changed(&self.saved_config, &self.config);
                 let mcp_changed = Self::mcp_configs_changed(&self.saved_config, &self.config);
@@ -330,7 +693,7 @@ impl State {
                 let server_config = match self.saved_matching_http_config(index) {
                     Some(c) => c.clone(),
                     None => {
-                        log::warn!(
+                        tracing::warn!(
                             "StartOAuthAuth({}): no saved config matches the current draft; \
                              save settings first",
                             index
@@ -360,7 +723,7 @@ impl State {
                         });
                     }
                     Err(e) => {
-                        log::error!("OAuth authorization failed for '{}': {}", server_name, e);
+                        tracing::error!("OAuth authorization failed for '{}': {}", server_name, e);
                         self.auth_status
                             .insert(server_name, AuthStatus::Error(e.clone()));
                     }
@@ -389,12 +752,45 @@ impl State {
                         });
                     }
                     Err(e) => {
-                        log::error!("Clearing OAuth tokens for '{}' failed: {}", server_name, e);
+                        tracing::error!("Clearing OAuth tokens for '{}' failed: {}", server_name, e);
                         self.auth_status
                             .insert(server_name, AuthStatus::Error(e.clone()));
                     }
                 }
             }
+            SettingsAction::ToggleSecretVisibility(field) => {
+                if !self.revealed_secrets.remove(&field) {
+                    self.revealed_secrets.insert(field);
+                }
+            }
+            SettingsAction::PasteSecret(field) => {
+                return iced::clipboard::read()
+                    .map(move |contents| SettingsAction::SecretPasted(field, contents));
+            }
+            SettingsAction::SecretPasted(field, contents) => {
+                let Some(value) = contents.map(|c| c.trim().to_string()) else {
+                    return Task::none();
+                };
+                return self.update(match field {
+                    SecretField::OpenAIKey => SettingsAction::ChangeOpenAIKey(value),
+                    SecretField::AnthropicKey => SettingsAction::ChangeAnthropicKey(value),
+                    SecretField::AzureKey => SettingsAction::ChangeAzureKey(value),
+                    SecretField::MistralKey => SettingsAction::ChangeMistralKey(value),
+                    SecretField::CohereKey => SettingsAction::ChangeCohereKey(value),
+                    SecretField::BedrockSecretKey => SettingsAction::ChangeBedrockSecretKey(value),
+                    SecretField::BedrockSessionToken => {
+                        SettingsAction::ChangeBedrockSessionToken(value)
+                    }
+                    SecretField::ProxyUrl => SettingsAction::ChangeProxyUrl(value),
+                    SecretField::LocalServerKey(index) => {
+                        SettingsAction::ChangeLocalServerKey(index, value)
+                    }
+                    SecretField::McpBearerToken(index) => {
+                        SettingsAction::ChangeMcpHttpBearerToken(index, value)
+                    }
+                    SecretField::McpEnv(index) => SettingsAction::ChangeMcpStdioEnv(index, value),
+                });
+            }
             SettingsAction::AddAcpAgent => {
                 self.config.acp_agents.push(AcpAgentConfig::default());
             }
@@ -433,38 +829,303 @@ impl State {
             }
             SettingsAction::ChangeAcpAgentEnv(index, env_str) => {
                 if let Some(AcpAgentConfig::Stdio(cfg)) = self.config.acp_agents.get_mut(index) {
-                    // Format: "KEY=value, KEY2=value2"
-                    cfg.env = env_str
-                        .split(',')
-                        .filter_map(|kv| {
-                            let kv = kv.trim();
-                            if kv.is_empty() {
-                                return None;
-                            }
-                            let mut parts = kv.splitn(2, '=');
-                            let k = parts.next()?.trim().to_string();
-                            let v = parts.next().unwrap_or("").trim().to_string();
-                            if k.is_empty() {
-                                None
-                            } else {
-                                Some((k, v))
-                            }
-                        })
-                        .collect();
+                    cfg.env = parse_env(&env_str);
+                }
+            }
+            SettingsAction::AddTemplate => {
+                self.config.templates.push(ConversationTemplate {
+                    name: format!("template-{}", self.config.templates.len() + 1),
+                    messages: vec![TemplateMessage {
+                        role: "system".to_string(),
+                        content: String::new(),
+                    }],
+                });
+            }
+            SettingsAction::RemoveTemplate(index) => {
+                if index < self.config.templates.len() {
+                    self.config.templates.remove(index);
+                }
+            }
+            SettingsAction::ChangeTemplateName(index, name) => {
+                if let Some(template) = self.config.templates.get_mut(index) {
+                    template.name = name;
+                }
+            }
+            SettingsAction::AddTemplateMessage(index) => {
+                if let Some(template) = self.config.templates.get_mut(index) {
+                    template.messages.push(TemplateMessage::default());
+                }
+            }
+            SettingsAction::RemoveTemplateMessage(index, message_index) => {
+                if let Some(template) = self.config.templates.get_mut(index) {
+                    if message_index < template.messages.len() {
+                        template.messages.remove(message_index);
+                    }
+                }
+            }
+            SettingsAction::ChangeTemplateMessageRole(index, message_index, role) => {
+                if let Some(message) = self
+                    .config
+                    .templates
+                    .get_mut(index)
+                    .and_then(|t| t.messages.get_mut(message_index))
+                {
+                    message.role = role.to_string();
+                }
+            }
+            SettingsAction::ChangeTemplateMessageContent(index, message_index, content) => {
+                if let Some(message) = self
+                    .config
+                    .templates
+                    .get_mut(index)
+                    .and_then(|t| t.messages.get_mut(message_index))
+                {
+                    message.content = content;
+                }
+            }
+            SettingsAction::ChangeToolPolicy(tool_name, policy) => {
+                // `Ask` is the default; only store overrides.
+                if policy == ToolPolicy::default() {
+                    self.config.tool_policies.remove(&tool_name);
+                } else {
+                    self.config.tool_policies.insert(tool_name, policy);
+                }
+            }
+            SettingsAction::ChangeMaxToolIterations(max) => {
+                self.config.max_tool_iterations = max;
+            }
+            SettingsAction::ChangeTitleModel(name) => {
+                self.config.title_model = (!name.trim().is_empty()).then_some(name);
+            }
+            SettingsAction::ChangeSummarizeAfter(count) => {
+                self.config.summarize_after = count;
+            }
+            SettingsAction::ChangeSummaryModel(name) => {
+                self.config.summary_model = (!name.trim().is_empty()).then_some(name);
+            }
+            SettingsAction::ChangeQuickChatHotkey(binding) => {
+                self.config.quick_chat_hotkey = binding;
+            }
+            SettingsAction::ChangeFallbackModels(names) => {
+                self.config.fallback_models = names
+                    .split(',')
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty())
+                    .collect();
+            }
+            SettingsAction::EditDefaultSystemPrompt(action) => {
+                self.default_system_prompt.perform(action);
+                self.config.default_system_prompt = self.default_system_prompt.text();
+            }
+            SettingsAction::AddPricing => {
+                self.pricing_rows
+                    .push((String::new(), ModelPricing::default()));
+            }
+            SettingsAction::RemovePricing(index) => {
+                if index < self.pricing_rows.len() {
+                    self.pricing_rows.remove(index);
+                    self.sync_pricing();
+                }
+            }
+            SettingsAction::ChangePricingModel(index, model) => {
+                if let Some(row) = self.pricing_rows.get_mut(index) {
+                    row.0 = model;
+                    self.sync_pricing();
+                }
+            }
+            SettingsAction::ChangePricingPrompt(index, price) => {
+                if let Some(row) = self.pricing_rows.get_mut(index) {
+                    row.1.prompt = price;
+                    self.sync_pricing();
+                }
+            }
+            SettingsAction::ChangePricingCompletion(index, price) => {
+                if let Some(row) = self.pricing_rows.get_mut(index) {
+                    row.1.completion = price;
+                    self.sync_pricing();
+                }
+            }
+            SettingsAction::ChangeMonthlyBudget(limit) => {
+                self.config.budget.monthly_limit = limit;
+            }
+            SettingsAction::ToggleBlockOverBudget(block) => {
+                self.config.budget.block_when_exceeded = block;
+            }
+            SettingsAction::ToggleAuditLog(enabled) => {
+                self.config.audit_log = enabled;
+            }
+            SettingsAction::ChangeEmbeddingProvider(provider) => {
+                self.config.embeddings.provider = provider;
+            }
+            SettingsAction::ChangeEmbeddingModel(model) => {
+                self.config.embeddings.model = model;
+            }
+            SettingsAction::ChangeEmbeddingBatchSize(size) => {
+                self.config.embeddings.batch_size = size;
+            }
+            SettingsAction::TestEmbeddings => {
+                self.embeddings_status = Some("Testing…".to_string());
+                return Task::perform(
+                    test_embeddings(self.config.clone()),
+                    SettingsAction::EmbeddingsTested,
+                );
+            }
+            SettingsAction::EmbeddingsTested(result) => {
+                self.embeddings_status = Some(match result {
+                    Ok(dimensions) => format!("Returned {}-dimensional vectors", dimensions),
+                    Err(e) => {
+                        tracing::error!("Embeddings test failed: {}", e);
+                        format!("Test failed: {}", e)
+                    }
+                });
+            }
+            SettingsAction::LoadKnowledge => {
+                return Task::perform(load_knowledge(), SettingsAction::KnowledgeLoaded);
+            }
+            SettingsAction::KnowledgeLoaded(result) => match result {
+                Ok(collections) => self.knowledge = collections,
+                Err(e) => tracing::error!("Failed to load the knowledge base: {}", e),
+            },
+            SettingsAction::AddKnowledge { folders } => {
+                return Task::perform(pick_paths(folders), SettingsAction::KnowledgePicked);
+            }
+            SettingsAction::KnowledgePicked(Some(paths)) => {
+                self.knowledge_status = Some("Indexing…".to_string());
+                let config = self.config.clone();
+                return Task::perform(
+                    async move {
+                        crate::knowledge::index(config, paths)
+                            .await
+                            .map_err(|e| e.to_string())
+                    },
+                    SettingsAction::KnowledgeIndexed,
+                );
+            }
+            SettingsAction::KnowledgePicked(None) => {}
+            SettingsAction::KnowledgeIndexed(result) => {
+                self.knowledge_status = Some(match result {
+                    Ok(indexed) if indexed.skipped.is_empty() => {
+                        format!("Indexed {} passages", indexed.passages)
+                    }
+                    Ok(indexed) => format!(
+                        "Indexed {} passages; skipped {}",
+                        indexed.passages,
+                        indexed.skipped.join("; ")
+                    ),
+                    Err(e) => {
+                        tracing::error!("Failed to index: {}", e);
+                        format!("Indexing failed: {}", e)
+                    }
+                });
+                return Task::perform(load_knowledge(), SettingsAction::KnowledgeLoaded);
+            }
+            SettingsAction::RemoveKnowledge(path) => {
+                return Task::perform(
+                    async move {
+                        get_storage()
+                            .and_then(|storage| storage.remove_knowledge(&path))
+                            .map_err(|e| e.to_string())?;
+                        load_knowledge().await
+                    },
+                    SettingsAction::KnowledgeLoaded,
+                );
+            }
+            SettingsAction::AddFsRoot => {
+                return Task::perform(pick_paths(true), SettingsAction::FsRootsPicked);
+            }
+            SettingsAction::FsRootsPicked(Some(paths)) => {
+                for path in paths {
+                    let path = path.display().to_string();
+                    if !self.config.fs_roots.contains(&path) {
+                        self.config.fs_roots.push(path);
+                    }
+                }
+            }
+            SettingsAction::FsRootsPicked(None) => {}
+            SettingsAction::RemoveFsRoot(index) => {
+                if index < self.config.fs_roots.len() {
+                    self.config.fs_roots.remove(index);
+                }
+            }
+            SettingsAction::ChooseShellDir => {
+                return Task::perform(pick_paths(true), SettingsAction::ShellDirPicked);
+            }
+            SettingsAction::ShellDirPicked(paths) => {
+                if let Some(path) = paths.and_then(|paths| paths.into_iter().next()) {
+                    self.config.shell_dir = Some(path.display().to_string());
                 }
             }
+            SettingsAction::ClearShellDir => self.config.shell_dir = None,
+            SettingsAction::ExportUsage => {
+                return Task::perform(
+                    export_usage(self.config.pricing.clone()),
+                    SettingsAction::UsageExported,
+                );
+            }
+            SettingsAction::UsageExported(result) => {
+                self.usage_export_status = match result {
+                    Ok(Some(path)) => Some(format!("Exported to {}", path.display())),
+                    Ok(None) => None,
+                    Err(e) => {
+                        tracing::error!("Failed to export usage: {}", e);
+                        Some(format!("Export failed: {}", e))
+                    }
+                };
+            }
+            SettingsAction::LoadResponseTimes => {
+                return Task::perform(load_timings(), SettingsAction::ResponseTimesLoaded);
+            }
+            SettingsAction::ResponseTimesLoaded(result) => match result {
+                Ok(records) => self.response_times = timings_by_model(&records),
+                Err(e) => tracing::error!("Failed to load response times: {}", e),
+            },
         }
         Task::none()
     }
 
+    /// Follow the MCP servers' statuses.
+    pub fn subscription() -> Subscription<SettingsAction> {
+        Subscription::run(mcp_status_updates)
+    }
+
+    /// Rebuild `config.pricing` from the table rows. Later rows win when a
+    /// model is listed twice.
+    fn sync_pricing(&mut self) {
+        self.config.pricing = self
+            .pricing_rows
+            .iter()
+            .filter(|(model, _)| !model.trim().is_empty())
+            .map(|(model, pricing)| (model.trim().to_string(), *pricing))
+            .collect();
+    }
+
     pub fn view(&self) -> Element<'_, SettingsAction> {
         let col = column![
             self.theme_view(),
             self.openai_view(),
             self.anthropic_view(),
-            self.vllm_view(),
+            self.local_servers_view(),
+            self.azure_view(),
+            self.mistral_view(),
+            self.cohere_view(),
+            self.bedrock_view(),
+            self.proxy_view(),
             self.mcp_configs_view(),
             self.acp_agents_view(),
+            self.templates_view(),
+            self.title_model_view(),
+            self.summary_view(),
+            self.quick_chat_hotkey_view(),
+            self.fallback_models_view(),
+            self.default_system_prompt_view(),
+            self.pricing_view(),
+            self.budget_view(),
+            self.response_times_view(),
+            self.audit_log_view(),
+            self.embeddings_view(),
+            self.knowledge_view(),
+            self.fs_roots_view(),
+            self.tool_policies_view(),
             button("Save Settings").on_press(SettingsAction::SaveSettings)
         ]
         .spacing(20)
@@ -490,41 +1151,239 @@ impl State {
     fn openai_view(&self) -> iced::widget::Row<'_, SettingsAction> {
         row![
             text("OpenAI API Key:"),
-            text_input("Enter API Key", &self.config.openai.api_key)
-                .on_input(SettingsAction::ChangeOpenAIKey),
+            self.secret_input(
+                SecretField::OpenAIKey,
+                "Enter API Key",
+                &self.config.openai.api_key,
+                SettingsAction::ChangeOpenAIKey,
+            ),
             text("Endpoint:"),
             text_input("Enter Endpoint", &self.config.openai.endpoint)
                 .on_input(SettingsAction::ChangeOpenAIUrl),
+            text("Organization:"),
+            text_input("org-... (optional)", &self.config.openai.organization)
+                .on_input(SettingsAction::ChangeOpenAIOrganization),
+            text("Project:"),
+            text_input("proj_... (optional)", &self.config.openai.project)
+                .on_input(SettingsAction::ChangeOpenAIProject),
         ]
         .spacing(10)
         .align_y(Alignment::Center)
     }
 
     fn anthropic_view(&self) -> iced::widget::Row<'_, SettingsAction> {
-        row![
+        let thinking = checkbox(self.config.anthropic.thinking_budget.is_some())
+            .label("Thinking")
+            .on_toggle(SettingsAction::ToggleAnthropicThinking);
+        let caching = checkbox(self.config.anthropic.prompt_caching)
+            .label("Prompt caching")
+            .on_toggle(SettingsAction::ToggleAnthropicPromptCaching);
+        let row = row![
             text("Anthropic API Key:"),
-            text_input("Enter API Key", &self.config.anthropic.api_key)
-                .on_input(SettingsAction::ChangeAnthropicKey),
+            self.secret_input(
+                SecretField::AnthropicKey,
+                "Enter API Key",
+                &self.config.anthropic.api_key,
+                SettingsAction::ChangeAnthropicKey,
+            ),
             text("Endpoint:"),
             text_input("Enter Endpoint", &self.config.anthropic.endpoint)
                 .on_input(SettingsAction::ChangeAnthropicUrl),
             text("Max Tokens:"),
             number_input(&self.config.anthropic.max_tokens, 1..=4096, |value| {
                 SettingsAction::ChangeAnthropicMaxTokens(value)
-            })
+            }),
+            caching,
+            thinking,
+        ]
+        .spacing(10)
+        .align_y(Alignment::Center);
+        match &self.config.anthropic.thinking_budget {
+            Some(budget) => row.push(
+                number_input(
+                    budget,
+                    1024..=32_000,
+                    SettingsAction::ChangeAnthropicThinkingBudget,
+                )
+                .step(1024),
+            ),
+            None => row,
+        }
+    }
+
+    /// A masked text input for `field` followed by a show/hide toggle and a
+    /// "paste from clipboard" button.
+    fn secret_input<'a>(
+        &self,
+        field: SecretField,
+        placeholder: &str,
+        value: &str,
+        on_input: impl Fn(String) -> SettingsAction + 'a,
+    ) -> iced::widget::Row<'a, SettingsAction> {
+        let revealed = self.revealed_secrets.contains(&field);
+        let toggle_icon = if revealed {
+            iced_fonts::lucide::eye_off()
+        } else {
+            iced_fonts::lucide::eye()
+        };
+        row![
+            text_input(placeholder, value)
+                .secure(!revealed)
+                .on_input(on_input),
+            button(toggle_icon).on_press(SettingsAction::ToggleSecretVisibility(field)),
+            button(iced_fonts::lucide::clipboard_paste())
+                .on_press(SettingsAction::PasteSecret(field)),
+        ]
+        .spacing(5)
+        .align_y(Alignment::Center)
+    }
+
+    /// Render the OpenAI-compatible servers section. Each server has a name,
+    /// endpoint, optional API key and a comma-separated model filter.
+    fn local_servers_view(&self) -> iced::widget::Column<'_, SettingsAction> {
+        let mut column = column![text("Local Servers (OpenAI-compatible):").size(18)];
+
+        for (index, server) in self.config.local_servers.iter().enumerate() {
+            let filter_str = server.model_filter.join(", ");
+            let header = row![
+                text_input("Name", &server.name)
+                    .on_input(move |name| SettingsAction::ChangeLocalServerName(index, name)),
+                button(iced_fonts::lucide::trash())
+                    .on_press(SettingsAction::RemoveLocalServer(index))
+            ]
+            .spacing(10)
+            .align_y(Alignment::Center);
+
+            let endpoint_row = row![
+                text("Endpoint:"),
+                text_input("http://localhost:8000/v1/", &server.endpoint)
+                    .on_input(move |e| SettingsAction::ChangeLocalServerUrl(index, e)),
+                text("API Key:"),
+                self.secret_input(
+                    SecretField::LocalServerKey(index),
+                    "(optional)",
+                    &server.api_key,
+                    move |k| SettingsAction::ChangeLocalServerKey(index, k),
+                ),
+            ]
+            .spacing(10)
+            .align_y(Alignment::Center);
+
+            let filter_row = row![
+                text("Models:"),
+                text_input("(all) or comma,separated,filters", &filter_str)
+                    .on_input(move |f| SettingsAction::ChangeLocalServerFilter(index, f)),
+            ]
+            .spacing(10)
+            .align_y(Alignment::Center);
+
+            column = column.push(column![header, endpoint_row, filter_row].spacing(5));
+        }
+
+        column
+            .push(button(iced_fonts::lucide::plus()).on_press(SettingsAction::AddLocalServer))
+            .spacing(10)
+            .align_x(Alignment::Center)
+    }
+
+    fn azure_view(&self) -> iced::widget::Row<'_, SettingsAction> {
+        let azure = &self.config.azure;
+        row![
+            text("Azure OpenAI Key:"),
+            self.secret_input(
+                SecretField::AzureKey,
+                "Enter API Key",
+                &azure.api_key,
+                SettingsAction::ChangeAzureKey,
+            ),
+            text("Resource:"),
+            text_input("Resource name", &azure.resource_name)
+                .on_input(SettingsAction::ChangeAzureResource),
+            text("Deployment:"),
+            text_input("Deployment name", &azure.deployment_name)
+                .on_input(SettingsAction::ChangeAzureDeployment),
+            text("API Version:"),
+            text_input("API version", &azure.api_version)
+                .on_input(SettingsAction::ChangeAzureApiVersion),
+        ]
+        .spacing(10)
+        .align_y(Alignment::Center)
+    }
+
+    fn proxy_view(&self) -> iced::widget::Row<'_, SettingsAction> {
+        let no_proxy_str = self.config.proxy.no_proxy.join(", ");
+        row![
+            text("Proxy:"),
+            self.secret_input(
+                SecretField::ProxyUrl,
+                "http://host:port (system default)",
+                &self.config.proxy.url,
+                SettingsAction::ChangeProxyUrl,
+            ),
+            text("Bypass for:"),
+            text_input("comma,separated,hosts", &no_proxy_str)
+                .on_input(SettingsAction::ChangeNoProxy),
+        ]
+        .spacing(10)
+        .align_y(Alignment::Center)
+    }
+
+    fn mistral_view(&self) -> iced::widget::Row<'_, SettingsAction> {
+        row![
+            text("Mistral API Key:"),
+            self.secret_input(
+                SecretField::MistralKey,
+                "Enter API Key",
+                &self.config.mistral.api_key,
+                SettingsAction::ChangeMistralKey,
+            ),
+            text("Endpoint:"),
+            text_input("Enter Endpoint", &self.config.mistral.endpoint)
+                .on_input(SettingsAction::ChangeMistralUrl),
+        ]
+        .spacing(10)
+        .align_y(Alignment::Center)
+    }
+
+    fn cohere_view(&self) -> iced::widget::Row<'_, SettingsAction> {
+        row![
+            text("Cohere API Key:"),
+            self.secret_input(
+                SecretField::CohereKey,
+                "Enter API Key",
+                &self.config.cohere.api_key,
+                SettingsAction::ChangeCohereKey,
+            ),
+            text("Endpoint:"),
+            text_input("Enter Endpoint", &self.config.cohere.endpoint)
+                .on_input(SettingsAction::ChangeCohereUrl),
         ]
         .spacing(10)
         .align_y(Alignment::Center)
     }
 
-    fn vllm_view(&self) -> iced::widget::Row<'_, SettingsAction> {
+    fn bedrock_view(&self) -> iced::widget::Row<'_, SettingsAction> {
+        let bedrock = &self.config.bedrock;
         row![
-            text("vLLM Endpoint:"),
-            text_input("Enter Endpoint", &self.config.vllm.endpoint)
-                .on_input(SettingsAction::ChangeVllmUrl),
-            text("Model:"),
-            text_input("Enter Model", &self.config.vllm.model)
-                .on_input(SettingsAction::ChangeVllmModel),
+            text("Bedrock Region:"),
+            text_input("us-east-1", &bedrock.region).on_input(SettingsAction::ChangeBedrockRegion),
+            text("Access Key ID:"),
+            text_input("Enter Access Key ID", &bedrock.access_key_id)
+                .on_input(SettingsAction::ChangeBedrockAccessKey),
+            text("Secret Key:"),
+            self.secret_input(
+                SecretField::BedrockSecretKey,
+                "Enter Secret Access Key",
+                &bedrock.secret_access_key,
+                SettingsAction::ChangeBedrockSecretKey,
+            ),
+            text("Session Token:"),
+            self.secret_input(
+                SecretField::BedrockSessionToken,
+                "Optional",
+                &bedrock.session_token,
+                SettingsAction::ChangeBedrockSessionToken,
+            ),
         ]
         .spacing(10)
         .align_y(Alignment::Center)
@@ -537,32 +1396,49 @@ impl State {
             let config_type = match mcp_config {
                 McpConfig::Stdio(_) => McpConfigType::Stdio,
                 McpConfig::StreamableHttp(_) => McpConfigType::StreamableHttp,
+                McpConfig::Sse(_) => McpConfigType::Sse,
             };
 
             let type_picker = pick_list(
                 &McpConfigType::ALL[..],
                 Some(config_type),
-                move |selected_type| {
-                    SettingsAction::ChangeMcpConfigType(
-                        index,
-                        matches!(selected_type, McpConfigType::Stdio),
-                    )
-                },
+                move |selected_type| SettingsAction::ChangeMcpConfigType(index, selected_type),
             );
 
             let config_fields = match mcp_config {
                 McpConfig::Stdio(stdio_config) => {
                     let args_str = stdio_config.args.join(", ");
-                    column![row![
-                        text("Command:"),
-                        text_input("Enter command", &stdio_config.command)
-                            .on_input(move |cmd| SettingsAction::ChangeMcpStdioCommand(index, cmd)),
-                        text("Args:"),
-                        text_input("comma,separated,args", &args_str)
-                            .on_input(move |args| SettingsAction::ChangeMcpStdioArgs(index, args)),
+                    let env_str = format_env(&stdio_config.env);
+                    let cwd = stdio_config.cwd.as_deref().unwrap_or_default();
+                    column![
+                        row![
+                            text("Command:"),
+                            text_input("Enter command", &stdio_config.command).on_input(
+                                move |cmd| SettingsAction::ChangeMcpStdioCommand(index, cmd)
+                            ),
+                            text("Args:"),
+                            text_input("comma,separated,args", &args_str).on_input(move |args| {
+                                SettingsAction::ChangeMcpStdioArgs(index, args)
+                            }),
+                        ]
+                        .spacing(10)
+                        .align_y(Alignment::Center),
+                        row![
+                            text("Working dir:"),
+                            text_input("(optional) /path/to/dir", cwd).on_input(move |cwd| {
+                                SettingsAction::ChangeMcpStdioCwd(index, cwd)
+                            }),
+                            text("Env:"),
+                            self.secret_input(
+                                SecretField::McpEnv(index),
+                                "KEY=value, KEY2=value2",
+                                &env_str,
+                                move |env| SettingsAction::ChangeMcpStdioEnv(index, env),
+                            ),
+                        ]
+                        .spacing(10)
+                        .align_y(Alignment::Center),
                     ]
-                    .spacing(10)
-                    .align_y(Alignment::Center)]
                     .spacing(5)
                 }
                 McpConfig::StreamableHttp(http_config) => {
@@ -597,9 +1473,12 @@ impl State {
                             col = col.push(
                                 row![
                                     text("Token:"),
-                                    text_input("Enter bearer token", token).on_input(move |t| {
-                                        SettingsAction::ChangeMcpHttpBearerToken(index, t)
-                                    }),
+                                    self.secret_input(
+                                        SecretField::McpBearerToken(index),
+                                        "Enter bearer token",
+                                        token,
+                                        move |t| SettingsAction::ChangeMcpHttpBearerToken(index, t),
+                                    ),
                                 ]
                                 .spacing(10)
                                 .align_y(Alignment::Center),
@@ -640,35 +1519,132 @@ impl State {
 
                     col
                 }
+                McpConfig::Sse(sse_config) => column![row![
+                    text("Endpoint:"),
+                    text_input("Enter SSE endpoint URL", &sse_config.endpoint).on_input(
+                        move |endpoint| SettingsAction::ChangeMcpHttpEndpoint(index, endpoint)
+                    ),
+                    text("Token:"),
+                    self.secret_input(
+                        SecretField::McpBearerToken(index),
+                        "(optional) bearer token",
+                        &sse_config.bearer_token,
+                        move |t| SettingsAction::ChangeMcpHttpBearerToken(index, t),
+                    ),
+                ]
+                .spacing(10)
+                .align_y(Alignment::Center)],
             };
 
-            column = column.push(
-                column![
-                    row![
-                        text_input("Name", mcp_config.name()).on_input(move |name| {
-                            SettingsAction::ChangeMcpConfigName(index, name)
-                        }),
-                        type_picker,
-                        button(iced_fonts::lucide::trash())
-                            .on_press(SettingsAction::RemoveMcpConfig(index))
-                    ]
-                    .spacing(10)
-                    .align_y(Alignment::Center),
-                    config_fields,
-                ]
-                .spacing(5),
+            let mut name_row = row![
+                checkbox(mcp_config.enabled())
+                    .on_toggle(move |enabled| SettingsAction::ToggleMcpServer(index, enabled)),
+                text_input("Name", mcp_config.name())
+                    .on_input(move |name| SettingsAction::ChangeMcpConfigName(index, name))
+            ]
+            .spacing(10)
+            .align_y(Alignment::Center);
+            if let Some(status) = self.mcp_status.get(mcp_config.name()) {
+                name_row = name_row.push(text(status.to_string()).style(status_style(status)));
+            }
+            let name_row = name_row.push(type_picker).push(
+                button(iced_fonts::lucide::trash())
+                    .on_press(SettingsAction::RemoveMcpConfig(index)),
             );
+            let mut server = column![name_row, config_fields].spacing(5);
+            if let Some(tools) = self.mcp_tools_view(index, mcp_config) {
+                server = server.push(tools);
+            }
+            column = column.push(server);
         }
 
-        column
-            .push(button(iced_fonts::lucide::plus()).on_press(SettingsAction::AddMcpConfig))
+        let column = column
+            .push(
+                row![
+                    button(iced_fonts::lucide::plus()).on_press(SettingsAction::AddMcpConfig),
+                    button("Import MCP config…").on_press(SettingsAction::ImportMcpConfig),
+                ]
+                .spacing(10)
+                .align_y(Alignment::Center),
+            )
+            .push(
+                checkbox(self.config.lazy_mcp_start)
+                    .label("Start servers when a request can first use their tools")
+                    .on_toggle(SettingsAction::ToggleLazyMcpStart),
+            )
             .spacing(10)
-            .align_x(Alignment::Center)
+            .align_x(Alignment::Center);
+        match &self.mcp_import_status {
+            Some(status) => column.push(text(status)),
+            None => column,
+        }
     }
 
-    /// Build the "Authenticate / Clear tokens / status" row for an OAuth2 MCP config.
-    ///
-    /// Buttons are only enabled when:
+    /// One checkbox per tool of the server at `index`: those it offers while
+    /// connected plus any already turned off. `None` if there are none.
+    fn mcp_tools_view<'a>(
+        &self,
+        index: usize,
+        mcp_config: &'a McpConfig,
+    ) -> Option<Element<'a, SettingsAction>> {
+        let mut names = get_tool_manager()
+            .server_tool_names(mcp_config.name())
+            .unwrap_or_default();
+        names.extend(mcp_config.disabled_tools().iter().cloned());
+        names.sort();
+        names.dedup();
+        if names.is_empty() {
+            return None;
+        }
+        let tools = names.into_iter().map(|name| {
+            checkbox(mcp_config.tool_enabled(&name))
+                .label(name.clone())
+                .on_toggle(move |enabled| {
+                    SettingsAction::ToggleMcpTool(index, name.clone(), enabled)
+                })
+                .into()
+        });
+        Some(
+            row![text("Tools:")]
+                .extend(tools)
+                .spacing(10)
+                .align_y(Alignment::Center)
+                .wrap()
+                .into(),
+        )
+    }
+
+    /// Add imported MCP servers, skipping any whose name is already taken,
+    /// and describe the result. They take effect once settings are saved.
+    fn add_imported_mcp_configs(&mut self, configs: Vec<McpConfig>) -> String {
+        let mut added = 0;
+        let mut skipped = Vec::new();
+        for config in configs {
+            if self
+                .config
+                .mcp_configs
+                .iter()
+                .any(|existing| existing.name() == config.name())
+            {
+                skipped.push(config.name().to_string());
+            } else {
+                self.config.mcp_configs.push(config);
+                added += 1;
+            }
+        }
+        let mut status = match added {
+            1 => "Imported 1 server.".to_string(),
+            added => format!("Imported {added} servers."),
+        };
+        if !skipped.is_empty() {
+            status.push_str(&format!(" Skipped existing: {}.", skipped.join(", ")));
+        }
+        status
+    }
+
+    /// Build the "Authenticate / Clear tokens / status" row for an OAuth2 MCP config.
+    ///
+    /// Buttons are only enabled when:
     ///   - the row has a non-empty name, AND
     ///   - the draft config matches the saved (on-disk) config exactly.
     ///
@@ -736,12 +1712,7 @@ impl State {
         for (index, agent) in self.config.acp_agents.iter().enumerate() {
             let AcpAgentConfig::Stdio(cfg) = agent;
             let args_str = cfg.args.join(", ");
-            let env_str = cfg
-                .env
-                .iter()
-                .map(|(k, v)| format!("{}={}", k, v))
-                .collect::<Vec<_>>()
-                .join(", ");
+            let env_str = format_env(&cfg.env);
             let workspace_root = cfg.workspace_root.clone().unwrap_or_default();
 
             let header = row![
@@ -784,13 +1755,542 @@ impl State {
             .spacing(10)
             .align_x(Alignment::Center)
     }
+
+    /// Render the conversation templates section. Each template has a name
+    /// and an ordered list of seeded messages; message bodies may contain
+    /// `{{variable}}` placeholders that are filled in when a chat is started.
+    fn templates_view(&self) -> iced::widget::Column<'_, SettingsAction> {
+        let mut column = column![text("Conversation Templates:").size(18)];
+
+        for (index, template) in self.config.templates.iter().enumerate() {
+            let header = row![
+                text_input("Name", &template.name)
+                    .on_input(move |name| SettingsAction::ChangeTemplateName(index, name)),
+                button(iced_fonts::lucide::trash()).on_press(SettingsAction::RemoveTemplate(index))
+            ]
+            .spacing(10)
+            .align_y(Alignment::Center);
+
+            let mut messages = column![].spacing(5);
+            for (message_index, message) in template.messages.iter().enumerate() {
+                let role = TEMPLATE_ROLES.iter().copied().find(|r| *r == message.role);
+                messages = messages.push(
+                    row![
+                        pick_list(&TEMPLATE_ROLES[..], role, move |r| {
+                            SettingsAction::ChangeTemplateMessageRole(index, message_index, r)
+                        }),
+                        text_input("Message, e.g. Summarize {{topic}}", &message.content).on_input(
+                            move |c| SettingsAction::ChangeTemplateMessageContent(
+                                index,
+                                message_index,
+                                c
+                            )
+                        ),
+                        button(iced_fonts::lucide::trash())
+                            .on_press(SettingsAction::RemoveTemplateMessage(index, message_index)),
+                    ]
+                    .spacing(10)
+                    .align_y(Alignment::Center),
+                );
+            }
+            let add_message =
+                button(text("Add message")).on_press(SettingsAction::AddTemplateMessage(index));
+
+            column = column.push(column![header, messages, add_message].spacing(5));
+        }
+
+        column
+            .push(button(iced_fonts::lucide::plus()).on_press(SettingsAction::AddTemplate))
+            .spacing(10)
+            .align_x(Alignment::Center)
+    }
+
+    fn title_model_view(&self) -> iced::widget::Row<'_, SettingsAction> {
+        row![
+            text("Conversation title model:"),
+            text_input(
+                "Same as the conversation",
+                self.config.title_model.as_deref().unwrap_or_default(),
+            )
+            .on_input(SettingsAction::ChangeTitleModel),
+        ]
+        .spacing(10)
+        .align_y(Alignment::Center)
+    }
+
+    fn summary_view(&self) -> iced::widget::Row<'_, SettingsAction> {
+        row![
+            text("Summarize conversations after (messages, 0 for never):"),
+            number_input(&self.config.summarize_after, 0..=1000, |value| {
+                SettingsAction::ChangeSummarizeAfter(value)
+            }),
+            text("with model:"),
+            text_input(
+                "Same as the conversation",
+                self.config.summary_model.as_deref().unwrap_or_default(),
+            )
+            .on_input(SettingsAction::ChangeSummaryModel),
+        ]
+        .spacing(10)
+        .align_y(Alignment::Center)
+    }
+
+    fn quick_chat_hotkey_view(&self) -> iced::widget::Row<'_, SettingsAction> {
+        let binding = self.config.quick_chat_hotkey.trim();
+        let mut row = row![
+            text("Quick chat hotkey:"),
+            text_input(
+                "None, or e.g. Alt+Shift+Space",
+                &self.config.quick_chat_hotkey
+            )
+            .on_input(SettingsAction::ChangeQuickChatHotkey),
+        ];
+        if !binding.is_empty() && hotkey::parse(binding).is_err() {
+            row = row.push(text("Not a valid hotkey").style(text::danger));
+        }
+        row.spacing(10).align_y(Alignment::Center)
+    }
+
+    fn fallback_models_view(&self) -> iced::widget::Row<'_, SettingsAction> {
+        let models_str = self.config.fallback_models.join(", ");
+        row![
+            text("Fallback models:"),
+            text_input("None, or comma,separated,model names", &models_str)
+                .on_input(SettingsAction::ChangeFallbackModels),
+        ]
+        .spacing(10)
+        .align_y(Alignment::Center)
+    }
+
+    fn default_system_prompt_view(&self) -> iced::widget::Column<'_, SettingsAction> {
+        column![
+            text("Default system prompt for new conversations:"),
+            text_editor(&self.default_system_prompt)
+                .placeholder("None")
+                .on_action(SettingsAction::EditDefaultSystemPrompt)
+                .height(120),
+        ]
+        .spacing(10)
+    }
+
+    /// Render the pricing table used to estimate conversation costs. Model
+    /// names must match the names shown in the chat's model picker.
+    fn pricing_view(&self) -> iced::widget::Column<'_, SettingsAction> {
+        let mut column = column![text("Model Pricing (USD per million tokens):").size(18)];
+        for (index, (model, pricing)) in self.pricing_rows.iter().enumerate() {
+            column = column.push(
+                row![
+                    text_input("Model name", model)
+                        .on_input(move |model| SettingsAction::ChangePricingModel(index, model)),
+                    text("Prompt:"),
+                    number_input(&pricing.prompt, 0.0..=1000.0, move |price| {
+                        SettingsAction::ChangePricingPrompt(index, price)
+                    })
+                    .step(0.05),
+                    text("Completion:"),
+                    number_input(&pricing.completion, 0.0..=1000.0, move |price| {
+                        SettingsAction::ChangePricingCompletion(index, price)
+                    })
+                    .step(0.05),
+                    button(iced_fonts::lucide::trash())
+                        .on_press(SettingsAction::RemovePricing(index)),
+                ]
+                .spacing(10)
+                .align_y(Alignment::Center),
+            );
+        }
+        column
+            .push(button(iced_fonts::lucide::plus()).on_press(SettingsAction::AddPricing))
+            .spacing(10)
+            .align_x(Alignment::Center)
+    }
+
+    fn budget_view(&self) -> iced::widget::Column<'_, SettingsAction> {
+        let Budget {
+            monthly_limit,
+            block_when_exceeded,
+        } = self.config.budget;
+        let column = column![
+            text("Monthly Budget:").size(18),
+            row![
+                text("Limit in USD (0 for none):"),
+                number_input(&monthly_limit, 0.0..=100_000.0, |limit| {
+                    SettingsAction::ChangeMonthlyBudget(limit)
+                })
+                .step(5.0),
+            ]
+            .spacing(10)
+            .align_y(Alignment::Center),
+            checkbox(block_when_exceeded)
+                .label("Block requests once the limit is reached")
+                .on_toggle(SettingsAction::ToggleBlockOverBudget),
+            button("Export Usage as CSV…").on_press(SettingsAction::ExportUsage),
+        ]
+        .spacing(10)
+        .align_x(Alignment::Center);
+        match &self.usage_export_status {
+            Some(status) => column.push(text(status)),
+            None => column,
+        }
+    }
+
+    /// Time to first token, total time and throughput of each model's
+    /// streamed responses.
+    fn response_times_view(&self) -> iced::widget::Column<'_, SettingsAction> {
+        let mut column = column![text("Response Times:").size(18)]
+            .spacing(10)
+            .align_x(Alignment::Center);
+        if self.response_times.is_empty() {
+            return column.push(text("No streamed responses yet."));
+        }
+        for timings in &self.response_times {
+            let mut parts = vec![format!("{} responses", timings.responses)];
+            if let Some(first_token_ms) = timings.first_token_ms {
+                parts.push(format!(
+                    "first token after {:.2} s",
+                    first_token_ms as f64 / 1000.0
+                ));
+            }
+            parts.push(format!(
+                "done after {:.1} s",
+                timings.elapsed_ms as f64 / 1000.0
+            ));
+            if let Some(rate) = timings.tokens_per_second {
+                parts.push(format!("{rate:.1} tok/s"));
+            }
+            column = column.push(
+                row![text(&timings.model).width(200), text(parts.join(" · "))]
+                    .spacing(10)
+                    .align_y(Alignment::Center),
+            );
+        }
+        column
+    }
+
+    fn audit_log_view(&self) -> iced::widget::Column<'_, SettingsAction> {
+        let path = crate::config::ergon_dir().join("audit.log");
+        column![
+            text("Audit Log:").size(18),
+            checkbox(self.config.audit_log)
+                .label(format!("Log every request to {}", path.display()))
+                .on_toggle(SettingsAction::ToggleAuditLog),
+            text("Each line records the time, provider, model, token counts and status.")
+                .size(12)
+                .style(text::secondary),
+        ]
+        .spacing(10)
+        .align_x(Alignment::Center)
+    }
+
+    /// The providers with an embeddings API, by the name
+    /// [`crate::config::EmbeddingConfig::provider`] takes.
+    fn embedding_providers(&self) -> Vec<String> {
+        let builtin = ["OpenAI", "Azure OpenAI", "Mistral"].map(String::from);
+        let local = self.config.local_servers.iter().map(|s| s.name.clone());
+        let custom = self
+            .config
+            .custom_providers
+            .iter()
+            .filter(|p| p.flavor == ApiFlavor::OpenAI)
+            .map(|p| p.name.clone());
+        builtin.into_iter().chain(local).chain(custom).collect()
+    }
+
+    fn embeddings_view(&self) -> iced::widget::Column<'_, SettingsAction> {
+        let embeddings = &self.config.embeddings;
+        let column = column![
+            text("Embeddings:").size(18),
+            row![
+                text("Provider:"),
+                pick_list(
+                    self.embedding_providers(),
+                    Some(embeddings.provider.clone()),
+                    SettingsAction::ChangeEmbeddingProvider,
+                ),
+                text("Model:"),
+                text_input("text-embedding-3-small", &embeddings.model)
+                    .on_input(SettingsAction::ChangeEmbeddingModel),
+            ]
+            .spacing(10)
+            .align_y(Alignment::Center),
+            row![
+                text("Texts per request:"),
+                number_input(&embeddings.batch_size, 1..=2048, |size| {
+                    SettingsAction::ChangeEmbeddingBatchSize(size)
+                }),
+                button("Test").on_press(SettingsAction::TestEmbeddings),
+            ]
+            .spacing(10)
+            .align_y(Alignment::Center),
+        ]
+        .spacing(10)
+        .align_x(Alignment::Center);
+        match &self.embeddings_status {
+            Some(status) => column.push(text(status)),
+            None => column,
+        }
+    }
+
+    fn knowledge_view(&self) -> iced::widget::Column<'_, SettingsAction> {
+        let mut column = column![text("Knowledge Base:").size(18)]
+            .spacing(10)
+            .align_x(Alignment::Center);
+        if self.knowledge.is_empty() {
+            column = column.push(text("Nothing indexed yet."));
+        }
+        for collection in &self.knowledge {
+            column = column.push(
+                row![
+                    text(&collection.path),
+                    text(format!(
+                        "{} files, {} passages",
+                        collection.files, collection.chunks
+                    )),
+                    button(iced_fonts::lucide::trash())
+                        .on_press(SettingsAction::RemoveKnowledge(collection.path.clone())),
+                ]
+                .spacing(10)
+                .align_y(Alignment::Center),
+            );
+        }
+        let indexing = self.knowledge_status.as_deref() == Some("Indexing…");
+        column = column.push(
+            row![
+                button("Add Files…").on_press_maybe(
+                    (!indexing).then_some(SettingsAction::AddKnowledge { folders: false })
+                ),
+                button("Add Folder…").on_press_maybe(
+                    (!indexing).then_some(SettingsAction::AddKnowledge { folders: true })
+                ),
+            ]
+            .spacing(10),
+        );
+        match &self.knowledge_status {
+            Some(status) => column.push(text(status)),
+            None => column,
+        }
+    }
+
+    /// The folders shared with the built-in `read_file` and `list_dir`
+    /// tools, each removable, and where the shell tool runs commands.
+    fn fs_roots_view(&self) -> iced::widget::Column<'_, SettingsAction> {
+        let mut column = column![text("Built-in Tools:").size(18)]
+            .spacing(10)
+            .align_x(Alignment::Center);
+        if self.config.fs_roots.is_empty() {
+            column = column.push(text("No folders shared; the tools are off."));
+        }
+        for (index, root) in self.config.fs_roots.iter().enumerate() {
+            column = column.push(
+                row![
+                    text(root),
+                    button(iced_fonts::lucide::trash())
+                        .on_press(SettingsAction::RemoveFsRoot(index)),
+                ]
+                .spacing(10)
+                .align_y(Alignment::Center),
+            );
+        }
+        column = column.push(button("Share Folder…").on_press(SettingsAction::AddFsRoot));
+
+        let shell_dir = match &self.config.shell_dir {
+            Some(dir) => format!("Shell commands run in {}", dir),
+            None => "The shell tool is off.".to_string(),
+        };
+        column.push(
+            row![
+                text(shell_dir),
+                button("Choose Folder…").on_press(SettingsAction::ChooseShellDir),
+                button("Turn Off").on_press_maybe(
+                    self.config
+                        .shell_dir
+                        .is_some()
+                        .then_some(SettingsAction::ClearShellDir)
+                ),
+            ]
+            .spacing(10)
+            .align_y(Alignment::Center),
+        )
+    }
+
+    /// Render one policy picker per tool: every tool currently offered by
+    /// the MCP servers plus any tool that already has a stored policy.
+    fn tool_policies_view(&self) -> iced::widget::Column<'_, SettingsAction> {
+        let mut names: Vec<String> = crate::mcp::get_tool_manager()
+            .get_tools()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|tool| match tool {
+                crate::models::Tool::Function(function) => function.name,
+            })
+            .chain(self.config.tool_policies.keys().cloned())
+            .collect();
+        names.sort();
+        names.dedup();
+
+        let mut column = column![
+            text("Tool Policies:").size(18),
+            row![
+                text("Max tool iterations per turn:"),
+                number_input(&self.config.max_tool_iterations, 1..=100, |value| {
+                    SettingsAction::ChangeMaxToolIterations(value)
+                }),
+            ]
+            .spacing(10)
+            .align_y(Alignment::Center),
+        ];
+        if names.is_empty() {
+            column = column.push(text("No tools loaded."));
+        }
+        for name in names {
+            let policy = self.config.tool_policy(&name);
+            let picker_name = name.clone();
+            column = column.push(
+                row![
+                    text(name).width(Length::Fill),
+                    pick_list(ToolPolicy::ALL, Some(policy), move |p| {
+                        SettingsAction::ChangeToolPolicy(picker_name.clone(), p)
+                    }),
+                ]
+                .spacing(10)
+                .align_y(Alignment::Center),
+            );
+        }
+        column.spacing(10).align_x(Alignment::Center)
+    }
+}
+
+/// Embed a sample text with the embedding model in `config`, returning
+/// the length of the vector.
+/// Parse environment variables typed as "KEY=value, KEY2=value2". Entries
+/// without a name are dropped.
+fn parse_env(env_str: &str) -> Vec<(String, String)> {
+    env_str
+        .split(',')
+        .filter_map(|kv| {
+            let kv = kv.trim();
+            if kv.is_empty() {
+                return None;
+            }
+            let mut parts = kv.splitn(2, '=');
+            let k = parts.next()?.trim().to_string();
+            let v = parts.next().unwrap_or("").trim().to_string();
+            if k.is_empty() {
+                None
+            } else {
+                Some((k, v))
+            }
+        })
+        .collect()
+}
+
+/// Environment variables as [`parse_env`] reads them.
+fn format_env(env: &[(String, String)]) -> String {
+    env.iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Stream of [`SettingsAction::McpStatusChanged`] driven by the tool
+/// manager's status channel.
+fn mcp_status_updates() -> impl iced::futures::Stream<Item = SettingsAction> {
+    WatchStream::from_changes(get_tool_manager().subscribe_status())
+        .map(SettingsAction::McpStatusChanged)
+}
+
+async fn test_embeddings(config: Config) -> Result<usize, String> {
+    let embeddings = crate::api::clients::embed(config, vec!["Hello, world".to_string()])
+        .await
+        .map_err(|e| e.to_string())?;
+    embeddings
+        .first()
+        .map(Vec::len)
+        .ok_or_else(|| "No embedding was returned".to_string())
+}
+
+/// Everything indexed into the knowledge base.
+async fn load_knowledge() -> Result<Vec<KnowledgeCollection>, String> {
+    get_storage()
+        .and_then(|storage| storage.knowledge_collections())
+        .map_err(|e| e.to_string())
+}
+
+/// Timing of every streamed completion recorded.
+async fn load_timings() -> Result<Vec<TimingRecord>, String> {
+    get_storage()
+        .and_then(|storage| storage.timings_since(0))
+        .map_err(|e| e.to_string())
+}
+
+/// Ask for files, or folders. `None` if the user cancelled.
+async fn pick_paths(folders: bool) -> Option<Vec<PathBuf>> {
+    let dialog = rfd::AsyncFileDialog::new();
+    let picked = if folders {
+        dialog.pick_folders().await
+    } else {
+        dialog.pick_files().await
+    }?;
+    Some(
+        picked
+            .into_iter()
+            .map(|handle| handle.path().to_path_buf())
+            .collect(),
+    )
+}
+
+/// Ask for a Claude Desktop config and read its MCP servers. Returns
+/// `Ok(None)` if the user cancelled.
+async fn import_mcp_config() -> Result<Option<Vec<McpConfig>>, String> {
+    let Some(file) = rfd::AsyncFileDialog::new()
+        .add_filter("JSON", &["json"])
+        .pick_file()
+        .await
+    else {
+        return Ok(None);
+    };
+    let json = String::from_utf8(file.read().await).map_err(|e| e.to_string())?;
+    crate::mcp::import::parse_claude_desktop_config(&json)
+        .map(Some)
+        .map_err(|e| e.to_string())
+}
+
+/// Ask where to save every recorded completion's usage as CSV and write it
+/// there, priced with `pricing`. Returns `Ok(None)` if the user cancelled.
+async fn export_usage(pricing: HashMap<String, ModelPricing>) -> Result<Option<PathBuf>, String> {
+    let records = crate::storage::get_storage()
+        .and_then(|storage| storage.usage_since(0))
+        .map_err(|e| e.to_string())?;
+    let csv = crate::export::usage_csv(&records, &pricing, |model| {
+        crate::api::clients::get_model_manager()
+            .find_model(model)
+            .ok()
+            .flatten()
+            .map(|info| format!("{:?}", info.client))
+    });
+    let Some(file) = rfd::AsyncFileDialog::new()
+        .add_filter("CSV", &["csv"])
+        .set_file_name("ergon-usage.csv")
+        .save_file()
+        .await
+    else {
+        return Ok(None);
+    };
+    file.write(csv.as_bytes())
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(Some(file.path().to_path_buf()))
 }
 
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
 
-    use crate::config::{AnthropicConfig, OpenAIConfig, VllmConfig};
+    use crate::config::{
+        AnthropicConfig, AzureConfig, BedrockConfig, CohereConfig, CustomProviderConfig,
+        EmbeddingConfig, MistralConfig, OpenAIConfig, ProxyConfig, RetryConfig, TimeoutConfig,
+        DEFAULT_MAX_TOOL_ITERATIONS, DEFAULT_QUICK_CHAT_HOTKEY,
+    };
 
     use super::*;
 
@@ -817,6 +2317,17 @@ mod tests {
         assert_eq!(state.config.openai.endpoint, "https://new.endpoint.com");
     }
 
+    #[test]
+    fn test_update_openai_organization_and_project() {
+        let mut state = State::default();
+        let _ = state.update(SettingsAction::ChangeOpenAIOrganization(
+            "org-123".to_string(),
+        ));
+        let _ = state.update(SettingsAction::ChangeOpenAIProject("proj_456".to_string()));
+        assert_eq!(state.config.openai.organization, "org-123");
+        assert_eq!(state.config.openai.project, "proj_456");
+    }
+
     #[test]
     fn test_update_anthropic_key() {
         let mut state = State::default();
@@ -826,6 +2337,101 @@ mod tests {
         assert_eq!(state.config.anthropic.api_key, "new_anthropic_key");
     }
 
+    #[test]
+    fn test_update_azure_settings() {
+        let mut state = State::default();
+        let _ = state.update(SettingsAction::ChangeAzureKey("azure_key".to_string()));
+        let _ = state.update(SettingsAction::ChangeAzureResource("contoso".to_string()));
+        let _ = state.update(SettingsAction::ChangeAzureDeployment("gpt-4o".to_string()));
+        let _ = state.update(SettingsAction::ChangeAzureApiVersion(
+            "2025-01-01-preview".to_string(),
+        ));
+        assert_eq!(
+            state.config.azure,
+            AzureConfig {
+                api_key: "azure_key".to_string(),
+                resource_name: "contoso".to_string(),
+                deployment_name: "gpt-4o".to_string(),
+                api_version: "2025-01-01-preview".to_string(),
+                retry: RetryConfig::default(),
+                timeouts: TimeoutConfig::default(),
+                proxy: None,
+            }
+        );
+        assert!(State::llm_configs_changed(
+            &state.saved_config,
+            &state.config
+        ));
+    }
+
+    #[test]
+    fn test_update_mistral_settings() {
+        let mut state = State::default();
+        let _ = state.update(SettingsAction::ChangeMistralKey("mistral_key".to_string()));
+        let _ = state.update(SettingsAction::ChangeMistralUrl(
+            "https://mistral.example.com/v1".to_string(),
+        ));
+        assert_eq!(state.config.mistral.api_key, "mistral_key");
+        assert_eq!(
+            state.config.mistral.endpoint,
+            "https://mistral.example.com/v1"
+        );
+    }
+
+    #[test]
+    fn test_update_cohere_settings() {
+        let mut state = State::default();
+        let _ = state.update(SettingsAction::ChangeCohereKey("cohere_key".to_string()));
+        let _ = state.update(SettingsAction::ChangeCohereUrl(
+            "https://cohere.example.com".to_string(),
+        ));
+        assert_eq!(state.config.cohere.api_key, "cohere_key");
+        assert_eq!(state.config.cohere.endpoint, "https://cohere.example.com");
+    }
+
+    #[test]
+    fn test_update_proxy_settings() {
+        let mut state = State::default();
+        let _ = state.update(SettingsAction::ChangeProxyUrl(
+            "http://proxy.corp:3128".to_string(),
+        ));
+        let _ = state.update(SettingsAction::ChangeNoProxy(
+            "localhost, .corp, ,10.0.0.0/8".to_string(),
+        ));
+        assert_eq!(state.config.proxy.url, "http://proxy.corp:3128");
+        assert_eq!(
+            state.config.proxy.no_proxy,
+            vec!["localhost", ".corp", "10.0.0.0/8"]
+        );
+        let old = Config::default();
+        assert!(State::llm_configs_changed(&old, &state.config));
+        assert!(State::mcp_configs_changed(&old, &state.config));
+    }
+
+    #[test]
+    fn test_update_bedrock_settings() {
+        let mut state = State::default();
+        let _ = state.update(SettingsAction::ChangeBedrockRegion("eu-west-1".to_string()));
+        let _ = state.update(SettingsAction::ChangeBedrockAccessKey("AKID".to_string()));
+        let _ = state.update(SettingsAction::SecretPasted(
+            SecretField::BedrockSecretKey,
+            Some(" secret \n".to_string()),
+        ));
+        assert_eq!(
+            state.config.bedrock,
+            BedrockConfig {
+                region: "eu-west-1".to_string(),
+                access_key_id: "AKID".to_string(),
+                secret_access_key: "secret".to_string(),
+                session_token: String::new(),
+                retry: RetryConfig::default(),
+                timeouts: TimeoutConfig::default(),
+                proxy: None,
+            }
+        );
+        assert!(state.config.bedrock.is_configured());
+    }
+
     #[test]
     fn test_update_anthropic_url() {
         let mut state = State::default();
@@ -846,19 +2452,54 @@ mod tests {
     }
 
     #[test]
-    fn test_update_vllm_url() {
+    fn test_toggle_anthropic_thinking() {
         let mut state = State::default();
-        let _ = state.update(SettingsAction::ChangeVllmUrl(
-            "http://new.vllm.endpoint.com".to_string(),
-        ));
-        assert_eq!(state.config.vllm.endpoint, "http://new.vllm.endpoint.com");
+        let _ = state.update(SettingsAction::ToggleAnthropicThinking(true));
+        assert_eq!(
+            state.config.anthropic.thinking_budget,
+            Some(DEFAULT_THINKING_BUDGET)
+        );
+        let _ = state.update(SettingsAction::ChangeAnthropicThinkingBudget(8192));
+        assert_eq!(state.config.anthropic.thinking_budget, Some(8192));
+        let _ = state.update(SettingsAction::ToggleAnthropicThinking(false));
+        assert_eq!(state.config.anthropic.thinking_budget, None);
     }
 
     #[test]
-    fn test_update_vllm_model() {
+    fn test_toggle_anthropic_prompt_caching() {
         let mut state = State::default();
-        let _ = state.update(SettingsAction::ChangeVllmModel("new-model".to_string()));
-        assert_eq!(state.config.vllm.model, "new-model");
+        let _ = state.update(SettingsAction::ToggleAnthropicPromptCaching(true));
+        assert!(state.config.anthropic.prompt_caching);
+    }
+
+    #[test]
+    fn test_update_local_servers() {
+        let mut state = State::default();
+        state.config.local_servers.clear();
+        let _ = state.update(SettingsAction::AddLocalServer);
+        let _ = state.update(SettingsAction::AddLocalServer);
+        let _ = state.update(SettingsAction::ChangeLocalServerName(
+            1,
+            "LM Studio".to_string(),
+        ));
+        let _ = state.update(SettingsAction::ChangeLocalServerUrl(
+            1,
+            "http://localhost:1234/v1/".to_string(),
+        ));
+        let _ = state.update(SettingsAction::ChangeLocalServerKey(1, "key".to_string()));
+        let _ = state.update(SettingsAction::ChangeLocalServerFilter(
+            1,
+            "qwen, , gemma".to_string(),
+        ));
+        let server = &state.config.local_servers[1];
+        assert_eq!(server.name, "LM Studio");
+        assert_eq!(server.endpoint, "http://localhost:1234/v1/");
+        assert_eq!(server.api_key, "key");
+        assert_eq!(server.model_filter, vec!["qwen", "gemma"]);
+
+        let _ = state.update(SettingsAction::RemoveLocalServer(0));
+        assert_eq!(state.config.local_servers.len(), 1);
+        assert_eq!(state.config.local_servers[0].name, "LM Studio");
     }
 
     #[test]
@@ -869,24 +2510,63 @@ mod tests {
                 openai: OpenAIConfig {
                     api_key: String::new(),
                     endpoint: "https://api.openai.com/v1/".to_string(),
+                    organization: String::new(),
+                    project: String::new(),
+                    retry: RetryConfig::default(),
+                    timeouts: TimeoutConfig::default(),
+                    proxy: None,
                 },
                 anthropic: AnthropicConfig {
                     api_key: String::new(),
                     endpoint: "https://api.anthropic.com/v1/".to_string(),
                     max_tokens: 1024,
+                    thinking_budget: None,
+                    prompt_caching: false,
+                    retry: RetryConfig::default(),
+                    timeouts: TimeoutConfig::default(),
+                    proxy: None,
                 },
-                vllm: VllmConfig {
-                    endpoint: "http://localhost:8000/v1/".to_string(),
-                    model: "google/gemma-3-270m".to_string(),
-                },
+                local_servers: vec![LocalServerConfig::default()],
                 mcp_configs: vec![],
                 acp_agents: vec![],
                 acp_session_state: HashMap::new(),
                 oauth_tokens: HashMap::new(),
+                templates: vec![],
+                tool_policies: HashMap::new(),
+                max_tool_iterations: DEFAULT_MAX_TOOL_ITERATIONS,
+                title_model: None,
+                fallback_models: vec![],
+                default_system_prompt: String::new(),
+                pricing: HashMap::new(),
+                budget: Budget::default(),
+                azure: AzureConfig::default(),
+                mistral: MistralConfig::default(),
+                cohere: CohereConfig::default(),
+                bedrock: BedrockConfig::default(),
+                custom_providers: vec![],
+                proxy: ProxyConfig::default(),
+                embeddings: EmbeddingConfig::default(),
+                fs_roots: vec![],
+                shell_dir: None,
+                summarize_after: 0,
+                summary_model: None,
+                quick_chat_hotkey: DEFAULT_QUICK_CHAT_HOTKEY.to_string(),
+                audit_log: false,
+                lazy_mcp_start: false,
                 settings_file: "./test.json".to_string(),
             },
             saved_config: Config::default(),
             auth_status: HashMap::new(),
+            mcp_status: BTreeMap::new(),
+            mcp_import_status: None,
+            revealed_secrets: HashSet::new(),
+            default_system_prompt: text_editor::Content::new(),
+            pricing_rows: vec![],
+            usage_export_status: None,
+            embeddings_status: None,
+            knowledge: vec![],
+            knowledge_status: None,
+            response_times: vec![],
         };
         let _ = state.update(SettingsAction::ChangeTheme(Theme::Dark));
         let _ = state.update(SettingsAction::ChangeOpenAIKey("test_key".to_string()));
@@ -911,8 +2591,10 @@ mod tests {
             "https://api.anthropic.com/v1/"
         );
         assert_eq!(state.config.anthropic.max_tokens, 1024);
-        assert_eq!(state.config.vllm.endpoint, "http://localhost:8000/v1/");
-        assert_eq!(state.config.vllm.model, "google/gemma-3-270m");
+        assert_eq!(
+            state.config.local_servers[0].endpoint,
+            "http://localhost:8000/v1/"
+        );
         assert_eq!(state.config.mcp_configs.len(), 1);
     }
 
@@ -971,6 +2653,7 @@ mod tests {
                 auth: McpAuthConfig::BearerToken {
                     token: String::new(),
                 },
+                ..McpStreamableHttpConfig::default()
             }));
 
         let _ = state.update(SettingsAction::ChangeMcpHttpBearerToken(
@@ -1004,6 +2687,7 @@ mod tests {
                     client_name: "Ergon".to_string(),
                     redirect_port: 8585,
                 },
+                ..McpStreamableHttpConfig::default()
             }));
 
         let _ = state.update(SettingsAction::ChangeMcpHttpOAuthScopes(
@@ -1041,13 +2725,40 @@ mod tests {
             openai: OpenAIConfig {
                 api_key: "a".into(),
                 endpoint: "http://a".into(),
+                organization: String::new(),
+                project: String::new(),
+                retry: RetryConfig::default(),
+                timeouts: TimeoutConfig::default(),
+                proxy: None,
             },
             anthropic: AnthropicConfig::default(),
-            vllm: VllmConfig::default(),
+            local_servers: vec![],
             mcp_configs: vec![],
             acp_agents: vec![],
             acp_session_state: HashMap::new(),
             oauth_tokens: HashMap::new(),
+            templates: vec![],
+            tool_policies: HashMap::new(),
+            max_tool_iterations: DEFAULT_MAX_TOOL_ITERATIONS,
+            title_model: None,
+            fallback_models: vec![],
+            default_system_prompt: String::new(),
+            pricing: HashMap::new(),
+            budget: Budget::default(),
+            azure: AzureConfig::default(),
+            mistral: MistralConfig::default(),
+            cohere: CohereConfig::default(),
+            bedrock: BedrockConfig::default(),
+            custom_providers: vec![],
+            proxy: ProxyConfig::default(),
+            embeddings: EmbeddingConfig::default(),
+            fs_roots: vec![],
+            shell_dir: None,
+            summarize_after: 0,
+            summary_model: None,
+            quick_chat_hotkey: DEFAULT_QUICK_CHAT_HOTKEY.to_string(),
+            audit_log: false,
+            lazy_mcp_start: false,
             settings_file: "./t.json".into(),
         };
         let mut b = a.clone();
@@ -1060,8 +2771,12 @@ mod tests {
         assert!(State::llm_configs_changed(&a, &c));
 
         let mut d = a.clone();
-        d.vllm.model = "x".into();
+        d.local_servers.push(LocalServerConfig::default());
         assert!(State::llm_configs_changed(&a, &d));
+
+        let mut e = a.clone();
+        e.audit_log = true;
+        assert!(State::llm_configs_changed(&a, &e));
     }
 
     #[test]
@@ -1070,11 +2785,33 @@ mod tests {
             theme: Theme::Dark,
             openai: OpenAIConfig::default(),
             anthropic: AnthropicConfig::default(),
-            vllm: VllmConfig::default(),
+            local_servers: vec![],
             mcp_configs: vec![],
             acp_agents: vec![],
             acp_session_state: HashMap::new(),
             oauth_tokens: HashMap::new(),
+            templates: vec![],
+            tool_policies: HashMap::new(),
+            max_tool_iterations: DEFAULT_MAX_TOOL_ITERATIONS,
+            title_model: None,
+            fallback_models: vec![],
+            default_system_prompt: String::new(),
+            pricing: HashMap::new(),
+            budget: Budget::default(),
+            azure: AzureConfig::default(),
+            mistral: MistralConfig::default(),
+            cohere: CohereConfig::default(),
+            bedrock: BedrockConfig::default(),
+            custom_providers: vec![],
+            proxy: ProxyConfig::default(),
+            embeddings: EmbeddingConfig::default(),
+            fs_roots: vec![],
+            shell_dir: None,
+            summarize_after: 0,
+            summary_model: None,
+            quick_chat_hotkey: DEFAULT_QUICK_CHAT_HOTKEY.to_string(),
+            audit_log: false,
+            lazy_mcp_start: false,
             settings_file: "./t.json".into(),
         };
         let mut b = a.clone();
@@ -1082,6 +2819,332 @@ mod tests {
         b.mcp_configs
             .push(McpConfig::StreamableHttp(McpStreamableHttpConfig::default()));
         assert!(State::mcp_configs_changed(&a, &b));
+
+        let mut c = a.clone();
+        c.fs_roots.push("/home/me/notes".to_string());
+        assert!(State::mcp_configs_changed(&a, &c));
+
+        let mut d = a.clone();
+        d.lazy_mcp_start = true;
+        assert!(State::mcp_configs_changed(&a, &d));
+    }
+
+    #[test]
+    fn test_edit_template_messages() {
+        let mut state = State::default();
+        state.config.templates.clear();
+        let _ = state.update(SettingsAction::AddTemplate);
+        let _ = state.update(SettingsAction::ChangeTemplateName(0, "review".to_string()));
+        let _ = state.update(SettingsAction::ChangeTemplateMessageContent(
+            0,
+            0,
+            "Review {{language}} code.".to_string(),
+        ));
+        let _ = state.update(SettingsAction::AddTemplateMessage(0));
+        let _ = state.update(SettingsAction::ChangeTemplateMessageRole(0, 1, "assistant"));
+
+        let template = &state.config.templates[0];
+        assert_eq!(template.name, "review");
+        assert_eq!(template.messages.len(), 2);
+        assert_eq!(template.messages[0].role, "system");
+        assert_eq!(template.messages[1].role, "assistant");
+        assert_eq!(template.variables(), vec!["language"]);
+
+        let _ = state.update(SettingsAction::RemoveTemplateMessage(0, 0));
+        assert_eq!(state.config.templates[0].messages.len(), 1);
+        let _ = state.update(SettingsAction::RemoveTemplate(0));
+        assert!(state.config.templates.is_empty());
+    }
+
+    #[test]
+    fn test_change_tool_policy_stores_only_overrides() {
+        let mut state = State::default();
+        state.config.tool_policies.clear();
+        let _ = state.update(SettingsAction::ChangeToolPolicy(
+            "__fs__delete".to_string(),
+            ToolPolicy::Never,
+        ));
+        assert_eq!(state.config.tool_policy("__fs__delete"), ToolPolicy::Never);
+
+        let _ = state.update(SettingsAction::ChangeToolPolicy(
+            "__fs__delete".to_string(),
+            ToolPolicy::Ask,
+        ));
+        assert!(state.config.tool_policies.is_empty());
+    }
+
+    #[test]
+    fn test_edit_default_system_prompt() {
+        let mut state = State::default();
+        let _ = state.update(SettingsAction::EditDefaultSystemPrompt(
+            text_editor::Action::Edit(text_editor::Edit::Paste(std::sync::Arc::new(
+                "Be brief.".to_string(),
+            ))),
+        ));
+        assert_eq!(state.config.default_system_prompt, "Be brief.");
+    }
+
+    #[test]
+    fn test_pricing_rows_sync_to_config() {
+        let mut state = State::default();
+        state.pricing_rows.clear();
+        let _ = state.update(SettingsAction::AddPricing);
+        let _ = state.update(SettingsAction::ChangePricingPrompt(0, 2.5));
+        // Rows without a model name are not stored.
+        assert!(state.config.pricing.is_empty());
+
+        let _ = state.update(SettingsAction::ChangePricingModel(
+            0,
+            " gpt-4o ".to_string(),
+        ));
+        let _ = state.update(SettingsAction::ChangePricingCompletion(0, 10.0));
+        assert_eq!(
+            state.config.pricing.get("gpt-4o"),
+            Some(&ModelPricing {
+                prompt: 2.5,
+                completion: 10.0,
+            })
+        );
+
+        let _ = state.update(SettingsAction::RemovePricing(0));
+        assert!(state.config.pricing.is_empty());
+    }
+
+    #[test]
+    fn test_change_budget() {
+        let mut state = State::default();
+        let _ = state.update(SettingsAction::ChangeMonthlyBudget(25.0));
+        let _ = state.update(SettingsAction::ToggleBlockOverBudget(true));
+        assert_eq!(
+            state.config.budget,
+            Budget {
+                monthly_limit: 25.0,
+                block_when_exceeded: true,
+            }
+        );
+    }
+
+    #[test]
+    fn test_change_embeddings() {
+        let mut state = State::default();
+        state.config.custom_providers = vec![
+            CustomProviderConfig {
+                name: "OpenRouter".to_string(),
+                ..CustomProviderConfig::default()
+            },
+            CustomProviderConfig {
+                name: "Proxy".to_string(),
+                flavor: ApiFlavor::Anthropic,
+                ..CustomProviderConfig::default()
+            },
+        ];
+        assert_eq!(
+            state.embedding_providers(),
+            ["OpenAI", "Azure OpenAI", "Mistral", "OpenRouter"]
+        );
+
+        let _ = state.update(SettingsAction::ChangeEmbeddingProvider(
+            "Mistral".to_string(),
+        ));
+        let _ = state.update(SettingsAction::ChangeEmbeddingModel(
+            "mistral-embed".to_string(),
+        ));
+        let _ = state.update(SettingsAction::ChangeEmbeddingBatchSize(32));
+        assert_eq!(
+            state.config.embeddings,
+            EmbeddingConfig {
+                provider: "Mistral".to_string(),
+                model: "mistral-embed".to_string(),
+                batch_size: 32,
+            }
+        );
+    }
+
+    #[test]
+    fn test_share_folders_with_builtin_tools() {
+        let mut state = State::default();
+        state.config.fs_roots.clear();
+        let picked = vec![PathBuf::from("/home/me/notes"), PathBuf::from("/srv/docs")];
+        let _ = state.update(SettingsAction::FsRootsPicked(Some(picked.clone())));
+        let _ = state.update(SettingsAction::FsRootsPicked(Some(picked)));
+        assert_eq!(state.config.fs_roots, ["/home/me/notes", "/srv/docs"]);
+
+        let _ = state.update(SettingsAction::RemoveFsRoot(0));
+        let _ = state.update(SettingsAction::RemoveFsRoot(5));
+        assert_eq!(state.config.fs_roots, ["/srv/docs"]);
+
+        let _ = state.update(SettingsAction::ShellDirPicked(Some(vec![PathBuf::from(
+            "/home/me/project",
+        )])));
+        assert_eq!(state.config.shell_dir.as_deref(), Some("/home/me/project"));
+        let _ = state.update(SettingsAction::ShellDirPicked(None));
+        assert_eq!(state.config.shell_dir.as_deref(), Some("/home/me/project"));
+        let _ = state.update(SettingsAction::ClearShellDir);
+        assert_eq!(state.config.shell_dir, None);
+    }
+
+    #[test]
+    fn test_change_fallback_models() {
+        let mut state = State::default();
+        let _ = state.update(SettingsAction::ChangeFallbackModels(
+            "claude-haiku-4-5, , gpt-4o-mini".to_string(),
+        ));
+        assert_eq!(
+            state.config.fallback_models,
+            vec!["claude-haiku-4-5", "gpt-4o-mini"]
+        );
+    }
+
+    #[test]
+    fn test_response_times_are_averaged_per_model() {
+        let record = |model: Option<&str>, elapsed_ms, generation_ms, tokens| TimingRecord {
+            created_at: 0,
+            model: model.map(String::from),
+            timing: ResponseTiming {
+                elapsed_ms,
+                generation_ms,
+                tokens,
+            },
+        };
+        let mut state = State::new();
+        let _ = state.update(SettingsAction::ResponseTimesLoaded(Ok(vec![
+            record(Some("gpt-4o"), 2_000, 1_500, 60),
+            record(None, 1_000, 0, 0),
+            record(Some("gpt-4o"), 4_000, 2_500, 100),
+        ])));
+        assert_eq!(
+            state.response_times,
+            vec![
+                ModelTimings {
+                    model: "Unknown model".to_string(),
+                    responses: 1,
+                    first_token_ms: None,
+                    elapsed_ms: 1_000,
+                    tokens_per_second: None,
+                },
+                ModelTimings {
+                    model: "gpt-4o".to_string(),
+                    responses: 2,
+                    first_token_ms: Some(1_000),
+                    elapsed_ms: 3_000,
+                    tokens_per_second: Some(40.0),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_usage_export_status() {
+        let mut state = State::default();
+        let _ = state.update(SettingsAction::UsageExported(Err("disk full".to_string())));
+        assert_eq!(
+            state.usage_export_status.as_deref(),
+            Some("Export failed: disk full")
+        );
+        let _ = state.update(SettingsAction::UsageExported(Ok(Some(PathBuf::from(
+            "usage.csv",
+        )))));
+        assert_eq!(
+            state.usage_export_status.as_deref(),
+            Some("Exported to usage.csv")
+        );
+        let _ = state.update(SettingsAction::UsageExported(Ok(None)));
+        assert_eq!(state.usage_export_status, None);
+    }
+
+    #[test]
+    fn test_toggle_mcp_server_and_tool() {
+        let mut state = State::default();
+        state.config.mcp_configs = vec![McpConfig::default()];
+
+        let _ = state.update(SettingsAction::ToggleMcpServer(0, false));
+        let _ = state.update(SettingsAction::ToggleMcpTool(
+            0,
+            "delete_file".to_string(),
+            false,
+        ));
+        assert!(!state.config.mcp_configs[0].enabled());
+        assert!(!state.config.mcp_configs[0].tool_enabled("delete_file"));
+
+        let _ = state.update(SettingsAction::ToggleMcpServer(0, true));
+        let _ = state.update(SettingsAction::ToggleMcpTool(
+            0,
+            "delete_file".to_string(),
+            true,
+        ));
+        assert!(state.config.mcp_configs[0].enabled());
+        assert!(state.config.mcp_configs[0].disabled_tools().is_empty());
+    }
+
+    #[test]
+    fn test_import_mcp_config_skips_existing_names() {
+        let mut state = State::default();
+        let existing = McpConfig::Stdio(McpStdioConfig {
+            name: "github".to_string(),
+            command: "github-mcp".to_string(),
+            ..McpStdioConfig::default()
+        });
+        state.config.mcp_configs = vec![existing.clone()];
+        let imported = McpConfig::StreamableHttp(McpStreamableHttpConfig {
+            name: "docs".to_string(),
+            endpoint: "https://docs.example.com/mcp".to_string(),
+            auth: McpAuthConfig::None,
+            ..McpStreamableHttpConfig::default()
+        });
+        let duplicate = McpConfig::Stdio(McpStdioConfig {
+            name: "github".to_string(),
+            command: "docker".to_string(),
+            ..McpStdioConfig::default()
+        });
+
+        let _ = state.update(SettingsAction::McpConfigImported(Ok(Some(vec![
+            imported.clone(),
+            duplicate,
+        ]))));
+
+        assert_eq!(state.config.mcp_configs, vec![existing, imported]);
+        assert_eq!(
+            state.mcp_import_status.as_deref(),
+            Some("Imported 1 server. Skipped existing: github.")
+        );
+        let _ = state.update(SettingsAction::McpConfigImported(Err(
+            "No \"mcpServers\" section found".to_string(),
+        )));
+        assert_eq!(
+            state.mcp_import_status.as_deref(),
+            Some("Import failed: No \"mcpServers\" section found")
+        );
+    }
+
+    #[test]
+    fn test_toggle_secret_visibility() {
+        let mut state = State::default();
+        assert!(!state.revealed_secrets.contains(&SecretField::OpenAIKey));
+        let _ = state.update(SettingsAction::ToggleSecretVisibility(
+            SecretField::OpenAIKey,
+        ));
+        assert!(state.revealed_secrets.contains(&SecretField::OpenAIKey));
+        let _ = state.update(SettingsAction::ToggleSecretVisibility(
+            SecretField::OpenAIKey,
+        ));
+        assert!(!state.revealed_secrets.contains(&SecretField::OpenAIKey));
+    }
+
+    #[test]
+    fn test_secret_pasted_sets_trimmed_value() {
+        let mut state = State::default();
+        let _ = state.update(SettingsAction::SecretPasted(
+            SecretField::AnthropicKey,
+            Some("  sk-ant-pasted\n".to_string()),
+        ));
+        assert_eq!(state.config.anthropic.api_key, "sk-ant-pasted");
+
+        // An empty clipboard leaves the current value alone.
+        let _ = state.update(SettingsAction::SecretPasted(
+            SecretField::AnthropicKey,
+            None,
+        ));
+        assert_eq!(state.config.anthropic.api_key, "sk-ant-pasted");
     }
 
     #[test]
@@ -1101,6 +3164,7 @@ mod tests {
                     client_name: "Ergon".into(),
                     redirect_port: 8585,
                 },
+                ..McpStreamableHttpConfig::default()
             }));
         // Should not panic, nor produce any task that hits the network.
         let _ = state.update(SettingsAction::StartOAuthAuth(0));
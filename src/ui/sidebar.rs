@@ -0,0 +1,266 @@
+//! Left-hand list of stored conversations.
+
+use iced::{
+    widget::{button, column, container, row, scrollable, text, text_input},
+    Alignment, Element, Length, Task,
+};
+
+use crate::storage::{get_storage, unix_now, ConversationSummary, SearchHit};
+
+/// Width of the sidebar in logical pixels.
+const SIDEBAR_WIDTH: f32 = 220.0;
+
+#[derive(Debug, Default)]
+pub struct State {
+    conversations: Vec<ConversationSummary>,
+    /// Text in the search box. While non-empty, search hits replace the
+    /// conversation list.
+    search_query: String,
+    search_hits: Vec<SearchHit>,
+    /// Outcome of the last import, shown under the buttons.
+    import_status: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum SidebarAction {
+    /// The conversation list was (re)read from the database.
+    ConversationsLoaded(Vec<ConversationSummary>),
+    /// User clicked a conversation in the list.
+    Open(String),
+    /// User clicked "New chat", which opens a new tab.
+    NewChat,
+    /// User clicked "Import": pick an export file from another chat app.
+    Import,
+    /// The import finished. `Ok(None)` means the file dialog was cancelled.
+    Imported(Result<Option<usize>, String>),
+    /// User edited the search box.
+    SearchChanged(String),
+    /// Hits for the given search query.
+    SearchResults(String, Vec<SearchHit>),
+    /// User clicked a search hit: open the conversation and jump to the
+    /// message at this position.
+    OpenMessage(String, usize),
+}
+
+impl State {
+    pub fn new() -> (Self, Task<SidebarAction>) {
+        (Self::default(), Self::refresh())
+    }
+
+    /// Re-read the conversation list in the background.
+    pub fn refresh() -> Task<SidebarAction> {
+        Task::perform(load_conversations(), SidebarAction::ConversationsLoaded)
+    }
+
+    pub fn update(&mut self, action: SidebarAction) -> Task<SidebarAction> {
+        match action {
+            SidebarAction::ConversationsLoaded(conversations) => {
+                self.conversations = conversations;
+            }
+            SidebarAction::SearchChanged(query) => {
+                self.search_query = query.clone();
+                if query.trim().is_empty() {
+                    self.search_hits.clear();
+                } else {
+                    return Task::perform(search_messages(query.clone()), move |hits| {
+                        SidebarAction::SearchResults(query.clone(), hits)
+                    });
+                }
+            }
+            SidebarAction::Import => {
+                return Task::perform(import_conversations(), SidebarAction::Imported);
+            }
+            SidebarAction::Imported(result) => {
+                self.import_status = match result {
+                    Ok(None) => None,
+                    Ok(Some(count)) => Some(format!("Imported {count} conversations.")),
+                    Err(err) => {
+                        tracing::error!("Failed to import conversations: {}", err);
+                        Some(format!("Import failed: {err}"))
+                    }
+                };
+                return Self::refresh();
+            }
+            SidebarAction::SearchResults(query, hits) => {
+                // Drop results for a query the user has since changed.
+                if query == self.search_query {
+                    self.search_hits = hits;
+                }
+            }
+            // Handled by `ui::update`, which routes them to the chat page.
+            SidebarAction::Open(_) | SidebarAction::OpenMessage(..) | SidebarAction::NewChat => {}
+        }
+        Task::none()
+    }
+
+    /// Render the list, highlighting `active_id`.
+    pub fn view(&self, active_id: &str) -> Element<'_, SidebarAction> {
+        let now = unix_now();
+        let new_chat = button(
+            row![iced_fonts::lucide::plus(), text("New chat")]
+                .spacing(6)
+                .align_y(Alignment::Center),
+        )
+        .on_press(SidebarAction::NewChat)
+        .width(Length::Fill);
+        let import = button(
+            row![iced_fonts::lucide::upload(), text("Import")]
+                .spacing(6)
+                .align_y(Alignment::Center),
+        )
+        .on_press(SidebarAction::Import)
+        .style(button::secondary)
+        .width(Length::Fill);
+
+        let search = text_input("Search conversations", &self.search_query)
+            .on_input(SidebarAction::SearchChanged);
+
+        let list = if self.search_query.trim().is_empty() {
+            self.conversation_list(active_id, now)
+        } else {
+            self.search_results()
+        };
+
+        let mut content = column![new_chat, import].spacing(10);
+        if let Some(status) = &self.import_status {
+            content = content.push(text(status).size(11));
+        }
+        content = content
+            .push(search)
+            .push(scrollable(list).height(Length::Fill));
+
+        container(content)
+            .width(SIDEBAR_WIDTH)
+            .height(Length::Fill)
+            .into()
+    }
+
+    fn conversation_list(&self, active_id: &str, now: i64) -> Element<'_, SidebarAction> {
+        let entries = self.conversations.iter().map(|conversation| {
+            let title = if conversation.title.is_empty() {
+                "Untitled"
+            } else {
+                conversation.title.as_str()
+            };
+            button(column![
+                text(title).size(14),
+                text(format_updated(conversation.updated_at, now)).size(11),
+            ])
+            .on_press(SidebarAction::Open(conversation.id.clone()))
+            .style(if conversation.id == active_id {
+                button::secondary
+            } else {
+                button::text
+            })
+            .width(Length::Fill)
+            .into()
+        });
+        column(entries).spacing(2).into()
+    }
+
+    fn search_results(&self) -> Element<'_, SidebarAction> {
+        if self.search_hits.is_empty() {
+            return text("No matches.").size(14).into();
+        }
+        let hits = self.search_hits.iter().map(|hit| {
+            let title = if hit.title.is_empty() {
+                "Untitled"
+            } else {
+                hit.title.as_str()
+            };
+            button(column![text(title).size(14), text(&hit.snippet).size(11)])
+                .on_press(SidebarAction::OpenMessage(
+                    hit.conversation_id.clone(),
+                    hit.position,
+                ))
+                .style(button::text)
+                .width(Length::Fill)
+                .into()
+        });
+        column(hits).spacing(2).into()
+    }
+}
+
+async fn load_conversations() -> Vec<ConversationSummary> {
+    match get_storage().and_then(|storage| storage.list_conversations()) {
+        Ok(conversations) => conversations,
+        Err(e) => {
+            tracing::error!("Failed to list conversations: {}", e);
+            vec![]
+        }
+    }
+}
+
+/// Let the user pick an export file and import every conversation in it.
+async fn import_conversations() -> Result<Option<usize>, String> {
+    let Some(file) = rfd::AsyncFileDialog::new()
+        .add_filter("Conversation export", &["json"])
+        .pick_file()
+        .await
+    else {
+        return Ok(None);
+    };
+    crate::import::import_file(file.path())
+        .map(Some)
+        .map_err(|e| e.to_string())
+}
+
+async fn search_messages(query: String) -> Vec<SearchHit> {
+    match get_storage().and_then(|storage| storage.search(&query)) {
+        Ok(hits) => hits,
+        Err(e) => {
+            tracing::error!("Failed to search conversations: {}", e);
+            vec![]
+        }
+    }
+}
+
+/// How long ago `updated_at` was, relative to `now` (both Unix seconds).
+fn format_updated(updated_at: i64, now: i64) -> String {
+    let elapsed = (now - updated_at).max(0);
+    match elapsed {
+        0..60 => "just now".to_string(),
+        60..3600 => format!("{} min ago", elapsed / 60),
+        3600..86400 => format!("{} h ago", elapsed / 3600),
+        _ => format!("{} d ago", elapsed / 86400),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_updated() {
+        assert_eq!(format_updated(1_000, 1_030), "just now");
+        assert_eq!(format_updated(1_000, 1_000 + 5 * 60), "5 min ago");
+        assert_eq!(format_updated(0, 3 * 3600 + 10), "3 h ago");
+        assert_eq!(format_updated(0, 2 * 86400), "2 d ago");
+        // Clock skew never yields a negative age.
+        assert_eq!(format_updated(2_000, 1_000), "just now");
+    }
+
+    #[test]
+    fn test_stale_search_results_are_dropped() {
+        let hit = SearchHit {
+            conversation_id: "a".to_string(),
+            title: "Pasta".to_string(),
+            position: 0,
+            snippet: "Best pasta recipes".to_string(),
+        };
+        let mut state = State {
+            search_query: "pasta".to_string(),
+            ..State::default()
+        };
+        let _ = state.update(SidebarAction::SearchResults(
+            "pas".to_string(),
+            vec![hit.clone()],
+        ));
+        assert!(state.search_hits.is_empty());
+        let _ = state.update(SidebarAction::SearchResults("pasta".to_string(), vec![hit]));
+        assert_eq!(state.search_hits.len(), 1);
+
+        let _ = state.update(SidebarAction::SearchChanged(String::new()));
+        assert!(state.search_hits.is_empty());
+    }
+}
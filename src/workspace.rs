@@ -0,0 +1,184 @@
+//! A folder attached to a conversation as its workspace.
+//!
+//! The model is sent a summary of the folder's file tree and reads the
+//! files it needs with a built-in tool, so a project can be discussed
+//! without attaching its files one by one.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use serde_json::json;
+
+use crate::{
+    knowledge::SKIPPED_DIRS,
+    mcp::builtin,
+    models::{Function, Tool},
+};
+
+/// Name of the built-in tool the model reads workspace files with.
+pub const READ_FILE_TOOL: &str = "read_workspace_file";
+/// Entries listed in the file tree; larger folders are cut short.
+const MAX_TREE_ENTRIES: usize = 500;
+
+/// A folder attached as the workspace, with the summary of its files sent
+/// to the model.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Workspace {
+    pub root: PathBuf,
+    pub tree: String,
+}
+
+impl Workspace {
+    /// Scan the folder at `root`.
+    pub fn open(root: PathBuf) -> Result<Self> {
+        if !root.is_dir() {
+            bail!("{} is not a folder", root.display());
+        }
+        let mut lines = Vec::new();
+        list_tree(&root, 0, &mut lines);
+        let mut tree = lines.join("\n");
+        if lines.len() >= MAX_TREE_ENTRIES {
+            tree.push_str(&format!(
+                "\n[Only the first {} entries are listed.]",
+                MAX_TREE_ENTRIES
+            ));
+        }
+        Ok(Self { root, tree })
+    }
+
+    /// The folder's name.
+    pub fn name(&self) -> String {
+        self.root
+            .file_name()
+            .unwrap_or(self.root.as_os_str())
+            .to_string_lossy()
+            .to_string()
+    }
+
+    /// The system message introducing the workspace to the model.
+    pub fn prompt(&self) -> String {
+        format!(
+            "The user attached the folder `{}` as the workspace. Its files are \
+             listed below, folders ending in `/`. Call `{}` with a path from \
+             the list to read a file when you need its contents.\n\n{}",
+            self.name(),
+            READ_FILE_TOOL,
+            self.tree
+        )
+    }
+
+    /// The text of the file at `path`, relative to the workspace root.
+    /// Paths leading out of the workspace are refused.
+    pub fn read_file(&self, path: &str) -> Result<String> {
+        let root = self.root.canonicalize()?;
+        let file = root
+            .join(path)
+            .canonicalize()
+            .with_context(|| format!("There is no {} in the workspace", path))?;
+        if !file.starts_with(&root) {
+            bail!("{} is outside the workspace", path);
+        }
+        builtin::read_text(&file)
+    }
+}
+
+/// Append a line for each entry under `dir`, folders first, indented by
+/// `depth`, leaving out hidden entries and build folders.
+fn list_tree(dir: &Path, depth: usize, lines: &mut Vec<String>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    let mut entries: Vec<(bool, String, PathBuf)> = entries
+        .flatten()
+        .map(|entry| {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().to_string();
+            (!path.is_dir(), name, path)
+        })
+        .filter(|(_, name, _)| !name.starts_with('.') && !SKIPPED_DIRS.contains(&name.as_str()))
+        .collect();
+    entries.sort();
+    for (is_file, name, path) in entries {
+        if lines.len() >= MAX_TREE_ENTRIES {
+            return;
+        }
+        let indent = "  ".repeat(depth);
+        if is_file {
+            lines.push(format!("{indent}{name}"));
+        } else {
+            lines.push(format!("{indent}{name}/"));
+            list_tree(&path, depth + 1, lines);
+        }
+    }
+}
+
+/// The tool offered to the model while a workspace is attached.
+pub fn read_file_tool() -> Tool {
+    Tool::Function(Function {
+        name: READ_FILE_TOOL.to_string(),
+        description: "Read a text file from the user's workspace.".to_string(),
+        parameters: json!({
+            "type": "object",
+            "properties": {
+                "path": {
+                    "type": "string",
+                    "description": "Path of the file relative to the workspace, as listed"
+                }
+            },
+            "required": ["path"]
+        }),
+    })
+}
+
+/// The path a call to [`READ_FILE_TOOL`] asks for.
+pub fn requested_path(arguments: &str) -> Option<String> {
+    let arguments: serde_json::Value = serde_json::from_str(arguments).ok()?;
+    Some(arguments["path"].as_str()?.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_workspace_tree_and_files() {
+        let dir = std::env::temp_dir().join("ergon_test_workspace");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("src/ui")).unwrap();
+        std::fs::create_dir_all(dir.join(".git")).unwrap();
+        std::fs::create_dir_all(dir.join("target")).unwrap();
+        std::fs::write(dir.join("Cargo.toml"), "[package]").unwrap();
+        std::fs::write(dir.join("src/main.rs"), "fn main() {}").unwrap();
+        std::fs::write(dir.join("src/ui/mod.rs"), "").unwrap();
+        std::fs::write(dir.join(".git/HEAD"), "ref").unwrap();
+
+        let workspace = Workspace::open(dir.clone()).unwrap();
+        assert_eq!(
+            workspace.tree,
+            "src/\n  ui/\n    mod.rs\n  main.rs\nCargo.toml"
+        );
+        assert_eq!(workspace.name(), "ergon_test_workspace");
+        assert!(workspace.prompt().ends_with(&workspace.tree));
+
+        assert_eq!(workspace.read_file("src/main.rs").unwrap(), "fn main() {}");
+        assert!(workspace.read_file("src/missing.rs").is_err());
+        assert!(workspace.read_file("src").is_err());
+        assert!(workspace
+            .read_file("../ergon_test_workspace/Cargo.toml")
+            .is_ok());
+        assert!(workspace.read_file("..").is_err());
+        std::fs::write(std::env::temp_dir().join("ergon_test_outside.txt"), "x").unwrap();
+        assert!(workspace.read_file("../ergon_test_outside.txt").is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_requested_path() {
+        assert_eq!(
+            requested_path(r#"{"path": "src/main.rs"}"#).as_deref(),
+            Some("src/main.rs")
+        );
+        assert_eq!(requested_path(r#"{"file": "src/main.rs"}"#), None);
+        assert_eq!(requested_path("not json"), None);
+    }
+}